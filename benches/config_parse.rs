@@ -0,0 +1,40 @@
+// Parse time of a generated 50k-line configuration, mostly servers.
+//
+//     cargo bench --bench config_parse
+
+use std::time::{Duration, Instant};
+use turbogate::config::Config;
+
+const LINES: usize = 50_000;
+const RUNS: usize = 10;
+const BUDGET: Duration = Duration::from_secs(1);
+
+fn large_config(lines: usize) -> String {
+    let mut config = String::from("global\n    maxconn 100000\n\ndefaults\n    mode tcp\n    timeout connect 5s\n\n");
+    let mut backend = 0;
+    while config.lines().count() < lines {
+        config.push_str(&format!("frontend f{backend}\n    bind 127.0.0.1:{}\n    acl admin src 10.{}.0.0/16\n    use_backend b{backend} if admin\n    default_backend b{backend}\n\n", 10000 + backend, backend % 256));
+        config.push_str(&format!("backend b{backend}\n    balance roundrobin\n    option httpchk GET /health\n"));
+        for server in 0..100 {
+            config.push_str(&format!("    server s{server} 10.{}.{}.{}:8080 check inter 2s rise 2 fall 3 weight 10\n", backend % 256, server / 256, server % 256));
+        }
+        config.push('\n');
+        backend += 1;
+    }
+    config
+}
+
+fn main() {
+    let content = large_config(LINES);
+    let mut times: Vec<Duration> = (0..RUNS)
+        .map(|_| {
+            let started = Instant::now();
+            Config::from_haproxy_config(&content, None).expect("generated configuration parses");
+            started.elapsed()
+        })
+        .collect();
+    times.sort();
+    let median = times[RUNS / 2];
+    println!("config_parse: {} lines, median {:?}, min {:?}, max {:?}", content.lines().count(), median, times[0], times[RUNS - 1]);
+    assert!(median < BUDGET, "parsing {} lines took {:?}, over the {:?} budget", LINES, median, BUDGET);
+}
@@ -0,0 +1,67 @@
+// Per-connection use_backend evaluation against the precompiled routing
+// table, versus parsing every rule's ACLs for each connection as before.
+//
+//     cargo bench --bench routing
+
+use std::hint::black_box;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use turbogate::acl::{Acl, AclContext, CompiledRouting};
+use turbogate::config::{Config, FrontendConfig};
+
+const RULES: usize = 50;
+const CONNECTIONS: usize = 20_000;
+
+// The last rule matches, so every connection walks the whole list.
+fn frontend() -> FrontendConfig {
+    let mut config = String::from("frontend web\n    bind 127.0.0.1:8080\n");
+    for rule in 0..RULES {
+        config.push_str(&format!("    acl net{rule} src 10.{rule}.0.0/16\n    acl port{rule} dst_port {}\n", 8000 + rule));
+        config.push_str(&format!("    use_backend b{rule} if net{rule} port{rule}\n"));
+    }
+    config.push_str("    default_backend b0\n");
+    Config::from_haproxy_config(&config, None).expect("generated configuration parses").frontends.remove(0)
+}
+
+fn context(connection: usize) -> AclContext<'static> {
+    let client: SocketAddr = format!("10.{}.0.1:{}", RULES - 1, 1024 + connection % 60_000).parse().unwrap();
+    let destination: SocketAddr = format!("127.0.0.1:{}", 8000 + RULES - 1).parse().unwrap();
+    AclContext::with_payload(client, b"").with_destination(Some(destination))
+}
+
+fn compiled(routing: &CompiledRouting, context: &AclContext) -> Option<usize> {
+    routing.use_backend.iter().position(|rule| {
+        rule.condition.as_ref().is_none_or(|condition| routing.acls.evaluate(condition, context).unwrap_or(false))
+    })
+}
+
+fn reparsed(frontend: &FrontendConfig, context: &AclContext) -> Option<usize> {
+    frontend.use_backend.iter().position(|rule| {
+        rule.condition.iter().flatten().skip(1).all(|name| {
+            frontend.acl.iter()
+                .filter(|acl| &acl.name == name)
+                .any(|acl| Acl::from_config(acl, &[]).and_then(|acl| acl.evaluate(context)).unwrap_or(false))
+        })
+    })
+}
+
+fn measure(route: impl Fn(&AclContext) -> Option<usize>) -> Duration {
+    let started = Instant::now();
+    for connection in 0..CONNECTIONS {
+        assert_eq!(black_box(route(&context(connection))), Some(RULES - 1));
+    }
+    started.elapsed() / CONNECTIONS as u32
+}
+
+fn main() {
+    let frontend = frontend();
+    let routing = CompiledRouting::compile(&frontend, &[]).expect("generated routing compiles");
+
+    let compiled_cost = measure(|context| compiled(&routing, context));
+    let reparsed_cost = measure(|context| reparsed(&frontend, context));
+
+    println!("routing: {RULES} use_backend rules, {CONNECTIONS} connections");
+    println!("  CompiledRouting:         {compiled_cost:?} per connection");
+    println!("  parse per connection:    {reparsed_cost:?} per connection (old)");
+    assert!(compiled_cost < reparsed_cost, "precompiled routing is not cheaper than parsing per connection");
+}
@@ -0,0 +1,117 @@
+// Server status lookups on the connection path while health checkers keep
+// publishing, against the nested HashMap behind an RwLock it replaced.
+//
+//     cargo bench --bench status_table
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use turbogate::config::Config;
+use turbogate::health::ServerStatus;
+use turbogate::status::{ServerReport, StatusTable};
+
+const BACKENDS: usize = 50;
+const SERVERS: usize = 20;
+const READERS: usize = 4;
+const LOOKUPS: usize = 2_000_000;
+
+fn config() -> String {
+    let mut config = String::new();
+    for backend in 0..BACKENDS {
+        config.push_str(&format!("backend b{backend}\n"));
+        for server in 0..SERVERS {
+            config.push_str(&format!("    server s{server} 127.0.0.1:{} check\n", 10000 + server));
+        }
+    }
+    config
+}
+
+fn report(status: ServerStatus) -> ServerReport {
+    ServerReport { status, interval: Some(Duration::from_secs(2)), failure: None, duration: None }
+}
+
+fn status(round: usize, server: usize) -> ServerStatus {
+    if (round + server).is_multiple_of(7) { ServerStatus::Down } else { ServerStatus::Up }
+}
+
+// Runs `lookup` LOOKUPS times on each reader thread while `publish` runs in a
+// loop, and returns the mean time per lookup and the number of publishes.
+fn measure<L, P>(lookup: L, publish: P) -> (Duration, usize)
+where
+    L: Fn(usize, usize) -> bool + Send + Sync + 'static,
+    P: Fn(usize) + Send + 'static,
+{
+    let lookup = Arc::new(lookup);
+    let done = Arc::new(AtomicBool::new(false));
+    let writer = {
+        let done = Arc::clone(&done);
+        thread::spawn(move || {
+            let mut round = 0;
+            while !done.load(Ordering::Relaxed) {
+                publish(round);
+                round += 1;
+                thread::sleep(Duration::from_micros(50));
+            }
+            round
+        })
+    };
+    let readers: Vec<_> = (0..READERS)
+        .map(|reader| {
+            let lookup = Arc::clone(&lookup);
+            thread::spawn(move || {
+                let started = Instant::now();
+                let mut up = 0;
+                for lookup_index in 0..LOOKUPS {
+                    let key = lookup_index.wrapping_mul(2_654_435_761).wrapping_add(reader);
+                    up += usize::from(lookup(key / SERVERS % BACKENDS, key % SERVERS));
+                }
+                assert!(up > 0);
+                started.elapsed()
+            })
+        })
+        .collect();
+    let elapsed: Duration = readers.into_iter().map(|reader| reader.join().unwrap()).sum();
+    done.store(true, Ordering::Relaxed);
+    (elapsed / (READERS * LOOKUPS) as u32, writer.join().unwrap())
+}
+
+fn main() {
+    let backends = Config::from_haproxy_config(&config(), None).expect("generated configuration parses").backends;
+
+    let table = Arc::new(StatusTable::new(&backends));
+    let reader = Arc::clone(&table);
+    let (table_lookup, table_publishes) = measure(
+        move |backend, server| reader.status(backend, server) == Some(ServerStatus::Up),
+        move |round| {
+            let backend = round % BACKENDS;
+            table.publish(backend, (0..SERVERS).map(|server| (server, report(status(round, server)))));
+        },
+    );
+
+    let names: Vec<(String, Vec<String>)> = backends.iter()
+        .map(|backend| (backend.name.clone(), backend.server.iter().map(|server| server.name.clone()).collect()))
+        .collect();
+    let map: Arc<RwLock<HashMap<String, HashMap<String, ServerStatus>>>> = Arc::new(RwLock::new(names.iter()
+        .map(|(backend, servers)| (backend.clone(), servers.iter().map(|server| (server.clone(), ServerStatus::Up)).collect()))
+        .collect()));
+    let (reader, reader_names) = (Arc::clone(&map), names.clone());
+    let (map_lookup, map_publishes) = measure(
+        move |backend, server| {
+            let (ref backend, ref servers) = reader_names[backend];
+            reader.read().unwrap().get(backend).and_then(|servers_map| servers_map.get(&servers[server]).cloned()) == Some(ServerStatus::Up)
+        },
+        move |round| {
+            let (ref backend, ref servers) = names[round % BACKENDS];
+            let statuses: HashMap<String, ServerStatus> = servers.iter().enumerate()
+                .map(|(server, name)| (name.clone(), status(round, server)))
+                .collect();
+            map.write().unwrap().insert(backend.clone(), statuses);
+        },
+    );
+
+    println!("status_table: {READERS} readers, {BACKENDS}x{SERVERS} servers");
+    println!("  StatusTable snapshot:   {table_lookup:?} per lookup ({table_publishes} publishes)");
+    println!("  RwLock<HashMap> (old):  {map_lookup:?} per lookup ({map_publishes} publishes)");
+}
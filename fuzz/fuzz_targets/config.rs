@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use turbogate::config::Config;
+
+// Any input must parse or fail with an error, never panic.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(content) = std::str::from_utf8(data) {
+        let _ = Config::from_haproxy_config(content, Some("fuzz.cfg"));
+    }
+});
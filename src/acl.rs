@@ -1,19 +1,461 @@
-use crate::config::AclConfig;
+use crate::auth::Userlist;
+use crate::config::{AclConfig, FrontendConfig, UserlistConfig};
+use crate::inspect::{self, NameTemplate};
+use crate::geoip::Country;
+use crate::http1::StaticResponse;
 use crate::utils;
 use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use thiserror::Error;
 use tracing::{debug, warn};
 use ipnetwork::IpNetwork;
+use regex::Regex;
+
+#[derive(Debug, Error)]
+#[error("ACL '{acl}' failed to evaluate: {error:#}")]
+pub struct AclEvalError {
+    pub acl: String,
+    error: anyhow::Error,
+}
 
 #[derive(Debug, Clone)]
 pub enum AclCondition {
     SourceIp(IpNetwork),
     SourcePort(u16),
+    DestinationIp(IpNetwork),
     DestinationPort(u16),
     Hostname(String),
-    Path(String),
-    Header(String, String),
+    Path(MatchMethod, Vec<String>),
+    Header(String, MatchMethod, Vec<String>),
+    HeaderRegex(String, Vec<Regex>),
+    Payload(usize, usize, Vec<Vec<u8>>),
+    PayloadLength(Comparison, usize),
+    Ssl,
+    SslAlpn(Vec<String>),
+    SslSni(Vec<String>),
+    ClientHelloSni(Vec<String>),
+    SourceDomain(Vec<String>),
+    SourceCountry(Vec<Country>),
+    ConnectHost(Vec<String>),
+    ConnectPort(Vec<(u16, u16)>),
+    HttpAuth(Arc<Userlist>, Vec<String>),
     Custom(()),
+    #[cfg(test)]
+    Failing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchMethod {
+    Exact,
+    Prefix,
+    Suffix,
+    Substring,
+}
+
+impl MatchMethod {
+    fn matches(&self, value: &str, pattern: &str) -> bool {
+        match self {
+            MatchMethod::Exact => value == pattern,
+            MatchMethod::Prefix => value.starts_with(pattern),
+            MatchMethod::Suffix => value.ends_with(pattern),
+            MatchMethod::Substring => value.contains(pattern),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ge,
+    Gt,
+    Le,
+    Lt,
+}
+
+impl Comparison {
+    fn matches(&self, value: usize, expected: usize) -> bool {
+        match self {
+            Comparison::Eq => value == expected,
+            Comparison::Ge => value >= expected,
+            Comparison::Gt => value > expected,
+            Comparison::Le => value <= expected,
+            Comparison::Lt => value < expected,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AclContext<'a> {
+    pub client_addr: SocketAddr,
+    pub destination: Option<SocketAddr>,
+    pub payload: &'a [u8],
+    pub ssl: bool,
+    pub alpn: Option<&'a str>,
+    pub sni: Option<&'a str>,
+    pub src_dn: Option<&'a str>,
+    pub country: Country,
+    pub connect: Option<(&'a str, u16)>,
+}
+
+impl<'a> AclContext<'a> {
+    pub fn with_payload(client_addr: SocketAddr, payload: &'a [u8]) -> Self {
+        Self { client_addr, destination: None, payload, ssl: false, alpn: None, sni: None, src_dn: None, country: Country::UNKNOWN, connect: None }
+    }
+
+    pub fn with_destination(mut self, destination: Option<SocketAddr>) -> Self {
+        self.destination = destination;
+        self
+    }
+
+    pub fn with_tls(mut self, ssl: bool, alpn: Option<&'a str>) -> Self {
+        self.ssl = ssl;
+        self.alpn = alpn;
+        self
+    }
+
+    pub fn with_sni(mut self, sni: Option<&'a str>) -> Self {
+        self.sni = sni;
+        self
+    }
+
+    pub fn with_src_dn(mut self, src_dn: Option<&'a str>) -> Self {
+        self.src_dn = src_dn;
+        self
+    }
+
+    pub fn with_country(mut self, country: Country) -> Self {
+        self.country = country;
+        self
+    }
+
+    pub fn with_connect(mut self, connect: Option<(&'a str, u16)>) -> Self {
+        self.connect = connect;
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+struct RuleCondition {
+    unless: bool,
+    any_of: Vec<Vec<(bool, String)>>,
+}
+
+impl RuleCondition {
+    fn parse(expression: &[String]) -> Result<Self> {
+        let mut parts = expression.iter().map(String::as_str);
+        let unless = match parts.next() {
+            Some("if") => false,
+            Some("unless") => true,
+            Some(other) => return Err(anyhow!("Expected 'if' or 'unless' before condition, got '{}'", other)),
+            None => return Err(anyhow!("Empty condition")),
+        };
+
+        let mut any_of = vec![Vec::new()];
+        for part in parts {
+            match part {
+                "or" | "||" => any_of.push(Vec::new()),
+                _ => {
+                    let term = match part.strip_prefix('!') {
+                        Some(name) => (true, name.to_string()),
+                        None => (false, part.to_string()),
+                    };
+                    if let Some(group) = any_of.last_mut() {
+                        group.push(term);
+                    }
+                }
+            }
+        }
+
+        if any_of.iter().any(|group| group.is_empty()) {
+            return Err(anyhow!("Condition '{}' has an empty term", expression.join(" ")));
+        }
+
+        Ok(Self { unless, any_of })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CompiledCondition {
+    unless: bool,
+    any_of: Vec<Vec<(bool, usize)>>,
+}
+
+#[derive(Debug, Default)]
+struct AclCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    last_hit: AtomicI64,
+}
+
+impl AclCounters {
+    fn record(&self, matched: bool) {
+        if matched {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            self.last_hit.store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AclStat {
+    pub frontend: String,
+    pub acl: String,
+    pub criterion: String,
+    pub hits: u64,
+    pub misses: u64,
+    pub last_hit: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone)]
+struct NamedAcl {
+    name: String,
+    criterion: String,
+    alternatives: Vec<Acl>,
+    counters: Arc<AclCounters>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AclTable {
+    acls: Vec<NamedAcl>,
+}
+
+impl AclTable {
+    pub fn compile(configs: &[AclConfig], userlists: &[UserlistConfig]) -> Result<Self> {
+        let mut acls: Vec<NamedAcl> = Vec::new();
+        for config in configs {
+            let acl = Acl::from_config(config, userlists)
+                .map_err(|e| anyhow!("{}: acl {}: {}", config.span, config.name, e))?;
+            let criterion = config.criterion.join(" ");
+            match acls.iter_mut().find(|named| named.name == config.name) {
+                Some(named) => {
+                    named.criterion = format!("{} || {}", named.criterion, criterion);
+                    named.alternatives.push(acl);
+                }
+                None => acls.push(NamedAcl {
+                    name: config.name.clone(),
+                    criterion,
+                    alternatives: vec![acl],
+                    counters: Arc::new(AclCounters::default()),
+                }),
+            }
+        }
+
+        Ok(Self { acls })
+    }
+
+    pub fn adopt_counters(&mut self, previous: &AclTable) {
+        for named in &mut self.acls {
+            if let Some(old) = previous.acls.iter().find(|old| old.name == named.name && old.criterion == named.criterion) {
+                named.counters = Arc::clone(&old.counters);
+            }
+        }
+    }
+
+    pub fn stats(&self, frontend: &str) -> Vec<AclStat> {
+        self.acls.iter()
+            .map(|named| {
+                let last_hit = named.counters.last_hit.load(Ordering::Relaxed);
+                AclStat {
+                    frontend: frontend.to_string(),
+                    acl: named.name.clone(),
+                    criterion: named.criterion.clone(),
+                    hits: named.counters.hits.load(Ordering::Relaxed),
+                    misses: named.counters.misses.load(Ordering::Relaxed),
+                    last_hit: (last_hit > 0).then(|| DateTime::from_timestamp_millis(last_hit)).flatten(),
+                }
+            })
+            .collect()
+    }
+
+    pub fn payload_needed(&self) -> usize {
+        self.acls.iter()
+            .flat_map(|named| &named.alternatives)
+            .map(Acl::payload_needed)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn inspects_http(&self) -> bool {
+        self.acls.iter()
+            .flat_map(|named| &named.alternatives)
+            .flat_map(|acl| &acl.conditions)
+            .any(|condition| matches!(condition, AclCondition::Path(..) | AclCondition::Header(..) | AclCondition::HeaderRegex(..) | AclCondition::HttpAuth(..)))
+    }
+
+    pub fn uses_src_dn(&self) -> bool {
+        self.acls.iter()
+            .flat_map(|named| &named.alternatives)
+            .flat_map(|acl| &acl.conditions)
+            .any(|condition| matches!(condition, AclCondition::SourceDomain(_)))
+    }
+
+    pub fn uses_ssl_fc_sni(&self) -> bool {
+        self.acls.iter()
+            .flat_map(|named| &named.alternatives)
+            .flat_map(|acl| &acl.conditions)
+            .any(|condition| matches!(condition, AclCondition::SslSni(_)))
+    }
+
+    pub fn uses_req_ssl_sni(&self) -> bool {
+        self.acls.iter()
+            .flat_map(|named| &named.alternatives)
+            .flat_map(|acl| &acl.conditions)
+            .any(|condition| matches!(condition, AclCondition::ClientHelloSni(_)))
+    }
+
+    pub fn uses_src_country(&self) -> bool {
+        self.acls.iter()
+            .flat_map(|named| &named.alternatives)
+            .flat_map(|acl| &acl.conditions)
+            .any(|condition| matches!(condition, AclCondition::SourceCountry(_)))
+    }
+
+    pub fn condition(&self, expression: &[String]) -> Result<CompiledCondition> {
+        let condition = RuleCondition::parse(expression)?;
+        let any_of = condition.any_of.iter()
+            .map(|group| group.iter()
+                .map(|(negated, name)| self.acls.iter()
+                    .position(|named| named.name == *name)
+                    .map(|index| (*negated, index))
+                    .ok_or_else(|| anyhow!("references unknown ACL '{}'", name)))
+                .collect::<Result<Vec<_>>>())
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(CompiledCondition { unless: condition.unless, any_of })
+    }
+
+    pub fn evaluate(&self, condition: &CompiledCondition, context: &AclContext) -> std::result::Result<bool, AclEvalError> {
+        let mut matched = false;
+        for group in &condition.any_of {
+            let mut group_matched = true;
+            for &(negated, index) in group {
+                if self.evaluate_named(index, context)? == negated {
+                    group_matched = false;
+                    break;
+                }
+            }
+            if group_matched {
+                matched = true;
+                break;
+            }
+        }
+
+        Ok(matched != condition.unless)
+    }
+
+    // No criterion fails at runtime yet, so tests swap one in.
+    #[cfg(test)]
+    pub(crate) fn make_failing(&mut self, name: &str) {
+        let named = self.acls.iter_mut().find(|named| named.name == name).expect("known ACL");
+        named.alternatives = vec![Acl { conditions: vec![AclCondition::Failing] }];
+    }
+
+    fn evaluate_named(&self, index: usize, context: &AclContext) -> std::result::Result<bool, AclEvalError> {
+        let named = &self.acls[index];
+        let mut matched = false;
+        for acl in &named.alternatives {
+            let alternative_matched = acl.evaluate(context)
+                .map_err(|error| AclEvalError { acl: named.name.clone(), error })?;
+            if alternative_matched {
+                matched = true;
+                break;
+            }
+        }
+
+        named.counters.record(matched);
+        Ok(matched)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum RouteTarget {
+    Backend(String),
+    Template(NameTemplate),
+}
+
+#[derive(Debug, Clone)]
+pub struct CompiledRule {
+    pub backend: String,
+    pub condition: Option<CompiledCondition>,
+    pub target: RouteTarget,
+}
+
+#[derive(Debug, Clone)]
+pub struct CompiledAuthRule {
+    pub realm: String,
+    pub condition: Option<CompiledCondition>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CompiledReturnRule {
+    pub response: Arc<StaticResponse>,
+    pub condition: Option<CompiledCondition>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CompiledRouting {
+    pub acls: AclTable,
+    pub use_backend: Vec<CompiledRule>,
+    pub tcp_request_connection: Vec<Option<CompiledCondition>>,
+    pub http_request_auth: Vec<CompiledAuthRule>,
+    pub http_request_return: Vec<CompiledReturnRule>,
+}
+
+impl CompiledRouting {
+    pub fn compile(config: &FrontendConfig, userlists: &[UserlistConfig]) -> Result<Self> {
+        let acls = AclTable::compile(&config.acl, userlists)?;
+
+        let use_backend = config.use_backend.iter()
+            .map(|rule| {
+                let target = if NameTemplate::is_template(&rule.backend) {
+                    RouteTarget::Template(NameTemplate::parse(&rule.backend)
+                        .map_err(|e| anyhow!("{}: use_backend {}: {}", rule.span, rule.backend, e))?)
+                } else {
+                    RouteTarget::Backend(rule.backend.clone())
+                };
+                let condition = rule.condition.as_deref()
+                    .map(|condition| acls.condition(condition))
+                    .transpose()
+                    .map_err(|e| anyhow!("{}: use_backend {}: {}", rule.span, rule.backend, e))?;
+                Ok(CompiledRule { backend: rule.backend.clone(), condition, target })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let tcp_request_connection = config.tcp_request_connection.iter()
+            .map(|rule| rule.condition.as_deref()
+                .map(|condition| acls.condition(condition))
+                .transpose()
+                .map_err(|e| anyhow!("{}: tcp-request connection: {}", config.span, e)))
+            .collect::<Result<Vec<_>>>()?;
+
+        let http_request_auth = config.http_request_auth.iter()
+            .map(|rule| {
+                let condition = rule.condition.as_deref()
+                    .map(|condition| acls.condition(condition))
+                    .transpose()
+                    .map_err(|e| anyhow!("{}: http-request auth: {}", rule.span, e))?;
+                Ok(CompiledAuthRule { realm: rule.realm.clone().unwrap_or_else(|| config.name.clone()), condition })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let http_request_return = config.http_request_return.iter()
+            .map(|rule| {
+                let condition = rule.condition.as_deref()
+                    .map(|condition| acls.condition(condition))
+                    .transpose()
+                    .map_err(|e| anyhow!("{}: http-request return: {}", rule.span, e))?;
+                Ok(CompiledReturnRule { response: Arc::new(StaticResponse::new(rule)), condition })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { acls, use_backend, tcp_request_connection, http_request_auth, http_request_return })
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -22,26 +464,38 @@ pub struct Acl {
 }
 
 impl Acl {
-    pub fn from_config(config: &AclConfig) -> Result<Self> {
-        let conditions = Self::parse_criterion(&config.criterion)?;
+    pub fn from_config(config: &AclConfig, userlists: &[UserlistConfig]) -> Result<Self> {
+        let conditions = Self::parse_criterion(&config.criterion, userlists)?;
         
         Ok(Self {
             conditions,
         })
     }
 
-    pub fn evaluate(&self, client_addr: SocketAddr) -> Result<bool> {
+    pub fn evaluate(&self, context: &AclContext) -> Result<bool> {
         for condition in &self.conditions {
-            if !Self::evaluate_condition(condition, client_addr)? {
+            if !Self::evaluate_condition(condition, context)? {
                 return Ok(false);
             }
         }
         Ok(true)
     }
 
-    fn parse_criterion(criterion: &str) -> Result<Vec<AclCondition>> {
+    pub fn payload_needed(&self) -> usize {
+        self.conditions.iter()
+            .map(|condition| match condition {
+                AclCondition::Payload(offset, len, _) => offset.saturating_add((*len).max(1)),
+                AclCondition::PayloadLength(Comparison::Gt, len) => len.saturating_add(1),
+                AclCondition::PayloadLength(_, len) => *len,
+                _ => 0,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn parse_criterion(criterion: &[String], userlists: &[UserlistConfig]) -> Result<Vec<AclCondition>> {
         let mut conditions = Vec::new();
-        let parts: Vec<&str> = criterion.split_whitespace().collect();
+        let parts: Vec<&str> = criterion.iter().map(String::as_str).collect();
         
         if parts.is_empty() {
             return Err(anyhow!("Empty ACL criterion"));
@@ -62,6 +516,13 @@ impl Acl {
                 let port: u16 = parts[1].parse()?;
                 conditions.push(AclCondition::SourcePort(port));
             }
+            "dst" => {
+                if parts.len() < 2 {
+                    return Err(anyhow!("Invalid dst ACL: missing IP/CIDR"));
+                }
+                let network = utils::parse_ip_or_cidr(parts[1])?;
+                conditions.push(AclCondition::DestinationIp(network));
+            }
             "dst_port" => {
                 if parts.len() < 2 {
                     return Err(anyhow!("Invalid dst_port ACL: missing port"));
@@ -73,15 +534,39 @@ impl Acl {
                 if parts.len() < 3 {
                     return Err(anyhow!("Invalid hdr ACL: missing header name or value"));
                 }
-                let header_name = parts[1].to_string();
-                let header_value = parts[2].to_string();
-                conditions.push(AclCondition::Header(header_name, header_value));
+                let values = parts[2..].iter().map(|value| value.to_string()).collect();
+                conditions.push(AclCondition::Header(parts[1].to_string(), MatchMethod::Exact, values));
             }
-            "path" => {
+            fetch if fetch.starts_with("hdr_reg(") => {
+                let header_name = Self::parse_header_name(fetch)?;
                 if parts.len() < 2 {
-                    return Err(anyhow!("Invalid path ACL: missing path"));
+                    return Err(anyhow!("Invalid {} ACL: missing regex", fetch));
                 }
-                conditions.push(AclCondition::Path(parts[1].to_string()));
+                let patterns = parts[1..].iter()
+                    .map(|pattern| Regex::new(pattern).map_err(|e| anyhow!("Invalid {} ACL: invalid regex '{}': {}", fetch, pattern, e)))
+                    .collect::<Result<Vec<_>>>()?;
+                conditions.push(AclCondition::HeaderRegex(header_name, patterns));
+            }
+            fetch if fetch.starts_with("hdr") => {
+                let (method, header_name) = Self::parse_header_fetch(fetch)?;
+                if parts.len() < 2 {
+                    return Err(anyhow!("Invalid {} ACL: missing value", fetch));
+                }
+                let values = parts[1..].iter().map(|value| value.to_string()).collect();
+                conditions.push(AclCondition::Header(header_name, method, values));
+            }
+            "path" | "path_beg" | "path_end" | "path_sub" => {
+                if parts.len() < 2 {
+                    return Err(anyhow!("Invalid {} ACL: missing path", parts[0]));
+                }
+                let method = match parts[0] {
+                    "path_beg" => MatchMethod::Prefix,
+                    "path_end" => MatchMethod::Suffix,
+                    "path_sub" => MatchMethod::Substring,
+                    _ => MatchMethod::Exact,
+                };
+                let values = parts[1..].iter().map(|value| value.to_string()).collect();
+                conditions.push(AclCondition::Path(method, values));
             }
             "host" => {
                 if parts.len() < 2 {
@@ -89,6 +574,107 @@ impl Acl {
                 }
                 conditions.push(AclCondition::Hostname(parts[1].to_string()));
             }
+            "ssl_fc" => {
+                if parts.len() > 1 {
+                    return Err(anyhow!("Invalid ssl_fc ACL: it takes no value"));
+                }
+                conditions.push(AclCondition::Ssl);
+            }
+            "ssl_fc_alpn" => {
+                if parts.len() < 2 {
+                    return Err(anyhow!("Invalid ssl_fc_alpn ACL: missing protocol"));
+                }
+                conditions.push(AclCondition::SslAlpn(parts[1..].iter().map(|protocol| protocol.to_string()).collect()));
+            }
+            "ssl_fc_sni" => {
+                if parts.len() < 2 {
+                    return Err(anyhow!("Invalid ssl_fc_sni ACL: missing server name"));
+                }
+                conditions.push(AclCondition::SslSni(parts[1..].iter().map(|name| name.to_ascii_lowercase()).collect()));
+            }
+            "req_ssl_sni" | "req.ssl_sni" => {
+                if parts.len() < 2 {
+                    return Err(anyhow!("Invalid {} ACL: missing server name", parts[0]));
+                }
+                conditions.push(AclCondition::ClientHelloSni(parts[1..].iter().map(|name| name.to_ascii_lowercase()).collect()));
+            }
+            "src_dn" => {
+                if parts.len() < 2 {
+                    return Err(anyhow!("Invalid src_dn ACL: missing domain suffix"));
+                }
+                conditions.push(AclCondition::SourceDomain(parts[1..].iter().map(|suffix| suffix.to_ascii_lowercase()).collect()));
+            }
+            "src_country" => {
+                if parts.len() < 2 {
+                    return Err(anyhow!("Invalid src_country ACL: missing country code"));
+                }
+                let countries = parts[1..].iter()
+                    .map(|code| Country::parse(code)
+                        .ok_or_else(|| anyhow!("Invalid src_country ACL: '{}' is not a two-letter country code", code)))
+                    .collect::<Result<Vec<_>>>()?;
+                conditions.push(AclCondition::SourceCountry(countries));
+            }
+            "connect_host" => {
+                if parts.len() < 2 {
+                    return Err(anyhow!("Invalid connect_host ACL: missing host name"));
+                }
+                conditions.push(AclCondition::ConnectHost(parts[1..].iter().map(|host| host.to_ascii_lowercase()).collect()));
+            }
+            "connect_port" => {
+                if parts.len() < 2 {
+                    return Err(anyhow!("Invalid connect_port ACL: missing port"));
+                }
+                let ranges = parts[1..].iter()
+                    .map(|range| Self::parse_port_range(range))
+                    .collect::<Result<Vec<_>>>()?;
+                conditions.push(AclCondition::ConnectPort(ranges));
+            }
+            fetch if fetch.starts_with("http_auth(") || fetch.starts_with("http_auth_group(") => {
+                let (criterion, name) = fetch.strip_suffix(')')
+                    .and_then(|fetch| fetch.split_once('('))
+                    .filter(|(_, name)| !name.is_empty())
+                    .ok_or_else(|| anyhow!("Invalid {} ACL: expected {}(<userlist>)", fetch, fetch.split('(').next().unwrap_or(fetch)))?;
+                let userlist = userlists.iter()
+                    .find(|userlist| userlist.name == name)
+                    .ok_or_else(|| anyhow!("Invalid {} ACL: references non-existent userlist '{}'", criterion, name))?;
+                let groups: Vec<String> = parts[1..].iter().map(|group| group.to_string()).collect();
+                match criterion {
+                    "http_auth" if !groups.is_empty() => return Err(anyhow!("Invalid http_auth ACL: it takes no value, use http_auth_group to match groups")),
+                    "http_auth_group" if groups.is_empty() => return Err(anyhow!("Invalid http_auth_group ACL: missing group")),
+                    _ => {}
+                }
+                if let Some(group) = groups.iter().find(|group| !userlist.groups.iter().any(|g| &g.name == *group)) {
+                    return Err(anyhow!("Invalid http_auth_group ACL: userlist '{}' has no group '{}'", name, group));
+                }
+                conditions.push(AclCondition::HttpAuth(Arc::new(Userlist::new(userlist)), groups));
+            }
+            "req.len" => {
+                let (comparison, value) = match parts[1..] {
+                    [value] => (Comparison::Eq, value),
+                    [operator, value] => (Self::parse_comparison(operator)?, value),
+                    _ => return Err(anyhow!("Invalid req.len ACL: expected 'req.len [eq|ge|gt|le|lt] <n>'")),
+                };
+                let value: usize = value.parse()
+                    .map_err(|_| anyhow!("Invalid req.len ACL: '{}' is not a byte count", value))?;
+                conditions.push(AclCondition::PayloadLength(comparison, value));
+            }
+            fetch if fetch.starts_with("req.payload(") => {
+                let (offset, len) = fetch.strip_prefix("req.payload(")
+                    .and_then(|args| args.strip_suffix(')'))
+                    .and_then(|args| args.split_once(','))
+                    .and_then(|(offset, len)| Some((offset.trim().parse::<usize>().ok()?, len.trim().parse::<usize>().ok()?)))
+                    .ok_or_else(|| anyhow!("Invalid req.payload ACL '{}': expected req.payload(<offset>,<len>)", fetch))?;
+                let patterns = match parts[1..] {
+                    ["-m", "bin", ref patterns @ ..] if !patterns.is_empty() => patterns.iter()
+                        .map(|pattern| Self::parse_hex(pattern))
+                        .collect::<Result<Vec<_>>>()?,
+                    _ => return Err(anyhow!("Invalid req.payload ACL: expected '-m bin <hex>...' after {}", fetch)),
+                };
+                if len > 0 && patterns.iter().any(|pattern| pattern.len() != len) {
+                    return Err(anyhow!("Invalid req.payload ACL: patterns must be {} bytes long to match {}", len, fetch));
+                }
+                conditions.push(AclCondition::Payload(offset, len, patterns));
+            }
             _ => {
                 warn!("Unknown ACL criterion: {}", parts[0]);
                 conditions.push(AclCondition::Custom(()));
@@ -98,10 +684,71 @@ impl Acl {
         Ok(conditions)
     }
 
+    fn parse_header_fetch(fetch: &str) -> Result<(MatchMethod, String)> {
+        let method = match fetch.split('(').next().unwrap_or(fetch) {
+            "hdr" => MatchMethod::Exact,
+            "hdr_beg" => MatchMethod::Prefix,
+            "hdr_end" => MatchMethod::Suffix,
+            "hdr_sub" => MatchMethod::Substring,
+            method => return Err(anyhow!("Unknown header ACL criterion '{}'", method)),
+        };
+
+        Ok((method, Self::parse_header_name(fetch)?))
+    }
+
+    fn parse_header_name(fetch: &str) -> Result<String> {
+        let (_, args) = fetch.split_once('(')
+            .ok_or_else(|| anyhow!("Invalid {} ACL: missing header name, expected {}(<name>)", fetch, fetch))?;
+        let name = args.strip_suffix(')')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .ok_or_else(|| anyhow!("Invalid {} ACL: missing header name, expected {}(<name>)", fetch, fetch.split('(').next().unwrap_or(fetch)))?;
+
+        Ok(name.to_string())
+    }
+
+    fn parse_comparison(operator: &str) -> Result<Comparison> {
+        match operator {
+            "eq" => Ok(Comparison::Eq),
+            "ge" => Ok(Comparison::Ge),
+            "gt" => Ok(Comparison::Gt),
+            "le" => Ok(Comparison::Le),
+            "lt" => Ok(Comparison::Lt),
+            _ => Err(anyhow!("Unknown comparison operator '{}', expected eq, ge, gt, le or lt", operator)),
+        }
+    }
+
+    fn parse_port_range(range: &str) -> Result<(u16, u16)> {
+        let (low, high) = range.split_once('-').unwrap_or((range, range));
+        match (low.parse::<u16>(), high.parse::<u16>()) {
+            (Ok(low), Ok(high)) if low <= high => Ok((low, high)),
+            _ => Err(anyhow!("Invalid connect_port ACL: '{}' is not a port or a low-high port range", range)),
+        }
+    }
+
+    fn parse_hex(pattern: &str) -> Result<Vec<u8>> {
+        if pattern.is_empty() || !pattern.len().is_multiple_of(2) || !pattern.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(anyhow!("Invalid binary pattern '{}': expected an even number of hex digits", pattern));
+        }
+
+        (0..pattern.len()).step_by(2)
+            .map(|i| u8::from_str_radix(&pattern[i..i + 2], 16)
+                .map_err(|_| anyhow!("Invalid binary pattern '{}'", pattern)))
+            .collect()
+    }
+
+    fn domain_matches(name: &str, suffix: &str) -> bool {
+        match suffix.strip_prefix("*.").or_else(|| suffix.strip_prefix('.')) {
+            Some(parent) => name.strip_suffix(parent).is_some_and(|label| label.ends_with('.')),
+            None => name == suffix || name.strip_suffix(suffix).is_some_and(|label| label.ends_with('.')),
+        }
+    }
+
     fn evaluate_condition(
         condition: &AclCondition,
-        client_addr: SocketAddr,
+        context: &AclContext,
     ) -> Result<bool> {
+        let client_addr = context.client_addr;
         match condition {
             AclCondition::SourceIp(network) => {
                 Ok(utils::ip_in_network(client_addr.ip(), network))
@@ -109,25 +756,325 @@ impl Acl {
             AclCondition::SourcePort(port) => {
                 Ok(client_addr.port() == *port)
             }
-            AclCondition::DestinationPort(_port) => {
-                Ok(true)
+            AclCondition::DestinationIp(network) => {
+                Ok(context.destination.is_some_and(|destination| utils::ip_in_network(destination.ip(), network)))
+            }
+            AclCondition::DestinationPort(port) => {
+                Ok(context.destination.is_some_and(|destination| destination.port() == *port))
             }
             AclCondition::Hostname(_hostname) => {
                 debug!("Hostname ACL condition in L4 mode, allowing");
                 Ok(true)
             }
-            AclCondition::Path(_path) => {
-                debug!("Path ACL condition in L4 mode, allowing");
-                Ok(true)
+            AclCondition::Path(method, values) => {
+                let Some(path) = inspect::extract_path(context.payload) else {
+                    debug!("Path ACL condition without an HTTP request, allowing");
+                    return Ok(true);
+                };
+                Ok(values.iter().any(|value| method.matches(path, value)))
             }
-            AclCondition::Header(_name, _value) => {
-                debug!("Header ACL condition in L4 mode, allowing");
-                Ok(true)
+            AclCondition::Header(name, method, values) => {
+                if inspect::extract_path(context.payload).is_none() {
+                    debug!("Header ACL condition without an HTTP request, allowing");
+                    return Ok(true);
+                }
+                Ok(inspect::extract_headers(context.payload, name).iter()
+                    .any(|header| values.iter().any(|value| method.matches(header, value))))
+            }
+            AclCondition::HeaderRegex(name, patterns) => {
+                if inspect::extract_path(context.payload).is_none() {
+                    debug!("Header ACL condition without an HTTP request, allowing");
+                    return Ok(true);
+                }
+                Ok(inspect::extract_headers(context.payload, name).iter()
+                    .any(|header| patterns.iter().any(|pattern| pattern.is_match(header))))
+            }
+            AclCondition::Payload(offset, len, patterns) => {
+                let end = if *len == 0 { context.payload.len() } else { offset.saturating_add(*len) };
+                let sample = context.payload.get(*offset..end);
+                Ok(sample.is_some_and(|sample| patterns.iter().any(|pattern| pattern.as_slice() == sample)))
+            }
+            AclCondition::PayloadLength(comparison, len) => {
+                Ok(comparison.matches(context.payload.len(), *len))
+            }
+            AclCondition::Ssl => Ok(context.ssl),
+            AclCondition::SslAlpn(protocols) => {
+                Ok(context.alpn.is_some_and(|alpn| protocols.iter().any(|protocol| protocol == alpn)))
+            }
+            AclCondition::SslSni(names) => {
+                Ok(context.sni.is_some_and(|sni| names.iter().any(|name| name == sni)))
+            }
+            AclCondition::ClientHelloSni(names) => {
+                Ok(inspect::extract_sni(context.payload).is_some_and(|sni| names.contains(&sni)))
+            }
+            AclCondition::SourceDomain(suffixes) => {
+                Ok(context.src_dn.is_some_and(|name| suffixes.iter().any(|suffix| Self::domain_matches(name, suffix))))
+            }
+            AclCondition::SourceCountry(countries) => {
+                Ok(countries.contains(&context.country))
+            }
+            AclCondition::ConnectHost(hosts) => {
+                Ok(context.connect.is_some_and(|(host, _)| hosts.iter().any(|pattern| {
+                    if pattern.starts_with("*.") || pattern.starts_with('.') {
+                        Self::domain_matches(host, pattern)
+                    } else {
+                        host == pattern
+                    }
+                })))
+            }
+            AclCondition::ConnectPort(ranges) => {
+                Ok(context.connect.is_some_and(|(_, port)| ranges.iter().any(|(low, high)| (*low..=*high).contains(&port))))
+            }
+            AclCondition::HttpAuth(userlist, groups) => {
+                if inspect::extract_path(context.payload).is_none() {
+                    return Ok(false);
+                }
+                let header = inspect::extract_headers(context.payload, "authorization");
+                Ok(userlist.authenticate(header.first().copied())
+                    .is_some_and(|user| groups.is_empty() || userlist.in_groups(user, groups)))
             }
             AclCondition::Custom(_) => {
                 debug!("Custom ACL condition in L4 mode, allowing");
                 Ok(true)
             }
+            #[cfg(test)]
+            AclCondition::Failing => Err(anyhow!("injected failure")),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::io::AsyncWriteExt;
+
+    const CLIENT: &str = "192.0.2.1:40000";
+
+    fn acl(criterion: &str) -> Result<Acl> {
+        let config = AclConfig { name: "test".to_string(), criterion: words(criterion), span: Default::default() };
+        Acl::from_config(&config, &[])
+    }
+
+    fn words(text: &str) -> Vec<String> {
+        text.split_whitespace().map(str::to_string).collect()
+    }
+
+    fn table(acls: &[(&str, &str)]) -> AclTable {
+        let configs: Vec<AclConfig> = acls.iter()
+            .map(|(name, criterion)| AclConfig { name: name.to_string(), criterion: words(criterion), span: Default::default() })
+            .collect();
+        AclTable::compile(&configs, &[]).unwrap()
+    }
+
+    fn matches(criterion: &str, payload: &[u8]) -> bool {
+        acl(criterion).unwrap().evaluate(&AclContext::with_payload(CLIENT.parse().unwrap(), payload)).unwrap()
+    }
+
+    const REQUEST: &[u8] = b"GET /index.html HTTP/1.1\r\nHost: www.example.com\r\nUser-Agent: curl/8.4.0\r\n\r\n";
+
+    #[test]
+    fn header_fetches_parse_the_header_name_and_method() {
+        for (fetch, method) in [("hdr", MatchMethod::Exact), ("hdr_beg", MatchMethod::Prefix),
+                                ("hdr_end", MatchMethod::Suffix), ("hdr_sub", MatchMethod::Substring)] {
+            let acl = acl(&format!("{fetch}(User-Agent) curl wget")).unwrap();
+            match &acl.conditions[..] {
+                [AclCondition::Header(name, parsed, values)] => {
+                    assert_eq!((name.as_str(), *parsed), ("User-Agent", method));
+                    assert_eq!(values, &["curl", "wget"]);
+                }
+                other => panic!("{fetch}: {other:?}"),
+            }
+        }
+        assert!(matches!(acl("hdr_reg(host) ^www\\.").unwrap().conditions[..], [AclCondition::HeaderRegex(ref name, _)] if name == "host"));
+    }
+
+    #[test]
+    fn header_fetches_reject_missing_names_values_and_bad_regexes() {
+        for criterion in ["hdr_beg() www.", "hdr_sub curl", "hdr_end(host", "hdr_reg() x", "hdr_beg(host)", "hdr_reg(host)", "hdr_xyz(host) a"] {
+            assert!(acl(criterion).is_err(), "{criterion}");
+        }
+        let error = acl("hdr_reg(host) (unclosed").unwrap_err().to_string();
+        assert!(error.contains("invalid regex '(unclosed'"), "{error}");
+    }
+
+    #[test]
+    fn header_fetches_match_each_method() {
+        assert!(matches("hdr(host) www.example.com", REQUEST));
+        assert!(!matches("hdr(host) example.com", REQUEST));
+        assert!(matches("hdr_beg(host) api. www.", REQUEST));
+        assert!(!matches("hdr_beg(host) api.", REQUEST));
+        assert!(matches("hdr_end(host) .com", REQUEST));
+        assert!(!matches("hdr_end(host) .org", REQUEST));
+        assert!(matches("hdr_sub(user-agent) wget curl", REQUEST));
+        assert!(!matches("hdr_sub(user-agent) wget", REQUEST));
+        assert!(matches("hdr_reg(user-agent) ^curl/[0-9]+\\.", REQUEST));
+        assert!(!matches("hdr_reg(user-agent) ^wget/", REQUEST));
+    }
+
+    #[test]
+    fn header_names_are_case_insensitive() {
+        assert!(matches("hdr_sub(USER-AGENT) curl", REQUEST));
+        assert!(matches("hdr_reg(uSeR-aGeNt) curl", REQUEST));
+        assert!(matches("hdr_beg(Host) www.", b"GET / HTTP/1.1\r\nHOST: www.example.com\r\n\r\n"));
+    }
+
+    #[test]
+    fn missing_headers_never_match() {
+        for criterion in ["hdr(referer) x", "hdr_beg(referer) x", "hdr_end(referer) x", "hdr_sub(referer) x", "hdr_reg(referer) .*"] {
+            assert!(!matches(criterion, REQUEST), "{criterion}");
+        }
+    }
+
+    #[test]
+    fn header_fetches_match_connections_without_an_http_request() {
+        assert!(matches("hdr_beg(host) www.", b"SSH-2.0-OpenSSH_9.6\r\n"));
+        assert!(matches("hdr_reg(host) ^www\\.", b"\x16\x03\x01\x00\x05"));
+    }
+
+    #[test]
+    fn payload_outside_the_buffer_never_matches() {
+        let banner = b"SSH-2.0-OpenSSH_9.6\r\n";
+        assert!(matches("req.payload(0,4) -m bin 5353482d", banner));
+        assert!(matches("req.payload(4,3) -m bin 322e30", banner));
+        assert!(!matches("req.payload(20,4) -m bin 5353482d", banner));
+        assert!(!matches("req.payload(64,0) -m bin 00", banner));
+        assert!(!matches(&format!("req.payload({},2) -m bin 0000", usize::MAX), banner));
+        assert!(!matches("req.payload(0,4) -m bin 5353482d", b""));
+    }
+
+    #[test]
+    fn payload_patterns_must_fit_the_fetch() {
+        assert!(acl("req.payload(0,2) -m bin 160301").is_err());
+        assert!(acl("req.payload(0,2) -m bin 1g03").is_err());
+        assert!(acl("req.payload(0,2) 1603").is_err());
+        assert!(acl("req.payload(0) -m bin 16").is_err());
+        assert_eq!(acl("req.payload(3,2) -m bin 1603").unwrap().payload_needed(), 5);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn short_payload_waits_for_the_inspect_delay() {
+        let acl = acl("req.payload(0,7) -m bin 5353482d322e30").unwrap();
+        let delay = Duration::from_secs(5);
+        let (mut client, mut server) = tokio::io::duplex(64);
+
+        client.write_all(b"SSH-").await.unwrap();
+        let started = tokio::time::Instant::now();
+        let mut buffer = Vec::new();
+        let reading = crate::session::read_client_payload(&mut server, &mut buffer, 1024, acl.payload_needed(), false, false, delay);
+        let completing = async {
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            client.write_all(b"2.0-OpenSSH").await.unwrap();
+        };
+        let (overflow, _) = tokio::join!(reading, completing);
+        assert!(!overflow.unwrap());
+        assert_eq!(started.elapsed(), Duration::from_secs(2));
+        assert!(acl.evaluate(&AclContext::with_payload(CLIENT.parse().unwrap(), &buffer)).unwrap());
+
+        let (mut client, mut server) = tokio::io::duplex(64);
+        client.write_all(b"SSH-").await.unwrap();
+        let started = tokio::time::Instant::now();
+        let mut buffer = Vec::new();
+        crate::session::read_client_payload(&mut server, &mut buffer, 1024, acl.payload_needed(), false, false, delay).await.unwrap();
+        assert_eq!(started.elapsed(), delay);
+        assert_eq!(buffer, b"SSH-");
+        assert!(!acl.evaluate(&AclContext::with_payload(CLIENT.parse().unwrap(), &buffer)).unwrap());
+    }
+
+    #[test]
+    fn request_length_comparisons() {
+        for (criterion, empty, four) in [("req.len 0", true, false), ("req.len eq 4", false, true), ("req.len ge 4", false, true),
+                                         ("req.len gt 4", false, false), ("req.len le 0", true, false), ("req.len lt 4", true, false)] {
+            assert_eq!(matches(criterion, b""), empty, "{criterion} on 0 bytes");
+            assert_eq!(matches(criterion, b"abcd"), four, "{criterion} on 4 bytes");
+        }
+        assert_eq!(acl("req.len gt 4").unwrap().payload_needed(), 5);
+        assert_eq!(acl("req.len le 4").unwrap().payload_needed(), 4);
+        assert!(acl("req.len ne 4").is_err());
+        assert!(acl("req.len ge -1").is_err());
+        assert!(acl("req.len").is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn request_length_sees_the_buffered_bytes() {
+        let bufsize = 16;
+        let full = acl("req.len ge 16").unwrap();
+        let (mut client, mut server) = tokio::io::duplex(64);
+        client.write_all(&[b'x'; 40]).await.unwrap();
+        let mut buffer = Vec::new();
+        let overflow = crate::session::read_client_payload(&mut server, &mut buffer, bufsize, full.payload_needed(), false, false, Duration::from_secs(5)).await.unwrap();
+        assert!(!overflow);
+        assert_eq!(buffer.len(), bufsize);
+        assert!(full.evaluate(&AclContext::with_payload(CLIENT.parse().unwrap(), &buffer)).unwrap());
+
+        let (mut client, mut server) = tokio::io::duplex(64);
+        client.write_all(b"abcde").await.unwrap();
+        let mut buffer = Vec::new();
+        crate::session::read_client_payload(&mut server, &mut buffer, bufsize, full.payload_needed(), false, false, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(buffer, b"abcde");
+        let context = AclContext::with_payload(CLIENT.parse().unwrap(), &buffer);
+        assert!(!full.evaluate(&context).unwrap());
+        assert!(acl("req.len lt 16").unwrap().evaluate(&context).unwrap());
+        assert!(acl("req.len 5").unwrap().evaluate(&context).unwrap());
+    }
+
+    fn matches_destination(criterion: &str, destination: Option<&str>) -> bool {
+        let context = AclContext::with_payload(CLIENT.parse().unwrap(), b"")
+            .with_destination(destination.map(|destination| destination.parse().unwrap()));
+        acl(criterion).unwrap().evaluate(&context).unwrap()
+    }
+
+    #[test]
+    fn destination_matches_ipv4_and_ipv6_networks() {
+        assert!(matches_destination("dst 203.0.113.10", Some("203.0.113.10:443")));
+        assert!(!matches_destination("dst 203.0.113.10", Some("203.0.113.11:443")));
+        assert!(matches_destination("dst 10.0.0.0/8", Some("10.20.30.40:443")));
+        assert!(!matches_destination("dst 10.0.0.0/8", Some("11.0.0.1:443")));
+        assert!(matches_destination("dst 2001:db8::/32", Some("[2001:db8:1::5]:443")));
+        assert!(!matches_destination("dst 2001:db8::/32", Some("[2001:db9::5]:443")));
+        assert!(!matches_destination("dst 10.0.0.0/8", Some("[2001:db8::1]:443")));
+        assert!(!matches_destination("dst 10.0.0.0/8", None));
+    }
+
+    #[test]
+    fn destination_matches_ipv4_mapped_addresses_against_ipv4_networks() {
+        assert!(matches_destination("dst 10.0.0.0/8", Some("[::ffff:10.0.0.1]:443")));
+        assert!(!matches_destination("dst 192.168.0.0/16", Some("[::ffff:10.0.0.1]:443")));
+        let context = AclContext::with_payload("[::ffff:10.1.2.3]:40000".parse().unwrap(), b"");
+        assert!(acl("src 10.0.0.0/8").unwrap().evaluate(&context).unwrap());
+    }
+
+    #[test]
+    fn destination_port_combines_with_the_address() {
+        assert!(matches_destination("dst_port 443", Some("203.0.113.10:443")));
+        assert!(!matches_destination("dst_port 443", Some("203.0.113.10:8443")));
+        assert!(!matches_destination("dst_port 443", None));
+
+        let table = table(&[("site_a", "dst 203.0.113.0/24"), ("https", "dst_port 443")]);
+        let both = table.condition(&words("if site_a https")).unwrap();
+        let context = |destination: &str| AclContext::with_payload(CLIENT.parse().unwrap(), b"").with_destination(Some(destination.parse().unwrap()));
+        assert!(table.evaluate(&both, &context("203.0.113.10:443")).unwrap());
+        assert!(!table.evaluate(&both, &context("203.0.113.10:80")).unwrap());
+        assert!(!table.evaluate(&both, &context("198.51.100.1:443")).unwrap());
+    }
+
+    #[test]
+    fn destination_rejects_bad_networks() {
+        for criterion in ["dst", "dst 10.0.0.0/33", "dst not-an-ip", "dst_port", "dst_port 70000"] {
+            assert!(acl(criterion).is_err(), "{criterion}");
+        }
+    }
+
+    #[test]
+    fn evaluation_errors_name_the_failing_acl() {
+        let mut table = table(&[("broken", "src 0.0.0.0/0"), ("local", "src 192.0.2.0/24")]);
+        table.make_failing("broken");
+        let context = AclContext::with_payload(CLIENT.parse().unwrap(), b"");
+
+        let error = table.evaluate(&table.condition(&words("if local broken")).unwrap(), &context).unwrap_err();
+        assert_eq!(error.acl, "broken");
+        assert_eq!(error.to_string(), "ACL 'broken' failed to evaluate: injected failure");
+        assert!(table.evaluate(&table.condition(&words("unless broken")).unwrap(), &context).is_err());
+        assert!(table.evaluate(&table.condition(&words("if local or broken")).unwrap(), &context).unwrap());
+    }
+}
@@ -0,0 +1,304 @@
+use crate::audit::{AuditEntry, AuditSource};
+use crate::balancer::{AdminState, MAX_WEIGHT};
+use crate::error_log::ErrorScope;
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::task;
+use tracing::{info, warn, error, debug};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MaxconnTarget {
+    Global,
+    Frontend(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdminCommand {
+    SetServerState {
+        backend: String,
+        server: String,
+        state: AdminState,
+    },
+    SetWeight {
+        backend: String,
+        server: String,
+        weight: u32,
+    },
+    ShowServersState,
+    ShowBalance {
+        backend: String,
+    },
+    ShowStat,
+    ShowAcl,
+    ShowInfo {
+        json: bool,
+    },
+    SetMaxconn {
+        target: MaxconnTarget,
+        value: u32,
+    },
+    ShowErrors {
+        scope: ErrorScope,
+    },
+    ClearErrors,
+    UpdateSslCert {
+        path: String,
+    },
+    SetDraining {
+        on: bool,
+    },
+    ShowAudit {
+        last: usize,
+    },
+    ShowMetrics,
+    SetMetricsBind {
+        addresses: Vec<String>,
+    },
+    SetMetrics {
+        on: bool,
+    },
+    SetBalanceAdaptive {
+        backend: String,
+        on: bool,
+    },
+    AddBackend {
+        backend: String,
+        server: String,
+        args: Vec<String>,
+    },
+    DelBackend {
+        backend: String,
+    },
+    AddServer {
+        backend: String,
+        server: String,
+        args: Vec<String>,
+    },
+    DelServer {
+        backend: String,
+        server: String,
+    },
+    SetDefaultBackend {
+        frontend: String,
+        backend: String,
+    },
+}
+
+impl AdminCommand {
+    pub fn parse(line: &str) -> Result<Self> {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+
+        match parts.as_slice() {
+            ["set", "server", target, "state", state] => {
+                let (backend, server) = target.split_once('/')
+                    .ok_or_else(|| anyhow!("Expected <backend>/<server>, got '{}'", target))?;
+                Ok(AdminCommand::SetServerState {
+                    backend: backend.to_string(),
+                    server: server.to_string(),
+                    state: AdminState::parse(state)?,
+                })
+            }
+            ["set", "weight", target, weight] => {
+                let (backend, server) = target.split_once('/')
+                    .ok_or_else(|| anyhow!("Expected <backend>/<server>, got '{}'", target))?;
+                Ok(AdminCommand::SetWeight {
+                    backend: backend.to_string(),
+                    server: server.to_string(),
+                    weight: parse_weight(weight)?,
+                })
+            }
+            ["show", "servers", "state"] => Ok(AdminCommand::ShowServersState),
+            ["show", "balance", backend] => Ok(AdminCommand::ShowBalance {
+                backend: backend.to_string(),
+            }),
+            ["show", "stat"] => Ok(AdminCommand::ShowStat),
+            ["show", "acl"] => Ok(AdminCommand::ShowAcl),
+            ["show", "info"] => Ok(AdminCommand::ShowInfo { json: false }),
+            ["show", "info", "json"] => Ok(AdminCommand::ShowInfo { json: true }),
+            ["set", "maxconn", "global", value] => Ok(AdminCommand::SetMaxconn {
+                target: MaxconnTarget::Global,
+                value: parse_maxconn(value)?,
+            }),
+            ["set", "maxconn", "frontend", frontend, value] => Ok(AdminCommand::SetMaxconn {
+                target: MaxconnTarget::Frontend(frontend.to_string()),
+                value: parse_maxconn(value)?,
+            }),
+            ["show", "errors"] => Ok(AdminCommand::ShowErrors { scope: ErrorScope::All }),
+            ["show", "errors", "frontend", frontend] => Ok(AdminCommand::ShowErrors {
+                scope: ErrorScope::Frontend(frontend.to_string()),
+            }),
+            ["show", "errors", "backend", backend] => Ok(AdminCommand::ShowErrors {
+                scope: ErrorScope::Backend(backend.to_string()),
+            }),
+            ["clear", "errors"] => Ok(AdminCommand::ClearErrors),
+            ["update", "ssl", "cert", path] => Ok(AdminCommand::UpdateSslCert {
+                path: path.to_string(),
+            }),
+            ["set", "draining", "on"] => Ok(AdminCommand::SetDraining { on: true }),
+            ["set", "draining", "off"] => Ok(AdminCommand::SetDraining { on: false }),
+            ["set", "draining", other] => Err(anyhow!("Invalid draining state '{}', expected on or off", other)),
+            ["show", "audit", "last", count] => Ok(AdminCommand::ShowAudit {
+                last: count.parse().map_err(|_| anyhow!("Invalid count '{}', expected a number", count))?,
+            }),
+            ["show", "metrics"] => Ok(AdminCommand::ShowMetrics),
+            ["set", "metrics", "bind", addresses @ ..] if !addresses.is_empty() => Ok(AdminCommand::SetMetricsBind {
+                addresses: addresses.iter().map(|address| address.to_string()).collect(),
+            }),
+            ["set", "metrics", "on"] => Ok(AdminCommand::SetMetrics { on: true }),
+            ["set", "metrics", "off"] => Ok(AdminCommand::SetMetrics { on: false }),
+            ["set", "metrics", other] => Err(anyhow!("Invalid metrics state '{}', expected on or off", other)),
+            ["set", "balance-adaptive", backend, "on"] => Ok(AdminCommand::SetBalanceAdaptive { backend: backend.to_string(), on: true }),
+            ["set", "balance-adaptive", backend, "off"] => Ok(AdminCommand::SetBalanceAdaptive { backend: backend.to_string(), on: false }),
+            ["set", "balance-adaptive", _, other] => Err(anyhow!("Invalid balance-adaptive state '{}', expected on or off", other)),
+            ["add", "backend", backend, server, args @ ..] if !args.is_empty() => Ok(AdminCommand::AddBackend {
+                backend: backend.to_string(),
+                server: server.to_string(),
+                args: args.iter().map(|arg| arg.to_string()).collect(),
+            }),
+            ["del", "backend", backend] => Ok(AdminCommand::DelBackend { backend: backend.to_string() }),
+            ["add", "server", target, args @ ..] if !args.is_empty() => {
+                let (backend, server) = target.split_once('/')
+                    .ok_or_else(|| anyhow!("Expected <backend>/<server>, got '{}'", target))?;
+                Ok(AdminCommand::AddServer {
+                    backend: backend.to_string(),
+                    server: server.to_string(),
+                    args: args.iter().map(|arg| arg.to_string()).collect(),
+                })
+            }
+            ["del", "server", target] => {
+                let (backend, server) = target.split_once('/')
+                    .ok_or_else(|| anyhow!("Expected <backend>/<server>, got '{}'", target))?;
+                Ok(AdminCommand::DelServer {
+                    backend: backend.to_string(),
+                    server: server.to_string(),
+                })
+            }
+            ["set", "frontend", frontend, "default_backend", backend] => Ok(AdminCommand::SetDefaultBackend {
+                frontend: frontend.to_string(),
+                backend: backend.to_string(),
+            }),
+            [] => Err(anyhow!("Empty command")),
+            _ => Err(anyhow!("Unknown command: {}", line.trim())),
+        }
+    }
+
+    pub fn audit_entry(&self, source: AuditSource) -> Option<AuditEntry> {
+        let entry = match self {
+            AdminCommand::SetServerState { backend, server, state } => AuditEntry::new(source, "set-server-state")
+                .target(format!("{}/{}", backend, server))
+                .value(state.as_str()),
+            AdminCommand::SetWeight { backend, server, weight } => AuditEntry::new(source, "set-weight")
+                .target(format!("{}/{}", backend, server))
+                .value(weight.to_string()),
+            AdminCommand::SetMaxconn { target, value } => AuditEntry::new(source, "set-maxconn")
+                .target(match target {
+                    MaxconnTarget::Global => "global".to_string(),
+                    MaxconnTarget::Frontend(frontend) => format!("frontend/{}", frontend),
+                })
+                .value(value.to_string()),
+            AdminCommand::ClearErrors => AuditEntry::new(source, "clear-errors"),
+            AdminCommand::UpdateSslCert { path } => AuditEntry::new(source, "update-ssl-cert").target(path.as_str()),
+            AdminCommand::SetDraining { on } => AuditEntry::new(source, "set-draining")
+                .value(if *on { "on" } else { "off" }),
+            AdminCommand::SetMetricsBind { addresses } => AuditEntry::new(source, "set-metrics-bind")
+                .value(addresses.join(" ")),
+            AdminCommand::SetMetrics { on } => AuditEntry::new(source, "set-metrics")
+                .value(if *on { "on" } else { "off" }),
+            AdminCommand::SetBalanceAdaptive { backend, on } => AuditEntry::new(source, "set-balance-adaptive")
+                .target(backend.as_str())
+                .value(if *on { "on" } else { "off" }),
+            AdminCommand::AddBackend { backend, server, args } => AuditEntry::new(source, "add-backend")
+                .target(backend.as_str())
+                .value(format!("{} {}", server, args.join(" "))),
+            AdminCommand::DelBackend { backend } => AuditEntry::new(source, "del-backend").target(backend.as_str()),
+            AdminCommand::AddServer { backend, server, args } => AuditEntry::new(source, "add-server")
+                .target(format!("{}/{}", backend, server))
+                .value(args.join(" ")),
+            AdminCommand::DelServer { backend, server } => AuditEntry::new(source, "del-server")
+                .target(format!("{}/{}", backend, server)),
+            AdminCommand::SetDefaultBackend { frontend, backend } => AuditEntry::new(source, "set-default-backend")
+                .target(frontend.as_str())
+                .value(backend.as_str()),
+            _ => return None,
+        };
+        Some(entry)
+    }
+}
+
+fn parse_weight(value: &str) -> Result<u32> {
+    value.parse()
+        .ok()
+        .filter(|weight| *weight <= MAX_WEIGHT)
+        .ok_or_else(|| anyhow!("Invalid weight '{}', expected 0 to {}", value, MAX_WEIGHT))
+}
+
+fn parse_maxconn(value: &str) -> Result<u32> {
+    value.parse().map_err(|_| anyhow!("Invalid maxconn '{}', expected a number", value))
+}
+
+#[async_trait]
+pub trait AdminHandler: Send + Sync {
+    async fn handle(&self, command: AdminCommand, source: AuditSource) -> Result<String>;
+}
+
+pub struct AdminServer {
+    path: String,
+    handler: Arc<dyn AdminHandler>,
+}
+
+impl AdminServer {
+    pub fn new(path: String, handler: Arc<dyn AdminHandler>) -> Self {
+        Self { path, handler }
+    }
+
+    pub async fn run(self) -> Result<()> {
+        if Path::new(&self.path).exists() {
+            std::fs::remove_file(&self.path)?;
+        }
+        let listener = UnixListener::bind(&self.path)?;
+        info!("Admin socket listening on {}", self.path);
+
+        loop {
+            let (stream, _addr) = listener.accept().await?;
+            let handler = Arc::clone(&self.handler);
+            task::spawn(async move {
+                if let Err(e) = Self::handle_client(stream, handler).await {
+                    warn!("Admin client error: {}", e);
+                }
+            });
+        }
+    }
+
+    async fn handle_client(stream: UnixStream, handler: Arc<dyn AdminHandler>) -> Result<()> {
+        let peer = AuditSource::unix_peer(&stream);
+        let (read_half, mut write_half) = stream.into_split();
+        let mut lines = BufReader::new(read_half).lines();
+
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            debug!("Admin command: {}", line);
+
+            let response = match AdminCommand::parse(&line) {
+                Ok(command) => match handler.handle(command, AuditSource::AdminSocket { peer: peer.clone() }).await {
+                    Ok(output) => output,
+                    Err(e) => {
+                        error!("Admin command '{}' failed: {}", line, e);
+                        e.to_string()
+                    }
+                },
+                Err(e) => e.to_string(),
+            };
+
+            write_half.write_all(response.as_bytes()).await?;
+            write_half.write_all(b"\n\n").await?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,241 @@
+use crate::config::GlobalConfig;
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use tokio::net::UnixStream;
+use tracing::{info, warn};
+
+pub const AUDIT_RING_SIZE: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum AuditSource {
+    FileWatcher,
+    Sighup,
+    AdminSocket { peer: String },
+    StatsHttp { peer: String },
+}
+
+impl AuditSource {
+    pub fn unix_peer(stream: &UnixStream) -> String {
+        match stream.peer_cred() {
+            Ok(cred) => match cred.pid() {
+                Some(pid) => format!("uid={},pid={}", cred.uid(), pid),
+                None => format!("uid={}", cred.uid()),
+            },
+            Err(_) => "unknown".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for AuditSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditSource::FileWatcher => f.write_str("file-watcher"),
+            AuditSource::Sighup => f.write_str("sighup"),
+            AuditSource::AdminSocket { peer } => write!(f, "admin-socket({})", peer),
+            AuditSource::StatsHttp { peer } => write!(f, "stats-http({})", peer),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Utc>,
+    pub source: AuditSource,
+    pub action: &'static str,
+    pub target: Option<String>,
+    pub value: Option<String>,
+    pub config_hash: Option<String>,
+    pub result: &'static str,
+    pub error: Option<String>,
+}
+
+impl AuditEntry {
+    pub fn new(source: AuditSource, action: &'static str) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            source,
+            action,
+            target: None,
+            value: None,
+            config_hash: None,
+            result: "ok",
+            error: None,
+        }
+    }
+
+    pub fn target(mut self, target: impl Into<String>) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+
+    pub fn value(mut self, value: impl Into<String>) -> Self {
+        self.value = Some(value.into());
+        self
+    }
+
+    pub fn config_hash(mut self, hash: impl Into<String>) -> Self {
+        self.config_hash = Some(hash.into());
+        self
+    }
+
+    pub fn outcome<T>(mut self, result: &Result<T>) -> Self {
+        if let Err(e) = result {
+            self.result = "error";
+            self.error = Some(e.to_string());
+        }
+        self
+    }
+
+    pub fn to_line(&self) -> String {
+        format!("{} {} {} {} {} {}{}",
+            self.timestamp.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            self.source,
+            self.action,
+            self.target.as_deref().or(self.config_hash.as_deref()).unwrap_or("-"),
+            self.value.as_deref().unwrap_or("-"),
+            self.result,
+            self.error.as_ref().map(|error| format!(" {}", error)).unwrap_or_default())
+    }
+}
+
+struct AuditFile {
+    path: String,
+    file: File,
+    fsync: bool,
+}
+
+#[derive(Default)]
+pub struct AuditLog {
+    entries: Mutex<VecDeque<Arc<AuditEntry>>>,
+    file: Mutex<Option<AuditFile>>,
+}
+
+impl AuditLog {
+    pub fn configure(&self, global: &GlobalConfig) -> Result<()> {
+        let mut current = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(ref path) = global.audit_log else {
+            *current = None;
+            return Ok(());
+        };
+
+        if let Some(ref mut audit_file) = *current {
+            if audit_file.path == *path {
+                audit_file.fsync = global.audit_log_fsync;
+                return Ok(());
+            }
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| anyhow!("Cannot open audit log '{}': {}", path, e))?;
+        info!("Audit log writing to {}{}", path, if global.audit_log_fsync { " with fsync" } else { "" });
+        *current = Some(AuditFile { path: path.clone(), file, fsync: global.audit_log_fsync });
+        Ok(())
+    }
+
+    pub fn record(&self, entry: AuditEntry) {
+        let entry = Arc::new(entry);
+        {
+            let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+            if entries.len() == AUDIT_RING_SIZE {
+                entries.pop_front();
+            }
+            entries.push_back(Arc::clone(&entry));
+        }
+
+        let mut current = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(ref mut audit_file) = *current else {
+            return;
+        };
+        let mut line = match serde_json::to_string(&*entry) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("Cannot serialize audit entry: {}", e);
+                return;
+            }
+        };
+        line.push('\n');
+        let written = audit_file.file.write_all(line.as_bytes())
+            .and_then(|()| if audit_file.fsync { audit_file.file.sync_data() } else { Ok(()) });
+        if let Err(e) = written {
+            warn!("Cannot write audit log '{}': {}", audit_file.path, e);
+        }
+    }
+
+    pub fn last(&self, count: usize) -> Vec<Arc<AuditEntry>> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.iter().skip(entries.len().saturating_sub(count)).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_file(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("turbogate-audit-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join("audit.log").to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn lines_show_source_target_and_outcome() {
+        let entry = AuditEntry::new(AuditSource::AdminSocket { peer: "uid=0".to_string() }, "set-weight")
+            .target("app/s1")
+            .value("50")
+            .outcome(&Err::<(), _>(anyhow!("no such server")));
+        let line = entry.to_line();
+        assert!(line.ends_with(" admin-socket(uid=0) set-weight app/s1 50 error no such server"), "{line}");
+
+        let reload = AuditEntry::new(AuditSource::Sighup, "reload").config_hash("abc123").outcome(&Ok(()));
+        assert!(reload.to_line().ends_with(" sighup reload abc123 - ok"), "{}", reload.to_line());
+    }
+
+    #[test]
+    fn the_ring_keeps_the_latest_entries() {
+        let log = AuditLog::default();
+        for index in 0..AUDIT_RING_SIZE + 3 {
+            log.record(AuditEntry::new(AuditSource::FileWatcher, "reload").value(index.to_string()));
+        }
+        let last = log.last(2);
+        assert_eq!(last.iter().map(|entry| entry.value.clone().unwrap()).collect::<Vec<_>>(),
+                   [(AUDIT_RING_SIZE + 1).to_string(), (AUDIT_RING_SIZE + 2).to_string()]);
+        assert_eq!(log.last(usize::MAX).len(), AUDIT_RING_SIZE);
+    }
+
+    #[test]
+    fn entries_are_appended_to_the_file_as_json() {
+        let path = scratch_file("append");
+        let log = AuditLog::default();
+        let global = GlobalConfig { audit_log: Some(path.clone()), ..GlobalConfig::default() };
+        log.configure(&global).unwrap();
+        log.record(AuditEntry::new(AuditSource::StatsHttp { peer: "192.0.2.1:1".to_string() }, "drain"));
+        log.configure(&GlobalConfig::default()).unwrap();
+        log.record(AuditEntry::new(AuditSource::Sighup, "reload"));
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = content.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 1, "entries after the audit log was turned off are kept in memory only");
+        assert_eq!(lines[0]["source"]["kind"], "stats-http");
+        assert_eq!(lines[0]["source"]["peer"], "192.0.2.1:1");
+        assert_eq!(lines[0]["action"], "drain");
+        assert_eq!(log.last(10).len(), 2);
+        let _ = std::fs::remove_dir_all(std::path::Path::new(&path).parent().unwrap());
+    }
+
+    #[test]
+    fn an_unwritable_path_is_an_error() {
+        let global = GlobalConfig { audit_log: Some("/nonexistent/dir/audit.log".to_string()), ..GlobalConfig::default() };
+        let error = AuditLog::default().configure(&global).unwrap_err().to_string();
+        assert!(error.starts_with("Cannot open audit log '/nonexistent/dir/audit.log'"), "{error}");
+    }
+}
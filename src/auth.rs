@@ -0,0 +1,400 @@
+use crate::bcrypt::BcryptHash;
+use crate::config::{UserPassword, UserlistConfig};
+use crate::utils;
+use anyhow::{Result, anyhow};
+use base64::Engine;
+use sha2::{Digest, Sha256, Sha512};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::runtime::{Handle, RuntimeFlavor};
+
+const CRYPT_ALPHABET: &[u8; 64] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+const SHA_CRYPT_ROUNDS: u32 = 5000;
+const SHA_CRYPT_MAX_SALT: usize = 16;
+const VERIFIED_CACHE_SIZE: usize = 1024;
+const SHA256_ORDER: &[[usize; 3]] = &[
+    [0, 10, 20], [21, 1, 11], [12, 22, 2], [3, 13, 23], [24, 4, 14],
+    [15, 25, 5], [6, 16, 26], [27, 7, 17], [18, 28, 8], [9, 19, 29],
+];
+const SHA512_ORDER: &[[usize; 3]] = &[
+    [0, 21, 42], [22, 43, 1], [44, 2, 23], [3, 24, 45], [25, 46, 4], [47, 5, 26], [6, 27, 48],
+    [28, 49, 7], [50, 8, 29], [9, 30, 51], [31, 52, 10], [53, 11, 32], [12, 33, 54], [34, 55, 13],
+    [56, 14, 35], [15, 36, 57], [37, 58, 16], [59, 17, 38], [18, 39, 60], [40, 61, 19], [62, 20, 41],
+];
+
+#[derive(Debug)]
+struct User {
+    name: String,
+    password: UserPassword,
+    groups: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct Userlist {
+    name: String,
+    users: Vec<User>,
+    verified: Mutex<HashMap<[u8; 32], usize>>,
+}
+
+impl Userlist {
+    pub fn new(config: &UserlistConfig) -> Self {
+        let users = config.users.iter()
+            .map(|user| User {
+                name: user.name.clone(),
+                password: user.password.clone(),
+                groups: config.groups.iter()
+                    .filter(|group| user.groups.contains(&group.name) || group.users.contains(&user.name))
+                    .map(|group| group.name.clone())
+                    .collect(),
+            })
+            .collect();
+
+        Self { name: config.name.clone(), users, verified: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn authenticate(&self, header: Option<&str>) -> Option<&str> {
+        let (name, password) = basic_credentials(header?)?;
+        let index = self.users.iter().position(|user| user.name == name)?;
+        let user = &self.users[index];
+        let UserPassword::Hashed(_) = user.password else {
+            return verify_password(&password, &user.password).then_some(user.name.as_str());
+        };
+
+        // Hashes are deliberately slow, so a credential that verified once is
+        // remembered by its digest rather than hashed again on every request.
+        let key: [u8; 32] = Sha256::new().chain_update(&name).chain_update([0]).chain_update(&password).finalize().into();
+        if self.verified.lock().unwrap_or_else(|e| e.into_inner()).get(&key) == Some(&index) {
+            return Some(user.name.as_str());
+        }
+        if !verify_off_worker(&password, &user.password) {
+            return None;
+        }
+        let mut verified = self.verified.lock().unwrap_or_else(|e| e.into_inner());
+        if verified.len() >= VERIFIED_CACHE_SIZE {
+            verified.clear();
+        }
+        verified.insert(key, index);
+        Some(user.name.as_str())
+    }
+
+    pub fn in_groups(&self, user: &str, groups: &[String]) -> bool {
+        self.users.iter()
+            .find(|candidate| candidate.name == user)
+            .is_some_and(|user| user.groups.iter().any(|group| groups.contains(group)))
+    }
+}
+
+pub fn challenge(status: &str, header: &str, realm: &str, message: &str) -> Vec<u8> {
+    format!("HTTP/1.0 {status}\r\nCache-Control: no-cache\r\nConnection: close\r\n{header}: Basic realm=\"{}\"\r\nContent-Type: text/html\r\n\r\n<html><body><h1>{status}</h1>\n{message}\n</body></html>\n",
+            realm.replace(['"', '\\'], "_")).into_bytes()
+}
+
+fn basic_credentials(header: &str) -> Option<(String, String)> {
+    let (scheme, encoded) = header.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = base64::engine::general_purpose::STANDARD.decode(encoded.trim()).ok()?;
+    let (name, password) = String::from_utf8(decoded).ok()?.split_once(':')
+        .map(|(name, password)| (name.to_string(), password.to_string()))?;
+    Some((name, password))
+}
+
+fn verify_off_worker(password: &str, stored: &UserPassword) -> bool {
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(|| verify_password(password, stored))
+        }
+        _ => verify_password(password, stored),
+    }
+}
+
+fn verify_password(password: &str, stored: &UserPassword) -> bool {
+    match stored {
+        UserPassword::Insecure(expected) => utils::constant_time_eq(expected.as_bytes(), password.as_bytes()),
+        UserPassword::Hashed(hash) => crypt(password, hash)
+            .is_ok_and(|computed| utils::constant_time_eq(computed.as_bytes(), hash.as_bytes())),
+    }
+}
+
+pub fn check_hash(hash: &str) -> Result<()> {
+    if hash.starts_with("$2") {
+        return BcryptHash::parse(hash).map(|_| ());
+    }
+    let (setting, checksum_len) = match hash.get(..3) {
+        Some("$5$") => (&hash[3..], 43),
+        Some("$6$") => (&hash[3..], 86),
+        _ => return Err(unsupported_hash()),
+    };
+    let (_, salt, checksum) = sha_crypt_setting(setting)?;
+    if salt.len() > SHA_CRYPT_MAX_SALT || checksum.len() != checksum_len || !checksum.bytes().all(|byte| CRYPT_ALPHABET.contains(&byte)) {
+        return Err(anyhow!("malformed SHA crypt hash, expected {}[rounds=<n>$]<salt>$<{} characters>", &hash[..3], checksum_len));
+    }
+    Ok(())
+}
+
+fn unsupported_hash() -> anyhow::Error {
+    anyhow!("unsupported password hash, expected bcrypt ($2a$, $2b$, $2y$), SHA-256 crypt ($5$) or SHA-512 crypt ($6$)")
+}
+
+fn crypt(password: &str, hash: &str) -> Result<String> {
+    if hash.starts_with("$2") {
+        return Ok(BcryptHash::parse(hash)?.hash(password.as_bytes()));
+    }
+    match hash.get(..3) {
+        Some("$5$") => sha_crypt::<Sha256>(password, &hash[3..], "$5$", SHA256_ORDER),
+        Some("$6$") => sha_crypt::<Sha512>(password, &hash[3..], "$6$", SHA512_ORDER),
+        _ => Err(unsupported_hash()),
+    }
+}
+
+fn sha_crypt_setting(setting: &str) -> Result<(Option<u32>, &str, &str)> {
+    let (rounds, setting) = match setting.strip_prefix("rounds=").and_then(|rest| rest.split_once('$')) {
+        Some((rounds, rest)) => {
+            let rounds: u32 = rounds.parse().map_err(|_| anyhow!("invalid rounds '{}'", rounds))?;
+            (Some(rounds.clamp(1000, 999_999_999)), rest)
+        }
+        None => (None, setting),
+    };
+    let (salt, checksum) = setting.split_once('$').unwrap_or((setting, ""));
+    Ok((rounds, salt, checksum))
+}
+
+fn sha_crypt<D: Digest>(password: &str, setting: &str, prefix: &str, order: &[[usize; 3]]) -> Result<String> {
+    let (rounds, salt, _) = sha_crypt_setting(setting)?;
+    let salt = &salt.as_bytes()[..salt.len().min(SHA_CRYPT_MAX_SALT)];
+    let password = password.as_bytes();
+
+    let alternate = D::new().chain_update(password).chain_update(salt).chain_update(password).finalize();
+    let mut digest = D::new().chain_update(password).chain_update(salt);
+    for chunk in password.chunks(alternate.len()) {
+        digest.update(&alternate[..chunk.len()]);
+    }
+    let mut length = password.len();
+    while length > 0 {
+        if length & 1 == 1 {
+            digest.update(&alternate);
+        } else {
+            digest.update(password);
+        }
+        length >>= 1;
+    }
+    let mut current = digest.finalize();
+
+    let mut p_digest = D::new();
+    for _ in 0..password.len() {
+        p_digest.update(password);
+    }
+    let p_digest = p_digest.finalize();
+    let p_bytes: Vec<u8> = p_digest.iter().cycle().take(password.len()).copied().collect();
+    let mut s_digest = D::new();
+    for _ in 0..16 + current[0] as usize {
+        s_digest.update(salt);
+    }
+    let s_digest = s_digest.finalize();
+    let s_bytes: Vec<u8> = s_digest.iter().cycle().take(salt.len()).copied().collect();
+
+    for round in 0..rounds.unwrap_or(SHA_CRYPT_ROUNDS) {
+        let mut digest = D::new();
+        if round & 1 == 1 {
+            digest.update(&p_bytes);
+        } else {
+            digest.update(&current);
+        }
+        if round % 3 != 0 {
+            digest.update(&s_bytes);
+        }
+        if round % 7 != 0 {
+            digest.update(&p_bytes);
+        }
+        if round & 1 == 1 {
+            digest.update(&current);
+        } else {
+            digest.update(&p_bytes);
+        }
+        current = digest.finalize();
+    }
+
+    let mut encoded = String::from(prefix);
+    if let Some(rounds) = rounds {
+        encoded.push_str(&format!("rounds={}$", rounds));
+    }
+    encoded.push_str(std::str::from_utf8(salt)?);
+    encoded.push('$');
+    for [a, b, c] in order {
+        push_crypt64(&mut encoded, (current[*a] as u32) << 16 | (current[*b] as u32) << 8 | current[*c] as u32, 4);
+    }
+    match current.len() {
+        32 => push_crypt64(&mut encoded, (current[31] as u32) << 8 | current[30] as u32, 3),
+        _ => push_crypt64(&mut encoded, current[63] as u32, 2),
+    }
+    Ok(encoded)
+}
+
+fn push_crypt64(encoded: &mut String, mut word: u32, chars: usize) {
+    for _ in 0..chars {
+        encoded.push(CRYPT_ALPHABET[(word & 0x3f) as usize] as char);
+        word >>= 6;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{GroupConfig, UserConfig};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    const SHA512_HELLO: &str = "$6$saltstring$svn8UoSVapNtMuq1ukKS4tPQd8iKwSMHWjl/O817G3uBnIFNjnQJuesI68u4OTLiBFdcbYEdFCoEOfaS35inz1";
+    const BCRYPT_UU: &str = "$2a$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW";
+
+    #[test]
+    fn sha_crypt_known_answers() {
+        let cases = [
+            ("Hello world!", SHA512_HELLO),
+            ("Hello world!", "$6$rounds=10000$saltstringsaltst$OW1/O6BYHV6BcXZu8QVeXbDWra3Oeqh0sbHbbMCVNSnCM/UrjmM0Dp8vOuZeHBy/YTBmSK6H9qs/y3RnOaw5v."),
+            ("", "$6$rounds=1000$abc$noBronbzNMcAtG61/dMHzc1H.fuLjHArF9.wCx8LbkVDMMEuChQCPee28tiPcXHL/CMNpCqo6OPTyoEpoCMsY/"),
+            ("Hello world!", "$5$saltstring$5B8vYYiY.CVt1RlTTf8KbXBH3hsxY/GNooZaBBGWEc5"),
+            ("Hello world!", "$5$rounds=10000$saltstringsaltst$3xv.VbSHBb41AL9AvLeujZkZRBAwqFMz2.opqey6IcA"),
+        ];
+        for (password, hash) in cases {
+            assert_eq!(crypt(password, hash).unwrap(), hash, "{hash}");
+            assert!(verify_password(password, &UserPassword::Hashed(hash.to_string())), "{hash}");
+        }
+    }
+
+    #[test]
+    fn sha_crypt_salt_is_truncated_to_sixteen_characters() {
+        let hash = "$6$0123456789abcdef$8CGkKDSyVpVlVNcs5PssRw/Tb8EgfecuA6D/zsRQFumJaU.s8KgTWWc2HfgkB7h2n1qIKiJFeSyUFrwvhILXy.";
+        assert_eq!(crypt("secret", "$6$0123456789abcdefXYZ$").unwrap(), hash);
+    }
+
+    #[test]
+    fn wrong_passwords_do_not_verify() {
+        for hash in [SHA512_HELLO, BCRYPT_UU] {
+            assert!(!verify_password("Hello world?", &UserPassword::Hashed(hash.to_string())));
+        }
+        assert!(verify_password("U*U", &UserPassword::Hashed(BCRYPT_UU.to_string())));
+        assert!(verify_password("plain", &UserPassword::Insecure("plain".to_string())));
+        assert!(!verify_password("plain ", &UserPassword::Insecure("plain".to_string())));
+    }
+
+    #[test]
+    fn hash_formats_are_checked() {
+        assert!(check_hash(SHA512_HELLO).is_ok());
+        assert!(check_hash(BCRYPT_UU).is_ok());
+        let cases = [
+            ("$1$salt$hash", "unsupported password hash"),
+            ("plain", "unsupported password hash"),
+            ("$6$saltstring$short", "malformed SHA crypt hash, expected $6$"),
+            ("$5$saltstring$5B8vYYiY.CVt1RlTTf8KbXBH3hsxY/GNooZaBBGWEc!", "malformed SHA crypt hash, expected $5$"),
+            ("$6$rounds=lots$salt$x", "invalid rounds 'lots'"),
+            ("$6$01234567890123456$svn8UoSVapNtMuq1ukKS4tPQd8iKwSMHWjl/O817G3uBnIFNjnQJuesI68u4OTLiBFdcbYEdFCoEOfaS35inz1", "malformed SHA crypt hash"),
+        ];
+        for (hash, message) in cases {
+            let error = check_hash(hash).unwrap_err().to_string();
+            assert!(error.contains(message), "{hash}: {error}");
+        }
+    }
+
+    fn userlist() -> Userlist {
+        Userlist::new(&UserlistConfig {
+            name: "admins".to_string(),
+            users: vec![
+                UserConfig { name: "alice".to_string(), password: UserPassword::Insecure("secret".to_string()), groups: vec!["ops".to_string()] },
+                UserConfig { name: "bob".to_string(), password: UserPassword::Hashed(BCRYPT_UU.to_string()), groups: Vec::new() },
+            ],
+            groups: vec![
+                GroupConfig { name: "ops".to_string(), users: Vec::new() },
+                GroupConfig { name: "dev".to_string(), users: vec!["bob".to_string()] },
+            ],
+        })
+    }
+
+    fn basic(credentials: &str) -> String {
+        format!("Basic {}", base64::engine::general_purpose::STANDARD.encode(credentials))
+    }
+
+    #[test]
+    fn basic_credentials_are_authenticated() {
+        let userlist = userlist();
+        assert_eq!(userlist.authenticate(Some(&basic("alice:secret"))), Some("alice"));
+        assert_eq!(userlist.authenticate(Some(&basic("alice:wrong"))), None);
+        assert_eq!(userlist.authenticate(Some(&basic("bob:U*U"))), Some("bob"));
+        assert_eq!(userlist.authenticate(Some(&basic("bob:U*V"))), None);
+        assert_eq!(userlist.authenticate(Some(&basic("carol:secret"))), None);
+        assert_eq!(userlist.authenticate(Some("basic YWxpY2U6c2VjcmV0")), Some("alice"));
+        assert_eq!(userlist.authenticate(Some("Bearer YWxpY2U6c2VjcmV0")), None);
+        assert_eq!(userlist.authenticate(Some("Basic !!!")), None);
+        assert_eq!(userlist.authenticate(None), None);
+    }
+
+    #[test]
+    fn passwords_may_contain_colons() {
+        assert_eq!(basic_credentials(&basic("bob:pa:ss")), Some(("bob".to_string(), "pa:ss".to_string())));
+        assert_eq!(basic_credentials(&basic("nocolon")), None);
+    }
+
+    #[test]
+    fn verified_hashes_are_cached_per_credential() {
+        let userlist = userlist();
+        assert_eq!(userlist.authenticate(Some(&basic("bob:U*V"))), None);
+        assert!(userlist.verified.lock().unwrap().is_empty());
+        assert_eq!(userlist.authenticate(Some(&basic("bob:U*U"))), Some("bob"));
+        assert_eq!(userlist.verified.lock().unwrap().len(), 1);
+        assert_eq!(userlist.authenticate(Some(&basic("bob:U*U"))), Some("bob"));
+        assert_eq!(userlist.verified.lock().unwrap().len(), 1);
+        assert_eq!(userlist.authenticate(Some(&basic("bob:U*V"))), None);
+        assert_eq!(userlist.authenticate(Some(&basic("alice:secret"))), Some("alice"));
+        assert_eq!(userlist.verified.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn hashes_are_verified_off_the_async_worker() {
+        let userlist = Arc::new(Userlist::new(&UserlistConfig {
+            name: "slow".to_string(),
+            users: vec![UserConfig {
+                name: "bob".to_string(),
+                password: UserPassword::Hashed("$2a$08$CCCCCCCCCCCCCCCCCCCCC.juuBx6uGT9eo7PfOPCq5A5jcWfsBlpC".to_string()),
+                groups: Vec::new(),
+            }],
+            groups: Vec::new(),
+        }));
+        let (started, ticking) = tokio::sync::oneshot::channel();
+        let ticker = tokio::spawn(async move {
+            let _ = started.send(());
+            for _ in 0..5 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            Instant::now()
+        });
+        ticking.await.unwrap();
+        let verifier = tokio::spawn(async move {
+            let user = userlist.authenticate(Some(&basic("bob:U*U"))).map(str::to_string);
+            (user, Instant::now())
+        });
+        let (user, verified_at) = verifier.await.unwrap();
+        assert_eq!(user.as_deref(), Some("bob"));
+        assert!(ticker.await.unwrap() < verified_at, "the only worker was blocked while hashing");
+    }
+
+    #[test]
+    fn groups_include_both_directions() {
+        let userlist = userlist();
+        assert!(userlist.in_groups("alice", &["ops".to_string()]));
+        assert!(userlist.in_groups("bob", &["dev".to_string()]));
+        assert!(!userlist.in_groups("alice", &["dev".to_string()]));
+        assert!(!userlist.in_groups("carol", &["ops".to_string()]));
+    }
+
+    #[test]
+    fn challenge_output() {
+        let challenge = challenge("401 Unauthorized", "WWW-Authenticate", "my \"realm\"", "Go away.");
+        assert_eq!(String::from_utf8(challenge).unwrap(),
+                   "HTTP/1.0 401 Unauthorized\r\nCache-Control: no-cache\r\nConnection: close\r\nWWW-Authenticate: Basic realm=\"my _realm_\"\r\nContent-Type: text/html\r\n\r\n<html><body><h1>401 Unauthorized</h1>\nGo away.\n</body></html>\n");
+    }
+}
@@ -1,32 +1,327 @@
 use crate::config::ServerConfig;
 use crate::health::ServerStatus;
-use anyhow::Result;
-use tracing::{debug, warn};
+use crate::metrics;
+use crate::status::StatusTable;
+use crate::utils;
+use anyhow::{Result, anyhow};
+use rand::Rng;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+pub const MAX_WEIGHT: u32 = 256;
+pub const DEFAULT_TIER: u32 = 1;
+const DECISION_TRACE_LIMIT: u32 = 10;
+const DECISION_TRACE_WINDOW: Duration = Duration::from_secs(1);
+pub const DEFAULT_ADAPTIVE_HALF_LIFE: Duration = Duration::from_secs(10);
+const ADAPTIVE_MIN_FACTOR: f64 = 0.25;
+const ADAPTIVE_MAX_FACTOR: f64 = 4.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminState {
+    Ready,
+    Drain,
+    Maint,
+}
+
+impl AdminState {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "ready" => Ok(AdminState::Ready),
+            "drain" => Ok(AdminState::Drain),
+            "maint" => Ok(AdminState::Maint),
+            _ => Err(anyhow!("Unknown server state '{}', expected ready, drain or maint", value)),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AdminState::Ready => "ready",
+            AdminState::Drain => "drain",
+            AdminState::Maint => "maint",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdaptiveMetric {
+    Latency,
+    ErrorRate,
+}
+
+impl AdaptiveMetric {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "latency" => Ok(AdaptiveMetric::Latency),
+            "error-rate" => Ok(AdaptiveMetric::ErrorRate),
+            _ => Err(anyhow!("Unknown adaptive metric '{}', expected latency or error-rate", value)),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AdaptiveMetric::Latency => "latency",
+            AdaptiveMetric::ErrorRate => "error-rate",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AdaptiveSettings {
+    pub enabled: bool,
+    pub metric: AdaptiveMetric,
+    pub half_life: Duration,
+}
+
+impl Default for AdaptiveSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            metric: AdaptiveMetric::Latency,
+            half_life: DEFAULT_ADAPTIVE_HALF_LIFE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Ewma {
+    value: Option<f64>,
+    updated: Option<Instant>,
+}
+
+impl Ewma {
+    fn observe(&mut self, sample: f64, half_life: Duration) {
+        let now = Instant::now();
+        self.value = Some(match (self.value, self.updated) {
+            (Some(value), Some(updated)) => {
+                let decay = 0.5f64.powf(now.duration_since(updated).as_secs_f64() / half_life.as_secs_f64());
+                value * decay + sample * (1.0 - decay)
+            }
+            _ => sample,
+        });
+        self.updated = Some(now);
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        self.value
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ineligibility {
+    Disabled,
+    Draining,
+    Down,
+    Unchecked,
+    WeightZero,
+    Maxconn,
+}
+
+impl Ineligibility {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Ineligibility::Disabled => "disabled",
+            Ineligibility::Draining => "draining",
+            Ineligibility::Down => "down",
+            Ineligibility::Unchecked => "unchecked",
+            Ineligibility::WeightZero => "weight-0",
+            Ineligibility::Maxconn => "maxconn",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EffectiveServerState {
+    Up,
+    NoCheck,
+    Standby,
+    Drain,
+    Maint,
+    Disabled,
+    Down,
+    CheckRequired,
+}
+
+impl EffectiveServerState {
+    pub const ALL: [EffectiveServerState; 8] = [
+        EffectiveServerState::Up,
+        EffectiveServerState::NoCheck,
+        EffectiveServerState::Standby,
+        EffectiveServerState::Drain,
+        EffectiveServerState::Maint,
+        EffectiveServerState::Disabled,
+        EffectiveServerState::Down,
+        EffectiveServerState::CheckRequired,
+    ];
+
+    pub fn compute(server: &ServerState, health: &ServerStatus, active_tier: Option<u32>) -> Self {
+        match (server.admin_state, health) {
+            (AdminState::Maint, _) if server.config.disabled == Some(true) => EffectiveServerState::Disabled,
+            (AdminState::Maint, _) => EffectiveServerState::Maint,
+            (AdminState::Drain, _) => EffectiveServerState::Drain,
+            (AdminState::Ready, ServerStatus::Down) => EffectiveServerState::Down,
+            (AdminState::Ready, ServerStatus::Unchecked) if server.require_check => EffectiveServerState::CheckRequired,
+            (AdminState::Ready, ServerStatus::Up | ServerStatus::Unchecked) if server.weight == 0 => EffectiveServerState::Drain,
+            (AdminState::Ready, ServerStatus::Up | ServerStatus::Unchecked) if active_tier.is_some_and(|tier| server.tier > tier) => EffectiveServerState::Standby,
+            (AdminState::Ready, ServerStatus::Up) => EffectiveServerState::Up,
+            (AdminState::Ready, ServerStatus::Unchecked) => EffectiveServerState::NoCheck,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EffectiveServerState::Up => "up",
+            EffectiveServerState::NoCheck => "no-check",
+            EffectiveServerState::Standby => "standby",
+            EffectiveServerState::Drain => "drain",
+            EffectiveServerState::Maint => "maint",
+            EffectiveServerState::Disabled => "disabled",
+            EffectiveServerState::Down => "down",
+            EffectiveServerState::CheckRequired => "check-required",
+        }
+    }
+
+    pub fn stat_status(&self) -> &'static str {
+        match self {
+            EffectiveServerState::Up | EffectiveServerState::Standby => "UP",
+            EffectiveServerState::NoCheck | EffectiveServerState::CheckRequired => "no check",
+            EffectiveServerState::Drain => "DRAIN",
+            EffectiveServerState::Maint | EffectiveServerState::Disabled => "MAINT",
+            EffectiveServerState::Down => "DOWN",
+        }
+    }
+
+    pub fn is_usable(&self) -> bool {
+        match self {
+            EffectiveServerState::Up | EffectiveServerState::NoCheck | EffectiveServerState::Standby => true,
+            EffectiveServerState::Drain
+            | EffectiveServerState::Maint
+            | EffectiveServerState::Disabled
+            | EffectiveServerState::Down
+            | EffectiveServerState::CheckRequired => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerStateChange {
+    pub server: String,
+    pub id: u32,
+    pub previous: Option<EffectiveServerState>,
+    pub state: EffectiveServerState,
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerDecision {
+    pub name: String,
+    pub weight: u32,
+    pub effective_weight: u32,
+    pub latency_ms: Option<f64>,
+    pub error_rate: Option<f64>,
+    pub tier: u32,
+    pub status: ServerStatus,
+    pub admin_state: AdminState,
+    pub active_connections: u32,
+    pub eligible: bool,
+    pub reason: &'static str,
+}
 
 #[derive(Debug, Clone)]
 pub struct ServerState {
+    pub id: u32,
+    pub index: usize,
     pub config: ServerConfig,
     pub active_connections: u32,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
     pub weight: u32,
     pub status: ServerStatus,
+    pub admin_state: AdminState,
+    pub admin_updated: u64,
+    pub require_check: bool,
+    pub tier: u32,
+    pub reported_state: Option<EffectiveServerState>,
+    pub latency: Ewma,
+    pub errors: Ewma,
+    pub adaptive_factor: f64,
 }
 
 impl ServerState {
-    pub fn new(config: ServerConfig) -> Self {
+    pub fn new(index: usize, config: ServerConfig) -> Self {
         let weight = config.weight.unwrap_or(1);
+        let admin_state = if config.disabled.unwrap_or(false) {
+            AdminState::Maint
+        } else {
+            AdminState::Ready
+        };
+        let status = if config.check == Some(true) { ServerStatus::Up } else { ServerStatus::Unchecked };
+        let tier = config.tier.unwrap_or(DEFAULT_TIER);
         Self {
+            id: config.id.unwrap_or(0),
+            index,
             config,
             active_connections: 0,
+            bytes_in: 0,
+            bytes_out: 0,
             weight,
-            status: ServerStatus::Up,
+            status,
+            admin_state,
+            admin_updated: 0,
+            require_check: false,
+            tier,
+            reported_state: None,
+            latency: Ewma::default(),
+            errors: Ewma::default(),
+            adaptive_factor: 1.0,
+        }
+    }
+
+    pub fn effective_weight(&self) -> u32 {
+        if self.weight == 0 {
+            return 0;
+        }
+        ((self.weight as f64 * self.adaptive_factor).round() as u32).max(1)
+    }
+
+    fn adaptive_score(&self, metric: AdaptiveMetric) -> Option<f64> {
+        match metric {
+            AdaptiveMetric::Latency => self.latency.value(),
+            AdaptiveMetric::ErrorRate => self.errors.value().map(|rate| 1.0 - rate),
         }
     }
 
-    pub fn is_available(&self) -> bool {
-        matches!(self.status, ServerStatus::Up) && 
-        !self.config.disabled.unwrap_or(false) &&
-        !self.config.backup.unwrap_or(false) &&
-        self.weight > 0
+    pub fn ineligibility(&self) -> Option<Ineligibility> {
+        match self.admin_state {
+            AdminState::Maint => return Some(Ineligibility::Disabled),
+            AdminState::Drain => return Some(Ineligibility::Draining),
+            AdminState::Ready => {}
+        }
+
+        match self.status {
+            ServerStatus::Up => {}
+            ServerStatus::Unchecked if !self.require_check => {}
+            ServerStatus::Unchecked => return Some(Ineligibility::Unchecked),
+            ServerStatus::Down => return Some(Ineligibility::Down),
+        }
+
+        if self.weight == 0 {
+            return Some(Ineligibility::WeightZero);
+        }
+
+        if let Some(maxconn) = self.config.maxconn {
+            if self.active_connections >= maxconn {
+                return Some(Ineligibility::Maxconn);
+            }
+        }
+
+        None
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.admin_state == AdminState::Drain || (self.admin_state == AdminState::Ready && self.weight == 0)
+    }
+
+    fn is_selectable(&self) -> bool {
+        self.ineligibility().is_none()
     }
 
     pub fn is_backup(&self) -> bool {
@@ -35,67 +330,57 @@ impl ServerState {
 }
 
 pub trait LoadBalancer {
-    fn select_server<'a>(&mut self, servers: &'a [ServerState]) -> Result<Option<&'a ServerState>>;
+    fn select_server<'a>(&mut self, candidates: &[&'a ServerState]) -> Result<Option<&'a ServerState>>;
 }
 
+#[derive(Default)]
 pub struct RoundRobinBalancer {
-    current_index: usize,
+    current_weights: Vec<i64>,
 }
 
 impl RoundRobinBalancer {
     pub fn new() -> Self {
-        Self { current_index: 0 }
+        Self { current_weights: Vec::new() }
     }
 }
 
 impl LoadBalancer for RoundRobinBalancer {
-    fn select_server<'a>(&mut self, servers: &'a [ServerState]) -> Result<Option<&'a ServerState>> {
-        if servers.is_empty() {
+    fn select_server<'a>(&mut self, candidates: &[&'a ServerState]) -> Result<Option<&'a ServerState>> {
+        if candidates.is_empty() {
             return Ok(None);
         }
 
-        let available_servers: Vec<&ServerState> = servers.iter()
-            .filter(|s| s.is_available())
-            .collect();
-
-        if available_servers.is_empty() {
-            let backup_servers: Vec<&ServerState> = servers.iter()
-                .filter(|s| s.is_backup() && matches!(s.status, ServerStatus::Up))
-                .collect();
-
-            if backup_servers.is_empty() {
-                return Ok(None);
+        let total: i64 = candidates.iter().map(|s| s.effective_weight() as i64).sum();
+        let mut selected = candidates[0];
+        for &server in candidates {
+            if self.current_weights.len() <= server.index {
+                self.current_weights.resize(server.index + 1, 0);
+            }
+            self.current_weights[server.index] += server.effective_weight() as i64;
+            if self.current_weights[server.index] > self.current_weights[selected.index] {
+                selected = server;
             }
-
-            self.current_index = (self.current_index + 1) % backup_servers.len();
-            Ok(Some(backup_servers[self.current_index]))
-        } else {
-            self.current_index = (self.current_index + 1) % available_servers.len();
-            Ok(Some(available_servers[self.current_index]))
         }
+        self.current_weights[selected.index] -= total;
+        Ok(Some(selected))
     }
 }
 
 pub struct LeastConnectionBalancer;
 
 impl LoadBalancer for LeastConnectionBalancer {
-    fn select_server<'a>(&mut self, servers: &'a [ServerState]) -> Result<Option<&'a ServerState>> {
-        let available_servers: Vec<&ServerState> = servers.iter()
-            .filter(|s| s.is_available())
-            .collect();
-
-        if available_servers.is_empty() {
+    fn select_server<'a>(&mut self, candidates: &[&'a ServerState]) -> Result<Option<&'a ServerState>> {
+        if candidates.is_empty() {
             warn!("No available servers for least connection balancing");
             return Ok(None);
         }
 
-        let min_connections = available_servers.iter()
-            .map(|s| s.active_connections)
-            .min()
+        let least_loaded = candidates.iter()
+            .min_by(|a, b| load_cmp(a, b))
             .unwrap();
 
-        let servers_with_min_connections: Vec<&ServerState> = available_servers.iter()
-            .filter(|s| s.active_connections == min_connections)
+        let servers_with_min_connections: Vec<&ServerState> = candidates.iter()
+            .filter(|s| load_cmp(s, least_loaded).is_eq())
             .copied()
             .collect();
 
@@ -113,20 +398,49 @@ impl LoadBalancer for LeastConnectionBalancer {
     }
 }
 
+fn load_cmp(a: &ServerState, b: &ServerState) -> std::cmp::Ordering {
+    (a.active_connections as u64 * b.effective_weight() as u64)
+        .cmp(&(b.active_connections as u64 * a.effective_weight() as u64))
+}
+
+fn weighted_random<'a>(rng: &mut impl Rng, candidates: &[&'a ServerState], skip: Option<usize>) -> Option<&'a ServerState> {
+    let eligible = || candidates.iter().copied().filter(|server| Some(server.index) != skip);
+    let total: u64 = eligible().map(|s| s.effective_weight() as u64).sum();
+    let mut point = rng.gen::<u64>() % total.max(1);
+    for server in eligible() {
+        let weight = server.effective_weight() as u64;
+        if point < weight {
+            return Some(server);
+        }
+        point -= weight;
+    }
+    eligible().next_back()
+}
+
 pub struct RandomBalancer;
 
 impl LoadBalancer for RandomBalancer {
-    fn select_server<'a>(&mut self, servers: &'a [ServerState]) -> Result<Option<&'a ServerState>> {
-        let available_servers: Vec<&ServerState> = servers.iter()
-            .filter(|s| s.is_available())
-            .collect();
+    fn select_server<'a>(&mut self, candidates: &[&'a ServerState]) -> Result<Option<&'a ServerState>> {
+        Ok(weighted_random(&mut rand::thread_rng(), candidates, None))
+    }
+}
 
-        if available_servers.is_empty() {
+pub struct P2cBalancer;
+
+impl LoadBalancer for P2cBalancer {
+    fn select_server<'a>(&mut self, candidates: &[&'a ServerState]) -> Result<Option<&'a ServerState>> {
+        let rng = &mut rand::thread_rng();
+        let Some(first) = weighted_random(rng, candidates, None) else {
             return Ok(None);
-        }
+        };
+        let Some(second) = weighted_random(rng, candidates, Some(first.index)) else {
+            return Ok(Some(first));
+        };
 
-        let index = rand::random::<usize>() % available_servers.len();
-        Ok(Some(available_servers[index]))
+        let selected = if load_cmp(second, first).is_lt() { second } else { first };
+        debug!("Selected server: {} with {} active connections out of {} and {}",
+               selected.config.name, selected.active_connections, first.config.name, second.config.name);
+        Ok(Some(selected))
     }
 }
 
@@ -137,7 +451,8 @@ impl LoadBalancerFactory {
         match algorithm {
             "roundrobin" => Ok(Box::new(RoundRobinBalancer::new())),
             "leastconn" => Ok(Box::new(LeastConnectionBalancer)),
-            "random" => Ok(Box::new(RandomBalancer)),
+            "random" | "random(1)" => Ok(Box::new(RandomBalancer)),
+            "random(2)" => Ok(Box::new(P2cBalancer)),
             _ => {
                 warn!("Unknown load balancing algorithm: {}, using roundrobin", algorithm);
                 Ok(Box::new(RoundRobinBalancer::new()))
@@ -146,23 +461,629 @@ impl LoadBalancerFactory {
     }
 }
 
+struct DecisionTrace {
+    enabled: bool,
+    window_start: Instant,
+    logged_in_window: u32,
+}
+
+impl DecisionTrace {
+    fn allow(&mut self) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        if self.window_start.elapsed() >= DECISION_TRACE_WINDOW {
+            self.window_start = Instant::now();
+            self.logged_in_window = 0;
+        }
+
+        if self.logged_in_window >= DECISION_TRACE_LIMIT {
+            return false;
+        }
+
+        self.logged_in_window += 1;
+        true
+    }
+}
+
 pub struct BackendLoadBalancer {
+    name: String,
     servers: Vec<ServerState>,
     balancer: Box<dyn LoadBalancer + Send + Sync>,
+    trace: DecisionTrace,
+    persist_weight_zero: bool,
+    active_tier: Option<u32>,
+    adaptive: AdaptiveSettings,
 }
 
 impl BackendLoadBalancer {
-    pub fn new(servers: Vec<ServerConfig>, algorithm: &str) -> Result<Self> {
-        let server_states: Vec<ServerState> = servers.into_iter().map(ServerState::new).collect();
+    pub fn new(name: &str, servers: Vec<ServerConfig>, algorithm: &str) -> Result<Self> {
+        let mut server_states: Vec<ServerState> = servers.into_iter()
+            .enumerate()
+            .map(|(index, config)| ServerState::new(index, config))
+            .collect();
+        let backup_tier = server_states.iter()
+            .filter(|s| !s.is_backup())
+            .map(|s| s.tier)
+            .max()
+            .unwrap_or(DEFAULT_TIER) + 1;
+        for server in server_states.iter_mut().filter(|s| s.is_backup()) {
+            server.tier = backup_tier;
+        }
         let balancer = LoadBalancerFactory::create(algorithm)?;
 
-        Ok(Self {
+        let mut load_balancer = Self {
+            name: name.to_string(),
             servers: server_states,
             balancer,
-        })
+            trace: DecisionTrace {
+                enabled: false,
+                window_start: Instant::now(),
+                logged_in_window: 0,
+            },
+            persist_weight_zero: true,
+            active_tier: None,
+            adaptive: AdaptiveSettings::default(),
+        };
+        load_balancer.active_tier = load_balancer.eligible_tier();
+        metrics::backend_active_tier(name, load_balancer.active_tier.unwrap_or(0));
+        Ok(load_balancer)
+    }
+
+    pub fn set_decision_trace(&mut self, enabled: bool) {
+        self.trace.enabled = enabled;
+    }
+
+    pub fn set_persist_weight_zero(&mut self, persist: bool) {
+        self.persist_weight_zero = persist;
+    }
+
+    pub fn set_adaptive(&mut self, adaptive: AdaptiveSettings) {
+        self.adaptive = adaptive;
+        if !adaptive.enabled {
+            self.reset_adaptive_weights();
+        }
+    }
+
+    pub fn set_adaptive_enabled(&mut self, enabled: bool) {
+        self.set_adaptive(AdaptiveSettings { enabled, ..self.adaptive });
+    }
+
+    pub fn adaptive(&self) -> AdaptiveSettings {
+        self.adaptive
+    }
+
+    fn reset_adaptive_weights(&mut self) {
+        for server in &mut self.servers {
+            server.adaptive_factor = 1.0;
+        }
+    }
+
+    pub fn set_require_check(&mut self, required: bool) {
+        for server in &mut self.servers {
+            server.require_check = required;
+        }
+    }
+
+    fn eligible_tier(&self) -> Option<u32> {
+        self.servers.iter()
+            .filter(|s| s.is_selectable())
+            .map(|s| s.tier)
+            .min()
+    }
+
+    fn refresh_tier(&mut self) -> Option<u32> {
+        let tier = self.eligible_tier();
+        if tier != self.active_tier {
+            match tier {
+                Some(tier) => info!(backend = %self.name, from = ?self.active_tier, to = tier, event = "tier_change",
+                    "Backend {} now balancing across tier {}", self.name, tier),
+                None => warn!(backend = %self.name, from = ?self.active_tier, event = "tier_change",
+                    "Backend {} has no eligible server in any tier", self.name),
+            }
+            metrics::backend_tier_changed(&self.name, tier.unwrap_or(0));
+            self.active_tier = tier;
+        }
+        tier
+    }
+
+    pub fn effective_state(&self, server: &ServerState, health: &ServerStatus) -> EffectiveServerState {
+        EffectiveServerState::compute(server, health, self.active_tier)
+    }
+
+    pub fn refresh_states(&mut self) -> Vec<ServerStateChange> {
+        let active_tier = self.refresh_tier();
+        self.servers.iter_mut()
+            .filter_map(|server| {
+                let state = EffectiveServerState::compute(server, &server.status, active_tier);
+                let previous = server.reported_state.replace(state);
+                (previous != Some(state)).then(|| ServerStateChange {
+                    server: server.config.name.clone(),
+                    id: server.id,
+                    previous,
+                    state,
+                })
+            })
+            .collect()
     }
 
     pub fn select_server(&mut self) -> Result<Option<&ServerState>> {
-        self.balancer.select_server(&self.servers)
+        self.select_server_excluding(None)
+    }
+
+    pub fn select_server_excluding(&mut self, excluded: Option<&str>) -> Result<Option<&ServerState>> {
+        let tier = self.refresh_tier();
+        let allowed = |s: &&ServerState| s.is_selectable() && Some(s.config.name.as_str()) != excluded;
+        let tier = match excluded {
+            Some(_) => self.servers.iter().filter(allowed).map(|s| s.tier).min(),
+            None => tier,
+        };
+        let candidates: Vec<&ServerState> = self.servers.iter()
+            .filter(|s| Some(s.tier) == tier && allowed(s))
+            .collect();
+        let selected = self.balancer.select_server(&candidates)?;
+
+        if self.trace.allow() {
+            let skipped: Vec<String> = self.servers.iter()
+                .filter_map(|s| s.ineligibility().map(|reason| format!("{}={}", s.config.name, reason.as_str())))
+                .collect();
+            debug!(
+                backend = %self.name,
+                selected = %selected.map(|s| s.config.name.as_str()).unwrap_or("<none>"),
+                skipped = %skipped.join(","),
+                event = "balance_decision",
+                "Load balancer decision"
+            );
+        }
+
+        Ok(selected)
+    }
+
+    pub fn explain(&self) -> Vec<ServerDecision> {
+        let active_tier = self.eligible_tier();
+
+        self.servers.iter()
+            .map(|server| {
+                let (eligible, reason) = match server.ineligibility() {
+                    Some(reason) => (false, reason.as_str()),
+                    None if Some(server.tier) != active_tier && server.is_backup() => (false, "backup-standby"),
+                    None if Some(server.tier) != active_tier => (false, "tier-standby"),
+                    None if server.is_backup() => (true, "backup-active"),
+                    None => (true, "eligible"),
+                };
+
+                ServerDecision {
+                    name: server.config.name.clone(),
+                    weight: server.weight,
+                    effective_weight: server.effective_weight(),
+                    latency_ms: server.latency.value(),
+                    error_rate: server.errors.value(),
+                    tier: server.tier,
+                    status: server.status.clone(),
+                    admin_state: server.admin_state,
+                    active_connections: server.active_connections,
+                    eligible,
+                    reason,
+                }
+            })
+            .collect()
+    }
+
+    pub fn select_sticky(&self, index: usize) -> Option<&ServerState> {
+        self.servers.get(index)
+            .filter(|s| match s.ineligibility() {
+                None | Some(Ineligibility::Draining) => true,
+                Some(Ineligibility::WeightZero) => self.persist_weight_zero,
+                Some(_) => false,
+            })
+    }
+
+    pub fn servers(&self) -> &[ServerState] {
+        &self.servers
+    }
+
+    pub fn update_health(&mut self, statuses: &StatusTable, backend_index: usize) {
+        statuses.read(|statuses| {
+            for server in &mut self.servers {
+                if let Some(status) = statuses.status(backend_index, server.index) {
+                    server.status = status;
+                }
+            }
+        });
+    }
+
+    pub fn set_admin_state(&mut self, server_name: &str, state: AdminState) -> Result<&ServerState> {
+        let server = self.server_mut(server_name)?;
+        server.admin_state = state;
+        server.admin_updated = utils::unix_millis();
+        Ok(server)
+    }
+
+    pub fn apply_admin_state(&mut self, server_name: &str, state: AdminState, updated: u64) -> Result<Option<&ServerState>> {
+        let server = self.server_mut(server_name)?;
+        if server.admin_updated >= updated {
+            return Ok(None);
+        }
+        server.admin_state = state;
+        server.admin_updated = updated;
+        Ok(Some(server))
+    }
+
+    pub fn set_weight(&mut self, server_name: &str, weight: u32) -> Result<&ServerState> {
+        if weight > MAX_WEIGHT {
+            return Err(anyhow!("Weight {} is outside 0..={}", weight, MAX_WEIGHT));
+        }
+        let server = self.server_mut(server_name)?;
+        server.weight = weight;
+        Ok(server)
+    }
+
+    pub fn record_outcome(&mut self, server_name: &str, duration: Duration, ok: bool) -> Result<&ServerState> {
+        let half_life = self.adaptive.half_life;
+        let server = self.server_mut(server_name)?;
+        if ok {
+            server.latency.observe(duration.as_secs_f64() * 1000.0, half_life);
+        }
+        server.errors.observe(if ok { 0.0 } else { 1.0 }, half_life);
+        Ok(server)
+    }
+
+    pub fn adapt_weights(&mut self) {
+        if !self.adaptive.enabled {
+            return;
+        }
+
+        let metric = self.adaptive.metric;
+        let mut scores: Vec<f64> = self.servers.iter()
+            .filter(|s| s.is_selectable())
+            .filter_map(|s| s.adaptive_score(metric))
+            .collect();
+        if scores.len() < 2 {
+            self.reset_adaptive_weights();
+            return;
+        }
+        scores.sort_by(f64::total_cmp);
+        let middle = scores.len() / 2;
+        let median = if scores.len().is_multiple_of(2) { (scores[middle - 1] + scores[middle]) / 2.0 } else { scores[middle] };
+
+        for server in &mut self.servers {
+            let factor = match server.adaptive_score(metric) {
+                Some(_) if median <= 0.0 => 1.0,
+                Some(score) => match metric {
+                    AdaptiveMetric::Latency => median / score.max(f64::EPSILON),
+                    AdaptiveMetric::ErrorRate => score / median,
+                },
+                None => 1.0,
+            };
+            server.adaptive_factor = factor.clamp(ADAPTIVE_MIN_FACTOR, ADAPTIVE_MAX_FACTOR);
+        }
+    }
+
+    pub fn connection_started(&mut self, server_name: &str) -> Result<&ServerState> {
+        let server = self.server_mut(server_name)?;
+        server.active_connections += 1;
+        Ok(server)
+    }
+
+    // Also tells whether this was the last connection of a draining server.
+    pub fn connection_finished(&mut self, server_name: &str) -> Result<(&ServerState, bool)> {
+        let server = self.server_mut(server_name)?;
+        let drained = server.active_connections == 1 && server.is_draining();
+        server.active_connections = server.active_connections.saturating_sub(1);
+        Ok((server, drained))
+    }
+
+    pub fn record_bytes(&mut self, server_name: &str, bytes_in: u64, bytes_out: u64) -> Result<()> {
+        let server = self.server_mut(server_name)?;
+        server.bytes_in += bytes_in;
+        server.bytes_out += bytes_out;
+        Ok(())
+    }
+
+    pub fn inherit(&mut self, previous: &BackendLoadBalancer) {
+        self.active_tier = previous.active_tier;
+        for server in &mut self.servers {
+            if let Some(old) = server.config.predecessor(&previous.servers, |old| (&old.config.name, old.config.id)) {
+                server.active_connections = old.active_connections;
+                server.bytes_in = old.bytes_in;
+                server.bytes_out = old.bytes_out;
+                server.reported_state = old.reported_state;
+                server.latency = old.latency;
+                server.errors = old.errors;
+                if self.adaptive.enabled {
+                    server.adaptive_factor = old.adaptive_factor;
+                }
+                if server.config.disabled != Some(true) {
+                    server.admin_state = old.admin_state;
+                    server.admin_updated = old.admin_updated;
+                }
+            }
+        }
+    }
+
+    fn server_mut(&mut self, server_name: &str) -> Result<&mut ServerState> {
+        self.servers.iter_mut()
+            .find(|s| s.config.name == server_name)
+            .ok_or_else(|| anyhow!("Server '{}' not found", server_name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    fn balancer(servers: &str, algorithm: &str) -> BackendLoadBalancer {
+        let config = Config::from_haproxy_config(&format!("backend be\n{servers}"), None).unwrap();
+        BackendLoadBalancer::new("be", config.backends[0].server.clone(), algorithm).unwrap()
+    }
+
+    fn pick(balancer: &mut BackendLoadBalancer) -> Option<String> {
+        balancer.select_server().unwrap().map(|s| s.config.name.clone())
+    }
+
+    const TWO: &str = "    server s1 127.0.0.1:9001\n    server s2 127.0.0.1:9002\n";
+
+    #[test]
+    fn excluded_servers_are_never_picked() {
+        for algorithm in ["roundrobin", "leastconn", "random", "random(2)"] {
+            let mut balancer = balancer(TWO, algorithm);
+            for _ in 0..50 {
+                let picked = balancer.select_server_excluding(Some("s1")).unwrap().map(|s| s.config.name.clone());
+                assert_eq!(picked.as_deref(), Some("s2"), "{algorithm}");
+            }
+        }
+
+        let mut single = balancer("    server s1 127.0.0.1:9001\n", "roundrobin");
+        assert!(single.select_server_excluding(Some("s1")).unwrap().is_none());
+    }
+
+    #[test]
+    fn renamed_servers_inherit_their_admin_state_by_explicit_id() {
+        let mut previous = balancer("    server old 127.0.0.1:9001 id 7\n    server s2 127.0.0.1:9002\n", "roundrobin");
+        previous.set_admin_state("old", AdminState::Maint).unwrap();
+        previous.set_admin_state("s2", AdminState::Drain).unwrap();
+
+        let mut reloaded = balancer("    server s2 127.0.0.1:9002\n    server new 127.0.0.1:9001 id 7\n", "roundrobin");
+        reloaded.inherit(&previous);
+        let states: Vec<(&str, AdminState)> = reloaded.servers().iter().map(|s| (s.config.name.as_str(), s.admin_state)).collect();
+        assert_eq!(states, [("s2", AdminState::Drain), ("new", AdminState::Maint)]);
+    }
+
+    #[test]
+    fn effective_states_cover_every_combination() {
+        let cases = [
+            ("", AdminState::Ready, ServerStatus::Up, false, EffectiveServerState::Up),
+            ("", AdminState::Ready, ServerStatus::Unchecked, false, EffectiveServerState::NoCheck),
+            ("", AdminState::Ready, ServerStatus::Unchecked, true, EffectiveServerState::CheckRequired),
+            ("", AdminState::Ready, ServerStatus::Down, false, EffectiveServerState::Down),
+            ("", AdminState::Drain, ServerStatus::Up, false, EffectiveServerState::Drain),
+            ("", AdminState::Maint, ServerStatus::Down, false, EffectiveServerState::Maint),
+            (" disabled", AdminState::Maint, ServerStatus::Up, false, EffectiveServerState::Disabled),
+            (" weight 0", AdminState::Ready, ServerStatus::Up, false, EffectiveServerState::Drain),
+            (" weight 0", AdminState::Ready, ServerStatus::Down, false, EffectiveServerState::Down),
+            (" backup", AdminState::Ready, ServerStatus::Up, false, EffectiveServerState::Standby),
+            (" backup", AdminState::Ready, ServerStatus::Unchecked, false, EffectiveServerState::Standby),
+            (" backup", AdminState::Maint, ServerStatus::Up, false, EffectiveServerState::Maint),
+        ];
+        let mut seen = Vec::new();
+        for (options, admin_state, health, require_check, expected) in cases {
+            let balancer = balancer(&format!("    server s1 127.0.0.1:9001{options}\n"), "roundrobin");
+            let mut server = balancer.servers()[0].clone();
+            server.admin_state = admin_state;
+            server.require_check = require_check;
+            let state = EffectiveServerState::compute(&server, &health, Some(DEFAULT_TIER));
+            assert_eq!(state, expected, "{options:?} {admin_state:?} {health:?} require_check={require_check}");
+            seen.push(state);
+        }
+        assert!(EffectiveServerState::ALL.iter().all(|state| seen.contains(state)));
+
+        let mut names: Vec<&str> = EffectiveServerState::ALL.iter().map(EffectiveServerState::as_str).collect();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), EffectiveServerState::ALL.len());
+    }
+
+    #[test]
+    fn excluding_the_last_server_of_a_tier_falls_back_to_the_next_tier() {
+        let mut balancer = balancer("    server s1 127.0.0.1:9001\n    server b1 127.0.0.1:9003 backup\n", "roundrobin");
+        assert_eq!(pick(&mut balancer).as_deref(), Some("s1"));
+        let picked = balancer.select_server_excluding(Some("s1")).unwrap().map(|s| s.config.name.clone());
+        assert_eq!(picked.as_deref(), Some("b1"));
+    }
+
+    #[test]
+    fn drain_and_maint_servers_are_not_selected_until_ready() {
+        for algorithm in ["roundrobin", "leastconn", "random", "random(2)"] {
+            for state in [AdminState::Drain, AdminState::Maint] {
+                let mut balancer = balancer(TWO, algorithm);
+                balancer.set_admin_state("s1", state).unwrap();
+                for _ in 0..20 {
+                    assert_eq!(pick(&mut balancer).as_deref(), Some("s2"), "{algorithm} {state:?}");
+                }
+
+                balancer.set_admin_state("s1", AdminState::Ready).unwrap();
+                let picked: Vec<String> = (0..50).filter_map(|_| pick(&mut balancer)).collect();
+                assert!(picked.iter().any(|name| name == "s1"), "{algorithm} {state:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn draining_servers_keep_persistent_connections() {
+        let mut balancer = balancer(TWO, "roundrobin");
+        balancer.set_admin_state("s1", AdminState::Drain).unwrap();
+        assert_eq!(balancer.select_sticky(0).map(|s| s.config.name.as_str()), Some("s1"));
+        balancer.set_admin_state("s1", AdminState::Maint).unwrap();
+        assert!(balancer.select_sticky(0).is_none());
+    }
+
+    #[test]
+    fn draining_server_is_drained_once_at_its_last_connection() {
+        let mut balancer = balancer(TWO, "roundrobin");
+        let drained = |balancer: &mut BackendLoadBalancer, name: &str| balancer.connection_finished(name).unwrap().1;
+        for _ in 0..2 {
+            balancer.connection_started("s1").unwrap();
+            balancer.connection_started("s2").unwrap();
+        }
+        balancer.set_admin_state("s1", AdminState::Drain).unwrap();
+
+        assert!(!drained(&mut balancer, "s1"));
+        assert!(drained(&mut balancer, "s1"));
+        assert!(!drained(&mut balancer, "s1"));
+        assert!(!drained(&mut balancer, "s2"));
+        assert!(!drained(&mut balancer, "s2"));
+
+        balancer.connection_started("s2").unwrap();
+        balancer.set_weight("s2", 0).unwrap();
+        assert!(drained(&mut balancer, "s2"));
+    }
+
+    #[test]
+    fn weighted_random_follows_the_weights() {
+        let balancer = balancer("    server s1 127.0.0.1:9001 weight 3\n    server s2 127.0.0.1:9002 weight 1\n", "random");
+        let candidates: Vec<&ServerState> = balancer.servers().iter().collect();
+        let mut rng = StdRng::seed_from_u64(2459);
+        let draws = 10_000;
+        let first = (0..draws)
+            .filter(|_| weighted_random(&mut rng, &candidates, None).unwrap().config.name == "s1")
+            .count();
+        let share = first as f64 / draws as f64;
+        assert!((share - 0.75).abs() < 0.02, "s1 got {share}");
+
+        for _ in 0..100 {
+            assert_eq!(weighted_random(&mut rng, &candidates, Some(0)).unwrap().config.name, "s2");
+        }
+        assert!(weighted_random(&mut rng, &[], None).is_none());
+    }
+
+    #[test]
+    fn power_of_two_choices_avoids_the_busiest_server() {
+        let mut three = balancer("    server s1 127.0.0.1:9001\n    server s2 127.0.0.1:9002\n    server s3 127.0.0.1:9003\n", "random(2)");
+        for _ in 0..100 {
+            three.connection_started("s1").unwrap();
+        }
+        for _ in 0..200 {
+            assert_ne!(pick(&mut three).as_deref(), Some("s1"));
+        }
+
+        let mut pair = balancer(TWO, "random(2)");
+        pair.connection_started("s2").unwrap();
+        for _ in 0..50 {
+            assert_eq!(pick(&mut pair).as_deref(), Some("s1"));
+        }
+    }
+
+    const TIERS: &str = "    server local 127.0.0.1:9001 check\n    server remote 127.0.0.1:9002 check tier 2\n    \
+                         server sorry 127.0.0.1:9003 check tier 3\n";
+
+    fn set_status(balancer: &mut BackendLoadBalancer, server: &str, status: ServerStatus) {
+        balancer.server_mut(server).unwrap().status = status;
+    }
+
+    #[test]
+    fn tiers_fail_over_and_back() {
+        let mut balancer = balancer(TIERS, "roundrobin");
+        assert_eq!(pick(&mut balancer).as_deref(), Some("local"));
+        assert_eq!(balancer.active_tier, Some(1));
+
+        set_status(&mut balancer, "local", ServerStatus::Down);
+        assert_eq!(pick(&mut balancer).as_deref(), Some("remote"));
+        assert_eq!(balancer.active_tier, Some(2));
+
+        set_status(&mut balancer, "remote", ServerStatus::Down);
+        assert_eq!(pick(&mut balancer).as_deref(), Some("sorry"));
+        assert_eq!(balancer.active_tier, Some(3));
+
+        set_status(&mut balancer, "local", ServerStatus::Up);
+        assert_eq!(pick(&mut balancer).as_deref(), Some("local"));
+        assert_eq!(balancer.active_tier, Some(1));
+
+        set_status(&mut balancer, "local", ServerStatus::Down);
+        set_status(&mut balancer, "sorry", ServerStatus::Down);
+        assert_eq!(pick(&mut balancer), None);
+        assert_eq!(balancer.active_tier, None);
+    }
+
+    #[test]
+    fn standby_tiers_are_reported_as_such() {
+        let mut balancer = balancer(TIERS, "roundrobin");
+        let changes = balancer.refresh_states();
+        let states: Vec<(&str, EffectiveServerState)> = changes.iter().map(|c| (c.server.as_str(), c.state)).collect();
+        assert_eq!(states, [("local", EffectiveServerState::Up), ("remote", EffectiveServerState::Standby), ("sorry", EffectiveServerState::Standby)]);
+
+        set_status(&mut balancer, "local", ServerStatus::Down);
+        let changes = balancer.refresh_states();
+        let states: Vec<(&str, EffectiveServerState)> = changes.iter().map(|c| (c.server.as_str(), c.state)).collect();
+        assert_eq!(states, [("local", EffectiveServerState::Down), ("remote", EffectiveServerState::Up)]);
+    }
+
+    #[test]
+    fn adaptive_weights_shrink_slow_servers_within_bounds() {
+        let mut balancer = balancer("    server fast 127.0.0.1:9001 weight 100\n    server quick 127.0.0.1:9002 weight 100\n    \
+                                     server slow 127.0.0.1:9003 weight 100\n    server crawl 127.0.0.1:9004 weight 100\n", "random");
+        balancer.set_adaptive_enabled(true);
+        for round in 0..20 {
+            for (server, millis) in [("fast", 10), ("quick", 10), ("slow", 40), ("crawl", 5000)] {
+                balancer.record_outcome(server, Duration::from_millis(millis), true).unwrap();
+            }
+            balancer.adapt_weights();
+
+            let weight = |name: &str| balancer.servers().iter().find(|s| s.config.name == name).unwrap().effective_weight();
+            assert!(weight("slow") < 100, "round {round}: {}", weight("slow"));
+            assert!(weight("slow") >= 25, "round {round}: {}", weight("slow"));
+            assert_eq!(weight("crawl"), 25, "round {round}");
+            assert!(weight("fast") > 100 && weight("fast") <= 400, "round {round}: {}", weight("fast"));
+        }
+
+        balancer.set_adaptive_enabled(false);
+        assert!(balancer.servers().iter().all(|s| s.effective_weight() == 100));
+    }
+
+    #[test]
+    fn explain_gives_a_reason_for_every_server() {
+        let mut balancer = balancer("    server ok 127.0.0.1:9001\n    server off 127.0.0.1:9002 disabled\n    \
+                                     server leaving 127.0.0.1:9003\n    server dead 127.0.0.1:9004\n    \
+                                     server idle 127.0.0.1:9005 weight 0\n    server full 127.0.0.1:9006 maxconn 1\n    \
+                                     server spare 127.0.0.1:9007 backup\n", "roundrobin");
+        balancer.set_admin_state("leaving", AdminState::Drain).unwrap();
+        set_status(&mut balancer, "dead", ServerStatus::Down);
+        balancer.connection_started("full").unwrap();
+
+        let reasons: Vec<(String, bool, &str)> = balancer.explain().into_iter().map(|d| (d.name, d.eligible, d.reason)).collect();
+        let expected = [
+            ("ok", true, "eligible"),
+            ("off", false, "disabled"),
+            ("leaving", false, "draining"),
+            ("dead", false, "down"),
+            ("idle", false, "weight-0"),
+            ("full", false, "maxconn"),
+            ("spare", false, "backup-standby"),
+        ];
+        assert_eq!(reasons, expected.map(|(name, eligible, reason)| (name.to_string(), eligible, reason)));
+
+        set_status(&mut balancer, "ok", ServerStatus::Down);
+        let decisions = balancer.explain();
+        assert_eq!((decisions[6].eligible, decisions[6].reason), (true, "backup-active"));
+
+        balancer.set_require_check(true);
+        let decisions = balancer.explain();
+        assert_eq!((decisions[6].eligible, decisions[6].reason), (false, "unchecked"));
+    }
+
+    #[test]
+    fn weight_zero_reports_drain_not_down() {
+        let balancer = balancer("    server idle 127.0.0.1:9001 weight 0\n", "roundrobin");
+        for status in [ServerStatus::Up, ServerStatus::Unchecked] {
+            let state = balancer.effective_state(&balancer.servers()[0], &status);
+            assert_eq!(state, EffectiveServerState::Drain);
+            assert_eq!(state.stat_status(), "DRAIN");
+            assert!(!state.is_usable());
+        }
+        let down = balancer.effective_state(&balancer.servers()[0], &ServerStatus::Down);
+        assert_eq!(down.stat_status(), "DOWN");
     }
 }
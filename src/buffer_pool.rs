@@ -0,0 +1,119 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+pub struct BufferPool {
+    capacity: usize,
+    max_idle: usize,
+    idle: Mutex<Vec<Vec<u8>>>,
+    in_use: AtomicUsize,
+}
+
+impl BufferPool {
+    pub fn new(capacity: usize, max_idle: usize) -> Self {
+        Self {
+            capacity,
+            max_idle,
+            idle: Mutex::new(Vec::new()),
+            in_use: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn in_use(&self) -> usize {
+        self.in_use.load(Ordering::Relaxed)
+    }
+
+    pub fn idle(&self) -> usize {
+        self.idle.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    pub fn get(self: &Arc<Self>) -> PooledBuffer {
+        let buffer = self.idle.lock().unwrap_or_else(|e| e.into_inner()).pop()
+            .unwrap_or_else(|| Vec::with_capacity(self.capacity));
+        self.in_use.fetch_add(1, Ordering::Relaxed);
+        PooledBuffer {
+            buffer,
+            pool: Arc::clone(self),
+        }
+    }
+
+    fn put(&self, mut buffer: Vec<u8>) {
+        self.in_use.fetch_sub(1, Ordering::Relaxed);
+        if buffer.capacity() < self.capacity {
+            return;
+        }
+        buffer.clear();
+        let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        if idle.len() < self.max_idle {
+            idle.push(buffer);
+        }
+    }
+}
+
+pub struct PooledBuffer {
+    buffer: Vec<u8>,
+    pool: Arc<BufferPool>,
+}
+
+impl Deref for PooledBuffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.buffer
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buffer
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        self.pool.put(std::mem::take(&mut self.buffer));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffers_are_reused_empty() {
+        let pool = Arc::new(BufferPool::new(64, 4));
+        let mut buffer = pool.get();
+        assert!(buffer.capacity() >= 64);
+        buffer.extend_from_slice(b"request");
+        let address = buffer.as_ptr();
+        assert_eq!((pool.in_use(), pool.idle()), (1, 0));
+        drop(buffer);
+        assert_eq!((pool.in_use(), pool.idle()), (0, 1));
+
+        let buffer = pool.get();
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.as_ptr(), address, "the idle buffer was not reused");
+    }
+
+    #[test]
+    fn idle_buffers_are_bounded() {
+        let pool = Arc::new(BufferPool::new(16, 2));
+        let buffers: Vec<PooledBuffer> = (0..5).map(|_| pool.get()).collect();
+        assert_eq!(pool.in_use(), 5);
+        drop(buffers);
+        assert_eq!((pool.in_use(), pool.idle()), (0, 2));
+    }
+
+    #[test]
+    fn shrunk_buffers_are_not_kept() {
+        let pool = Arc::new(BufferPool::new(16, 2));
+        let mut buffer = pool.get();
+        buffer.shrink_to(0);
+        drop(buffer);
+        assert_eq!((pool.in_use(), pool.idle()), (0, 0));
+    }
+}
@@ -0,0 +1,170 @@
+use crate::config::BwlimConfig;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+struct BucketState {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct TokenBucket {
+    state: Mutex<BucketState>,
+}
+
+impl TokenBucket {
+    fn new() -> Self {
+        Self { state: Mutex::new(BucketState { tokens: f64::MAX, updated: Instant::now() }) }
+    }
+
+    fn take(&self, rate: u64, bytes: usize) -> Duration {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let rate = rate as f64;
+        let refill = now.duration_since(state.updated).as_secs_f64() * rate;
+        state.tokens = (state.tokens + refill).min(rate) - bytes as f64;
+        state.updated = now;
+
+        if state.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-state.tokens / rate)
+        }
+    }
+}
+
+pub struct BandwidthLimit {
+    rate: AtomicU64,
+    shared: AtomicBool,
+    bucket: TokenBucket,
+}
+
+impl BandwidthLimit {
+    pub fn new(config: Option<&BwlimConfig>) -> Self {
+        let limit = Self {
+            rate: AtomicU64::new(0),
+            shared: AtomicBool::new(false),
+            bucket: TokenBucket::new(),
+        };
+        limit.configure(config);
+        limit
+    }
+
+    pub fn configure(&self, config: Option<&BwlimConfig>) {
+        self.rate.store(config.map_or(0, |config| config.rate), Ordering::Relaxed);
+        self.shared.store(config.is_some_and(|config| config.shared), Ordering::Relaxed);
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.rate.load(Ordering::Relaxed)
+    }
+}
+
+pub struct Throttle {
+    limits: Vec<(Arc<BandwidthLimit>, TokenBucket)>,
+}
+
+impl Throttle {
+    pub fn new<I>(limits: I) -> Self
+    where
+        I: IntoIterator<Item = Arc<BandwidthLimit>>,
+    {
+        Self { limits: limits.into_iter().map(|limit| (limit, TokenBucket::new())).collect() }
+    }
+
+    pub fn unlimited() -> Self {
+        Self { limits: Vec::new() }
+    }
+
+    pub async fn pace(&self, bytes: usize) {
+        let wait = self.limits.iter()
+            .filter_map(|(limit, own)| {
+                let rate = limit.rate();
+                if rate == 0 {
+                    return None;
+                }
+                let bucket = if limit.shared.load(Ordering::Relaxed) { &limit.bucket } else { own };
+                Some(bucket.take(rate, bytes))
+            })
+            .max()
+            .unwrap_or(Duration::ZERO);
+
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct BandwidthLimits {
+    pub inbound: Arc<BandwidthLimit>,
+    pub outbound: Arc<BandwidthLimit>,
+}
+
+impl BandwidthLimits {
+    pub fn new(inbound: Option<&BwlimConfig>, outbound: Option<&BwlimConfig>) -> Self {
+        Self {
+            inbound: Arc::new(BandwidthLimit::new(inbound)),
+            outbound: Arc::new(BandwidthLimit::new(outbound)),
+        }
+    }
+
+    pub fn configure(&self, inbound: Option<&BwlimConfig>, outbound: Option<&BwlimConfig>) {
+        self.inbound.configure(inbound);
+        self.outbound.configure(outbound);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(rate: u64, shared: bool) -> Arc<BandwidthLimit> {
+        Arc::new(BandwidthLimit::new(Some(&BwlimConfig { rate, shared })))
+    }
+
+    async fn paced(throttle: &Throttle, bytes: usize) -> Duration {
+        let started = Instant::now();
+        throttle.pace(bytes).await;
+        started.elapsed()
+    }
+
+    #[test]
+    fn a_bucket_allows_one_second_of_burst() {
+        let bucket = TokenBucket::new();
+        assert_eq!(bucket.take(1000, 1000), Duration::ZERO);
+        let wait = bucket.take(1000, 500);
+        assert!(wait > Duration::from_millis(490) && wait <= Duration::from_millis(500), "{wait:?}");
+    }
+
+    #[tokio::test]
+    async fn pace_waits_for_the_slowest_limit() {
+        let throttle = Throttle::new([limit(100_000, false), limit(1000, false)]);
+        throttle.pace(1000).await;
+        let waited = paced(&throttle, 50).await;
+        assert!(waited >= Duration::from_millis(45), "{waited:?}");
+    }
+
+    #[tokio::test]
+    async fn shared_limits_pace_every_session_together() {
+        let shared = limit(1000, true);
+        let (first, second) = (Throttle::new([Arc::clone(&shared)]), Throttle::new([Arc::clone(&shared)]));
+        first.pace(1000).await;
+        assert!(paced(&second, 50).await >= Duration::from_millis(45));
+
+        let own = limit(1000, false);
+        let (first, second) = (Throttle::new([Arc::clone(&own)]), Throttle::new([Arc::clone(&own)]));
+        first.pace(1000).await;
+        assert!(paced(&second, 50).await < Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn unset_limits_never_wait() {
+        let limits = BandwidthLimits::new(Some(&BwlimConfig { rate: 10, shared: false }), None);
+        limits.configure(None, None);
+        assert_eq!((limits.inbound.rate(), limits.outbound.rate()), (0, 0));
+        assert!(paced(&Throttle::new([Arc::clone(&limits.inbound)]), 1 << 20).await < Duration::from_millis(20));
+        assert!(paced(&Throttle::unlimited(), 1 << 20).await < Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,50 @@
+use crate::config::SourceSpan;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Capability {
+    Tls,
+    Http,
+    Udp,
+}
+
+impl Capability {
+    pub fn available(self) -> bool {
+        match self {
+            Capability::Tls => true,
+            Capability::Http | Capability::Udp => false,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Capability::Tls => "tls",
+            Capability::Http => "http",
+            Capability::Udp => "udp",
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CapabilityUse {
+    pub section: String,
+    pub directive: String,
+    pub capability: Capability,
+    #[serde(skip)]
+    pub span: SourceSpan,
+}
+
+impl fmt::Display for CapabilityUse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} uses '{}' which requires the {} capability",
+               self.span, self.section, self.directive, self.capability)
+    }
+}
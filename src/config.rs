@@ -1,11 +1,28 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use tokio::fs;
+use sha2::{Digest, Sha256};
 use anyhow::{Result, anyhow};
 use tracing::{debug, warn, info};
 use crate::options::Options;
-use std::net::IpAddr;
+use crate::log_format::LogFormat;
+use crate::acl::CompiledRouting;
+use crate::balancer::MAX_WEIGHT;
+use crate::tls::TlsTerminator;
+use crate::inspect::{self, NameTemplate};
+use crate::utils;
+use crate::metrics;
+use crate::auth;
+use crate::session;
+use crate::capability::{Capability, CapabilityUse};
+use crate::geoip::Country;
+use crate::rate_limit::{ProtectionMode, RateLimitKey};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -13,16 +30,39 @@ pub struct Config {
     pub defaults: DefaultsConfig,
     pub frontends: Vec<FrontendConfig>,
     pub backends: Vec<BackendConfig>,
+    pub resolvers: Vec<ResolversConfig>,
+    pub peers: Vec<PeersConfig>,
+    #[serde(default)]
+    pub userlists: Vec<UserlistConfig>,
     pub metrics: MetricsConfig,
     pub rate_limit: Option<RateLimitConfig>,
     pub ddos_protection: Option<DdosProtectionConfig>,
     pub hot_reload: Option<HotReloadConfig>,
     pub compression: Option<CompressionConfig>,
+    #[serde(default)]
+    pub capability_uses: Vec<CapabilityUse>,
+    #[serde(default)]
+    pub ignored_directives: Vec<IgnoredDirective>,
+    pub content_hash: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IgnoredDirective {
+    pub section: String,
+    pub directive: String,
+    #[serde(skip)]
+    pub span: SourceSpan,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("unsupported directive")]
+struct UnsupportedDirective;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GlobalConfig {
     pub maxconn: Option<u32>,
+    pub keep_runtime_maxconn: bool,
+    pub strict_limits: bool,
     pub log: Option<String>,
     pub user: Option<String>,
     pub group: Option<String>,
@@ -30,30 +70,76 @@ pub struct GlobalConfig {
     pub pidfile: Option<String>,
     pub ssl_default_bind_ciphers: Option<String>,
     pub ssl_default_bind_options: Option<String>,
-    pub option: Vec<String>,
+    pub stats_socket: Option<String>,
+    pub tarpit_maxconn: Option<u32>,
+    pub bind_range_max: Option<u32>,
+    pub ulimit_n: Option<u64>,
+    pub tune_maxcheckconn: Option<usize>,
+    pub tune_inspect_bufsize: Option<usize>,
+    pub tune_inspect_overflow: Option<String>,
+    pub warmup_checks: bool,
+    pub health_events: bool,
+    pub warmup_timeout: Option<Duration>,
+    pub pre_stop_delay: Option<Duration>,
+    pub drain_keepalive_delay: Option<Duration>,
+    pub drain_kill_idle: Option<Duration>,
+    pub audit_log: Option<String>,
+    pub audit_log_fsync: bool,
+    pub tune_connect_race_delay: Option<Duration>,
+    pub tune_copy_yield_bytes: Option<u64>,
+    pub tune_log_burst: Option<u64>,
+    pub tune_log_interval: Option<Duration>,
+    pub tune_fair_accept: bool,
+    pub tune_backend_connect_rate: Option<u32>,
+    pub src_dn_resolvers: Option<String>,
+    pub tune_src_dn_timeout: Option<Duration>,
+    pub tune_src_dn_maxlookups: Option<usize>,
+    pub tune_src_dn_cache_size: Option<usize>,
+    pub tune_src_dn_cache_ttl: Option<Duration>,
+    pub geoip_db: Option<String>,
+    pub geoip_metrics_countries: Vec<String>,
+    pub localpeer: Option<String>,
+    pub option: Vec<Vec<String>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DefaultsConfig {
     pub mode: Option<String>,
     pub log: Option<String>,
-    pub option: Vec<String>,
+    pub option: Vec<Vec<String>>,
     pub timeout: HashMap<String, String>,
     pub retries: Option<u32>,
     pub options: Option<Options>,
+    pub log_format: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FrontendConfig {
     pub name: String,
-    pub bind: Vec<String>,
+    #[serde(deserialize_with = "deserialize_binds")]
+    pub bind: Vec<BindConfig>,
     pub mode: Option<String>,
     pub default_backend: Option<String>,
     pub acl: Vec<AclConfig>,
     pub use_backend: Vec<UseBackendConfig>,
-    pub option: Vec<String>,
+    pub tcp_request_connection: Vec<TcpRequestRuleConfig>,
+    pub tcp_request_inspect_delay: Option<String>,
+    #[serde(default)]
+    pub http_request_auth: Vec<HttpRequestAuthConfig>,
+    #[serde(default)]
+    pub http_request_return: Vec<HttpReturnConfig>,
+    pub rate_limit_sessions: Option<u32>,
+    pub maxconn: Option<u32>,
+    pub bwlim_in: Option<BwlimConfig>,
+    pub bwlim_out: Option<BwlimConfig>,
+    pub option: Vec<Vec<String>>,
     pub timeout: HashMap<String, String>,
     pub options: Option<Options>,
+    pub log_format: Option<String>,
+    pub on_no_backend: Option<String>,
+    pub connect_auth: Option<String>,
+    #[serde(skip)]
+    pub span: SourceSpan,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -62,38 +148,703 @@ pub struct BackendConfig {
     pub mode: Option<String>,
     pub balance: Option<String>,
     pub server: Vec<ServerConfig>,
-    pub option: Vec<String>,
+    pub option: Vec<Vec<String>>,
     pub timeout: HashMap<String, String>,
     pub health_check: Option<HealthCheckConfig>,
     pub options: Option<Options>,
     pub retries: Option<u32>,
+    pub stick_table: Option<StickTableConfig>,
+    pub stick_on: Option<String>,
+    pub fullconn: Option<u32>,
+    pub on_fullconn: Option<String>,
+    pub require_check: bool,
+    pub retry_on_conn_failure: bool,
+    pub persist_weight_zero: bool,
+    pub bwlim_in: Option<BwlimConfig>,
+    pub bwlim_out: Option<BwlimConfig>,
+    pub alert_threshold: Option<f64>,
+    pub connect_rate: Option<u32>,
+    pub resolve_on_down_interval: Option<Duration>,
+    pub balance_adaptive: bool,
+    pub adaptive_metric: Option<String>,
+    pub adaptive_half_life: Option<Duration>,
+    pub send_name_header: Option<String>,
+    pub send_backend_header: Option<String>,
+    #[serde(default)]
+    pub http_request_return: Option<HttpReturnConfig>,
+    #[serde(skip)]
+    pub span: SourceSpan,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BwlimConfig {
+    pub rate: u64,
+    pub shared: bool,
+}
+
+impl BwlimConfig {
+    fn parse(args: &[String]) -> Result<Self> {
+        let parts: Vec<&str> = args.iter().map(String::as_str).collect();
+        let (rate, shared) = match parts.as_slice() {
+            [rate] => (rate, false),
+            [rate, "shared"] => (rate, true),
+            _ => return Err(anyhow!("expected '<bytes-per-second> [shared]'")),
+        };
+        let rate = utils::parse_size(rate)?;
+        if rate == 0 {
+            return Err(anyhow!("rate must be positive"));
+        }
+        Ok(Self { rate, shared })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StickTableConfig {
+    pub size: usize,
+    pub expire: String,
+    pub peers: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerConfig {
+    pub id: Option<u32>,
+    #[serde(skip)]
+    pub explicit_id: bool,
     pub name: String,
     pub address: String,
-    pub port: u16,
+    pub port: Option<u16>,
     pub weight: Option<u32>,
     pub maxconn: Option<u32>,
     pub check: Option<bool>,
     pub inter: Option<String>,
+    pub fastinter: Option<String>,
+    pub downinter: Option<String>,
+    pub check_timeout: Option<String>,
     pub rise: Option<u32>,
     pub fall: Option<u32>,
     pub backup: Option<bool>,
+    pub tier: Option<u32>,
     pub disabled: Option<bool>,
+    pub tfo: Option<bool>,
+    pub resolvers: Option<String>,
+    #[serde(skip)]
+    pub span: SourceSpan,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BindConfig {
+    pub host: IpAddr,
+    pub port_start: u16,
+    pub port_end: u16,
+    pub v6only: Option<bool>,
+    pub transparent: bool,
+    pub name: Option<String>,
+    pub ssl: bool,
+    pub crt: Option<String>,
+    pub default_crt: Option<String>,
+    pub alpn: Vec<String>,
+    pub accept_proxy: bool,
+    pub backlog: Option<u32>,
+    pub interface: Option<String>,
+    #[serde(default)]
+    pub tfo: bool,
+    #[serde(default)]
+    pub defer_accept: bool,
+    #[serde(default)]
+    pub reuseport: bool,
+    #[serde(default)]
+    pub shards: Option<u32>,
+    pub options: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BindEntry {
+    Line(String),
+    Config(BindConfig),
+}
+
+fn deserialize_binds<'de, D>(deserializer: D) -> std::result::Result<Vec<BindConfig>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Vec::<BindEntry>::deserialize(deserializer)?
+        .into_iter()
+        .map(|entry| match entry {
+            BindEntry::Line(line) => BindConfig::parse(&line.split_whitespace().map(str::to_string).collect::<Vec<_>>())
+                .map_err(serde::de::Error::custom),
+            BindEntry::Config(bind) => Ok(bind),
+        })
+        .collect()
+}
+
+impl BackendConfig {
+    pub fn new(name: &str, span: SourceSpan) -> Self {
+        Self {
+            name: name.to_string(),
+            mode: None,
+            balance: None,
+            server: Vec::new(),
+            option: Vec::new(),
+            timeout: HashMap::new(),
+            health_check: None,
+            options: None,
+            retries: None,
+            stick_table: None,
+            stick_on: None,
+            fullconn: None,
+            on_fullconn: None,
+            require_check: false,
+            retry_on_conn_failure: false,
+            persist_weight_zero: true,
+            bwlim_in: None,
+            bwlim_out: None,
+            alert_threshold: None,
+            connect_rate: None,
+            resolve_on_down_interval: None,
+            balance_adaptive: false,
+            adaptive_metric: None,
+            adaptive_half_life: None,
+            send_name_header: None,
+            send_backend_header: None,
+            http_request_return: None,
+            span,
+        }
+    }
+
+    pub fn apply_defaults(&mut self, defaults: &DefaultsConfig) -> Result<()> {
+        let mode = self.mode.as_deref().unwrap_or("tcp");
+        self.options = Some(section_options(&self.option, mode, &[("defaults", &defaults.timeout), (&format!("backend '{}'", self.name), &self.timeout)])?);
+        if self.retries.is_none() {
+            self.retries = defaults.retries;
+        }
+        self.health_check = create_health_check_config(self);
+        Ok(())
+    }
+}
+
+impl ServerConfig {
+    pub fn parse(parts: &[&str], span: &SourceSpan) -> Result<Self> {
+        if parts.len() < 2 {
+            return Err(anyhow!("server requires a name and an address"));
+        }
+        let server_name = parts[0].to_string();
+        let server_addr = parts[1].to_string();
+        let server_name_clone = server_name.clone();
+        
+        let (address, port) = if server_addr.contains(':') {
+            let addr_parts: Vec<&str> = server_addr.split(':').collect();
+            if addr_parts.len() == 2 {
+                (addr_parts[0].to_string(), Some(addr_parts[1].parse().unwrap_or(80)))
+            } else {
+                (server_addr, Some(80))
+            }
+        } else if server_addr.starts_with('_') {
+            (server_addr, None)
+        } else {
+            (server_addr, Some(80))
+        };
+        
+        let mut server = ServerConfig {
+            id: None,
+            explicit_id: false,
+            name: server_name,
+            address,
+            port,
+            weight: Some(1),
+            maxconn: None,
+            check: None,
+            inter: None,
+            fastinter: None,
+            downinter: None,
+            check_timeout: None,
+            rise: None,
+            fall: None,
+            backup: None,
+            tier: None,
+            disabled: None,
+            tfo: None,
+            resolvers: None,
+            span: span.clone(),
+        };
+
+        let mut i = 2;
+        while i < parts.len() {
+            let part = parts[i];
+            match part {
+                "weight" => {
+                    if i + 1 < parts.len() {
+                        let weight_str = parts[i + 1];
+                        server.weight = Some(weight_str.parse()
+                            .map_err(|_| anyhow!("Server '{}' has invalid weight '{}'", server_name_clone, weight_str))?);
+                        tracing::debug!("Parsed weight for server {}: {}", server_name_clone, server.weight.unwrap());
+                        i += 1;
+                    }
+                    i += 1;
+                },
+                "maxconn" => {
+                    if i + 1 < parts.len() {
+                        server.maxconn = Some(parts[i + 1].parse().unwrap_or(1000));
+                        i += 1;
+                    }
+                    i += 1;
+                },
+                "id" => {
+                    if i + 1 < parts.len() {
+                        let id = parts[i + 1].parse()
+                            .map_err(|_| anyhow!("Server '{}' has invalid id '{}'", server_name_clone, parts[i + 1]))?;
+                        server.id = Some(id);
+                        server.explicit_id = true;
+                        i += 1;
+                    }
+                    i += 1;
+                },
+                "resolvers" => {
+                    if i + 1 < parts.len() {
+                        server.resolvers = Some(parts[i + 1].to_string());
+                        i += 1;
+                    }
+                    i += 1;
+                },
+                "check" => {
+                    server.check = Some(true);
+                    i += 1;
+                },
+                "inter" => {
+                    if i + 1 < parts.len() {
+                        server.inter = Some(parts[i + 1].to_string());
+                        i += 1;
+                    }
+                    i += 1;
+                },
+                "fastinter" | "downinter" => {
+                    if i + 1 < parts.len() {
+                        utils::parse_duration(parts[i + 1])
+                            .map_err(|e| anyhow!("Server '{}' has invalid {} '{}': {}", server_name_clone, part, parts[i + 1], e))?;
+                        if part == "fastinter" {
+                            server.fastinter = Some(parts[i + 1].to_string());
+                        } else {
+                            server.downinter = Some(parts[i + 1].to_string());
+                        }
+                        i += 1;
+                    }
+                    i += 1;
+                },
+                "check-timeout" => {
+                    if i + 1 < parts.len() {
+                        utils::parse_duration(parts[i + 1])
+                            .map_err(|e| anyhow!("Server '{}' has invalid check-timeout '{}': {}", server_name_clone, parts[i + 1], e))?;
+                        server.check_timeout = Some(parts[i + 1].to_string());
+                        i += 1;
+                    }
+                    i += 1;
+                },
+                "rise" => {
+                    if i + 1 < parts.len() {
+                        server.rise = Some(parts[i + 1].parse().unwrap_or(2));
+                        i += 1;
+                    }
+                    i += 1;
+                },
+                "fall" => {
+                    if i + 1 < parts.len() {
+                        server.fall = Some(parts[i + 1].parse().unwrap_or(3));
+                        i += 1;
+                    }
+                    i += 1;
+                },
+                "backup" => {
+                    server.backup = Some(true);
+                    i += 1;
+                },
+                "tier" => {
+                    if i + 1 < parts.len() {
+                        server.tier = Some(parts[i + 1].parse().ok().filter(|tier| *tier > 0)
+                            .ok_or_else(|| anyhow!("Server '{}' has invalid tier '{}', expected a positive number", server_name_clone, parts[i + 1]))?);
+                        i += 1;
+                    }
+                    i += 1;
+                },
+                "disabled" => {
+                    server.disabled = Some(true);
+                    i += 1;
+                },
+                "tfo" => {
+                    server.tfo = Some(true);
+                    i += 1;
+                },
+                _ => {
+                    i += 1;
+                },
+            }
+        }
+
+        if server.backup == Some(true) && server.tier.is_some() {
+            return Err(anyhow!("Server '{}' cannot combine 'backup' with 'tier': backup servers always form the last tier", server_name_clone));
+        }
+
+        Ok(server)
+    }
+
+    pub fn is_srv(&self) -> bool {
+        self.port.is_none()
+    }
+
+    // Finds the server this one replaces on reload among `previous`, given the
+    // name and id of each. An explicit id survives renames; assigned ids shift
+    // when servers are reordered, so otherwise the name decides.
+    pub fn predecessor<'a, T>(&self, previous: &'a [T], identity: impl Fn(&T) -> (&str, Option<u32>)) -> Option<&'a T> {
+        self.explicit_id.then(|| previous.iter().find(|old| identity(old).1 == self.id)).flatten()
+            .or_else(|| previous.iter().find(|old| identity(old).0 == self.name))
+    }
+
+    pub fn endpoint(&self) -> String {
+        match self.port {
+            Some(port) => format!("{}:{}", self.address, port),
+            None => self.address.clone(),
+        }
+    }
+}
+
+impl BindConfig {
+    pub fn parse(args: &[String]) -> Result<Self> {
+        let mut parts = args.iter().map(String::as_str);
+        let address = parts.next().ok_or_else(|| anyhow!("Missing bind address"))?;
+
+        let (host, ports) = address.rsplit_once(':')
+            .ok_or_else(|| anyhow!("Bind address '{}' has no port", address))?;
+        let host = match host.trim_start_matches('[').trim_end_matches(']') {
+            "" | "*" => IpAddr::from([0, 0, 0, 0]),
+            host => host.parse().map_err(|_| anyhow!("Invalid bind address '{}'", host))?,
+        };
+
+        let (port_start, port_end) = match ports.split_once('-') {
+            Some((start, end)) => (start.parse()?, end.parse()?),
+            None => {
+                let port = ports.parse()?;
+                (port, port)
+            }
+        };
+        if port_start > port_end {
+            return Err(anyhow!("Invalid bind port range '{}'", ports));
+        }
+
+        let mut bind = BindConfig {
+            host,
+            port_start,
+            port_end,
+            v6only: None,
+            transparent: false,
+            name: None,
+            ssl: false,
+            crt: None,
+            default_crt: None,
+            alpn: Vec::new(),
+            accept_proxy: false,
+            backlog: None,
+            interface: None,
+            tfo: false,
+            defer_accept: false,
+            reuseport: false,
+            shards: None,
+            options: Vec::new(),
+        };
+
+        while let Some(keyword) = parts.next() {
+            match keyword {
+                "v4v6" => bind.v6only = Some(false),
+                "v6only" => bind.v6only = Some(true),
+                "transparent" => bind.transparent = true,
+                "name" => {
+                    let name = parts.next().ok_or_else(|| anyhow!("Bind keyword 'name' needs an identifier"))?;
+                    bind.name = Some(name.to_string());
+                },
+                "ssl" => bind.ssl = true,
+                "crt" => {
+                    let crt = parts.next().ok_or_else(|| anyhow!("Bind keyword 'crt' needs a certificate path"))?;
+                    bind.crt = Some(crt.to_string());
+                },
+                "default-crt" => {
+                    let crt = parts.next().ok_or_else(|| anyhow!("Bind keyword 'default-crt' needs a certificate path"))?;
+                    bind.default_crt = Some(crt.to_string());
+                },
+                "accept-proxy" => bind.accept_proxy = true,
+                "tfo" => bind.tfo = true,
+                "defer-accept" => bind.defer_accept = true,
+                "reuseport" => bind.reuseport = true,
+                "shards" => {
+                    let shards = parts.next().ok_or_else(|| anyhow!("Bind keyword 'shards' needs a listener count"))?;
+                    bind.shards = Some(shards.parse().ok().filter(|shards| *shards > 0)
+                        .ok_or_else(|| anyhow!("Invalid bind shards '{}', expected a positive number", shards))?);
+                    bind.reuseport = true;
+                },
+                "backlog" => {
+                    let backlog = parts.next().ok_or_else(|| anyhow!("Bind keyword 'backlog' needs a queue length"))?;
+                    bind.backlog = Some(backlog.parse().ok().filter(|backlog| *backlog > 0)
+                        .ok_or_else(|| anyhow!("Invalid bind backlog '{}', expected a positive number", backlog))?);
+                },
+                "interface" => {
+                    let interface = parts.next().ok_or_else(|| anyhow!("Bind keyword 'interface' needs an interface name"))?;
+                    bind.interface = Some(interface.to_string());
+                },
+                "alpn" => {
+                    let alpn = parts.next().ok_or_else(|| anyhow!("Bind keyword 'alpn' needs a protocol list"))?;
+                    bind.alpn = alpn.split(',').filter(|protocol| !protocol.is_empty()).map(str::to_string).collect();
+                    if bind.alpn.is_empty() {
+                        return Err(anyhow!("Bind keyword 'alpn' needs at least one protocol"));
+                    }
+                },
+                _ => bind.options.push(keyword.to_string()),
+            }
+        }
+
+        Ok(bind)
+    }
+
+    pub fn listener_name(&self, addr: SocketAddr) -> String {
+        match self.name {
+            Some(ref name) if self.port_start == self.port_end => name.clone(),
+            Some(ref name) => format!("{}:{}", name, addr.port()),
+            None => addr.to_string(),
+        }
+    }
+
+    pub fn shard_count(&self) -> u32 {
+        self.shards.unwrap_or(1)
+    }
+
+    pub fn shard_name(&self, addr: SocketAddr, shard: u32) -> String {
+        match self.shard_count() {
+            1 => self.listener_name(addr),
+            _ => format!("{}#{}", self.listener_name(addr), shard + 1),
+        }
+    }
+
+    pub fn range_size(&self) -> u32 {
+        (self.port_end - self.port_start) as u32 + 1
+    }
+
+    pub fn addresses(&self) -> Vec<SocketAddr> {
+        (self.port_start..=self.port_end)
+            .map(|port| SocketAddr::new(self.host, port))
+            .collect()
+    }
+
+    fn accepts_ipv4(&self) -> bool {
+        self.host.is_ipv4() || (self.host.is_unspecified() && self.v6only != Some(true))
+    }
+
+    fn overlaps(&self, other: &BindConfig) -> bool {
+        if self.port_start > other.port_end || other.port_start > self.port_end {
+            return false;
+        }
+
+        let (host, other_host) = (self.host.to_canonical(), other.host.to_canonical());
+        if host == other_host {
+            return true;
+        }
+
+        let wildcard_overlap = |a: &BindConfig, a_host: IpAddr, b_host: IpAddr| {
+            a_host.is_unspecified() && (a_host.is_ipv4() == b_host.is_ipv4() || (b_host.is_ipv4() && a.accepts_ipv4()))
+        };
+        wildcard_overlap(self, host, other_host) || wildcard_overlap(other, other_host, host)
+    }
+}
+
+impl std::fmt::Display for BindConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let host = match self.host {
+            IpAddr::V6(host) => format!("[{}]", host),
+            IpAddr::V4(host) => host.to_string(),
+        };
+        if self.port_start == self.port_end {
+            write!(f, "{}:{}", host, self.port_start)
+        } else {
+            write!(f, "{}:{}-{}", host, self.port_start, self.port_end)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResolversConfig {
+    pub name: String,
+    pub nameservers: Vec<NameserverConfig>,
+    pub resolve_retries: u32,
+    pub timeout_resolve_ms: u64,
+    pub timeout_retry_ms: u64,
+    pub hold_valid_ms: u64,
+    pub hold_nx_ms: u64,
+    pub hold_timeout_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NameserverConfig {
+    pub name: String,
+    pub address: SocketAddr,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeersConfig {
+    pub name: String,
+    pub peers: Vec<PeerConfig>,
+    pub secret: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerConfig {
+    pub name: String,
+    pub address: SocketAddr,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserlistConfig {
+    pub name: String,
+    pub users: Vec<UserConfig>,
+    pub groups: Vec<GroupConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserConfig {
+    pub name: String,
+    pub password: UserPassword,
+    pub groups: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum UserPassword {
+    Insecure(String),
+    Hashed(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroupConfig {
+    pub name: String,
+    pub users: Vec<String>,
+}
+
+impl UserlistConfig {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            users: Vec::new(),
+            groups: Vec::new(),
+        }
+    }
+}
+
+impl PeersConfig {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            peers: Vec::new(),
+            secret: None,
+        }
+    }
+
+    pub fn local(&self, localpeer: &str) -> Option<&PeerConfig> {
+        self.peers.iter().find(|peer| peer.name == localpeer)
+    }
+
+    pub fn remotes<'a>(&'a self, localpeer: &'a str) -> impl Iterator<Item = &'a PeerConfig> {
+        self.peers.iter().filter(move |peer| peer.name != localpeer)
+    }
+}
+
+impl ResolversConfig {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            nameservers: Vec::new(),
+            resolve_retries: 3,
+            timeout_resolve_ms: 1000,
+            timeout_retry_ms: 1000,
+            hold_valid_ms: 10_000,
+            hold_nx_ms: 30_000,
+            hold_timeout_ms: 30_000,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AclConfig {
     pub name: String,
-    pub criterion: String,
+    pub criterion: Vec<String>,
+    #[serde(skip)]
+    pub span: SourceSpan,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UseBackendConfig {
     pub backend: String,
-    pub condition: Option<String>,
+    pub condition: Option<Vec<String>>,
+    #[serde(skip)]
+    pub span: SourceSpan,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpRequestAuthConfig {
+    pub realm: Option<String>,
+    pub condition: Option<Vec<String>>,
+    #[serde(skip)]
+    pub span: SourceSpan,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpReturnConfig {
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+    pub condition: Option<Vec<String>>,
+    #[serde(skip)]
+    pub span: SourceSpan,
+}
+
+impl HttpReturnConfig {
+    fn parse(args: &[&str], span: &SourceSpan) -> Result<Self> {
+        let mut config = Self { status: 200, content_type: None, body: Vec::new(), condition: None, span: span.clone() };
+        let mut body = false;
+        let mut args = args;
+        loop {
+            args = match args {
+                ["status", status, rest @ ..] => {
+                    config.status = status.parse().ok().filter(|status| (200..=599).contains(status))
+                        .ok_or_else(|| anyhow!("invalid status '{}', expected a code between 200 and 599", status))?;
+                    rest
+                }
+                ["content-type", content_type, rest @ ..] => {
+                    config.content_type = Some(content_type.to_string());
+                    rest
+                }
+                [source @ ("string" | "file"), value, rest @ ..] => {
+                    if body {
+                        return Err(anyhow!("only one of 'string' or 'file' may be given"));
+                    }
+                    body = true;
+                    config.body = match *source {
+                        "string" => value.as_bytes().to_vec(),
+                        _ => std::fs::read(value).map_err(|e| anyhow!("cannot read file '{}': {}", value, e))?,
+                    };
+                    rest
+                }
+                ["if" | "unless", _, ..] => {
+                    config.condition = Some(args.iter().map(|arg| arg.to_string()).collect());
+                    break;
+                }
+                [] => break,
+                _ => return Err(anyhow!("expected 'http-request return [status <code>] [content-type <type>] [string <text> | file <path>] [if|unless <condition>]'")),
+            };
+        }
+        if body && config.content_type.is_none() {
+            return Err(anyhow!("a 'string' or 'file' body requires a content-type"));
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TcpRequestRuleConfig {
+    pub action: String,
+    pub condition: Option<Vec<String>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -107,67 +858,129 @@ pub struct HealthCheckConfig {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MetricsConfig {
     pub enabled: bool,
-    pub bind: Option<String>,
-    pub path: Option<String>,
+    pub binds: Vec<StatsBindConfig>,
+    pub paths: Vec<(String, metrics::StatsHandler)>,
+    pub refresh_secs: Option<u64>,
+    pub admin: bool,
+    #[serde(default)]
+    pub auth: Option<UserlistConfig>,
+    pub use_server_id: bool,
+    pub max_series_per_backend: Option<usize>,
+    pub required: bool,
+    pub prefix: Option<String>,
+    pub labels: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatsBindConfig {
+    pub address: String,
+    pub unix_mode: Option<u32>,
+    pub unix_user: Option<String>,
+    pub unix_group: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceSpan {
+    pub file: Option<Arc<str>>,
+    pub line: usize,
+}
+
+impl SourceSpan {
+    pub fn runtime() -> Self {
+        Self { file: Some(Arc::from("runtime")), line: 0 }
+    }
+}
+
+impl fmt::Display for SourceSpan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.file {
+            Some(ref file) if self.line == 0 => f.write_str(file),
+            Some(ref file) => write!(f, "{}:{}", file, self.line),
+            None => write!(f, "line {}", self.line),
+        }
+    }
 }
 
 impl Config {
     pub async fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
         let content = fs::read_to_string(path).await?;
-        Self::from_haproxy_config(&content)
+        Self::from_haproxy_config(&content, Some(&path.display().to_string()))
     }
 
-    pub fn from_haproxy_config(content: &str) -> Result<Self> {
+    pub fn from_haproxy_config(content: &str, file: Option<&str>) -> Result<Self> {
         let mut config = Config {
             global: GlobalConfig::default(),
             defaults: DefaultsConfig::default(),
             frontends: Vec::new(),
             backends: Vec::new(),
+            resolvers: Vec::new(),
+            peers: Vec::new(),
+            userlists: Vec::new(),
             metrics: MetricsConfig::default(),
             rate_limit: None,
             ddos_protection: None,
             hot_reload: None,
             compression: None,
+            capability_uses: Vec::new(),
+            ignored_directives: Vec::new(),
+            content_hash: content_hash(content),
         };
         
+        let file: Option<Arc<str>> = file.map(Arc::from);
         let mut stats_binds = Vec::new();
 
         let mut current_section = None;
         let mut current_frontend: Option<FrontendConfig> = None;
         let mut current_backend: Option<BackendConfig> = None;
+        let mut current_resolvers: Option<ResolversConfig> = None;
+        let mut current_peers: Option<PeersConfig> = None;
+        let mut current_userlist: Option<UserlistConfig> = None;
 
-        for (line_num, line) in content.lines().enumerate() {
-            let line = line.trim();
-            
-            if line.is_empty() || line.starts_with('#') {
+        for (line_num, line) in logical_lines(content) {
+            let span = SourceSpan { file: file.clone(), line: line_num + 1 };
+            let tokens = tokenize_line(&line)
+                .map_err(|e| anyhow!("{}: Invalid line: {}", span, e))?;
+            if tokens.is_empty() {
                 continue;
             }
 
-            debug!("Parsing line {}: '{}'", line_num + 1, line);
+            debug!("Parsing line {}: {:?}", span, tokens);
 
-            match parse_line(line, line_num + 1)? {
-                LineType::Section(section) => {
+            match parse_line(tokens, &span)? {
+                LineType::Section(tokens) => {
+                    let section = tokens.join(" ");
                     if let Some(mut frontend) = current_frontend.take() {
                         let mode = frontend.mode.as_deref().unwrap_or("tcp");
-                        frontend.options = Some(Options::from_strings(&frontend.option, mode)?);
+                        frontend.options = Some(section_options(&frontend.option, mode, &[("defaults", &config.defaults.timeout), (&format!("frontend '{}'", frontend.name), &frontend.timeout)])
+                            .map_err(|e| anyhow!("{}: {}", frontend.span, e))?);
+                        if frontend.log_format.is_none() {
+                            frontend.log_format = config.defaults.log_format.clone();
+                        }
                         config.frontends.push(frontend);
                     }
                     if let Some(mut backend) = current_backend.take() {
-                        let mode = backend.mode.as_deref().unwrap_or("tcp");
-                        backend.options = Some(Options::from_strings(&backend.option, mode)?);
-                        
-                        backend.health_check = create_health_check_config(&backend);
-                        
+                        backend.apply_defaults(&config.defaults)
+                            .map_err(|e| anyhow!("{}: {}", backend.span, e))?;
                         config.backends.push(backend);
                     }
+                    if let Some(resolvers) = current_resolvers.take() {
+                        config.resolvers.push(resolvers);
+                    }
+                    if let Some(peers) = current_peers.take() {
+                        config.peers.push(peers);
+                    }
+                    if let Some(userlist) = current_userlist.take() {
+                        config.userlists.push(userlist);
+                    }
 
                     current_section = Some(section.clone());
                     match section.as_str() {
                         "global" => {},
                         "defaults" => {},
                         _ if section.starts_with("frontend ") => {
-                            let name = section.split_whitespace().nth(1)
-                                .ok_or_else(|| anyhow!("Invalid frontend name at line {}", line_num + 1))?;
+                            let name = tokens.get(1)
+                                .ok_or_else(|| anyhow!("{}: Invalid frontend name", span))?;
                             current_frontend = Some(FrontendConfig {
                                 name: name.to_string(),
                                 bind: Vec::new(),
@@ -175,58 +988,116 @@ impl Config {
                                 default_backend: None,
                                 acl: Vec::new(),
                                 use_backend: Vec::new(),
+                                tcp_request_connection: Vec::new(),
+                                tcp_request_inspect_delay: None,
+                                http_request_auth: Vec::new(),
+                                http_request_return: Vec::new(),
+                                rate_limit_sessions: None,
+                                maxconn: None,
+                                bwlim_in: None,
+                                bwlim_out: None,
                                 option: Vec::new(),
                                 timeout: HashMap::new(),
                                 options: None,
+                                log_format: None,
+                                on_no_backend: None,
+                                connect_auth: None,
+                                span: span.clone(),
                             });
                         },
                         _ if section.starts_with("backend ") => {
-                            let name = section.split_whitespace().nth(1)
-                                .ok_or_else(|| anyhow!("Invalid backend name at line {}", line_num + 1))?;
-                            current_backend = Some(BackendConfig {
-                                name: name.to_string(),
-                                mode: None,
-                                balance: None,
-                                server: Vec::new(),
-                                option: Vec::new(),
-                                timeout: HashMap::new(),
-                                health_check: None,
-                                options: None,
-                                retries: None,
-                            });
+                            let name = tokens.get(1)
+                                .ok_or_else(|| anyhow!("{}: Invalid backend name", span))?;
+                            current_backend = Some(BackendConfig::new(name, span.clone()));
+                        },
+                        _ if section.starts_with("resolvers ") => {
+                            let name = tokens.get(1)
+                                .ok_or_else(|| anyhow!("{}: Invalid resolvers name", span))?;
+                            current_resolvers = Some(ResolversConfig::new(name));
+                        },
+                        _ if section.starts_with("peers ") => {
+                            let name = tokens.get(1)
+                                .ok_or_else(|| anyhow!("{}: Invalid peers name", span))?;
+                            current_peers = Some(PeersConfig::new(name));
+                        },
+                        _ if section.starts_with("userlist ") => {
+                            let name = tokens.get(1)
+                                .ok_or_else(|| anyhow!("{}: Invalid userlist name", span))?;
+                            current_userlist = Some(UserlistConfig::new(name));
                         },
                         _ => {
-                            warn!("Unknown section: {}", section);
+                            warn!("{}: Unknown section: {}", span, section);
+                            config.ignored_directives.push(IgnoredDirective {
+                                section: String::new(),
+                                directive: section.clone(),
+                                span: span.clone(),
+                            });
                         }
                     }
                 },
-                LineType::Directive(key, value) => {
-                    debug!("Parsing directive: {} = {}", key, value);
-                    match current_section.as_deref() {
+                LineType::Directive(key, args) => {
+                    debug!("Parsing directive: {} = {:?}", key, args);
+                    let capability_use = current_section.as_deref()
+                        .and_then(|section| required_capability(section, &key, &args).map(|(directive, capability)| CapabilityUse {
+                            section: section.to_string(),
+                            directive,
+                            capability,
+                            span: span.clone(),
+                        }));
+                    let requires_capability = capability_use.is_some();
+                    config.capability_uses.extend(capability_use);
+                    let parsed = match current_section.as_deref() {
                         Some("global") => {
-                            if key == "stats" && value.starts_with("bind") {
-                                let parts: Vec<&str> = value.split_whitespace().collect();
-                                if parts.len() >= 2 {
-                                    stats_binds.push(parts[1].to_string());
+                            if key == "stats" && args.first().is_some_and(|arg| arg == "bind") {
+                                if args.len() >= 2 {
+                                    stats_binds.push((span.clone(), args[1..].to_vec()));
                                 }
+                                Ok(())
                             } else {
-                                parse_global_directive(&mut config.global, &key, &value)?;
+                                parse_global_directive(&mut config.global, &key, &args)
                             }
                         },
-                        Some("defaults") => parse_defaults_directive(&mut config.defaults, &key, &value)?,
-                        Some(section) if section.starts_with("frontend ") => {
-                            if let Some(ref mut frontend) = current_frontend {
-                                parse_frontend_directive(frontend, &key, &value)?;
-                            }
+                        Some("defaults") => parse_defaults_directive(&mut config.defaults, &key, &args),
+                        Some(section) if section.starts_with("frontend ") => match current_frontend {
+                            Some(ref mut frontend) => parse_frontend_directive(frontend, &key, &args, &span),
+                            None => Ok(()),
                         },
-                        Some(section) if section.starts_with("backend ") => {
-                            if let Some(ref mut backend) = current_backend {
-                                parse_backend_directive(backend, &key, &value)?;
-                            }
+                        Some(section) if section.starts_with("backend ") => match current_backend {
+                            Some(ref mut backend) => parse_backend_directive(backend, &key, &args, &span),
+                            None => Ok(()),
                         },
-                        _ => {
-                            warn!("Directive outside section: {} {}", key, value);
+                        Some(section) if section.starts_with("resolvers ") => match current_resolvers {
+                            Some(ref mut resolvers) => parse_resolvers_directive(resolvers, &key, &args),
+                            None => Ok(()),
+                        },
+                        Some(section) if section.starts_with("peers ") => match current_peers {
+                            Some(ref mut peers) => parse_peers_directive(peers, &key, &args),
+                            None => Ok(()),
+                        },
+                        Some(section) if section.starts_with("userlist ") => match current_userlist {
+                            Some(ref mut userlist) => parse_userlist_directive(userlist, &key, &args),
+                            None => Ok(()),
+                        },
+                        Some(section) => {
+                            warn!("{}: Directive in unknown section '{}': {} {}", span, section, key, args.join(" "));
+                            Ok(())
                         }
+                        None => Err(UnsupportedDirective.into()),
+                    };
+                    match parsed {
+                        Err(e) if e.is::<UnsupportedDirective>() => {
+                            let section = current_section.clone().unwrap_or_default();
+                            let directive = std::iter::once(&key).chain(&args).map(String::as_str).collect::<Vec<_>>().join(" ");
+                            if section.is_empty() {
+                                warn!("{}: Directive outside section: {}", span, directive);
+                            } else if !requires_capability {
+                                warn!("{}: Unsupported {} directive: {}", span, section.split_whitespace().next().unwrap_or_default(), directive);
+                            }
+                            if !requires_capability {
+                                config.ignored_directives.push(IgnoredDirective { section, directive, span: span.clone() });
+                            }
+                        }
+                        parsed => parsed.map_err(|e| anyhow!("{}: {}", span, e))?,
                     }
                 }
             }
@@ -234,237 +1105,1152 @@ impl Config {
 
         if let Some(mut frontend) = current_frontend {
             let mode = frontend.mode.as_deref().unwrap_or("tcp");
-            frontend.options = Some(Options::from_strings(&frontend.option, mode)?);
+            frontend.options = Some(section_options(&frontend.option, mode, &[("defaults", &config.defaults.timeout), (&format!("frontend '{}'", frontend.name), &frontend.timeout)])
+                .map_err(|e| anyhow!("{}: {}", frontend.span, e))?);
+            if frontend.log_format.is_none() {
+                frontend.log_format = config.defaults.log_format.clone();
+            }
             config.frontends.push(frontend);
         }
         if let Some(mut backend) = current_backend {
-            let mode = backend.mode.as_deref().unwrap_or("tcp");
-            backend.options = Some(Options::from_strings(&backend.option, mode)?);
-            
-            backend.health_check = create_health_check_config(&backend);
-            
+            backend.apply_defaults(&config.defaults)
+                .map_err(|e| anyhow!("{}: {}", backend.span, e))?;
             config.backends.push(backend);
         }
+        if let Some(resolvers) = current_resolvers {
+            config.resolvers.push(resolvers);
+        }
+        if let Some(peers) = current_peers {
+            config.peers.push(peers);
+        }
+        if let Some(userlist) = current_userlist {
+            config.userlists.push(userlist);
+        }
+
+        for backend in &mut config.backends {
+            assign_server_ids(&mut backend.server);
+        }
 
         let mode = config.defaults.mode.as_deref().unwrap_or("tcp");
-        config.defaults.options = Some(Options::from_strings(&config.defaults.option, mode)?);
+        config.defaults.options = Some(section_options(&config.defaults.option, mode, &[("defaults", &config.defaults.timeout)])?);
 
         if !stats_binds.is_empty() {
-            config.metrics.bind = Some(stats_binds[0].clone());
-            info!("Metrics will be available on: {}", stats_binds[0]);
+            config.metrics.binds.clear();
+        }
+        for (span, stats_bind) in &stats_binds {
+            let bind = StatsBindConfig::parse(stats_bind)
+                .map_err(|e| anyhow!("{}: {}", span, e))?;
+            if config.metrics.binds.iter().any(|other| other.address == bind.address) {
+                return Err(anyhow!("{}: stats bind '{}' is declared twice", span, bind.address));
+            }
+            info!("Metrics will be available on: {}", bind.address);
+            config.metrics.binds.push(bind);
         }
+        config.metrics.use_server_id = config.global.option.iter().any(|o| *o == ["metrics-use-server-id"]);
+        config.metrics.max_series_per_backend = config.global.option.iter()
+            .rev()
+            .find_map(|o| option_value(o, "metrics-max-series-per-backend"))
+            .and_then(|max| max.parse().ok());
+        config.metrics.required = !config.global.option.iter().any(|o| *o == ["metrics-required-off"]);
+        config.metrics.paths = Self::parse_stats_paths(&config.global.option)?;
+        config.metrics.refresh_secs = config.global.option.iter()
+            .rev()
+            .find_map(|o| option_value(o, "stats-refresh"))
+            .and_then(|secs| secs.parse().ok());
+        config.metrics.admin = config.global.option.iter().any(|o| *o == ["stats-admin"]);
+        config.metrics.auth = match config.global.option.iter().rev().find_map(|o| option_value(o, "stats-userlist")) {
+            Some(name) => Some(config.userlists.iter()
+                .find(|userlist| userlist.name == name)
+                .cloned()
+                .ok_or_else(|| anyhow!("stats userlist references non-existent userlist '{}'", name))?),
+            None => None,
+        };
+        config.metrics.prefix = config.global.option.iter()
+            .rev()
+            .find_map(|o| option_value(o, "metrics-prefix"))
+            .map(|prefix| prefix.to_string());
+        config.metrics.labels = config.global.option.iter()
+            .filter_map(|o| match o.as_slice() {
+                [option, key, value] if option == "metrics-label" => Some((key.clone(), value.clone())),
+                _ => None,
+            })
+            .collect();
 
         Self::parse_rate_limit_config(&mut config)?;
         Self::parse_ddos_protection_config(&mut config)?;
         Self::parse_compression_config(&mut config)?;
+        Self::parse_hot_reload_config(&mut config);
 
         Ok(config)
     }
 
-    pub fn validate(&self) -> Result<()> {
+    pub fn validate(&self, allow_degraded: bool) -> Result<()> {
+        let missing: Vec<&CapabilityUse> = self.capability_uses.iter()
+            .filter(|usage| !usage.capability.available())
+            .collect();
+        if allow_degraded {
+            for usage in &missing {
+                warn!("{}; running without it because of --allow-degraded", usage);
+            }
+        } else if !missing.is_empty() {
+            let list: Vec<String> = missing.iter().map(|usage| usage.to_string()).collect();
+            return Err(anyhow!("Configuration uses directives this build does not provide (pass --allow-degraded to ignore them):\n  {}",
+                             list.join("\n  ")));
+        }
+
         let backend_names: std::collections::HashSet<_> = self.backends.iter()
             .map(|b| &b.name)
             .collect();
 
+        let bind_range_max = self.global.bind_range_max.unwrap_or(100);
+        let mut binds: Vec<(&str, &BindConfig)> = Vec::new();
+
         for frontend in &self.frontends {
+            let mut listener_names = HashSet::new();
+            for bind in &frontend.bind {
+                let mut names = bind.addresses().into_iter()
+                    .flat_map(|addr| (0..bind.shard_count()).map(move |shard| bind.shard_name(addr, shard)));
+                if let Some(name) = names.find(|name| !listener_names.insert(name.clone())) {
+                    return Err(anyhow!("{}: Frontend '{}' has more than one listener named '{}'", frontend.span, frontend.name, name));
+                }
+                if bind.range_size() > bind_range_max {
+                    return Err(anyhow!("{}: Frontend '{}' bind {} covers {} ports, more than bind-range-max {}",
+                                     frontend.span, frontend.name, bind, bind.range_size(), bind_range_max));
+                }
+                let shared = |other_frontend: &str, other: &BindConfig| other_frontend == frontend.name && bind.reuseport && other.reuseport;
+                if let Some((other_frontend, other)) = binds.iter().find(|(other_frontend, other)| bind.overlaps(other) && !shared(other_frontend, other)) {
+                    let hint = match (bind.reuseport || other.reuseport, *other_frontend == frontend.name) {
+                        (false, _) => "",
+                        (true, true) => "; both binds need 'reuseport' to share it",
+                        (true, false) => "; 'reuseport' only shares an address between binds of the same frontend",
+                    };
+                    return Err(anyhow!("{}: Frontend '{}' bind {} overlaps bind {} of frontend '{}'{}",
+                                     frontend.span, frontend.name, bind, other, other_frontend, hint));
+                }
+                if bind.ssl {
+                    TlsTerminator::new(bind)
+                        .map_err(|e| anyhow!("{}: Frontend '{}' bind {}: {}", frontend.span, frontend.name, bind, e))?;
+                } else if bind.crt.is_some() || bind.default_crt.is_some() || !bind.alpn.is_empty() {
+                    return Err(anyhow!("{}: Frontend '{}' bind {}: 'crt', 'default-crt' and 'alpn' need 'ssl'", frontend.span, frontend.name, bind));
+                }
+                if bind.interface.is_some() && !cfg!(target_os = "linux") {
+                    return Err(anyhow!("{}: Frontend '{}' bind {}: 'interface' needs SO_BINDTODEVICE, which is only available on Linux",
+                                     frontend.span, frontend.name, bind));
+                }
+                if bind.transparent && !cfg!(target_os = "linux") {
+                    return Err(anyhow!("{}: Frontend '{}' bind {}: 'transparent' needs SO_ORIGINAL_DST, which is only available on Linux",
+                                     frontend.span, frontend.name, bind));
+                }
+                if bind.tfo && !cfg!(target_os = "linux") {
+                    return Err(anyhow!("{}: Frontend '{}' bind {}: 'tfo' needs TCP_FASTOPEN, which is only available on Linux",
+                                     frontend.span, frontend.name, bind));
+                }
+                if bind.reuseport && !cfg!(target_os = "linux") {
+                    return Err(anyhow!("{}: Frontend '{}' bind {}: 'reuseport' and 'shards' need SO_REUSEPORT load balancing, which is only available on Linux",
+                                     frontend.span, frontend.name, bind));
+                }
+                if bind.defer_accept && !cfg!(target_os = "linux") {
+                    return Err(anyhow!("{}: Frontend '{}' bind {}: 'defer-accept' needs TCP_DEFER_ACCEPT, which is only available on Linux",
+                                     frontend.span, frontend.name, bind));
+                }
+                binds.push((&frontend.name, bind));
+            }
+
             if let Some(ref backend_name) = frontend.default_backend {
                 if !backend_names.contains(backend_name) {
-                    return Err(anyhow!("Frontend '{}' references non-existent backend '{}'", 
-                                     frontend.name, backend_name));
+                    return Err(anyhow!("{}: Frontend '{}' references non-existent backend '{}'", 
+                                     frontend.span, frontend.name, backend_name));
+                }
+            }
+
+            if !frontend.bind.iter().any(|bind| bind.transparent) {
+                let routed = frontend.default_backend.iter()
+                    .chain(frontend.use_backend.iter().map(|rule| &rule.backend));
+                for backend_name in routed {
+                    let use_original_dst = self.backends.iter()
+                        .find(|backend| &backend.name == backend_name)
+                        .and_then(|backend| backend.options.as_ref())
+                        .is_some_and(|options| options.tcp_options.use_original_dst);
+                    if use_original_dst {
+                        warn!("{}: Frontend '{}' routes to backend '{}' which uses the original destination, but has no transparent bind",
+                              frontend.span, frontend.name, backend_name);
+                    }
+                }
+            }
+
+            let connect_mode = frontend.mode.as_deref() == Some("http-connect");
+            if !connect_mode {
+                let routed = frontend.default_backend.iter()
+                    .chain(frontend.use_backend.iter().map(|rule| &rule.backend));
+                for backend_name in routed {
+                    let connect_direct = self.backends.iter()
+                        .find(|backend| &backend.name == backend_name)
+                        .and_then(|backend| backend.options.as_ref())
+                        .is_some_and(|options| options.tcp_options.connect_direct);
+                    if connect_direct {
+                        return Err(anyhow!("{}: Frontend '{}' routes to backend '{}' which uses 'option connect-direct', but is not in 'mode http-connect'",
+                                         frontend.span, frontend.name, backend_name));
+                    }
+                }
+            }
+            if let Some(ref userlist) = frontend.connect_auth {
+                if !connect_mode {
+                    return Err(anyhow!("{}: Frontend '{}' uses 'connect-auth', which needs 'mode http-connect'", frontend.span, frontend.name));
+                }
+                if !self.userlists.iter().any(|u| &u.name == userlist) {
+                    return Err(anyhow!("{}: Frontend '{}' references non-existent userlist '{}'", frontend.span, frontend.name, userlist));
+                }
+            }
+            if let Some(rule) = frontend.http_request_auth.first() {
+                if frontend.mode.as_deref() != Some("http") {
+                    return Err(anyhow!("{}: Frontend '{}' uses 'http-request auth', which needs 'mode http'", rule.span, frontend.name));
+                }
+            }
+            if let Some(rule) = frontend.http_request_return.first() {
+                if frontend.mode.as_deref() != Some("http") {
+                    return Err(anyhow!("{}: Frontend '{}' uses 'http-request return', which needs 'mode http'", rule.span, frontend.name));
+                }
+            }
+
+            if let Some(ref template) = frontend.log_format {
+                LogFormat::parse(template)
+                    .map_err(|e| anyhow!("{}: Frontend '{}' has invalid log-format: {}", frontend.span, frontend.name, e))?;
+            }
+
+            let routing = CompiledRouting::compile(frontend, &self.userlists)
+                .map_err(|e| anyhow!("{} in frontend '{}'", e, frontend.name))?;
+            let payload_needed = routing.acls.payload_needed();
+            if routing.acls.uses_src_dn() && self.global.src_dn_resolvers.is_none() {
+                return Err(anyhow!("{}: Frontend '{}' uses src_dn ACLs but no src-dn-resolvers is set in global", frontend.span, frontend.name));
+            }
+            if routing.acls.uses_src_country() && self.global.geoip_db.is_none() {
+                return Err(anyhow!("{}: Frontend '{}' uses src_country ACLs but no geoip-db is set in global", frontend.span, frontend.name));
+            }
+            if routing.acls.uses_req_ssl_sni() {
+                if let Some(bind) = frontend.bind.iter().find(|bind| bind.ssl) {
+                    return Err(anyhow!("{}: Frontend '{}' uses req_ssl_sni ACLs but bind {} terminates TLS, whose handshake consumes the ClientHello; use ssl_fc_sni instead",
+                                     frontend.span, frontend.name, bind));
+                }
+            }
+            if routing.acls.uses_ssl_fc_sni() && !frontend.bind.iter().any(|bind| bind.ssl) {
+                return Err(anyhow!("{}: Frontend '{}' uses ssl_fc_sni ACLs but none of its binds has 'ssl'; use req_ssl_sni with tcp-request inspect-delay to match the ClientHello",
+                                 frontend.span, frontend.name));
+            }
+
+            if let Some(ref delay) = frontend.tcp_request_inspect_delay {
+                utils::parse_duration(delay)
+                    .map_err(|e| anyhow!("{}: Frontend '{}' has invalid tcp-request inspect-delay '{}': {}", frontend.span, frontend.name, delay, e))?;
+            } else if payload_needed > 0 || routing.acls.inspects_http() || routing.acls.uses_req_ssl_sni() {
+                warn!("{}: Frontend '{}' uses req.payload/req.len/req_ssl_sni/path/hdr ACLs without tcp-request inspect-delay; they only see data already buffered",
+                      frontend.span, frontend.name);
+            }
+
+            let inspect_bufsize = self.global.tune_inspect_bufsize.unwrap_or(session::DEFAULT_INSPECT_BUFSIZE);
+            if payload_needed > inspect_bufsize {
+                warn!("{}: Frontend '{}' has payload ACLs that need {} bytes but tune.inspect-bufsize is {}; they never see more",
+                      frontend.span, frontend.name, payload_needed, inspect_bufsize);
+            }
+
+            for rule in &frontend.tcp_request_connection {
+                if !matches!(rule.action.as_str(), "accept" | "reject" | "tarpit") {
+                    return Err(anyhow!("{}: Frontend '{}' has unknown tcp-request connection action '{}'",
+                                     frontend.span, frontend.name, rule.action));
+                }
+            }
+
+            for use_backend in &frontend.use_backend {
+                if !NameTemplate::is_template(&use_backend.backend) && !backend_names.contains(&use_backend.backend) {
+                    return Err(anyhow!("{}: Frontend '{}' references non-existent backend '{}'",
+                                     use_backend.span, frontend.name, use_backend.backend));
+                }
+            }
+        }
+
+        for resolvers in &self.resolvers {
+            if resolvers.nameservers.is_empty() {
+                return Err(anyhow!("Resolvers '{}' has no nameservers", resolvers.name));
+            }
+        }
+
+        let mut userlist_names = HashSet::new();
+        for userlist in &self.userlists {
+            if !userlist_names.insert(&userlist.name) {
+                return Err(anyhow!("Userlist '{}' is declared twice", userlist.name));
+            }
+            if userlist.users.is_empty() {
+                return Err(anyhow!("Userlist '{}' has no users", userlist.name));
+            }
+            for user in &userlist.users {
+                if let Some(group) = user.groups.iter().find(|group| !userlist.groups.iter().any(|g| &g.name == *group)) {
+                    return Err(anyhow!("Userlist '{}' user '{}' references non-existent group '{}'", userlist.name, user.name, group));
+                }
+            }
+            for group in &userlist.groups {
+                if let Some(user) = group.users.iter().find(|user| !userlist.users.iter().any(|u| &u.name == *user)) {
+                    return Err(anyhow!("Userlist '{}' group '{}' references non-existent user '{}'", userlist.name, group.name, user));
+                }
+            }
+        }
+
+        let localpeer = self.global.local_peer_name();
+        for peers in &self.peers {
+            if peers.local(&localpeer).is_none() {
+                return Err(anyhow!("Peers '{}' has no peer named '{}' for this instance, set 'localpeer' in global or name a peer after the hostname",
+                                 peers.name, localpeer));
+            }
+            if peers.remotes(&localpeer).next().is_none() {
+                warn!("Peers '{}' has no remote peer, nothing will be synchronized", peers.name);
+            }
+        }
+
+        if let Some(ref prefix) = self.metrics.prefix {
+            if !is_metric_name(prefix) {
+                return Err(anyhow!("Invalid metrics prefix '{}', expected letters, digits, '_' or ':' not starting with a digit", prefix));
+            }
+        }
+        let mut metric_labels = HashSet::new();
+        for (key, _) in &self.metrics.labels {
+            if !is_label_name(key) || key.starts_with("__") {
+                return Err(anyhow!("Invalid metrics label '{}', expected letters, digits or '_' not starting with a digit or '__'", key));
+            }
+            if RESERVED_METRIC_LABELS.contains(&key.as_str()) {
+                return Err(anyhow!("Metrics label '{}' is reserved by the exporter", key));
+            }
+            if !metric_labels.insert(key) {
+                return Err(anyhow!("Metrics label '{}' is declared twice", key));
+            }
+        }
+
+        if let Some(ref resolvers) = self.global.src_dn_resolvers {
+            if !self.resolvers.iter().any(|r| &r.name == resolvers) {
+                return Err(anyhow!("src-dn-resolvers references non-existent resolvers '{}'", resolvers));
+            }
+        }
+
+        for backend in &self.backends {
+            let use_original_dst = backend.options.as_ref()
+                .is_some_and(|options| options.tcp_options.use_original_dst);
+            let connect_direct = backend.options.as_ref()
+                .is_some_and(|options| options.tcp_options.connect_direct);
+            if use_original_dst && !cfg!(target_os = "linux") {
+                return Err(anyhow!("{}: Backend '{}' uses 'option use-original-dst', which is only available on Linux", backend.span, backend.name));
+            }
+            if use_original_dst && connect_direct {
+                return Err(anyhow!("{}: Backend '{}' cannot use both 'option use-original-dst' and 'option connect-direct'", backend.span, backend.name));
+            }
+
+            if backend.mode.as_deref() == Some("health") {
+                if !backend.server.is_empty() {
+                    warn!("{}: Backend '{}' is in health mode, its servers are ignored", backend.span, backend.name);
+                }
+            } else if backend.http_request_return.is_some() {
+                if !backend.server.is_empty() {
+                    warn!("{}: Backend '{}' answers with http-request return, its servers are ignored", backend.span, backend.name);
+                }
+            } else if use_original_dst {
+                if !backend.server.is_empty() {
+                    warn!("{}: Backend '{}' connects to the original destination, its servers are ignored", backend.span, backend.name);
+                }
+            } else if connect_direct {
+                if !backend.server.is_empty() {
+                    warn!("{}: Backend '{}' connects to the CONNECT destination, its servers are ignored", backend.span, backend.name);
+                }
+            } else if backend.server.is_empty() {
+                return Err(anyhow!("{}: Backend '{}' has no servers", backend.span, backend.name));
+            }
+
+            for server in &backend.server {
+                if server.weight.is_some_and(|weight| weight > MAX_WEIGHT) {
+                    return Err(anyhow!("{}: Server '{}/{}' has weight {} outside 0..={}",
+                                     server.span, backend.name, server.name, server.weight.unwrap_or_default(), MAX_WEIGHT));
+                }
+                if let Some(ref resolvers) = server.resolvers {
+                    if !self.resolvers.iter().any(|r| &r.name == resolvers) {
+                        return Err(anyhow!("{}: Server '{}/{}' references non-existent resolvers '{}'",
+                                         server.span, backend.name, server.name, resolvers));
+                    }
+                }
+                if server.tfo == Some(true) && !cfg!(target_os = "linux") {
+                    return Err(anyhow!("{}: Server '{}/{}': 'tfo' needs TCP_FASTOPEN_CONNECT, which is only available on Linux",
+                                     server.span, backend.name, server.name));
+                }
+                if server.is_srv() && server.resolvers.is_none() {
+                    return Err(anyhow!("{}: Server '{}/{}' uses SRV name '{}' and requires a 'resolvers' reference",
+                                     server.span, backend.name, server.name, server.address));
+                }
+                for (keyword, value) in [("inter", &server.inter), ("fastinter", &server.fastinter), ("downinter", &server.downinter)] {
+                    let Some(value) = value else {
+                        continue;
+                    };
+                    let interval = utils::parse_duration(value)
+                        .map_err(|e| anyhow!("{}: Server '{}/{}' has invalid '{} {}': {}", server.span, backend.name, server.name, keyword, value, e))?;
+                    if interval.is_zero() {
+                        return Err(anyhow!("{}: Server '{}/{}' has zero health check interval '{}'", server.span, backend.name, server.name, keyword));
+                    }
+                }
+            }
+
+            if let Some(ref health_check) = backend.health_check {
+                for (directive, value) in [("interval", &health_check.interval), ("timeout", &health_check.timeout)] {
+                    let duration = utils::parse_duration(value)
+                        .map_err(|e| anyhow!("{}: Backend '{}' has invalid health check {} '{}': {}", backend.span, backend.name, directive, value, e))?;
+                    if duration.is_zero() {
+                        return Err(anyhow!("{}: Backend '{}' has zero health check {}", backend.span, backend.name, directive));
+                    }
+                }
+            }
+
+            if let Some(ref stick_table) = backend.stick_table {
+                if let Some(ref peers) = stick_table.peers {
+                    if !self.peers.iter().any(|p| &p.name == peers) {
+                        return Err(anyhow!("{}: Backend '{}' stick-table references non-existent peers '{}'", backend.span, backend.name, peers));
+                    }
+                }
+                let expire = utils::parse_duration(&stick_table.expire)
+                    .map_err(|e| anyhow!("{}: Backend '{}' has invalid stick-table expire '{}': {}", backend.span, backend.name, stick_table.expire, e))?;
+                if expire.is_zero() {
+                    return Err(anyhow!("{}: Backend '{}' has zero stick-table expire", backend.span, backend.name));
                 }
             }
 
-            for use_backend in &frontend.use_backend {
-                if !backend_names.contains(&use_backend.backend) {
-                    return Err(anyhow!("Frontend '{}' references non-existent backend '{}'", 
-                                     frontend.name, use_backend.backend));
-                }
-            }
-        }
+            match backend.stick_on.as_deref() {
+                Some("ssl_session") if backend.stick_table.is_none() => {
+                    return Err(anyhow!("{}: Backend '{}' uses 'stick on ssl_session' without a stick-table", backend.span, backend.name));
+                }
+                Some("ssl_session") | None => {},
+                Some(other) => {
+                    return Err(anyhow!("{}: Backend '{}' has unsupported stick pattern '{}', expected ssl_session", backend.span, backend.name, other));
+                }
+            }
+
+            if backend.fullconn == Some(0) {
+                return Err(anyhow!("{}: Backend '{}' has fullconn 0", backend.span, backend.name));
+            }
+
+            match backend.on_fullconn.as_deref() {
+                Some("reject") | Some("errorfile") | Some("queue") | None => {},
+                Some(other) => {
+                    return Err(anyhow!("{}: Backend '{}' has invalid on-fullconn '{}', expected reject, errorfile or queue", backend.span, backend.name, other));
+                }
+            }
+
+            if backend.require_check && !backend.server.is_empty() && !backend.server.iter().any(|server| server.check == Some(true)) {
+                warn!("{}: Backend '{}' sets require-check on but none of its servers has 'check', it routes to no server", backend.span, backend.name);
+            }
+
+            if backend.alert_threshold.is_some_and(|threshold| !(0.0..=1.0).contains(&threshold)) {
+                return Err(anyhow!("{}: Backend '{}' has alert-threshold outside 0..1", backend.span, backend.name));
+            }
+
+            match backend.adaptive_metric.as_deref() {
+                Some("latency") | Some("error-rate") | None => {},
+                Some(other) => {
+                    return Err(anyhow!("{}: Backend '{}' has invalid adaptive-metric '{}', expected latency or error-rate", backend.span, backend.name, other));
+                }
+            }
+
+            if !backend.balance_adaptive && (backend.adaptive_metric.is_some() || backend.adaptive_half_life.is_some()) {
+                warn!("{}: Backend '{}' sets adaptive-metric or adaptive-half-life without balance-adaptive on, they have no effect until it is enabled at runtime", backend.span, backend.name);
+            }
+
+            if backend.on_fullconn.is_some() && backend.fullconn.is_none() {
+                warn!("{}: Backend '{}' sets on-fullconn without fullconn, it has no effect", backend.span, backend.name);
+            }
+
+            let mut server_ids = HashMap::new();
+            for server in &backend.server {
+                if let Some(id) = server.id {
+                    if let Some(other) = server_ids.insert(id, &server.name) {
+                        return Err(anyhow!("{}: Backend '{}' servers '{}' and '{}' share id {}",
+                                         backend.span, backend.name, other, server.name, id));
+                    }
+                }
+            }
+        }
+
+        let labels = self.frontends.iter()
+            .flat_map(|frontend| std::iter::once((&frontend.span, "Frontend", &frontend.name))
+                .chain(frontend.bind.iter().filter_map(|bind| bind.name.as_ref().map(|name| (&frontend.span, "Listener", name)))))
+            .chain(self.backends.iter()
+                .flat_map(|backend| std::iter::once((&backend.span, "Backend", &backend.name))
+                    .chain(backend.server.iter().map(|server| (&server.span, "Server", &server.name)))));
+        for (span, kind, name) in labels {
+            if let Cow::Owned(sanitized) = metrics::sanitize_label(name) {
+                warn!("{}: {} name '{}' is not a valid metrics label value, metrics report it as '{}'", span, kind, name, sanitized);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn content_hash(content: &str) -> String {
+    Sha256::digest(content.as_bytes()).iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[derive(Debug)]
+enum LineType {
+    Section(Vec<String>),
+    Directive(String, Vec<String>),
+}
+
+const RESERVED_METRIC_LABELS: [&str; 2] = ["le", "quantile"];
+
+fn is_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn is_metric_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+fn logical_lines(content: &str) -> Vec<(usize, Cow<'_, str>)> {
+    let mut lines = Vec::new();
+    let mut pending: Option<(usize, String)> = None;
+
+    for (line_num, line) in content.lines().enumerate() {
+        let line = line.trim();
+        let trailing_backslashes = line.len() - line.trim_end_matches('\\').len();
+        let (text, continued) = if trailing_backslashes % 2 == 1 {
+            (&line[..line.len() - 1], true)
+        } else {
+            (line, false)
+        };
+
+        match (pending.take(), continued) {
+            (None, false) => lines.push((line_num, Cow::Borrowed(text))),
+            (pending_line, _) => {
+                let (start, mut joined) = pending_line.unwrap_or((line_num, String::new()));
+                if !joined.is_empty() && !text.is_empty() {
+                    joined.push(' ');
+                }
+                joined.push_str(text);
+
+                if continued {
+                    pending = Some((start, joined));
+                } else {
+                    lines.push((start, Cow::Owned(joined)));
+                }
+            }
+        }
+    }
+
+    lines.extend(pending.map(|(start, joined)| (start, Cow::Owned(joined))));
+    lines
+}
+
+fn tokenize_line(line: &str) -> Result<Vec<String>> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut in_token = false;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '#' => break,
+            c if c.is_whitespace() => {
+                if in_token {
+                    tokens.push(std::mem::take(&mut token));
+                    in_token = false;
+                }
+            },
+            '\\' => {
+                token.push(chars.next().ok_or_else(|| anyhow!("trailing backslash"))?);
+                in_token = true;
+            },
+            '"' => {
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => token.push(chars.next().ok_or_else(|| anyhow!("unterminated double quote"))?),
+                        Some(c) => token.push(c),
+                        None => return Err(anyhow!("unterminated double quote")),
+                    }
+                }
+                in_token = true;
+            },
+            '\'' => {
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => token.push(c),
+                        None => return Err(anyhow!("unterminated single quote")),
+                    }
+                }
+                in_token = true;
+            },
+            c => {
+                token.push(c);
+                in_token = true;
+            },
+        }
+    }
+
+    if in_token {
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+const UNSUPPORTED_SECTIONS: &[&str] = &["listen", "cache", "mailers", "program", "http-errors", "ring", "crt-store", "fcgi-app", "log-forward", "traces"];
+
+fn parse_line(tokens: Vec<String>, span: &SourceSpan) -> Result<LineType> {
+    let Some(first) = tokens.first().map(String::as_str) else {
+        return Err(anyhow!("{}: Empty directive", span));
+    };
+    if first == "global" || first == "defaults" || first.starts_with("frontend") || first.starts_with("backend") || first == "resolvers" || first == "peers" || first == "userlist"
+        || UNSUPPORTED_SECTIONS.contains(&first) {
+        Ok(LineType::Section(tokens))
+    } else {
+        let mut tokens = tokens.into_iter();
+        let key = tokens.next().unwrap_or_default();
+        Ok(LineType::Directive(key, tokens.collect()))
+    }
+}
+
+impl StatsBindConfig {
+    pub fn new(address: &str) -> Self {
+        Self {
+            address: match address.strip_prefix("*:") {
+                Some(port) => format!("0.0.0.0:{}", port),
+                None => address.to_string(),
+            },
+            unix_mode: None,
+            unix_user: None,
+            unix_group: None,
+        }
+    }
+
+    pub fn parse(parts: &[String]) -> Result<Self> {
+        let (address, options) = parts.split_first()
+            .ok_or_else(|| anyhow!("stats bind requires an address"))?;
+        let mut bind = Self::new(address);
+
+        let mut options = options.iter();
+        while let Some(option) = options.next() {
+            let value = options.next()
+                .ok_or_else(|| anyhow!("stats bind option '{}' requires a value", option))?;
+            match option.as_str() {
+                "mode" => bind.unix_mode = Some(u32::from_str_radix(value, 8)
+                    .map_err(|_| anyhow!("Invalid stats bind mode '{}'", value))?),
+                "user" => bind.unix_user = Some(value.clone()),
+                "group" => bind.unix_group = Some(value.clone()),
+                _ => return Err(anyhow!("Unknown stats bind option '{}'", option)),
+            }
+        }
+
+        if !bind.is_unix() && (bind.unix_mode.is_some() || bind.unix_user.is_some() || bind.unix_group.is_some()) {
+            return Err(anyhow!("stats bind mode/user/group only apply to unix: sockets"));
+        }
+
+        Ok(bind)
+    }
+
+    pub fn is_unix(&self) -> bool {
+        self.address.starts_with("unix:")
+    }
+}
+
+fn parse_resolvers_directive(resolvers: &mut ResolversConfig, key: &str, args: &[String]) -> Result<()> {
+    let parts: Vec<&str> = args.iter().map(String::as_str).collect();
+    let millis = |value: &str| utils::parse_duration(value).map(|d| d.as_millis() as u64);
+
+    match (key, parts.as_slice()) {
+        ("nameserver", [name, address]) => {
+            let address = address.parse()
+                .map_err(|_| anyhow!("Resolvers '{}' nameserver '{}' has invalid address '{}'", resolvers.name, name, address))?;
+            resolvers.nameservers.push(NameserverConfig {
+                name: name.to_string(),
+                address,
+            });
+        },
+        ("resolve_retries", [retries]) => resolvers.resolve_retries = retries.parse()?,
+        ("timeout", ["resolve", value]) => resolvers.timeout_resolve_ms = millis(value)?,
+        ("timeout", ["retry", value]) => resolvers.timeout_retry_ms = millis(value)?,
+        ("hold", ["valid", value]) => resolvers.hold_valid_ms = millis(value)?,
+        ("hold", ["nx", value]) => resolvers.hold_nx_ms = millis(value)?,
+        ("hold", ["timeout", value]) => resolvers.hold_timeout_ms = millis(value)?,
+        _ => return Err(UnsupportedDirective.into()),
+    }
+
+    Ok(())
+}
+
+fn parse_peers_directive(peers: &mut PeersConfig, key: &str, args: &[String]) -> Result<()> {
+    let parts: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match (key, parts.as_slice()) {
+        ("peer", [name, address]) => {
+            let address = address.parse()
+                .map_err(|_| anyhow!("Peers '{}' peer '{}' has invalid address '{}', expected <ip>:<port>", peers.name, name, address))?;
+            if peers.peers.iter().any(|peer| peer.name == *name) {
+                return Err(anyhow!("Peers '{}' declares peer '{}' twice", peers.name, name));
+            }
+            peers.peers.push(PeerConfig {
+                name: name.to_string(),
+                address,
+            });
+        },
+        ("secret", [secret]) => peers.secret = Some(secret.to_string()),
+        _ => return Err(UnsupportedDirective.into()),
+    }
+
+    Ok(())
+}
+
+fn parse_userlist_directive(userlist: &mut UserlistConfig, key: &str, args: &[String]) -> Result<()> {
+    let parts: Vec<&str> = args.iter().map(String::as_str).collect();
+    let names = |list: &str| list.split(',').filter(|name| !name.is_empty()).map(str::to_string).collect::<Vec<_>>();
 
-        for backend in &self.backends {
-            if backend.server.is_empty() {
-                return Err(anyhow!("Backend '{}' has no servers", backend.name));
+    match (key, parts.as_slice()) {
+        ("user", [name, kind @ ("insecure-password" | "password"), password, rest @ ..]) => {
+            let groups = match rest {
+                [] => Vec::new(),
+                ["groups", groups] => names(groups),
+                _ => return Err(anyhow!("Userlist '{}' user '{}' has invalid options '{}', expected 'groups <group>[,<group>...]'", userlist.name, name, rest.join(" "))),
+            };
+            if name.contains(':') {
+                return Err(anyhow!("Userlist '{}' user '{}' contains ':', which Basic authentication cannot carry", userlist.name, name));
             }
-        }
-
-        Ok(())
+            if userlist.users.iter().any(|user| user.name == *name) {
+                return Err(anyhow!("Userlist '{}' declares user '{}' twice", userlist.name, name));
+            }
+            let password = if *kind == "password" {
+                auth::check_hash(password)
+                    .map_err(|e| anyhow!("Userlist '{}' user '{}' has an invalid password hash: {}", userlist.name, name, e))?;
+                UserPassword::Hashed(password.to_string())
+            } else {
+                UserPassword::Insecure(password.to_string())
+            };
+            userlist.users.push(UserConfig {
+                name: name.to_string(),
+                password,
+                groups,
+            });
+        },
+        ("group", [name, rest @ ..]) => {
+            let users = match rest {
+                [] => Vec::new(),
+                ["users", users] => names(users),
+                _ => return Err(anyhow!("Userlist '{}' group '{}' has invalid options '{}', expected 'users <user>[,<user>...]'", userlist.name, name, rest.join(" "))),
+            };
+            if userlist.groups.iter().any(|group| group.name == *name) {
+                return Err(anyhow!("Userlist '{}' declares group '{}' twice", userlist.name, name));
+            }
+            userlist.groups.push(GroupConfig {
+                name: name.to_string(),
+                users,
+            });
+        },
+        _ => return Err(UnsupportedDirective.into()),
     }
-}
 
-#[derive(Debug)]
-enum LineType {
-    Section(String),
-    Directive(String, String),
+    Ok(())
 }
 
-fn parse_line(line: &str, line_num: usize) -> Result<LineType> {
-    let parts: Vec<&str> = line.split_whitespace().collect();
-    if parts.is_empty() {
-        return Err(anyhow!("Empty line at {}", line_num));
-    }
+pub fn assign_server_ids(servers: &mut [ServerConfig]) {
+    let mut used: std::collections::HashSet<u32> = servers.iter().filter_map(|s| s.id).collect();
+    let mut next_id = 1;
 
-    let first = parts[0];
-    if first == "global" || first == "defaults" || first.starts_with("frontend") || first.starts_with("backend") {
-        Ok(LineType::Section(line.to_string()))
-    } else {
-        if parts.len() < 2 {
-            return Err(anyhow!("Invalid directive at line {}: {}", line_num, line));
+    for server in servers.iter_mut().filter(|s| s.id.is_none()) {
+        while used.contains(&next_id) {
+            next_id += 1;
         }
-        let key = parts[0].to_string();
-        let value = parts[1..].join(" ");
-        Ok(LineType::Directive(key, value))
+        server.id = Some(next_id);
+        used.insert(next_id);
     }
 }
 
-fn parse_global_directive(global: &mut GlobalConfig, key: &str, value: &str) -> Result<()> {
+fn parse_global_directive(global: &mut GlobalConfig, key: &str, args: &[String]) -> Result<()> {
+    let parts: Vec<&str> = args.iter().map(String::as_str).collect();
     match key {
-        "maxconn" => global.maxconn = Some(value.parse()?),
-        "log" => global.log = Some(value.to_string()),
-        "user" => global.user = Some(value.to_string()),
-        "group" => global.group = Some(value.to_string()),
-        "daemon" => global.daemon = Some(match value {
-            "on" | "true" | "yes" => true,
-            "off" | "false" | "no" => false,
-            _ => value.parse()?,
+        "maxconn" => {
+            let value = single_arg(key, args)?;
+            global.maxconn = Some(value.parse().ok().filter(|n| *n > 0)
+                .ok_or_else(|| anyhow!("Invalid maxconn '{}', expected a positive number", value))?);
+        },
+        "maxconn-on-reload" => {
+            let value = single_arg(key, args)?;
+            global.keep_runtime_maxconn = match value {
+                "config" => false,
+                "runtime" => true,
+                _ => return Err(anyhow!("Invalid maxconn-on-reload '{}', expected config or runtime", value)),
+            }
+        },
+        "tarpit-maxconn" => {
+            let value = single_arg(key, args)?;
+            global.tarpit_maxconn = Some(value.parse()?);
+        },
+        "bind-range-max" => {
+            let value = single_arg(key, args)?;
+            global.bind_range_max = Some(value.parse()?);
+        },
+        "tune.maxcheckconn" => {
+            let value = single_arg(key, args)?;
+            global.tune_maxcheckconn = Some(value.parse().ok().filter(|n| *n > 0)
+                .ok_or_else(|| anyhow!("Invalid tune.maxcheckconn '{}', expected a positive number", value))?);
+        },
+        "tune.inspect-bufsize" => {
+            let value = single_arg(key, args)?;
+            global.tune_inspect_bufsize = Some(value.parse().ok().filter(|n| *n > 0)
+                .ok_or_else(|| anyhow!("Invalid tune.inspect-bufsize '{}', expected a positive number of bytes", value))?);
+        },
+        "tune.inspect-overflow" => {
+            let value = single_arg(key, args)?;
+            match value {
+                "route" | "reject" => global.tune_inspect_overflow = Some(value.to_string()),
+                _ => return Err(anyhow!("Invalid tune.inspect-overflow '{}', expected route or reject", value)),
+            }
+        },
+        "tune.log-burst" => {
+            let value = single_arg(key, args)?;
+            global.tune_log_burst = Some(value.parse()
+                .map_err(|_| anyhow!("Invalid tune.log-burst '{}', expected a number of log lines", value))?);
+        },
+        "tune.log-interval" => {
+            let value = single_arg(key, args)?;
+            global.tune_log_interval = Some(utils::parse_duration(value)
+                .ok()
+                .filter(|interval| !interval.is_zero())
+                .ok_or_else(|| anyhow!("Invalid tune.log-interval '{}', expected a positive duration", value))?);
+        },
+        "tune.backend-connect-rate" => {
+            let value = single_arg(key, args)?;
+            global.tune_backend_connect_rate = Some(value.parse().ok().filter(|n| *n > 0)
+                .ok_or_else(|| anyhow!("Invalid tune.backend-connect-rate '{}', expected a positive number of connects per second", value))?);
+        },
+        "tune.fair-accept" => {
+            let value = single_arg(key, args)?;
+            global.tune_fair_accept = match value {
+                "on" => true,
+                "off" => false,
+                _ => return Err(anyhow!("Invalid tune.fair-accept '{}', expected on or off", value)),
+            }
+        },
+        "src-dn-resolvers" => {
+            let value = single_arg(key, args)?;
+            global.src_dn_resolvers = Some(value.to_string());
+        },
+        "localpeer" => {
+            let value = single_arg(key, args)?;
+            global.localpeer = Some(value.to_string());
+        },
+        "geoip-db" => {
+            let value = single_arg(key, args)?;
+            global.geoip_db = Some(value.to_string());
+        },
+        "geoip-metrics-countries" => {
+            for code in &parts {
+                let country = Country::parse(code)
+                    .ok_or_else(|| anyhow!("Invalid country code '{}' in geoip-metrics-countries, expected two letters", code))?;
+                global.geoip_metrics_countries.push(country.to_string());
+            }
+        },
+        "tune.src-dn.timeout" => {
+            let value = single_arg(key, args)?;
+            global.tune_src_dn_timeout = Some(utils::parse_duration(value)
+                .ok()
+                .filter(|timeout| !timeout.is_zero())
+                .ok_or_else(|| anyhow!("Invalid tune.src-dn.timeout '{}', expected a positive duration", value))?);
+        },
+        "tune.src-dn.maxlookups" => {
+            let value = single_arg(key, args)?;
+            global.tune_src_dn_maxlookups = Some(value.parse().ok().filter(|n| *n > 0)
+                .ok_or_else(|| anyhow!("Invalid tune.src-dn.maxlookups '{}', expected a positive number", value))?);
+        },
+        "tune.src-dn.cache-size" => {
+            let value = single_arg(key, args)?;
+            global.tune_src_dn_cache_size = Some(value.parse().ok().filter(|n| *n > 0)
+                .ok_or_else(|| anyhow!("Invalid tune.src-dn.cache-size '{}', expected a positive number of entries", value))?);
+        },
+        "tune.src-dn.cache-ttl" => {
+            let value = single_arg(key, args)?;
+            global.tune_src_dn_cache_ttl = Some(utils::parse_duration(value)
+                .map_err(|e| anyhow!("Invalid tune.src-dn.cache-ttl '{}': {}", value, e))?);
+        },
+        "tune.connect-race-delay" => {
+            let value = single_arg(key, args)?;
+            global.tune_connect_race_delay = Some(utils::parse_duration(value)
+                .map_err(|e| anyhow!("Invalid tune.connect-race-delay '{}': {}", value, e))?);
+        },
+        "tune.copy-yield-bytes" => {
+            let value = single_arg(key, args)?;
+            global.tune_copy_yield_bytes = Some(utils::parse_size(value)
+                .map_err(|e| anyhow!("Invalid tune.copy-yield-bytes '{}': {}", value, e))?);
+        },
+        "health-events" => {
+            let value = single_arg(key, args)?;
+            global.health_events = match value {
+                "on" => true,
+                "off" => false,
+                _ => return Err(anyhow!("Invalid health-events '{}', expected on or off", value)),
+            }
+        },
+        "warmup-checks" => {
+            let value = single_arg(key, args)?;
+            global.warmup_checks = match value {
+                "on" => true,
+                "off" => false,
+                _ => return Err(anyhow!("Invalid warmup-checks '{}', expected on or off", value)),
+            }
+        },
+        "warmup-timeout" => {
+            let value = single_arg(key, args)?;
+            global.warmup_timeout = Some(utils::parse_duration(value)
+                .map_err(|e| anyhow!("Invalid warmup-timeout '{}': {}", value, e))?);
+        },
+        "pre-stop-delay" => {
+            let value = single_arg(key, args)?;
+            global.pre_stop_delay = Some(utils::parse_duration(value)
+                .map_err(|e| anyhow!("Invalid pre-stop-delay '{}': {}", value, e))?);
+        },
+        "drain-keepalive-delay" => {
+            let value = single_arg(key, args)?;
+            global.drain_keepalive_delay = Some(utils::parse_duration(value)
+                .map_err(|e| anyhow!("Invalid drain-keepalive-delay '{}': {}", value, e))?);
+        },
+        "drain-kill-idle" => {
+            let value = single_arg(key, args)?;
+            global.drain_kill_idle = Some(utils::parse_duration(value).ok().filter(|idle| !idle.is_zero())
+                .ok_or_else(|| anyhow!("Invalid drain-kill-idle '{}', expected a non-zero duration", value))?);
+        },
+        "audit-log" => match parts.as_slice() {
+            [path] => global.audit_log = Some(path.to_string()),
+            [path, "fsync"] => {
+                global.audit_log = Some(path.to_string());
+                global.audit_log_fsync = true;
+            }
+            _ => return Err(anyhow!("audit-log expects <path> [fsync], got '{}'", args.join(" "))),
+        },
+        "strict-limits" => {
+            let value = single_arg(key, args)?;
+            global.strict_limits = match value {
+                "on" => true,
+                "off" => false,
+                _ => return Err(anyhow!("Invalid strict-limits '{}', expected on or off", value)),
+            }
+        },
+        "ulimit-n" => {
+            let value = single_arg(key, args)?;
+            global.ulimit_n = Some(value.parse()
+                .map_err(|_| anyhow!("Invalid ulimit-n '{}'", value))?);
+        },
+        "metrics" => {
+            match parts.as_slice() {
+                ["use-server-id", "on"] => global.option.push(vec!["metrics-use-server-id".to_string()]),
+                ["use-server-id", "off"] => {},
+                ["required", "on"] => {},
+                ["required", "off"] => global.option.push(vec!["metrics-required-off".to_string()]),
+                ["prefix", prefix] => global.option.push(vec!["metrics-prefix".to_string(), prefix.to_string()]),
+                ["label", key, label] => global.option.push(vec!["metrics-label".to_string(), key.to_string(), label.to_string()]),
+                ["max-series-per-backend", max] => match max.parse::<usize>() {
+                    Ok(max) if max > 0 => global.option.push(vec!["metrics-max-series-per-backend".to_string(), max.to_string()]),
+                    _ => return Err(anyhow!("Invalid metrics max-series-per-backend '{}', expected a positive number", max)),
+                },
+                _ => return Err(anyhow!("Invalid metrics directive '{}', expected 'use-server-id on|off', 'required on|off', 'prefix <prefix>', 'label <name> <value>' or 'max-series-per-backend <n>'", args.join(" "))),
+            }
+        },
+        "log" => global.log = Some(args.join(" ")),
+        "user" => {
+            let value = single_arg(key, args)?;
+            global.user = Some(value.to_string());
+        },
+        "group" => {
+            let value = single_arg(key, args)?;
+            global.group = Some(value.to_string());
+        },
+        "daemon" => global.daemon = Some(match parts.as_slice() {
+            [] | ["on" | "true" | "yes"] => true,
+            ["off" | "false" | "no"] => false,
+            _ => single_arg(key, args)?.parse()?,
         }),
-        "pidfile" => global.pidfile = Some(value.to_string()),
-        "ssl-default-bind-ciphers" => global.ssl_default_bind_ciphers = Some(value.to_string()),
-        "ssl-default-bind-options" => global.ssl_default_bind_options = Some(value.to_string()),
+        "pidfile" => {
+            let value = single_arg(key, args)?;
+            global.pidfile = Some(value.to_string());
+        },
+        "ssl-default-bind-ciphers" => {
+            let value = single_arg(key, args)?;
+            global.ssl_default_bind_ciphers = Some(value.to_string());
+        },
+        "ssl-default-bind-options" => global.ssl_default_bind_options = Some(args.join(" ")),
         "stats" => {
-            if value.starts_with("bind") {
-                let parts: Vec<&str> = value.split_whitespace().collect();
-                if parts.len() >= 2 {
+            match parts.as_slice() {
+                ["socket", path, ..] => global.stats_socket = Some(path.to_string()),
+                ["socket"] => return Err(anyhow!("stats socket requires a path")),
+                ["events-uri", path] if path.starts_with('/') => global.option.push(vec!["stats-events-uri".to_string(), path.to_string()]),
+                ["events-uri", ..] => return Err(anyhow!("stats events-uri requires an absolute path")),
+                ["path", path, "disabled"] if path.starts_with('/') => global.option.push(vec!["stats-path".to_string(), path.to_string(), "disabled".to_string()]),
+                ["path", path, handler] if path.starts_with('/') => {
+                    metrics::StatsHandler::parse(handler)?;
+                    global.option.push(vec!["stats-path".to_string(), path.to_string(), handler.to_string()]);
                 }
+                ["path", ..] => return Err(anyhow!("stats path requires an absolute path and a handler")),
+                ["refresh", delay] => {
+                    let delay = utils::parse_duration(delay)?;
+                    if delay.as_secs() == 0 {
+                        return Err(anyhow!("stats refresh must be at least 1s"));
+                    }
+                    global.option.push(vec!["stats-refresh".to_string(), delay.as_secs().to_string()]);
+                }
+                ["refresh", ..] => return Err(anyhow!("stats refresh requires a delay")),
+                ["admin"] | ["admin", "if", "TRUE"] => global.option.push(vec!["stats-admin".to_string()]),
+                ["admin", ..] => return Err(anyhow!("stats admin only supports 'if TRUE'")),
+                ["userlist", userlist] => global.option.push(vec!["stats-userlist".to_string(), userlist.to_string()]),
+                ["userlist", ..] => return Err(anyhow!("stats userlist requires a userlist name")),
+                _ => return Err(UnsupportedDirective.into()),
             }
         },
         "rate-limit" => {
-            let parts: Vec<&str> = value.split_whitespace().collect();
             if parts.len() >= 2 {
                 match parts[0] {
                     "requests-per-second" => {
                         if let Ok(rate) = parts[1].parse::<u32>() {
-                            global.option.push(format!("rate-limit-rps {}", rate));
+                            global.option.push(vec!["rate-limit-rps".to_string(), rate.to_string()]);
                         }
                     },
                     "burst-size" => {
                         if let Ok(burst) = parts[1].parse::<u32>() {
-                            global.option.push(format!("rate-limit-burst {}", burst));
+                            global.option.push(vec!["rate-limit-burst".to_string(), burst.to_string()]);
                         }
                     },
+                    "key" => {
+                        RateLimitKey::parse(parts[1])?;
+                        global.option.push(vec!["rate-limit-key".to_string(), parts[1].to_string()]);
+                    },
+                    "mode" => {
+                        ProtectionMode::parse(parts[1])?;
+                        global.option.push(vec!["rate-limit-mode".to_string(), parts[1].to_string()]);
+                    },
                     _ => {}
                 }
             }
         },
         "ddos-protection" => {
-            let parts: Vec<&str> = value.split_whitespace().collect();
             if parts.len() >= 2 {
                 match parts[0] {
                     "max-requests-per-minute" => {
                         if let Ok(max_req) = parts[1].parse::<u32>() {
-                            global.option.push(format!("ddos-protection max-requests-per-minute {}", max_req));
+                            global.option.push(vec!["ddos-protection".to_string(), "max-requests-per-minute".to_string(), max_req.to_string()]);
                             debug!("Parsed DDoS max-requests-per-minute: {}", max_req);
                         }
                     },
                     "max-connections-per-ip" => {
                         if let Ok(max_conn) = parts[1].parse::<u32>() {
-                            global.option.push(format!("ddos-protection max-connections-per-ip {}", max_conn));
+                            global.option.push(vec!["ddos-protection".to_string(), "max-connections-per-ip".to_string(), max_conn.to_string()]);
                             debug!("Parsed DDoS max-connections-per-ip: {}", max_conn);
                         }
                     },
                     "reset-interval-seconds" => {
-                        if let Ok(interval) = parts[1].parse::<u64>() {
-                            global.option.push(format!("ddos-protection reset-interval-seconds {}", interval));
-                            debug!("Parsed DDoS reset-interval-seconds: {}", interval);
+                        let interval = utils::parse_duration(parts[1])
+                            .map_err(|e| anyhow!("global: invalid 'ddos-protection reset-interval-seconds {}': {}", parts[1], e))?;
+                        if interval.as_secs() == 0 {
+                            return Err(anyhow!("global: ddos-protection reset-interval-seconds must be at least 1s"));
                         }
+                        global.option.push(vec!["ddos-protection".to_string(), "reset-interval-seconds".to_string(), interval.as_secs().to_string()]);
+                        debug!("Parsed DDoS reset-interval-seconds: {}", interval.as_secs());
                     },
                     "suspicious-pattern" => {
                         let patterns: Vec<&str> = parts[1].split(&[',', ' '][..]).filter(|s| !s.trim().is_empty()).map(|s| s.trim()).collect();
                         for pattern in patterns {
-                            global.option.push(format!("ddos-protection suspicious-pattern {}", pattern));
+                            global.option.push(vec!["ddos-protection".to_string(), "suspicious-pattern".to_string(), pattern.to_string()]);
                             debug!("Parsed DDoS suspicious-pattern: {}", pattern);
                         }
                     },
                     "whitelist" => {
                         let ips: Vec<&str> = parts[1].split(&[',', ' '][..]).filter(|s| !s.trim().is_empty()).map(|s| s.trim()).collect();
                         for ip in ips {
-                            global.option.push(format!("ddos-protection whitelist {}", ip));
+                            global.option.push(vec!["ddos-protection".to_string(), "whitelist".to_string(), ip.to_string()]);
                             debug!("Parsed DDoS whitelist: {}", ip);
                         }
                     },
                     "blacklist" => {
                         let ips: Vec<&str> = parts[1].split(&[',', ' '][..]).filter(|s| !s.trim().is_empty()).map(|s| s.trim()).collect();
                         for ip in ips {
-                            global.option.push(format!("ddos-protection blacklist {}", ip));
+                            global.option.push(vec!["ddos-protection".to_string(), "blacklist".to_string(), ip.to_string()]);
                             debug!("Parsed DDoS blacklist: {}", ip);
                         }
                     },
+                    "mode" => {
+                        ProtectionMode::parse(parts[1])?;
+                        global.option.push(vec!["ddos-protection".to_string(), "mode".to_string(), parts[1].to_string()]);
+                    },
                     _ => {}
                 }
             }
         },
         "compression-gzip" => {
-            global.option.push(format!("compression-gzip {}", value));
+            let value = single_arg(key, args)?;
+            global.option.push(vec!["compression-gzip".to_string(), value.to_string()]);
         },
         "compression-brotli" => {
-            global.option.push(format!("compression-brotli {}", value));
+            let value = single_arg(key, args)?;
+            global.option.push(vec!["compression-brotli".to_string(), value.to_string()]);
         },
         "compression-deflate" => {
-            global.option.push(format!("compression-deflate {}", value));
+            let value = single_arg(key, args)?;
+            global.option.push(vec!["compression-deflate".to_string(), value.to_string()]);
         },
         "compression-min-size" => {
+            let value = single_arg(key, args)?;
             if let Ok(size) = value.parse::<usize>() {
-                global.option.push(format!("compression-min-size {}", size));
+                global.option.push(vec!["compression-min-size".to_string(), size.to_string()]);
             }
         },
         "compression-max-size" => {
+            let value = single_arg(key, args)?;
             if let Ok(size) = value.parse::<usize>() {
-                global.option.push(format!("compression-max-size {}", size));
+                global.option.push(vec!["compression-max-size".to_string(), size.to_string()]);
             }
         },
         "compression-level" => {
+            let value = single_arg(key, args)?;
             if let Ok(level) = value.parse::<u32>() {
-                global.option.push(format!("compression-level {}", level));
+                global.option.push(vec!["compression-level".to_string(), level.to_string()]);
             }
         },
-        _ => warn!("Unknown global directive: {}", key),
+        _ => return Err(UnsupportedDirective.into()),
     }
     Ok(())
 }
 
-fn parse_defaults_directive(defaults: &mut DefaultsConfig, key: &str, value: &str) -> Result<()> {
+fn parse_defaults_directive(defaults: &mut DefaultsConfig, key: &str, args: &[String]) -> Result<()> {
+    let parts: Vec<&str> = args.iter().map(String::as_str).collect();
     match key {
-        "mode" => defaults.mode = Some(value.to_string()),
-        "log" => defaults.log = Some(value.to_string()),
-        "option" => defaults.option.push(value.to_string()),
+        "mode" => {
+            let value = single_arg(key, args)?;
+            defaults.mode = Some(value.to_string());
+        },
+        "log" => defaults.log = Some(args.join(" ")),
+        "option" if !Options::known(args) => return Err(UnsupportedDirective.into()),
+        "option" => defaults.option.push(args.to_vec()),
         "timeout" => {
-            let parts: Vec<&str> = value.split_whitespace().collect();
             if parts.len() >= 2 {
                 defaults.timeout.insert(parts[0].to_string(), parts[1].to_string());
-                
-                if defaults.options.is_none() {
-                    defaults.options = Some(crate::options::Options::default());
-                }
-                if let Some(ref mut options) = defaults.options {
-                    if let Err(e) = options.apply_timeout(parts[0], parts[1]) {
-                        warn!("Failed to apply timeout {} {}: {}", parts[0], parts[1], e);
-                    }
-                }
             }
         },
-        "retries" => defaults.retries = Some(value.parse()?),
+        "retries" => {
+            let value = single_arg(key, args)?;
+            defaults.retries = Some(value.parse()?);
+        },
+        "log-format" => defaults.log_format = Some(args.join(" ")),
         "rate-limit" => {
-            let parts: Vec<&str> = value.split_whitespace().collect();
             if parts.len() >= 2 {
                 match parts[0] {
                     "requests-per-second" => {
                         if let Ok(rate) = parts[1].parse::<u32>() {
-                            defaults.option.push(format!("rate-limit-rps {}", rate));
+                            defaults.option.push(vec!["rate-limit-rps".to_string(), rate.to_string()]);
                         }
                     },
                     "burst-size" => {
                         if let Ok(burst) = parts[1].parse::<u32>() {
-                            defaults.option.push(format!("rate-limit-burst {}", burst));
+                            defaults.option.push(vec!["rate-limit-burst".to_string(), burst.to_string()]);
                         }
                     },
                     _ => {}
@@ -472,54 +2258,52 @@ fn parse_defaults_directive(defaults: &mut DefaultsConfig, key: &str, value: &st
             }
         },
         "ddos-protection" => {
-            let parts: Vec<&str> = value.split_whitespace().collect();
             if parts.len() >= 2 {
                 match parts[0] {
                     "max-requests-per-minute" => {
                         if let Ok(max_req) = parts[1].parse::<u32>() {
-                            defaults.option.push(format!("ddos-protection max-requests-per-minute {}", max_req));
+                            defaults.option.push(vec!["ddos-protection".to_string(), "max-requests-per-minute".to_string(), max_req.to_string()]);
                         }
                     },
                     "max-connections-per-ip" => {
                         if let Ok(max_conn) = parts[1].parse::<u32>() {
-                            defaults.option.push(format!("ddos-protection max-connections-per-ip {}", max_conn));
+                            defaults.option.push(vec!["ddos-protection".to_string(), "max-connections-per-ip".to_string(), max_conn.to_string()]);
                         }
                     },
                     "reset-interval-seconds" => {
-                        if let Ok(interval) = parts[1].parse::<u64>() {
-                            defaults.option.push(format!("ddos-protection reset-interval-seconds {}", interval));
-                        }
+                        let interval = utils::parse_duration(parts[1])
+                            .map_err(|e| anyhow!("defaults: invalid 'ddos-protection reset-interval-seconds {}': {}", parts[1], e))?;
+                        defaults.option.push(vec!["ddos-protection".to_string(), "reset-interval-seconds".to_string(), interval.as_secs().to_string()]);
                     },
                     "suspicious-pattern" => {
-                        defaults.option.push(format!("ddos-protection suspicious-pattern {}", parts[1]));
+                        defaults.option.push(vec!["ddos-protection".to_string(), "suspicious-pattern".to_string(), parts[1].to_string()]);
                     },
                     "whitelist" => {
-                        defaults.option.push(format!("ddos-protection whitelist {}", parts[1]));
+                        defaults.option.push(vec!["ddos-protection".to_string(), "whitelist".to_string(), parts[1].to_string()]);
                     },
                     "blacklist" => {
-                        defaults.option.push(format!("ddos-protection blacklist {}", parts[1]));
+                        defaults.option.push(vec!["ddos-protection".to_string(), "blacklist".to_string(), parts[1].to_string()]);
                     },
                     _ => {}
                 }
             }
         },
         "compression" => {
-            let parts: Vec<&str> = value.split_whitespace().collect();
             if parts.len() >= 2 {
                 match parts[0] {
                     "gzip" | "brotli" | "deflate" => {
                         if parts[1] == "enabled" {
-                            defaults.option.push(format!("compression-{} enabled", parts[0]));
+                            defaults.option.push(vec![format!("compression-{}", parts[0]), "enabled".to_string()]);
                         }
                     },
                     "min-size" => {
                         if let Ok(size) = parts[1].parse::<usize>() {
-                            defaults.option.push(format!("compression-min-size {}", size));
+                            defaults.option.push(vec!["compression-min-size".to_string(), size.to_string()]);
                         }
                     },
                     "max-size" => {
                         if let Ok(size) = parts[1].parse::<usize>() {
-                            defaults.option.push(format!("compression-max-size {}", size));
+                            defaults.option.push(vec!["compression-max-size".to_string(), size.to_string()]);
                         }
                     },
                     _ => {}
@@ -527,259 +2311,399 @@ fn parse_defaults_directive(defaults: &mut DefaultsConfig, key: &str, value: &st
             }
         },
         "http2" | "http3" => {
-            let parts: Vec<&str> = value.split_whitespace().collect();
             if parts.len() >= 1 {
                 if parts[0] == "enabled" {
-                    defaults.option.push(format!("{}-enabled", key));
+                    defaults.option.push(vec![format!("{}-enabled", key)]);
                 }
                 if parts.len() >= 2 {
-                    defaults.option.push(format!("{}-{} {}", key, parts[0], parts[1]));
+                    defaults.option.push(vec![format!("{}-{}", key, parts[0]), parts[1].to_string()]);
                 }
             }
         },
         "hot-reload" => {
-            let parts: Vec<&str> = value.split_whitespace().collect();
             if parts.len() >= 1 {
                 if parts[0] == "enabled" {
-                    defaults.option.push("hot-reload-enabled".to_string());
+                    defaults.option.push(vec!["hot-reload-enabled".to_string()]);
                 } else if parts.len() >= 2 {
-                    defaults.option.push(format!("hot-reload-{} {}", parts[0], parts[1]));
+                    defaults.option.push(vec![format!("hot-reload-{}", parts[0]), parts[1].to_string()]);
                 }
             }
         },
-        _ => warn!("Unknown defaults directive: {}", key),
+        _ => return Err(UnsupportedDirective.into()),
     }
     
     Ok(())
 }
 
-fn parse_frontend_directive(frontend: &mut FrontendConfig, key: &str, value: &str) -> Result<()> {
+fn parse_frontend_directive(frontend: &mut FrontendConfig, key: &str, args: &[String], span: &SourceSpan) -> Result<()> {
+    let parts: Vec<&str> = args.iter().map(String::as_str).collect();
     match key {
-        "bind" => {
-            let bind_value = if value.trim().starts_with("*:") {
-                let port = &value.trim()[2..];
-                format!("0.0.0.0:{}", port)
-            } else {
-                value.to_string()
-            };
-            frontend.bind.push(bind_value)
+        "bind" => frontend.bind.push(BindConfig::parse(args)
+            .map_err(|e| anyhow!("Frontend '{}' has invalid bind '{}': {}", frontend.name, args.join(" "), e))?),
+        "mode" => {
+            let value = single_arg(key, args)?;
+            frontend.mode = Some(value.to_string());
+        },
+        "default_backend" => {
+            let value = single_arg(key, args)?;
+            frontend.default_backend = Some(value.to_string());
+        },
+        "bwlim-in" => frontend.bwlim_in = Some(BwlimConfig::parse(args)
+            .map_err(|e| anyhow!("Frontend '{}' has invalid bwlim-in '{}': {}", frontend.name, args.join(" "), e))?),
+        "bwlim-out" => frontend.bwlim_out = Some(BwlimConfig::parse(args)
+            .map_err(|e| anyhow!("Frontend '{}' has invalid bwlim-out '{}': {}", frontend.name, args.join(" "), e))?),
+        "maxconn" => {
+            let value = single_arg(key, args)?;
+            frontend.maxconn = Some(value.parse().ok().filter(|n| *n > 0)
+                .ok_or_else(|| anyhow!("Frontend '{}' has invalid maxconn '{}', expected a positive number", frontend.name, value))?);
+        },
+        "log-format" => frontend.log_format = Some(args.join(" ")),
+        "on-no-backend" => {
+            let value = single_arg(key, args)?;
+            match value {
+                "reject" | "silent-drop" | "tarpit" => frontend.on_no_backend = Some(value.to_string()),
+                _ => return Err(anyhow!("Frontend '{}' has invalid on-no-backend '{}', expected reject, silent-drop or tarpit", frontend.name, value)),
+            }
+        },
+        "connect-auth" => match parts.as_slice() {
+            [userlist] => frontend.connect_auth = Some(userlist.to_string()),
+            _ => return Err(anyhow!("Frontend '{}' has invalid connect-auth '{}', expected a userlist name", frontend.name, args.join(" "))),
         },
-        "mode" => frontend.mode = Some(value.to_string()),
-        "default_backend" => frontend.default_backend = Some(value.to_string()),
         "acl" => {
-            let parts: Vec<&str> = value.split_whitespace().collect();
             if parts.len() >= 2 {
                 frontend.acl.push(AclConfig {
                     name: parts[0].to_string(),
-                    criterion: parts[1..].join(" "),
+                    criterion: args[1..].to_vec(),
+                    span: span.clone(),
                 });
             }
         },
         "use_backend" => {
-            let parts: Vec<&str> = value.split_whitespace().collect();
-            if parts.len() >= 2 {
+            if let Some((backend, condition)) = parts.split_first() {
                 frontend.use_backend.push(UseBackendConfig {
-                    backend: parts[0].to_string(),
-                    condition: Some(parts[1..].join(" ")),
+                    backend: backend.to_string(),
+                    condition: (!condition.is_empty()).then(|| condition.iter().map(|word| word.to_string()).collect()),
+                    span: span.clone(),
                 });
             }
         },
-        "option" => frontend.option.push(value.to_string()),
+        "option" if !Options::known(args) => return Err(UnsupportedDirective.into()),
+        "option" => frontend.option.push(args.to_vec()),
+        "rate-limit" => {
+            match parts.as_slice() {
+                ["sessions", limit] => frontend.rate_limit_sessions = Some(limit.parse()
+                    .map_err(|_| anyhow!("Frontend '{}' has invalid rate-limit sessions '{}'", frontend.name, limit))?),
+                _ => return Err(UnsupportedDirective.into()),
+            }
+        },
+        "tcp-request" => {
+            match parts.as_slice() {
+                ["connection", action, condition @ ..] => {
+                    frontend.tcp_request_connection.push(TcpRequestRuleConfig {
+                        action: action.to_string(),
+                        condition: if condition.is_empty() { None } else { Some(condition.iter().map(|word| word.to_string()).collect()) },
+                    });
+                },
+                ["inspect-delay", delay] => frontend.tcp_request_inspect_delay = Some(delay.to_string()),
+                _ => return Err(UnsupportedDirective.into()),
+            }
+        },
         "timeout" => {
-            let parts: Vec<&str> = value.split_whitespace().collect();
             if parts.len() >= 2 {
                 frontend.timeout.insert(parts[0].to_string(), parts[1].to_string());
-                
-                if frontend.options.is_none() {
-                    frontend.options = Some(crate::options::Options::default());
-                }
-                if let Some(ref mut options) = frontend.options {
-                    if let Err(e) = options.apply_timeout(parts[0], parts[1]) {
-                        warn!("Failed to apply timeout {} {}: {}", parts[0], parts[1], e);
-                    }
-                }
             }
         },
-        "http-request" => {
-            let parts: Vec<&str> = value.split_whitespace().collect();
-            if parts.len() >= 3 {
-                match parts[0] {
-                    "set-header" | "add-header" => {
-                        frontend.option.push(format!("http-request-{}-header {} {}", parts[0], parts[1], parts[2]));
-                    },
-                    _ => {},
-                }
+        "http-request" if parts.first() == Some(&"auth") => {
+            let (realm, condition) = match &parts[1..] {
+                ["realm", realm, condition @ ..] => (Some(realm.to_string()), condition),
+                condition => (None, condition),
+            };
+            if !condition.is_empty() && !matches!(condition[0], "if" | "unless") {
+                return Err(anyhow!("Frontend '{}' has invalid http-request auth '{}', expected 'http-request auth [realm <realm>] [if|unless <condition>]'", frontend.name, args.join(" ")));
             }
+            frontend.http_request_auth.push(HttpRequestAuthConfig {
+                realm,
+                condition: (!condition.is_empty()).then(|| condition.iter().map(|word| word.to_string()).collect()),
+                span: span.clone(),
+            });
         },
-        "http-response" => {
-            let parts: Vec<&str> = value.split_whitespace().collect();
-            if parts.len() >= 3 {
-                match parts[0] {
-                    "set-header" | "add-header" => {
-                        frontend.option.push(format!("http-response-{}-header {} {}", parts[0], parts[1], parts[2]));
-                    },
-                    _ => {},
-                }
-            }
+        "http-request" if parts.first() == Some(&"return") => {
+            let rule = HttpReturnConfig::parse(&parts[1..], span)
+                .map_err(|e| anyhow!("Frontend '{}' has invalid http-request return: {}", frontend.name, e))?;
+            frontend.http_request_return.push(rule);
+        },
+        "http-request" => match parts.as_slice() {
+            [action @ ("set-header" | "add-header"), name, value, ..] => {
+                frontend.option.push(vec![format!("http-request-{}-header", action), name.to_string(), value.to_string()]);
+            },
+            _ => return Err(UnsupportedDirective.into()),
+        },
+        "http-response" => match parts.as_slice() {
+            [action @ ("set-header" | "add-header"), name, value, ..] => {
+                frontend.option.push(vec![format!("http-response-{}-header", action), name.to_string(), value.to_string()]);
+            },
+            _ => return Err(UnsupportedDirective.into()),
         },
         "compression-gzip" => {
-            frontend.option.push(format!("compression-gzip {}", value));
+            let value = single_arg(key, args)?;
+            frontend.option.push(vec!["compression-gzip".to_string(), value.to_string()]);
         },
         "compression-brotli" => {
-            frontend.option.push(format!("compression-brotli {}", value));
+            let value = single_arg(key, args)?;
+            frontend.option.push(vec!["compression-brotli".to_string(), value.to_string()]);
         },
         "compression-deflate" => {
-            frontend.option.push(format!("compression-deflate {}", value));
+            let value = single_arg(key, args)?;
+            frontend.option.push(vec!["compression-deflate".to_string(), value.to_string()]);
         },
         "compression-min-size" => {
+            let value = single_arg(key, args)?;
             if let Ok(size) = value.parse::<usize>() {
-                frontend.option.push(format!("compression-min-size {}", size));
+                frontend.option.push(vec!["compression-min-size".to_string(), size.to_string()]);
             }
         },
         "compression-max-size" => {
+            let value = single_arg(key, args)?;
             if let Ok(size) = value.parse::<usize>() {
-                frontend.option.push(format!("compression-max-size {}", size));
+                frontend.option.push(vec!["compression-max-size".to_string(), size.to_string()]);
             }
         },
         "compression-level" => {
+            let value = single_arg(key, args)?;
             if let Ok(level) = value.parse::<u32>() {
-                frontend.option.push(format!("compression-level {}", level));
+                frontend.option.push(vec!["compression-level".to_string(), level.to_string()]);
             }
         },
-        _ => warn!("Unknown frontend directive: {}", key),
+        _ => return Err(UnsupportedDirective.into()),
     }
     
     Ok(())
 }
 
-fn parse_backend_directive(backend: &mut BackendConfig, key: &str, value: &str) -> Result<()> {
+fn parse_backend_directive(backend: &mut BackendConfig, key: &str, args: &[String], span: &SourceSpan) -> Result<()> {
+    let parts: Vec<&str> = args.iter().map(String::as_str).collect();
     match key {
-        "mode" => backend.mode = Some(value.to_string()),
-        "balance" => {
-            let algorithm = value.split('#').next().unwrap_or(value).trim();
-            backend.balance = Some(algorithm.to_string());
+        "mode" => {
+            let value = single_arg(key, args)?;
+            backend.mode = Some(value.to_string());
         },
+        "balance" => {
+            let value = single_arg(key, args)?;
+            if value.starts_with("random(") && !matches!(value, "random(1)" | "random(2)") {
+                return Err(anyhow!("Invalid balance '{}', random takes 1 or 2 draws", value));
+            }
+            if !matches!(value, "roundrobin" | "leastconn" | "random" | "random(1)" | "random(2)") {
+                return Err(UnsupportedDirective.into());
+            }
+            backend.balance = Some(value.to_string());
+        }
         "server" => {
-            let parts: Vec<&str> = value.split_whitespace().collect();
             if parts.len() >= 2 {
-                let server_name = parts[0].to_string();
-                let server_addr = parts[1].to_string();
-                let server_name_clone = server_name.clone();
-                
-                let (address, port) = if server_addr.contains(':') {
-                    let addr_parts: Vec<&str> = server_addr.split(':').collect();
-                    if addr_parts.len() == 2 {
-                        (addr_parts[0].to_string(), addr_parts[1].parse().unwrap_or(80))
-                    } else {
-                        (server_addr, 80)
-                    }
-                } else {
-                    (server_addr, 80)
-                };
-                
-                let mut server = ServerConfig {
-                    name: server_name,
-                    address,
-                    port,
-                    weight: Some(1),
-                    maxconn: None,
-                    check: None,
-                    inter: None,
-                    rise: None,
-                    fall: None,
-                    backup: None,
-                    disabled: None,
-                };
-
-                let mut i = 2;
-                while i < parts.len() {
-                    let part = parts[i];
-                    match part {
-                        "weight" => {
-                            if i + 1 < parts.len() {
-                                let weight_str = parts[i + 1];
-                                server.weight = Some(weight_str.parse().unwrap_or(1));
-                                tracing::debug!("Parsed weight for server {}: {}", server_name_clone, server.weight.unwrap());
-                                i += 2;
-                            }
-                            i += 1;
-                        },
-                        "maxconn" => {
-                            if i + 1 < parts.len() {
-                                server.maxconn = Some(parts[i + 1].parse().unwrap_or(1000));
-                                i += 2;
-                            }
-                            i += 1;
-                        },
-                        "check" => {
-                            server.check = Some(true);
-                            i += 1;
-                        },
-                        "inter" => {
-                            if i + 1 < parts.len() {
-                                server.inter = Some(parts[i + 1].to_string());
-                                i += 2;
-                            }
-                            i += 1;
-                        },
-                        "rise" => {
-                            if i + 1 < parts.len() {
-                                server.rise = Some(parts[i + 1].parse().unwrap_or(2));
-                                i += 2;
-                            }
-                            i += 1;
-                        },
-                        "fall" => {
-                            if i + 1 < parts.len() {
-                                server.fall = Some(parts[i + 1].parse().unwrap_or(3));
-                                i += 2;
-                            }
-                            i += 1;
-                        },
-                        "backup" => {
-                            server.backup = Some(true);
-                            i += 1;
-                        },
-                        "disabled" => {
-                            server.disabled = Some(true);
-                            i += 1;
-                        },
-                        _ => {
-                            i += 1;
-                        },
-                    }
-                }
-
-                backend.server.push(server);
+                backend.server.push(ServerConfig::parse(&parts, span)?);
+            }
+        },
+        "option" if !Options::known(args) => return Err(UnsupportedDirective.into()),
+        "option" => backend.option.push(args.to_vec()),
+        "tcp-check" | "http-check" => backend.option.push(std::iter::once(key.to_string()).chain(args.iter().cloned()).collect()),
+        "retries" => {
+            let value = single_arg(key, args)?;
+            backend.retries = Some(value.parse()?);
+        },
+        "fullconn" => {
+            let value = single_arg(key, args)?;
+            backend.fullconn = Some(value.parse()?);
+        },
+        "on-fullconn" => {
+            let value = single_arg(key, args)?;
+            backend.on_fullconn = Some(value.to_string());
+        },
+        "connect-rate" => {
+            let value = single_arg(key, args)?;
+            backend.connect_rate = Some(value.parse().ok().filter(|n| *n > 0)
+                .ok_or_else(|| anyhow!("Backend '{}' has invalid connect-rate '{}', expected a positive number of connects per second", backend.name, value))?);
+        },
+        "resolve-on-down-interval" => {
+            let value = single_arg(key, args)?;
+            backend.resolve_on_down_interval = Some(utils::parse_duration(value).ok().filter(|interval| !interval.is_zero())
+                .ok_or_else(|| anyhow!("Backend '{}' has invalid resolve-on-down-interval '{}', expected a positive duration", backend.name, value))?);
+        },
+        "alert-threshold" => {
+            let value = single_arg(key, args)?;
+            backend.alert_threshold = Some(value.parse()
+                .map_err(|_| anyhow!("Backend '{}' has invalid alert-threshold '{}', expected a ratio between 0 and 1", backend.name, value))?);
+        },
+        "balance-adaptive" => {
+            let value = single_arg(key, args)?;
+            backend.balance_adaptive = match value {
+                "on" => true,
+                "off" => false,
+                _ => return Err(anyhow!("Invalid balance-adaptive '{}', expected on or off", value)),
+            }
+        },
+        "adaptive-metric" => {
+            let value = single_arg(key, args)?;
+            backend.adaptive_metric = Some(value.to_string());
+        },
+        "adaptive-half-life" => {
+            let value = single_arg(key, args)?;
+            backend.adaptive_half_life = Some(utils::parse_duration(value).ok().filter(|half_life| !half_life.is_zero())
+                .ok_or_else(|| anyhow!("Backend '{}' has invalid adaptive-half-life '{}', expected a positive duration", backend.name, value))?);
+        },
+        "http-send-name-header" | "http-send-backend-header" => {
+            let value = single_arg(key, args)?;
+            if !inspect::is_token(value.as_bytes()) {
+                return Err(anyhow!("Backend '{}' has invalid {} '{}', expected a header name", backend.name, key, value));
+            }
+            match key {
+                "http-send-name-header" => backend.send_name_header = Some(value.to_string()),
+                _ => backend.send_backend_header = Some(value.to_string()),
+            }
+        },
+        "http-request" if parts.first() == Some(&"return") => {
+            let rule = HttpReturnConfig::parse(&parts[1..], span)
+                .map_err(|e| anyhow!("Backend '{}' has invalid http-request return: {}", backend.name, e))?;
+            if rule.condition.is_some() {
+                return Err(anyhow!("Backend '{}' has a conditional http-request return, conditions are only supported in frontends", backend.name));
+            }
+            backend.http_request_return = Some(rule);
+        },
+        "require-check" => {
+            let value = single_arg(key, args)?;
+            backend.require_check = match value {
+                "on" => true,
+                "off" => false,
+                _ => return Err(anyhow!("Invalid require-check '{}', expected on or off", value)),
+            }
+        },
+        "bwlim-in" => backend.bwlim_in = Some(BwlimConfig::parse(args)
+            .map_err(|e| anyhow!("Backend '{}' has invalid bwlim-in '{}': {}", backend.name, args.join(" "), e))?),
+        "bwlim-out" => backend.bwlim_out = Some(BwlimConfig::parse(args)
+            .map_err(|e| anyhow!("Backend '{}' has invalid bwlim-out '{}': {}", backend.name, args.join(" "), e))?),
+        "persist-weight-0" => {
+            let value = single_arg(key, args)?;
+            backend.persist_weight_zero = match value {
+                "on" => true,
+                "off" => false,
+                _ => return Err(anyhow!("Invalid persist-weight-0 '{}', expected on or off", value)),
+            }
+        },
+        "retry-on" => {
+            let value = single_arg(key, args)?;
+            backend.retry_on_conn_failure = match value {
+                "conn-failure" => true,
+                "none" => false,
+                _ => return Err(anyhow!("Invalid retry-on '{}', expected conn-failure or none", value)),
+            }
+        },
+        "stick-table" => backend.stick_table = Some(parse_stick_table(args)?),
+        "stick" => {
+            match parts.as_slice() {
+                ["on", pattern] => backend.stick_on = Some(pattern.to_string()),
+                _ => return Err(anyhow!("Invalid stick directive '{}', expected 'stick on <pattern>'", args.join(" "))),
             }
         },
-        "option" => backend.option.push(value.to_string()),
-        "tcp-check" => backend.option.push(value.to_string()),
-        "retries" => backend.retries = Some(value.parse()?),
         "timeout" => {
-            let parts: Vec<&str> = value.split_whitespace().collect();
             if parts.len() >= 2 {
                 backend.timeout.insert(parts[0].to_string(), parts[1].to_string());
-                
-                if backend.options.is_none() {
-                    backend.options = Some(crate::options::Options::default());
-                }
-                if let Some(ref mut options) = backend.options {
-                    if let Err(e) = options.apply_timeout(parts[0], parts[1]) {
-                        warn!("Failed to apply timeout {} {}: {}", parts[0], parts[1], e);
-                    }
-                }
             }
         },
-        _ => warn!("Unknown backend directive: {}", key),
+        _ => return Err(UnsupportedDirective.into()),
     }
     
     Ok(())
 }
 
+fn parse_stick_table(args: &[String]) -> Result<StickTableConfig> {
+    let mut table = StickTableConfig {
+        size: 0,
+        expire: "30m".to_string(),
+        peers: None,
+    };
+
+    let parts: Vec<&str> = args.iter().map(String::as_str).collect();
+    for pair in parts.chunks(2) {
+        match pair {
+            ["type", _] | ["len", _] => {},
+            ["size", size] => {
+                let (digits, multiplier) = match size.char_indices().last() {
+                    Some((i, 'k')) | Some((i, 'K')) => (&size[..i], 1_000),
+                    Some((i, 'm')) | Some((i, 'M')) => (&size[..i], 1_000_000),
+                    _ => (*size, 1),
+                };
+                table.size = digits.parse::<usize>().ok()
+                    .and_then(|n| n.checked_mul(multiplier))
+                    .ok_or_else(|| anyhow!("Invalid stick-table size '{}'", size))?;
+            },
+            ["expire", expire] => table.expire = expire.to_string(),
+            ["peers", peers] => table.peers = Some(peers.to_string()),
+            _ => return Err(anyhow!("Invalid stick-table argument '{}'", pair.join(" "))),
+        }
+    }
+
+    if table.size == 0 {
+        return Err(anyhow!("stick-table requires a non-zero size"));
+    }
+
+    Ok(table)
+}
+
+fn required_capability(section: &str, key: &str, args: &[String]) -> Option<(String, Capability)> {
+    let kind = section.split_whitespace().next().unwrap_or_default();
+    let first = args.first().map(String::as_str).unwrap_or_default();
+    let directive = |words: usize| std::iter::once(key)
+        .chain(args.iter().take(words).map(String::as_str))
+        .collect::<Vec<_>>()
+        .join(" ");
+    match (kind, key, first) {
+        ("frontend", "http-request", "auth" | "return") => None,
+        ("frontend", "http-request" | "http-response", _) => Some((directive(1), Capability::Http)),
+        ("global" | "frontend", _, _) if key.starts_with("compression-") => Some((directive(0), Capability::Http)),
+        ("defaults", "compression", _) => Some((directive(1), Capability::Http)),
+        ("defaults", "http2", _) => Some((directive(0), Capability::Http)),
+        ("defaults", "http3", _) => Some((directive(0), Capability::Udp)),
+        ("defaults" | "frontend" | "backend", "option", "forwardfor" | "httplog" | "httpclose" | "http-server-close" | "http-keep-alive") => {
+            Some((directive(1), Capability::Http))
+        },
+        ("frontend", "bind", _) if args.iter().any(|arg| arg == "ssl") => Some(("bind ssl".to_string(), Capability::Tls)),
+        _ => None,
+    }
+}
+
+fn section_options(option: &[Vec<String>], mode: &str, timeouts: &[(&str, &HashMap<String, String>)]) -> Result<Options> {
+    let mut options = Options::from_tokens(option, mode)?;
+    for (section, timeout) in timeouts {
+        for (timeout_type, value) in timeout.iter() {
+            options.apply_timeout(timeout_type, value)
+                .map_err(|e| anyhow!("{}: invalid 'timeout {} {}': {}", section, timeout_type, value, e))?;
+        }
+    }
+    Ok(options)
+}
+
+// The argument of a directive that takes exactly one, so that extra words are
+// reported rather than glued back together.
+fn single_arg<'a>(key: &str, args: &'a [String]) -> Result<&'a str> {
+    match args {
+        [value] => Ok(value),
+        [] => Err(anyhow!("'{}' requires an argument", key)),
+        _ => Err(anyhow!("'{}' expects a single argument, got '{}'", key, args.join("' '"))),
+    }
+}
+
+// The value of a stored `<name> <value>` option.
+fn option_value<'a>(option: &'a [String], name: &str) -> Option<&'a str> {
+    match option {
+        [option, value] if option == name => Some(value),
+        _ => None,
+    }
+}
+
 fn create_health_check_config(backend: &BackendConfig) -> Option<HealthCheckConfig> {
     let mut interval = "2s".to_string();
-    let timeout = "1s".to_string();
+    let timeout = backend.options.as_ref()
+        .and_then(|options| options.general_options.timeout_check)
+        .map(|ms| format!("{}ms", ms))
+        .unwrap_or_else(|| "1s".to_string());
     let mut rise = 2;
     let mut fall = 3;
     
@@ -811,10 +2735,18 @@ fn create_health_check_config(backend: &BackendConfig) -> Option<HealthCheckConf
     }
 }
 
+impl GlobalConfig {
+    pub fn local_peer_name(&self) -> String {
+        self.localpeer.clone().unwrap_or_else(utils::hostname)
+    }
+}
+
 impl Default for GlobalConfig {
     fn default() -> Self {
         Self {
             maxconn: Some(4096),
+            keep_runtime_maxconn: false,
+            strict_limits: false,
             log: Some("stdout".to_string()),
             user: None,
             group: None,
@@ -822,6 +2754,35 @@ impl Default for GlobalConfig {
             pidfile: None,
             ssl_default_bind_ciphers: Some("EECDH+AESGCM:EDH+AESGCM".to_string()),
             ssl_default_bind_options: Some("no-sslv3".to_string()),
+            stats_socket: None,
+            tarpit_maxconn: Some(1000),
+            bind_range_max: Some(100),
+            ulimit_n: None,
+            tune_maxcheckconn: None,
+            tune_inspect_bufsize: None,
+            tune_inspect_overflow: None,
+            warmup_checks: false,
+            health_events: false,
+            warmup_timeout: None,
+            pre_stop_delay: None,
+            drain_keepalive_delay: None,
+            drain_kill_idle: None,
+            audit_log: None,
+            audit_log_fsync: false,
+            tune_connect_race_delay: None,
+            tune_copy_yield_bytes: None,
+            tune_log_burst: None,
+            tune_log_interval: None,
+            tune_fair_accept: false,
+            tune_backend_connect_rate: None,
+            src_dn_resolvers: None,
+            tune_src_dn_timeout: None,
+            tune_src_dn_maxlookups: None,
+            tune_src_dn_cache_size: None,
+            tune_src_dn_cache_ttl: None,
+            geoip_db: None,
+            geoip_metrics_countries: Vec::new(),
+            localpeer: None,
             option: Vec::new(),
         }
     }
@@ -832,10 +2793,11 @@ impl Default for DefaultsConfig {
         Self {
             mode: Some("tcp".to_string()),
             log: Some("global".to_string()),
-            option: vec!["dontlognull".to_string()],
+            option: vec![vec!["dontlognull".to_string()]],
             timeout: HashMap::new(),
             retries: Some(3),
             options: None,
+            log_format: None,
         }
     }
 }
@@ -844,8 +2806,16 @@ impl Default for MetricsConfig {
     fn default() -> Self {
         Self {
             enabled: true,
-            bind: Some("0.0.0.0:9090".to_string()),
-            path: Some("/metrics".to_string()),
+            binds: vec![StatsBindConfig::new("127.0.0.1:9090")],
+            paths: metrics::default_stats_paths(),
+            refresh_secs: None,
+            admin: false,
+            auth: None,
+            use_server_id: false,
+            max_series_per_backend: None,
+            required: true,
+            prefix: None,
+            labels: Vec::new(),
         }
     }
 }
@@ -855,6 +2825,8 @@ pub struct RateLimitConfig {
     pub requests_per_second: u32,
     pub burst_size: u32,
     pub window_size: u64,
+    pub key: String,
+    pub mode: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -865,6 +2837,7 @@ pub struct DdosProtectionConfig {
     pub suspicious_patterns: Vec<String>,
     pub whitelist: Vec<String>,
     pub blacklist: Vec<String>,
+    pub mode: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -885,13 +2858,39 @@ pub struct CompressionConfig {
 }
 
 impl Config {
+    fn parse_stats_paths(options: &[Vec<String>]) -> Result<Vec<(String, metrics::StatsHandler)>> {
+        let mut declared: Vec<(String, Option<metrics::StatsHandler>)> = Vec::new();
+        let events_uri = options.iter()
+            .find_map(|o| option_value(o, "stats-events-uri"))
+            .map(|path| (path.to_string(), Some(metrics::StatsHandler::Events)));
+        for (path, handler) in events_uri.into_iter().chain(options.iter()
+            .filter_map(|o| match o.as_slice() {
+                [option, path, handler] if option == "stats-path" => Some((path.clone(), metrics::StatsHandler::parse(handler).ok())),
+                _ => None,
+            }))
+        {
+            if declared.iter().any(|(other, _)| *other == path) {
+                return Err(anyhow!("stats path '{}' is declared twice", path));
+            }
+            declared.push((path, handler));
+        }
+
+        let mut paths: Vec<(String, metrics::StatsHandler)> = metrics::default_stats_paths().into_iter()
+            .filter(|(path, handler)| !declared.iter().any(|(other, other_handler)| other == path || *other_handler == Some(*handler)))
+            .collect();
+        paths.extend(declared.into_iter().filter_map(|(path, handler)| handler.map(|handler| (path, handler))));
+        Ok(paths)
+    }
+
     fn parse_rate_limit_config(config: &mut Config) -> Result<()> {
         let mut requests_per_second = None;
         let mut burst_size = None;
+        let mut key = "src".to_string();
+        let mut mode = "enforce".to_string();
         let window_size = 1;
 
         for option in &config.global.option {
-            let parts: Vec<&str> = option.split_whitespace().collect();
+            let parts: Vec<&str> = option.iter().map(String::as_str).collect();
             if parts.len() >= 2 {
                 match parts[0] {
                     "rate-limit-rps" => {
@@ -904,6 +2903,8 @@ impl Config {
                             burst_size = Some(burst);
                         }
                     },
+                    "rate-limit-key" => key = parts[1].to_string(),
+                    "rate-limit-mode" => mode = parts[1].to_string(),
                     _ => {},
                 }
             }
@@ -914,8 +2915,10 @@ impl Config {
                 requests_per_second: rps,
                 burst_size: burst,
                 window_size,
+                key: key.clone(),
+                mode: mode.clone(),
             });
-            info!("Rate limiting configured: {} req/s, burst: {}", rps, burst);
+            info!("Rate limiting configured: {} req/s, burst: {}, key: {}, mode: {}", rps, burst, key, mode);
         }
 
         Ok(())
@@ -928,9 +2931,10 @@ impl Config {
         let mut suspicious_patterns = Vec::new();
         let mut whitelist = Vec::new();
         let mut blacklist = Vec::new();
+        let mut mode = "enforce".to_string();
 
         for option in &config.global.option {
-            let parts: Vec<&str> = option.split_whitespace().collect();
+            let parts: Vec<&str> = option.iter().map(String::as_str).collect();
             if parts.len() >= 2 {
                 match parts[0] {
                     "ddos-protection" => {
@@ -960,6 +2964,9 @@ impl Config {
                                 "blacklist" => {
                                     blacklist.push(parts[2].to_string());
                                 },
+                                "mode" => {
+                                    mode = parts[2].to_string();
+                                },
                                 _ => {}
                             }
                         }
@@ -976,11 +2983,38 @@ impl Config {
             suspicious_patterns,
             whitelist,
             blacklist,
+            mode,
         });
 
         Ok(())
     }
 
+    fn parse_hot_reload_config(config: &mut Config) {
+        let mut enabled = false;
+        let mut watch_interval = 1;
+
+        for option in &config.defaults.option {
+            let parts: Vec<&str> = option.iter().map(String::as_str).collect();
+            match parts.as_slice() {
+                ["hot-reload-enabled"] => enabled = true,
+                ["hot-reload-interval", interval] => {
+                    if let Ok(interval) = interval.parse::<u64>() {
+                        watch_interval = interval;
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        if enabled {
+            config.hot_reload = Some(HotReloadConfig {
+                enabled,
+                watch_interval,
+            });
+            info!("Hot reload enabled");
+        }
+    }
+
     fn parse_compression_config(config: &mut Config) -> Result<()> {
         let mut gzip_enabled = false;
         let mut brotli_enabled = false;
@@ -997,7 +3031,7 @@ impl Config {
         ];
 
         for option in &config.global.option {
-            let parts: Vec<&str> = option.split_whitespace().collect();
+            let parts: Vec<&str> = option.iter().map(String::as_str).collect();
             if parts.len() >= 2 {
                 match parts[0] {
                     "compression-gzip" => gzip_enabled = parts[1] == "enabled",
@@ -1039,3 +3073,204 @@ impl Config {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::HttpCheckExpect;
+
+    fn parse(content: &str) -> Result<Config> {
+        Config::from_haproxy_config(content, None)
+    }
+
+    fn backend_expect(directives: &str) -> Result<Vec<HttpCheckExpect>> {
+        let config = parse(&format!("backend be\n    mode http\n    option httpchk GET /health\n{directives}    server s1 127.0.0.1:8080 check\n"))?;
+        let options = config.backends[0].options.as_ref().expect("backend options are resolved");
+        Ok(options.http_options.httpchk.as_ref().expect("httpchk is set").expect.clone())
+    }
+
+    #[test]
+    fn http_check_patterns_keep_quoted_spaces() {
+        let expect = backend_expect("    http-check expect string \"status ok\"\n    http-check expect rstring '^up( |$)'\n").unwrap();
+        assert_eq!(expect, [HttpCheckExpect::String("status ok".to_string()), HttpCheckExpect::Rstring("^up( |$)".to_string())]);
+    }
+
+    #[test]
+    fn http_check_rejects_several_patterns() {
+        let error = backend_expect("    http-check expect string status ok\n").unwrap_err();
+        assert!(error.to_string().contains("http-check expect string: expected exactly one pattern"), "{error}");
+    }
+
+    #[test]
+    fn options_are_stored_as_tokens() {
+        let config = parse("backend be\n    option redispatch 2\n    http-check expect status 200-299\n    server s1 127.0.0.1:8080\n").unwrap();
+        assert_eq!(config.backends[0].option, [vec!["redispatch", "2"], vec!["http-check", "expect", "status", "200-299"]]);
+    }
+
+    #[test]
+    fn rule_conditions_are_stored_as_tokens() {
+        let config = parse(concat!(
+            "frontend web\n",
+            "    bind 127.0.0.1:8080\n",
+            "    acl api path_beg /api\n",
+            "    acl blocked src 10.0.0.0/8\n",
+            "    use_backend be if api !blocked || \"api\"\n",
+            "    tcp-request connection reject unless !blocked\n",
+            "    default_backend be\n",
+            "backend be\n",
+            "    server s1 127.0.0.1:8081\n",
+        )).unwrap();
+        let frontend = &config.frontends[0];
+        assert_eq!(frontend.use_backend[0].condition.as_deref(), Some(&["if", "api", "!blocked", "||", "api"].map(String::from)[..]));
+        assert_eq!(frontend.tcp_request_connection[0].condition.as_deref(), Some(&["unless", "!blocked"].map(String::from)[..]));
+    }
+
+    #[test]
+    fn transparent_binds_need_linux() {
+        let config = parse("frontend web\n    bind 127.0.0.1:15001 transparent\n    default_backend be\n\n\
+                            backend be\n    option use-original-dst\n    server s1 127.0.0.1:8080\n").unwrap();
+        assert!(config.frontends[0].bind[0].transparent);
+        let validated = config.validate(false);
+        assert_eq!(validated.is_ok(), cfg!(target_os = "linux"));
+        if let Err(e) = validated {
+            assert!(e.to_string().contains("'transparent' needs SO_ORIGINAL_DST"), "{e}");
+        }
+    }
+
+    #[test]
+    fn tfo_and_defer_accept_need_linux() {
+        for (config, needs) in [
+            ("frontend web\n    bind 127.0.0.1:15001 tfo\n    default_backend be\n\nbackend be\n    server s1 127.0.0.1:8080\n", "'tfo' needs TCP_FASTOPEN"),
+            ("frontend web\n    bind 127.0.0.1:15001 defer-accept\n    default_backend be\n\nbackend be\n    server s1 127.0.0.1:8080\n", "'defer-accept' needs TCP_DEFER_ACCEPT"),
+            ("frontend web\n    bind 127.0.0.1:15001\n    default_backend be\n\nbackend be\n    server s1 127.0.0.1:8080 tfo\n", "'tfo' needs TCP_FASTOPEN_CONNECT"),
+        ] {
+            let validated = parse(config).unwrap().validate(false);
+            assert_eq!(validated.is_ok(), cfg!(target_os = "linux"), "{needs}");
+            if let Err(e) = validated {
+                assert!(e.to_string().contains(needs), "{e}");
+            }
+        }
+    }
+
+    #[test]
+    fn metrics_labels_must_be_prometheus_label_names() {
+        let backend = "\nbackend be\n    server s1 127.0.0.1:8080\n";
+        let config = parse(&format!("global\n    metrics prefix acme\n    metrics label dc eu1\n    metrics label group edge\n{backend}")).unwrap();
+        config.validate(false).unwrap();
+        assert_eq!(config.metrics.prefix.as_deref(), Some("acme"));
+        assert_eq!(config.metrics.labels, [("dc".to_string(), "eu1".to_string()), ("group".to_string(), "edge".to_string())]);
+
+        for (global, error) in [
+            ("    metrics prefix 1acme\n", "Invalid metrics prefix '1acme'"),
+            ("    metrics label 1dc eu1\n", "Invalid metrics label '1dc'"),
+            ("    metrics label data-center eu1\n", "Invalid metrics label 'data-center'"),
+            ("    metrics label __name eu1\n", "Invalid metrics label '__name'"),
+            ("    metrics label le 1\n", "Metrics label 'le' is reserved"),
+            ("    metrics label dc eu1\n    metrics label dc eu2\n", "Metrics label 'dc' is declared twice"),
+        ] {
+            let e = parse(&format!("global\n{global}{backend}")).unwrap().validate(false).unwrap_err();
+            assert!(e.to_string().contains(error), "{global}: {e}");
+        }
+    }
+
+    fn two_frontends(web: &str, api: &str) -> Result<()> {
+        parse(&format!("frontend web\n    bind {web}\n    default_backend be\n\n\
+                        frontend api\n    bind {api}\n    default_backend be\n\n\
+                        backend be\n    server s1 127.0.0.1:8080\n"))?.validate(false)
+    }
+
+    #[test]
+    fn overlapping_binds_are_compared_on_normalized_addresses() {
+        for (web, api) in [
+            ("*:15001", "127.0.0.1:15001"),
+            ("0.0.0.0:15001", "127.0.0.1:15001"),
+            ("[::]:15001", "0.0.0.0:15001"),
+            ("[::]:15001 v4v6", "127.0.0.1:15001"),
+            ("[::ffff:127.0.0.1]:15001", "127.0.0.1:15001"),
+            ("127.0.0.1:15000-15002", "127.0.0.1:15002"),
+        ] {
+            let e = two_frontends(web, api).unwrap_err().to_string();
+            assert!(e.contains("Frontend 'api' bind") && e.contains("of frontend 'web'"), "{web} / {api}: {e}");
+        }
+        for (web, api) in [
+            ("[::]:15001 v6only", "0.0.0.0:15001"),
+            ("127.0.0.1:15001", "127.0.0.2:15001"),
+            ("*:15001", "127.0.0.1:15002"),
+        ] {
+            assert!(two_frontends(web, api).is_ok(), "{web} / {api}");
+        }
+    }
+
+    #[test]
+    fn only_reuseport_binds_of_one_frontend_share_an_address() {
+        let backend = "    default_backend be\n\nbackend be\n    server s1 127.0.0.1:8080\n";
+        let shared = parse(&format!("frontend web\n    bind 127.0.0.1:15001 reuseport name a\n    bind 127.0.0.1:15001 reuseport name b\n{backend}")).unwrap();
+        assert_eq!(shared.validate(false).is_ok(), cfg!(target_os = "linux"));
+
+        let e = parse(&format!("frontend web\n    bind 127.0.0.1:15001 reuseport name a\n    bind 127.0.0.1:15001 name b\n{backend}"))
+            .unwrap().validate(false).unwrap_err();
+        assert!(e.to_string().contains("both binds need 'reuseport' to share it"), "{e}");
+        let e = two_frontends("127.0.0.1:15001 reuseport", "127.0.0.1:15001 reuseport").unwrap_err();
+        assert!(e.to_string().contains("'reuseport' only shares an address between binds of the same frontend"), "{e}");
+
+        let sharded = parse(&format!("frontend web\n    bind 127.0.0.1:15001 shards 3\n{backend}")).unwrap();
+        let bind = &sharded.frontends[0].bind[0];
+        assert!(bind.reuseport);
+        let addr = "127.0.0.1:15001".parse().unwrap();
+        let names: Vec<String> = (0..bind.shard_count()).map(|shard| bind.shard_name(addr, shard)).collect();
+        assert_eq!(names, ["127.0.0.1:15001#1", "127.0.0.1:15001#2", "127.0.0.1:15001#3"]);
+        assert!(parse(&format!("frontend web\n    bind 127.0.0.1:15001 shards 0\n{backend}")).is_err());
+    }
+
+    #[test]
+    fn stats_bind_defaults_to_loopback() {
+        let addresses = |config: &Config| config.metrics.binds.iter().map(|bind| bind.address.clone()).collect::<Vec<_>>();
+        assert_eq!(addresses(&parse("").unwrap()), ["127.0.0.1:9090"]);
+
+        let config = parse("global\n    stats bind *:9100\n    stats bind unix:/run/stats.sock mode 640 user prometheus\n").unwrap();
+        assert_eq!(addresses(&config), ["0.0.0.0:9100", "unix:/run/stats.sock"]);
+        assert_eq!(config.metrics.binds[1].unix_mode, Some(0o640));
+        assert_eq!(config.metrics.binds[1].unix_user.as_deref(), Some("prometheus"));
+        assert!(parse("global\n    stats bind 127.0.0.1:9100 mode 640\n").is_err());
+    }
+
+    #[test]
+    fn metrics_labels_keep_quoted_spaces() {
+        let config = parse("global\n    metrics label region \"eu west\"\n    metrics prefix edge\n").unwrap();
+        assert_eq!(config.metrics.labels, [("region".to_string(), "eu west".to_string())]);
+        assert_eq!(config.metrics.prefix.as_deref(), Some("edge"));
+    }
+
+    #[test]
+    fn only_unimplemented_directives_require_a_capability() {
+        let words = |line: &str| line.split_whitespace().map(str::to_string).collect::<Vec<_>>();
+        for (section, key, args) in [
+            ("frontend web", "mode", "http"),
+            ("defaults", "timeout", "http-keep-alive 10s"),
+            ("frontend web", "timeout", "http-keep-alive 10s"),
+            ("backend be", "timeout", "http-keep-alive 10s"),
+            ("frontend web", "http-request", "auth realm admin if !authorized"),
+            ("frontend web", "http-request", "return status 200"),
+            ("frontend web", "bind", "127.0.0.1:8080"),
+        ] {
+            assert_eq!(required_capability(section, key, &words(args)), None, "{section}: {key} {args}");
+        }
+        for (section, key, args, directive, capability) in [
+            ("global", "compression-gzip", "enabled", "compression-gzip", Capability::Http),
+            ("frontend web", "compression-min-size", "512", "compression-min-size", Capability::Http),
+            ("frontend web", "http-request", "set-header X-Id 1", "http-request set-header", Capability::Http),
+            ("defaults", "http3", "enabled", "http3", Capability::Udp),
+            ("frontend web", "bind", "127.0.0.1:8443 ssl crt site.pem", "bind ssl", Capability::Tls),
+        ] {
+            assert_eq!(required_capability(section, key, &words(args)), Some((directive.to_string(), capability)), "{section}: {key} {args}");
+        }
+    }
+
+    #[test]
+    fn http_keep_alive_timeout_is_applied() {
+        let config = parse("frontend web\n    bind 127.0.0.1:8080\n    mode http\n    timeout http-keep-alive 2s\n    default_backend be\n\nbackend be\n    server s1 127.0.0.1:8081\n").unwrap();
+        assert!(config.capability_uses.is_empty());
+        let options = config.frontends[0].options.as_ref().expect("frontend options are resolved");
+        assert_eq!(options.http_options.http_keep_alive_timeout, Some(2000));
+    }
+}
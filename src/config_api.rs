@@ -0,0 +1,260 @@
+use crate::config::{self, BackendConfig, Config, ServerConfig};
+use crate::inspect::NameTemplate;
+use std::fmt;
+use thiserror::Error;
+use tokio::sync::{mpsc, oneshot};
+
+const CHANGE_QUEUE: usize = 16;
+
+pub type ChangeRequest = (ConfigChange, oneshot::Sender<Result<(), ConfigApiError>>);
+
+#[derive(Debug, Error)]
+pub enum ConfigApiError {
+    #[error("Frontend '{0}' not found")]
+    UnknownFrontend(String),
+    #[error("Backend '{0}' not found")]
+    UnknownBackend(String),
+    #[error("Server '{backend}/{server}' not found")]
+    UnknownServer { backend: String, server: String },
+    #[error("Backend '{0}' already exists")]
+    BackendExists(String),
+    #[error("Server '{backend}/{server}' already exists")]
+    ServerExists { backend: String, server: String },
+    #[error("Backend '{backend}' is still used by frontend '{frontend}'")]
+    BackendInUse { backend: String, frontend: String },
+    #[error("{0:#}")]
+    Invalid(anyhow::Error),
+    #[error("Proxy server is not running")]
+    Stopped,
+}
+
+#[derive(Debug)]
+pub enum ConfigChange {
+    AddBackend(Box<BackendConfig>),
+    RemoveBackend(String),
+    AddServer { backend: String, server: Box<ServerConfig> },
+    RemoveServer { backend: String, server: String },
+    SetDefaultBackend { frontend: String, backend: String },
+}
+
+impl ConfigChange {
+    pub fn apply(self, config: &mut Config) -> Result<(), ConfigApiError> {
+        match self {
+            ConfigChange::AddBackend(mut backend) => {
+                if config.backends.iter().any(|b| b.name == backend.name) {
+                    return Err(ConfigApiError::BackendExists(backend.name));
+                }
+                if backend.options.is_none() {
+                    backend.apply_defaults(&config.defaults).map_err(ConfigApiError::Invalid)?;
+                }
+                config::assign_server_ids(&mut backend.server);
+                config.backends.push(*backend);
+            }
+            ConfigChange::RemoveBackend(name) => {
+                let index = config.backends.iter().position(|b| b.name == name)
+                    .ok_or_else(|| ConfigApiError::UnknownBackend(name.clone()))?;
+                let user = config.frontends.iter().find(|frontend| {
+                    frontend.default_backend.as_deref() == Some(name.as_str())
+                        || frontend.use_backend.iter().any(|rule| rule.backend == name)
+                });
+                if let Some(frontend) = user {
+                    return Err(ConfigApiError::BackendInUse { backend: name, frontend: frontend.name.clone() });
+                }
+                config.backends.remove(index);
+            }
+            ConfigChange::AddServer { backend, server } => {
+                let backend_config = config.backends.iter_mut().find(|b| b.name == backend)
+                    .ok_or_else(|| ConfigApiError::UnknownBackend(backend.clone()))?;
+                if backend_config.server.iter().any(|s| s.name == server.name) {
+                    return Err(ConfigApiError::ServerExists { backend, server: server.name });
+                }
+                backend_config.server.push(*server);
+                config::assign_server_ids(&mut backend_config.server);
+            }
+            ConfigChange::RemoveServer { backend, server } => {
+                let backend_config = config.backends.iter_mut().find(|b| b.name == backend)
+                    .ok_or_else(|| ConfigApiError::UnknownBackend(backend.clone()))?;
+                let index = backend_config.server.iter().position(|s| s.name == server)
+                    .ok_or(ConfigApiError::UnknownServer { backend, server })?;
+                backend_config.server.remove(index);
+            }
+            ConfigChange::SetDefaultBackend { frontend, backend } => {
+                if !NameTemplate::is_template(&backend) && !config.backends.iter().any(|b| b.name == backend) {
+                    return Err(ConfigApiError::UnknownBackend(backend));
+                }
+                let frontend_config = config.frontends.iter_mut().find(|f| f.name == frontend)
+                    .ok_or(ConfigApiError::UnknownFrontend(frontend))?;
+                frontend_config.default_backend = Some(backend);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ConfigChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigChange::AddBackend(backend) => write!(f, "add backend {}", backend.name),
+            ConfigChange::RemoveBackend(backend) => write!(f, "del backend {}", backend),
+            ConfigChange::AddServer { backend, server } => write!(f, "add server {}/{} {}", backend, server.name, server.endpoint()),
+            ConfigChange::RemoveServer { backend, server } => write!(f, "del server {}/{}", backend, server),
+            ConfigChange::SetDefaultBackend { frontend, backend } => write!(f, "set frontend {} default_backend {}", frontend, backend),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ConfigApi {
+    changes: mpsc::Sender<ChangeRequest>,
+}
+
+impl ConfigApi {
+    pub fn channel() -> (Self, mpsc::Receiver<ChangeRequest>) {
+        let (changes, receiver) = mpsc::channel(CHANGE_QUEUE);
+        (Self { changes }, receiver)
+    }
+
+    pub async fn add_backend(&self, backend: BackendConfig) -> Result<(), ConfigApiError> {
+        self.submit(ConfigChange::AddBackend(Box::new(backend))).await
+    }
+
+    pub async fn remove_backend(&self, name: &str) -> Result<(), ConfigApiError> {
+        self.submit(ConfigChange::RemoveBackend(name.to_string())).await
+    }
+
+    pub async fn add_server(&self, backend: &str, server: ServerConfig) -> Result<(), ConfigApiError> {
+        self.submit(ConfigChange::AddServer { backend: backend.to_string(), server: Box::new(server) }).await
+    }
+
+    pub async fn remove_server(&self, backend: &str, name: &str) -> Result<(), ConfigApiError> {
+        self.submit(ConfigChange::RemoveServer { backend: backend.to_string(), server: name.to_string() }).await
+    }
+
+    pub async fn set_frontend_default_backend(&self, frontend: &str, backend: &str) -> Result<(), ConfigApiError> {
+        self.submit(ConfigChange::SetDefaultBackend { frontend: frontend.to_string(), backend: backend.to_string() }).await
+    }
+
+    async fn submit(&self, change: ConfigChange) -> Result<(), ConfigApiError> {
+        let (done, result) = oneshot::channel();
+        self.changes.send((change, done)).await.map_err(|_| ConfigApiError::Stopped)?;
+        result.await.map_err(|_| ConfigApiError::Stopped)?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        Config::from_haproxy_config("
+frontend web
+    bind 127.0.0.1:8000
+    default_backend app
+
+backend app
+    server s1 127.0.0.1:9001
+    server s2 127.0.0.1:9002 id 1
+
+backend spare
+    server s9 127.0.0.1:9009
+", None).unwrap()
+    }
+
+    fn backend(config: &Config, name: &str) -> BackendConfig {
+        config.backends.iter().find(|b| b.name == name).unwrap().clone()
+    }
+
+    fn server_names(config: &Config, name: &str) -> Vec<(String, Option<u32>)> {
+        backend(config, name).server.iter().map(|s| (s.name.clone(), s.id)).collect()
+    }
+
+    #[test]
+    fn backends_are_added_once_and_removed_only_when_unused() {
+        let mut config = config();
+        let mut extra = backend(&config, "spare");
+        extra.name = "extra".to_string();
+        extra.server[0].id = None;
+        ConfigChange::AddBackend(Box::new(extra.clone())).apply(&mut config).unwrap();
+        assert_eq!(server_names(&config, "extra"), [("s9".to_string(), Some(1))]);
+
+        let err = ConfigChange::AddBackend(Box::new(extra)).apply(&mut config).unwrap_err();
+        assert_eq!(err.to_string(), "Backend 'extra' already exists");
+
+        let err = ConfigChange::RemoveBackend("app".to_string()).apply(&mut config).unwrap_err();
+        assert_eq!(err.to_string(), "Backend 'app' is still used by frontend 'web'");
+        let err = ConfigChange::RemoveBackend("missing".to_string()).apply(&mut config).unwrap_err();
+        assert_eq!(err.to_string(), "Backend 'missing' not found");
+
+        ConfigChange::RemoveBackend("extra".to_string()).apply(&mut config).unwrap();
+        assert!(config.backends.iter().all(|b| b.name != "extra"));
+    }
+
+    #[test]
+    fn servers_get_the_lowest_free_id() {
+        let mut config = config();
+        assert_eq!(server_names(&config, "app"), [("s1".to_string(), Some(2)), ("s2".to_string(), Some(1))]);
+
+        let mut s3 = backend(&config, "spare").server[0].clone();
+        s3.name = "s3".to_string();
+        s3.id = None;
+        ConfigChange::RemoveServer { backend: "app".to_string(), server: "s2".to_string() }.apply(&mut config).unwrap();
+        ConfigChange::AddServer { backend: "app".to_string(), server: Box::new(s3.clone()) }.apply(&mut config).unwrap();
+        assert_eq!(server_names(&config, "app"), [("s1".to_string(), Some(2)), ("s3".to_string(), Some(1))]);
+
+        let err = ConfigChange::AddServer { backend: "app".to_string(), server: Box::new(s3.clone()) }.apply(&mut config).unwrap_err();
+        assert_eq!(err.to_string(), "Server 'app/s3' already exists");
+        let err = ConfigChange::AddServer { backend: "nope".to_string(), server: Box::new(s3) }.apply(&mut config).unwrap_err();
+        assert_eq!(err.to_string(), "Backend 'nope' not found");
+        let err = ConfigChange::RemoveServer { backend: "app".to_string(), server: "s2".to_string() }.apply(&mut config).unwrap_err();
+        assert_eq!(err.to_string(), "Server 'app/s2' not found");
+    }
+
+    #[test]
+    fn default_backend_must_exist_unless_it_is_a_template() {
+        let mut config = config();
+        let set = |frontend: &str, backend: &str| ConfigChange::SetDefaultBackend { frontend: frontend.to_string(), backend: backend.to_string() };
+
+        set("web", "spare").apply(&mut config).unwrap();
+        assert_eq!(config.frontends[0].default_backend.as_deref(), Some("spare"));
+        assert_eq!(set("web", "missing").apply(&mut config).unwrap_err().to_string(), "Backend 'missing' not found");
+        assert_eq!(set("api", "spare").apply(&mut config).unwrap_err().to_string(), "Frontend 'api' not found");
+
+        set("web", "pool-%[connect_port]").apply(&mut config).unwrap();
+        assert_eq!(config.frontends[0].default_backend.as_deref(), Some("pool-%[connect_port]"));
+        ConfigChange::RemoveBackend("spare".to_string()).apply(&mut config).unwrap();
+    }
+
+    #[test]
+    fn changes_display_like_runtime_commands() {
+        let config = config();
+        let server = backend(&config, "spare").server[0].clone();
+        assert_eq!(ConfigChange::AddBackend(Box::new(backend(&config, "spare"))).to_string(), "add backend spare");
+        assert_eq!(ConfigChange::AddServer { backend: "app".to_string(), server: Box::new(server) }.to_string(),
+            "add server app/s9 127.0.0.1:9009");
+        assert_eq!(ConfigChange::RemoveServer { backend: "app".to_string(), server: "s1".to_string() }.to_string(), "del server app/s1");
+        assert_eq!(ConfigChange::SetDefaultBackend { frontend: "web".to_string(), backend: "app".to_string() }.to_string(),
+            "set frontend web default_backend app");
+    }
+
+    #[tokio::test]
+    async fn api_calls_wait_for_the_proxy_to_apply_them() {
+        let (api, mut changes) = ConfigApi::channel();
+        let applier = tokio::spawn(async move {
+            let mut config = config();
+            while let Some((change, done)) = changes.recv().await {
+                let _ = done.send(change.apply(&mut config));
+            }
+            config
+        });
+
+        api.remove_server("app", "s1").await.unwrap();
+        let err = api.remove_backend("app").await.unwrap_err();
+        assert!(matches!(err, ConfigApiError::BackendInUse { .. }), "{}", err);
+        drop(api);
+        assert_eq!(server_names(&applier.await.unwrap(), "app"), [("s2".to_string(), Some(1))]);
+
+        let (api, changes) = ConfigApi::channel();
+        drop(changes);
+        assert!(matches!(api.remove_backend("spare").await, Err(ConfigApiError::Stopped)));
+    }
+}
@@ -1,7 +1,14 @@
 use std::net::IpAddr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use dashmap::DashMap;
-use tracing::debug;
+use tracing::{debug, info, warn};
+
+use crate::metrics;
+use crate::rate_limit::ProtectionMode;
+
+const SUSPICIOUS_REQUEST_WEIGHT: u32 = 10;
+const OBSERVE_LOG_SAMPLE: u64 = 100;
 
 #[derive(Debug, Clone)]
 pub struct DdosConfig {
@@ -11,6 +18,7 @@ pub struct DdosConfig {
     pub suspicious_patterns: Vec<String>,
     pub whitelist: Vec<IpAddr>,
     pub blacklist: Vec<IpAddr>,
+    pub mode: ProtectionMode,
 }
 
 impl Default for DdosConfig {
@@ -22,6 +30,7 @@ impl Default for DdosConfig {
             suspicious_patterns: Vec::new(),
             whitelist: Vec::new(),
             blacklist: Vec::new(),
+            mode: ProtectionMode::Enforce,
         }
     }
 }
@@ -46,23 +55,55 @@ impl Default for IpActivity {
 pub struct DdosProtection {
     activity: Arc<DashMap<IpAddr, IpActivity>>,
     config: DdosConfig,
+    observe: AtomicBool,
+    would_reject: AtomicU64,
 }
 
 impl DdosProtection {
     pub fn new(config: DdosConfig) -> Self {
         Self {
             activity: Arc::new(DashMap::new()),
+            observe: AtomicBool::new(config.mode == ProtectionMode::Observe),
+            would_reject: AtomicU64::new(0),
             config,
         }
     }
 
+    pub fn mode(&self) -> ProtectionMode {
+        if self.observe.load(Ordering::Relaxed) {
+            ProtectionMode::Observe
+        } else {
+            ProtectionMode::Enforce
+        }
+    }
+
+    pub fn set_mode(&self, mode: ProtectionMode) {
+        if self.mode() != mode {
+            info!("DDoS protection switched to {} mode", mode.as_str());
+        }
+        self.observe.store(mode == ProtectionMode::Observe, Ordering::Relaxed);
+    }
+
+    fn reject(&self, client_ip: IpAddr, reason: &str) -> bool {
+        if self.mode() == ProtectionMode::Enforce {
+            return false;
+        }
+
+        metrics::ddos_would_reject(reason);
+        let seen = self.would_reject.fetch_add(1, Ordering::Relaxed);
+        if seen.is_multiple_of(OBSERVE_LOG_SAMPLE) {
+            warn!("DDoS protection would reject {} ({}, observe mode, {} would-reject so far)", client_ip, reason, seen + 1);
+        }
+        true
+    }
+
     pub fn check_rate_limit(&self, client_ip: IpAddr) -> bool {
         if self.config.whitelist.contains(&client_ip) {
             return true;
         }
 
         if self.config.blacklist.contains(&client_ip) {
-            return false;
+            return self.reject(client_ip, "blacklist");
         }
 
         let mut activity = self.activity.entry(client_ip).or_insert_with(IpActivity::default);
@@ -77,7 +118,8 @@ impl DdosProtection {
             
             if activity.request_count >= max_requests {
                 debug!("DDoS protection: IP {} exceeded max requests per minute", client_ip);
-                return false;
+                drop(activity);
+                return self.reject(client_ip, "request_rate");
             }
             
             activity.request_count += 1;
@@ -93,7 +135,7 @@ impl DdosProtection {
         }
 
         if self.config.blacklist.contains(&client_ip) {
-            return false;
+            return self.reject(client_ip, "blacklist");
         }
 
         let mut activity = self.activity.entry(client_ip).or_insert_with(IpActivity::default);
@@ -101,7 +143,11 @@ impl DdosProtection {
         if let Some(max_connections) = self.config.max_connections_per_ip {
             if activity.connection_count >= max_connections {
                 debug!("DDoS protection: IP {} exceeded max connections per IP", client_ip);
-                return false;
+                drop(activity);
+                if !self.reject(client_ip, "connection_limit") {
+                    return false;
+                }
+                activity = self.activity.entry(client_ip).or_default();
             }
             
             activity.connection_count += 1;
@@ -118,6 +164,17 @@ impl DdosProtection {
         }
     }
 
+    pub fn record_suspicious(&self, client_ip: IpAddr) {
+        if self.config.whitelist.contains(&client_ip) {
+            return;
+        }
+
+        let mut activity = self.activity.entry(client_ip).or_default();
+        activity.request_count = activity.request_count.saturating_add(SUSPICIOUS_REQUEST_WEIGHT);
+        activity.last_request_time = std::time::Instant::now();
+        debug!("DDoS protection: IP {} flagged as suspicious", client_ip);
+    }
+
     pub fn check_suspicious_pattern(&self, user_agent: Option<&str>) -> bool {
         if let Some(ua) = user_agent {
             for pattern in &self.config.suspicious_patterns {
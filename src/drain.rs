@@ -0,0 +1,291 @@
+use crate::config::GlobalConfig;
+use crate::http1::{Exchange, RequestPolicy, RequestTiming};
+use crate::metrics;
+use crate::session::SessionCounters;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::{Notify, watch};
+use tokio::time::Instant;
+use tracing::info;
+
+pub const DEFAULT_KEEPALIVE_DELAY: Duration = Duration::from_millis(100);
+const BUSY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Default)]
+pub struct DrainState {
+    draining: AtomicBool,
+    refusing: AtomicBool,
+    generation: AtomicU64,
+    pre_stop_delay_ms: AtomicU64,
+    keepalive_delay_ms: AtomicU64,
+    kill_idle_ms: AtomicU64,
+    state: watch::Sender<bool>,
+}
+
+impl DrainState {
+    pub fn configure(&self, global: &GlobalConfig) {
+        let delay = global.pre_stop_delay.unwrap_or_default();
+        self.pre_stop_delay_ms.store(delay.as_millis() as u64, Ordering::Relaxed);
+        let keepalive_delay = global.drain_keepalive_delay.unwrap_or(DEFAULT_KEEPALIVE_DELAY);
+        self.keepalive_delay_ms.store(keepalive_delay.as_millis() as u64, Ordering::Relaxed);
+        self.kill_idle_ms.store(global.drain_kill_idle.map_or(0, |idle| idle.as_millis() as u64), Ordering::Relaxed);
+    }
+
+    pub fn pre_stop_delay(&self) -> Duration {
+        Duration::from_millis(self.pre_stop_delay_ms.load(Ordering::Relaxed))
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    pub fn refuses_connections(&self) -> bool {
+        self.refusing.load(Ordering::Relaxed)
+    }
+
+    pub fn watch(&self, http: bool) -> DrainWatch {
+        let kill_idle_ms = self.kill_idle_ms.load(Ordering::Relaxed);
+        DrainWatch {
+            receiver: self.state.subscribe(),
+            exchange: http.then(|| Arc::new(Mutex::new(Exchange::new(Vec::new())))),
+            policy: None,
+            local: Arc::new(Notify::new()),
+            keepalive_delay: Duration::from_millis(self.keepalive_delay_ms.load(Ordering::Relaxed)),
+            kill_idle: (kill_idle_ms > 0).then(|| Duration::from_millis(kill_idle_ms)),
+        }
+    }
+
+    pub fn begin(self: &Arc<Self>) -> bool {
+        if self.draining.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let delay = self.pre_stop_delay();
+        metrics::draining(true);
+        self.state.send_replace(true);
+        info!("Draining: readiness is now failing, new connections are refused in {:?}", delay);
+
+        let drain = Arc::clone(self);
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            if drain.generation.load(Ordering::SeqCst) == generation && drain.is_draining() {
+                drain.refusing.store(true, Ordering::SeqCst);
+                info!("Draining: refusing new connections");
+            }
+        });
+        true
+    }
+
+    pub fn end(&self) -> bool {
+        if !self.draining.swap(false, Ordering::SeqCst) {
+            return false;
+        }
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.refusing.store(false, Ordering::SeqCst);
+        self.state.send_replace(false);
+        metrics::draining(false);
+        info!("Draining cancelled: ready and accepting connections again");
+        true
+    }
+}
+
+#[derive(Clone)]
+pub struct DrainWatch {
+    receiver: watch::Receiver<bool>,
+    exchange: Option<Arc<Mutex<Exchange>>>,
+    policy: Option<(Arc<dyn RequestPolicy>, u64)>,
+    local: Arc<Notify>,
+    keepalive_delay: Duration,
+    kill_idle: Option<Duration>,
+}
+
+impl DrainWatch {
+    pub fn draining(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    pub fn tracks_http(&self) -> bool {
+        self.exchange.is_some()
+    }
+
+    pub fn with_policy(mut self, policy: Arc<dyn RequestPolicy>, checked: u64) -> Self {
+        self.policy = Some((policy, checked));
+        self.reset_exchange(Vec::new());
+        self
+    }
+
+    pub fn reset_exchange(&self, request_headers: Vec<(String, String)>) {
+        if let Some(ref exchange) = self.exchange {
+            let mut fresh = Exchange::new(request_headers);
+            if let Some((ref policy, checked)) = self.policy {
+                fresh = fresh.with_policy(Arc::clone(policy), checked);
+            }
+            *exchange.lock().unwrap_or_else(|e| e.into_inner()) = fresh;
+        }
+    }
+
+    pub fn forward_request(&self, data: &[u8], output: &mut Vec<u8>) {
+        let Some(ref exchange) = self.exchange else {
+            output.extend_from_slice(data);
+            return;
+        };
+        let mut exchange = exchange.lock().unwrap_or_else(|e| e.into_inner());
+        exchange.forward_request(data, output);
+        if exchange.local_ready() {
+            self.local.notify_one();
+        }
+    }
+
+    pub async fn local_ready(&self) {
+        match self.exchange {
+            Some(_) => self.local.notified().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    pub fn flush_local(&self, output: &mut Vec<u8>) -> bool {
+        match self.exchange {
+            Some(ref exchange) => exchange.lock().unwrap_or_else(|e| e.into_inner()).flush_local(self.draining(), output),
+            None => false,
+        }
+    }
+
+    pub fn take_timings(&self) -> Vec<RequestTiming> {
+        match self.exchange {
+            Some(ref exchange) => exchange.lock().unwrap_or_else(|e| e.into_inner()).take_timings(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn forward_response(&self, data: &[u8], output: &mut Vec<u8>) -> bool {
+        let Some(ref exchange) = self.exchange else {
+            output.extend_from_slice(data);
+            return false;
+        };
+        let closed = exchange.lock().unwrap_or_else(|e| e.into_inner()).forward_response(data, self.draining(), output);
+        if closed && self.draining() {
+            metrics::drain_closed("after_response");
+        }
+        closed
+    }
+
+    // Resolves once the connection has sat between requests for `timeout`.
+    pub async fn keep_alive_expired(&self, timeout: Duration) {
+        let Some(ref exchange) = self.exchange else {
+            return std::future::pending().await;
+        };
+        loop {
+            let idle_since = exchange.lock().unwrap_or_else(|e| e.into_inner()).idle_since();
+            let wait = match idle_since {
+                Some(since) => {
+                    let deadline = Instant::from_std(since) + timeout;
+                    if Instant::now() >= deadline {
+                        return;
+                    }
+                    deadline - Instant::now()
+                }
+                None => BUSY_POLL_INTERVAL,
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    pub async fn idle_closed(&mut self, counters: &SessionCounters) -> &'static str {
+        loop {
+            if self.receiver.wait_for(|draining| *draining).await.is_err() {
+                return std::future::pending().await;
+            }
+            let started = Instant::now();
+            while self.draining() {
+                let wait = match (&self.exchange, self.kill_idle) {
+                    (Some(exchange), _) => match exchange.lock().unwrap_or_else(|e| e.into_inner()).idle_since() {
+                        Some(since) => {
+                            let deadline = Instant::from_std(since).max(started) + self.keepalive_delay;
+                            if Instant::now() >= deadline {
+                                return "keepalive_idle";
+                            }
+                            deadline - Instant::now()
+                        }
+                        None => BUSY_POLL_INTERVAL,
+                    },
+                    (None, Some(kill_idle)) => {
+                        let idle = counters.idle_for();
+                        if idle >= kill_idle {
+                            return "tcp_idle";
+                        }
+                        kill_idle - idle
+                    }
+                    (None, None) => return std::future::pending().await,
+                };
+                tokio::select! {
+                    _ = tokio::time::sleep(wait) => {}
+                    _ = self.receiver.changed() => {}
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(pre_stop_delay: Duration, kill_idle: Option<Duration>) -> Arc<DrainState> {
+        let drain = Arc::new(DrainState::default());
+        drain.configure(&GlobalConfig { pre_stop_delay: Some(pre_stop_delay), drain_kill_idle: kill_idle, ..GlobalConfig::default() });
+        drain
+    }
+
+    #[tokio::test]
+    async fn connections_are_refused_after_the_pre_stop_delay() {
+        let drain = drain(Duration::from_millis(50), None);
+        let watch = drain.watch(false);
+        assert!(drain.begin());
+        assert!(!drain.begin(), "already draining");
+        assert!(drain.is_draining() && watch.draining());
+        assert!(!drain.refuses_connections());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(drain.refuses_connections());
+
+        assert!(drain.end());
+        assert!(!drain.end());
+        assert!(!drain.is_draining() && !drain.refuses_connections() && !watch.draining());
+    }
+
+    #[tokio::test]
+    async fn a_cancelled_drain_never_refuses() {
+        let drain = drain(Duration::from_millis(30), None);
+        drain.begin();
+        drain.end();
+        drain.configure(&GlobalConfig { pre_stop_delay: Some(Duration::from_secs(10)), ..GlobalConfig::default() });
+        drain.begin();
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        assert!(!drain.refuses_connections(), "the delay of the cancelled drain fired");
+    }
+
+    #[tokio::test]
+    async fn idle_tcp_sessions_close_once_draining() {
+        let drain = drain(Duration::ZERO, Some(Duration::from_millis(20)));
+        let mut watch = drain.watch(false);
+        assert!(!watch.tracks_http());
+        let counters = SessionCounters::new();
+        let closed = tokio::spawn(async move { watch.idle_closed(&counters).await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!closed.is_finished(), "closed before draining");
+        drain.begin();
+        let reason = tokio::time::timeout(Duration::from_secs(1), closed).await.unwrap().unwrap();
+        assert_eq!(reason, "tcp_idle");
+    }
+
+    #[test]
+    fn tcp_watches_pass_data_through() {
+        let watch = drain(Duration::ZERO, None).watch(false);
+        let mut output = Vec::new();
+        watch.forward_request(b"ping", &mut output);
+        assert!(!watch.forward_response(b"pong", &mut output));
+        assert_eq!(output, b"pingpong");
+        assert!(!watch.flush_local(&mut output));
+        assert!(watch.take_timings().is_empty());
+    }
+}
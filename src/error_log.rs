@@ -0,0 +1,231 @@
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub const ERROR_RING_SIZE: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorScope {
+    All,
+    Frontend(String),
+    Backend(String),
+}
+
+#[derive(Debug)]
+pub struct ErrorTarget {
+    pub backend: String,
+    pub server: Option<String>,
+}
+
+impl ErrorTarget {
+    pub fn backend(backend: &str) -> Self {
+        Self { backend: backend.to_string(), server: None }
+    }
+
+    pub fn server(backend: &str, server: &str) -> Self {
+        Self { backend: backend.to_string(), server: Some(server.to_string()) }
+    }
+}
+
+impl fmt::Display for ErrorTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.server {
+            Some(ref server) => write!(f, "{}/{}", self.backend, server),
+            None => write!(f, "{}", self.backend),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorEntry {
+    #[serde(skip)]
+    pub sequence: u64,
+    pub timestamp: DateTime<Utc>,
+    pub client: SocketAddr,
+    pub frontend: String,
+    pub backend: Option<String>,
+    pub server: Option<String>,
+    pub error: &'static str,
+    pub termination_state: &'static str,
+    pub message: String,
+}
+
+impl ErrorEntry {
+    pub fn to_line(&self) -> String {
+        format!("{} {} {} {} {} {} {} {}",
+            self.timestamp.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            self.client,
+            self.frontend,
+            self.backend.as_deref().unwrap_or("-"),
+            self.server.as_deref().unwrap_or("-"),
+            self.error,
+            self.termination_state,
+            self.message)
+    }
+}
+
+struct ErrorRing {
+    entries: Vec<Arc<ErrorEntry>>,
+    next: usize,
+}
+
+impl ErrorRing {
+    fn new() -> Self {
+        Self { entries: Vec::with_capacity(ERROR_RING_SIZE), next: 0 }
+    }
+
+    fn push(&mut self, entry: Arc<ErrorEntry>) {
+        if self.entries.len() < ERROR_RING_SIZE {
+            self.entries.push(entry);
+        } else {
+            self.entries[self.next] = entry;
+        }
+        self.next = (self.next + 1) % ERROR_RING_SIZE;
+    }
+
+    fn newest_first(&self) -> impl Iterator<Item = &Arc<ErrorEntry>> {
+        let (older, newer) = self.entries.split_at(self.next.min(self.entries.len()));
+        older.iter().rev().chain(newer.iter().rev())
+    }
+}
+
+#[derive(Default)]
+pub struct ErrorLog {
+    sequence: AtomicU64,
+    frontends: DashMap<String, ErrorRing>,
+    backends: DashMap<String, ErrorRing>,
+}
+
+impl ErrorLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(
+        &self,
+        client: SocketAddr,
+        frontend: &str,
+        error: &anyhow::Error,
+        error_type: &'static str,
+        termination_state: &'static str,
+    ) {
+        let target = error.downcast_ref::<ErrorTarget>();
+        let message = match target {
+            Some(_) => error.chain().nth(1).map(|cause| cause.to_string()).unwrap_or_default(),
+            None => error.to_string(),
+        };
+        let entry = Arc::new(ErrorEntry {
+            sequence: self.sequence.fetch_add(1, Ordering::Relaxed),
+            timestamp: Utc::now(),
+            client,
+            frontend: frontend.to_string(),
+            backend: target.map(|target| target.backend.clone()),
+            server: target.and_then(|target| target.server.clone()),
+            error: error_type,
+            termination_state,
+            message,
+        });
+
+        if let Some(ref backend) = entry.backend {
+            Self::push(&self.backends, backend, Arc::clone(&entry));
+        }
+        Self::push(&self.frontends, frontend, entry);
+    }
+
+    fn push(rings: &DashMap<String, ErrorRing>, name: &str, entry: Arc<ErrorEntry>) {
+        match rings.get_mut(name) {
+            Some(mut ring) => ring.push(entry),
+            None => rings.entry(name.to_string()).or_insert_with(ErrorRing::new).push(entry),
+        }
+    }
+
+    pub fn recent(&self, scope: &ErrorScope) -> Vec<Arc<ErrorEntry>> {
+        let mut entries: Vec<Arc<ErrorEntry>> = match scope {
+            ErrorScope::All => self.frontends.iter()
+                .flat_map(|ring| ring.newest_first().cloned().collect::<Vec<_>>())
+                .collect(),
+            ErrorScope::Frontend(name) => self.frontends.get(name)
+                .map(|ring| ring.newest_first().cloned().collect())
+                .unwrap_or_default(),
+            ErrorScope::Backend(name) => self.backends.get(name)
+                .map(|ring| ring.newest_first().cloned().collect())
+                .unwrap_or_default(),
+        };
+        entries.sort_by_key(|entry| Reverse(entry.sequence));
+        entries
+    }
+
+    pub fn to_json(&self, scope: &ErrorScope) -> String {
+        let entries = self.recent(scope);
+        let entries: Vec<&ErrorEntry> = entries.iter().map(Arc::as_ref).collect();
+        serde_json::to_string(&entries).unwrap_or_else(|_| "[]".to_string())
+    }
+
+    pub fn clear(&self) {
+        self.frontends.clear();
+        self.backends.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    fn client() -> SocketAddr {
+        "192.0.2.1:40000".parse().unwrap()
+    }
+
+    fn messages(log: &ErrorLog, scope: &ErrorScope) -> Vec<String> {
+        log.recent(scope).iter().map(|entry| entry.message.clone()).collect()
+    }
+
+    #[test]
+    fn targeted_errors_are_filed_under_frontend_and_backend() {
+        let log = ErrorLog::new();
+        log.record(client(), "web", &anyhow!("connection refused").context(ErrorTarget::server("app", "s1")), "connection_refused", "SC");
+        log.record(client(), "web", &anyhow!("rate limit exceeded"), "rate_limit_exceeded", "--");
+
+        assert_eq!(messages(&log, &ErrorScope::Frontend("web".to_string())), ["rate limit exceeded", "connection refused"]);
+        assert_eq!(messages(&log, &ErrorScope::Backend("app".to_string())), ["connection refused"]);
+        assert!(log.recent(&ErrorScope::Backend("other".to_string())).is_empty());
+
+        let entry = &log.recent(&ErrorScope::Backend("app".to_string()))[0];
+        assert_eq!((entry.backend.as_deref(), entry.server.as_deref()), (Some("app"), Some("s1")));
+        assert!(entry.to_line().ends_with(" 192.0.2.1:40000 web app s1 connection_refused SC connection refused"), "{}", entry.to_line());
+    }
+
+    #[test]
+    fn rings_keep_the_newest_entries() {
+        let log = ErrorLog::new();
+        for index in 0..ERROR_RING_SIZE + 5 {
+            log.record(client(), "web", &anyhow!("error {index}"), "connection_failed", "SC");
+        }
+        let messages = messages(&log, &ErrorScope::Frontend("web".to_string()));
+        assert_eq!(messages.len(), ERROR_RING_SIZE);
+        assert_eq!(messages.first(), Some(&format!("error {}", ERROR_RING_SIZE + 4)));
+        assert_eq!(messages.last(), Some(&"error 5".to_string()));
+    }
+
+    #[test]
+    fn all_merges_frontends_newest_first() {
+        let log = ErrorLog::new();
+        log.record(client(), "a", &anyhow!("first"), "connection_failed", "SC");
+        log.record(client(), "b", &anyhow!("second"), "connection_failed", "SC");
+        log.record(client(), "a", &anyhow!("third"), "connection_failed", "SC");
+        assert_eq!(messages(&log, &ErrorScope::All), ["third", "second", "first"]);
+
+        let json: serde_json::Value = serde_json::from_str(&log.to_json(&ErrorScope::All)).unwrap();
+        assert_eq!(json[0]["frontend"], "a");
+        assert!(json[0]["backend"].is_null());
+        assert!(json[0].get("sequence").is_none());
+
+        log.clear();
+        assert!(log.recent(&ErrorScope::All).is_empty());
+    }
+}
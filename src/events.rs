@@ -0,0 +1,252 @@
+use crate::config::GlobalConfig;
+use crate::metrics;
+use serde::Serialize;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::{broadcast, mpsc};
+use tracing::debug;
+
+const EVENT_CHANNEL_CAPACITY: usize = 1024;
+const HEALTH_EVENT_QUEUE: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TurbogateEvent {
+    ServerUp { backend: String, server: String },
+    ServerDown { backend: String, server: String, reason: Option<String> },
+    ServerMaintenance { backend: String, server: String, maintenance: bool },
+    ServerStateChanged { backend: String, server: String, state: String, previous: Option<String> },
+    BackendDegraded { backend: String, active: usize, total: usize },
+    BackendEmpty { backend: String, total: usize },
+    BackendRecovered { backend: String, active: usize, total: usize },
+    BackendHealthLow { backend: String, ratio: f64, threshold: f64 },
+    BackendHealthRestored { backend: String, ratio: f64, threshold: f64 },
+    ConfigReloaded { ok: bool, error: Option<String> },
+    ConfigChanged { change: String },
+    HealthCheckCompleted { backend: String, server: String, ok: bool, latency_ms: f64, reason: Option<String>, error_type: Option<String> },
+    FrontendBound { frontend: String, address: String },
+    FrontendUnbound { frontend: String, address: String },
+    ListenerFailed { frontend: String, address: String, error: String },
+    Ready { frontends: Vec<ReadyFrontend>, backends: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReadyFrontend {
+    pub name: String,
+    pub binds: Vec<ReadyBind>,
+    pub backends: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReadyBind {
+    pub address: String,
+    pub tls: bool,
+}
+
+impl TurbogateEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TurbogateEvent::ServerUp { .. } => "server_up",
+            TurbogateEvent::ServerDown { .. } => "server_down",
+            TurbogateEvent::ServerMaintenance { .. } => "server_maintenance",
+            TurbogateEvent::ServerStateChanged { .. } => "server_state_changed",
+            TurbogateEvent::BackendDegraded { .. } => "backend_degraded",
+            TurbogateEvent::BackendEmpty { .. } => "backend_empty",
+            TurbogateEvent::BackendRecovered { .. } => "backend_recovered",
+            TurbogateEvent::BackendHealthLow { .. } => "backend_health_low",
+            TurbogateEvent::BackendHealthRestored { .. } => "backend_health_restored",
+            TurbogateEvent::ConfigReloaded { .. } => "config_reloaded",
+            TurbogateEvent::ConfigChanged { .. } => "config_changed",
+            TurbogateEvent::HealthCheckCompleted { .. } => "health_check_completed",
+            TurbogateEvent::FrontendBound { .. } => "frontend_bound",
+            TurbogateEvent::FrontendUnbound { .. } => "frontend_unbound",
+            TurbogateEvent::ListenerFailed { .. } => "listener_failed",
+            TurbogateEvent::Ready { .. } => "ready",
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_else(|_| format!("{{\"event\":\"{}\"}}", self.name()))
+    }
+
+    pub fn backend_health(backend: &str, active: usize, total: usize, previous_active: usize) -> Option<Self> {
+        let backend = backend.to_string();
+        match (active, previous_active) {
+            (active, previous) if active == previous => None,
+            (0, _) => Some(TurbogateEvent::BackendEmpty { backend, total }),
+            (active, _) if active == total => Some(TurbogateEvent::BackendRecovered { backend, active, total }),
+            (active, _) => Some(TurbogateEvent::BackendDegraded { backend, active, total }),
+        }
+    }
+
+    pub fn health_threshold(backend: &str, ratio: f64, previous_ratio: f64, threshold: f64) -> Option<Self> {
+        let backend = backend.to_string();
+        match (ratio < threshold, previous_ratio < threshold) {
+            (true, false) => Some(TurbogateEvent::BackendHealthLow { backend, ratio, threshold }),
+            (false, true) => Some(TurbogateEvent::BackendHealthRestored { backend, ratio, threshold }),
+            _ => None,
+        }
+    }
+}
+
+pub struct EventBus {
+    sender: broadcast::Sender<TurbogateEvent>,
+    health_events: AtomicBool,
+    health_subscribers: Mutex<Vec<mpsc::Sender<TurbogateEvent>>>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _receiver) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        Self {
+            sender,
+            health_events: AtomicBool::new(false),
+            health_subscribers: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn configure(&self, global: &GlobalConfig) {
+        self.health_events.store(global.health_events, Ordering::Relaxed);
+    }
+
+    pub fn health_events(&self) -> bool {
+        self.health_events.load(Ordering::Relaxed)
+    }
+
+    pub fn publish(&self, event: TurbogateEvent) {
+        debug!("Publishing event {}", event.to_json());
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<TurbogateEvent> {
+        self.sender.subscribe()
+    }
+
+    pub fn publish_health_check(&self, event: TurbogateEvent) {
+        let mut subscribers = self.health_subscribers.lock().unwrap_or_else(|e| e.into_inner());
+        subscribers.retain(|subscriber| match subscriber.try_send(event.clone()) {
+            Ok(()) => true,
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                metrics::health_event_dropped();
+                true
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => false,
+        });
+    }
+
+    pub fn subscribe_health_checks(&self) -> mpsc::Receiver<TurbogateEvent> {
+        let (sender, receiver) = mpsc::channel(HEALTH_EVENT_QUEUE);
+        self.health_subscribers.lock().unwrap_or_else(|e| e.into_inner()).push(sender);
+        receiver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server_up() -> TurbogateEvent {
+        TurbogateEvent::ServerUp { backend: "app".to_string(), server: "s1".to_string() }
+    }
+
+    #[test]
+    fn backend_health_reports_transitions_only() {
+        assert_eq!(TurbogateEvent::backend_health("app", 2, 3, 2), None);
+        assert_eq!(TurbogateEvent::backend_health("app", 0, 3, 1),
+            Some(TurbogateEvent::BackendEmpty { backend: "app".to_string(), total: 3 }));
+        assert_eq!(TurbogateEvent::backend_health("app", 3, 3, 0),
+            Some(TurbogateEvent::BackendRecovered { backend: "app".to_string(), active: 3, total: 3 }));
+        assert_eq!(TurbogateEvent::backend_health("app", 1, 3, 3),
+            Some(TurbogateEvent::BackendDegraded { backend: "app".to_string(), active: 1, total: 3 }));
+        assert_eq!(TurbogateEvent::backend_health("app", 2, 3, 0),
+            Some(TurbogateEvent::BackendDegraded { backend: "app".to_string(), active: 2, total: 3 }));
+    }
+
+    #[test]
+    fn events_serialize_with_their_name_as_the_tag() {
+        let down = TurbogateEvent::ServerDown { backend: "app".to_string(), server: "s1".to_string(), reason: None };
+        assert_eq!(down.to_json(), r#"{"event":"server_down","backend":"app","server":"s1","reason":null}"#);
+        assert_eq!(TurbogateEvent::ConfigReloaded { ok: true, error: None }.to_json(),
+            r#"{"event":"config_reloaded","ok":true,"error":null}"#);
+
+        for event in [down, server_up(), TurbogateEvent::BackendEmpty { backend: "app".to_string(), total: 2 }] {
+            let json: serde_json::Value = serde_json::from_str(&event.to_json()).unwrap();
+            assert_eq!(json["event"], event.name());
+        }
+    }
+
+    #[test]
+    fn ready_events_list_every_frontend_with_its_binds() {
+        let ready = TurbogateEvent::Ready {
+            frontends: vec![ReadyFrontend {
+                name: "web".to_string(),
+                binds: vec![
+                    ReadyBind { address: "127.0.0.1:80".to_string(), tls: false },
+                    ReadyBind { address: "127.0.0.1:443".to_string(), tls: true },
+                ],
+                backends: 2,
+            }],
+            backends: 3,
+        };
+        assert_eq!(ready.to_json(), r#"{"event":"ready","frontends":[{"name":"web","binds":[{"address":"127.0.0.1:80","tls":false},{"address":"127.0.0.1:443","tls":true}],"backends":2}],"backends":3}"#);
+    }
+
+    #[test]
+    fn health_threshold_fires_when_the_ratio_crosses_it() {
+        assert_eq!(TurbogateEvent::health_threshold("app", 0.4, 0.8, 0.5),
+            Some(TurbogateEvent::BackendHealthLow { backend: "app".to_string(), ratio: 0.4, threshold: 0.5 }));
+        assert_eq!(TurbogateEvent::health_threshold("app", 0.5, 0.4, 0.5),
+            Some(TurbogateEvent::BackendHealthRestored { backend: "app".to_string(), ratio: 0.5, threshold: 0.5 }));
+        assert_eq!(TurbogateEvent::health_threshold("app", 0.3, 0.4, 0.5), None);
+        assert_eq!(TurbogateEvent::health_threshold("app", 1.0, 0.6, 0.5), None);
+    }
+
+    #[tokio::test]
+    async fn published_events_reach_every_subscriber() {
+        let bus = EventBus::new();
+        bus.publish(server_up());
+
+        let mut first = bus.subscribe();
+        let mut second = bus.subscribe();
+        bus.publish(server_up());
+        assert_eq!(first.recv().await.unwrap(), server_up());
+        assert_eq!(second.recv().await.unwrap(), server_up());
+        assert!(first.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn health_checks_skip_full_queues_and_drop_closed_subscribers() {
+        let bus = EventBus::new();
+        let mut slow = bus.subscribe_health_checks();
+        let closed = bus.subscribe_health_checks();
+        drop(closed);
+
+        for _ in 0..HEALTH_EVENT_QUEUE + 5 {
+            bus.publish_health_check(server_up());
+        }
+        assert_eq!(bus.health_subscribers.lock().unwrap().len(), 1);
+
+        let mut received = 0;
+        while slow.try_recv().is_ok() {
+            received += 1;
+        }
+        assert_eq!(received, HEALTH_EVENT_QUEUE);
+
+        bus.publish_health_check(server_up());
+        assert_eq!(slow.recv().await, Some(server_up()));
+    }
+
+    #[test]
+    fn health_events_follow_the_global_setting() {
+        let bus = EventBus::new();
+        assert!(!bus.health_events());
+        bus.configure(&GlobalConfig { health_events: true, ..GlobalConfig::default() });
+        assert!(bus.health_events());
+    }
+}
@@ -0,0 +1,316 @@
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+pub const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+pub const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpositionFormat {
+    Prometheus,
+    OpenMetrics,
+    Json,
+}
+
+impl ExpositionFormat {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "prometheus" | "text" => Ok(ExpositionFormat::Prometheus),
+            "openmetrics" => Ok(ExpositionFormat::OpenMetrics),
+            "json" => Ok(ExpositionFormat::Json),
+            other => Err(anyhow!("Unknown metrics format '{}', expected prometheus, openmetrics or json", other)),
+        }
+    }
+
+    pub fn negotiate(accept: Option<&str>) -> Self {
+        let Some(accept) = accept else {
+            return ExpositionFormat::Prometheus;
+        };
+        let mut ranges: Vec<(&str, f32)> = accept.split(',')
+            .filter_map(|range| {
+                let mut params = range.split(';').map(str::trim);
+                let media_type = params.next().filter(|media_type| !media_type.is_empty())?;
+                let quality = params
+                    .find_map(|param| param.strip_prefix("q="))
+                    .and_then(|q| q.parse().ok())
+                    .unwrap_or(1.0);
+                Some((media_type, quality))
+            })
+            .filter(|(_, quality)| *quality > 0.0)
+            .collect();
+        ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        ranges.iter()
+            .find_map(|(media_type, _)| match media_type.to_ascii_lowercase().as_str() {
+                "application/openmetrics-text" => Some(ExpositionFormat::OpenMetrics),
+                "application/json" => Some(ExpositionFormat::Json),
+                "text/plain" | "text/*" | "*/*" => Some(ExpositionFormat::Prometheus),
+                _ => None,
+            })
+            .unwrap_or(ExpositionFormat::Prometheus)
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExpositionFormat::Prometheus => PROMETHEUS_CONTENT_TYPE,
+            ExpositionFormat::OpenMetrics => OPENMETRICS_CONTENT_TYPE,
+            ExpositionFormat::Json => JSON_CONTENT_TYPE,
+        }
+    }
+
+    pub fn render(&self, prometheus_text: String) -> String {
+        match self {
+            ExpositionFormat::Prometheus => prometheus_text,
+            ExpositionFormat::OpenMetrics => to_openmetrics(&parse(&prometheus_text)),
+            ExpositionFormat::Json => to_json(&parse(&prometheus_text)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricFamily {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub help: Option<String>,
+    pub samples: Vec<Sample>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Sample {
+    pub name: String,
+    pub labels: BTreeMap<String, String>,
+    #[serde(skip)]
+    pub raw_labels: String,
+    #[serde(skip)]
+    pub raw_value: String,
+    pub value: Option<f64>,
+}
+
+pub fn parse(text: &str) -> Vec<MetricFamily> {
+    let mut families: Vec<MetricFamily> = Vec::new();
+    let mut help: Option<(String, String)> = None;
+
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if let Some(rest) = line.strip_prefix("# HELP ") {
+            if let Some((name, text)) = rest.split_once(' ') {
+                help = Some((name.to_string(), text.to_string()));
+            }
+            continue;
+        }
+        if let Some(rest) = line.strip_prefix("# TYPE ") {
+            if let Some((name, kind)) = rest.split_once(' ') {
+                families.push(MetricFamily {
+                    name: name.to_string(),
+                    kind: kind.to_string(),
+                    help: help.take().filter(|(help_name, _)| help_name == name).map(|(_, text)| text),
+                    samples: Vec::new(),
+                });
+            }
+            continue;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+
+        let Some(sample) = parse_sample(line) else {
+            continue;
+        };
+        let belongs = |family: &MetricFamily| sample.name == family.name
+            || ["_sum", "_count", "_bucket"].iter().any(|suffix| sample.name.strip_suffix(suffix) == Some(family.name.as_str()));
+        match families.iter_mut().rev().find(|family| belongs(family)) {
+            Some(family) => family.samples.push(sample),
+            None => families.push(MetricFamily {
+                name: sample.name.clone(),
+                kind: "untyped".to_string(),
+                help: None,
+                samples: vec![sample],
+            }),
+        }
+    }
+
+    families
+}
+
+fn parse_sample(line: &str) -> Option<Sample> {
+    let name_end = line.find(|c: char| c == '{' || c.is_whitespace())?;
+    let name = line[..name_end].to_string();
+    let mut rest = &line[name_end..];
+    let mut labels = BTreeMap::new();
+    let mut raw_labels = String::new();
+
+    if rest.starts_with('{') {
+        let close = label_block_end(rest)?;
+        raw_labels = rest[1..close].to_string();
+        labels = parse_labels(&raw_labels)?;
+        rest = &rest[close + 1..];
+    }
+
+    let raw_value = rest.split_whitespace().next()?.to_string();
+    let value = match raw_value.as_str() {
+        "+Inf" | "Inf" => Some(f64::INFINITY),
+        "-Inf" => Some(f64::NEG_INFINITY),
+        value => value.parse::<f64>().ok(),
+    }.filter(|value| value.is_finite());
+
+    Some(Sample { name, labels, raw_labels, raw_value, value })
+}
+
+fn label_block_end(block: &str) -> Option<usize> {
+    let mut in_value = false;
+    let mut escaped = false;
+    for (index, c) in block.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_value => escaped = true,
+            '"' => in_value = !in_value,
+            '}' if !in_value => return Some(index),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn parse_labels(raw: &str) -> Option<BTreeMap<String, String>> {
+    let mut labels = BTreeMap::new();
+    let mut chars = raw.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| *c == ',' || c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            return Some(labels);
+        }
+
+        let mut name = String::new();
+        while let Some(c) = chars.next_if(|c| *c != '=') {
+            name.push(c);
+        }
+        if chars.next() != Some('=') || chars.next() != Some('"') {
+            return None;
+        }
+
+        let mut value = String::new();
+        loop {
+            match chars.next()? {
+                '"' => break,
+                '\\' => match chars.next()? {
+                    'n' => value.push('\n'),
+                    other => value.push(other),
+                },
+                c => value.push(c),
+            }
+        }
+        labels.insert(name.trim().to_string(), value);
+    }
+}
+
+pub fn to_openmetrics(families: &[MetricFamily]) -> String {
+    let mut output = String::new();
+
+    for family in families {
+        let (name, kind) = match family.kind.as_str() {
+            "counter" => match family.name.strip_suffix("_total") {
+                Some(name) => (name, "counter"),
+                None => (family.name.as_str(), "unknown"),
+            },
+            "gauge" | "summary" | "histogram" => (family.name.as_str(), family.kind.as_str()),
+            _ => (family.name.as_str(), "unknown"),
+        };
+
+        output.push_str(&format!("# TYPE {} {}\n", name, kind));
+        if let Some(ref help) = family.help {
+            output.push_str(&format!("# HELP {} {}\n", name, help));
+        }
+        for sample in &family.samples {
+            output.push_str(&sample.name);
+            if !sample.raw_labels.is_empty() {
+                output.push('{');
+                output.push_str(&sample.raw_labels);
+                output.push('}');
+            }
+            output.push(' ');
+            output.push_str(&sample.raw_value);
+            output.push('\n');
+        }
+    }
+
+    output.push_str("# EOF\n");
+    output
+}
+
+pub fn to_json(families: &[MetricFamily]) -> String {
+    serde_json::to_string(families).unwrap_or_else(|_| "[]".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "# HELP turbogate_connections_total Accepted connections\n\
+                        # TYPE turbogate_connections_total counter\n\
+                        turbogate_connections_total{frontend=\"web\",note=\"a \\\"b\\\" }\"} 12\n\
+                        # TYPE turbogate_active gauge\n\
+                        turbogate_active 3\n\
+                        # TYPE turbogate_latency summary\n\
+                        turbogate_latency{quantile=\"0.5\"} NaN\n\
+                        turbogate_latency_sum 1.5\n\
+                        turbogate_latency_count 4\n\
+                        turbogate_orphan 1\n";
+
+    #[test]
+    fn accept_headers_pick_the_best_supported_format() {
+        assert_eq!(ExpositionFormat::negotiate(None), ExpositionFormat::Prometheus);
+        assert_eq!(ExpositionFormat::negotiate(Some("application/json")), ExpositionFormat::Json);
+        assert_eq!(ExpositionFormat::negotiate(Some("text/plain;q=0.5, application/openmetrics-text;q=0.9")), ExpositionFormat::OpenMetrics);
+        assert_eq!(ExpositionFormat::negotiate(Some("application/json;q=0, */*")), ExpositionFormat::Prometheus);
+        assert_eq!(ExpositionFormat::negotiate(Some("image/png")), ExpositionFormat::Prometheus);
+        assert_eq!(ExpositionFormat::negotiate(Some("APPLICATION/JSON")), ExpositionFormat::Json);
+    }
+
+    #[test]
+    fn format_names_parse() {
+        assert_eq!(ExpositionFormat::parse("text").unwrap(), ExpositionFormat::Prometheus);
+        assert_eq!(ExpositionFormat::parse("openmetrics").unwrap().content_type(), OPENMETRICS_CONTENT_TYPE);
+        assert_eq!(ExpositionFormat::parse("xml").unwrap_err().to_string(),
+                   "Unknown metrics format 'xml', expected prometheus, openmetrics or json");
+    }
+
+    #[test]
+    fn samples_join_their_family() {
+        let families = parse(TEXT);
+        let names: Vec<(&str, &str, usize)> = families.iter()
+            .map(|family| (family.name.as_str(), family.kind.as_str(), family.samples.len()))
+            .collect();
+        assert_eq!(names, [
+            ("turbogate_connections_total", "counter", 1),
+            ("turbogate_active", "gauge", 1),
+            ("turbogate_latency", "summary", 3),
+            ("turbogate_orphan", "untyped", 1),
+        ]);
+        assert_eq!(families[0].help.as_deref(), Some("Accepted connections"));
+        assert_eq!(families[0].samples[0].labels["note"], "a \"b\" }");
+        assert_eq!(families[0].samples[0].value, Some(12.0));
+        assert_eq!(families[2].samples[0].value, None, "NaN has no JSON value");
+    }
+
+    #[test]
+    fn openmetrics_renames_counters_and_ends_with_eof() {
+        let output = to_openmetrics(&parse(TEXT));
+        assert!(output.starts_with("# TYPE turbogate_connections counter\n# HELP turbogate_connections Accepted connections\n\
+                                    turbogate_connections_total{frontend=\"web\",note=\"a \\\"b\\\" }\"} 12\n"), "{output}");
+        assert!(output.contains("# TYPE turbogate_orphan unknown\n"));
+        assert!(output.ends_with("turbogate_orphan 1\n# EOF\n"));
+    }
+
+    #[test]
+    fn json_keeps_labels_as_objects() {
+        let json: serde_json::Value = serde_json::from_str(&ExpositionFormat::Json.render(TEXT.to_string())).unwrap();
+        assert_eq!(json[0]["type"], "counter");
+        assert_eq!(json[0]["samples"][0]["labels"]["frontend"], "web");
+        assert_eq!(json[1]["samples"][0]["value"], 3.0);
+        assert!(json[1].get("help").is_none());
+        assert!(json[2]["samples"][0]["value"].is_null());
+    }
+}
@@ -3,40 +3,87 @@ use anyhow::Result;
 use tracing::{info, debug, warn};
 
 use crate::config::Config;
-use crate::rate_limit::RateLimiter;
+use crate::rate_limit::{ProtectionMode, RateLimitKey, RateLimiter};
 use crate::ddos_protection::DdosProtection;
+use crate::events::EventBus;
+use crate::error_log::ErrorLog;
+use crate::logging::LogThrottle;
+use crate::limits::{FairAccept, FdLimits, RuntimeLimit, DEFAULT_MAXCONN};
 use crate::hot_reload::HotReload;
 use crate::compression::Compressor;
+use crate::drain::DrainState;
+use crate::audit::AuditLog;
+use crate::geoip::GeoIp;
+use crate::resolver::{Resolver, ReverseDns};
+use crate::stats::ProcessStats;
+use crate::metrics::MetricsHandle;
+use std::collections::HashMap;
 
 pub struct FeaturesManager {
     pub rate_limiter: Option<RateLimiter>,
     pub ddos_protection: Option<DdosProtection>,
     pub hot_reload: Option<HotReload>,
     pub compressor: Option<Compressor>,
+    pub resolvers: HashMap<String, Arc<Resolver>>,
+    pub reverse_dns: Option<Arc<ReverseDns>>,
+    pub events: Arc<EventBus>,
+    pub errors: Arc<ErrorLog>,
+    pub log_throttle: Arc<LogThrottle>,
+    pub maxconn: Arc<RuntimeLimit>,
+    pub fds: Arc<FdLimits>,
+    pub fair_accept: Arc<FairAccept>,
+    pub drain: Arc<DrainState>,
+    pub audit: Arc<AuditLog>,
+    pub geoip: Arc<GeoIp>,
+    pub process: Arc<ProcessStats>,
+    pub metrics_server: MetricsHandle,
     pub config: Arc<Config>,
+    pub config_path: String,
+    pub allow_degraded: bool,
 }
 
 impl FeaturesManager {
-    pub fn new(config: Arc<Config>) -> Result<Self> {
+    pub fn new(config: Arc<Config>, config_path: &str, allow_degraded: bool) -> Result<Self> {
         let mut features = Self {
             rate_limiter: None,
             ddos_protection: None,
             hot_reload: None,
             compressor: None,
+            resolvers: HashMap::new(),
+            reverse_dns: None,
+            events: Arc::new(EventBus::new()),
+            errors: Arc::new(ErrorLog::new()),
+            log_throttle: Arc::new(LogThrottle::new(&config.global)),
+            maxconn: Arc::new(RuntimeLimit::new(config.global.maxconn.unwrap_or(DEFAULT_MAXCONN))),
+            fds: Arc::new(FdLimits::default()),
+            fair_accept: Arc::new(FairAccept::default()),
+            drain: Arc::new(DrainState::default()),
+            audit: Arc::new(AuditLog::default()),
+            geoip: Arc::new(GeoIp::default()),
+            process: Arc::new(ProcessStats::default()),
+            metrics_server: MetricsHandle::default(),
             config,
+            config_path: config_path.to_string(),
+            allow_degraded,
         };
 
-        features.initialize_features()?;
+        features.fair_accept.configure(&features.config.global);
+        features.drain.configure(&features.config.global);
+        features.events.configure(&features.config.global);
+        features.audit.configure(&features.config.global)?;
+        features.geoip.configure(&features.config.global)?;
+        features.initialize_features(config_path)?;
         Ok(features)
     }
 
-    fn initialize_features(&mut self) -> Result<()> {
+    fn initialize_features(&mut self, config_path: &str) -> Result<()> {
         info!("Initializing Turbogate features...");
 
         self.initialize_rate_limiting()?;
         self.initialize_ddos_protection()?;
-        self.initialize_hot_reload()?;
+        self.initialize_hot_reload(config_path)?;
         self.initialize_compression()?;
+        self.initialize_resolvers();
 
         info!("All features initialized successfully");
         Ok(())
@@ -48,6 +95,8 @@ impl FeaturesManager {
                 requests_per_second: rate_limit_config.requests_per_second,
                 burst_size: rate_limit_config.burst_size,
                 window_size: std::time::Duration::from_secs(rate_limit_config.window_size),
+                key: RateLimitKey::parse(&rate_limit_config.key)?,
+                mode: ProtectionMode::parse(&rate_limit_config.mode)?,
             });
             self.rate_limiter = Some(rate_limiter);
         }
@@ -100,17 +149,34 @@ impl FeaturesManager {
                 suspicious_patterns: ddos_config.suspicious_patterns.clone(),
                 whitelist,
                 blacklist,
+                mode: ProtectionMode::parse(&ddos_config.mode)?,
             });
             self.ddos_protection = Some(ddos_protection);
         }
         Ok(())
     }
 
-    fn initialize_hot_reload(&mut self) -> Result<()> {
+    pub fn apply_protection_modes(&self, config: &Config) {
+        if let (Some(rate_limiter), Some(rate_limit_config)) = (&self.rate_limiter, &config.rate_limit) {
+            match ProtectionMode::parse(&rate_limit_config.mode) {
+                Ok(mode) => rate_limiter.set_mode(mode),
+                Err(e) => warn!("Ignoring reloaded rate-limit mode: {}", e),
+            }
+        }
+
+        if let (Some(ddos_protection), Some(ddos_config)) = (&self.ddos_protection, &config.ddos_protection) {
+            match ProtectionMode::parse(&ddos_config.mode) {
+                Ok(mode) => ddos_protection.set_mode(mode),
+                Err(e) => warn!("Ignoring reloaded ddos-protection mode: {}", e),
+            }
+        }
+    }
+
+    fn initialize_hot_reload(&mut self, config_path: &str) -> Result<()> {
         if let Some(hot_reload_config) = &self.config.hot_reload {
             if hot_reload_config.enabled {
                 info!("Initializing hot reload...");
-                let hot_reload = HotReload::new("test.cfg".to_string())?;
+                let hot_reload = HotReload::new(config_path.to_string(), Arc::clone(&self.events), Arc::clone(&self.audit), self.allow_degraded)?;
                 hot_reload.start_watching()?;
                 self.hot_reload = Some(hot_reload);
                 debug!("Hot reload enabled with interval: {}s", hot_reload_config.watch_interval);
@@ -119,6 +185,20 @@ impl FeaturesManager {
         Ok(())
     }
 
+    fn initialize_resolvers(&mut self) {
+        for resolvers_config in &self.config.resolvers {
+            info!("Initializing resolvers '{}' with {} nameservers", resolvers_config.name, resolvers_config.nameservers.len());
+            self.resolvers.insert(resolvers_config.name.clone(), Arc::new(Resolver::new(resolvers_config.clone())));
+        }
+
+        if let Some(name) = &self.config.global.src_dn_resolvers {
+            if let Some(resolver) = self.resolvers.get(name) {
+                info!("Reverse DNS for src_dn ACLs uses resolvers '{}'", name);
+                self.reverse_dns = Some(Arc::new(ReverseDns::new(Arc::clone(resolver), &self.config.global)));
+            }
+        }
+    }
+
     fn initialize_compression(&mut self) -> Result<()> {
         if let Some(compression_config) = &self.config.compression {
             info!("Initializing compression...");
@@ -0,0 +1,587 @@
+use crate::config::GlobalConfig;
+use anyhow::{Result, anyhow};
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::net::IpAddr;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, RwLock};
+use tracing::{debug, info, warn};
+
+const METADATA_MARKER: &[u8] = b"\xab\xcd\xefMaxMind.com";
+const METADATA_MAX_SIZE: usize = 128 * 1024;
+const DATA_SECTION_SEPARATOR: usize = 16;
+const MAX_POINTER_DEPTH: usize = 8;
+pub const OTHER_COUNTRIES: &str = "other";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Country([u8; 2]);
+
+impl Country {
+    pub const UNKNOWN: Country = Country(*b"ZZ");
+
+    pub fn parse(code: &str) -> Option<Self> {
+        match code.as_bytes() {
+            [a, b] if a.is_ascii_alphabetic() && b.is_ascii_alphabetic() => {
+                Some(Country([a.to_ascii_uppercase(), b.to_ascii_uppercase()]))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.0).unwrap_or("ZZ")
+    }
+}
+
+impl Default for Country {
+    fn default() -> Self {
+        Country::UNKNOWN
+    }
+}
+
+impl fmt::Display for Country {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Default)]
+pub struct GeoIp {
+    database: RwLock<Option<Arc<GeoDatabase>>>,
+    metric_countries: RwLock<Vec<Country>>,
+}
+
+impl GeoIp {
+    pub fn configure(&self, global: &GlobalConfig) -> Result<()> {
+        *self.metric_countries.write().unwrap_or_else(|e| e.into_inner()) = global.geoip_metrics_countries.iter()
+            .filter_map(|code| Country::parse(code))
+            .collect();
+
+        let Some(ref path) = global.geoip_db else {
+            if self.database.write().unwrap_or_else(|e| e.into_inner()).take().is_some() {
+                info!("GeoIP disabled, every client is now country {}", Country::UNKNOWN);
+            }
+            return Ok(());
+        };
+        if self.current().is_some_and(|database| &database.path == path) {
+            return Ok(());
+        }
+        let database = GeoDatabase::open(path)?;
+        info!("GeoIP database {} loaded ({}, {} nodes)", path, database.reader.database_type, database.reader.node_count);
+        *self.database.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(database));
+        Ok(())
+    }
+
+    pub fn refresh(&self) {
+        let Some(current) = self.current() else {
+            return;
+        };
+        match FileStamp::read(&current.path) {
+            Ok(stamp) if stamp == current.stamp => return,
+            Ok(_) => {}
+            Err(e) => {
+                debug!("Cannot stat GeoIP database {}: {}", current.path, e);
+                return;
+            }
+        }
+        match GeoDatabase::open(&current.path) {
+            Ok(database) => {
+                info!("GeoIP database {} changed on disk, reloaded ({} nodes)", current.path, database.reader.node_count);
+                let mut slot = self.database.write().unwrap_or_else(|e| e.into_inner());
+                if slot.as_ref().is_some_and(|slot| Arc::ptr_eq(slot, &current)) {
+                    *slot = Some(Arc::new(database));
+                }
+            }
+            Err(e) => warn!("GeoIP database {} changed on disk but cannot be loaded, keeping the previous one: {}", current.path, e),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.current().is_some()
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> Country {
+        let Some(database) = self.current() else {
+            return Country::UNKNOWN;
+        };
+        match database.reader.country(ip.to_canonical()) {
+            Ok(country) => country.unwrap_or(Country::UNKNOWN),
+            Err(e) => {
+                debug!("GeoIP lookup for {} failed: {}", ip, e);
+                Country::UNKNOWN
+            }
+        }
+    }
+
+    pub fn metric_label(&self, country: Country) -> Option<String> {
+        let countries = self.metric_countries.read().unwrap_or_else(|e| e.into_inner());
+        if countries.is_empty() {
+            return None;
+        }
+        if countries.contains(&country) {
+            Some(country.to_string())
+        } else {
+            Some(OTHER_COUNTRIES.to_string())
+        }
+    }
+
+    fn current(&self) -> Option<Arc<GeoDatabase>> {
+        self.database.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+struct GeoDatabase {
+    path: String,
+    stamp: FileStamp,
+    reader: MmdbReader,
+}
+
+impl GeoDatabase {
+    fn open(path: &str) -> Result<Self> {
+        let stamp = FileStamp::read(path).map_err(|e| anyhow!("Cannot open GeoIP database {}: {}", path, e))?;
+        let map = Mmap::open(path).map_err(|e| anyhow!("Cannot map GeoIP database {}: {}", path, e))?;
+        let reader = MmdbReader::new(map).map_err(|e| anyhow!("Invalid GeoIP database {}: {}", path, e))?;
+        Ok(Self { path: path.to_string(), stamp, reader })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileStamp {
+    dev: u64,
+    ino: u64,
+    len: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+}
+
+impl FileStamp {
+    fn read(path: &str) -> io::Result<Self> {
+        let metadata = std::fs::metadata(path)?;
+        Ok(Self {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            len: metadata.len(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+        })
+    }
+}
+
+struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    fn open(path: &str) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "file too large"))?;
+        if len == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "file is empty"));
+        }
+        let ptr = unsafe { libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ, libc::MAP_PRIVATE, file.as_raw_fd(), 0) };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { ptr, len })
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr, self.len) };
+    }
+}
+
+struct MmdbReader {
+    map: Mmap,
+    node_count: usize,
+    record_size: usize,
+    ip_version: u64,
+    database_type: String,
+    tree_size: usize,
+    ipv4_start: usize,
+}
+
+impl MmdbReader {
+    fn new(map: Mmap) -> Result<Self> {
+        let buffer = map.as_slice();
+        let window = buffer.len().saturating_sub(METADATA_MAX_SIZE);
+        let metadata_start = buffer[window..].windows(METADATA_MARKER.len())
+            .rposition(|candidate| candidate == METADATA_MARKER)
+            .map(|position| window + position + METADATA_MARKER.len())
+            .ok_or_else(|| anyhow!("no MaxMind metadata section"))?;
+
+        let metadata = Decoder { data: &buffer[metadata_start..] };
+        let (node_count, record_size, ip_version, database_type) = {
+            let (map_len, entries) = metadata.map_at(0)?;
+            let field = |name: &str| metadata.find(entries, map_len, name);
+            let uint = |name: &str| -> Result<u64> {
+                match field(name)?.map(|offset| metadata.decode(offset)).transpose()? {
+                    Some((Value::Uint(value), _)) => Ok(value),
+                    _ => Err(anyhow!("metadata has no '{}'", name)),
+                }
+            };
+            let database_type = match field("database_type")?.map(|offset| metadata.decode(offset)).transpose()? {
+                Some((Value::String(value), _)) => value.to_string(),
+                _ => "unknown".to_string(),
+            };
+            (uint("node_count")? as usize, uint("record_size")? as usize, uint("ip_version")?, database_type)
+        };
+        if !matches!(record_size, 24 | 28 | 32) {
+            return Err(anyhow!("unsupported record size {}", record_size));
+        }
+        if !matches!(ip_version, 4 | 6) {
+            return Err(anyhow!("unsupported IP version {}", ip_version));
+        }
+        let tree_size = node_count * record_size / 4;
+        if tree_size + DATA_SECTION_SEPARATOR > metadata_start {
+            return Err(anyhow!("search tree of {} nodes does not fit in the file", node_count));
+        }
+
+        let mut reader = Self { map, node_count, record_size, ip_version, database_type, tree_size, ipv4_start: 0 };
+        if ip_version == 6 {
+            let mut node = 0;
+            for _ in 0..96 {
+                if node >= node_count {
+                    break;
+                }
+                node = reader.record(node, 0)?;
+            }
+            reader.ipv4_start = node;
+        }
+        Ok(reader)
+    }
+
+    fn country(&self, ip: IpAddr) -> Result<Option<Country>> {
+        let (bytes, start): (Vec<u8>, usize) = match ip {
+            IpAddr::V4(ip) => (ip.octets().to_vec(), self.ipv4_start),
+            IpAddr::V6(_) if self.ip_version == 4 => return Ok(None),
+            IpAddr::V6(ip) => (ip.octets().to_vec(), 0),
+        };
+
+        let mut node = start;
+        for bit in 0..bytes.len() * 8 {
+            if node >= self.node_count {
+                break;
+            }
+            node = self.record(node, (bytes[bit / 8] >> (7 - bit % 8)) & 1)?;
+        }
+        if node <= self.node_count {
+            return Ok(None);
+        }
+
+        let data = Decoder { data: &self.map.as_slice()[self.tree_size + DATA_SECTION_SEPARATOR..] };
+        let offset = node - self.node_count - DATA_SECTION_SEPARATOR;
+        let (map_len, entries) = data.map_at(offset)?;
+        for key in ["country", "registered_country"] {
+            let Some(offset) = data.find(entries, map_len, key)? else {
+                continue;
+            };
+            let (country_len, country_entries) = data.map_at(offset)?;
+            if let Some(offset) = data.find(country_entries, country_len, "iso_code")? {
+                if let (Value::String(code), _) = data.decode(offset)? {
+                    return Ok(Country::parse(code));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn record(&self, node: usize, bit: u8) -> Result<usize> {
+        let bytes = self.map.as_slice();
+        let width = self.record_size / 4;
+        let record = bytes.get(node * width..(node + 1) * width)
+            .ok_or_else(|| anyhow!("node {} is outside the search tree", node))?;
+        let be = |bytes: &[u8]| bytes.iter().fold(0usize, |value, byte| (value << 8) | *byte as usize);
+        Ok(match (self.record_size, bit) {
+            (24, 0) => be(&record[0..3]),
+            (24, _) => be(&record[3..6]),
+            (28, 0) => ((record[3] as usize & 0xf0) << 20) | be(&record[0..3]),
+            (28, _) => ((record[3] as usize & 0x0f) << 24) | be(&record[4..7]),
+            (_, 0) => be(&record[0..4]),
+            (_, _) => be(&record[4..8]),
+        })
+    }
+}
+
+enum Value<'a> {
+    String(&'a str),
+    Uint(u64),
+    Other,
+}
+
+struct Field {
+    kind: u8,
+    size: usize,
+    payload: usize,
+    pointer_end: Option<usize>,
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn bytes(&self, offset: usize, len: usize) -> Result<&'a [u8]> {
+        self.data.get(offset..offset + len).ok_or_else(|| anyhow!("data at {} runs past the end of the section", offset))
+    }
+
+    fn number(&self, offset: usize, len: usize) -> Result<usize> {
+        Ok(self.bytes(offset, len)?.iter().fold(0usize, |value, byte| (value << 8) | *byte as usize))
+    }
+
+    fn decode(&self, offset: usize) -> Result<(Value<'a>, usize)> {
+        let field = self.field(offset, 0)?;
+        let value = match field.kind {
+            2 => Value::String(std::str::from_utf8(self.bytes(field.payload, field.size)?)
+                .map_err(|_| anyhow!("invalid UTF-8 string at {}", field.payload))?),
+            5 | 6 | 9 | 10 if field.size <= 8 => Value::Uint(self.number(field.payload, field.size)? as u64),
+            _ => Value::Other,
+        };
+        let end = match field.pointer_end {
+            Some(end) => end,
+            None => self.skip(&field)?,
+        };
+        Ok((value, end))
+    }
+
+    fn skip(&self, field: &Field) -> Result<usize> {
+        let elements = match field.kind {
+            2 | 4 | 5 | 6 | 8 | 9 | 10 => return Ok(field.payload + field.size),
+            3 => return Ok(field.payload + 8),
+            15 => return Ok(field.payload + 4),
+            14 => return Ok(field.payload),
+            7 => field.size * 2,
+            11 => field.size,
+            kind => return Err(anyhow!("unsupported data type {} at {}", kind, field.payload)),
+        };
+        let mut next = field.payload;
+        for _ in 0..elements {
+            next = self.decode(next)?.1;
+        }
+        Ok(next)
+    }
+
+    fn field(&self, offset: usize, depth: usize) -> Result<Field> {
+        let control = self.bytes(offset, 1)?[0];
+        let mut offset = offset + 1;
+        let mut kind = control >> 5;
+
+        if kind == 1 {
+            if depth >= MAX_POINTER_DEPTH {
+                return Err(anyhow!("too many nested pointers"));
+            }
+            let high = (control & 0x07) as usize;
+            let (target, len) = match (control >> 3) & 0x03 {
+                0 => ((high << 8) | self.number(offset, 1)?, 1),
+                1 => (((high << 16) | self.number(offset, 2)?) + 2048, 2),
+                2 => (((high << 24) | self.number(offset, 3)?) + 526_336, 3),
+                _ => (self.number(offset, 4)?, 4),
+            };
+            let field = self.field(target, depth + 1)?;
+            return Ok(Field { pointer_end: Some(offset + len), ..field });
+        }
+        if kind == 0 {
+            kind = 7 + self.bytes(offset, 1)?[0];
+            offset += 1;
+        }
+
+        let (size, extra) = match control & 0x1f {
+            29 => (29 + self.number(offset, 1)?, 1),
+            30 => (285 + self.number(offset, 2)?, 2),
+            31 => (65_821 + self.number(offset, 3)?, 3),
+            size => (size as usize, 0),
+        };
+        Ok(Field { kind, size, payload: offset + extra, pointer_end: None })
+    }
+
+    fn map_at(&self, offset: usize) -> Result<(usize, usize)> {
+        match self.field(offset, 0)? {
+            Field { kind: 7, size, payload, .. } => Ok((size, payload)),
+            _ => Err(anyhow!("expected a map at {}", offset)),
+        }
+    }
+    fn find(&self, entries: usize, len: usize, key: &str) -> Result<Option<usize>> {
+        let mut offset = entries;
+        for _ in 0..len {
+            let (name, value) = self.decode(offset)?;
+            if matches!(name, Value::String(name) if name == key) {
+                return Ok(Some(value));
+            }
+            offset = self.decode(value)?.1;
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv6Addr;
+
+    fn string(value: &str) -> Vec<u8> {
+        let mut bytes = vec![0x40 | value.len() as u8];
+        bytes.extend_from_slice(value.as_bytes());
+        bytes
+    }
+
+    // A one-node IPv4 tree: 0.0.0.0/1 maps to `code`, 128.0.0.0/1 has no data.
+    fn database(code: &str) -> Vec<u8> {
+        let node_count = 1usize;
+        let mut data = vec![0xe1];
+        data.extend(string("country"));
+        data.push(0xe1);
+        data.extend(string("iso_code"));
+        data.extend(string(code));
+
+        let left = (node_count + DATA_SECTION_SEPARATOR) as u32;
+        let right = node_count as u32;
+        let mut file = Vec::new();
+        file.extend_from_slice(&left.to_be_bytes()[1..]);
+        file.extend_from_slice(&right.to_be_bytes()[1..]);
+        file.extend_from_slice(&[0; DATA_SECTION_SEPARATOR]);
+        file.extend(data);
+        file.extend_from_slice(METADATA_MARKER);
+        file.push(0xe4);
+        file.extend(string("node_count"));
+        file.extend([0xc1, node_count as u8]);
+        file.extend(string("record_size"));
+        file.extend([0xa1, 24]);
+        file.extend(string("ip_version"));
+        file.extend([0xa1, 4]);
+        file.extend(string("database_type"));
+        file.extend(string("Test-Country"));
+        file
+    }
+
+    fn write(path: &std::path::Path, bytes: &[u8]) {
+        let staging = path.with_extension("tmp");
+        std::fs::write(&staging, bytes).unwrap();
+        std::fs::rename(&staging, path).unwrap();
+    }
+
+    fn global(db: Option<&std::path::Path>, countries: &[&str]) -> GlobalConfig {
+        GlobalConfig {
+            geoip_db: db.map(|path| path.to_string_lossy().into_owned()),
+            geoip_metrics_countries: countries.iter().map(|code| code.to_string()).collect(),
+            ..GlobalConfig::default()
+        }
+    }
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn country_codes_are_two_letters_uppercased() {
+        assert_eq!(Country::parse("nl").map(|c| c.to_string()).as_deref(), Some("NL"));
+        assert_eq!(Country::parse("De").map(|c| c.to_string()).as_deref(), Some("DE"));
+        for invalid in ["", "N", "N1", "NLD", "é"] {
+            assert_eq!(Country::parse(invalid), None, "{:?}", invalid);
+        }
+        assert_eq!(Country::default(), Country::UNKNOWN);
+        assert_eq!(Country::UNKNOWN.as_str(), "ZZ");
+    }
+
+    #[test]
+    fn without_a_database_every_client_is_unknown() {
+        let geoip = GeoIp::default();
+        geoip.configure(&global(None, &[])).unwrap();
+        assert!(!geoip.enabled());
+        assert_eq!(geoip.lookup(ip("192.0.2.1")), Country::UNKNOWN);
+        assert_eq!(geoip.metric_label(Country::UNKNOWN), None);
+    }
+
+    #[test]
+    fn metric_labels_fold_unlisted_countries_into_other() {
+        let geoip = GeoIp::default();
+        geoip.configure(&global(None, &["nl", "DE"])).unwrap();
+        assert_eq!(geoip.metric_label(Country::parse("NL").unwrap()).as_deref(), Some("NL"));
+        assert_eq!(geoip.metric_label(Country::parse("de").unwrap()).as_deref(), Some("DE"));
+        assert_eq!(geoip.metric_label(Country::parse("FR").unwrap()).as_deref(), Some(OTHER_COUNTRIES));
+        assert_eq!(geoip.metric_label(Country::UNKNOWN).as_deref(), Some(OTHER_COUNTRIES));
+
+        geoip.configure(&global(None, &[])).unwrap();
+        assert_eq!(geoip.metric_label(Country::parse("NL").unwrap()), None);
+    }
+
+    #[test]
+    fn lookups_walk_the_search_tree() {
+        let dir = tempfile_dir("lookup");
+        let path = dir.join("country.mmdb");
+        write(&path, &database("nl"));
+
+        let geoip = GeoIp::default();
+        geoip.configure(&global(Some(&path), &[])).unwrap();
+        assert!(geoip.enabled());
+        assert_eq!(geoip.lookup(ip("10.1.2.3")), Country::parse("NL").unwrap());
+        assert_eq!(geoip.lookup(ip("::ffff:10.1.2.3")), Country::parse("NL").unwrap());
+        assert_eq!(geoip.lookup(ip("192.0.2.1")), Country::UNKNOWN);
+        assert_eq!(geoip.lookup(IpAddr::V6(Ipv6Addr::LOCALHOST)), Country::UNKNOWN);
+
+        geoip.configure(&global(None, &[])).unwrap();
+        assert!(!geoip.enabled());
+        assert_eq!(geoip.lookup(ip("10.1.2.3")), Country::UNKNOWN);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn refresh_reloads_a_replaced_database_and_keeps_the_old_one_on_errors() {
+        let dir = tempfile_dir("refresh");
+        let path = dir.join("country.mmdb");
+        write(&path, &database("NL"));
+        let geoip = GeoIp::default();
+        geoip.configure(&global(Some(&path), &[])).unwrap();
+
+        geoip.refresh();
+        assert_eq!(geoip.lookup(ip("10.0.0.1")), Country::parse("NL").unwrap());
+
+        write(&path, &database("DE"));
+        geoip.refresh();
+        assert_eq!(geoip.lookup(ip("10.0.0.1")), Country::parse("DE").unwrap());
+
+        write(&path, b"not a database");
+        geoip.refresh();
+        assert_eq!(geoip.lookup(ip("10.0.0.1")), Country::parse("DE").unwrap());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_databases_are_rejected() {
+        let dir = tempfile_dir("invalid");
+        let missing = dir.join("missing.mmdb");
+        let err = GeoIp::default().configure(&global(Some(&missing), &[])).unwrap_err();
+        assert!(err.to_string().starts_with("Cannot open GeoIP database"), "{}", err);
+
+        let garbage = dir.join("garbage.mmdb");
+        write(&garbage, b"not a database");
+        let err = GeoIp::default().configure(&global(Some(&garbage), &[])).unwrap_err();
+        assert!(err.to_string().contains("no MaxMind metadata section"), "{}", err);
+
+        let odd = dir.join("odd.mmdb");
+        let mut bytes = database("NL");
+        let field = bytes.windows(11).rposition(|window| window == b"record_size").unwrap();
+        bytes[field + 12] = 20;
+        write(&odd, &bytes);
+        let err = GeoIp::default().configure(&global(Some(&odd), &[])).unwrap_err();
+        assert!(err.to_string().contains("unsupported record size 20"), "{}", err);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn tempfile_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("turbogate-geoip-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+}
@@ -1,19 +1,46 @@
 use crate::config::{BackendConfig, ServerConfig};
+use crate::events::{EventBus, TurbogateEvent};
 use crate::logging;
 use crate::metrics;
+use crate::options::{HttpCheck, HttpCheckExpect, TcpCheckConnect};
+use crate::resolver::Resolver;
+use crate::session::ConnectFailure;
+use crate::status::{ServerReport, StatusTable};
+use crate::tasks::{TaskGroup, TaskRegistry};
+use crate::utils;
+use regex::Regex;
 use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
-use tokio::sync::RwLock;
+use futures::future::join_all;
+use tokio::sync::{RwLock, Semaphore};
+use tokio::task::{AbortHandle, JoinHandle};
 use tokio::time::sleep;
 use tracing::{debug, info, warn, error};
 
+const MAX_HTTP_CHECK_BODY: usize = 16 * 1024;
+const MAX_HTTP_CHECK_HEADERS: usize = 16 * 1024;
+const DEFAULT_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+const DEFAULT_RESOLVE_ON_DOWN_INTERVAL: Duration = Duration::from_secs(30);
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum ServerStatus {
     Up,
     Down,
+    Unchecked,
+}
+
+impl ServerStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ServerStatus::Up => "Up",
+            ServerStatus::Down => "Down",
+            ServerStatus::Unchecked => "Up(no-check)",
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -24,6 +51,11 @@ pub struct HealthState {
     pub consecutive_successes: u32,
     pub last_success: Option<Instant>,
     pub last_failure: Option<Instant>,
+    pub last_failure_reason: Option<ConnectFailure>,
+    pub last_duration: Option<Duration>,
+    pub interval: Duration,
+    pub next_check: Instant,
+    pub last_resolve: Option<Instant>,
 }
 
 impl Default for HealthState {
@@ -35,10 +67,107 @@ impl Default for HealthState {
             consecutive_successes: 0,
             last_success: None,
             last_failure: None,
+            last_failure_reason: None,
+            last_duration: None,
+            interval: DEFAULT_CHECK_INTERVAL,
+            next_check: Instant::now(),
+            last_resolve: None,
+        }
+    }
+}
+
+impl HealthState {
+    pub fn report(&self) -> ServerReport {
+        ServerReport {
+            status: self.status.clone(),
+            interval: Some(self.interval),
+            failure: self.last_failure_reason,
+            duration: self.last_duration,
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CheckIntervals {
+    pub inter: Duration,
+    pub fastinter: Duration,
+    pub downinter: Duration,
+}
+
+impl CheckIntervals {
+    pub fn new(server: &ServerConfig, default_inter: Duration) -> Self {
+        let parse = |value: &Option<String>| value.as_deref().and_then(|value| utils::parse_duration(value).ok());
+        let inter = parse(&server.inter).unwrap_or(default_inter);
+        Self {
+            inter,
+            fastinter: parse(&server.fastinter).unwrap_or(inter),
+            downinter: parse(&server.downinter).unwrap_or(inter),
+        }
+    }
+
+    pub fn select(&self, state: &HealthState, rise: u32, fall: u32) -> Duration {
+        match state.status {
+            ServerStatus::Down if state.consecutive_successes == 0 => self.downinter,
+            ServerStatus::Down if state.consecutive_successes < rise => self.fastinter,
+            ServerStatus::Up if state.consecutive_failures > 0 && state.consecutive_failures < fall => self.fastinter,
+            _ => self.inter,
+        }
+    }
+}
+
+pub struct HealthManager {
+    checkers: Vec<HealthChecker>,
+    tasks: Vec<AbortHandle>,
+}
+
+impl HealthManager {
+    pub fn new(
+        backends: &[BackendConfig],
+        max_check_conn: usize,
+        events: Arc<EventBus>,
+        resolvers: &HashMap<String, Arc<Resolver>>,
+    ) -> Self {
+        let budget = Arc::new(Semaphore::new(max_check_conn.max(1)));
+        let checkers = backends.iter()
+            .filter(|backend| backend.health_check.is_some())
+            .map(|backend| HealthChecker::new(backend.clone(), Arc::clone(&events), Arc::clone(&budget), resolvers))
+            .collect();
+
+        Self { checkers, tasks: Vec::new() }
+    }
+
+    pub fn start(&mut self, status_table: Arc<StatusTable>, registry: &TaskRegistry) {
+        let count = self.checkers.len() as u32;
+        for (position, checker) in self.checkers.iter().enumerate() {
+            let initial_delay = checker.check_interval() * position as u32 / count.max(1);
+            let handle = checker.start_with_callback(Arc::clone(&status_table), initial_delay);
+            self.tasks.push(registry.register(format!("health:{}", checker.config.name), TaskGroup::Health, handle));
+        }
+    }
+
+    pub fn stop(&mut self) {
+        for task in self.tasks.drain(..) {
+            task.abort();
+        }
+    }
+
+    pub async fn warm_up(&self, status_table: Arc<StatusTable>, timeout: Duration) {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let passes = self.checkers.iter().map(|checker| {
+            let status_table = Arc::clone(&status_table);
+            async move {
+                let backend_name = &checker.config.name;
+                match tokio::time::timeout_at(deadline, checker.warm_up(&status_table)).await {
+                    Ok(Some((active, total))) => info!("Warm-up: backend '{}' checked, {}/{} checked servers up", backend_name, active, total),
+                    Ok(None) => warn!("Warm-up: backend '{}' not found in status table, skipped", backend_name),
+                    Err(_) => warn!("Warm-up: backend '{}' did not finish its first check pass within {:?}", backend_name, timeout),
+                }
+            }
+        });
+        join_all(passes).await;
+    }
+}
+
 pub struct HealthChecker {
     backends: Arc<RwLock<HashMap<String, BackendHealthState>>>,
     config: BackendConfig,
@@ -46,14 +175,91 @@ pub struct HealthChecker {
 
 #[derive(Clone)]
 struct BackendHealthState {
+    name: String,
+    events: Arc<EventBus>,
+    budget: Arc<Semaphore>,
     servers: HashMap<String, HealthState>,
+    intervals: HashMap<String, CheckIntervals>,
+    resolvers: HashMap<String, Arc<Resolver>>,
+    resolve_on_down_interval: Duration,
     rise_threshold: u32,
     fall_threshold: u32,
     check_timeout: Duration,
+    connect_steps: Vec<TcpCheckConnect>,
+    http_check: Option<HttpHealthCheck>,
+    alert_threshold: Option<f64>,
+}
+
+#[derive(Clone)]
+struct HttpHealthCheck {
+    method: String,
+    path: String,
+    expect: Vec<ExpectMatcher>,
+}
+
+#[derive(Clone)]
+enum ExpectMatcher {
+    Status(u16, u16),
+    String(String),
+    Rstring(Regex),
+}
+
+impl HttpHealthCheck {
+    fn new(check: &HttpCheck) -> Self {
+        let expect = check.expect.iter()
+            .filter_map(|expect| match expect {
+                HttpCheckExpect::Status(low, high) => Some(ExpectMatcher::Status(*low, *high)),
+                HttpCheckExpect::String(pattern) => Some(ExpectMatcher::String(pattern.clone())),
+                HttpCheckExpect::Rstring(pattern) => match Regex::new(pattern) {
+                    Ok(regex) => Some(ExpectMatcher::Rstring(regex)),
+                    Err(e) => {
+                        error!("Ignoring invalid http-check expect rstring '{}': {}", pattern, e);
+                        None
+                    }
+                },
+            })
+            .collect();
+
+        Self {
+            method: check.method.clone(),
+            path: check.path.clone(),
+            expect,
+        }
+    }
+
+    fn needs_body(&self) -> bool {
+        self.expect.iter().any(|matcher| !matches!(matcher, ExpectMatcher::Status(..)))
+    }
+
+    fn evaluate(&self, status: u16, body: &str) -> anyhow::Result<()> {
+        if self.expect.is_empty() {
+            if (200..400).contains(&status) {
+                return Ok(());
+            }
+            return Err(anyhow::anyhow!("HTTP check returned status {}", status));
+        }
+
+        for matcher in &self.expect {
+            match matcher {
+                ExpectMatcher::Status(low, high) if !(*low..=*high).contains(&status) => {
+                    return Err(anyhow::anyhow!("HTTP check returned status {}, expected {}-{}", status, low, high));
+                }
+                ExpectMatcher::String(pattern) if !body.contains(pattern.as_str()) => {
+                    return Err(anyhow::anyhow!("HTTP check response does not contain '{}'", pattern));
+                }
+                ExpectMatcher::Rstring(regex) if !regex.is_match(body) => {
+                    return Err(anyhow::anyhow!("HTTP check response does not match '{}'", regex));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl HealthChecker {
-    pub fn new(config: BackendConfig) -> Self {
+    pub fn new(config: BackendConfig, events: Arc<EventBus>, budget: Arc<Semaphore>, resolvers: &HashMap<String, Arc<Resolver>>) -> Self {
         let mut servers = HashMap::new();
         let rise_threshold = config.health_check.as_ref()
             .map(|hc| hc.rise)
@@ -62,20 +268,47 @@ impl HealthChecker {
             .map(|hc| hc.fall)
             .unwrap_or(3);
         let check_timeout = config.health_check.as_ref()
-            .and_then(|hc| parse_duration(&hc.timeout))
+            .and_then(|hc| utils::parse_duration(&hc.timeout).ok())
             .unwrap_or(Duration::from_secs(1));
+        let connect_steps = config.options.as_ref()
+            .map(|options| options.tcp_options.tcp_check_connects.clone())
+            .unwrap_or_default();
+        let http_check = config.options.as_ref()
+            .and_then(|options| options.http_options.httpchk.as_ref())
+            .map(HttpHealthCheck::new);
 
+        let default_inter = config.health_check.as_ref()
+            .and_then(|hc| utils::parse_duration(&hc.interval).ok())
+            .unwrap_or(DEFAULT_CHECK_INTERVAL);
+        let mut intervals = HashMap::new();
         for server in &config.server {
             if server.check.unwrap_or(false) {
-                servers.insert(server.name.clone(), HealthState::default());
+                let server_intervals = CheckIntervals::new(server, default_inter);
+                servers.insert(server.name.clone(), HealthState { interval: server_intervals.inter, ..HealthState::default() });
+                intervals.insert(server.name.clone(), server_intervals);
             }
         }
+        let server_resolvers = config.server.iter()
+            .filter_map(|server| {
+                let resolver = resolvers.get(server.resolvers.as_ref()?)?;
+                Some((server.name.clone(), Arc::clone(resolver)))
+            })
+            .collect();
 
         let backend_state = BackendHealthState {
+            name: config.name.clone(),
+            events,
+            budget,
             servers,
+            intervals,
+            resolvers: server_resolvers,
+            resolve_on_down_interval: config.resolve_on_down_interval.unwrap_or(DEFAULT_RESOLVE_ON_DOWN_INTERVAL),
             rise_threshold,
             fall_threshold,
             check_timeout,
+            connect_steps,
+            http_check,
+            alert_threshold: config.alert_threshold,
         };
 
         let mut backends = HashMap::new();
@@ -87,63 +320,76 @@ impl HealthChecker {
         }
     }
 
-    pub async fn start(&self) {
-        let backends = Arc::clone(&self.backends);
-        let config = self.config.clone();
+    async fn warm_up(&self, status_table: &StatusTable) -> Option<(usize, usize)> {
+        let backend_index = status_table.backend_index(&self.config.name)?;
+        let backend_state = self.backends.read().await.get(&self.config.name).cloned()?;
 
-        tokio::spawn(async move {
-            Self::run_health_checks(backends, config).await;
-        });
-    }
+        let mut updated_servers = backend_state.servers.clone();
+        Self::check_servers(&self.config, &mut updated_servers, &backend_state).await;
+        for health_state in updated_servers.values_mut() {
+            if health_state.consecutive_failures > 0 {
+                health_state.status = ServerStatus::Down;
+            }
+        }
 
-    async fn run_health_checks(
-        backends: Arc<RwLock<HashMap<String, BackendHealthState>>>,
-        config: BackendConfig,
-    ) {
-        let check_interval = config.health_check.as_ref()
-            .and_then(|hc| parse_duration(&hc.interval))
-            .unwrap_or(Duration::from_secs(2));
+        status_table.publish(backend_index, self.config.server.iter()
+            .enumerate()
+            .filter_map(|(index, server)| updated_servers.get(&server.name)
+                .map(|health_state| (index, health_state.report()))));
+        let active = updated_servers.values()
+            .filter(|state| matches!(state.status, ServerStatus::Up))
+            .count();
+        Some((active, updated_servers.len()))
+    }
 
-        loop {
-            let backend_name = config.name.clone();
-            
-            let backend_state_opt = backends.read().await.get(&backend_name).cloned();
-            
-            if let Some(backend_state) = backend_state_opt {
-                let mut updated_servers = backend_state.servers.clone();
-                
-                for server in &config.server {
-                    if server.check.unwrap_or(false) {
-                        if let Some(health_state) = updated_servers.get_mut(&server.name) {
-                            Self::check_server_health(server, health_state, &backend_state).await;
-                        }
-                    }
-                }
+    fn check_interval(&self) -> Duration {
+        self.config.health_check.as_ref()
+            .and_then(|hc| utils::parse_duration(&hc.interval).ok())
+            .unwrap_or(DEFAULT_CHECK_INTERVAL)
+    }
 
-                drop(backends.read().await);
+    fn until_next_check(servers: &HashMap<String, HealthState>, fallback: Duration) -> Duration {
+        let now = Instant::now();
+        servers.values()
+            .map(|state| state.next_check.saturating_duration_since(now))
+            .min()
+            .unwrap_or(fallback)
+    }
 
-                {
-                    debug!("Updating backend state");
-                    let mut backends_write = backends.write().await;
-                    if let Some(backend_state) = backends_write.get_mut(&backend_name) {
-                        backend_state.servers = updated_servers.clone();
-                        debug!("Backend state updated successfully");
-                    } else {
-                        warn!("Failed to update backend state - backend not found");
-                    }
-                }
+    async fn check_servers(
+        config: &BackendConfig,
+        servers: &mut HashMap<String, HealthState>,
+        backend_state: &BackendHealthState,
+    ) {
+        let now = Instant::now();
+        let checks = servers.iter_mut()
+            .filter(|(_, health_state)| health_state.next_check <= now)
+            .filter_map(|(name, health_state)| config.server.iter()
+                .find(|server| &server.name == name && server.check.unwrap_or(false))
+                .map(|server| Self::check_server_health(server, health_state, backend_state)));
+        join_all(checks).await;
+    }
 
-                let active_servers = updated_servers.values()
-                    .filter(|state| matches!(state.status, ServerStatus::Up))
-                    .count();
-                let total_servers = updated_servers.len();
+    fn publish_backend_health(backend_state: &BackendHealthState, active_servers: usize, total_servers: usize) {
+        let previous_active = backend_state.servers.values()
+            .filter(|state| matches!(state.status, ServerStatus::Up))
+            .count();
+        if let Some(event) = TurbogateEvent::backend_health(&backend_state.name, active_servers, total_servers, previous_active) {
+            backend_state.events.publish(event);
+        }
 
-                logging::log_backend_status(&backend_name, active_servers, total_servers);
-                metrics::backend_active_servers(&backend_name, active_servers);
-                metrics::backend_total_servers(&backend_name, total_servers);
+        let Some(threshold) = backend_state.alert_threshold else {
+            return;
+        };
+        let ratio = health_ratio(active_servers, total_servers);
+        let previous_ratio = health_ratio(previous_active, total_servers);
+        if let Some(event) = TurbogateEvent::health_threshold(&backend_state.name, ratio, previous_ratio, threshold) {
+            if matches!(event, TurbogateEvent::BackendHealthLow { .. }) {
+                warn!("Backend {} health dropped below {}: {}/{} checked servers up", backend_state.name, threshold, active_servers, total_servers);
+            } else {
+                info!("Backend {} health recovered to {}: {}/{} checked servers up", backend_state.name, threshold, active_servers, total_servers);
             }
-
-            sleep(check_interval).await;
+            backend_state.events.publish(event);
         }
     }
 
@@ -152,12 +398,27 @@ impl HealthChecker {
         health_state: &mut HealthState,
         backend_state: &BackendHealthState,
     ) {
-        let server_addr = format!("{}:{}", server.address, server.port);
+        let Ok(_permit) = backend_state.budget.acquire().await else {
+            return;
+        };
+        metrics::health_check_started();
         let start_time = Instant::now();
+        let result = Self::run_check(server, backend_state).await;
+        metrics::health_check_finished();
+        let check_duration = start_time.elapsed();
+        health_state.last_duration = Some(check_duration);
+        if backend_state.events.health_events() {
+            backend_state.events.publish_health_check(TurbogateEvent::HealthCheckCompleted {
+                backend: backend_state.name.clone(),
+                server: server.name.clone(),
+                ok: result.is_ok(),
+                latency_ms: check_duration.as_micros() as f64 / 1000.0,
+                reason: result.as_ref().err().map(|e| e.to_string()),
+                error_type: result.as_ref().err().map(|e| ConnectFailure::classify(e).as_str().to_string()),
+            });
+        }
 
-        debug!("Performing health check for server '{}' at {}", server.name, server_addr);
-
-        match Self::perform_health_check(&server_addr, backend_state.check_timeout).await {
+        match result {
             Ok(_) => {
                 health_state.consecutive_successes += 1;
                 health_state.consecutive_failures = 0;
@@ -170,19 +431,25 @@ impl HealthChecker {
                 if health_state.consecutive_successes >= backend_state.rise_threshold {
                     if !matches!(health_state.status, ServerStatus::Up) {
                         health_state.status = ServerStatus::Up;
-                        logging::log_server_status(&server.name, "up", None);
-                        metrics::server_status_changed(&server.name, "up");
+                        health_state.last_resolve = None;
+                        metrics::server_flapped(&backend_state.name, &server.name);
+                        metrics::backend_state_changed(&backend_state.name);
+                        backend_state.events.publish(TurbogateEvent::ServerUp {
+                            backend: backend_state.name.clone(),
+                            server: server.name.clone(),
+                        });
                         info!("Server {} is now UP", server.name);
                     }
                 }
 
-                metrics::health_check(&server.name, true);
-                logging::log_server_status(&server.name, "healthy", None);
+                health_state.last_failure_reason = None;
+                metrics::health_check(&backend_state.name, &server.name, server.id.unwrap_or(0), None);
             }
             Err(e) => {
                 health_state.consecutive_failures += 1;
                 health_state.consecutive_successes = 0;
                 health_state.last_failure = Some(Instant::now());
+                health_state.last_failure_reason = Some(ConnectFailure::classify(&e));
                 health_state.last_check = Instant::now();
 
                 debug!("Health check FAILED for server '{}': consecutive_failures={}, fall_threshold={}, error={}", 
@@ -191,28 +458,188 @@ impl HealthChecker {
                 if health_state.consecutive_failures >= backend_state.fall_threshold {
                     if !matches!(health_state.status, ServerStatus::Down) {
                         health_state.status = ServerStatus::Down;
-                        logging::log_server_status(&server.name, "down", Some(&e.to_string()));
-                        metrics::server_status_changed(&server.name, "down");
+                        metrics::server_flapped(&backend_state.name, &server.name);
+                        metrics::backend_state_changed(&backend_state.name);
+                        backend_state.events.publish(TurbogateEvent::ServerDown {
+                            backend: backend_state.name.clone(),
+                            server: server.name.clone(),
+                            reason: Some(e.to_string()),
+                        });
                         warn!("Server {} is now DOWN: {}", server.name, e);
                     }
                 }
 
-                metrics::health_check(&server.name, false);
+                metrics::health_check(&backend_state.name, &server.name, server.id.unwrap_or(0), health_state.last_failure_reason);
                 debug!("Health check failed for {}: {}", server.name, e);
             }
         }
 
+        if let Some(intervals) = backend_state.intervals.get(&server.name) {
+            health_state.interval = intervals.select(health_state, backend_state.rise_threshold, backend_state.fall_threshold);
+            health_state.next_check = health_state.last_check + health_state.interval;
+        }
+
+        if matches!(health_state.status, ServerStatus::Down) {
+            Self::resolve_down_server(server, health_state, backend_state).await;
+        }
+
         let duration = start_time.elapsed();
-        debug!("Health check completed for server '{}' in {:?}", server.name, duration);
+        debug!("Health check completed for server '{}' in {:?}, next in {:?}", server.name, duration, health_state.interval);
+    }
+
+    async fn resolve_down_server(server: &ServerConfig, health_state: &mut HealthState, backend_state: &BackendHealthState) {
+        let Some(resolver) = backend_state.resolvers.get(&server.name) else {
+            return;
+        };
+        if server.address.parse::<IpAddr>().is_ok() {
+            return;
+        }
+        if health_state.last_resolve.is_some_and(|at| at.elapsed() < backend_state.resolve_on_down_interval) {
+            return;
+        }
+        health_state.last_resolve = Some(Instant::now());
+
+        let previous = match server.port {
+            Some(port) => resolver.cached(&server.address)
+                .map(|addresses| addresses.into_iter().map(|address| SocketAddr::new(address, port)).collect()),
+            None => resolver.cached_srv(&server.address),
+        };
+        match Self::resolve_server(server, resolver, true).await {
+            Ok(addresses) if previous.as_ref() != Some(&addresses) => {
+                info!("Server {} is down, re-resolved {} from {:?} to {:?}", server.name, server.address, previous.unwrap_or_default(), addresses);
+                metrics::server_reresolved(&backend_state.name, &server.name, "changed");
+                health_state.consecutive_failures = 0;
+                health_state.consecutive_successes = 0;
+                health_state.next_check = Instant::now();
+            }
+            Ok(_) => {
+                debug!("Server {} is down, {} still resolves to the same addresses", server.name, server.address);
+                metrics::server_reresolved(&backend_state.name, &server.name, "unchanged");
+            }
+            Err(e) => {
+                warn!("Server {} is down, re-resolving {} failed: {}", server.name, server.address, e);
+                metrics::server_reresolved(&backend_state.name, &server.name, "error");
+            }
+        }
     }
 
-    async fn perform_health_check(addr: &str, timeout: Duration) -> anyhow::Result<()> {
-        let socket_addr: SocketAddr = addr.parse()?;
-        
-        match tokio::time::timeout(timeout, TcpStream::connect(socket_addr)).await {
-            Ok(Ok(_)) => Ok(()),
-            Ok(Err(e)) => Err(anyhow::anyhow!("Connection failed: {}", e)),
-            Err(_) => Err(anyhow::anyhow!("Health check timeout")),
+    async fn resolve_server(server: &ServerConfig, resolver: &Resolver, refresh: bool) -> anyhow::Result<Vec<SocketAddr>> {
+        Ok(match server.port {
+            Some(port) => {
+                let addresses = if refresh {
+                    resolver.refresh(&server.address).await?
+                } else {
+                    resolver.resolve(&server.address).await?
+                };
+                addresses.into_iter().map(|address| SocketAddr::new(address, port)).collect()
+            }
+            None if refresh => resolver.refresh_srv(&server.address).await?,
+            None => resolver.resolve_srv(&server.address).await?,
+        })
+    }
+
+    async fn run_check(server: &ServerConfig, backend_state: &BackendHealthState) -> anyhow::Result<()> {
+        let resolved = match backend_state.resolvers.get(&server.name) {
+            Some(resolver) => Some(Self::resolve_server(server, resolver, false).await?
+                .into_iter()
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("No address resolved for server '{}'", server.name))?),
+            None => None,
+        };
+        let check_addrs = Self::check_addresses(server, &backend_state.connect_steps, resolved);
+        debug!("Performing health check for server '{}' at {:?}", server.name, check_addrs);
+
+        let check_timeout = server.check_timeout.as_deref()
+            .and_then(|timeout| utils::parse_duration(timeout).ok())
+            .unwrap_or(backend_state.check_timeout);
+        match backend_state.http_check {
+            Some(ref http_check) => Self::perform_http_check(server, &check_addrs, http_check, check_timeout).await,
+            None => Self::perform_health_check(&check_addrs, check_timeout).await,
+        }
+    }
+
+    pub async fn probe(&self, server: &ServerConfig) -> anyhow::Result<Duration> {
+        let backends = self.backends.read().await;
+        let backend_state = backends.get(&self.config.name)
+            .ok_or_else(|| anyhow::anyhow!("Backend '{}' has no health state", self.config.name))?;
+        let _permit = backend_state.budget.acquire().await?;
+        let start_time = Instant::now();
+        Self::run_check(server, backend_state).await?;
+        Ok(start_time.elapsed())
+    }
+
+    pub fn check_kind(&self) -> &'static str {
+        let http_check = self.config.options.as_ref()
+            .is_some_and(|options| options.http_options.httpchk.is_some());
+        if http_check { "http" } else { "tcp" }
+    }
+
+    fn check_addresses(server: &ServerConfig, connect_steps: &[TcpCheckConnect], resolved: Option<SocketAddr>) -> Vec<String> {
+        let (address, port) = match resolved {
+            Some(SocketAddr::V6(addr)) => (format!("[{}]", addr.ip()), addr.port()),
+            Some(addr) => (addr.ip().to_string(), addr.port()),
+            None => (server.address.clone(), server.port.unwrap_or_default()),
+        };
+        if connect_steps.is_empty() {
+            return vec![format!("{}:{}", address, port)];
+        }
+
+        connect_steps.iter()
+            .map(|step| {
+                let address = step.addr.as_deref().unwrap_or(&address);
+                format!("{}:{}", address, step.port.unwrap_or(port))
+            })
+            .collect()
+    }
+
+    async fn perform_health_check(addrs: &[String], timeout: Duration) -> anyhow::Result<()> {
+        let connects = async {
+            for addr in addrs {
+                let socket_addr: SocketAddr = addr.parse()?;
+                TcpStream::connect(socket_addr).await
+                    .map_err(|e| connect_failed(addr, e))?;
+            }
+            Ok(())
+        };
+
+        match tokio::time::timeout(timeout, connects).await {
+            Ok(result) => result,
+            Err(elapsed) => Err(anyhow::Error::new(elapsed).context(format!("Health check timeout after {:?}", timeout))),
+        }
+    }
+
+    async fn perform_http_check(
+        server: &ServerConfig,
+        addrs: &[String],
+        http_check: &HttpHealthCheck,
+        timeout: Duration,
+    ) -> anyhow::Result<()> {
+        let check = async {
+            let (addr, connects) = addrs.split_last()
+                .ok_or_else(|| anyhow::anyhow!("No address to check"))?;
+            for connect in connects {
+                let socket_addr: SocketAddr = connect.parse()?;
+                TcpStream::connect(socket_addr).await
+                    .map_err(|e| connect_failed(connect, e))?;
+            }
+
+            let socket_addr: SocketAddr = addr.parse()?;
+            let mut stream = TcpStream::connect(socket_addr).await
+                .map_err(|e| connect_failed(addr, e))?;
+
+            let request = format!(
+                "{} {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: turbogate-health-check\r\nConnection: close\r\n\r\n",
+                http_check.method, http_check.path, server.address
+            );
+            stream.write_all(request.as_bytes()).await?;
+
+            let (status, body) = read_http_response(&mut stream, http_check.needs_body()).await?;
+            http_check.evaluate(status, &String::from_utf8_lossy(&body))
+        };
+
+        match tokio::time::timeout(timeout, check).await {
+            Ok(result) => result,
+            Err(elapsed) => Err(anyhow::Error::new(elapsed).context(format!("Health check timeout after {:?}", timeout))),
         }
     }
 
@@ -256,10 +683,12 @@ impl HealthChecker {
                 } else {
                     ServerStatus::Up
                 };
-                
-                let status_str = if maintenance { "maintenance" } else { "up" };
-                logging::log_server_status(server_name, status_str, None);
-                metrics::server_status_changed(server_name, status_str);
+
+                backend_state.events.publish(TurbogateEvent::ServerMaintenance {
+                    backend: self.config.name.clone(),
+                    server: server_name.to_string(),
+                    maintenance,
+                });
             }
         }
     }
@@ -275,54 +704,56 @@ impl HealthChecker {
         }
     }
 
-    pub async fn start_with_callback(
-        &self,
-        backend_name: String,
-        server_statuses: Arc<RwLock<HashMap<String, HashMap<String, ServerStatus>>>>,
-    ) {
+    pub fn start_with_callback(&self, status_table: Arc<StatusTable>, initial_delay: Duration) -> JoinHandle<()> {
         let backends = Arc::clone(&self.backends);
         let config = self.config.clone();
+        let backend_name = config.name.clone();
 
         tokio::spawn(async move {
-            Self::run_health_checks_with_callback(backends, config, backend_name, server_statuses).await;
-        });
+            sleep(initial_delay).await;
+            Self::run_health_checks_with_callback(backends, config, backend_name, status_table).await;
+        })
     }
 
     async fn run_health_checks_with_callback(
         backends: Arc<RwLock<HashMap<String, BackendHealthState>>>,
         config: BackendConfig,
         backend_name: String,
-        server_statuses: Arc<RwLock<HashMap<String, HashMap<String, ServerStatus>>>>,
+        status_table: Arc<StatusTable>,
     ) {
+        let Some(backend_index) = status_table.backend_index(&backend_name) else {
+            error!("Backend '{}' not found in status table, health checks disabled", backend_name);
+            return;
+        };
         let check_interval = config.health_check.as_ref()
-            .and_then(|hc| parse_duration(&hc.interval))
-            .unwrap_or(Duration::from_secs(2));
+            .and_then(|hc| utils::parse_duration(&hc.interval).ok())
+            .unwrap_or(DEFAULT_CHECK_INTERVAL);
 
         info!("Health checker started for backend '{}' with interval {:?}", backend_name, check_interval);
 
+        if let Some(backend_state) = backends.write().await.get_mut(&backend_name) {
+            for (index, server) in config.server.iter().enumerate() {
+                if status_table.status(backend_index, index) != Some(ServerStatus::Down) {
+                    continue;
+                }
+                if let Some(health_state) = backend_state.servers.get_mut(&server.name) {
+                    health_state.status = ServerStatus::Down;
+                }
+            }
+        }
+
         loop {
             debug!("=== Starting health check cycle for backend '{}' ===", backend_name);
             
             if let Err(e) = async {
                 debug!("Running health checks for backend '{}'", backend_name);
                 
+                let mut wait = check_interval;
                 let backend_state_opt = backends.read().await.get(&backend_name).cloned();
                 if let Some(backend_state) = backend_state_opt {
                     debug!("Found backend state, checking {} servers", backend_state.servers.len());
                     let mut updated_servers = backend_state.servers.clone();
-                    
-                    for server in &config.server {
-                        if server.check.unwrap_or(false) {
-                            debug!("Checking server '{}' at {}:{}", server.name, server.address, server.port);
-                            if let Some(health_state) = updated_servers.get_mut(&server.name) {
-                                Self::check_server_health(server, health_state, &backend_state).await;
-                            } else {
-                                warn!("Server '{}' not found in health state", server.name);
-                            }
-                        } else {
-                            debug!("Server '{}' has health check disabled", server.name);
-                        }
-                    }
+                    Self::check_servers(&config, &mut updated_servers, &backend_state).await;
 
                     {
                         debug!("Saving updated health state for backend '{}'", backend_name);
@@ -343,50 +774,42 @@ impl HealthChecker {
                         }
                     }
 
-                    {
-                        debug!("Updating shared state for backend '{}'", backend_name);
-                        match tokio::time::timeout(Duration::from_secs(1), async {
-                            let mut statuses = server_statuses.write().await;
-                            let backend_statuses = statuses.entry(backend_name.clone()).or_insert_with(HashMap::new);
-                            debug!("Found {} existing server statuses", backend_statuses.len());
-                            
-                            for (server_name, health_state) in &updated_servers {
-                                let old_status = backend_statuses.get(server_name).cloned();
-                                backend_statuses.insert(server_name.clone(), health_state.status.clone());
-                                if old_status != Some(health_state.status.clone()) {
-                                    info!("Shared state updated: server '{}' status changed from {:?} to {:?}", 
-                                          server_name, old_status, health_state.status);
-                                } else {
-                                    debug!("Updated shared state: server '{}' = {:?}", server_name, health_state.status);
-                                }
-                            }
-                            debug!("Shared state updated, now has {} server statuses", backend_statuses.len());
-                        }).await {
-                            Ok(_) => debug!("Shared state update completed"),
-                            Err(_) => {
-                                error!("Shared state update timed out - possible deadlock");
-                                return Err(anyhow::anyhow!("Shared state update timed out"));
-                            }
+                    let reports: Vec<(usize, ServerReport)> = config.server.iter()
+                        .enumerate()
+                        .filter_map(|(index, server)| updated_servers.get(&server.name)
+                            .map(|health_state| (index, health_state.report())))
+                        .collect();
+                    let previous = status_table.snapshot();
+                    for (index, report) in &reports {
+                        let old_status = previous.status(backend_index, *index);
+                        if old_status.as_ref() != Some(&report.status) {
+                            info!("Shared state updated: server '{}' status changed from {:?} to {:?}",
+                                  config.server[*index].name, old_status, report.status);
                         }
                     }
+                    status_table.publish(backend_index, reports);
+                    wait = Self::until_next_check(&updated_servers, check_interval);
 
                     let active_servers = updated_servers.values()
                         .filter(|state| matches!(state.status, ServerStatus::Up))
                         .count();
                     let total_servers = updated_servers.len();
+                    Self::publish_backend_health(&backend_state, active_servers, total_servers);
 
-                    info!("Backend '{}' health check summary: {}/{} servers active", 
-                          backend_name, active_servers, total_servers);
+                    let unchecked_servers = config.server.len().saturating_sub(total_servers);
+                    info!("Backend '{}' health check summary: {}/{} checked servers active, {} unchecked",
+                          backend_name, active_servers, total_servers, unchecked_servers);
                     
-                    logging::log_backend_status(&backend_name, active_servers, total_servers);
+                    logging::log_backend_status(&backend_name, active_servers, total_servers, unchecked_servers);
                     metrics::backend_active_servers(&backend_name, active_servers);
+                    metrics::backend_health_ratio(&backend_name, health_ratio(active_servers, total_servers));
                     metrics::backend_total_servers(&backend_name, total_servers);
                 } else {
                     warn!("Backend '{}' not found in health checker state", backend_name);
                 }
 
-                debug!("Health check cycle completed for backend '{}', sleeping for {:?}", backend_name, check_interval);
-                sleep(check_interval).await;
+                debug!("Health check cycle completed for backend '{}', sleeping for {:?}", backend_name, wait);
+                sleep(wait).await;
                 debug!("Woke up from sleep, starting next cycle");
                 Ok::<(), anyhow::Error>(())
             }.await {
@@ -400,16 +823,372 @@ impl HealthChecker {
     }
 }
 
-fn parse_duration(s: &str) -> Option<Duration> {
-    if s.ends_with("ms") {
-        s[..s.len()-2].parse::<u64>().ok().map(Duration::from_millis)
-    } else if s.ends_with('s') {
-        s[..s.len()-1].parse::<u64>().ok().map(Duration::from_secs)
-    } else if s.ends_with('m') {
-        s[..s.len()-1].parse::<u64>().ok().map(|m| Duration::from_secs(m * 60))
-    } else if s.ends_with('h') {
-        s[..s.len()-1].parse::<u64>().ok().map(|h| Duration::from_secs(h * 3600))
+fn health_ratio(active_servers: usize, total_servers: usize) -> f64 {
+    if total_servers == 0 {
+        1.0
     } else {
-        s.parse::<u64>().ok().map(Duration::from_secs)
+        active_servers as f64 / total_servers as f64
+    }
+}
+
+fn connect_failed(addr: &str, error: std::io::Error) -> anyhow::Error {
+    let message = format!("Connection to {} failed: {}", addr, error);
+    anyhow::Error::new(error).context(message)
+}
+
+async fn read_http_response(stream: &mut TcpStream, with_body: bool) -> anyhow::Result<(u16, Vec<u8>)> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+
+    let headers_end = loop {
+        if let Some(position) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break position + 4;
+        }
+        if buffer.len() >= MAX_HTTP_CHECK_HEADERS {
+            return Err(anyhow::anyhow!("HTTP check response headers exceed {} bytes", MAX_HTTP_CHECK_HEADERS));
+        }
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(anyhow::anyhow!("HTTP check connection closed before response headers"));
+        }
+        buffer.extend_from_slice(&chunk[..n]);
+    };
+
+    let headers = String::from_utf8_lossy(&buffer[..headers_end]).into_owned();
+    let status = headers.lines().next()
+        .filter(|line| line.starts_with("HTTP/"))
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| anyhow::anyhow!("Invalid HTTP check status line"))?;
+
+    if !with_body {
+        return Ok((status, Vec::new()));
+    }
+
+    let content_length = headers.lines().skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok());
+    let limit = content_length.unwrap_or(MAX_HTTP_CHECK_BODY).min(MAX_HTTP_CHECK_BODY);
+
+    let mut body = buffer.split_off(headers_end);
+    while body.len() < limit {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        body.extend_from_slice(&chunk[..n]);
+    }
+    body.truncate(MAX_HTTP_CHECK_BODY);
+
+    Ok((status, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::net::TcpListener;
+
+    fn checker(backend: &str) -> HealthChecker {
+        let config = Config::from_haproxy_config(&format!("backend be\n{backend}"), None).unwrap();
+        HealthChecker::new(config.backends[0].clone(), Arc::new(EventBus::new()), Arc::new(Semaphore::new(64)), &HashMap::new())
+    }
+
+    async fn probe(checker: &HealthChecker) -> anyhow::Result<Duration> {
+        checker.probe(&checker.config.server[0]).await
+    }
+
+    // A port nothing listens on.
+    fn closed_port() -> u16 {
+        std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+    }
+
+    // Accepts connections and answers each one with `response` after `delay`.
+    async fn mock_server(response: &'static [u8], delay: Duration) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut request = [0u8; 1024];
+                    let _ = stream.read(&mut request).await;
+                    sleep(delay).await;
+                    let _ = stream.write_all(response).await;
+                });
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn tcp_check_connects_to_every_step_in_order() {
+        let first = mock_server(b"", Duration::ZERO).await;
+        let second = mock_server(b"", Duration::ZERO).await;
+        let closed = closed_port();
+        let steps = |ports: [u16; 2]| format!("    tcp-check connect port {}\n    tcp-check connect port {} addr 127.0.0.1\n    \
+                                               server s1 127.0.0.1:{} check\n", ports[0], ports[1], closed);
+
+        assert!(probe(&checker(&steps([first, second]))).await.is_ok());
+
+        let error = probe(&checker(&steps([first, closed]))).await.unwrap_err().to_string();
+        assert!(error.contains(&format!("127.0.0.1:{closed}")), "{error}");
+        let error = probe(&checker(&steps([closed, second]))).await.unwrap_err().to_string();
+        assert!(error.contains(&format!("127.0.0.1:{closed}")), "{error}");
+    }
+
+    #[tokio::test]
+    async fn failing_connect_step_marks_the_server_down() {
+        let open = mock_server(b"", Duration::ZERO).await;
+        let closed = closed_port();
+        let checker = checker(&format!("    tcp-check connect port {open}\n    tcp-check connect port {closed}\n    \
+                                        server s1 127.0.0.1:{open} check fall 1\n"));
+        let backend_state = checker.backends.read().await.get("be").cloned().unwrap();
+        let mut health_state = backend_state.servers["s1"].clone();
+
+        HealthChecker::check_server_health(&checker.config.server[0], &mut health_state, &backend_state).await;
+        assert_eq!(health_state.status, ServerStatus::Down);
+        assert_eq!(health_state.last_failure_reason, Some(ConnectFailure::Refused));
+    }
+
+    #[tokio::test]
+    async fn http_check_runs_after_the_connect_steps() {
+        let server = mock_server(b"HTTP/1.0 200 OK\r\n\r\n", Duration::ZERO).await;
+        let closed = closed_port();
+        let backend = |first: u16| format!("    mode http\n    option httpchk GET /health\n    tcp-check connect port {first}\n    \
+                                            tcp-check connect port {server}\n    server s1 127.0.0.1:{server} check\n");
+
+        let http = checker(&backend(server));
+        assert_eq!(http.check_kind(), "http");
+        assert!(probe(&http).await.is_ok());
+        assert!(probe(&checker(&backend(closed))).await.is_err());
+    }
+
+    #[test]
+    fn connect_port_needs_a_value() {
+        let error = Config::from_haproxy_config("backend be\n    tcp-check connect port\n    server s1 127.0.0.1:9001 check\n", None).unwrap_err();
+        assert!(format!("{error:#}").contains("'port' requires a value"), "{error:#}");
+    }
+
+    fn http_checker(port: u16, expect: &str) -> HealthChecker {
+        checker(&format!("    mode http\n    option httpchk GET /ready\n{expect}    server s1 127.0.0.1:{port} check\n"))
+    }
+
+    #[tokio::test]
+    async fn expect_rules_must_all_match() {
+        let port = mock_server(b"HTTP/1.1 204 No Content\r\nContent-Length: 15\r\n\r\n{\"status\":\"ok\"}", Duration::ZERO).await;
+        let cases = [
+            ("", true),
+            ("    http-check expect status 204\n", true),
+            ("    http-check expect status 200-399\n", true),
+            ("    http-check expect status 200\n", false),
+            ("    http-check expect string \"ok\"\n", true),
+            ("    http-check expect string degraded\n", false),
+            ("    http-check expect rstring '\"status\":\"(ok|warn)\"'\n", true),
+            ("    http-check expect rstring ^degraded\n", false),
+            ("    http-check expect status 200-299\n    http-check expect string ok\n", true),
+            ("    http-check expect status 200-299\n    http-check expect string degraded\n", false),
+        ];
+        for (expect, passes) in cases {
+            assert_eq!(probe(&http_checker(port, expect)).await.is_ok(), passes, "{expect:?}");
+        }
+
+        let error = probe(&http_checker(port, "    http-check expect status 200\n")).await.unwrap_err().to_string();
+        assert_eq!(error, "HTTP check returned status 204, expected 200-200");
+    }
+
+    #[tokio::test]
+    async fn default_expect_accepts_2xx_and_3xx_only() {
+        for (response, passes) in [(&b"HTTP/1.0 302 Found\r\n\r\n"[..], true), (b"HTTP/1.0 503 Service Unavailable\r\n\r\n", false)] {
+            let port = mock_server(response, Duration::ZERO).await;
+            assert_eq!(probe(&http_checker(port, "")).await.is_ok(), passes);
+        }
+    }
+
+    #[test]
+    fn bad_expect_rules_fail_at_parse_time() {
+        for expect in ["rstring (unclosed", "status 99", "status 500-200", "string", "header x-ready"] {
+            let config = format!("backend be\n    mode http\n    option httpchk\n    http-check expect {expect}\n    server s1 127.0.0.1:9001 check\n");
+            assert!(Config::from_haproxy_config(&config, None).is_err(), "{expect}");
+        }
+    }
+
+    #[tokio::test]
+    async fn partial_responses() {
+        let port = mock_server(b"HTTP/1.0 200 OK\r\nContent-Le", Duration::ZERO).await;
+        let error = probe(&http_checker(port, "")).await.unwrap_err().to_string();
+        assert_eq!(error, "HTTP check connection closed before response headers");
+
+        let port = mock_server(b"HTTP/1.0 200 OK\r\nContent-Length: 100\r\n\r\nready", Duration::ZERO).await;
+        assert!(probe(&http_checker(port, "    http-check expect string ready\n")).await.is_ok());
+        assert!(probe(&http_checker(port, "    http-check expect string ready-and-warm\n")).await.is_err());
+
+        let port = mock_server(b"SSH-2.0-OpenSSH_9.6\r\n\r\n", Duration::ZERO).await;
+        let error = probe(&http_checker(port, "")).await.unwrap_err().to_string();
+        assert_eq!(error, "Invalid HTTP check status line");
+    }
+
+    // Real time: a paused clock auto-advances while loopback I/O is still in flight.
+    #[tokio::test]
+    async fn check_timeout_covers_the_whole_exchange() {
+        let port = mock_server(b"HTTP/1.0 200 OK\r\n\r\n", Duration::from_millis(300)).await;
+        let backend = |timeouts: &str, server: &str| format!("    mode http\n    option httpchk GET /\n{timeouts}    \
+                                                              server s1 127.0.0.1:{port} check{server}\n");
+        let cases = [
+            ("", "", true),
+            ("    timeout check 100ms\n", "", false),
+            ("    timeout check 2s\n", "", true),
+            ("    timeout check 100ms\n", " check-timeout 2s", true),
+            ("    timeout check 2s\n", " check-timeout 100ms", false),
+        ];
+        for (timeouts, server, passes) in cases {
+            let result = probe(&checker(&backend(timeouts, server))).await;
+            assert_eq!(result.is_ok(), passes, "{timeouts:?}{server:?}: {result:?}");
+        }
+
+        let error = probe(&checker(&backend("    timeout check 100ms\n", ""))).await.unwrap_err();
+        assert_eq!(ConnectFailure::classify(&error), ConnectFailure::TimedOut);
+        assert!(error.to_string().starts_with("Health check timeout after 100ms"), "{error}");
+    }
+
+    #[test]
+    fn timeout_check_is_inherited_from_defaults() {
+        let config = Config::from_haproxy_config("defaults\n    timeout check 4s\n\nbackend be\n    server s1 127.0.0.1:9001 check\n", None).unwrap();
+        let inherited = HealthChecker::new(config.backends[0].clone(), Arc::new(EventBus::new()), Arc::new(Semaphore::new(1)), &HashMap::new());
+        assert_eq!(inherited.backends.try_read().unwrap()["be"].check_timeout, Duration::from_secs(4));
+
+        let checker = checker("    server s1 127.0.0.1:9001 check\n");
+        assert_eq!(checker.backends.try_read().unwrap()["be"].check_timeout, Duration::from_secs(1));
+    }
+
+    fn state(status: ServerStatus, consecutive_successes: u32, consecutive_failures: u32) -> HealthState {
+        HealthState { status, consecutive_successes, consecutive_failures, ..HealthState::default() }
+    }
+
+    const INTERVALS: CheckIntervals = CheckIntervals {
+        inter: Duration::from_secs(10),
+        fastinter: Duration::from_secs(1),
+        downinter: Duration::from_secs(30),
+    };
+
+    #[test]
+    fn intervals_follow_the_server_through_rise_and_fall() {
+        let (rise, fall) = (2, 3);
+        let walk = [
+            (state(ServerStatus::Down, 0, 4), INTERVALS.downinter),
+            (state(ServerStatus::Down, 1, 0), INTERVALS.fastinter),
+            (state(ServerStatus::Up, 2, 0), INTERVALS.inter),
+            (state(ServerStatus::Up, 0, 1), INTERVALS.fastinter),
+            (state(ServerStatus::Up, 0, 2), INTERVALS.fastinter),
+            (state(ServerStatus::Down, 0, 3), INTERVALS.downinter),
+        ];
+        for (state, expected) in walk {
+            assert_eq!(INTERVALS.select(&state, rise, fall), expected, "{:?} +{} -{}",
+                       state.status, state.consecutive_successes, state.consecutive_failures);
+        }
+        assert_eq!(INTERVALS.select(&state(ServerStatus::Unchecked, 0, 0), rise, fall), INTERVALS.inter);
+    }
+
+    #[test]
+    fn unset_intervals_fall_back_to_inter() {
+        let config = Config::from_haproxy_config("backend be\n    server a 127.0.0.1:9001 check inter 5s\n    \
+                                                  server b 127.0.0.1:9002 check fastinter 500ms downinter 1m\n", None).unwrap();
+        let servers = &config.backends[0].server;
+        assert_eq!(CheckIntervals::new(&servers[0], Duration::from_secs(2)), CheckIntervals {
+            inter: Duration::from_secs(5),
+            fastinter: Duration::from_secs(5),
+            downinter: Duration::from_secs(5),
+        });
+        assert_eq!(CheckIntervals::new(&servers[1], Duration::from_secs(2)), CheckIntervals {
+            inter: Duration::from_secs(2),
+            fastinter: Duration::from_millis(500),
+            downinter: Duration::from_secs(60),
+        });
+    }
+
+    // Answers HTTP checks after a pause, recording the most it ever held open at once.
+    async fn counting_server(peak: Arc<AtomicUsize>, answered: Arc<AtomicUsize>) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let (in_flight, peak, answered) = (Arc::clone(&in_flight), Arc::clone(&peak), Arc::clone(&answered));
+                tokio::spawn(async move {
+                    let mut request = [0u8; 1024];
+                    let _ = stream.read(&mut request).await;
+                    peak.fetch_max(in_flight.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                    sleep(Duration::from_millis(20)).await;
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                    answered.fetch_add(1, Ordering::SeqCst);
+                    let _ = stream.write_all(b"HTTP/1.0 200 OK\r\n\r\n").await;
+                });
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn checks_never_exceed_the_shared_budget() {
+        let (peak, answered) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let port = counting_server(Arc::clone(&peak), Arc::clone(&answered)).await;
+        let mut config = String::new();
+        for backend in 0..4 {
+            config.push_str(&format!("backend be{backend}\n    mode http\n    option httpchk GET /\n"));
+            for server in 0..25 {
+                config.push_str(&format!("    server s{server} 127.0.0.1:{port} check\n"));
+            }
+        }
+        let backends = Config::from_haproxy_config(&config, None).unwrap().backends;
+
+        let manager = HealthManager::new(&backends, 5, Arc::new(EventBus::new()), &HashMap::new());
+        manager.warm_up(Arc::new(StatusTable::new(&backends)), Duration::from_secs(10)).await;
+
+        assert_eq!(answered.load(Ordering::SeqCst), 100);
+        assert_eq!(peak.load(Ordering::SeqCst), 5);
+    }
+
+    #[tokio::test]
+    async fn down_servers_pick_up_a_changed_address() {
+        let port = mock_server(b"", Duration::ZERO).await;
+        let address = Arc::new(std::sync::Mutex::new(std::net::Ipv4Addr::new(127, 0, 0, 2)));
+        let nameserver = crate::resolver::tests::nameserver("app.test", Arc::clone(&address)).await;
+        let config = Config::from_haproxy_config(&format!("resolvers dns\n    nameserver ns1 {nameserver}\n\n\
+                                                           backend be\n    server s1 app.test:{port} check resolvers dns\n"), None).unwrap();
+        let resolver = Arc::new(Resolver::new(config.resolvers[0].clone()));
+        let checker = HealthChecker::new(config.backends[0].clone(), Arc::new(EventBus::new()), Arc::new(Semaphore::new(1)),
+                                         &HashMap::from([("dns".to_string(), Arc::clone(&resolver))]));
+        let backend_state = checker.backends.read().await.get("be").cloned().unwrap();
+        let server = &checker.config.server[0];
+
+        assert!(probe(&checker).await.is_err(), "nothing listens on 127.0.0.2");
+        *address.lock().unwrap() = std::net::Ipv4Addr::LOCALHOST;
+        let mut health_state = state(ServerStatus::Down, 0, 3);
+        HealthChecker::resolve_down_server(server, &mut health_state, &backend_state).await;
+
+        assert_eq!(resolver.cached("app.test"), Some(vec!["127.0.0.1".parse().unwrap()]));
+        assert_eq!((health_state.consecutive_failures, health_state.consecutive_successes), (0, 0));
+        assert!(probe(&checker).await.is_ok());
+
+        // Within resolve-on-down's interval the address is not looked up again.
+        *address.lock().unwrap() = std::net::Ipv4Addr::new(127, 0, 0, 2);
+        HealthChecker::resolve_down_server(server, &mut health_state, &backend_state).await;
+        assert_eq!(resolver.cached("app.test"), Some(vec!["127.0.0.1".parse().unwrap()]));
+    }
+
+    #[tokio::test]
+    async fn refused_and_timed_out_checks_report_different_reasons() {
+        let silent = mock_server(b"", Duration::from_secs(60)).await;
+        let mut labels = Vec::new();
+        for port in [closed_port(), silent] {
+            let checker = checker(&format!("    mode http\n    option httpchk GET /\n    timeout check 100ms\n    \
+                                            server s1 127.0.0.1:{port} check\n"));
+            let backend_state = checker.backends.read().await.get("be").cloned().unwrap();
+            let mut health_state = HealthState::default();
+            HealthChecker::check_server_health(&checker.config.server[0], &mut health_state, &backend_state).await;
+            labels.push(health_state.report().failure.map(|reason| reason.as_str()));
+        }
+        assert_eq!(labels, [Some("refused"), Some("timeout")]);
     }
 }
@@ -5,29 +5,46 @@ use std::time::Duration;
 use tokio::sync::broadcast;
 use anyhow::{Result, anyhow};
 use tracing::{info, error};
-use crate::config::Config;
+use crate::audit::{AuditEntry, AuditLog, AuditSource};
+use crate::config::{self, Config};
+use crate::events::{EventBus, TurbogateEvent};
+use crate::metrics;
+use std::sync::Arc;
 
 pub struct HotReload {
     config_path: String,
     reload_tx: broadcast::Sender<Config>,
+    events: Arc<EventBus>,
+    audit: Arc<AuditLog>,
+    allow_degraded: bool,
 }
 
 impl HotReload {
-    pub fn new(config_path: String) -> Result<Self> {
+    pub fn new(config_path: String, events: Arc<EventBus>, audit: Arc<AuditLog>, allow_degraded: bool) -> Result<Self> {
         let (reload_tx, _reload_rx) = broadcast::channel(10);
         
         Ok(Self {
             config_path,
             reload_tx,
+            events,
+            audit,
+            allow_degraded,
         })
     }
 
+    pub fn subscribe(&self) -> broadcast::Receiver<Config> {
+        self.reload_tx.subscribe()
+    }
+
     pub fn start_watching(&self) -> Result<()> {
         let config_path = self.config_path.clone();
         let reload_tx = self.reload_tx.clone();
+        let events = Arc::clone(&self.events);
+        let audit = Arc::clone(&self.audit);
+        let allow_degraded = self.allow_degraded;
 
         std::thread::spawn(move || {
-            if let Err(e) = Self::watch_config_file(&config_path, reload_tx) {
+            if let Err(e) = Self::watch_config_file(&config_path, reload_tx, events, audit, allow_degraded) {
                 error!("Config file watcher failed: {}", e);
             }
         });
@@ -36,7 +53,13 @@ impl HotReload {
         Ok(())
     }
 
-    fn watch_config_file(config_path: &str, reload_tx: broadcast::Sender<Config>) -> Result<()> {
+    fn watch_config_file(
+        config_path: &str,
+        reload_tx: broadcast::Sender<Config>,
+        events: Arc<EventBus>,
+        audit: Arc<AuditLog>,
+        allow_degraded: bool,
+    ) -> Result<()> {
         let (tx, rx) = mpsc::channel();
 
         let mut watcher = RecommendedWatcher::new(tx, NotifyConfig::default())?;
@@ -50,16 +73,16 @@ impl HotReload {
                     match event.kind {
                         EventKind::Modify(_) => {
                             info!("Config file modified, reloading...");
-                            match Self::reload_config(config_path) {
+                            match Self::reload_config(config_path, &audit, allow_degraded) {
                                 Ok(config) => {
                                     if let Err(e) = reload_tx.send(config) {
                                         error!("Failed to send reload signal: {}", e);
-                                    } else {
-                                        info!("Configuration reloaded successfully");
                                     }
                                 }
                                 Err(e) => {
                                     error!("Failed to reload config: {}", e);
+                                    metrics::config_reload_failed();
+                                    events.publish(TurbogateEvent::ConfigReloaded { ok: false, error: Some(e.to_string()) });
                                 }
                             }
                         }
@@ -75,12 +98,27 @@ impl HotReload {
         Ok(())
     }
 
-    fn reload_config(config_path: &str) -> Result<Config> {
+    fn reload_config(config_path: &str, audit: &AuditLog, allow_degraded: bool) -> Result<Config> {
         std::thread::sleep(Duration::from_millis(100));
-        
+        Self::load_config(config_path, audit, AuditSource::FileWatcher, allow_degraded)
+    }
+
+    // Reads, parses and validates the configuration file, auditing failures
+    // under `source`; successful reloads are audited once they are applied.
+    pub fn load_config(config_path: &str, audit: &AuditLog, source: AuditSource, allow_degraded: bool) -> Result<Config> {
         let content = std::fs::read_to_string(config_path)
-            .map_err(|e| anyhow!("Failed to read config file: {}", e))?;
-        
-        Config::from_haproxy_config(&content)
+            .map_err(|e| anyhow!("Failed to read config file: {}", e));
+        let config_hash = content.as_deref().ok().map(config::content_hash);
+        let result = content.and_then(|content| {
+            let config = Config::from_haproxy_config(&content, Some(config_path))?;
+            config.validate(allow_degraded)?;
+            Ok(config)
+        });
+        if result.is_err() {
+            let mut entry = AuditEntry::new(source, "reload").outcome(&result);
+            entry.config_hash = config_hash;
+            audit.record(entry);
+        }
+        result
     }
 }
@@ -0,0 +1,801 @@
+use crate::config::HttpReturnConfig;
+use crate::inspect;
+use std::collections::VecDeque;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+const MAX_LINE_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Chunk {
+    Size,
+    Data(u64),
+    DataEnd,
+    Trailers,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Body {
+    Empty,
+    Length(u64),
+    Chunked(Chunk),
+    UntilClose,
+}
+
+#[derive(Debug, Default)]
+struct Framer {
+    head: Vec<u8>,
+    line: Vec<u8>,
+    body: Option<Body>,
+}
+
+impl Framer {
+    fn at_boundary(&self) -> bool {
+        self.body.is_none() && self.head.is_empty()
+    }
+
+    fn read_head(&mut self, input: &mut &[u8]) -> Result<Option<Vec<u8>>, ()> {
+        if self.head.is_empty() {
+            let skipped = input.iter().take_while(|byte| matches!(byte, b'\r' | b'\n')).count();
+            *input = &input[skipped..];
+        }
+        let start = self.head.len().saturating_sub(3);
+        self.head.extend_from_slice(input);
+        match self.head[start..].windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(position) => {
+                let end = start + position + 4;
+                let unread = self.head.len() - end;
+                *input = &input[input.len() - unread..];
+                self.head.truncate(end);
+                Ok(Some(std::mem::take(&mut self.head)))
+            }
+            None => {
+                *input = &[];
+                if self.head.len() > MAX_HEAD_SIZE { Err(()) } else { Ok(None) }
+            }
+        }
+    }
+
+    fn read_line(&mut self, input: &mut &[u8]) -> Result<Option<Vec<u8>>, ()> {
+        match input.iter().position(|byte| *byte == b'\n') {
+            Some(position) => {
+                self.line.extend_from_slice(&input[..position]);
+                *input = &input[position + 1..];
+                let mut line = std::mem::take(&mut self.line);
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                Ok(Some(line))
+            }
+            None if self.line.len() + input.len() > MAX_LINE_SIZE => Err(()),
+            None => {
+                self.line.extend_from_slice(input);
+                *input = &[];
+                Ok(None)
+            }
+        }
+    }
+
+    fn read_body(&mut self, input: &mut &[u8]) -> Result<bool, ()> {
+        loop {
+            let Some(body) = self.body else {
+                return Ok(true);
+            };
+            let next = match body {
+                Body::Empty => None,
+                Body::Length(remaining) => {
+                    let taken = remaining.min(input.len() as u64);
+                    *input = &input[taken as usize..];
+                    (taken < remaining).then_some(Body::Length(remaining - taken))
+                }
+                Body::UntilClose => {
+                    *input = &[];
+                    Some(Body::UntilClose)
+                }
+                Body::Chunked(Chunk::Data(remaining)) => {
+                    let taken = remaining.min(input.len() as u64);
+                    *input = &input[taken as usize..];
+                    Some(Body::Chunked(if taken < remaining { Chunk::Data(remaining - taken) } else { Chunk::DataEnd }))
+                }
+                Body::Chunked(chunk) => match self.read_line(input)? {
+                    None => Some(body),
+                    Some(line) => match chunk {
+                        Chunk::Size => {
+                            let size = std::str::from_utf8(&line).ok()
+                                .map(|line| line.split(';').next().unwrap_or_default().trim())
+                                .and_then(|size| u64::from_str_radix(size, 16).ok())
+                                .ok_or(())?;
+                            Some(Body::Chunked(if size == 0 { Chunk::Trailers } else { Chunk::Data(size) }))
+                        }
+                        Chunk::DataEnd if line.is_empty() => Some(Body::Chunked(Chunk::Size)),
+                        Chunk::DataEnd => return Err(()),
+                        Chunk::Trailers if line.is_empty() => None,
+                        _ => Some(body),
+                    },
+                },
+            };
+            match next {
+                None => {
+                    self.body = None;
+                    return Ok(true);
+                }
+                Some(next) if next == body && input.is_empty() => return Ok(false),
+                Some(next) => {
+                    self.body = Some(next);
+                    if input.is_empty() {
+                        return Ok(false);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn body_of(head: &[u8], allow_until_close: bool) -> Body {
+    let chunked = inspect::extract_headers(head, "transfer-encoding").last()
+        .is_some_and(|codings| codings.rsplit(',').next().is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked")));
+    if chunked {
+        return Body::Chunked(Chunk::Size);
+    }
+    match inspect::extract_headers(head, "content-length").first().and_then(|length| length.parse().ok()) {
+        Some(0) => Body::Empty,
+        Some(length) => Body::Length(length),
+        None if allow_until_close => Body::UntilClose,
+        None => Body::Empty,
+    }
+}
+
+fn with_headers(head: &[u8], removed: &[&str], headers: &[(&str, &str)]) -> Vec<u8> {
+    let mut rewritten = Vec::with_capacity(head.len() + 64);
+    let mut lines = head[..head.len() - 2].split_inclusive(|byte| *byte == b'\n');
+    rewritten.extend_from_slice(lines.next().unwrap_or_default());
+    for line in lines {
+        let name = line.split(|byte| *byte == b':').next().unwrap_or_default();
+        let replaced = removed.iter().chain(headers.iter().map(|(name, _)| name))
+            .any(|other| name.eq_ignore_ascii_case(other.as_bytes()));
+        if !replaced {
+            rewritten.extend_from_slice(line);
+        }
+    }
+    for (name, value) in headers {
+        rewritten.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+    }
+    rewritten.extend_from_slice(b"\r\n");
+    rewritten
+}
+
+pub enum Verdict {
+    Forward,
+    Respond(Arc<StaticResponse>),
+    Reject(Vec<u8>),
+}
+
+pub trait RequestPolicy: Send + Sync {
+    fn check(&self, head: &mut Vec<u8>) -> Verdict;
+    fn malformed(&self) -> Verdict;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RequestTiming {
+    pub reused: bool,
+    pub started: Instant,
+    pub sent: Instant,
+    pub responded: Instant,
+    pub finished: Instant,
+}
+
+#[derive(Debug)]
+enum Pending {
+    Backend { head_request: bool, reused: bool, started: Instant, sent: Instant },
+    Local { response: Arc<StaticResponse>, head_request: bool, close: bool },
+    Reject(Vec<u8>),
+}
+
+pub struct Exchange {
+    request: Framer,
+    response: Framer,
+    pending: VecDeque<Pending>,
+    tunnel: bool,
+    closing: bool,
+    discarding: bool,
+    rejected: bool,
+    idle_since: Option<Instant>,
+    request_started: Option<Instant>,
+    responding: Option<RequestTiming>,
+    timings: Vec<RequestTiming>,
+    requests: u64,
+    request_headers: Vec<(String, String)>,
+    policy: Option<Arc<dyn RequestPolicy>>,
+    checked: u64,
+}
+
+impl Exchange {
+    pub fn new(request_headers: Vec<(String, String)>) -> Self {
+        Self {
+            request: Framer::default(),
+            response: Framer::default(),
+            pending: VecDeque::new(),
+            tunnel: false,
+            closing: false,
+            discarding: false,
+            rejected: false,
+            idle_since: Some(Instant::now()),
+            request_started: None,
+            responding: None,
+            timings: Vec::new(),
+            requests: 0,
+            request_headers,
+            policy: None,
+            checked: 0,
+        }
+    }
+
+    pub fn with_policy(mut self, policy: Arc<dyn RequestPolicy>, checked: u64) -> Self {
+        self.policy = Some(policy);
+        self.checked = checked;
+        self
+    }
+
+    pub fn idle_since(&self) -> Option<Instant> {
+        self.idle_since
+    }
+
+    pub fn take_timings(&mut self) -> Vec<RequestTiming> {
+        std::mem::take(&mut self.timings)
+    }
+
+    fn update_idle(&mut self) {
+        let idle = !self.tunnel && self.pending.is_empty() && self.request.at_boundary() && self.response.at_boundary();
+        self.idle_since = match (idle, self.idle_since) {
+            (true, Some(since)) => Some(since),
+            (true, None) => Some(Instant::now()),
+            (false, _) => None,
+        };
+    }
+
+    fn verdict(&mut self, head: &mut Vec<u8>) -> Verdict {
+        let checked = self.requests < self.checked;
+        self.requests += 1;
+        match self.policy {
+            Some(ref policy) if !checked => policy.check(head),
+            _ => Verdict::Forward,
+        }
+    }
+
+    fn malformed(&mut self) -> Option<Vec<u8>> {
+        match self.policy.as_ref().map(|policy| policy.malformed()) {
+            Some(Verdict::Reject(response)) => Some(response),
+            _ if self.discarding => Some(Vec::new()),
+            _ => None,
+        }
+    }
+
+    fn reject(&mut self, response: Vec<u8>) {
+        self.rejected = true;
+        self.request = Framer::default();
+        self.pending.push_back(Pending::Reject(response));
+    }
+
+    pub fn forward_request(&mut self, mut data: &[u8], output: &mut Vec<u8>) {
+        let now = Instant::now();
+        while !self.tunnel && !self.rejected && !data.is_empty() {
+            if self.request.body.is_none() {
+                if self.request.head.is_empty() && self.request_started.is_none() {
+                    self.request_started = Some(now);
+                }
+                match self.request.read_head(&mut data) {
+                    Ok(Some(mut head)) => {
+                        let reused = self.requests > 0;
+                        let verdict = self.verdict(&mut head);
+                        let method = head.split(|byte| *byte == b' ').next().unwrap_or_default();
+                        let head_request = method.eq_ignore_ascii_case(b"HEAD");
+                        let started = self.request_started.take().unwrap_or(now);
+                        self.request.body = Some(body_of(&head, false));
+                        match verdict {
+                            Verdict::Forward => {
+                                if method.eq_ignore_ascii_case(b"CONNECT") || !inspect::extract_headers(&head, "upgrade").is_empty() {
+                                    self.tunnel = true;
+                                }
+                                self.discarding = false;
+                                self.pending.push_back(Pending::Backend { head_request, reused, started, sent: now });
+                                if self.request_headers.is_empty() {
+                                    output.extend_from_slice(&head);
+                                } else {
+                                    let headers: Vec<(&str, &str)> = self.request_headers.iter()
+                                        .map(|(name, value)| (name.as_str(), value.as_str()))
+                                        .collect();
+                                    output.extend_from_slice(&with_headers(&head, &[], &headers));
+                                }
+                            }
+                            Verdict::Respond(response) => {
+                                self.discarding = true;
+                                self.pending.push_back(Pending::Local { response, head_request, close: wants_close(&head) });
+                            }
+                            Verdict::Reject(response) => {
+                                self.reject(response);
+                                break;
+                            }
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(()) => {
+                        match self.malformed() {
+                            Some(response) => self.reject(response),
+                            None => {
+                                self.tunnel = true;
+                                output.append(&mut self.request.head);
+                            }
+                        }
+                        break;
+                    }
+                }
+            }
+            let before = data;
+            if self.request.read_body(&mut data).is_err() {
+                match self.malformed() {
+                    Some(response) => {
+                        self.reject(response);
+                        break;
+                    }
+                    None => {
+                        self.tunnel = true;
+                        data = before;
+                    }
+                }
+            }
+            if !self.discarding {
+                output.extend_from_slice(&before[..before.len() - data.len()]);
+            }
+        }
+        if self.tunnel {
+            output.extend_from_slice(data);
+        }
+        self.update_idle();
+    }
+
+    pub fn local_ready(&self) -> bool {
+        self.response.at_boundary() && matches!(self.pending.front(), Some(Pending::Local { .. } | Pending::Reject(_)))
+    }
+
+    pub fn flush_local(&mut self, drain: bool, output: &mut Vec<u8>) -> bool {
+        let mut finished = false;
+        while !finished && self.local_ready() {
+            match self.pending.pop_front() {
+                Some(Pending::Local { response, head_request, close }) => {
+                    finished = close || drain;
+                    output.extend_from_slice(&response.render(head_request, finished));
+                }
+                Some(Pending::Reject(response)) => {
+                    finished = true;
+                    output.extend_from_slice(&response);
+                }
+                _ => {}
+            }
+        }
+        if finished {
+            self.closing = true;
+        }
+        self.update_idle();
+        finished
+    }
+
+    pub fn forward_response(&mut self, mut data: &[u8], drain: bool, output: &mut Vec<u8>) -> bool {
+        let mut finished = self.flush_local(drain, output);
+        while !finished && !self.tunnel && !data.is_empty() {
+            if self.response.body.is_none() {
+                let head = match self.response.read_head(&mut data) {
+                    Ok(Some(head)) => head,
+                    Ok(None) => break,
+                    Err(()) => {
+                        self.tunnel = true;
+                        output.append(&mut self.response.head);
+                        break;
+                    }
+                };
+                let status: u16 = head.split(|byte| *byte == b' ').nth(1)
+                    .and_then(|status| std::str::from_utf8(status).ok())
+                    .and_then(|status| status.parse().ok())
+                    .unwrap_or_default();
+                if (100..200).contains(&status) && status != 101 {
+                    output.extend_from_slice(&head);
+                    continue;
+                }
+                let Some(Pending::Backend { head_request, reused, started, sent }) = self.pending.pop_front() else {
+                    self.tunnel = true;
+                    output.extend_from_slice(&head);
+                    break;
+                };
+                if status == 101 {
+                    self.tunnel = true;
+                    output.extend_from_slice(&head);
+                    break;
+                }
+                let now = Instant::now();
+                self.responding = Some(RequestTiming { reused, started, sent, responded: now, finished: now });
+                self.response.body = Some(if head_request || status == 204 || status == 304 { Body::Empty } else { body_of(&head, true) });
+                if drain && !self.closing {
+                    self.closing = true;
+                    output.extend_from_slice(&with_headers(&head, &["keep-alive"], &[("Connection", "close")]));
+                } else {
+                    output.extend_from_slice(&head);
+                }
+            }
+            let before = data;
+            match self.response.read_body(&mut data) {
+                Ok(complete) => {
+                    output.extend_from_slice(&before[..before.len() - data.len()]);
+                    if complete {
+                        if let Some(timing) = self.responding.take() {
+                            self.timings.push(RequestTiming { finished: Instant::now(), ..timing });
+                        }
+                        finished = self.closing || self.flush_local(drain, output);
+                    }
+                }
+                Err(()) => {
+                    self.tunnel = true;
+                    output.extend_from_slice(before);
+                    data = &[];
+                }
+            }
+        }
+        if self.tunnel {
+            output.extend_from_slice(data);
+        }
+        self.update_idle();
+        finished
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        410 => "Gone",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "",
+    }
+}
+
+fn wants_close(head: &[u8]) -> bool {
+    let tokens = inspect::extract_headers(head, "connection").iter()
+        .flat_map(|value| value.split(','))
+        .map(|token| token.trim().to_ascii_lowercase())
+        .collect::<Vec<_>>();
+    let http10 = head.split(|byte| *byte == b'\r').next().is_some_and(|line| line.ends_with(b"HTTP/1.0"));
+    tokens.iter().any(|token| token == "close") || (http10 && !tokens.iter().any(|token| token == "keep-alive"))
+}
+
+#[derive(Debug)]
+pub struct StaticResponse {
+    status: u16,
+    content_type: Option<String>,
+    body: Vec<u8>,
+}
+
+impl StaticResponse {
+    pub fn new(config: &HttpReturnConfig) -> Self {
+        Self { status: config.status, content_type: config.content_type.clone(), body: config.body.clone() }
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    pub fn render(&self, head_request: bool, close: bool) -> Vec<u8> {
+        let mut response = format!("HTTP/1.1 {} {}\r\nCache-Control: no-cache\r\n", self.status, reason(self.status));
+        if let Some(ref content_type) = self.content_type {
+            response.push_str(&format!("Content-Type: {}\r\n", content_type));
+        }
+        response.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        if close {
+            response.push_str("Connection: close\r\n");
+        }
+        response.push_str("\r\n");
+        let mut response = response.into_bytes();
+        if !head_request {
+            response.extend_from_slice(&self.body);
+        }
+        response
+    }
+
+    pub async fn serve<S>(&self, stream: &mut S, prefix: &[u8], idle: Option<Duration>, draining: impl Fn() -> bool,
+                          policy: Option<&dyn RequestPolicy>, fallback: bool) -> io::Result<Served>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut framer = Framer::default();
+        let mut buffer = vec![0u8; 16 * 1024];
+        let mut served = Served::default();
+        let mut input = prefix;
+        loop {
+            while !input.is_empty() {
+                if framer.body.is_none() {
+                    match framer.read_head(&mut input) {
+                        Ok(Some(mut head)) => {
+                            let verdict = match policy {
+                                Some(policy) if served.requests > 0 => policy.check(&mut head),
+                                _ => Verdict::Forward,
+                            };
+                            let response = match verdict {
+                                Verdict::Reject(response) => {
+                                    stream.write_all(&response).await?;
+                                    stream.shutdown().await?;
+                                    return Ok(served);
+                                }
+                                Verdict::Respond(ref response) => response.as_ref(),
+                                Verdict::Forward if served.requests == 0 || fallback => {
+                                    served.responses += 1;
+                                    self
+                                }
+                                Verdict::Forward => {
+                                    head.extend_from_slice(input);
+                                    served.forward = Some(head);
+                                    return Ok(served);
+                                }
+                            };
+                            served.requests += 1;
+                            let method = head.split(|byte| *byte == b' ').next().unwrap_or_default();
+                            let close = wants_close(&head) || draining();
+                            framer.body = Some(body_of(&head, false));
+                            stream.write_all(&response.render(method.eq_ignore_ascii_case(b"HEAD"), close)).await?;
+                            if close {
+                                stream.shutdown().await?;
+                                return Ok(served);
+                            }
+                        }
+                        Ok(None) => {}
+                        Err(()) => {
+                            if let Some(Verdict::Reject(response)) = policy.map(|policy| policy.malformed()) {
+                                stream.write_all(&response).await?;
+                            }
+                            stream.shutdown().await?;
+                            return Ok(served);
+                        }
+                    }
+                }
+                if framer.read_body(&mut input).is_err() {
+                    if let Some(Verdict::Reject(response)) = policy.map(|policy| policy.malformed()) {
+                        stream.write_all(&response).await?;
+                    }
+                    stream.shutdown().await?;
+                    return Ok(served);
+                }
+            }
+
+            let n = match idle {
+                Some(idle) => match tokio::time::timeout(idle, stream.read(&mut buffer)).await {
+                    Ok(result) => result?,
+                    Err(_) => {
+                        stream.shutdown().await?;
+                        return Ok(served);
+                    }
+                },
+                None => stream.read(&mut buffer).await?,
+            };
+            if n == 0 {
+                return Ok(served);
+            }
+            input = &buffer[..n];
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Served {
+    pub requests: u64,
+    pub responses: u64,
+    pub forward: Option<Vec<u8>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SourceSpan;
+
+    const BAD_REQUEST: &[u8] = b"HTTP/1.1 400 Bad Request\r\nConnection: close\r\n\r\n";
+
+    struct TestPolicy {
+        local: Arc<StaticResponse>,
+    }
+
+    impl TestPolicy {
+        fn new() -> Arc<Self> {
+            let local = StaticResponse::new(&HttpReturnConfig {
+                status: 418,
+                content_type: Some("text/plain".to_string()),
+                body: b"teapot".to_vec(),
+                condition: None,
+                span: SourceSpan::default(),
+            });
+            Arc::new(Self { local: Arc::new(local) })
+        }
+    }
+
+    impl RequestPolicy for TestPolicy {
+        fn check(&self, head: &mut Vec<u8>) -> Verdict {
+            if inspect::validate_http_request(head).is_err() {
+                return Verdict::Reject(BAD_REQUEST.to_vec());
+            }
+            match inspect::extract_path(head) {
+                Some("/local") => Verdict::Respond(Arc::clone(&self.local)),
+                _ => Verdict::Forward,
+            }
+        }
+
+        fn malformed(&self) -> Verdict {
+            Verdict::Reject(BAD_REQUEST.to_vec())
+        }
+    }
+
+    fn exchange(checked: u64) -> Exchange {
+        Exchange::new(Vec::new()).with_policy(TestPolicy::new(), checked)
+    }
+
+    fn request(exchange: &mut Exchange, data: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        exchange.forward_request(data, &mut output);
+        output
+    }
+
+    fn response(exchange: &mut Exchange, data: &[u8]) -> (Vec<u8>, bool) {
+        let mut output = Vec::new();
+        let finished = exchange.forward_response(data, false, &mut output);
+        (output, finished)
+    }
+
+    fn flush(exchange: &mut Exchange) -> (Vec<u8>, bool) {
+        let mut output = Vec::new();
+        let finished = exchange.flush_local(false, &mut output);
+        (output, finished)
+    }
+
+    const FIRST: &[u8] = b"GET / HTTP/1.1\r\nHost: a\r\n\r\n";
+    const OK: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+
+    #[test]
+    fn smuggled_requests_are_rejected_and_not_forwarded() {
+        let cases: &[(&str, &[u8])] = &[
+            ("CL.TE", b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 6\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nG"),
+            ("TE.CL", b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 4\r\nTransfer-Encoding: chunked\r\n\r\n5c\r\nGPOST / HTTP/1.1\r\n\r\n0\r\n\r\n"),
+            ("TE.TE duplicate header", b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: cow\r\n\r\n"),
+            ("TE.TE chunked twice", b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked, chunked\r\n\r\n"),
+            ("TE.TE chunked not last", b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked, identity\r\n\r\n"),
+            ("TE.TE unknown coding", b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: xchunked\r\n\r\n"),
+            ("TE.TE space before colon", b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding : chunked\r\n\r\n"),
+            ("TE.TE obs-fold", b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding:\r\n chunked\r\n\r\n"),
+            ("TE.TE bare LF", b"POST / HTTP/1.1\r\nHost: a\nTransfer-Encoding: chunked\r\n\r\n"),
+            ("TE.TE bare CR", b"POST / HTTP/1.1\r\nHost: a\rTransfer-Encoding: chunked\r\n\r\n"),
+            ("CL.CL", b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 0\r\nContent-Length: 5\r\n\r\n"),
+            ("CL list", b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 0, 5\r\n\r\n"),
+            ("CL sign", b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: +5\r\n\r\n"),
+        ];
+        for (name, smuggled) in cases {
+            let mut exchange = exchange(0);
+            let mut data = FIRST.to_vec();
+            data.extend_from_slice(smuggled);
+            data.extend_from_slice(b"GET /after HTTP/1.1\r\nHost: a\r\n\r\n");
+            assert_eq!(request(&mut exchange, &data), FIRST, "{name}");
+            assert!(request(&mut exchange, b"GET /later HTTP/1.1\r\n\r\n").is_empty(), "{name}");
+            let (output, finished) = response(&mut exchange, OK);
+            let mut expected = OK.to_vec();
+            expected.extend_from_slice(BAD_REQUEST);
+            assert_eq!(output, expected, "{name}");
+            assert!(finished, "{name}");
+        }
+    }
+
+    #[test]
+    fn chunked_requests_pass_through() {
+        let mut exchange = exchange(0);
+        let data = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n3;ext=1\r\nabc\r\n0\r\nTrailer: x\r\n\r\nGET /next HTTP/1.1\r\n\r\n";
+        assert_eq!(request(&mut exchange, data), data);
+        assert_eq!(exchange.pending.len(), 2);
+    }
+
+    #[test]
+    fn bad_chunk_size_is_rejected() {
+        let mut exchange = exchange(0);
+        let output = request(&mut exchange, b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n");
+        assert_eq!(output, b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n");
+        let (output, _) = response(&mut exchange, OK);
+        assert!(output.ends_with(BAD_REQUEST));
+    }
+
+    #[test]
+    fn unterminated_heads_are_rejected_instead_of_tunnelled() {
+        let mut exchange = exchange(0);
+        let mut head = b"GET / HTTP/1.1\n".to_vec();
+        head.resize(MAX_HEAD_SIZE + 1, b'a');
+        assert!(request(&mut exchange, &head).is_empty());
+        assert_eq!(flush(&mut exchange), (BAD_REQUEST.to_vec(), true));
+    }
+
+    #[test]
+    fn checked_heads_are_not_evaluated_again() {
+        let mut exchange = exchange(1);
+        let invalid = b"GET / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\n";
+        let output = request(&mut exchange, invalid);
+        assert!(output.starts_with(invalid));
+        assert!(!exchange.local_ready());
+    }
+
+    #[test]
+    fn local_responses_keep_pipeline_order() {
+        let mut exchange = exchange(0);
+        let data = b"GET /a HTTP/1.1\r\n\r\nGET /local HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n\r\n";
+        assert_eq!(request(&mut exchange, data), b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n\r\n");
+        assert!(!exchange.local_ready());
+        let (output, finished) = response(&mut exchange, OK);
+        assert!(!finished);
+        let text = String::from_utf8(output).unwrap();
+        assert!(text.starts_with("HTTP/1.1 200 OK"));
+        assert!(text.ends_with("HTTP/1.1 418 \r\nCache-Control: no-cache\r\nContent-Type: text/plain\r\nContent-Length: 6\r\n\r\nteapot"), "{text}");
+        let (output, _) = response(&mut exchange, OK);
+        assert_eq!(output, OK);
+        assert!(exchange.pending.is_empty());
+    }
+
+    #[test]
+    fn local_response_on_idle_connection_is_ready_at_once() {
+        let mut exchange = exchange(0);
+        assert!(request(&mut exchange, b"HEAD /local HTTP/1.1\r\nConnection: close\r\n\r\n").is_empty());
+        assert!(exchange.local_ready());
+        let (output, finished) = flush(&mut exchange);
+        assert!(finished);
+        assert!(output.ends_with(b"Content-Length: 6\r\nConnection: close\r\n\r\n"));
+    }
+
+    #[test]
+    fn local_request_bodies_are_discarded() {
+        let mut exchange = exchange(0);
+        let data = b"POST /local HTTP/1.1\r\nContent-Length: 22\r\n\r\nGET /smuggled HTTP/1.1GET /b HTTP/1.1\r\n\r\n";
+        assert_eq!(request(&mut exchange, data), b"GET /b HTTP/1.1\r\n\r\n");
+    }
+
+    #[test]
+    fn timings_are_recorded_per_request() {
+        let mut exchange = exchange(0);
+        request(&mut exchange, b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n");
+        request(&mut exchange, b"\r\n");
+        response(&mut exchange, OK);
+        response(&mut exchange, b"HTTP/1.1 204 No Content\r\n\r\n");
+        let timings = exchange.take_timings();
+        assert_eq!(timings.len(), 2);
+        assert!(!timings[0].reused);
+        assert!(timings[1].reused);
+        for timing in &timings {
+            assert!(timing.started <= timing.sent && timing.sent <= timing.responded && timing.responded <= timing.finished);
+        }
+        assert!(exchange.take_timings().is_empty());
+    }
+
+    #[test]
+    fn without_policy_framing_errors_tunnel() {
+        let mut exchange = Exchange::new(Vec::new());
+        let data = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\nanything";
+        assert_eq!(request(&mut exchange, data), data);
+        assert!(exchange.tunnel);
+    }
+}
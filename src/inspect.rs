@@ -0,0 +1,660 @@
+const TLS_HANDSHAKE: u8 = 0x16;
+const TLS_CLIENT_HELLO: u8 = 0x01;
+const TLS_EXTENSION_SERVER_NAME: u16 = 0x0000;
+const TLS_SERVER_NAME_HOST: u8 = 0x00;
+const TLS_SERVER_HELLO: u8 = 0x02;
+const TLS_EXTENSION_SESSION_TICKET: u16 = 0x0023;
+const TLS_EXTENSION_PRE_SHARED_KEY: u16 = 0x0029;
+
+struct Cursor<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        let value = *self.data.get(self.position)?;
+        self.position += 1;
+        Some(value)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let bytes = self.bytes(2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u24(&mut self) -> Option<usize> {
+        let bytes = self.bytes(3)?;
+        Some(((bytes[0] as usize) << 16) | ((bytes[1] as usize) << 8) | bytes[2] as usize)
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let end = self.position.checked_add(len)?;
+        let bytes = self.data.get(self.position..end)?;
+        self.position = end;
+        Some(bytes)
+    }
+
+    fn skip(&mut self, len: usize) -> Option<()> {
+        self.bytes(len).map(|_| ())
+    }
+}
+
+pub fn tls_record_complete(prefix: &[u8]) -> bool {
+    match prefix {
+        [TLS_HANDSHAKE, _, _, high, low, rest @ ..] => rest.len() >= u16::from_be_bytes([*high, *low]) as usize,
+        _ => false,
+    }
+}
+
+pub fn is_tls_client_hello(prefix: &[u8]) -> bool {
+    prefix.len() >= 6 && prefix[0] == TLS_HANDSHAKE && prefix[1] == 0x03 && prefix[5] == TLS_CLIENT_HELLO
+}
+
+struct ClientHello<'a> {
+    session_id: &'a [u8],
+    extensions: &'a [u8],
+}
+
+impl<'a> ClientHello<'a> {
+    fn parse(prefix: &'a [u8]) -> Option<Self> {
+        if !is_tls_client_hello(prefix) {
+            return None;
+        }
+
+        let mut hello = handshake_body(prefix)?;
+        hello.u8()?;
+        hello.u24()?;
+        hello.skip(2 + 32)?;
+        let session_id_len = hello.u8()? as usize;
+        let session_id = hello.bytes(session_id_len)?;
+        let cipher_suites_len = hello.u16()? as usize;
+        hello.skip(cipher_suites_len)?;
+        let compression_len = hello.u8()? as usize;
+        hello.skip(compression_len)?;
+
+        let extensions_len = hello.u16()? as usize;
+        let extensions = hello.bytes(extensions_len)?;
+
+        Some(Self { session_id, extensions })
+    }
+
+    fn extension(&self, wanted: u16) -> Option<&'a [u8]> {
+        let mut extensions = Cursor::new(self.extensions);
+        while let Some(extension_type) = extensions.u16() {
+            let extension_len = extensions.u16()? as usize;
+            let extension = extensions.bytes(extension_len)?;
+            if extension_type == wanted {
+                return Some(extension);
+            }
+        }
+        None
+    }
+}
+
+fn handshake_body(prefix: &[u8]) -> Option<Cursor<'_>> {
+    let mut record = Cursor::new(prefix);
+    record.skip(3)?;
+    let record_len = record.u16()? as usize;
+    let record_len = record_len.min(prefix.len() - 5);
+    Some(Cursor::new(record.bytes(record_len)?))
+}
+
+pub fn extract_sni(prefix: &[u8]) -> Option<String> {
+    let hello = ClientHello::parse(prefix)?;
+
+    let mut names = Cursor::new(hello.extension(TLS_EXTENSION_SERVER_NAME)?);
+    let list_len = names.u16()? as usize;
+    let mut list = Cursor::new(names.bytes(list_len)?);
+    while let Some(name_type) = list.u8() {
+        let name_len = list.u16()? as usize;
+        let name = list.bytes(name_len)?;
+        if name_type == TLS_SERVER_NAME_HOST {
+            return std::str::from_utf8(name).ok().map(|n| n.to_ascii_lowercase());
+        }
+    }
+
+    None
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TlsSessionKind {
+    SessionId,
+    Ticket,
+    PskIdentity,
+}
+
+impl TlsSessionKind {
+    fn tag(&self) -> u8 {
+        match self {
+            TlsSessionKind::SessionId => b'i',
+            TlsSessionKind::Ticket => b't',
+            TlsSessionKind::PskIdentity => b'p',
+        }
+    }
+}
+
+fn tls_session_key(kind: TlsSessionKind, value: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(value.len() + 1);
+    key.push(kind.tag());
+    key.extend_from_slice(value);
+    key
+}
+
+pub fn extract_tls_session_keys(prefix: &[u8]) -> Vec<Vec<u8>> {
+    let Some(hello) = ClientHello::parse(prefix) else {
+        return Vec::new();
+    };
+
+    let mut keys = Vec::new();
+
+    if let Some(identities) = hello.extension(TLS_EXTENSION_PRE_SHARED_KEY).and_then(psk_identities) {
+        keys.extend(identities.into_iter().map(|identity| tls_session_key(TlsSessionKind::PskIdentity, identity)));
+    }
+    if let Some(ticket) = hello.extension(TLS_EXTENSION_SESSION_TICKET).filter(|ticket| !ticket.is_empty()) {
+        keys.push(tls_session_key(TlsSessionKind::Ticket, ticket));
+    }
+    if !hello.session_id.is_empty() {
+        keys.push(tls_session_key(TlsSessionKind::SessionId, hello.session_id));
+    }
+
+    keys
+}
+
+fn psk_identities(extension: &[u8]) -> Option<Vec<&[u8]>> {
+    let mut psk = Cursor::new(extension);
+    let identities_len = psk.u16()? as usize;
+    let mut identities = Cursor::new(psk.bytes(identities_len)?);
+
+    let mut found = Vec::new();
+    while let Some(identity_len) = identities.u16() {
+        let identity = identities.bytes(identity_len as usize)?;
+        identities.skip(4)?;
+        if !identity.is_empty() {
+            found.push(identity);
+        }
+    }
+
+    Some(found)
+}
+
+pub fn extract_server_hello_session_key(response: &[u8]) -> Option<Vec<u8>> {
+    if response.len() < 6 || response[0] != TLS_HANDSHAKE || response[1] != 0x03 || response[5] != TLS_SERVER_HELLO {
+        return None;
+    }
+
+    let mut hello = handshake_body(response)?;
+    hello.u8()?;
+    hello.u24()?;
+    hello.skip(2 + 32)?;
+    let session_id_len = hello.u8()? as usize;
+    let session_id = hello.bytes(session_id_len)?;
+
+    (!session_id.is_empty()).then(|| tls_session_key(TlsSessionKind::SessionId, session_id))
+}
+
+pub fn extract_host(prefix: &[u8]) -> Option<String> {
+    let text = std::str::from_utf8(prefix).ok()?;
+    let headers = text.split("\r\n\r\n").next()?;
+
+    headers.split("\r\n").skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("host"))
+        .map(|(_, value)| {
+            let value = value.trim();
+            let host = match value.strip_prefix('[') {
+                Some(rest) => rest.split(']').next().unwrap_or(rest),
+                None => value.split(':').next().unwrap_or(value),
+            };
+            host.to_ascii_lowercase()
+        })
+        .filter(|host| !host.is_empty())
+}
+
+pub fn extract_path(prefix: &[u8]) -> Option<&str> {
+    let text = std::str::from_utf8(prefix).ok()?;
+    let mut request_line = text.split("\r\n").next()?.split(' ');
+    let (_method, target, version) = (request_line.next()?, request_line.next()?, request_line.next()?);
+    if !version.starts_with("HTTP/") {
+        return None;
+    }
+
+    Some(target.split('?').next().unwrap_or(target))
+}
+
+pub fn extract_headers<'a>(prefix: &'a [u8], name: &str) -> Vec<&'a str> {
+    let Ok(text) = std::str::from_utf8(prefix) else {
+        return Vec::new();
+    };
+    let headers = text.split("\r\n\r\n").next().unwrap_or_default();
+
+    headers.split("\r\n").skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .filter(|(header, _)| header.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim())
+        .collect()
+}
+
+pub fn extract_server_name(prefix: &[u8]) -> Option<String> {
+    extract_sni(prefix).or_else(|| extract_host(prefix))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HttpViolation {
+    BadRequestLine,
+    BareCr,
+    BareLf,
+    ObsFold,
+    InvalidHeaderName,
+    InvalidHeaderValue,
+    InvalidContentLength,
+    ConflictingContentLength,
+    TransferEncodingAndContentLength,
+    InvalidTransferEncoding,
+    NotConnect,
+    BadAuthority,
+}
+
+impl HttpViolation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HttpViolation::BadRequestLine => "bad_request_line",
+            HttpViolation::BareCr => "bare_cr",
+            HttpViolation::BareLf => "bare_lf",
+            HttpViolation::ObsFold => "obs_fold",
+            HttpViolation::InvalidHeaderName => "invalid_header_name",
+            HttpViolation::InvalidHeaderValue => "invalid_header_value",
+            HttpViolation::InvalidContentLength => "invalid_content_length",
+            HttpViolation::ConflictingContentLength => "conflicting_content_length",
+            HttpViolation::TransferEncodingAndContentLength => "te_and_cl",
+            HttpViolation::InvalidTransferEncoding => "invalid_transfer_encoding",
+            HttpViolation::NotConnect => "not_connect",
+            HttpViolation::BadAuthority => "bad_authority",
+        }
+    }
+}
+
+fn head_end(prefix: &[u8]) -> Option<usize> {
+    prefix.windows(4).position(|w| w == b"\r\n\r\n").map(|position| position + 2)
+}
+
+pub fn head_complete(prefix: &[u8]) -> bool {
+    head_end(prefix).is_some()
+}
+
+pub fn is_token(name: &[u8]) -> bool {
+    !name.is_empty() && name.iter().all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(byte))
+}
+
+fn trim_ows(value: &[u8]) -> &[u8] {
+    let start = value.iter().position(|byte| *byte != b' ' && *byte != b'\t').unwrap_or(value.len());
+    let end = value.iter().rposition(|byte| *byte != b' ' && *byte != b'\t').map_or(start, |end| end + 1);
+    &value[start..end]
+}
+
+fn valid_request_line(line: &[u8]) -> bool {
+    let parts: Vec<&[u8]> = line.split(|byte| *byte == b' ').collect();
+    let [method, target, version] = parts.as_slice() else {
+        return false;
+    };
+    is_token(method)
+        && !target.is_empty()
+        && target.iter().all(|byte| byte.is_ascii_graphic())
+        && matches!(version, [b'H', b'T', b'T', b'P', b'/', major, b'.', minor] if major.is_ascii_digit() && minor.is_ascii_digit())
+}
+
+pub fn validate_http_request(prefix: &[u8]) -> Result<(), HttpViolation> {
+    let head = &prefix[..head_end(prefix).unwrap_or(prefix.len())];
+
+    for (position, byte) in head.iter().enumerate() {
+        match byte {
+            b'\n' if position == 0 || head[position - 1] != b'\r' => return Err(HttpViolation::BareLf),
+            b'\r' if position + 1 < head.len() && head[position + 1] != b'\n' => return Err(HttpViolation::BareCr),
+            _ => {}
+        }
+    }
+
+    let mut lines: Vec<&[u8]> = head.split(|byte| *byte == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .collect();
+    lines.pop();
+    let Some((request_line, headers)) = lines.split_first() else {
+        return Ok(());
+    };
+    if !valid_request_line(request_line) {
+        return Err(HttpViolation::BadRequestLine);
+    }
+
+    let mut content_length: Option<u64> = None;
+    let mut codings: Vec<String> = Vec::new();
+    for line in headers {
+        if line.first().is_some_and(|byte| *byte == b' ' || *byte == b'\t') {
+            return Err(HttpViolation::ObsFold);
+        }
+        let colon = line.iter().position(|byte| *byte == b':').ok_or(HttpViolation::InvalidHeaderName)?;
+        let (name, value) = (&line[..colon], trim_ows(&line[colon + 1..]));
+        if !is_token(name) {
+            return Err(HttpViolation::InvalidHeaderName);
+        }
+        if value.iter().any(|byte| (byte.is_ascii_control() && *byte != b'\t') || *byte == 0x7f) {
+            return Err(HttpViolation::InvalidHeaderValue);
+        }
+
+        if name.eq_ignore_ascii_case(b"content-length") {
+            for part in value.split(|byte| *byte == b',').map(trim_ows) {
+                let length = std::str::from_utf8(part).ok()
+                    .filter(|part| !part.is_empty() && part.bytes().all(|byte| byte.is_ascii_digit()))
+                    .and_then(|part| part.parse::<u64>().ok())
+                    .ok_or(HttpViolation::InvalidContentLength)?;
+                if content_length.is_some_and(|previous| previous != length) {
+                    return Err(HttpViolation::ConflictingContentLength);
+                }
+                content_length = Some(length);
+            }
+        } else if name.eq_ignore_ascii_case(b"transfer-encoding") {
+            codings.extend(value.split(|byte| *byte == b',')
+                .map(|coding| String::from_utf8_lossy(trim_ows(coding)).to_ascii_lowercase()));
+        }
+    }
+
+    if !codings.is_empty() {
+        if content_length.is_some() {
+            return Err(HttpViolation::TransferEncodingAndContentLength);
+        }
+        let chunked = codings.iter().filter(|coding| *coding == "chunked").count();
+        if codings.iter().any(String::is_empty) || chunked != 1 || codings.last().map(String::as_str) != Some("chunked") {
+            return Err(HttpViolation::InvalidTransferEncoding);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn normalize_request_head(prefix: &[u8]) -> Option<Vec<u8>> {
+    let end = head_end(prefix)?;
+    let mut lines = prefix[..end - 2].split(|byte| *byte == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line));
+    let mut normalized = Vec::with_capacity(prefix.len());
+    normalized.extend_from_slice(lines.next()?);
+    normalized.extend_from_slice(b"\r\n");
+    for line in lines {
+        let (name, value) = line.split_at(line.iter().position(|byte| *byte == b':')?);
+        normalized.extend(name.iter().map(u8::to_ascii_lowercase));
+        normalized.extend_from_slice(b": ");
+        normalized.extend_from_slice(trim_ows(&value[1..]));
+        normalized.extend_from_slice(b"\r\n");
+    }
+    normalized.extend_from_slice(&prefix[end..]);
+    Some(normalized)
+}
+
+pub fn strip_header(prefix: &[u8], head_len: usize, name: &str) -> Vec<u8> {
+    let mut stripped = Vec::with_capacity(prefix.len());
+    for line in prefix[..head_len].split_inclusive(|byte| *byte == b'\n') {
+        let header = line.iter().position(|byte| *byte == b':').map(|colon| trim_ows(&line[..colon]));
+        if !header.is_some_and(|header| header.eq_ignore_ascii_case(name.as_bytes())) {
+            stripped.extend_from_slice(line);
+        }
+    }
+    stripped.extend_from_slice(&prefix[head_len..]);
+    stripped
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectRequest {
+    pub host: String,
+    pub port: u16,
+    pub head_len: usize,
+}
+
+pub fn parse_connect_request(prefix: &[u8]) -> Result<ConnectRequest, HttpViolation> {
+    let head_len = head_end(prefix).map(|end| end + 2).ok_or(HttpViolation::BadRequestLine)?;
+    validate_http_request(&prefix[..head_len])?;
+
+    let line_end = prefix.windows(2).position(|w| w == b"\r\n").unwrap_or(head_len);
+    let request_line = std::str::from_utf8(&prefix[..line_end]).map_err(|_| HttpViolation::BadRequestLine)?;
+    let mut parts = request_line.split(' ');
+    let (method, authority) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+    if method != "CONNECT" {
+        return Err(HttpViolation::NotConnect);
+    }
+
+    let (host, port) = authority.rsplit_once(':').ok_or(HttpViolation::BadAuthority)?;
+    let host = match host.strip_prefix('[') {
+        Some(rest) => rest.strip_suffix(']').filter(|ip| ip.parse::<std::net::Ipv6Addr>().is_ok()),
+        None => Some(host).filter(|host| !host.contains(':')),
+    }
+    .filter(|host| !host.is_empty() && host.bytes().all(|byte| byte.is_ascii_alphanumeric() || b".-_:".contains(&byte)))
+    .ok_or(HttpViolation::BadAuthority)?;
+    let port = port.parse::<u16>().ok().filter(|port| *port > 0).ok_or(HttpViolation::BadAuthority)?;
+
+    Ok(ConnectRequest { host: host.to_ascii_lowercase(), port, head_len })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fetch {
+    Sni,
+    Host,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Converter {
+    Lower,
+    Word(usize, char),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TemplatePart {
+    Literal(String),
+    Fetch(Fetch, Vec<Converter>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameTemplate {
+    parts: Vec<TemplatePart>,
+}
+
+impl NameTemplate {
+    pub fn is_template(value: &str) -> bool {
+        value.contains("%[")
+    }
+
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut rest = value;
+
+        while let Some(start) = rest.find("%[") {
+            if start > 0 {
+                parts.push(TemplatePart::Literal(rest[..start].to_string()));
+            }
+            let end = rest[start..].find(']')
+                .ok_or_else(|| format!("unterminated '%[' in '{}'", value))?;
+            let expression = &rest[start + 2..start + end];
+            parts.push(Self::parse_expression(expression)?);
+            rest = &rest[start + end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(TemplatePart::Literal(rest.to_string()));
+        }
+
+        Ok(Self { parts })
+    }
+
+    fn parse_expression(expression: &str) -> Result<TemplatePart, String> {
+        let mut items = split_converters(expression).into_iter();
+        let fetch = match items.next().map(str::trim) {
+            Some("sni") | Some("req.ssl_sni") => Fetch::Sni,
+            Some("host") | Some("req.hdr(host)") => Fetch::Host,
+            other => return Err(format!("unsupported fetch '{}', expected sni or host", other.unwrap_or(""))),
+        };
+
+        let converters = items
+            .map(|converter| match converter.trim() {
+                "lower" => Ok(Converter::Lower),
+                word if word.starts_with("word(") && word.ends_with(')') => {
+                    let args = &word[5..word.len() - 1];
+                    let (index, separator) = args.split_once(',')
+                        .ok_or_else(|| format!("word() requires an index and a separator, got '{}'", word))?;
+                    let index: usize = index.trim().parse().ok().filter(|i| *i > 0)
+                        .ok_or_else(|| format!("word() index must be a positive integer, got '{}'", index))?;
+                    let mut separator = separator.chars();
+                    match (separator.next(), separator.next()) {
+                        (Some(separator), None) => Ok(Converter::Word(index, separator)),
+                        _ => Err(format!("word() separator must be a single character in '{}'", word)),
+                    }
+                }
+                other => Err(format!("unsupported converter '{}', expected lower or word(<n>,<sep>)", other)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(TemplatePart::Fetch(fetch, converters))
+    }
+
+    pub fn render(&self, prefix: &[u8], sni: Option<&str>) -> Option<String> {
+        let mut rendered = String::new();
+
+        for part in &self.parts {
+            match part {
+                TemplatePart::Literal(literal) => rendered.push_str(literal),
+                TemplatePart::Fetch(fetch, converters) => {
+                    let mut value = match fetch {
+                        Fetch::Sni => sni.map(str::to_string).or_else(|| extract_sni(prefix))?,
+                        Fetch::Host => extract_host(prefix)?,
+                    };
+                    for converter in converters {
+                        value = match converter {
+                            Converter::Lower => value.to_ascii_lowercase(),
+                            Converter::Word(index, separator) => value.split(*separator)
+                                .filter(|word| !word.is_empty())
+                                .nth(index - 1)?
+                                .to_string(),
+                        };
+                    }
+                    let value = value.to_ascii_lowercase();
+                    let valid = !value.is_empty()
+                        && value.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b"-_.".contains(&b));
+                    if !valid {
+                        return None;
+                    }
+                    rendered.push_str(&value);
+                }
+            }
+        }
+
+        Some(rendered)
+    }
+}
+
+fn split_converters(expression: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+
+    for (i, c) in expression.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                items.push(&expression[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(&expression[start..]);
+
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(head: &str) -> Result<(), HttpViolation> {
+        validate_http_request(head.as_bytes())
+    }
+
+    #[test]
+    fn valid_requests_pass() {
+        let cases = [
+            "GET / HTTP/1.1\r\nHost: a\r\n\r\n",
+            "GET /path?q=1 HTTP/1.0\r\n\r\n",
+            "POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello",
+            "POST / HTTP/1.1\r\nContent-Length: 5, 5\r\n\r\n",
+            "POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 5\r\n\r\n",
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n",
+            "POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n",
+            "POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\nTransfer-Encoding: CHUNKED\r\n\r\n",
+            "GET / HTTP/1.1\r\nX-Tab:\tvalue\t\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: a\r\n",
+        ];
+        for case in cases {
+            assert_eq!(validate(case), Ok(()), "{case:?}");
+        }
+    }
+
+    #[test]
+    fn smuggling_payloads_are_rejected() {
+        use HttpViolation::*;
+        let cases = [
+            ("POST / HTTP/1.1\r\nContent-Length: 6\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nG", TransferEncodingAndContentLength),
+            ("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 4\r\n\r\n5c\r\nGPOST", TransferEncodingAndContentLength),
+            ("POST / HTTP/1.1\r\nContent-Length: 0\r\nContent-Length: 5\r\n\r\n", ConflictingContentLength),
+            ("POST / HTTP/1.1\r\nContent-Length: 0, 5\r\n\r\n", ConflictingContentLength),
+            ("POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n", InvalidContentLength),
+            ("POST / HTTP/1.1\r\nContent-Length: 0x10\r\n\r\n", InvalidContentLength),
+            ("POST / HTTP/1.1\r\nContent-Length:\r\n\r\n", InvalidContentLength),
+            ("POST / HTTP/1.1\r\nContent-Length: 99999999999999999999\r\n\r\n", InvalidContentLength),
+            ("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: x\r\n\r\n", InvalidTransferEncoding),
+            ("POST / HTTP/1.1\r\nTransfer-Encoding: xchunked\r\n\r\n", InvalidTransferEncoding),
+            ("POST / HTTP/1.1\r\nTransfer-Encoding: chunked, chunked\r\n\r\n", InvalidTransferEncoding),
+            ("POST / HTTP/1.1\r\nTransfer-Encoding: chunked, identity\r\n\r\n", InvalidTransferEncoding),
+            ("POST / HTTP/1.1\r\nTransfer-Encoding: ,chunked\r\n\r\n", InvalidTransferEncoding),
+            ("POST / HTTP/1.1\r\nTransfer-Encoding : chunked\r\n\r\n", InvalidHeaderName),
+            ("POST / HTTP/1.1\r\n Transfer-Encoding: chunked\r\n\r\n", ObsFold),
+            ("POST / HTTP/1.1\r\nTransfer-Encoding:\r\n\tchunked\r\n\r\n", ObsFold),
+            ("POST / HTTP/1.1\r\nX: 1\nTransfer-Encoding: chunked\r\n\r\n", BareLf),
+            ("POST / HTTP/1.1\r\nX: 1\rTransfer-Encoding: chunked\r\n\r\n", BareCr),
+            ("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\x0b\r\n\r\n", InvalidHeaderValue),
+            ("POST / HTTP/1.1\r\nX: a\x00b\r\n\r\n", InvalidHeaderValue),
+            ("POST / HTTP/1.1\r\nNoColon\r\n\r\n", InvalidHeaderName),
+            ("GET  / HTTP/1.1\r\n\r\n", BadRequestLine),
+            ("GET / HTTP/1.1 extra\r\n\r\n", BadRequestLine),
+            ("GET / HTTX/1.1\r\n\r\n", BadRequestLine),
+            ("GET /\x7f HTTP/1.1\r\n\r\n", BadRequestLine),
+        ];
+        for (case, violation) in cases {
+            assert_eq!(validate(case), Err(violation), "{case:?}");
+        }
+    }
+
+    #[test]
+    fn only_the_head_is_validated() {
+        assert_eq!(validate("POST / HTTP/1.1\r\nContent-Length: 3\r\n\r\na\nb"), Ok(()));
+    }
+
+    #[test]
+    fn normalization_lowercases_names_and_trims_values() {
+        let normalized = normalize_request_head(b"GET / HTTP/1.1\r\nHOST:   a  \r\nX-Tab:\tb\t\r\n\r\nbody").unwrap();
+        assert_eq!(normalized, b"GET / HTTP/1.1\r\nhost: a\r\nx-tab: b\r\n\r\nbody");
+        assert_eq!(normalize_request_head(b"GET / HTTP/1.1\r\nHost: a\r\n"), None);
+    }
+
+    #[test]
+    fn normalized_heads_still_validate() {
+        let head = b"POST / HTTP/1.1\r\nTransfer-Encoding:  gzip ,  chunked \r\nContent-Type: text/plain\r\n\r\n";
+        let normalized = normalize_request_head(head).unwrap();
+        assert_eq!(validate_http_request(&normalized), Ok(()));
+        assert_eq!(normalize_request_head(&normalized).unwrap(), normalized);
+    }
+
+    #[test]
+    fn head_completion() {
+        assert!(head_complete(b"GET / HTTP/1.1\r\n\r\n"));
+        assert!(!head_complete(b"GET / HTTP/1.1\r\n"));
+    }
+}
@@ -0,0 +1,44 @@
+pub mod config;
+pub mod config_api;
+pub mod proxy;
+pub mod logging;
+pub mod metrics;
+pub mod health;
+pub mod utils;
+pub mod acl;
+pub mod balancer;
+pub mod options;
+pub mod rate_limit;
+pub mod ddos_protection;
+pub mod hot_reload;
+pub mod compression;
+pub mod features;
+pub mod admin;
+pub mod session;
+pub mod log_format;
+pub mod inspect;
+pub mod resolver;
+pub mod events;
+pub mod status;
+pub mod stick;
+pub mod buffer_pool;
+pub mod error_log;
+pub mod stats;
+pub mod limits;
+pub mod bwlim;
+pub mod tls;
+pub mod proxy_protocol;
+pub mod capability;
+pub mod exposition;
+pub mod selftest;
+pub mod drain;
+pub mod audit;
+pub mod tasks;
+pub mod peers;
+pub mod notify;
+pub mod geoip;
+pub mod pipeline;
+pub mod auth;
+pub mod bcrypt;
+pub mod migrate;
+pub mod http1;
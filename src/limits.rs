@@ -0,0 +1,324 @@
+use crate::config::{Config, GlobalConfig};
+use anyhow::{Result, anyhow};
+use dashmap::DashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_MAXCONN: u32 = 4096;
+pub const DEFAULT_MAX_CHECK_CONN: usize = 64;
+const FD_RESERVE: u64 = 64;
+const FAIR_ACCEPT_HIGH_WATER_PERCENT: u64 = 80;
+
+pub struct RuntimeLimit {
+    value: AtomicU32,
+    configured: AtomicU32,
+    ceiling: AtomicU32,
+    runtime: AtomicBool,
+}
+
+impl RuntimeLimit {
+    pub fn new(configured: u32) -> Self {
+        Self {
+            value: AtomicU32::new(configured),
+            configured: AtomicU32::new(configured),
+            ceiling: AtomicU32::new(u32::MAX),
+            runtime: AtomicBool::new(false),
+        }
+    }
+
+    pub fn get(&self) -> u32 {
+        self.value.load(Ordering::Relaxed).min(self.ceiling.load(Ordering::Relaxed))
+    }
+
+    pub fn configured(&self) -> u32 {
+        self.configured.load(Ordering::Relaxed)
+    }
+
+    pub fn is_runtime(&self) -> bool {
+        self.runtime.load(Ordering::Relaxed)
+    }
+
+    pub fn clamp(&self, ceiling: u32) {
+        self.ceiling.store(ceiling, Ordering::Relaxed);
+    }
+
+    pub fn set(&self, value: u32) {
+        self.value.store(value, Ordering::Relaxed);
+        self.runtime.store(true, Ordering::Relaxed);
+    }
+
+    pub fn reconcile(&self, configured: u32, keep_runtime: bool) {
+        self.configured.store(configured, Ordering::Relaxed);
+        if keep_runtime && self.is_runtime() {
+            return;
+        }
+        self.value.store(configured, Ordering::Relaxed);
+        self.runtime.store(false, Ordering::Relaxed);
+    }
+
+    pub fn admits(&self, current: u64) -> bool {
+        match self.get() {
+            0 => true,
+            limit => current < u64::from(limit),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct FdLimits {
+    wanted: AtomicU64,
+    limit: AtomicU64,
+}
+
+impl FdLimits {
+    pub fn record(&self, wanted: u64, limit: u64) {
+        self.wanted.store(wanted, Ordering::Relaxed);
+        self.limit.store(limit, Ordering::Relaxed);
+    }
+
+    pub fn wanted(&self) -> u64 {
+        self.wanted.load(Ordering::Relaxed)
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Default)]
+pub struct FairAccept {
+    enabled: AtomicBool,
+    active: DashMap<IpAddr, u32>,
+}
+
+impl FairAccept {
+    pub fn configure(&self, global: &GlobalConfig) {
+        self.enabled.store(global.tune_fair_accept, Ordering::Relaxed);
+    }
+
+    pub fn active_ips(&self) -> usize {
+        self.active.len()
+    }
+
+    pub fn admit(self: &Arc<Self>, ip: IpAddr, current: u64, maxconn: u32) -> Result<Option<FairShare>, u64> {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+
+        let maxconn = u64::from(maxconn);
+        if maxconn > 0 && current * 100 >= maxconn * FAIR_ACCEPT_HIGH_WATER_PERCENT {
+            let share = (maxconn / (self.active.len() as u64 + 1)).max(1);
+            let held = self.active.get(&ip).map_or(0, |held| u64::from(*held));
+            if held >= share {
+                return Err(share);
+            }
+        }
+
+        *self.active.entry(ip).or_insert(0) += 1;
+        Ok(Some(FairShare { accept: Arc::clone(self), ip }))
+    }
+
+    fn release(&self, ip: IpAddr) {
+        self.active.remove_if_mut(&ip, |_, held| {
+            *held = held.saturating_sub(1);
+            *held == 0
+        });
+    }
+}
+
+pub struct FairShare {
+    accept: Arc<FairAccept>,
+    ip: IpAddr,
+}
+
+impl Drop for FairShare {
+    fn drop(&mut self) {
+        self.accept.release(self.ip);
+    }
+}
+
+struct ConnectBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+pub struct ConnectRate {
+    rate: AtomicU32,
+    bucket: Mutex<ConnectBucket>,
+}
+
+impl ConnectRate {
+    pub fn new(rate: Option<u32>) -> Self {
+        Self {
+            rate: AtomicU32::new(rate.unwrap_or(0)),
+            bucket: Mutex::new(ConnectBucket { tokens: f64::MAX, updated: Instant::now() }),
+        }
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate.load(Ordering::Relaxed)
+    }
+
+    pub fn reserve(&self, max_wait: Duration) -> Option<Duration> {
+        let rate = self.rate();
+        if rate == 0 {
+            return Some(Duration::ZERO);
+        }
+
+        let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let rate = f64::from(rate);
+        let refill = now.duration_since(bucket.updated).as_secs_f64() * rate;
+        bucket.tokens = (bucket.tokens + refill).min(rate);
+        bucket.updated = now;
+
+        let wait = if bucket.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - bucket.tokens) / rate)
+        };
+        if wait > max_wait {
+            return None;
+        }
+        bucket.tokens -= 1.0;
+        Some(wait)
+    }
+}
+
+fn fd_overhead(config: &Config) -> u64 {
+    let listeners: u64 = config.frontends.iter()
+        .flat_map(|frontend| &frontend.bind)
+        .map(|bind| u64::from(bind.range_size()))
+        .sum();
+    let checks = config.backends.iter()
+        .flat_map(|backend| &backend.server)
+        .filter(|server| server.check == Some(true))
+        .count()
+        .min(config.global.tune_maxcheckconn.unwrap_or(DEFAULT_MAX_CHECK_CONN)) as u64;
+    listeners + checks + FD_RESERVE
+}
+
+pub fn fd_requirement(config: &Config) -> u64 {
+    u64::from(config.global.maxconn.unwrap_or(DEFAULT_MAXCONN)) * 2 + fd_overhead(config)
+}
+
+pub fn maxconn_ceiling(config: &Config, wanted: u64, obtained: u64) -> Result<Option<u32>> {
+    if config.global.strict_limits && obtained < wanted {
+        return Err(anyhow!("File descriptor limit {} is below the {} wanted and strict-limits is on", obtained, wanted));
+    }
+    if obtained >= fd_requirement(config) {
+        return Ok(None);
+    }
+
+    let ceiling = (obtained.saturating_sub(fd_overhead(config)) / 2).clamp(1, u64::from(u32::MAX)) as u32;
+    Ok(Some(ceiling))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runtime_values_survive_reloads_only_when_kept() {
+        let limit = RuntimeLimit::new(100);
+        limit.set(50);
+        assert_eq!((limit.get(), limit.configured(), limit.is_runtime()), (50, 100, true));
+
+        limit.reconcile(200, true);
+        assert_eq!((limit.get(), limit.configured(), limit.is_runtime()), (50, 200, true));
+        limit.reconcile(300, false);
+        assert_eq!((limit.get(), limit.configured(), limit.is_runtime()), (300, 300, false));
+    }
+
+    #[test]
+    fn the_ceiling_caps_every_value() {
+        let limit = RuntimeLimit::new(100);
+        limit.clamp(10);
+        assert_eq!(limit.get(), 10);
+        limit.set(5);
+        assert_eq!(limit.get(), 5);
+        assert!(limit.admits(4) && !limit.admits(5));
+        assert!(RuntimeLimit::new(0).admits(u64::MAX), "0 means unlimited");
+    }
+
+    fn config(global: &str) -> Config {
+        Config::from_haproxy_config(&format!("global\n{global}\nfrontend web\n    bind *:8000-8009\n    default_backend app\n\n\
+                                              backend app\n    server s1 127.0.0.1:1 check\n    server s2 127.0.0.1:2 check\n    server s3 127.0.0.1:3\n"), None).unwrap()
+    }
+
+    #[test]
+    fn descriptors_cover_both_sides_listeners_and_checks() {
+        // 10 listeners, 2 checked servers and the reserve on top of two descriptors per connection.
+        assert_eq!(fd_requirement(&config("    maxconn 100\n")), 200 + 10 + 2 + FD_RESERVE);
+        assert_eq!(fd_requirement(&config("    maxconn 100\n    tune.maxcheckconn 1\n")), 200 + 10 + 1 + FD_RESERVE);
+        assert_eq!(fd_requirement(&config("")), u64::from(DEFAULT_MAXCONN) * 2 + 12 + FD_RESERVE);
+    }
+
+    #[test]
+    fn a_short_descriptor_limit_lowers_maxconn() {
+        let config = config("    maxconn 100\n");
+        assert_eq!(maxconn_ceiling(&config, 276, 276).unwrap(), None);
+        assert_eq!(maxconn_ceiling(&config, 276, 176).unwrap(), Some(50));
+        assert_eq!(maxconn_ceiling(&config, 276, 10).unwrap(), Some(1));
+    }
+
+    #[test]
+    fn strict_limits_refuse_a_short_limit() {
+        let config = config("    maxconn 100\n    strict-limits on\n");
+        assert_eq!(maxconn_ceiling(&config, 276, 200).unwrap_err().to_string(),
+                   "File descriptor limit 200 is below the 276 wanted and strict-limits is on");
+        assert_eq!(maxconn_ceiling(&config, 276, 276).unwrap(), None);
+    }
+
+    fn fair_accept() -> Arc<FairAccept> {
+        let accept = Arc::new(FairAccept::default());
+        accept.configure(&config("    tune.fair-accept on\n").global);
+        accept
+    }
+
+    #[test]
+    fn fair_accept_caps_each_ip_near_maxconn() {
+        let accept = fair_accept();
+        let (busy, quiet): (IpAddr, IpAddr) = ("192.0.2.1".parse().unwrap(), "192.0.2.2".parse().unwrap());
+        let held: Vec<FairShare> = (0..5).map(|current| accept.admit(busy, current, 10).unwrap().unwrap()).collect();
+        assert_eq!(accept.active_ips(), 1);
+
+        // Past 80% of maxconn, maxconn is shared between the active IPs and one more.
+        let quiet_share = accept.admit(quiet, 8, 10).unwrap().unwrap();
+        assert_eq!(accept.admit(busy, 8, 10).err(), Some(3));
+        assert!(accept.admit(busy, 7, 10).is_ok(), "below the high water mark every IP is admitted");
+
+        drop(quiet_share);
+        drop(held);
+        assert_eq!(accept.active_ips(), 0);
+    }
+
+    #[test]
+    fn fair_accept_off_tracks_nothing() {
+        let accept = Arc::new(FairAccept::default());
+        assert!(accept.admit("192.0.2.1".parse().unwrap(), 100, 10).unwrap().is_none());
+        assert_eq!(accept.active_ips(), 0);
+    }
+
+    #[test]
+    fn connect_rate_spaces_connects_after_a_burst() {
+        let rate = ConnectRate::new(Some(100));
+        for _ in 0..100 {
+            assert_eq!(rate.reserve(Duration::ZERO), Some(Duration::ZERO));
+        }
+        assert_eq!(rate.reserve(Duration::from_millis(1)), None, "a wait beyond the allowed one reserves nothing");
+        let wait = rate.reserve(Duration::from_secs(1)).unwrap();
+        assert!(wait > Duration::from_millis(5) && wait <= Duration::from_millis(10), "{wait:?}");
+        let wait = rate.reserve(Duration::from_secs(1)).unwrap();
+        assert!(wait > Duration::from_millis(15) && wait <= Duration::from_millis(20), "reservations queue up: {wait:?}");
+    }
+
+    #[test]
+    fn connect_rate_off_never_waits() {
+        let rate = ConnectRate::new(None);
+        assert_eq!(rate.rate(), 0);
+        assert!((0..1000).all(|_| rate.reserve(Duration::ZERO) == Some(Duration::ZERO)));
+    }
+}
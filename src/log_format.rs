@@ -0,0 +1,369 @@
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
+use std::net::SocketAddr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogVariable {
+    ClientIp,
+    ClientPort,
+    FrontendIp,
+    FrontendPort,
+    OriginalDst,
+    AcceptDate,
+    Frontend,
+    FrontendTransport,
+    Backend,
+    Server,
+    ServerIp,
+    ServerPort,
+    Rule,
+    Retries,
+    TimeRequest,
+    TimeWait,
+    TimeConnect,
+    TimeResponse,
+    TimeTotal,
+    BytesRead,
+    BytesUploaded,
+    TerminationState,
+    Error,
+    UniqueId,
+    SslVersion,
+    SslCipher,
+    SslAlpn,
+    Country,
+}
+
+impl LogVariable {
+    fn from_name(name: &str) -> Option<Self> {
+        let variable = match name {
+            "ci" => LogVariable::ClientIp,
+            "cp" => LogVariable::ClientPort,
+            "fi" => LogVariable::FrontendIp,
+            "fp" => LogVariable::FrontendPort,
+            "original_dst" => LogVariable::OriginalDst,
+            "t" => LogVariable::AcceptDate,
+            "f" => LogVariable::Frontend,
+            "ft" => LogVariable::FrontendTransport,
+            "b" => LogVariable::Backend,
+            "s" => LogVariable::Server,
+            "si" => LogVariable::ServerIp,
+            "sp" => LogVariable::ServerPort,
+            "rule" => LogVariable::Rule,
+            "rc" => LogVariable::Retries,
+            "Tq" => LogVariable::TimeRequest,
+            "Tw" => LogVariable::TimeWait,
+            "Tc" => LogVariable::TimeConnect,
+            "Tr" => LogVariable::TimeResponse,
+            "Tt" => LogVariable::TimeTotal,
+            "B" => LogVariable::BytesRead,
+            "U" => LogVariable::BytesUploaded,
+            "ts" => LogVariable::TerminationState,
+            "error" => LogVariable::Error,
+            "ID" => LogVariable::UniqueId,
+            "sslv" => LogVariable::SslVersion,
+            "sslc" => LogVariable::SslCipher,
+            "alpn" => LogVariable::SslAlpn,
+            "country" => LogVariable::Country,
+            _ => return None,
+        };
+        Some(variable)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogToken {
+    Literal(String),
+    Variable { variable: LogVariable, quoted: bool },
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionRecord {
+    pub client_addr: SocketAddr,
+    pub frontend_addr: Option<SocketAddr>,
+    pub original_dst: Option<SocketAddr>,
+    pub accept_date: DateTime<Utc>,
+    pub frontend: String,
+    pub backend: String,
+    pub server: String,
+    pub server_addr: Option<SocketAddr>,
+    pub rule: String,
+    pub retries: String,
+    pub time_request_ms: Option<u64>,
+    pub time_wait_ms: Option<u64>,
+    pub time_connect_ms: Option<u64>,
+    pub time_response_ms: Option<u64>,
+    pub time_total_ms: u64,
+    pub bytes_read: u64,
+    pub bytes_uploaded: u64,
+    pub termination_state: String,
+    pub error: Option<String>,
+    pub unique_id: String,
+    pub ssl_version: Option<String>,
+    pub ssl_cipher: Option<String>,
+    pub alpn: Option<String>,
+    pub country: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LogFormat {
+    tokens: Vec<LogToken>,
+}
+
+impl LogFormat {
+    pub fn parse(template: &str) -> Result<Self> {
+        let template = unquote(template.trim())?;
+        let mut tokens = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                literal.push(c);
+                continue;
+            }
+
+            if chars.peek() == Some(&'%') {
+                chars.next();
+                literal.push('%');
+                continue;
+            }
+
+            let mut quoted = false;
+            if chars.peek() == Some(&'{') {
+                chars.next();
+                let mut flags = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => flags.push(c),
+                        None => return Err(anyhow!("Unterminated '%{{' in log-format")),
+                    }
+                }
+                for flag in flags.split(',') {
+                    match flag {
+                        "+Q" => quoted = true,
+                        "-Q" => quoted = false,
+                        _ => return Err(anyhow!("Unknown log-format flag '{}'", flag)),
+                    }
+                }
+            }
+
+            let mut name = String::new();
+            while let Some(&c) = chars.peek() {
+                if !c.is_ascii_alphanumeric() && c != '_' {
+                    break;
+                }
+                name.push(c);
+                chars.next();
+            }
+
+            if name.is_empty() {
+                return Err(anyhow!("Missing variable name after '%' in log-format"));
+            }
+
+            let variable = LogVariable::from_name(&name)
+                .ok_or_else(|| anyhow!("Unknown log-format token '%{}'", name))?;
+
+            if !literal.is_empty() {
+                tokens.push(LogToken::Literal(std::mem::take(&mut literal)));
+            }
+            tokens.push(LogToken::Variable { variable, quoted });
+        }
+
+        if !literal.is_empty() {
+            tokens.push(LogToken::Literal(literal));
+        }
+
+        Ok(Self { tokens })
+    }
+
+    pub fn render(&self, record: &SessionRecord) -> String {
+        let mut line = String::new();
+
+        for token in &self.tokens {
+            match token {
+                LogToken::Literal(text) => line.push_str(text),
+                LogToken::Variable { variable, quoted } => {
+                    let value = Self::variable_value(*variable, record);
+                    if *quoted {
+                        line.push('"');
+                        line.push_str(&value.replace('"', "\\\""));
+                        line.push('"');
+                    } else {
+                        line.push_str(&value);
+                    }
+                }
+            }
+        }
+
+        line
+    }
+
+    fn variable_value(variable: LogVariable, record: &SessionRecord) -> String {
+        let timer = |value: Option<u64>| value.map(|v| v.to_string()).unwrap_or_else(|| "-1".to_string());
+
+        match variable {
+            LogVariable::ClientIp => record.client_addr.ip().to_string(),
+            LogVariable::ClientPort => record.client_addr.port().to_string(),
+            LogVariable::FrontendIp => record.frontend_addr.map(|a| a.ip().to_string()).unwrap_or_else(|| "-".to_string()),
+            LogVariable::FrontendPort => record.frontend_addr.map(|a| a.port().to_string()).unwrap_or_else(|| "-".to_string()),
+            LogVariable::OriginalDst => record.original_dst.map(|a| a.to_string()).unwrap_or_else(|| "-".to_string()),
+            LogVariable::AcceptDate => record.accept_date.format("%d/%b/%Y:%H:%M:%S%.3f").to_string(),
+            LogVariable::Frontend => record.frontend.clone(),
+            LogVariable::FrontendTransport if record.ssl_version.is_some() => format!("{}~", record.frontend),
+            LogVariable::FrontendTransport => record.frontend.clone(),
+            LogVariable::Backend => record.backend.clone(),
+            LogVariable::Server => record.server.clone(),
+            LogVariable::ServerIp => record.server_addr.map(|a| a.ip().to_string()).unwrap_or_else(|| "-".to_string()),
+            LogVariable::ServerPort => record.server_addr.map(|a| a.port().to_string()).unwrap_or_else(|| "-".to_string()),
+            LogVariable::Rule => record.rule.clone(),
+            LogVariable::Retries => record.retries.clone(),
+            LogVariable::TimeRequest => timer(record.time_request_ms),
+            LogVariable::TimeWait => timer(record.time_wait_ms),
+            LogVariable::TimeConnect => timer(record.time_connect_ms),
+            LogVariable::TimeResponse => timer(record.time_response_ms),
+            LogVariable::TimeTotal => record.time_total_ms.to_string(),
+            LogVariable::BytesRead => record.bytes_read.to_string(),
+            LogVariable::BytesUploaded => record.bytes_uploaded.to_string(),
+            LogVariable::TerminationState => record.termination_state.clone(),
+            LogVariable::Error => record.error.clone().unwrap_or_else(|| "-".to_string()),
+            LogVariable::UniqueId => record.unique_id.clone(),
+            LogVariable::SslVersion => record.ssl_version.clone().unwrap_or_else(|| "-".to_string()),
+            LogVariable::SslCipher => record.ssl_cipher.clone().unwrap_or_else(|| "-".to_string()),
+            LogVariable::SslAlpn => record.alpn.clone().unwrap_or_else(|| "-".to_string()),
+            LogVariable::Country => record.country.clone().unwrap_or_else(|| "-".to_string()),
+        }
+    }
+}
+
+fn unquote(value: &str) -> Result<String> {
+    if !value.starts_with('"') {
+        return Ok(value.to_string());
+    }
+
+    let mut result = String::new();
+    let mut chars = value[1..].chars();
+    loop {
+        match chars.next() {
+            Some('\\') => match chars.next() {
+                Some(c) => result.push(c),
+                None => return Err(anyhow!("Unterminated escape in log-format")),
+            },
+            Some('"') => break,
+            Some(c) => result.push(c),
+            None => return Err(anyhow!("Unterminated quote in log-format")),
+        }
+    }
+
+    if chars.next().is_some() {
+        return Err(anyhow!("Unexpected characters after closing quote in log-format"));
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn session() -> SessionRecord {
+        SessionRecord {
+            client_addr: "192.0.2.10:51234".parse().unwrap(),
+            frontend_addr: Some("10.0.0.1:443".parse().unwrap()),
+            original_dst: None,
+            accept_date: Utc.with_ymd_and_hms(2024, 3, 9, 14, 5, 7).unwrap() + chrono::Duration::milliseconds(42),
+            frontend: "web".to_string(),
+            backend: "app".to_string(),
+            server: "s1".to_string(),
+            server_addr: Some("10.0.1.5:8080".parse().unwrap()),
+            rule: "default_backend".to_string(),
+            retries: "+1".to_string(),
+            time_request_ms: Some(3),
+            time_wait_ms: Some(0),
+            time_connect_ms: Some(12),
+            time_response_ms: None,
+            time_total_ms: 150,
+            bytes_read: 5120,
+            bytes_uploaded: 734,
+            termination_state: "SD".to_string(),
+            error: None,
+            unique_id: "c0a8000a-1".to_string(),
+            ssl_version: Some("TLSv1.3".to_string()),
+            ssl_cipher: Some("TLS_AES_128_GCM_SHA256".to_string()),
+            alpn: None,
+            country: Some("NL".to_string()),
+        }
+    }
+
+    fn render(template: &str) -> String {
+        LogFormat::parse(template).unwrap().render(&session())
+    }
+
+    #[test]
+    fn haproxy_tcp_format_renders_exactly() {
+        assert_eq!(render("\"%ci:%cp [%t] %ft %b/%s %Tw/%Tc/%Tt %B %ts\""),
+                   "192.0.2.10:51234 [09/Mar/2024:14:05:07.042] web~ app/s1 0/12/150 5120 SD");
+    }
+
+    #[test]
+    fn every_variable_renders() {
+        assert_eq!(render("%fi:%fp %original_dst %f %si:%sp %rule %rc %Tq/%Tr %U %error %ID %sslv %sslc %alpn %country"),
+                   "10.0.0.1:443 - web 10.0.1.5:8080 default_backend +1 3/-1 734 - c0a8000a-1 TLSv1.3 TLS_AES_128_GCM_SHA256 - NL");
+    }
+
+    #[test]
+    fn quoted_variables_escape_quotes() {
+        let mut record = session();
+        record.error = Some("bad \"header\"".to_string());
+        let format = LogFormat::parse("%{+Q}error %{+Q,-Q}b %%").unwrap();
+        assert_eq!(format.render(&record), "\"bad \\\"header\\\"\" app %");
+    }
+
+    #[test]
+    fn plain_transport_has_no_tilde() {
+        let mut record = session();
+        record.ssl_version = None;
+        assert_eq!(LogFormat::parse("%ft %sslv").unwrap().render(&record), "web -");
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        let error = |template: &str| LogFormat::parse(template).unwrap_err().to_string();
+        assert_eq!(error("%nope"), "Unknown log-format token '%nope'");
+        assert_eq!(error("%{+Q"), "Unterminated '%{' in log-format");
+        assert_eq!(error("%{+X}ci"), "Unknown log-format flag '+X'");
+        assert_eq!(error("100% "), "Missing variable name after '%' in log-format");
+        assert_eq!(error("\"%ci"), "Unterminated quote in log-format");
+        assert_eq!(error("\"%ci\" extra"), "Unexpected characters after closing quote in log-format");
+    }
+
+    #[test]
+    fn missing_addresses_render_as_dashes() {
+        let mut record = session();
+        record.frontend_addr = None;
+        record.server_addr = None;
+        record.original_dst = Some("198.51.100.7:443".parse().unwrap());
+        assert_eq!(LogFormat::parse("%fi:%fp %si:%sp %original_dst").unwrap().render(&record), "-:- -:- 198.51.100.7:443");
+    }
+
+    #[test]
+    fn literal_text_surrounds_variables() {
+        assert_eq!(render("100%% b=%b s=%s/%rc"), "100% b=app s=s1/+1");
+    }
+
+    #[test]
+    fn timers_render_minus_one_for_phases_never_reached() {
+        let mut record = session();
+        record.time_response_ms = Some(40);
+        assert_eq!(LogFormat::parse("%Tq/%Tw/%Tc/%Tr/%Tt").unwrap().render(&record), "3/0/12/40/150");
+
+        record.time_request_ms = None;
+        record.time_wait_ms = None;
+        record.time_connect_ms = None;
+        record.time_response_ms = None;
+        record.time_total_ms = 0;
+        assert_eq!(LogFormat::parse("%Tq/%Tw/%Tc/%Tr/%Tt").unwrap().render(&record), "-1/-1/-1/-1/0");
+    }
+}
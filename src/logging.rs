@@ -1,69 +1,342 @@
 use tracing::Level;
 use tracing_subscriber::{
-    fmt::time::ChronoUtc,
+    fmt::{time::ChronoUtc, writer::BoxMakeWriter},
     prelude::*,
     EnvFilter,
 };
-use serde_json::json;
-use std::time::Instant;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use crate::config::GlobalConfig;
+use crate::events::ReadyFrontend;
+use crate::geoip::Country;
+use crate::log_format::{LogFormat, SessionRecord};
+use crate::session::SessionCounters;
+use crate::tls::TlsInfo;
+
+pub const DEFAULT_LOG_BURST: u64 = 10;
+pub const DEFAULT_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+struct ThrottleWindow {
+    started_ms: AtomicU64,
+    emitted: AtomicU64,
+    suppressed: AtomicU64,
+}
+
+pub struct LogThrottle {
+    started: Instant,
+    burst: AtomicU64,
+    interval_ms: AtomicU64,
+    windows: DashMap<(&'static str, String), ThrottleWindow>,
+}
+
+impl LogThrottle {
+    pub fn new(global: &GlobalConfig) -> Self {
+        let throttle = Self {
+            started: Instant::now(),
+            burst: AtomicU64::new(DEFAULT_LOG_BURST),
+            interval_ms: AtomicU64::new(DEFAULT_LOG_INTERVAL.as_millis() as u64),
+            windows: DashMap::new(),
+        };
+        throttle.configure(global);
+        throttle
+    }
+
+    pub fn configure(&self, global: &GlobalConfig) {
+        let interval = global.tune_log_interval.unwrap_or(DEFAULT_LOG_INTERVAL);
+        self.burst.store(global.tune_log_burst.unwrap_or(DEFAULT_LOG_BURST), Ordering::Relaxed);
+        self.interval_ms.store((interval.as_millis() as u64).max(1), Ordering::Relaxed);
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms.load(Ordering::Relaxed))
+    }
+
+    fn now_ms(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64
+    }
+
+    pub fn admit(&self, error_type: &'static str, frontend: &str) -> bool {
+        let now = self.now_ms();
+        let key = (error_type, frontend.to_string());
+        let window = match self.windows.get(&key) {
+            Some(window) => window,
+            None => self.windows.entry(key)
+                .or_insert_with(|| ThrottleWindow {
+                    started_ms: AtomicU64::new(now),
+                    emitted: AtomicU64::new(0),
+                    suppressed: AtomicU64::new(0),
+                })
+                .downgrade(),
+        };
+        self.roll(error_type, frontend, &window, now);
+
+        if window.emitted.fetch_add(1, Ordering::Relaxed) < self.burst.load(Ordering::Relaxed) {
+            return true;
+        }
+        window.suppressed.fetch_add(1, Ordering::Relaxed);
+        false
+    }
+
+    pub fn flush(&self) {
+        let now = self.now_ms();
+        for window in self.windows.iter() {
+            let (error_type, ref frontend) = *window.key();
+            self.roll(error_type, frontend, window.value(), now);
+        }
+    }
+
+    fn roll(&self, error_type: &str, frontend: &str, window: &ThrottleWindow, now: u64) {
+        let started = window.started_ms.load(Ordering::Acquire);
+        let elapsed = now.saturating_sub(started);
+        if elapsed < self.interval_ms.load(Ordering::Relaxed) {
+            return;
+        }
+        if window.started_ms.compare_exchange(started, now, Ordering::AcqRel, Ordering::Acquire).is_err() {
+            return;
+        }
+
+        window.emitted.store(0, Ordering::Relaxed);
+        let suppressed = window.suppressed.swap(0, Ordering::Relaxed);
+        if suppressed > 0 {
+            log_suppressed(error_type, frontend, suppressed, Duration::from_millis(elapsed));
+        }
+    }
+}
+
+fn log_suppressed(error_type: &str, frontend: &str, suppressed: u64, window: Duration) {
+    tracing::warn!(
+        frontend = %frontend,
+        error_type = %error_type,
+        suppressed = suppressed,
+        window_ms = window.as_millis(),
+        event = "log_throttled",
+        "Suppressed {} similar {} errors on frontend {} in the last {}s",
+        suppressed, error_type, frontend, window.as_secs()
+    );
+}
 
 pub struct RequestLogger {
     start_time: Instant,
+    accept_date: DateTime<Utc>,
     request_id: String,
-    client_ip: String,
+    client_addr: SocketAddr,
+    frontend_addr: Option<SocketAddr>,
+    original_dst: Option<SocketAddr>,
+    frontend_name: String,
+    listener: String,
     backend_name: String,
     server_name: String,
+    server_addr: Option<SocketAddr>,
+    rule: String,
+    tls: Option<TlsInfo>,
+    country: Option<Country>,
+    retries: u32,
+    redispatched: bool,
+    error: Option<&'static str>,
+    log_format: Option<Arc<LogFormat>>,
+    request_ms: Option<u64>,
 }
 
 impl RequestLogger {
-    pub fn new(client_ip: String, backend_name: String, server_name: String) -> Self {
+    pub fn new(client_addr: SocketAddr, frontend_name: String, backend_name: String, server_name: String) -> Self {
         Self {
             start_time: Instant::now(),
+            accept_date: Utc::now(),
             request_id: uuid::Uuid::new_v4().to_string(),
-            client_ip,
+            client_addr,
+            frontend_addr: None,
+            original_dst: None,
+            frontend_name,
+            listener: "-".to_string(),
             backend_name,
             server_name,
+            server_addr: None,
+            rule: "-".to_string(),
+            tls: None,
+            country: None,
+            retries: 0,
+            redispatched: false,
+            error: None,
+            log_format: None,
+            request_ms: None,
+        }
+    }
+
+    pub fn with_accept_time(mut self, accepted: Instant, request_time: Duration) -> Self {
+        self.start_time = accepted;
+        self.accept_date = Utc::now() - chrono::Duration::from_std(accepted.elapsed()).unwrap_or_else(|_| chrono::Duration::zero());
+        self.request_ms = Some(request_time.as_millis() as u64);
+        self
+    }
+
+    pub fn with_addresses(mut self, frontend_addr: Option<SocketAddr>, server_addr: Option<SocketAddr>) -> Self {
+        self.frontend_addr = frontend_addr;
+        self.server_addr = server_addr;
+        self
+    }
+
+    pub fn with_original_dst(mut self, original_dst: Option<SocketAddr>) -> Self {
+        self.original_dst = original_dst;
+        self
+    }
+
+    pub fn with_listener(mut self, listener: String) -> Self {
+        self.listener = listener;
+        self
+    }
+
+    pub fn with_tls(mut self, tls: Option<TlsInfo>) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    pub fn with_country(mut self, country: Option<Country>) -> Self {
+        self.country = country;
+        self
+    }
+
+    pub fn with_rule(mut self, rule: String) -> Self {
+        self.rule = rule;
+        self
+    }
+
+    pub fn set_server(&mut self, server_name: String, server_addr: Option<SocketAddr>) {
+        self.server_name = server_name;
+        self.server_addr = server_addr;
+    }
+
+    pub fn set_retries(&mut self, retries: u32, redispatched: bool) {
+        self.retries = retries;
+        self.redispatched = redispatched;
+    }
+
+    pub fn set_error(&mut self, error: &'static str) {
+        self.error = Some(error);
+    }
+
+    fn retries_field(&self) -> String {
+        if self.redispatched {
+            format!("+{}", self.retries)
+        } else {
+            self.retries.to_string()
         }
     }
 
+    fn country_field(&self) -> &str {
+        self.country.as_ref().map(Country::as_str).unwrap_or("-")
+    }
+
+    pub fn with_log_format(mut self, log_format: Option<Arc<LogFormat>>) -> Self {
+        self.log_format = log_format;
+        self
+    }
+
     pub fn log_request_start(&self) {
         tracing::info!(
             request_id = %self.request_id,
-            client_ip = %self.client_ip,
+            client_ip = %self.client_addr.ip(),
+            country = %self.country_field(),
+            frontend = %self.frontend_name,
+            listener = %self.listener,
             backend = %self.backend_name,
             server = %self.server_name,
+            rule = %self.rule,
             event = "request_start",
             "Request started"
         );
     }
 
-    pub fn log_request_end(&self, status: &str, bytes_transferred: u64) {
+    pub fn log_request_end(&self, status: &str, termination_state: &str, counters: &SessionCounters) {
         let duration = self.start_time.elapsed();
+
+        if let Some(ref log_format) = self.log_format {
+            let record = self.session_record(termination_state, counters);
+            tracing::info!(event = "access_log", "{}", log_format.render(&record));
+            return;
+        }
+
         tracing::info!(
             request_id = %self.request_id,
-            client_ip = %self.client_ip,
+            client_ip = %self.client_addr.ip(),
+            country = %self.country_field(),
+            frontend = %self.frontend_name,
+            listener = %self.listener,
             backend = %self.backend_name,
             server = %self.server_name,
+            rule = %self.rule,
+            original_dst = %self.original_dst.map(|a| a.to_string()).unwrap_or_else(|| "-".to_string()),
+            tls_version = %self.tls.as_ref().map(|tls| tls.version).unwrap_or("-"),
+            tls_cipher = %self.tls.as_ref().map(|tls| tls.cipher.as_str()).unwrap_or("-"),
+            alpn = %self.tls.as_ref().and_then(|tls| tls.alpn.as_deref()).unwrap_or("-"),
             status = %status,
+            termination_state = %termination_state,
+            error = %self.error.unwrap_or("-"),
+            retries = %self.retries_field(),
+            tq_ms = timer(self.request_ms),
+            tw_ms = counters.wait_time_ms(),
+            tc_ms = timer(counters.connect_time_ms()),
+            tr_ms = timer(counters.response_time_ms()),
             duration_ms = duration.as_millis(),
             duration_us = duration.as_micros(),
-            bytes_transferred = bytes_transferred,
+            bytes_transferred = counters.bytes_out(),
             event = "request_end",
             "Request completed"
         );
     }
 
+    fn session_record(&self, termination_state: &str, counters: &SessionCounters) -> SessionRecord {
+        SessionRecord {
+            client_addr: self.client_addr,
+            frontend_addr: self.frontend_addr,
+            original_dst: self.original_dst,
+            accept_date: self.accept_date,
+            frontend: self.frontend_name.clone(),
+            backend: self.backend_name.clone(),
+            server: self.server_name.clone(),
+            server_addr: self.server_addr,
+            rule: self.rule.clone(),
+            retries: self.retries_field(),
+            time_request_ms: self.request_ms,
+            time_wait_ms: Some(counters.wait_time_ms()),
+            time_connect_ms: counters.connect_time_ms(),
+            time_response_ms: counters.response_time_ms(),
+            time_total_ms: self.start_time.elapsed().as_millis() as u64,
+            bytes_read: counters.bytes_out(),
+            bytes_uploaded: counters.bytes_in(),
+            termination_state: termination_state.to_string(),
+            error: self.error.map(str::to_string),
+            unique_id: self.request_id.clone(),
+            ssl_version: self.tls.as_ref().map(|tls| tls.version.to_string()),
+            ssl_cipher: self.tls.as_ref().map(|tls| tls.cipher.clone()),
+            alpn: self.tls.as_ref().and_then(|tls| tls.alpn.clone()),
+            country: self.country.map(|country| country.to_string()),
+        }
+    }
+}
+
+fn timer(value: Option<u64>) -> i64 {
+    value.map_or(-1, |ms| ms as i64)
 }
 
-pub fn init(level: Level, json_logs: bool) -> anyhow::Result<()> {
+pub fn init(level: Level, json_logs: bool, stderr: bool) -> anyhow::Result<()> {
     let env_filter = EnvFilter::try_from_default_env()
         .unwrap_or_else(|_| EnvFilter::new(format!("turbogate={}", level)));
+    let writer = if stderr {
+        BoxMakeWriter::new(std::io::stderr)
+    } else {
+        BoxMakeWriter::new(std::io::stdout)
+    };
 
     if json_logs {
         tracing_subscriber::registry()
             .with(env_filter)
             .with(
                 tracing_subscriber::fmt::layer()
+                    .with_writer(writer)
                     .with_timer(ChronoUtc::rfc_3339())
                     .with_target(true)
                     .with_thread_ids(true)
@@ -80,6 +353,7 @@ pub fn init(level: Level, json_logs: bool) -> anyhow::Result<()> {
             .with(env_filter)
             .with(
                 tracing_subscriber::fmt::layer()
+                    .with_writer(writer)
                     .with_timer(ChronoUtc::rfc_3339())
                     .with_target(true)
                     .with_thread_ids(true)
@@ -94,45 +368,66 @@ pub fn init(level: Level, json_logs: bool) -> anyhow::Result<()> {
     Ok(())
 }
 
-pub fn log_server_status(server_name: &str, status: &str, details: Option<&str>) {
-    let mut log_data = json!({
-        "server": server_name,
-        "status": status,
-        "timestamp": chrono::Utc::now().to_rfc3339(),
-        "event": "server_status_change"
-    });
-
-    if let Some(details) = details {
-        log_data["details"] = json!(details);
-    }
-
+pub fn log_server_status(backend_name: &str, server_name: &str, state: &str, previous: Option<&str>) {
     tracing::info!(
+        backend = %backend_name,
         server = %server_name,
-        status = %status,
-        details = ?details,
+        state = %state,
+        previous = ?previous,
         event = "server_status_change",
-        "Server status changed"
+        "Server state changed"
     );
 }
 
-pub fn log_backend_status(backend_name: &str, active_servers: usize, total_servers: usize) {
+pub fn log_server_drained(backend_name: &str, server_name: &str) {
+    tracing::info!(
+        backend = %backend_name,
+        server = %server_name,
+        active_connections = 0,
+        event = "server_drained",
+        "Draining server has no active connections left"
+    );
+}
+
+pub fn log_tarpit(frontend_name: &str, client_addr: SocketAddr, duration: std::time::Duration) {
+    tracing::info!(
+        frontend = %frontend_name,
+        client_ip = %client_addr.ip(),
+        duration_ms = duration.as_millis(),
+        termination_state = "PT",
+        event = "tarpit",
+        "Tarpitted connection closed"
+    );
+}
+
+pub fn log_backend_status(backend_name: &str, active_servers: usize, total_servers: usize, unchecked_servers: usize) {
     tracing::info!(
         backend = %backend_name,
         active_servers = active_servers,
         total_servers = total_servers,
+        unchecked_servers = unchecked_servers,
         health_percentage = (active_servers as f64 / total_servers as f64) * 100.0,
         event = "backend_status",
         "Backend status update"
     );
 }
 
-pub fn log_startup_info(version: &str, config_file: &str, bind_addresses: Vec<String>) {
+pub fn log_ready(version: &str, config_file: &str, frontends: &[ReadyFrontend], backends: usize) {
+    let listing: Vec<String> = frontends.iter()
+        .map(|frontend| {
+            let binds: Vec<String> = frontend.binds.iter()
+                .map(|bind| format!("{}{}", bind.address, if bind.tls { " ssl" } else { "" }))
+                .collect();
+            format!("{} [{}] -> {} backend(s)", frontend.name, binds.join(", "), frontend.backends)
+        })
+        .collect();
     tracing::info!(
         version = %version,
         config_file = %config_file,
-        bind_addresses = ?bind_addresses,
-        event = "startup",
-        "Turbogate started successfully"
+        frontends = ?listing,
+        backends = backends,
+        event = "ready",
+        "Turbogate ready"
     );
 }
 
@@ -143,3 +438,48 @@ pub fn log_graceful_shutdown(active_connections: u64) {
         "Starting graceful shutdown"
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Clone, Default)]
+    struct LogBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn floods_are_logged_as_a_burst_and_one_summary() {
+        let buffer = LogBuffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt().with_writer(move || writer.clone()).with_ansi(false).finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let global = GlobalConfig { tune_log_interval: Some(Duration::from_millis(50)), ..GlobalConfig::default() };
+        let throttle = LogThrottle::new(&global);
+
+        for _ in 0..10_000 {
+            if throttle.admit("connect_failed", "web") {
+                tracing::warn!("Connection failed");
+            }
+        }
+        assert!(throttle.admit("connect_failed", "api"), "frontends are throttled separately");
+        std::thread::sleep(Duration::from_millis(60));
+        throttle.flush();
+
+        let logs = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = logs.lines().collect();
+        assert_eq!(lines.len(), DEFAULT_LOG_BURST as usize + 1, "{logs}");
+        assert!(lines[..DEFAULT_LOG_BURST as usize].iter().all(|line| line.contains("Connection failed")));
+        assert!(lines[DEFAULT_LOG_BURST as usize].contains("Suppressed 9990 similar connect_failed errors on frontend web"), "{logs}");
+    }
+}
@@ -1,31 +1,22 @@
-use clap::Parser;
-use tracing::{info, error, Level};
+use clap::{Parser, Subcommand};
+use tracing::{info, warn, error, Level};
 use std::sync::Arc;
+use std::time::Duration;
 
-mod config;
-mod proxy;
-mod logging;
-mod metrics;
-mod health;
-mod utils;
-mod acl;
-mod balancer;
-mod options;
-mod rate_limit;
-mod ddos_protection;
-mod hot_reload;
-mod compression;
-mod features;
-
-use config::Config;
-use proxy::ProxyServer;
-use features::FeaturesManager;
+use turbogate::{limits, logging, metrics, migrate, selftest, utils};
+use turbogate::config::Config;
+use turbogate::proxy::ProxyServer;
+use turbogate::features::FeaturesManager;
+use turbogate::tasks::TaskGroup;
 
 #[derive(Parser)]
 #[command(name = "turbogate")]
 #[command(about = "High-performance L4 load balancer compatible with HAProxy")]
 struct Cli {
-    #[arg(short, long, default_value = "turbogate.cfg")]
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(short, long, default_value = "turbogate.cfg", global = true)]
     config: String,
 
     #[arg(short, long, default_value = "info")]
@@ -36,13 +27,51 @@ struct Cli {
 
     #[arg(long)]
     check: bool,
+
+    #[arg(long)]
+    allow_degraded: bool,
+
+    #[arg(long)]
+    check_backends: bool,
+
+    #[arg(long, default_value = "10s", value_parser = parse_check_timeout)]
+    check_timeout: Duration,
+
+    #[arg(long, global = true)]
+    json: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    Migrate,
+}
+
+fn parse_check_timeout(value: &str) -> Result<Duration, String> {
+    utils::parse_duration(value).map_err(|e| e.to_string())
+}
+
+fn check_failed(error: &anyhow::Error) -> ! {
+    eprintln!("{}", error);
+    std::process::exit(1)
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
-    logging::init(cli.log_level, cli.json_logs)?;
+    if let Some(Command::Migrate) = cli.command {
+        logging::init(Level::ERROR, cli.json_logs, true)?;
+        let config = Config::from_file(&cli.config).await.unwrap_or_else(|e| check_failed(&e));
+        let report = migrate::MigrationReport::new(&config);
+        if cli.json {
+            println!("{}", report.to_json());
+        } else {
+            print!("{}", report.to_text());
+        }
+        std::process::exit(report.exit_code());
+    }
+
+    logging::init(cli.log_level, cli.json_logs, cli.check_backends)?;
 
     info!("Starting Turbogate L4 Load Balancer");
     info!("Log level: {}", cli.log_level);
@@ -53,13 +82,17 @@ async fn main() -> anyhow::Result<()> {
             info!("Configuration loaded successfully");
             config
         }
+        Err(e) if cli.check => check_failed(&e),
         Err(e) => {
             error!("Failed to load configuration: {}", e);
             return Err(e);
         }
     };
 
-    if let Err(e) = config.validate() {
+    if let Err(e) = config.validate(cli.allow_degraded) {
+        if cli.check {
+            check_failed(&e);
+        }
         error!("Configuration validation failed: {}", e);
         return Err(e.into());
     }
@@ -69,12 +102,75 @@ async fn main() -> anyhow::Result<()> {
         return Ok(());
     }
 
-    metrics::init(&config.metrics).await?;
+    if cli.check_backends {
+        let report = selftest::check_backends(&config, cli.check_timeout).await;
+        if cli.json {
+            println!("{}", report.to_json());
+        } else {
+            print!("{}", report.to_text());
+        }
+        let failed: Vec<&str> = report.failed_backends().map(|backend| backend.backend.as_str()).collect();
+        if !failed.is_empty() {
+            return Err(anyhow::anyhow!("Backends with no reachable servers: {}", failed.join(", ")));
+        }
+        return Ok(());
+    }
+
+    let maxconn = config.global.maxconn.unwrap_or(limits::DEFAULT_MAXCONN);
+    let wanted_fds = config.global.ulimit_n.unwrap_or_else(|| limits::fd_requirement(&config));
+    let obtained_fds = match utils::raise_nofile_limit(wanted_fds) {
+        Ok(limit) => {
+            info!("File descriptor limit: {} ({} wanted)", limit, wanted_fds);
+            Some(limit)
+        }
+        Err(e) if config.global.strict_limits => {
+            error!("Failed to adjust file descriptor limit with strict-limits on: {}", e);
+            return Err(e);
+        }
+        Err(e) => {
+            warn!("Failed to adjust file descriptor limit: {}", e);
+            None
+        }
+    };
+    let maxconn_ceiling = match obtained_fds {
+        Some(limit) => limits::maxconn_ceiling(&config, wanted_fds, limit).inspect_err(|e| error!("{}", e))?,
+        None => None,
+    };
+    if let Some(ceiling) = maxconn_ceiling {
+        warn!("File descriptor limit {} cannot serve maxconn {}; maxconn is clamped to {} until restart (set ulimit-n or raise the hard limit)",
+              obtained_fds.unwrap_or_default(), maxconn, ceiling);
+    }
 
+    let metrics_config = config.metrics.clone();
     let config_arc = Arc::new(config);
-    let features_manager = FeaturesManager::new(config_arc.clone())?;
-    
-    let mut proxy = ProxyServer::new(Arc::new(features_manager));
+    let features_manager = Arc::new(FeaturesManager::new(config_arc.clone(), &cli.config, cli.allow_degraded)?);
+
+    features_manager.fds.record(wanted_fds, obtained_fds.unwrap_or_default());
+    if let Some(ceiling) = maxconn_ceiling {
+        features_manager.maxconn.clamp(ceiling);
+    }
+
+    let mut proxy = ProxyServer::new(Arc::clone(&features_manager));
+
+    let metrics_server = metrics::init(&metrics_config, Arc::clone(&features_manager.events), Arc::clone(&features_manager.errors), proxy.stats(), proxy.admin(), proxy.tasks()).await?;
+    *features_manager.metrics_server.lock().await = metrics_server;
+    metrics::config_loaded(&config_arc.content_hash, None);
+    metrics::fd_limit(obtained_fds.unwrap_or_default());
+
+    if features_manager.hot_reload.is_some() {
+        let mut reloads = proxy.subscribe_reloads();
+        let features_manager = Arc::clone(&features_manager);
+        proxy.tasks().spawn("reload-apply", TaskGroup::Background, async move {
+            while let Ok(config) = reloads.recv().await {
+                features_manager.apply_protection_modes(&config);
+                if let Some(ref mut metrics_server) = *features_manager.metrics_server.lock().await {
+                    if let Err(e) = metrics_server.rebind(&config.metrics).await {
+                        error!("Failed to apply reloaded metrics configuration: {}", e);
+                    }
+                }
+            }
+        });
+    }
     
     info!("Starting proxy server with enhanced features...");
     if let Err(e) = proxy.run().await {
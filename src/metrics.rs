@@ -1,22 +1,65 @@
-use crate::config::MetricsConfig;
-use metrics::{counter, gauge, histogram};
+use crate::admin::{AdminCommand, AdminHandler, MaxconnTarget};
+use crate::balancer::AdminState;
+use crate::audit::AuditSource;
+use crate::auth::{self, Userlist};
+use crate::inspect;
+use crate::balancer::EffectiveServerState;
+use crate::tasks::{TaskGroup, TaskRegistry};
+use crate::config::{BackendConfig, MetricsConfig, StatsBindConfig};
+use crate::events::EventBus;
+use crate::error_log::{ErrorLog, ErrorScope};
+use crate::exposition::ExpositionFormat;
+use crate::stats::{self, HtmlOptions, StatsSource};
+use crate::session::ConnectFailure;
+use serde::{Deserialize, Serialize};
+use metrics::{counter, decrement_gauge, gauge, histogram, increment_gauge, Label};
 use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use metrics_util::layers::{Layer, PrefixLayer};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
 use std::net::SocketAddr;
-use tokio::net::TcpListener;
-use tokio::task;
-use tracing::{info, error};
-use std::sync::Arc;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::{self, AbortHandle};
+use tracing::{info, error, warn};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static USE_SERVER_ID: AtomicBool = AtomicBool::new(false);
+static AGGREGATED_SERVERS: RwLock<BTreeMap<String, BTreeSet<String>>> = RwLock::new(BTreeMap::new());
+const MAX_LABEL_LEN: usize = 128;
+const OTHER_SERVERS: &str = "_other";
+const HEALTH_EVENT_BATCH_BYTES: usize = 64 * 1024;
 
 pub struct Metrics {
     handle: PrometheusHandle,
+    prefix: Option<String>,
+    labels: Vec<(String, String)>,
 }
 
 impl Metrics {
-    pub fn new() -> anyhow::Result<Self> {
-        let builder = PrometheusBuilder::new();
-        let handle = builder.install_recorder()?;
-        
-        Ok(Self { handle })
+    pub fn new(config: &MetricsConfig) -> anyhow::Result<Self> {
+        let builder = config.labels.iter()
+            .fold(PrometheusBuilder::new(), |builder, (key, value)| builder.add_global_label(key, value));
+        let handle = match config.prefix {
+            Some(ref prefix) => {
+                let recorder = builder.build_recorder();
+                let handle = recorder.handle();
+                metrics::set_boxed_recorder(Box::new(PrefixLayer::new(prefix.as_str()).layer(recorder)))?;
+                handle
+            }
+            None => builder.install_recorder()?,
+        };
+
+        Ok(Self {
+            handle,
+            prefix: config.prefix.clone(),
+            labels: config.labels.clone(),
+        })
     }
 
     pub fn render(&self) -> String {
@@ -24,146 +67,1028 @@ impl Metrics {
     }
 }
 
-pub fn connection_closed(frontend: &str) {
-    gauge!("turbogate_active_connections", -1.0, "frontend" => frontend.to_string());
+pub fn build_info() {
+    gauge!("turbogate_build_info", 1.0, 
+           "version" => env!("CARGO_PKG_VERSION"), 
+           "rustc" => env!("TURBOGATE_RUSTC_VERSION"), 
+           "git_sha" => env!("TURBOGATE_GIT_SHA"));
+}
+
+pub fn config_loaded(hash: &str, previous_hash: Option<&str>) {
+    if let Some(previous_hash) = previous_hash.filter(|previous| *previous != hash) {
+        gauge!("turbogate_config_hash", 0.0, "hash" => previous_hash.to_string());
+    }
+    gauge!("turbogate_config_hash", 1.0, "hash" => hash.to_string());
+    if previous_hash.is_some() {
+        counter!("turbogate_config_reloads_total", 1, "result" => "applied");
+    }
+    config_reload_result(true);
+}
+
+pub fn config_reload_failed() {
+    counter!("turbogate_config_reloads_total", 1, "result" => "rejected");
+    config_reload_result(false);
+}
+
+fn config_reload_result(success: bool) {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    gauge!("turbogate_config_last_reload_timestamp_seconds", now.as_secs_f64());
+    gauge!("turbogate_config_last_reload_success", if success { 1.0 } else { 0.0 });
+}
+
+pub fn draining(draining: bool) {
+    gauge!("turbogate_draining", if draining { 1.0 } else { 0.0 });
+}
+
+pub fn drain_closed(reason: &str) {
+    counter!("turbogate_drain_closed_total", 1, "reason" => reason.to_string());
+}
+
+pub fn http_invalid_request(frontend: &str, reason: &str, action: &str) {
+    counter!("turbogate_http_invalid_requests_total", 1,
+            "frontend" => label(frontend),
+            "reason" => reason.to_string(),
+            "action" => action.to_string());
+}
+
+pub fn task_failure(task: &str) {
+    counter!("turbogate_task_failures_total", 1, "task" => task.to_string());
+}
+
+pub fn connection_accepted(frontend: &str, listener: &str) {
+    counter!("turbogate_connections_total", 1, 
+            "frontend" => label(frontend), 
+            "listener" => label(listener));
+}
+
+pub fn fast_open_accepted(frontend: &str, listener: &str) {
+    counter!("turbogate_tfo_accepted_total", 1,
+            "frontend" => label(frontend),
+            "listener" => label(listener));
+}
+
+pub fn fast_open_connected(backend: &str, server: &str) {
+    counter!("turbogate_tfo_connected_total", 1,
+            "backend" => label(backend),
+            "server" => server_label(backend, server));
+}
+
+pub fn accept_error(frontend: &str, listener: &str, errno: &str) {
+    counter!("turbogate_accept_errors_total", 1, 
+            "frontend" => label(frontend), 
+            "listener" => label(listener), 
+            "errno" => errno.to_string());
+}
+
+pub fn connection_opened(frontend: &str, listener: &str) {
+    increment_gauge!("turbogate_active_connections", 1.0,
+                     "frontend" => label(frontend),
+                     "listener" => label(listener));
+}
+
+pub fn connection_closed(frontend: &str, listener: &str) {
+    decrement_gauge!("turbogate_active_connections", 1.0,
+                     "frontend" => label(frontend),
+                     "listener" => label(listener));
 }
 
-pub fn connection_error(frontend: &str, error_type: &str) {
+pub fn connection_error(frontend: &str, listener: &str, error_type: &str) {
     counter!("turbogate_connection_errors_total", 1, 
-            "frontend" => frontend.to_string(), 
+            "frontend" => label(frontend), 
+            "listener" => label(listener), 
             "error_type" => error_type.to_string());
 }
 
-pub fn request_started(backend: &str, server: &str) {
-    counter!("turbogate_requests_total", 1, 
-            "backend" => backend.to_string(), 
-            "server" => server.to_string());
-    gauge!("turbogate_active_requests", 1.0, 
-           "backend" => backend.to_string(), 
-           "server" => server.to_string());
-}
-
-pub fn request_completed(backend: &str, server: &str, status: &str, duration_ms: u64) {
-    counter!("turbogate_requests_total", 1, 
-            "backend" => backend.to_string(), 
-            "server" => server.to_string(), 
-            "status" => status.to_string());
-    histogram!("turbogate_request_duration_ms", duration_ms as f64, 
-              "backend" => backend.to_string(), 
-              "server" => server.to_string());
-    histogram!("turbogate_request_duration_us", (duration_ms * 1000) as f64, 
-              "backend" => backend.to_string(), 
-              "server" => server.to_string());
-    gauge!("turbogate_request_avg_duration_ms", duration_ms as f64, 
-           "backend" => backend.to_string(), 
-           "server" => server.to_string());
-    gauge!("turbogate_active_requests", -1.0, 
-           "backend" => backend.to_string(), 
-           "server" => server.to_string());
-}
-
-pub fn request_failed(backend: &str, server: &str, error_type: &str) {
-    counter!("turbogate_request_errors_total", 1, 
-            "backend" => backend.to_string(), 
-            "server" => server.to_string(), 
-            "error_type" => error_type.to_string());
-    gauge!("turbogate_active_requests", -1.0, 
-           "backend" => backend.to_string(), 
-           "server" => server.to_string());
+pub fn fair_accept_rejected(frontend: &str) {
+    counter!("turbogate_fair_accept_rejections_total", 1,
+            "frontend" => label(frontend));
+}
+
+pub fn fair_accept_active_ips(ips: usize) {
+    gauge!("turbogate_fair_accept_active_ips", ips as f64);
+}
+
+pub fn src_dn_lookup(result: &str) {
+    counter!("turbogate_src_dn_lookups_total", 1,
+            "result" => result.to_string());
+}
+
+pub fn connection_country(frontend: &str, country: &str) {
+    counter!("turbogate_connections_by_country_total", 1,
+            "frontend" => label(frontend),
+            "country" => country.to_string());
+}
+
+pub fn session_phases(request: Duration, wait_ms: u64, connect_ms: Option<u64>, response_ms: Option<u64>, total: Duration) {
+    histogram!("turbogate_session_phase_seconds", request.as_secs_f64(), "phase" => "request");
+    histogram!("turbogate_session_phase_seconds", wait_ms as f64 / 1000.0, "phase" => "queue");
+    if let Some(connect_ms) = connect_ms {
+        histogram!("turbogate_session_phase_seconds", connect_ms as f64 / 1000.0, "phase" => "connect");
+    }
+    if let Some(response_ms) = response_ms {
+        histogram!("turbogate_session_phase_seconds", response_ms as f64 / 1000.0, "phase" => "response");
+    }
+    histogram!("turbogate_session_phase_seconds", total.as_secs_f64(), "phase" => "total");
+}
+
+pub fn http_request_phases(request: Duration, queue: Duration, connect: Duration, response: Duration, total: Duration) {
+    histogram!("turbogate_http_request_phase_seconds", request.as_secs_f64(), "phase" => "Tq");
+    histogram!("turbogate_http_request_phase_seconds", queue.as_secs_f64(), "phase" => "Tw");
+    histogram!("turbogate_http_request_phase_seconds", connect.as_secs_f64(), "phase" => "Tc");
+    histogram!("turbogate_http_request_phase_seconds", response.as_secs_f64(), "phase" => "Tr");
+    histogram!("turbogate_http_request_phase_seconds", total.as_secs_f64(), "phase" => "Tt");
+}
+
+pub fn session_buffer_high_water(frontend: &str, to_server: u64, to_client: u64) {
+    histogram!("turbogate_session_buffer_high_water_bytes", to_server as f64,
+               "frontend" => label(frontend),
+               "direction" => "to_server");
+    histogram!("turbogate_session_buffer_high_water_bytes", to_client as f64,
+               "frontend" => label(frontend),
+               "direction" => "to_client");
+}
+
+pub fn tls_handshake(frontend: &str, version: &str, alpn: &str, result: &str, duration: Duration) {
+    counter!("turbogate_tls_handshakes_total", 1,
+            "frontend" => label(frontend),
+            "version" => version.to_string(),
+            "alpn" => alpn.to_string(),
+            "result" => result.to_string());
+    histogram!("turbogate_tls_handshake_duration_seconds", duration.as_secs_f64(),
+              "frontend" => label(frontend));
+}
+
+pub fn fd_limit(limit: u64) {
+    gauge!("turbogate_fd_limit", limit as f64);
+}
+
+pub fn fd_used(used: u64) {
+    gauge!("turbogate_fd_used", used as f64);
+}
+
+pub fn frontend_conn_rate(frontend: &str, rate: u64, rate_max: u64, refused_rate: u64) {
+    gauge!("turbogate_frontend_conn_rate", rate as f64, 
+           "frontend" => label(frontend));
+    gauge!("turbogate_frontend_conn_rate_max", rate_max as f64, 
+           "frontend" => label(frontend));
+    gauge!("turbogate_frontend_refused_rate", refused_rate as f64, 
+           "frontend" => label(frontend));
+}
+
+pub fn tarpitted(frontend: &str) {
+    counter!("turbogate_tarpitted_total", 1, 
+            "frontend" => label(frontend));
+}
+
+pub fn no_backend(frontend: &str, action: &str) {
+    counter!("turbogate_no_backend_total", 1,
+            "frontend" => label(frontend),
+            "action" => action.to_string());
+}
+
+pub fn connect_auth_failure(frontend: &str) {
+    counter!("turbogate_connect_auth_failures_total", 1,
+            "frontend" => label(frontend));
+}
+
+pub fn http_return(frontend: &str, proxy: &str, status: u16, responses: u64) {
+    counter!("turbogate_http_returns_total", responses,
+            "frontend" => label(frontend), "proxy" => label(proxy), "status" => status.to_string());
+}
+
+pub fn http_auth_challenge(frontend: &str) {
+    counter!("turbogate_http_auth_challenges_total", 1,
+            "frontend" => label(frontend));
+}
+
+pub fn rate_limit_would_reject(key: &str) {
+    counter!("turbogate_rate_limit_would_reject_total", 1, 
+            "key" => key.to_string());
+}
+
+pub fn ddos_would_reject(reason: &str) {
+    counter!("turbogate_ddos_would_reject_total", 1, 
+            "reason" => reason.to_string());
+}
+
+pub fn stick_table_lookup(backend: &str, result: &str, used: usize) {
+    counter!("turbogate_stick_table_lookups_total", 1, 
+            "backend" => label(backend), 
+            "result" => result.to_string());
+    gauge!("turbogate_stick_table_used", used as f64, 
+           "backend" => label(backend));
+}
+
+pub fn health_probe(backend: &str) {
+    counter!("turbogate_health_probes_total", 1, 
+            "backend" => label(backend));
+}
+
+pub fn backend_sessions(backend: &str, sessions: u32) {
+    gauge!("turbogate_backend_sessions", sessions as f64, 
+           "backend" => label(backend));
+}
+
+pub fn backend_fullconn_reject(backend: &str, action: &str) {
+    counter!("turbogate_backend_fullconn_rejects_total", 1, 
+            "backend" => label(backend), 
+            "action" => action.to_string());
+}
+
+pub fn backend_connect_throttled(backend: &str, action: &str) {
+    counter!("turbogate_backend_connect_throttled_total", 1, 
+            "backend" => label(backend), 
+            "action" => action.to_string());
+}
+
+pub fn inspect_buffer_overflow(frontend: &str, action: &str) {
+    counter!("turbogate_inspect_buffer_overflows_total", 1, 
+            "frontend" => label(frontend), 
+            "action" => action.to_string());
+}
+
+pub fn routing_rule_match(frontend: &str, rule: &str, backend: &str) {
+    counter!("turbogate_routing_rule_matches_total", 1, 
+            "frontend" => label(frontend), 
+            "rule" => label(rule), 
+            "backend" => label(backend));
+}
+
+pub fn acl_eval_error(frontend: &str, acl: &str) {
+    counter!("turbogate_acl_eval_errors_total", 1,
+            "frontend" => label(frontend),
+            "acl" => label(acl));
+}
+
+pub fn routing_no_match(frontend: &str) {
+    counter!("turbogate_routing_no_match_total", 1, 
+            "frontend" => label(frontend));
+}
+
+pub fn connect_retry(backend: &str, server: &str, server_id: u32) {
+    counter!("turbogate_connect_retries_total", 1, server_labels(backend, server, server_id));
+}
+
+pub fn redispatch(backend: &str) {
+    counter!("turbogate_redispatches_total", 1, 
+            "backend" => label(backend));
+}
+
+pub fn connect_race_won(backend: &str, family: &str) {
+    counter!("turbogate_connect_race_wins_total", 1, 
+            "backend" => label(backend),
+            "family" => family.to_string());
+}
+
+pub fn slowloris_expired(frontend: &str) {
+    counter!("turbogate_slowloris_expired_total", 1, 
+            "frontend" => label(frontend));
+}
+
+pub fn sanitize_label(value: &str) -> Cow<'_, str> {
+    let valid = |c: char| c.is_ascii_graphic() && c != '"' && c != '\\';
+    if value.len() <= MAX_LABEL_LEN && value.chars().all(valid) {
+        return Cow::Borrowed(value);
+    }
+    Cow::Owned(value.chars().take(MAX_LABEL_LEN).map(|c| if valid(c) { c } else { '_' }).collect())
+}
+
+fn label(value: &str) -> String {
+    sanitize_label(value).into_owned()
+}
+
+pub fn limit_server_series(max_per_backend: Option<usize>, backends: &[BackendConfig]) {
+    let mut aggregated = AGGREGATED_SERVERS.write().unwrap_or_else(|e| e.into_inner());
+    let mut limited = BTreeMap::new();
+    for backend in backends {
+        let Some(max) = max_per_backend.filter(|max| backend.server.len() > *max) else {
+            continue;
+        };
+        let servers: BTreeSet<String> = backend.server.iter().skip(max).map(|server| server.name.clone()).collect();
+        if aggregated.get(&backend.name) != Some(&servers) {
+            let names: Vec<&str> = backend.server.iter().skip(max).map(|server| server.name.as_str()).collect();
+            warn!("Backend {} has {} servers, more than metrics max-series-per-backend {}; metrics of {} are reported as server=\"{}\"",
+                  backend.name, backend.server.len(), max, names.join(", "), OTHER_SERVERS);
+        }
+        limited.insert(backend.name.clone(), servers);
+    }
+    *aggregated = limited;
+}
+
+fn is_aggregated(backend: &str, server: &str) -> bool {
+    let aggregated = AGGREGATED_SERVERS.read().unwrap_or_else(|e| e.into_inner());
+    aggregated.get(backend).is_some_and(|servers| servers.contains(server))
+}
+
+fn server_label(backend: &str, server: &str) -> String {
+    if is_aggregated(backend, server) {
+        OTHER_SERVERS.to_string()
+    } else {
+        label(server)
+    }
+}
+
+fn server_labels(backend: &str, server: &str, server_id: u32) -> Vec<Label> {
+    let mut labels = vec![Label::new("backend", label(backend))];
+    labels.extend(health_labels(backend, server, server_id));
+    labels
+}
+
+fn server_gauge_labels(backend: &str, server: &str, server_id: u32) -> Option<Vec<Label>> {
+    (!is_aggregated(backend, server)).then(|| server_labels(backend, server, server_id))
+}
+
+fn health_labels(backend: &str, server: &str, server_id: u32) -> Vec<Label> {
+    let aggregated = is_aggregated(backend, server);
+    let mut labels = vec![Label::new("server", if aggregated { OTHER_SERVERS.to_string() } else { label(server) })];
+    if USE_SERVER_ID.load(Ordering::Relaxed) {
+        labels.push(Label::new("id", if aggregated { 0 } else { server_id }.to_string()));
+    }
+    labels
+}
+
+pub fn request_started(backend: &str, server: &str, server_id: u32) {
+    let labels = server_labels(backend, server, server_id);
+    counter!("turbogate_requests_total", 1, labels.clone());
+    increment_gauge!("turbogate_active_requests", 1.0, labels);
+}
+
+pub fn request_moved(backend: &str, from: &str, from_id: u32, to: &str, to_id: u32) {
+    decrement_gauge!("turbogate_active_requests", 1.0, server_labels(backend, from, from_id));
+    increment_gauge!("turbogate_active_requests", 1.0, server_labels(backend, to, to_id));
+}
+
+pub fn request_completed(backend: &str, server: &str, server_id: u32, status: &str, duration_ms: u64) {
+    let labels = server_labels(backend, server, server_id);
+    let mut status_labels = labels.clone();
+    status_labels.push(Label::new("status", status.to_string()));
+    counter!("turbogate_requests_total", 1, status_labels);
+    histogram!("turbogate_request_duration_ms", duration_ms as f64, labels.clone());
+    histogram!("turbogate_request_duration_us", (duration_ms * 1000) as f64, labels.clone());
+    gauge!("turbogate_request_avg_duration_ms", duration_ms as f64, labels.clone());
+    decrement_gauge!("turbogate_active_requests", 1.0, labels);
+}
+
+pub fn request_failed(backend: &str, server: &str, server_id: u32, error_type: &str) {
+    let labels = server_labels(backend, server, server_id);
+    let mut error_labels = labels.clone();
+    error_labels.push(Label::new("error_type", error_type.to_string()));
+    counter!("turbogate_request_errors_total", 1, error_labels);
+    decrement_gauge!("turbogate_active_requests", 1.0, labels);
+}
+
+pub fn health_check_started() {
+    increment_gauge!("turbogate_health_checks_inflight", 1.0);
+}
+
+pub fn health_check_finished() {
+    decrement_gauge!("turbogate_health_checks_inflight", 1.0);
 }
 
 pub fn backend_active_servers(backend: &str, count: usize) {
     gauge!("turbogate_backend_active_servers", count as f64, 
-           "backend" => backend.to_string());
+           "backend" => label(backend));
 }
 
 pub fn backend_total_servers(backend: &str, count: usize) {
     gauge!("turbogate_backend_total_servers", count as f64, 
-           "backend" => backend.to_string());
+           "backend" => label(backend));
+}
+
+pub fn backend_health_ratio(backend: &str, ratio: f64) {
+    gauge!("turbogate_backend_health_ratio", ratio,
+           "backend" => label(backend));
+}
+
+pub fn backend_state_changed(backend: &str) {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+    gauge!("turbogate_backend_last_state_change_timestamp_seconds", now,
+           "backend" => label(backend));
+}
+
+pub fn server_reresolved(backend: &str, server: &str, result: &str) {
+    counter!("turbogate_server_reresolutions_total", 1,
+             "backend" => label(backend), "server" => server_label(backend, server), "result" => result.to_string());
 }
 
-pub fn server_status_changed(server: &str, status: &str) {
-    gauge!("turbogate_server_status", 
-           if status == "up" { 1.0 } else { 0.0 }, 
-           "server" => server.to_string());
+pub fn backend_active_tier(backend: &str, tier: u32) {
+    gauge!("turbogate_backend_active_tier", tier as f64,
+           "backend" => label(backend));
 }
 
-pub fn health_check(server: &str, success: bool) {
-    counter!("turbogate_health_checks_total", 1, 
-            "server" => server.to_string(), 
-            "success" => success.to_string());
+pub fn backend_tier_changed(backend: &str, tier: u32) {
+    counter!("turbogate_backend_tier_changes_total", 1,
+             "backend" => label(backend));
+    backend_active_tier(backend, tier);
+}
+
+pub fn server_flapped(backend: &str, server: &str) {
+    counter!("turbogate_server_flaps_total", 1,
+             "backend" => label(backend), "server" => server_label(backend, server));
+}
+
+pub fn server_state(backend: &str, server: &str, server_id: u32, state: EffectiveServerState) {
+    let Some(server_labels) = server_gauge_labels(backend, server, server_id) else {
+        return;
+    };
+    for candidate in EffectiveServerState::ALL {
+        let mut labels = server_labels.clone();
+        labels.push(Label::new("state", candidate.as_str()));
+        gauge!("turbogate_server_state",
+               if candidate == state { 1.0 } else { 0.0 },
+               labels);
+    }
+}
+
+pub fn server_active_connections(backend: &str, server: &str, server_id: u32, count: u32) {
+    if let Some(labels) = server_gauge_labels(backend, server, server_id) {
+        gauge!("turbogate_server_active_connections", count as f64, labels);
+    }
 }
 
-pub async fn init(config: &MetricsConfig) -> anyhow::Result<()> {
+pub fn peer_connected(peers: &str, peer: &str, connected: bool) {
+    gauge!("turbogate_peer_connected", if connected { 1.0 } else { 0.0 },
+           "peers" => label(peers), "peer" => label(peer));
+}
+
+pub fn peer_update(peers: &str, peer: &str, kind: &'static str, result: &'static str) {
+    counter!("turbogate_peer_updates_total", 1,
+             "peers" => label(peers), "peer" => label(peer), "type" => kind, "result" => result);
+}
+
+pub fn server_effective_weight(backend: &str, server: &str, server_id: u32, weight: u32) {
+    if let Some(labels) = server_gauge_labels(backend, server, server_id) {
+        gauge!("turbogate_server_effective_weight", weight as f64, labels);
+    }
+}
+
+pub fn server_admin_state(backend: &str, server: &str, server_id: u32, state: &str) {
+    let Some(server_labels) = server_gauge_labels(backend, server, server_id) else {
+        return;
+    };
+    for candidate in ["ready", "drain", "maint"] {
+        let mut labels = server_labels.clone();
+        labels.push(Label::new("state", candidate));
+        gauge!("turbogate_server_admin_state", 
+               if candidate == state { 1.0 } else { 0.0 }, 
+               labels);
+    }
+}
+
+pub fn health_event_dropped() {
+    counter!("turbogate_health_events_dropped_total", 1);
+}
+
+pub fn health_check(backend: &str, server: &str, server_id: u32, failure: Option<ConnectFailure>) {
+    let mut labels = health_labels(backend, server, server_id);
+    labels.push(Label::new("success", failure.is_none().to_string()));
+    labels.push(Label::new("error_type", failure.map(|failure| failure.as_str()).unwrap_or("none")));
+    counter!("turbogate_health_checks_total", 1, labels);
+}
+
+pub async fn init(
+    config: &MetricsConfig,
+    events: Arc<EventBus>,
+    errors: Arc<ErrorLog>,
+    stats: Arc<dyn StatsSource>,
+    admin: Arc<dyn AdminHandler>,
+    tasks: Arc<TaskRegistry>,
+) -> anyhow::Result<Option<MetricsServer>> {
+    USE_SERVER_ID.store(config.use_server_id, Ordering::Relaxed);
+
     if !config.enabled {
         info!("Metrics disabled");
-        return Ok(());
-    }
-
-    let metrics = Arc::new(Metrics::new()?);
-    
-    if let Some(bind_addr) = &config.bind {
-        let addr: SocketAddr = bind_addr.parse()?;
-        let listener = TcpListener::bind(addr).await?;
-        let path = config.path.as_deref().unwrap_or("/metrics").to_string();
-        
-        info!("Starting metrics server on {} with path {}", bind_addr, path);
-        let metrics_clone = Arc::clone(&metrics);
-        let path_clone = path.clone();
-        
-        task::spawn(async move {
-            if let Err(e) = run_metrics_server(listener, path_clone, metrics_clone).await {
-                error!("Metrics server error: {}", e);
+        return Ok(None);
+    }
+
+    let metrics = Arc::new(Metrics::new(config)?);
+    build_info();
+
+    let routes = Arc::new(RwLock::new(Arc::new(StatsRoutes {
+        paths: config.paths.clone(),
+        html: HtmlOptions { refresh_secs: config.refresh_secs, admin: config.admin },
+        auth: config.auth.as_ref().map(Userlist::new),
+        metrics,
+        events,
+        errors,
+        stats,
+        admin,
+    })));
+    let mut server = MetricsServer {
+        routes,
+        config: config.clone(),
+        tasks,
+        listeners: Vec::new(),
+    };
+    if let Err(e) = server.rebind(config).await {
+        if config.required {
+            return Err(e.context("metrics endpoint is required, set 'metrics required off' to start without it"));
+        }
+        error!("{:#}; continuing without it because 'metrics required off' is set", e);
+    }
+
+    Ok(Some(server))
+}
+
+pub type MetricsHandle = Arc<tokio::sync::Mutex<Option<MetricsServer>>>;
+
+pub struct MetricsListenerStatus {
+    pub address: String,
+    pub running: bool,
+}
+
+struct MetricsListener {
+    bind: StatsBindConfig,
+    task: AbortHandle,
+}
+
+pub struct MetricsServer {
+    routes: Arc<RwLock<Arc<StatsRoutes>>>,
+    config: MetricsConfig,
+    tasks: Arc<TaskRegistry>,
+    listeners: Vec<MetricsListener>,
+}
+
+impl MetricsServer {
+    pub async fn rebind(&mut self, config: &MetricsConfig) -> anyhow::Result<()> {
+        USE_SERVER_ID.store(config.use_server_id, Ordering::Relaxed);
+
+        {
+            let mut routes = self.routes.write().unwrap_or_else(|e| e.into_inner());
+            let current = Arc::clone(&routes);
+            if current.metrics.prefix != config.prefix || current.metrics.labels != config.labels {
+                warn!("Metrics prefix and labels changed, they only take effect after a restart");
             }
-        });
+            *routes = Arc::new(StatsRoutes {
+                paths: config.paths.clone(),
+                html: HtmlOptions { refresh_secs: config.refresh_secs, admin: config.admin },
+                auth: config.auth.as_ref().map(Userlist::new),
+                metrics: Arc::clone(&current.metrics),
+                events: Arc::clone(&current.events),
+                errors: Arc::clone(&current.errors),
+                stats: Arc::clone(&current.stats),
+                admin: Arc::clone(&current.admin),
+            });
+        }
+
+        let (kept, stale): (Vec<MetricsListener>, Vec<MetricsListener>) = std::mem::take(&mut self.listeners)
+            .into_iter()
+            .partition(|listener| config.binds.contains(&listener.bind) && !listener.task.is_finished());
+        for listener in stale {
+            info!("Stopping metrics server on {}", listener.bind.address);
+            listener.task.abort();
+        }
+        self.listeners = kept;
+        self.config = config.clone();
+
+        let mut failed = Vec::new();
+        for bind in &config.binds {
+            if self.listeners.iter().any(|listener| listener.bind == *bind) {
+                continue;
+            }
+            match self.start(bind).await {
+                Ok(task) => self.listeners.push(MetricsListener { bind: bind.clone(), task }),
+                Err(e) => failed.push(format!("{} ({})", bind.address, e)),
+            }
+        }
+
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!("Cannot start metrics server on {}", failed.join(", ")))
+        }
+    }
+
+    pub fn disable(&mut self) {
+        for listener in self.listeners.drain(..) {
+            info!("Stopping metrics server on {}", listener.bind.address);
+            listener.task.abort();
+        }
+    }
+
+    pub fn config(&self) -> &MetricsConfig {
+        &self.config
+    }
+
+    pub fn status(&self) -> Vec<MetricsListenerStatus> {
+        self.config.binds.iter()
+            .map(|bind| MetricsListenerStatus {
+                address: bind.address.clone(),
+                running: self.listeners.iter().any(|listener| listener.bind == *bind && !listener.task.is_finished()),
+            })
+            .collect()
+    }
+
+    async fn start(&self, bind: &StatsBindConfig) -> anyhow::Result<AbortHandle> {
+        let routes = Arc::clone(&self.routes);
+        let paths = routes.read().unwrap_or_else(|e| e.into_inner()).paths.iter()
+            .map(|(path, handler)| format!("{} ({})", path, handler.as_str()))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let task = if let Some(socket_path) = bind.address.strip_prefix("unix:") {
+            let listener = bind_unix(socket_path, bind)?;
+            task::spawn(async move {
+                loop {
+                    match listener.accept().await {
+                        Ok((socket, _addr)) => {
+                            let peer = AuditSource::unix_peer(&socket);
+                            task::spawn(serve_metrics(socket, peer, current_routes(&routes)));
+                        }
+                        Err(e) => {
+                            error!("Metrics server error: {}", e);
+                            return;
+                        }
+                    }
+                }
+            })
+        } else {
+            let addr: SocketAddr = bind.address.parse()?;
+            let listener = TcpListener::bind(addr).await?;
+            task::spawn(async move {
+                loop {
+                    match listener.accept().await {
+                        Ok((socket, addr)) => {
+                            task::spawn(serve_metrics(socket, addr.to_string(), current_routes(&routes)));
+                        }
+                        Err(e) => {
+                            error!("Metrics server error: {}", e);
+                            return;
+                        }
+                    }
+                }
+            })
+        };
+
+        info!("Starting metrics server on {} with paths {}", bind.address, paths);
+        Ok(self.tasks.register(format!("metrics:{}", bind.address), TaskGroup::Metrics, task))
+    }
+}
+
+fn current_routes(routes: &RwLock<Arc<StatsRoutes>>) -> Arc<StatsRoutes> {
+    Arc::clone(&routes.read().unwrap_or_else(|e| e.into_inner()))
+}
+
+fn bind_unix(socket_path: &str, config: &StatsBindConfig) -> anyhow::Result<UnixListener> {
+    if Path::new(socket_path).exists() {
+        std::fs::remove_file(socket_path)?;
+    }
+    let listener = UnixListener::bind(socket_path)?;
+
+    if let Some(mode) = config.unix_mode {
+        std::fs::set_permissions(socket_path, std::fs::Permissions::from_mode(mode))?;
     }
 
-    Ok(())
+    let uid = config.unix_user.as_deref().map(|user| lookup_id("/etc/passwd", user)).transpose()?;
+    let gid = config.unix_group.as_deref().map(|group| lookup_id("/etc/group", group)).transpose()?;
+    if uid.is_some() || gid.is_some() {
+        std::os::unix::fs::chown(socket_path, uid, gid)?;
+    }
+
+    Ok(listener)
+}
+
+fn lookup_id(database: &str, name: &str) -> anyhow::Result<u32> {
+    if let Ok(id) = name.parse() {
+        return Ok(id);
+    }
+
+    std::fs::read_to_string(database)?
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.first() == Some(&name))
+        .and_then(|fields| fields.get(2).and_then(|id| id.parse().ok()))
+        .ok_or_else(|| anyhow::anyhow!("Unknown name '{}' in {}", name, database))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StatsHandler {
+    Prometheus,
+    Json,
+    Html,
+    Csv,
+    Info,
+    Errors,
+    Acls,
+    Ready,
+    Liveness,
+    Events,
+    HealthEvents,
 }
 
-async fn run_metrics_server(
-    listener: TcpListener,
-    path: String,
+const DEFAULT_STATS_PATHS: &[(&str, StatsHandler)] = &[
+    ("/metrics", StatsHandler::Prometheus),
+    ("/stats", StatsHandler::Html),
+    ("/stats;csv", StatsHandler::Csv),
+    ("/info", StatsHandler::Info),
+    ("/errors", StatsHandler::Errors),
+    ("/acls", StatsHandler::Acls),
+    ("/ready", StatsHandler::Ready),
+    ("/events?filter=health", StatsHandler::HealthEvents),
+];
+
+impl StatsHandler {
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "prometheus" => Ok(StatsHandler::Prometheus),
+            "json" => Ok(StatsHandler::Json),
+            "html" => Ok(StatsHandler::Html),
+            "csv" => Ok(StatsHandler::Csv),
+            "info" => Ok(StatsHandler::Info),
+            "errors" => Ok(StatsHandler::Errors),
+            "acls" => Ok(StatsHandler::Acls),
+            "ready" => Ok(StatsHandler::Ready),
+            "liveness" => Ok(StatsHandler::Liveness),
+            "events" => Ok(StatsHandler::Events),
+            "health-events" => Ok(StatsHandler::HealthEvents),
+            _ => Err(anyhow::anyhow!("Unknown stats path handler '{}', expected prometheus, json, html, csv, info, errors, acls, \
+                                      ready, liveness, events, health-events or disabled", value)),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StatsHandler::Prometheus => "prometheus",
+            StatsHandler::Json => "json",
+            StatsHandler::Html => "html",
+            StatsHandler::Csv => "csv",
+            StatsHandler::Info => "info",
+            StatsHandler::Errors => "errors",
+            StatsHandler::Acls => "acls",
+            StatsHandler::Ready => "ready",
+            StatsHandler::Liveness => "liveness",
+            StatsHandler::Events => "events",
+            StatsHandler::HealthEvents => "health-events",
+        }
+    }
+}
+
+pub fn default_stats_paths() -> Vec<(String, StatsHandler)> {
+    DEFAULT_STATS_PATHS.iter().map(|(path, handler)| (path.to_string(), *handler)).collect()
+}
+
+struct StatsRoutes {
+    paths: Vec<(String, StatsHandler)>,
+    html: HtmlOptions,
+    auth: Option<Userlist>,
     metrics: Arc<Metrics>,
-) -> anyhow::Result<()> {
-    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    events: Arc<EventBus>,
+    errors: Arc<ErrorLog>,
+    stats: Arc<dyn StatsSource>,
+    admin: Arc<dyn AdminHandler>,
+}
+
+impl StatsRoutes {
+    fn route<'a>(&self, target: &'a str) -> Option<(StatsHandler, &'a str)> {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        self.paths.iter()
+            .find(|(route, _)| route == target)
+            .map(|(_, handler)| (*handler, ""))
+            .or_else(|| self.paths.iter()
+                .find(|(route, _)| route == path)
+                .map(|(_, handler)| (*handler, query)))
+    }
+
+    fn path_of(&self, handler: StatsHandler) -> Option<&str> {
+        self.paths.iter().find(|(_, other)| *other == handler).map(|(path, _)| path.as_str())
+    }
+}
+
+async fn serve_metrics<S>(mut socket: S, peer: String, routes: Arc<StatsRoutes>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buffer = [0; 1024];
+    let mut request = String::new();
     loop {
-        let (mut socket, _addr) = listener.accept().await?;
-        let metrics = Arc::clone(&metrics);
-        let path = path.clone();
-        task::spawn(async move {
-            let mut buffer = [0; 1024];
-            let mut request = String::new();
-            loop {
-                let n = match socket.read(&mut buffer).await {
-                    Ok(n) if n == 0 => return,
-                    Ok(n) => n,
-                    Err(_) => return,
+        let n = match socket.read(&mut buffer).await {
+            Ok(n) if n == 0 => return,
+            Ok(n) => n,
+            Err(_) => return,
+        };
+        request.push_str(&String::from_utf8_lossy(&buffer[..n]));
+        if request.contains("\r\n\r\n") {
+            break;
+        }
+    }
+    if let Some(ref userlist) = routes.auth {
+        let header = inspect::extract_headers(request.as_bytes(), "authorization");
+        if userlist.authenticate(header.first().copied()).is_none() {
+            let challenge = auth::challenge("401 Unauthorized", "WWW-Authenticate", userlist.name(),
+                                            "You need a valid user and password to access this content.");
+            let _ = socket.write_all(&challenge).await;
+            return;
+        }
+    }
+    let route = request.strip_prefix("GET ")
+        .and_then(|rest| rest.split_whitespace().next())
+        .and_then(|target| routes.route(target));
+    match route {
+        Some((StatsHandler::Events, _)) => {
+            serve_events(socket, &routes.events).await;
+            return;
+        }
+        Some((StatsHandler::HealthEvents, _)) => {
+            serve_health_events(socket, &routes.events).await;
+            return;
+        }
+        _ => {}
+    }
+
+    let response = if let Some(command) = maxconn_command(&request) {
+        let result = match command {
+            Ok(command) => routes.admin.handle(command, AuditSource::StatsHttp { peer }).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(_) => http_response("200 OK", None, ""),
+            Err(e) => http_response("400 Bad Request", Some("text/plain"), &format!("{}\n", e)),
+        }
+    } else if let Some(command) = server_state_command(&request) {
+        let result = match command {
+            Ok(_) if !routes.html.admin => Err(anyhow::anyhow!("Server state changes are disabled, set 'stats admin' to allow them")),
+            Ok(command) => routes.admin.handle(command, AuditSource::StatsHttp { peer }).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(_) => format!(
+                "HTTP/1.1 303 See Other\r\n\
+                 Location: {}\r\n\
+                 Content-Length: 0\r\n\
+                 \r\n",
+                routes.path_of(StatsHandler::Html).unwrap_or("/")
+            ),
+            Err(e) => http_response(if routes.html.admin { "400 Bad Request" } else { "403 Forbidden" },
+                                    Some("text/plain"), &format!("{}\n", e)),
+        }
+    } else if let Some((handler, query)) = route {
+        match handler {
+            StatsHandler::Prometheus => match metrics_format(&request, query) {
+                Ok(format) => http_response("200 OK", Some(format.content_type()), &format.render(routes.metrics.render())),
+                Err(e) => http_response("400 Bad Request", Some("text/plain"), &format!("{}\n", e)),
+            },
+            StatsHandler::Json => http_response("200 OK", Some("application/json"), &stats::to_json(&routes.stats.rows())),
+            StatsHandler::Html => http_response("200 OK", Some("text/html"), &stats::to_html(&routes.stats.rows(), &routes.html)),
+            StatsHandler::Csv => http_response("200 OK", Some("text/plain"), &stats::to_csv(&routes.stats.rows())),
+            StatsHandler::Info => http_response("200 OK", Some("application/json"), &stats::info_to_json(&routes.stats.info())),
+            StatsHandler::Errors => match errors_scope(query) {
+                Some(scope) => http_response("200 OK", Some("application/json"), &routes.errors.to_json(&scope)),
+                None => http_response("404 Not Found", None, ""),
+            },
+            StatsHandler::Acls => http_response("200 OK", Some("application/json"),
+                                                &serde_json::to_string(&routes.stats.acls()).unwrap_or_else(|_| "[]".to_string())),
+            StatsHandler::Ready => {
+                let (status, body) = if routes.stats.draining() {
+                    ("503 Service Unavailable", "draining\n")
+                } else if !routes.stats.ready() {
+                    ("503 Service Unavailable", "starting\n")
+                } else {
+                    ("200 OK", "ready\n")
                 };
-                request.push_str(&String::from_utf8_lossy(&buffer[..n]));
-                if request.contains("\r\n\r\n") {
-                    break;
-                }
+                http_response(status, Some("text/plain"), body)
             }
-            let response = if request.starts_with(&format!("GET {} HTTP/1.1", path)) {
-                let metrics_data = metrics.render();
-                format!(
-                    "HTTP/1.1 200 OK\r\n\
-                     Content-Type: text/plain; version=0.0.4\r\n\
-                     Content-Length: {}\r\n\
-                     \r\n\
-                     {}",
-                    metrics_data.len(),
-                    metrics_data
-                )
-            } else {
-                "HTTP/1.1 404 Not Found\r\n\
-                 Content-Length: 0\r\n\
-                 \r\n".to_string()
+            StatsHandler::Liveness => http_response("200 OK", Some("text/plain"), "ok\n"),
+            StatsHandler::Events | StatsHandler::HealthEvents => unreachable!(),
+        }
+    } else {
+        http_response("404 Not Found", None, "")
+    };
+    let _ = socket.write_all(response.as_bytes()).await;
+}
+
+fn http_response(status: &str, content_type: Option<&str>, body: &str) -> String {
+    let content_type = content_type.map(|content_type| format!("Content-Type: {}\r\n", content_type)).unwrap_or_default();
+    format!(
+        "HTTP/1.1 {}\r\n\
+         {}\
+         Content-Length: {}\r\n\
+         \r\n\
+         {}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+fn metrics_format(request: &str, query: &str) -> anyhow::Result<ExpositionFormat> {
+    let requested = query.split('&')
+        .filter_map(|param| param.split_once('='))
+        .find_map(|(name, value)| (name == "format").then_some(value));
+    if let Some(format) = requested {
+        return ExpositionFormat::parse(format);
+    }
+
+    let accept = request.lines()
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .find_map(|(name, value)| name.trim().eq_ignore_ascii_case("accept").then(|| value.trim()));
+    Ok(ExpositionFormat::negotiate(accept))
+}
+
+fn maxconn_command(request: &str) -> Option<anyhow::Result<AdminCommand>> {
+    let target = request.strip_prefix("POST ")?.split_whitespace().next()?;
+    let query = match target.split_once('?') {
+        Some(("/maxconn", query)) => query,
+        _ if target == "/maxconn" => "",
+        _ => return None,
+    };
+    let params: Vec<(&str, &str)> = query.split('&').filter_map(|param| param.split_once('=')).collect();
+    let value = |value: &str| value.parse().map_err(|_| anyhow::anyhow!("Invalid maxconn '{}', expected a number", value));
+
+    Some(match params.as_slice() {
+        [("global", limit)] => value(limit).map(|value| AdminCommand::SetMaxconn { target: MaxconnTarget::Global, value }),
+        [("frontend", name), ("value", limit)] | [("value", limit), ("frontend", name)] => value(limit)
+            .map(|value| AdminCommand::SetMaxconn { target: MaxconnTarget::Frontend(name.to_string()), value }),
+        _ => Err(anyhow::anyhow!("Expected global=<n> or frontend=<name>&value=<n>")),
+    })
+}
+
+fn server_state_command(request: &str) -> Option<anyhow::Result<AdminCommand>> {
+    let target = request.strip_prefix("POST ")?.split_whitespace().next()?;
+    let query = match target.split_once('?') {
+        Some(("/server", query)) => query,
+        _ if target == "/server" => "",
+        _ => return None,
+    };
+    let params: Vec<(&str, &str)> = query.split('&').filter_map(|param| param.split_once('=')).collect();
+    let param = |name: &str| params.iter().find_map(|(key, value)| (*key == name).then_some(*value));
+
+    Some(match (param("backend"), param("server"), param("state")) {
+        (Some(backend), Some(server), Some(state)) => AdminState::parse(state).map(|state| AdminCommand::SetServerState {
+            backend: backend.to_string(),
+            server: server.to_string(),
+            state,
+        }),
+        _ => Err(anyhow::anyhow!("Expected backend=<name>&server=<name>&state=ready|drain|maint")),
+    })
+}
+
+fn errors_scope(query: &str) -> Option<ErrorScope> {
+    if query.is_empty() {
+        return Some(ErrorScope::All);
+    }
+    match query.split_once('=') {
+        Some(("frontend", name)) => Some(ErrorScope::Frontend(name.to_string())),
+        Some(("backend", name)) => Some(ErrorScope::Backend(name.to_string())),
+        _ => None,
+    }
+}
+
+async fn serve_health_events<S>(mut socket: S, events: &EventBus)
+where
+    S: AsyncWrite + Unpin,
+{
+    let mut receiver = events.subscribe_health_checks();
+    let headers = "HTTP/1.1 200 OK\r\n\
+                   Content-Type: application/x-ndjson\r\n\
+                   Cache-Control: no-cache\r\n\
+                   Connection: close\r\n\
+                   \r\n";
+    if socket.write_all(headers.as_bytes()).await.is_err() {
+        return;
+    }
+
+    let mut batch = String::new();
+    while let Some(event) = receiver.recv().await {
+        batch.clear();
+        batch.push_str(&event.to_json());
+        batch.push('\n');
+        while batch.len() < HEALTH_EVENT_BATCH_BYTES {
+            let Ok(event) = receiver.try_recv() else {
+                break;
             };
-            let _ = socket.write_all(response.as_bytes()).await;
-        });
+            batch.push_str(&event.to_json());
+            batch.push('\n');
+        }
+        if socket.write_all(batch.as_bytes()).await.is_err() {
+            return;
+        }
     }
 }
 
+async fn serve_events<S>(mut socket: S, events: &EventBus)
+where
+    S: AsyncWrite + Unpin,
+{
+    let mut receiver = events.subscribe();
+    let headers = "HTTP/1.1 200 OK\r\n\
+                   Content-Type: text/event-stream\r\n\
+                   Cache-Control: no-cache\r\n\
+                   Connection: keep-alive\r\n\
+                   \r\n";
+    if socket.write_all(headers.as_bytes()).await.is_err() {
+        return;
+    }
 
+    loop {
+        let message = match receiver.recv().await {
+            Ok(event) => format!("event: {}\ndata: {}\n\n", event.name(), event.to_json()),
+            Err(RecvError::Lagged(skipped)) => format!(": {} events dropped\n\n", skipped),
+            Err(RecvError::Closed) => return,
+        };
+        if socket.write_all(message.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
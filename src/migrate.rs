@@ -0,0 +1,250 @@
+use crate::config::Config;
+use serde::Serialize;
+
+pub const EXIT_CLEAN: i32 = 0;
+pub const EXIT_WARNINGS: i32 = 2;
+pub const EXIT_BLOCKING: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Category {
+    Equivalent,
+    Planned,
+    Unsupported,
+}
+
+impl Category {
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::Equivalent => "equivalent",
+            Category::Planned => "planned",
+            Category::Unsupported => "unsupported",
+        }
+    }
+
+    pub fn blocking(self) -> bool {
+        self != Category::Equivalent
+    }
+}
+
+struct Mapping {
+    pattern: &'static str,
+    category: Category,
+    suggestion: &'static str,
+}
+
+const HTTPLOG_FORMAT: &str = "log-format \"%ci:%cp [%t] %ft %b/%s %Tw/%Tc/%Tt %B %ts\"";
+
+const MAPPINGS: &[Mapping] = &[
+    Mapping { pattern: "listen", category: Category::Equivalent, suggestion: "split it into a frontend with the bind lines and a backend with the servers, joined by default_backend" },
+    Mapping { pattern: "stats enable", category: Category::Equivalent, suggestion: "stats bind <addr> in the global section serves /metrics and the /stats page" },
+    Mapping { pattern: "stats uri", category: Category::Equivalent, suggestion: "stats bind <addr> and stats path <uri> html in the global section" },
+    Mapping { pattern: "stats refresh", category: Category::Equivalent, suggestion: "stats refresh <delay> in the global section" },
+    Mapping { pattern: "stats admin", category: Category::Equivalent, suggestion: "stats admin in the global section" },
+    Mapping { pattern: "stats auth", category: Category::Equivalent, suggestion: "declare the user in a userlist section and set stats userlist <name> in the global section" },
+    Mapping { pattern: "stats realm", category: Category::Equivalent, suggestion: "the realm of stats userlist is the userlist name" },
+    Mapping { pattern: "monitor-uri", category: Category::Equivalent, suggestion: "stats path <uri> liveness in the global section, or probe GET /ready on the stats endpoint" },
+    Mapping { pattern: "option httplog", category: Category::Equivalent, suggestion: HTTPLOG_FORMAT },
+    Mapping { pattern: "option tcplog", category: Category::Equivalent, suggestion: HTTPLOG_FORMAT },
+    Mapping { pattern: "option tcpka", category: Category::Equivalent, suggestion: "option clitcpka (client side only)" },
+    Mapping { pattern: "log", category: Category::Equivalent, suggestion: "access logs are written to the process output, formatted by log-format; remove the line" },
+    Mapping { pattern: "nbproc", category: Category::Equivalent, suggestion: "remove it; a single process serves every connection on all worker threads" },
+    Mapping { pattern: "nbthread", category: Category::Equivalent, suggestion: "remove it; the worker threads follow the number of CPUs" },
+    Mapping { pattern: "chroot", category: Category::Equivalent, suggestion: "use the service manager's sandboxing, such as systemd RootDirectory=" },
+    Mapping { pattern: "crt-base", category: Category::Equivalent, suggestion: "give crt and default-crt paths in full" },
+    Mapping { pattern: "maxconn", category: Category::Equivalent, suggestion: "maxconn in the global or frontend section" },
+    Mapping { pattern: "mailers", category: Category::Equivalent, suggestion: "subscribe to server_down events on stats events-uri" },
+    Mapping { pattern: "email-alert", category: Category::Equivalent, suggestion: "subscribe to server_down events on stats events-uri" },
+    Mapping { pattern: "tcp-request content", category: Category::Planned, suggestion: "route on inspected data with tcp-request inspect-delay and use_backend conditions, reject with tcp-request connection" },
+    Mapping { pattern: "default-server", category: Category::Planned, suggestion: "repeat the options on every server line" },
+    Mapping { pattern: "balance source", category: Category::Planned, suggestion: "roundrobin is used instead" },
+    Mapping { pattern: "balance", category: Category::Unsupported, suggestion: "roundrobin is used instead; leastconn, random and random(2) are available" },
+    Mapping { pattern: "option forwardfor", category: Category::Planned, suggestion: "needs the http capability, which this build does not provide" },
+    Mapping { pattern: "http-request", category: Category::Planned, suggestion: "needs the http capability, which this build does not provide; http-request auth and return are available" },
+    Mapping { pattern: "cache", category: Category::Planned, suggestion: "responses are not cached; keep a cache in front of turbogate or behind it" },
+    Mapping { pattern: "program", category: Category::Unsupported, suggestion: "run the program under the service manager" },
+];
+
+fn lookup(directive: &str) -> Option<&'static Mapping> {
+    let words: Vec<&str> = directive.split_whitespace().collect();
+    MAPPINGS.iter()
+        .filter(|mapping| {
+            let pattern: Vec<&str> = mapping.pattern.split_whitespace().collect();
+            words.starts_with(&pattern)
+        })
+        .max_by_key(|mapping| mapping.pattern.split_whitespace().count())
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    pub location: String,
+    pub line: usize,
+    pub section: String,
+    pub directive: String,
+    pub category: Category,
+    pub suggestion: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MigrationReport {
+    pub status: &'static str,
+    pub equivalent: usize,
+    pub planned: usize,
+    pub unsupported: usize,
+    pub findings: Vec<Finding>,
+}
+
+impl MigrationReport {
+    pub fn new(config: &Config) -> Self {
+        let ignored = config.ignored_directives.iter()
+            .map(|ignored| (&ignored.span, &ignored.section, &ignored.directive, None));
+        let capabilities = config.capability_uses.iter()
+            .filter(|usage| !usage.capability.available())
+            .map(|usage| (&usage.span, &usage.section, &usage.directive, Some(usage.capability)));
+
+        let mut findings: Vec<Finding> = ignored.chain(capabilities)
+            .map(|(span, section, directive, capability)| {
+                let (category, suggestion) = match (lookup(directive), capability) {
+                    (Some(mapping), _) => (mapping.category, Some(mapping.suggestion.to_string())),
+                    (None, Some(capability)) => (Category::Planned, Some(format!("needs the {} capability, which this build does not provide", capability))),
+                    (None, None) => (Category::Unsupported, None),
+                };
+                Finding {
+                    location: span.to_string(),
+                    line: span.line,
+                    section: section.clone(),
+                    directive: directive.clone(),
+                    category,
+                    suggestion,
+                }
+            })
+            .collect();
+        findings.sort_by_key(|finding| finding.line);
+
+        let count = |category| findings.iter().filter(|finding| finding.category == category).count();
+        let (equivalent, planned, unsupported) = (count(Category::Equivalent), count(Category::Planned), count(Category::Unsupported));
+        let status = if findings.iter().any(|finding| finding.category.blocking()) {
+            "blocking"
+        } else if findings.is_empty() {
+            "clean"
+        } else {
+            "warnings"
+        };
+
+        Self { status, equivalent, planned, unsupported, findings }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self.status {
+            "clean" => EXIT_CLEAN,
+            "warnings" => EXIT_WARNINGS,
+            _ => EXIT_BLOCKING,
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut output = String::new();
+        for finding in &self.findings {
+            let section = if finding.section.is_empty() { String::new() } else { format!(" [{}]", finding.section) };
+            output.push_str(&format!("{}{}: {}: {}\n", finding.location, section, finding.category.name(), finding.directive));
+            if let Some(ref suggestion) = finding.suggestion {
+                output.push_str(&format!("    -> {}\n", suggestion));
+            }
+        }
+        output.push_str(&format!("{} directives with a turbogate equivalent, {} planned, {} unsupported: {}\n",
+                                 self.equivalent, self.planned, self.unsupported, self.status));
+        output
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_else(|_| "{}".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(config: &str) -> MigrationReport {
+        MigrationReport::new(&Config::from_haproxy_config(config, Some("haproxy.cfg")).unwrap())
+    }
+
+    fn category(directive: &str) -> Option<Category> {
+        lookup(directive).map(|mapping| mapping.category)
+    }
+
+    fn pattern(directive: &str) -> Option<&'static str> {
+        lookup(directive).map(|mapping| mapping.pattern)
+    }
+
+    #[test]
+    fn patterns_are_unique_and_suggest_something() {
+        for (i, mapping) in MAPPINGS.iter().enumerate() {
+            assert!(!mapping.suggestion.is_empty(), "{}", mapping.pattern);
+            assert!(MAPPINGS[i + 1..].iter().all(|other| other.pattern != mapping.pattern), "{} mapped twice", mapping.pattern);
+        }
+    }
+
+    #[test]
+    fn longest_pattern_wins() {
+        assert_eq!(category("balance source"), Some(Category::Planned));
+        assert_eq!(category("balance uri"), Some(Category::Unsupported));
+        assert_eq!(pattern("stats uri /haproxy"), Some("stats uri"));
+        assert_eq!(lookup("option tcplog").unwrap().suggestion, HTTPLOG_FORMAT);
+    }
+
+    #[test]
+    fn patterns_match_whole_words() {
+        assert_eq!(pattern("log global"), Some("log"));
+        assert_eq!(pattern("log-send-hostname"), None);
+        assert_eq!(pattern("stats  uri   /x"), Some("stats uri"));
+        assert_eq!(pattern("stats"), None);
+        assert_eq!(pattern(""), None);
+    }
+
+    #[test]
+    fn clean_config_reports_nothing() {
+        let report = report("frontend web\n    bind *:80\n    default_backend app\n\nbackend app\n    server s1 127.0.0.1:8080\n");
+        assert!(report.findings.is_empty());
+        assert_eq!((report.status, report.exit_code()), ("clean", EXIT_CLEAN));
+        assert_eq!(report.to_text(), "0 directives with a turbogate equivalent, 0 planned, 0 unsupported: clean\n");
+    }
+
+    #[test]
+    fn equivalents_only_warn() {
+        let report = report("global\n    nbthread 4\n\nfrontend web\n    bind *:80\n    default_backend app\n\nbackend app\n    server s1 127.0.0.1:8080\n");
+        assert_eq!((report.status, report.exit_code()), ("warnings", EXIT_WARNINGS));
+        assert_eq!(report.to_text(),
+                   "haproxy.cfg:2 [global]: equivalent: nbthread 4\n    -> remove it; the worker threads follow the number of CPUs\n\
+                    1 directives with a turbogate equivalent, 0 planned, 0 unsupported: warnings\n");
+    }
+
+    #[test]
+    fn findings_are_categorized_in_line_order() {
+        let report = report("global\n    stats timeout 30s\n    chroot /var/empty\n\nfrontend web\n    bind *:80\n    http-request set-header X-Id 1\n    default_backend app\n\nbackend app\n    balance source\n    server s1 127.0.0.1:8080\n\ncache static\n    total-max-size 64\n");
+        let findings: Vec<(usize, Category, &str)> = report.findings.iter()
+            .map(|finding| (finding.line, finding.category, finding.directive.as_str()))
+            .collect();
+        assert_eq!(findings, [
+            (2, Category::Unsupported, "stats timeout 30s"),
+            (3, Category::Equivalent, "chroot /var/empty"),
+            (7, Category::Planned, "http-request set-header"),
+            (11, Category::Planned, "balance source"),
+            (14, Category::Planned, "cache static"),
+        ]);
+        assert_eq!(report.findings[0].suggestion, None);
+        assert_eq!(report.findings[2].section, "frontend web");
+        assert_eq!(report.findings[2].suggestion.as_deref(), Some("needs the http capability, which this build does not provide; http-request auth and return are available"));
+        assert_eq!((report.equivalent, report.planned, report.unsupported), (1, 3, 1));
+        assert_eq!((report.status, report.exit_code()), ("blocking", EXIT_BLOCKING));
+    }
+
+    #[test]
+    fn json_report_uses_kebab_case_categories() {
+        let json: serde_json::Value = serde_json::from_str(&report("global\n    chroot /var/empty\n").to_json()).unwrap();
+        assert_eq!(json["status"], "warnings");
+        assert_eq!(json["equivalent"], 1);
+        assert_eq!(json["findings"][0]["category"], "equivalent");
+        assert_eq!(json["findings"][0]["location"], "haproxy.cfg:2");
+    }
+}
@@ -0,0 +1,54 @@
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use tracing::{debug, warn};
+
+const NOTIFY_SOCKET: &str = "NOTIFY_SOCKET";
+
+pub fn ready(status: &str) {
+    notify(&format!("READY=1\nSTATUS={}", status));
+}
+
+pub fn reloading() {
+    notify(&format!("RELOADING=1\nMONOTONIC_USEC={}\nSTATUS=Reloading configuration", monotonic_usec()));
+}
+
+pub fn stopping() {
+    notify("STOPPING=1\nSTATUS=Shutting down");
+}
+
+fn notify(message: &str) {
+    let Some(path) = std::env::var_os(NOTIFY_SOCKET) else {
+        return;
+    };
+    let path = path.to_string_lossy();
+    match send(&path, message) {
+        Ok(()) => debug!("sd_notify: {}", message.replace('\n', " ")),
+        Err(e) => warn!("Failed to notify service manager on {}: {}", path, e),
+    }
+}
+
+fn send(path: &str, message: &str) -> io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+    match path.strip_prefix('@') {
+        Some(name) => send_abstract(&socket, name, message),
+        None => socket.send_to(message.as_bytes(), path).map(|_| ()),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn send_abstract(socket: &UnixDatagram, name: &str, message: &str) -> io::Result<()> {
+    use std::os::linux::net::SocketAddrExt;
+    let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+    socket.send_to_addr(message.as_bytes(), &addr).map(|_| ())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_abstract(_socket: &UnixDatagram, _name: &str, _message: &str) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "abstract sockets are only available on Linux"))
+}
+
+fn monotonic_usec() -> u64 {
+    let mut now = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    now.tv_sec as u64 * 1_000_000 + now.tv_nsec as u64 / 1_000
+}
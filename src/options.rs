@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use std::collections::HashMap;
 use tracing::{debug, warn};
 use serde::{Deserialize, Serialize};
@@ -15,8 +15,12 @@ pub struct HttpOptions {
     pub httpchk: Option<HttpCheck>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub http_keep_alive_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http_request_timeout: Option<u64>,
     pub dontlognull: bool,
     pub logasap: bool,
+    #[serde(default)]
+    pub accept_invalid_http_request: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,7 +28,18 @@ pub struct TcpOptions {
     pub clitcpka: bool,
     pub tcp_check: bool,
     pub tcp_check_connect: bool,
+    pub tcp_check_connects: Vec<TcpCheckConnect>,
     pub retries: Option<u32>,
+    pub use_original_dst: bool,
+    #[serde(default)]
+    pub connect_direct: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TcpCheckConnect {
+    pub port: Option<u16>,
+    pub addr: Option<String>,
+    pub ssl: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -37,6 +52,21 @@ pub struct GeneralOptions {
     pub timeout_server: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub timeout_queue: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_tunnel: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_client_fin: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_client_stall: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_server_stall: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_tarpit: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_check: Option<u64>,
+    pub balance_debug: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redispatch: Option<i32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -44,6 +74,15 @@ pub struct HttpCheck {
     pub method: String,
     pub path: String,
     pub headers: HashMap<String, String>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub expect: Vec<HttpCheckExpect>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum HttpCheckExpect {
+    Status(u16, u16),
+    String(String),
+    Rstring(String),
 }
 
 impl Default for Options {
@@ -61,8 +100,10 @@ impl Default for HttpOptions {
         Self {
             httpchk: None,
             http_keep_alive_timeout: None,
+            http_request_timeout: None,
             dontlognull: true,
             logasap: false,
+            accept_invalid_http_request: false,
         }
     }
 }
@@ -73,7 +114,10 @@ impl Default for TcpOptions {
             clitcpka: false,
             tcp_check: false,
             tcp_check_connect: false,
+            tcp_check_connects: Vec::new(),
             retries: Some(3),
+            use_original_dst: false,
+            connect_direct: false,
         }
     }
 }
@@ -85,12 +129,20 @@ impl Default for GeneralOptions {
             timeout_client: Some(50000),
             timeout_server: Some(50000),
             timeout_queue: Some(10000),
+            timeout_tunnel: None,
+            timeout_client_fin: None,
+            timeout_client_stall: None,
+            timeout_server_stall: None,
+            timeout_tarpit: None,
+            timeout_check: None,
+            balance_debug: false,
+            redispatch: None,
         }
     }
 }
 
 impl Options {
-    pub fn from_strings(options: &[String], mode: &str) -> Result<Self> {
+    pub fn from_tokens(options: &[Vec<String>], mode: &str) -> Result<Self> {
         let mut opts = Self::default();
         
         for option in options {
@@ -99,83 +151,183 @@ impl Options {
         
         Ok(opts)
     }
+
+    pub fn known(option: &[String]) -> bool {
+        matches!(option.first().map(String::as_str),
+                 Some("httpchk" | "http-check" | "dontlognull" | "logasap" | "accept-invalid-http-request" | "balance-debug"
+                      | "redispatch" | "clitcpka" | "use-original-dst" | "connect-direct" | "tcp-check"))
+    }
     
-    fn parse_option(option: &str, opts: &mut Options, mode: &str) -> Result<()> {
-        let parts: Vec<&str> = option.split_whitespace().collect();
+    fn parse_option(option: &[String], opts: &mut Options, mode: &str) -> Result<()> {
+        let parts: Vec<&str> = option.iter().map(String::as_str).collect();
         if parts.is_empty() {
             return Ok(());
         }
         
         match parts[0] {
             "httpchk" => {
-                if mode == "http" {
-                    opts.http_options.httpchk = Some(Self::parse_httpchk(option)?);
+                if mode == "http" || mode == "health" {
+                    let mut httpchk = Self::parse_httpchk(&parts)?;
+                    if let Some(previous) = opts.http_options.httpchk.take() {
+                        httpchk.expect = previous.expect;
+                    }
+                    opts.http_options.httpchk = Some(httpchk);
                 } else {
                     warn!("httpchk option ignored in {} mode", mode);
                 }
             }
+            "http-check" => {
+                if parts.get(1) != Some(&"expect") {
+                    return Err(anyhow!("Unsupported http-check directive: {}", option.join(" ")));
+                }
+                let expect = Self::parse_http_check_expect(&parts[2..])?;
+                if mode == "http" {
+                    opts.http_options.httpchk
+                        .get_or_insert_with(|| HttpCheck {
+                            method: "GET".to_string(),
+                            path: "/".to_string(),
+                            headers: HashMap::new(),
+                            expect: Vec::new(),
+                        })
+                        .expect
+                        .push(expect);
+                } else {
+                    warn!("http-check option ignored in {} mode", mode);
+                }
+            }
             "dontlognull" => {
                 opts.http_options.dontlognull = true;
             }
             "logasap" => {
                 opts.http_options.logasap = true;
             }
+            "accept-invalid-http-request" => {
+                opts.http_options.accept_invalid_http_request = true;
+            }
+            "balance-debug" => {
+                opts.general_options.balance_debug = true;
+            }
+            "redispatch" => {
+                let interval = match parts.get(1) {
+                    Some(value) => value.parse()
+                        .map_err(|_| anyhow!("Invalid option redispatch interval '{}', expected a non-zero integer", value))?,
+                    None => -1,
+                };
+                opts.general_options.redispatch = (interval != 0).then_some(interval);
+            }
             "clitcpka" => {
                 opts.tcp_options.clitcpka = true;
             }
+            "use-original-dst" => {
+                opts.tcp_options.use_original_dst = true;
+            }
+            "connect-direct" => {
+                opts.tcp_options.connect_direct = true;
+            }
             "tcp-check" => {
                 opts.tcp_options.tcp_check = true;
                 if parts.len() > 1 && parts[1] == "connect" {
                     opts.tcp_options.tcp_check_connect = true;
+                    let step = Self::parse_tcp_check_connect(&parts[2..])?;
+                    opts.tcp_options.tcp_check_connects.push(step);
                 }
             }
             _ => {
-                debug!("Unknown option: {}", option);
+                debug!("Unknown option: {}", option.join(" "));
             }
         }
         
         Ok(())
     }
     
-    fn parse_httpchk(option: &str) -> Result<HttpCheck> {
-        let parts: Vec<&str> = option.split_whitespace().collect();
-        
+    fn parse_tcp_check_connect(args: &[&str]) -> Result<TcpCheckConnect> {
+        let mut step = TcpCheckConnect {
+            port: None,
+            addr: None,
+            ssl: false,
+        };
+
+        let mut i = 0;
+        while i < args.len() {
+            match args[i] {
+                "port" => {
+                    let value = args.get(i + 1)
+                        .ok_or_else(|| anyhow!("tcp-check connect: 'port' requires a value"))?;
+                    step.port = Some(value.parse()
+                        .map_err(|_| anyhow!("tcp-check connect: invalid port '{}'", value))?);
+                    i += 2;
+                }
+                "addr" => {
+                    let value = args.get(i + 1)
+                        .ok_or_else(|| anyhow!("tcp-check connect: 'addr' requires a value"))?;
+                    step.addr = Some(value.to_string());
+                    i += 2;
+                }
+                "ssl" => {
+                    step.ssl = true;
+                    i += 1;
+                }
+                other => {
+                    debug!("Ignoring unsupported tcp-check connect argument: {}", other);
+                    i += 1;
+                }
+            }
+        }
+
+        Ok(step)
+    }
+
+    fn parse_httpchk(parts: &[&str]) -> Result<HttpCheck> {
         if parts.len() >= 3 {
             Ok(HttpCheck {
                 method: parts[1].to_string(),
                 path: parts[2].to_string(),
                 headers: HashMap::new(),
+                expect: Vec::new(),
             })
         } else {
             Ok(HttpCheck {
                 method: "GET".to_string(),
                 path: "/".to_string(),
                 headers: HashMap::new(),
+                expect: Vec::new(),
             })
         }
     }
-    
-    pub fn parse_timeout(timeout_str: &str) -> Result<std::time::Duration> {
-        if timeout_str.ends_with("ms") {
-            let ms: u64 = timeout_str[..timeout_str.len()-2].parse()?;
-            Ok(std::time::Duration::from_millis(ms))
-        } else if timeout_str.ends_with('s') {
-            let secs: u64 = timeout_str[..timeout_str.len()-1].parse()?;
-            Ok(std::time::Duration::from_secs(secs))
-        } else if timeout_str.ends_with('m') {
-            let mins: u64 = timeout_str[..timeout_str.len()-1].parse()?;
-            Ok(std::time::Duration::from_secs(mins * 60))
-        } else if timeout_str.ends_with('h') {
-            let hours: u64 = timeout_str[..timeout_str.len()-1].parse()?;
-            Ok(std::time::Duration::from_secs(hours * 3600))
-        } else {
-            let secs: u64 = timeout_str.parse()?;
-            Ok(std::time::Duration::from_secs(secs))
+
+    fn parse_http_check_expect(args: &[&str]) -> Result<HttpCheckExpect> {
+        let (matcher, pattern) = match args {
+            [matcher, pattern] => (*matcher, *pattern),
+            [matcher, ..] => return Err(anyhow!("http-check expect {}: expected exactly one pattern", matcher)),
+            [] => return Err(anyhow!("http-check expect requires a matcher")),
+        };
+
+        match matcher {
+            "status" => {
+                let (low, high) = pattern.split_once('-').unwrap_or((pattern, pattern));
+                let parse_status = |value: &str| -> Result<u16> {
+                    value.parse::<u16>().ok()
+                        .filter(|status| (100..=599).contains(status))
+                        .ok_or_else(|| anyhow!("http-check expect status: invalid status '{}'", value))
+                };
+                let (low, high) = (parse_status(low)?, parse_status(high)?);
+                if low > high {
+                    return Err(anyhow!("http-check expect status: empty range '{}'", pattern));
+                }
+                Ok(HttpCheckExpect::Status(low, high))
+            }
+            "string" => Ok(HttpCheckExpect::String(pattern.to_string())),
+            "rstring" => {
+                regex::Regex::new(pattern)
+                    .map_err(|e| anyhow!("http-check expect rstring: invalid regex '{}': {}", pattern, e))?;
+                Ok(HttpCheckExpect::Rstring(pattern.to_string()))
+            }
+            other => Err(anyhow!("Unknown http-check expect matcher '{}', expected status, string or rstring", other)),
         }
     }
     
     pub fn apply_timeout(&mut self, timeout_type: &str, value: &str) -> Result<()> {
-        let duration = Self::parse_timeout(value)?;
+        let duration = crate::utils::parse_duration(value)?;
         let duration_ms = duration.as_millis() as u64;
         
         match timeout_type {
@@ -183,6 +335,13 @@ impl Options {
             "client" => self.general_options.timeout_client = Some(duration_ms),
             "server" => self.general_options.timeout_server = Some(duration_ms),
             "queue" => self.general_options.timeout_queue = Some(duration_ms),
+            "tunnel" => self.general_options.timeout_tunnel = Some(duration_ms),
+            "client-fin" => self.general_options.timeout_client_fin = Some(duration_ms),
+            "client-stall" => self.general_options.timeout_client_stall = Some(duration_ms),
+            "server-stall" => self.general_options.timeout_server_stall = Some(duration_ms),
+            "tarpit" => self.general_options.timeout_tarpit = Some(duration_ms),
+            "check" => self.general_options.timeout_check = Some(duration_ms),
+            "http-request" => self.http_options.http_request_timeout = Some(duration_ms),
             "http-keep-alive" => self.http_options.http_keep_alive_timeout = Some(duration_ms),
             _ => warn!("Unknown timeout type: {}", timeout_type),
         }
@@ -0,0 +1,481 @@
+use crate::config::{PeerConfig, PeersConfig};
+use crate::metrics;
+use crate::tasks::{TaskGroup, TaskRegistry};
+use crate::utils;
+use anyhow::{Result, anyhow};
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::{AbortHandle, JoinSet};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tracing::{debug, info, warn};
+
+const PEER_UPDATE_CAPACITY: usize = 4096;
+const PEER_MAX_FRAME: usize = 1024 * 1024;
+const PEER_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const PEER_HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+const PEER_RECONNECT_MIN: Duration = Duration::from_millis(500);
+const PEER_RECONNECT_MAX: Duration = Duration::from_secs(30);
+const PEER_STABLE_AFTER: Duration = Duration::from_secs(10);
+const HMAC_BLOCK_SIZE: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum PeerUpdate {
+    StickStore {
+        backend: String,
+        key: Vec<u8>,
+        server: String,
+        expires_in_ms: u64,
+        updated: u64,
+    },
+    StickForget {
+        backend: String,
+        key: Vec<u8>,
+        updated: u64,
+    },
+    ServerState {
+        backend: String,
+        server: String,
+        state: String,
+        updated: u64,
+    },
+}
+
+impl PeerUpdate {
+    pub fn backend(&self) -> &str {
+        match self {
+            PeerUpdate::StickStore { backend, .. }
+            | PeerUpdate::StickForget { backend, .. }
+            | PeerUpdate::ServerState { backend, .. } => backend,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            PeerUpdate::StickStore { .. } => "stick-store",
+            PeerUpdate::StickForget { .. } => "stick-forget",
+            PeerUpdate::ServerState { .. } => "server-state",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum PeerMessage {
+    Hello { section: String, peer: String },
+    Update { update: PeerUpdate },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PeerFrame {
+    body: String,
+    mac: Option<String>,
+}
+
+pub trait PeerStore: Send + Sync {
+    fn apply(&self, section: &str, update: PeerUpdate) -> Result<bool>;
+    fn snapshot(&self, section: &str) -> Vec<PeerUpdate>;
+}
+
+pub struct PeerSection {
+    config: PeersConfig,
+    local: PeerConfig,
+    updates: broadcast::Sender<PeerUpdate>,
+}
+
+impl PeerSection {
+    fn new(config: &PeersConfig, localpeer: &str) -> Result<Self> {
+        let local = config.local(localpeer)
+            .ok_or_else(|| anyhow!("Peers '{}' has no peer named '{}'", config.name, localpeer))?
+            .clone();
+        let (updates, _) = broadcast::channel(PEER_UPDATE_CAPACITY);
+        Ok(Self { config: config.clone(), local, updates })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    pub fn publish(&self, update: PeerUpdate) {
+        let _ = self.updates.send(update);
+    }
+
+    fn start(self: &Arc<Self>, tasks: &TaskRegistry, store: &Arc<dyn PeerStore>) -> Vec<AbortHandle> {
+        let mut handles = Vec::new();
+
+        let section = Arc::clone(self);
+        let listen_store = Arc::clone(store);
+        handles.push(tasks.spawn(format!("peers:{}", self.config.name), TaskGroup::Accept, async move {
+            section.listen(listen_store).await;
+        }));
+
+        for remote in self.config.remotes(&self.local.name) {
+            let section = Arc::clone(self);
+            let remote = remote.clone();
+            let push_store = Arc::clone(store);
+            handles.push(tasks.spawn(format!("peers:{}/{}", self.config.name, remote.name), TaskGroup::Background, async move {
+                section.push_loop(remote, push_store).await;
+            }));
+        }
+
+        handles
+    }
+
+    async fn listen(self: Arc<Self>, store: Arc<dyn PeerStore>) {
+        let mut backoff = PEER_RECONNECT_MIN;
+        let listener = loop {
+            match TcpListener::bind(self.local.address).await {
+                Ok(listener) => break listener,
+                Err(e) => {
+                    warn!("Peers {}: cannot listen on {}: {}, retrying in {:?}", self.config.name, self.local.address, e, backoff);
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(PEER_RECONNECT_MAX);
+                }
+            }
+        };
+        info!("Peers {}: peer {} listening on {}", self.config.name, self.local.name, self.local.address);
+
+        let mut receivers = JoinSet::new();
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, addr)) => {
+                        let section = Arc::clone(&self);
+                        let store = Arc::clone(&store);
+                        receivers.spawn(async move {
+                            if let Err(e) = section.receive(stream, addr, store).await {
+                                warn!("Peers {}: connection from {} closed: {}", section.config.name, addr, e);
+                            }
+                        });
+                    }
+                    Err(e) => warn!("Peers {}: accept failed: {}", self.config.name, e),
+                },
+                Some(_) = receivers.join_next() => {}
+            }
+        }
+    }
+
+    async fn receive(&self, stream: TcpStream, addr: SocketAddr, store: Arc<dyn PeerStore>) -> Result<()> {
+        let mut framed = Framed::new(stream, codec());
+        let hello = tokio::time::timeout(PEER_HELLO_TIMEOUT, framed.next()).await
+            .map_err(|_| anyhow!("no hello within {:?}", PEER_HELLO_TIMEOUT))?
+            .ok_or_else(|| anyhow!("closed before hello"))??;
+        let PeerMessage::Hello { section, peer } = self.decode(&hello)? else {
+            return Err(anyhow!("expected a hello message"));
+        };
+        if section != self.config.name {
+            return Err(anyhow!("peer '{}' belongs to peers '{}'", peer, section));
+        }
+        if peer == self.local.name || !self.config.peers.iter().any(|known| known.name == peer) {
+            return Err(anyhow!("unknown peer '{}'", peer));
+        }
+        info!(peers = %self.config.name, peer = %peer, client_addr = %addr, event = "peer_connected",
+              "Peers {}: receiving updates from peer {} ({})", self.config.name, peer, addr);
+
+        while let Some(frame) = framed.next().await {
+            let PeerMessage::Update { update } = self.decode(&frame?)? else {
+                return Err(anyhow!("unexpected hello from peer '{}'", peer));
+            };
+            let kind = update.kind();
+            let result = match store.apply(&self.config.name, update) {
+                Ok(true) => "applied",
+                Ok(false) => "stale",
+                Err(e) => {
+                    debug!("Peers {}: update from peer {} rejected: {}", self.config.name, peer, e);
+                    "rejected"
+                }
+            };
+            metrics::peer_update(&self.config.name, &peer, kind, result);
+        }
+
+        info!(peers = %self.config.name, peer = %peer, event = "peer_disconnected",
+              "Peers {}: peer {} closed its connection", self.config.name, peer);
+        Ok(())
+    }
+
+    async fn push_loop(self: Arc<Self>, remote: PeerConfig, store: Arc<dyn PeerStore>) {
+        let mut backoff = PEER_RECONNECT_MIN;
+        loop {
+            let started = Instant::now();
+            let result = self.push(&remote, &store).await;
+            metrics::peer_connected(&self.config.name, &remote.name, false);
+            if started.elapsed() >= PEER_STABLE_AFTER {
+                backoff = PEER_RECONNECT_MIN;
+            }
+            if let Err(e) = result {
+                warn!(peers = %self.config.name, peer = %remote.name, event = "peer_disconnected",
+                      "Peers {}: connection to peer {} at {} failed: {:#}, retrying in {:?}",
+                      self.config.name, remote.name, remote.address, e, backoff);
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(PEER_RECONNECT_MAX);
+        }
+    }
+
+    async fn push(&self, remote: &PeerConfig, store: &Arc<dyn PeerStore>) -> Result<()> {
+        let mut updates = self.updates.subscribe();
+        let stream = tokio::time::timeout(PEER_CONNECT_TIMEOUT, TcpStream::connect(remote.address)).await
+            .map_err(|_| anyhow!("connect timed out after {:?}", PEER_CONNECT_TIMEOUT))??;
+        stream.set_nodelay(true)?;
+        let mut framed = Framed::new(stream, codec());
+
+        self.send(&mut framed, &PeerMessage::Hello {
+            section: self.config.name.clone(),
+            peer: self.local.name.clone(),
+        }).await?;
+        metrics::peer_connected(&self.config.name, &remote.name, true);
+        info!(peers = %self.config.name, peer = %remote.name, event = "peer_connected",
+              "Peers {}: connected to peer {} at {}", self.config.name, remote.name, remote.address);
+        self.resync(&mut framed, remote, store).await?;
+
+        loop {
+            tokio::select! {
+                update = updates.recv() => match update {
+                    Ok(update) => {
+                        let kind = update.kind();
+                        self.send(&mut framed, &PeerMessage::Update { update }).await?;
+                        metrics::peer_update(&self.config.name, &remote.name, kind, "sent");
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Peers {}: {} updates for peer {} were dropped, resending the full state", self.config.name, skipped, remote.name);
+                        self.resync(&mut framed, remote, store).await?;
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+                frame = framed.next() => return Err(match frame {
+                    None => anyhow!("closed by peer"),
+                    Some(Err(e)) => e.into(),
+                    Some(Ok(_)) => anyhow!("unexpected data from peer"),
+                }),
+            }
+        }
+    }
+
+    async fn resync(&self, framed: &mut Framed<TcpStream, LengthDelimitedCodec>, remote: &PeerConfig, store: &Arc<dyn PeerStore>) -> Result<()> {
+        let snapshot = store.snapshot(&self.config.name);
+        debug!("Peers {}: sending {} entries to peer {}", self.config.name, snapshot.len(), remote.name);
+        for update in snapshot {
+            let kind = update.kind();
+            self.send(framed, &PeerMessage::Update { update }).await?;
+            metrics::peer_update(&self.config.name, &remote.name, kind, "sent");
+        }
+        Ok(())
+    }
+
+    async fn send(&self, framed: &mut Framed<TcpStream, LengthDelimitedCodec>, message: &PeerMessage) -> Result<()> {
+        let body = serde_json::to_string(message)?;
+        let mac = self.config.secret.as_ref().map(|secret| hex(&hmac_sha256(secret.as_bytes(), body.as_bytes())));
+        framed.send(Bytes::from(serde_json::to_vec(&PeerFrame { body, mac })?)).await?;
+        Ok(())
+    }
+
+    fn decode(&self, frame: &[u8]) -> Result<PeerMessage> {
+        let frame: PeerFrame = serde_json::from_slice(frame)?;
+        if let Some(ref secret) = self.config.secret {
+            let mac = frame.mac.ok_or_else(|| anyhow!("unsigned message"))?;
+            let expected = hex(&hmac_sha256(secret.as_bytes(), frame.body.as_bytes()));
+            if !utils::constant_time_eq(mac.as_bytes(), expected.as_bytes()) {
+                return Err(anyhow!("message signature mismatch"));
+            }
+        }
+        Ok(serde_json::from_str(&frame.body)?)
+    }
+}
+
+struct RunningSection {
+    section: Arc<PeerSection>,
+    tasks: Vec<AbortHandle>,
+}
+
+#[derive(Default)]
+pub struct Peers {
+    sections: HashMap<String, RunningSection>,
+}
+
+impl Peers {
+    pub fn reconcile(&mut self, configs: &[PeersConfig], localpeer: &str, tasks: &TaskRegistry, store: Arc<dyn PeerStore>) {
+        self.sections.retain(|name, running| {
+            let keep = configs.iter()
+                .any(|config| config == &running.section.config && running.section.local.name == localpeer);
+            if !keep {
+                info!("Peers {}: stopping", name);
+                for task in &running.tasks {
+                    task.abort();
+                }
+            }
+            keep
+        });
+
+        for config in configs {
+            if self.sections.contains_key(&config.name) {
+                continue;
+            }
+            match PeerSection::new(config, localpeer) {
+                Ok(section) => {
+                    let section = Arc::new(section);
+                    let tasks = section.start(tasks, &store);
+                    self.sections.insert(config.name.clone(), RunningSection { section, tasks });
+                }
+                Err(e) => warn!("{}, state is not synchronized", e),
+            }
+        }
+    }
+
+    pub fn section(&self, name: &str) -> Option<Arc<PeerSection>> {
+        self.sections.get(name).map(|running| Arc::clone(&running.section))
+    }
+}
+
+fn codec() -> LengthDelimitedCodec {
+    LengthDelimitedCodec::builder()
+        .max_frame_length(PEER_MAX_FRAME)
+        .new_codec()
+}
+
+fn hmac_sha256(secret: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut key = [0u8; HMAC_BLOCK_SIZE];
+    if secret.len() > HMAC_BLOCK_SIZE {
+        key[..32].copy_from_slice(&Sha256::digest(secret));
+    } else {
+        key[..secret.len()].copy_from_slice(secret);
+    }
+
+    let inner = Sha256::new()
+        .chain_update(key.map(|byte| byte ^ 0x36))
+        .chain_update(message)
+        .finalize();
+    Sha256::new()
+        .chain_update(key.map(|byte| byte ^ 0x5c))
+        .chain_update(inner)
+        .finalize()
+        .into()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct RecordingStore {
+        snapshot: Vec<PeerUpdate>,
+        applied: Mutex<Vec<(String, PeerUpdate)>>,
+    }
+
+    impl PeerStore for RecordingStore {
+        fn apply(&self, section: &str, update: PeerUpdate) -> Result<bool> {
+            self.applied.lock().unwrap().push((section.to_string(), update));
+            Ok(true)
+        }
+
+        fn snapshot(&self, _section: &str) -> Vec<PeerUpdate> {
+            self.snapshot.clone()
+        }
+    }
+
+    fn free_addr() -> SocketAddr {
+        std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+    }
+
+    fn peers(secret: Option<&str>) -> PeersConfig {
+        PeersConfig {
+            name: "mesh".to_string(),
+            peers: ["a", "b"].iter().map(|name| PeerConfig { name: name.to_string(), address: free_addr() }).collect(),
+            secret: secret.map(str::to_string),
+        }
+    }
+
+    fn server_state(state: &str) -> PeerUpdate {
+        PeerUpdate::ServerState { backend: "app".to_string(), server: "s1".to_string(), state: state.to_string(), updated: 1 }
+    }
+
+    #[test]
+    fn hmac_matches_the_rfc_4231_vectors() {
+        assert_eq!(hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+        assert_eq!(hex(&hmac_sha256(&[0xaa; 131], b"Test Using Larger Than Block-Size Key - Hash Key First")),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54");
+    }
+
+    #[test]
+    fn signed_frames_reject_tampering_and_missing_signatures() {
+        let section = PeerSection::new(&peers(Some("s3cret")), "a").unwrap();
+        let body = serde_json::to_string(&PeerMessage::Update { update: server_state("up") }).unwrap();
+        let mac = hex(&hmac_sha256(b"s3cret", body.as_bytes()));
+        let frame = |body: &str, mac: Option<&str>| {
+            serde_json::to_vec(&PeerFrame { body: body.to_string(), mac: mac.map(str::to_string) }).unwrap()
+        };
+
+        assert!(matches!(section.decode(&frame(&body, Some(&mac))).unwrap(),
+            PeerMessage::Update { update } if update == server_state("up")));
+        let tampered = body.replace("\"up\"", "\"down\"");
+        assert_eq!(section.decode(&frame(&tampered, Some(&mac))).unwrap_err().to_string(), "message signature mismatch");
+        assert_eq!(section.decode(&frame(&body, None)).unwrap_err().to_string(), "unsigned message");
+
+        let open = PeerSection::new(&peers(None), "a").unwrap();
+        assert!(open.decode(&frame(&body, None)).is_ok());
+    }
+
+    #[test]
+    fn updates_serialize_with_a_kebab_case_type() {
+        let update = PeerUpdate::StickForget { backend: "app".to_string(), key: vec![1, 2], updated: 7 };
+        assert_eq!(serde_json::to_string(&update).unwrap(), r#"{"type":"stick-forget","backend":"app","key":[1,2],"updated":7}"#);
+        assert_eq!(update.kind(), "stick-forget");
+        assert_eq!(update.backend(), "app");
+    }
+
+    #[test]
+    fn sections_need_the_local_peer() {
+        let err = PeerSection::new(&peers(None), "c").err().unwrap();
+        assert_eq!(err.to_string(), "Peers 'mesh' has no peer named 'c'");
+    }
+
+    #[tokio::test]
+    async fn peers_resync_and_stream_updates() {
+        let config = peers(Some("s3cret"));
+        let tasks = TaskRegistry::default();
+        let sender_store = Arc::new(RecordingStore { snapshot: vec![server_state("up")], ..RecordingStore::default() });
+        let receiver_store = Arc::new(RecordingStore::default());
+
+        let mut receiver = Peers::default();
+        receiver.reconcile(std::slice::from_ref(&config), "b", &tasks, receiver_store.clone());
+        let mut sender = Peers::default();
+        sender.reconcile(std::slice::from_ref(&config), "a", &tasks, sender_store);
+
+        let received = |count: usize| {
+            let store = Arc::clone(&receiver_store);
+            async move {
+                for _ in 0..200 {
+                    if store.applied.lock().unwrap().len() >= count {
+                        return;
+                    }
+                    tokio::time::sleep(Duration::from_millis(25)).await;
+                }
+                panic!("peer received {:?}", store.applied.lock().unwrap());
+            }
+        };
+        received(1).await;
+        sender.section("mesh").unwrap().publish(server_state("maint"));
+        received(2).await;
+        assert_eq!(*receiver_store.applied.lock().unwrap(), [
+            ("mesh".to_string(), server_state("up")),
+            ("mesh".to_string(), server_state("maint")),
+        ]);
+
+        sender.reconcile(&[], "a", &tasks, Arc::new(RecordingStore::default()));
+        assert!(sender.section("mesh").is_none());
+        tasks.shutdown().await;
+    }
+}
@@ -0,0 +1,104 @@
+use crate::config::BindConfig;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    ProxyProtocol,
+    TlsAccept,
+    Inspect,
+    Route,
+    Forward,
+}
+
+impl Stage {
+    const ORDER: [Stage; 5] = [Stage::ProxyProtocol, Stage::TlsAccept, Stage::Inspect, Stage::Route, Stage::Forward];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Stage::ProxyProtocol => "proxy-protocol",
+            Stage::TlsAccept => "tls",
+            Stage::Inspect => "inspect",
+            Stage::Route => "route",
+            Stage::Forward => "forward",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Pipeline {
+    proxy_protocol: bool,
+    tls: bool,
+    inspect: bool,
+}
+
+impl Pipeline {
+    pub fn for_bind(bind: &BindConfig) -> Self {
+        Self { proxy_protocol: bind.accept_proxy, tls: bind.ssl, inspect: false }
+    }
+
+    pub fn with_inspect(mut self, inspect: bool) -> Self {
+        self.inspect = inspect;
+        self
+    }
+
+    pub fn has(&self, stage: Stage) -> bool {
+        match stage {
+            Stage::ProxyProtocol => self.proxy_protocol,
+            Stage::TlsAccept => self.tls,
+            Stage::Inspect => self.inspect,
+            Stage::Route | Stage::Forward => true,
+        }
+    }
+
+    pub fn stages(&self) -> impl Iterator<Item = Stage> + '_ {
+        Stage::ORDER.into_iter().filter(|stage| self.has(*stage))
+    }
+}
+
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, stage) in self.stages().enumerate() {
+            if i > 0 {
+                f.write_str(" -> ")?;
+            }
+            f.write_str(stage.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn bind(line: &str) -> BindConfig {
+        let config = Config::from_haproxy_config(&format!("frontend web\n    {}\n    default_backend app\n\nbackend app\n    server s1 127.0.0.1:8080\n", line), None).unwrap();
+        config.frontends[0].bind[0].clone()
+    }
+
+    #[test]
+    fn plain_bind_routes_and_forwards() {
+        let pipeline = Pipeline::for_bind(&bind("bind *:8080"));
+        assert_eq!(pipeline.stages().collect::<Vec<_>>(), [Stage::Route, Stage::Forward]);
+        assert_eq!(pipeline.to_string(), "route -> forward");
+    }
+
+    #[test]
+    fn stages_run_in_order() {
+        let pipeline = Pipeline::for_bind(&bind("bind *:8080 accept-proxy")).with_inspect(true);
+        assert_eq!(pipeline.stages().collect::<Vec<_>>(), [Stage::ProxyProtocol, Stage::Inspect, Stage::Route, Stage::Forward]);
+
+        let all = Pipeline { proxy_protocol: true, tls: true, inspect: true };
+        assert_eq!(all.stages().collect::<Vec<_>>(), Stage::ORDER);
+        assert_eq!(all.to_string(), "proxy-protocol -> tls -> inspect -> route -> forward");
+    }
+
+    #[test]
+    fn route_and_forward_cannot_be_skipped() {
+        let pipeline = Pipeline::default();
+        assert!(pipeline.has(Stage::Route) && pipeline.has(Stage::Forward));
+        assert!(!pipeline.has(Stage::ProxyProtocol) && !pipeline.has(Stage::TlsAccept) && !pipeline.has(Stage::Inspect));
+        assert!(!pipeline.with_inspect(true).with_inspect(false).has(Stage::Inspect));
+    }
+}
@@ -1,16 +1,49 @@
-use crate::config::{FrontendConfig, BackendConfig, ServerConfig};
-use crate::logging::{RequestLogger, log_startup_info, log_graceful_shutdown};
-use crate::metrics;
-use crate::health::{HealthChecker, ServerStatus};
-use crate::balancer::BackendLoadBalancer;
-use crate::acl::Acl;
+use crate::config::{BindConfig, Config, FrontendConfig, BackendConfig, GlobalConfig, ServerConfig, SourceSpan, StatsBindConfig};
+use crate::logging::{LogThrottle, RequestLogger, log_ready, log_graceful_shutdown, log_server_drained, log_server_status, log_tarpit};
+use crate::metrics::{self, MetricsHandle};
+use crate::events::{EventBus, ReadyBind, ReadyFrontend, TurbogateEvent};
+use crate::error_log::{ErrorLog, ErrorTarget};
+use crate::health::{HealthManager, ServerStatus};
+use crate::balancer::{AdaptiveMetric, AdaptiveSettings, AdminState, BackendLoadBalancer, EffectiveServerState, ServerState, ServerStateChange, DEFAULT_ADAPTIVE_HALF_LIFE};
+use crate::acl::{AclContext, AclStat, CompiledRouting, RouteTarget};
+use crate::auth::{self, Userlist};
+use crate::admin::{AdminCommand, AdminHandler, AdminServer, MaxconnTarget};
+use crate::limits::{ConnectRate, FairShare, FdLimits, RuntimeLimit, DEFAULT_MAXCONN, DEFAULT_MAX_CHECK_CONN};
+use crate::bwlim::{BandwidthLimits, Throttle};
+use crate::log_format::LogFormat;
+use crate::inspect::{self, NameTemplate};
+use crate::rate_limit::{RateKey, RateLimitKey};
+use crate::resolver::ReverseDns;
+use crate::status::StatusTable;
+use crate::stats::{self, InfoField, ProcessStats, StatKind, StatRow, StatsSource};
+use crate::stick::StickTable;
+use crate::buffer_pool::{BufferPool, PooledBuffer};
+use crate::utils::{self, OriginalDst};
+use crate::session::{self, ConnectFailure, ReplayBuffer, SessionCounters, SessionError, SessionTimeouts, Side};
+use crate::tls::{self, ClientStream, TlsInfo, TlsTerminator};
+use crate::proxy_protocol;
+use crate::geoip::Country;
+use crate::pipeline::{Pipeline, Stage};
+use crate::notify;
+use crate::drain::DrainState;
+use crate::http1::{RequestPolicy, StaticResponse, Verdict};
+use crate::tasks::{TaskGroup, TaskRegistry};
+use crate::peers::{PeerSection, PeerStore, PeerUpdate, Peers};
+use crate::audit::{AuditEntry, AuditLog, AuditSource};
+use crate::hot_reload::HotReload;
+use crate::config_api::{ChangeRequest, ConfigApi, ConfigApiError, ConfigChange};
 use anyhow::{Result, anyhow};
+use async_trait::async_trait;
 use dashmap::DashMap;
-use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, mpsc, Notify};
 use tokio::task;
 use tracing::{info, warn, error, debug};
 use crate::features::FeaturesManager;
@@ -18,432 +51,3601 @@ use crate::features::FeaturesManager;
 pub struct ProxyServer {
     frontends: Arc<DashMap<String, FrontendState>>,
     backends: Arc<DashMap<String, BackendState>>,
-    health_checkers: Arc<DashMap<String, HealthChecker>>,
-    active_connections: Arc<RwLock<HashMap<String, u64>>>,
-    server_statuses: Arc<RwLock<HashMap<String, HashMap<String, ServerStatus>>>>,
+    health_manager: HealthManager,
+    active_connections: Arc<DashMap<String, u64>>,
+    tarpit_connections: Arc<AtomicU32>,
+    server_statuses: Arc<StatusTable>,
+    accept_tasks: Vec<task::AbortHandle>,
+    tasks: Arc<TaskRegistry>,
+    inspect_buffers: InspectBuffers,
+    config: Arc<Config>,
+    config_hash: String,
+    reloaded: broadcast::Sender<Arc<Config>>,
+    config_api: ConfigApi,
+    config_changes: Option<mpsc::Receiver<ChangeRequest>>,
+    peers: Peers,
     features_manager: Arc<FeaturesManager>,
 }
 
-struct FrontendState {
-    config: FrontendConfig,
-    listeners: Vec<Arc<TcpListener>>,
+struct StagedReload {
+    config: Arc<Config>,
+    frontends: Vec<FrontendState>,
+    backends: Vec<BackendState>,
+    health_manager: HealthManager,
+    inspect_buffers: InspectBuffers,
+    bound: Vec<(String, SocketAddr)>,
 }
 
-struct BackendState {
-    config: BackendConfig,
-    load_balancer: BackendLoadBalancer,
+const DEFAULT_INSPECT_TIMEOUT_MS: u64 = 5000;
+const DEFAULT_LISTEN_BACKLOG: i32 = 1024;
+const DEFER_ACCEPT_SECS: u32 = 1;
+const ACCEPT_RESOURCE_BACKOFF: Duration = Duration::from_millis(100);
+const FD_SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+const SERVER_STATE_INTERVAL: Duration = Duration::from_secs(1);
+const ADAPTIVE_WEIGHT_INTERVAL: Duration = Duration::from_secs(2);
+const GEOIP_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+const RELOAD_CHANNEL_CAPACITY: usize = 4;
+const INSPECT_POOL_IDLE: usize = 1024;
+const CONN_RATE_SLOTS: usize = 60;
+const COUNT_MASK: u64 = u32::MAX as u64;
+const DEFAULT_QUEUE_TIMEOUT_MS: u64 = 10000;
+const DEFAULT_WARMUP_TIMEOUT: Duration = Duration::from_secs(5);
+const ORIGINAL_DST_SERVER: &str = "original_dst";
+const CONNECT_DST_SERVER: &str = "connect_dst";
+const HEALTH_RESPONSE: &[u8] = b"OK\n";
+const HEALTH_HTTP_RESPONSE: &[u8] = b"HTTP/1.0 200 OK\r\nCache-Control: no-cache\r\nConnection: close\r\nContent-Type: text/plain\r\n\r\nOK\n";
+const BAD_REQUEST_RESPONSE: &[u8] = b"HTTP/1.0 400 Bad Request\r\nCache-Control: no-cache\r\nConnection: close\r\nContent-Type: text/html\r\n\r\n<html><body><h1>400 Bad Request</h1>\nYour browser sent an invalid request.\n</body></html>\n";
+const FULLCONN_RESPONSE: &[u8] = b"HTTP/1.0 503 Service Unavailable\r\nCache-Control: no-cache\r\nConnection: close\r\nContent-Type: text/html\r\n\r\n<html><body><h1>503 Service Unavailable</h1>\nNo server is available to handle this request.\n</body></html>\n";
+const FORBIDDEN_RESPONSE: &[u8] = b"HTTP/1.0 403 Forbidden\r\nCache-Control: no-cache\r\nConnection: close\r\nContent-Type: text/html\r\n\r\n<html><body><h1>403 Forbidden</h1>\nRequest forbidden by administrative rules.\n</body></html>\n";
+const METHOD_NOT_ALLOWED_RESPONSE: &[u8] = b"HTTP/1.0 405 Method Not Allowed\r\nCache-Control: no-cache\r\nConnection: close\r\nAllow: CONNECT\r\nContent-Type: text/html\r\n\r\n<html><body><h1>405 Method Not Allowed</h1>\nThis proxy only accepts CONNECT requests.\n</body></html>\n";
+const CONNECT_ESTABLISHED_RESPONSE: &[u8] = b"HTTP/1.1 200 Connection Established\r\n\r\n";
+
+// Each slot packs the second it counts (high half) with its count (low half), so
+// moving a slot to a new second and counting an accept are one atomic step.
+struct ConnRateTracker {
+    started: Instant,
+    slots: [AtomicU64; CONN_RATE_SLOTS],
 }
 
-impl ProxyServer {
-    pub fn new(features_manager: Arc<FeaturesManager>) -> Self {
+impl ConnRateTracker {
+    fn new() -> Self {
         Self {
-            frontends: Arc::new(DashMap::new()),
-            backends: Arc::new(DashMap::new()),
-            health_checkers: Arc::new(DashMap::new()),
-            active_connections: Arc::new(RwLock::new(HashMap::new())),
-            server_statuses: Arc::new(RwLock::new(HashMap::new())),
-            features_manager,
+            started: Instant::now(),
+            slots: std::array::from_fn(|_| AtomicU64::new(u64::MAX)),
         }
     }
 
-    pub async fn run(&mut self) -> Result<()> {
-        self.initialize_frontends().await?;
-        self.initialize_backends().await?;
-        
-        self.start_health_checkers().await;
+    fn now(&self) -> u64 {
+        self.started.elapsed().as_secs()
+    }
 
-        let bind_addresses: Vec<String> = self.features_manager.config.frontends.iter()
-            .flat_map(|f| f.bind.clone())
-            .collect();
-        log_startup_info("0.1.0", "turbogate.toml", bind_addresses);
+    // Seconds wrap well before the initial u64::MAX stamp could be reached.
+    fn stamp(second: u64) -> u64 {
+        (second % COUNT_MASK) << 32
+    }
 
-        let mut shutdown_signal = Self::setup_shutdown_signal();
+    fn record(&self) {
+        self.record_at(self.now());
+    }
 
-        let ddos_reset_task = {
-            let features_manager = Arc::clone(&self.features_manager);
-            task::spawn(async move {
-                loop {
-                    let interval = {
-                        if let Some(ddos) = &features_manager.ddos_protection {
-                            let val = ddos.reset_interval_seconds();
-                            if val < 1 { 60 } else { val }
-                        } else {
-                            60
-                        }
-                    };
-                    info!("DDoS: ожидаю {} секунд до следующего сброса счетчиков", interval);
-                    tokio::time::sleep(std::time::Duration::from_secs(interval)).await;
-                    if let Some(ddos) = &features_manager.ddos_protection {
-                        ddos.reset_counters();
-                        info!("DDoS: счетчики сброшены (reset_counters)");
-                    }
-                }
+    fn record_at(&self, second: u64) {
+        let stamp = Self::stamp(second);
+        let slot = &self.slots[second as usize % CONN_RATE_SLOTS];
+        let _ = slot.fetch_update(Ordering::AcqRel, Ordering::Acquire, |packed| {
+            Some(match packed & !COUNT_MASK == stamp {
+                true if packed & COUNT_MASK == COUNT_MASK => packed,
+                true => packed + 1,
+                false => stamp | 1,
             })
-        };
+        });
+    }
 
-        let mut frontend_tasks = Vec::new();
-        for frontend_name in self.frontends.iter().map(|f| f.key().clone()) {
-            let frontends = Arc::clone(&self.frontends);
-            let backends = Arc::clone(&self.backends);
-            let active_connections = Arc::clone(&self.active_connections);
-            let server_statuses = Arc::clone(&self.server_statuses);
-            let features_manager = Arc::clone(&self.features_manager);
-            
-            let task = task::spawn(async move {
-                if let Err(e) = Self::run_frontend(frontend_name, frontends, backends, active_connections, server_statuses, features_manager).await {
-                    error!("Error running frontend: {}", e);
-                }
-            });
-            frontend_tasks.push(task);
+    fn count_at(&self, second: u64) -> u64 {
+        let packed = self.slots[second as usize % CONN_RATE_SLOTS].load(Ordering::Acquire);
+        match packed & !COUNT_MASK == Self::stamp(second) {
+            true => packed & COUNT_MASK,
+            false => 0,
         }
+    }
 
-        shutdown_signal.recv().await;
-        
-        let active_conns = self.active_connections.read().await.values().sum();
-        log_graceful_shutdown(active_conns);
-        
-        ddos_reset_task.abort();
-        for task in frontend_tasks {
-            task.abort();
-        }
+    fn current(&self) -> u64 {
+        self.count_at(self.now())
+    }
 
-        info!("Proxy server stopped");
-        Ok(())
+    fn rate(&self) -> u64 {
+        self.now().checked_sub(1).map(|second| self.count_at(second)).unwrap_or(0)
     }
 
-    async fn initialize_frontends(&mut self) -> Result<()> {
-        for frontend_config in &self.features_manager.config.frontends {
-            let mut listeners = Vec::new();
-            
-            for bind_addr in &frontend_config.bind {
-                let addr: SocketAddr = bind_addr.parse()?;
-                let listener = TcpListener::bind(addr).await?;
-                listeners.push(Arc::new(listener));
-                info!("Frontend '{}' listening on {}", frontend_config.name, bind_addr);
+    fn rate_max(&self) -> u64 {
+        let now = self.now();
+        (now.saturating_sub(CONN_RATE_SLOTS as u64 - 1)..=now)
+            .map(|second| self.count_at(second))
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn until_next_second(&self) -> Duration {
+        Duration::from_secs(1) - Duration::from_nanos(self.started.elapsed().subsec_nanos() as u64)
+    }
+}
+
+struct FrontendRates {
+    connections: ConnRateTracker,
+    refused: ConnRateTracker,
+}
+
+#[derive(Debug, Clone)]
+struct ConnectionContext {
+    client_addr: SocketAddr,
+    local_addr: Option<SocketAddr>,
+    original_dst: Option<SocketAddr>,
+    listener: String,
+    tls: Option<TlsTerminator>,
+    tls_info: Option<TlsInfo>,
+    src_dn: Option<String>,
+    country: Country,
+    pipeline: Pipeline,
+}
+
+impl ConnectionContext {
+    fn new<S: OriginalDst>(stream: &S, client_addr: SocketAddr, local_addr: Option<SocketAddr>, transparent: bool, listener: &str) -> Self {
+        let original_dst = if transparent {
+            match stream.original_dst() {
+                Ok(original_dst) => Some(original_dst),
+                Err(e) => {
+                    debug!("No original destination for client {}: {}", client_addr, e);
+                    None
+                }
             }
+        } else {
+            None
+        };
 
-            let frontend_state = FrontendState {
-                config: frontend_config.clone(),
-                listeners,
-            };
+        Self { client_addr, local_addr, original_dst, listener: listener.to_string(), tls: None, tls_info: None, src_dn: None, country: Country::UNKNOWN, pipeline: Pipeline::default() }
+    }
 
-            self.frontends.insert(frontend_config.name.clone(), frontend_state);
-        }
+    fn with_tls(mut self, tls: Option<TlsTerminator>) -> Self {
+        self.tls = tls;
+        self
+    }
 
-        Ok(())
+    fn with_pipeline(mut self, pipeline: Pipeline) -> Self {
+        self.pipeline = pipeline;
+        self
     }
 
-    async fn initialize_backends(&mut self) -> Result<()> {
-        for backend_config in &self.features_manager.config.backends {
-            let algorithm = backend_config.balance.as_deref().unwrap_or("roundrobin");
-            let load_balancer = BackendLoadBalancer::new(backend_config.server.clone(), algorithm)?;
-            
-            let backend_state = BackendState {
-                config: backend_config.clone(),
-                load_balancer,
-            };
+    fn with_country(mut self, country: Country) -> Self {
+        self.country = country;
+        self
+    }
 
-            self.backends.insert(backend_config.name.clone(), backend_state);
-        }
+    fn destination(&self) -> Option<SocketAddr> {
+        self.original_dst.or(self.local_addr)
+    }
 
-        Ok(())
+    fn acl_context<'a>(&'a self, payload: &'a [u8]) -> AclContext<'a> {
+        AclContext::with_payload(self.client_addr, payload)
+            .with_destination(self.destination())
+            .with_tls(self.tls.is_some(), self.tls_info.as_ref().and_then(|info| info.alpn.as_deref()))
+            .with_sni(self.tls_info.as_ref().and_then(|info| info.sni.as_deref()))
+            .with_src_dn(self.src_dn.as_deref())
+            .with_country(self.country)
     }
 
-    async fn start_health_checkers(&self) {
-        for backend_config in &self.features_manager.config.backends {
-            if backend_config.health_check.is_some() {
-                let health_checker = HealthChecker::new(backend_config.clone());
-                self.health_checkers.insert(backend_config.name.clone(), health_checker);
-            }
-        }
+    fn original_dst_server(&self) -> Result<ServerConfig> {
+        let original_dst = self.original_dst
+            .filter(|original_dst| Some(*original_dst) != self.local_addr)
+            .ok_or_else(|| anyhow!("No original destination for client {}", self.client_addr))?;
+        Ok(direct_server(ORIGINAL_DST_SERVER, &original_dst.ip().to_string(), original_dst.port()))
+    }
+}
 
-        for health_checker in self.health_checkers.iter() {
-            health_checker.value().start().await;
-        }
+struct HttpRequestPolicy {
+    frontend: String,
+    connection: ConnectionContext,
+    routing: Arc<CompiledRouting>,
+    accept_invalid: bool,
+    log_throttle: Arc<LogThrottle>,
+}
+
+impl HttpRequestPolicy {
+    fn validate(&self, head: &mut Vec<u8>) -> bool {
+        validate_http_head(&self.frontend, self.connection.client_addr, self.accept_invalid, &self.log_throttle, head)
     }
+}
 
-    async fn run_frontend(
-        frontend_name: String,
-        frontends: Arc<DashMap<String, FrontendState>>,
-        backends: Arc<DashMap<String, BackendState>>,
-        active_connections: Arc<RwLock<HashMap<String, u64>>>,
-        server_statuses: Arc<RwLock<HashMap<String, HashMap<String, ServerStatus>>>>,
-        features_manager: Arc<FeaturesManager>,
-    ) -> Result<()> {
-        if let Some(frontend_state) = frontends.get(&frontend_name) {
-            for listener in &frontend_state.listeners {
-                let listener = Arc::clone(listener);
-                let frontend_name = frontend_name.clone();
-                let frontends = Arc::clone(&frontends);
-                let backends = Arc::clone(&backends);
-                let active_connections = Arc::clone(&active_connections);
-                let server_statuses = Arc::clone(&server_statuses);
-                let features_manager = Arc::clone(&features_manager);
-                
-                task::spawn(async move {
-                    if let Err(e) = Self::accept_connections(
-                        &listener,
-                        &frontend_name,
-                        frontends,
-                        backends,
-                        active_connections,
-                        server_statuses,
-                        features_manager,
-                    ).await {
-                        error!("Error accepting connections on frontend {}: {}", frontend_name, e);
-                    }
-                });
+impl RequestPolicy for HttpRequestPolicy {
+    fn check(&self, head: &mut Vec<u8>) -> Verdict {
+        if !self.validate(head) {
+            return Verdict::Reject(BAD_REQUEST_RESPONSE.to_vec());
+        }
+        let acl_context = self.connection.acl_context(head);
+        if let Some(realm) = ProxyServer::auth_challenge(&self.frontend, &self.routing, &acl_context, &self.log_throttle) {
+            debug!("Client {} must authenticate on frontend {}", self.connection.client_addr, self.frontend);
+            metrics::http_auth_challenge(&self.frontend);
+            return Verdict::Reject(auth::challenge("401 Unauthorized", "WWW-Authenticate", realm,
+                                                   "You need a valid user and password to access this content."));
+        }
+        match ProxyServer::http_return(&self.frontend, &self.routing, &acl_context, &self.log_throttle) {
+            Some(response) => {
+                metrics::http_return(&self.frontend, &self.frontend, response.status(), 1);
+                Verdict::Respond(Arc::clone(response))
             }
+            None => Verdict::Forward,
         }
+    }
 
-        Ok(())
+    fn malformed(&self) -> Verdict {
+        if self.accept_invalid {
+            return Verdict::Forward;
+        }
+        if self.log_throttle.admit("invalid_http_request", &self.frontend) {
+            warn!("Client {} sent a request that cannot be framed on frontend {}, rejecting", self.connection.client_addr, self.frontend);
+        }
+        metrics::http_invalid_request(&self.frontend, "bad_framing", "rejected");
+        Verdict::Reject(BAD_REQUEST_RESPONSE.to_vec())
     }
+}
 
-    async fn accept_connections(
-        listener: &TcpListener,
-        frontend_name: &str,
-        frontends: Arc<DashMap<String, FrontendState>>,
-        backends: Arc<DashMap<String, BackendState>>,
-        active_connections: Arc<RwLock<HashMap<String, u64>>>,
-        server_statuses: Arc<RwLock<HashMap<String, HashMap<String, ServerStatus>>>>,
-        features_manager: Arc<FeaturesManager>,
-    ) -> Result<()> {
-        loop {
-            let (client_stream, client_addr) = listener.accept().await?;
-            
-            let max_connections = features_manager.config.global.maxconn.unwrap_or(4096);
-            let current_connections = {
-                let conns = active_connections.read().await;
-                conns.values().sum::<u64>()
-            };
-            
-            if current_connections >= max_connections as u64 {
-                warn!("Max connections limit reached: {} >= {}", current_connections, max_connections);
-                metrics::connection_error(frontend_name, "maxconn_limit");
-                continue;
+fn validate_http_head(frontend_name: &str, client_addr: SocketAddr, accept_invalid: bool, log_throttle: &LogThrottle, head: &mut Vec<u8>) -> bool {
+    match inspect::validate_http_request(head) {
+        Ok(()) => {
+            if let Some(normalized) = inspect::normalize_request_head(head) {
+                *head = normalized;
+            }
+            true
+        }
+        Err(violation) if accept_invalid => {
+            if log_throttle.admit("invalid_http_request", frontend_name) {
+                warn!("Client {} sent an invalid HTTP request on frontend {} ({}), forwarding it as is",
+                      client_addr, frontend_name, violation.as_str());
             }
-            
-            {
-                let mut conns = active_connections.write().await;
-                *conns.entry(frontend_name.to_string()).or_insert(0) += 1;
+            metrics::http_invalid_request(frontend_name, violation.as_str(), "accepted");
+            true
+        }
+        Err(violation) => {
+            if log_throttle.admit("invalid_http_request", frontend_name) {
+                warn!("Client {} sent an invalid HTTP request on frontend {} ({}), rejecting",
+                      client_addr, frontend_name, violation.as_str());
             }
+            metrics::http_invalid_request(frontend_name, violation.as_str(), "rejected");
+            false
+        }
+    }
+}
 
-            let frontend_name = frontend_name.to_string();
-            let frontends = Arc::clone(&frontends);
-            let backends = Arc::clone(&backends);
-            let active_connections = Arc::clone(&active_connections);
-            let server_statuses = Arc::clone(&server_statuses);
-            let features_manager = Arc::clone(&features_manager);
-            
-            let handle_timeout = std::time::Duration::from_secs(30);
+fn direct_server(name: &str, host: &str, port: u16) -> ServerConfig {
+    let address = match host.parse::<std::net::Ipv6Addr>() {
+        Ok(ip) => format!("[{}]", ip),
+        Err(_) => host.to_string(),
+    };
 
-            task::spawn(async move {
-                match tokio::time::timeout(handle_timeout, Self::handle_connection(
-                    client_stream,
-                    client_addr,
-                    &frontend_name,
-                    frontends,
-                    backends,
-                    server_statuses,
-                    features_manager,
-                )).await {
-                    Ok(Ok(())) => {
-                        debug!("Connection from {} handled successfully", client_addr);
-                    }
-                    Ok(Err(e)) => {
-                        error!("Error handling connection from {}: {}", client_addr, e);
-                        metrics::connection_error(&frontend_name, "handle_error");
-                    }
-                    Err(_) => {
-                        error!("Connection from {} timed out after {:?}", client_addr, handle_timeout);
-                        metrics::connection_error(&frontend_name, "handle_timeout");
-                    }
-                }
+    ServerConfig {
+        id: None,
+        explicit_id: false,
+        name: name.to_string(),
+        address,
+        port: Some(port),
+        weight: None,
+        maxconn: None,
+        check: None,
+        inter: None,
+        fastinter: None,
+        downinter: None,
+        check_timeout: None,
+        rise: None,
+        fall: None,
+        backup: None,
+        tier: None,
+        disabled: None,
+        tfo: None,
+        resolvers: None,
+        span: SourceSpan::default(),
+    }
+}
 
-                {
-                    let mut conns = active_connections.write().await;
-                    if let Some(count) = conns.get_mut(&frontend_name) {
-                        *count = count.saturating_sub(1);
-                    }
-                }
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RoutingRule {
+    UseBackend(usize),
+    DefaultBackend,
+}
 
-                metrics::connection_closed(&frontend_name);
-            });
+impl RoutingRule {
+    fn label(&self) -> String {
+        match self {
+            RoutingRule::UseBackend(index) => (index + 1).to_string(),
+            RoutingRule::DefaultBackend => "default_backend".to_string(),
         }
     }
+}
 
-    async fn handle_connection(
-        client_stream: TcpStream,
-        client_addr: SocketAddr,
-        frontend_name: &str,
-        frontends: Arc<DashMap<String, FrontendState>>,
-        backends: Arc<DashMap<String, BackendState>>,
-        server_statuses: Arc<RwLock<HashMap<String, HashMap<String, ServerStatus>>>>,
-        features_manager: Arc<FeaturesManager>,
-    ) -> Result<()> {
-        if let Some(rate_limiter) = &features_manager.rate_limiter {
-            if !rate_limiter.check_rate_limit(client_addr.ip()) {
-                warn!("Rate limit exceeded for client {} on frontend {}", client_addr.ip(), frontend_name);
-                metrics::connection_error(frontend_name, "rate_limit_exceeded");
-                return Err(anyhow!("Rate limit exceeded"));
-            }
-        }
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct RetryPolicy {
+    retries: u32,
+    redispatch: Option<i32>,
+    conn_failure: bool,
+}
 
-        if let Some(ddos_protection) = &features_manager.ddos_protection {
-            if !ddos_protection.check_connection_limit(client_addr.ip()) {
-                warn!("DDoS protection: connection limit exceeded for client {} on frontend {}", client_addr.ip(), frontend_name);
-                metrics::connection_error(frontend_name, "ddos_connection_limit");
-                return Err(anyhow!("DDoS protection: connection limit exceeded"));
-            }
+impl RetryPolicy {
+    fn new(config: &BackendConfig) -> Self {
+        Self {
+            retries: config.retries.unwrap_or(0),
+            redispatch: config.options.as_ref().and_then(|options| options.general_options.redispatch),
+            conn_failure: config.retry_on_conn_failure,
         }
+    }
 
-        let frontend_config = if let Some(frontend_state) = frontends.get(frontend_name) {
-            frontend_state.config.clone()
-        } else {
-            return Err(anyhow!("Frontend '{}' not found", frontend_name));
-        };
+    fn redispatch_on(&self, attempt: u32) -> bool {
+        match self.redispatch {
+            Some(interval) if interval > 0 => attempt.is_multiple_of(interval as u32),
+            Some(interval) => i64::from(attempt) == (i64::from(self.retries) + 1 + i64::from(interval)).max(1),
+            None => false,
+        }
+    }
+}
 
-        let backend_name = Self::select_backend(&frontend_config, client_addr)?;
-        
-        let mut backend_state = if let Some(backend) = backends.get_mut(&backend_name) {
-            backend
-        } else {
-            return Err(anyhow!("Backend '{}' not found", backend_name));
-        };
+enum ConnectionAction {
+    Accept,
+    Reject,
+    Tarpit(Duration, Arc<AtomicU32>),
+}
 
-        let server = Self::select_server(&mut backend_state, &server_statuses).await?;
-        
-        let start_time = std::time::Instant::now();
-        let logger = RequestLogger::new(
-            client_addr.ip().to_string(),
-            backend_name.clone(),
-            server.name.clone(),
-        );
+#[derive(Default)]
+struct ListenerCounters {
+    current: AtomicU32,
+    total: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+}
 
-        logger.log_request_start();
-        metrics::request_started(&backend_name, &server.name);
+#[derive(Debug, Clone, PartialEq)]
+enum ConnectionState {
+    Accepted,
+    Routed { backend: String },
+    Proxied { backend: String, server: String, server_id: u32, started: Instant },
+    Closed,
+    Rejected(&'static str),
+}
 
-        if let Some(ddos_protection) = &features_manager.ddos_protection {
-            if !ddos_protection.check_rate_limit(client_addr.ip()) {
-                warn!("DDoS protection: rate limit exceeded for client {} on frontend {}", client_addr.ip(), frontend_name);
-                metrics::connection_error(frontend_name, "ddos_rate_limit");
-                return Err(anyhow!("DDoS protection: rate limit exceeded"));
-            }
+impl ConnectionState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ConnectionState::Accepted => "accepted",
+            ConnectionState::Routed { .. } => "routed",
+            ConnectionState::Proxied { .. } => "proxied",
+            ConnectionState::Closed => "closed",
+            ConnectionState::Rejected(_) => "rejected",
         }
+    }
+}
 
-        match Self::proxy_connection(client_stream, &server).await {
-            Ok(()) => {
-                let duration = start_time.elapsed();
-                logger.log_request_end("success", 0);
-                metrics::request_completed(&backend_name, &server.name, "success", duration.as_millis() as u64);
-                
-                if let Some(ddos_protection) = &features_manager.ddos_protection {
-                    ddos_protection.connection_closed(client_addr.ip());
-                }
-                
-                Ok(())
-            }
-            Err(e) => {
-                logger.log_request_end("failure", 0);
-                metrics::request_failed(&backend_name, &server.name, "connection_failed");
-                
-                if let Some(ddos_protection) = &features_manager.ddos_protection {
-                    ddos_protection.connection_closed(client_addr.ip());
-                }
-                
-                Err(e)
-            }
+struct ConnectionGuard {
+    frontend: String,
+    listener: String,
+    client_addr: SocketAddr,
+    state: ConnectionState,
+    ddos_ip: Option<IpAddr>,
+    active_connections: Arc<DashMap<String, u64>>,
+    listener_counters: Arc<ListenerCounters>,
+    features_manager: Arc<FeaturesManager>,
+    accepted: Instant,
+    fair_share: Option<FairShare>,
+}
+
+impl ConnectionGuard {
+    fn accept(
+        frontend: &str,
+        listener: &FrontendListener,
+        client_addr: SocketAddr,
+        active_connections: Arc<DashMap<String, u64>>,
+        features_manager: Arc<FeaturesManager>,
+    ) -> Self {
+        *active_connections.entry(frontend.to_string()).or_insert(0) += 1;
+        listener.counters.current.fetch_add(1, Ordering::Relaxed);
+        features_manager.process.session_opened();
+        metrics::connection_opened(frontend, &listener.name);
+        debug!("Connection from {} accepted on frontend {} listener {}", client_addr, frontend, listener.name);
+
+        Self {
+            frontend: frontend.to_string(),
+            listener: listener.name.clone(),
+            client_addr,
+            state: ConnectionState::Accepted,
+            ddos_ip: None,
+            active_connections,
+            listener_counters: Arc::clone(&listener.counters),
+            features_manager,
+            accepted: Instant::now(),
+            fair_share: None,
         }
     }
 
-    fn select_backend(frontend_config: &FrontendConfig, client_addr: SocketAddr) -> Result<String> {
-        if let Some(ref backend_name) = frontend_config.default_backend {
-            return Ok(backend_name.clone());
-        }
+    fn hold_fair_share(&mut self, fair_share: Option<FairShare>) {
+        self.fair_share = fair_share;
+    }
 
-        for use_backend in &frontend_config.use_backend {
-            if let Some(ref condition) = use_backend.condition {
-                if Self::evaluate_acl_condition(condition, client_addr)? {
-                    return Ok(use_backend.backend.clone());
-                }
-            }
-        }
+    fn accepted(&self) -> Instant {
+        self.accepted
+    }
 
-        Err(anyhow!("No backend selected for frontend"))
+    fn frontend(&self) -> &str {
+        &self.frontend
     }
 
-    async fn select_server(
-        backend_state: &mut BackendState,
-        server_statuses: &Arc<RwLock<HashMap<String, HashMap<String, ServerStatus>>>>,
-    ) -> Result<ServerConfig> {
-        let statuses = server_statuses.read().await;
-        let backend_statuses = statuses.get(&backend_state.config.name);
-        
-        let available_servers: Vec<&ServerConfig> = backend_state.config.server.iter()
-            .filter(|server| {
-                if server.disabled.unwrap_or(false) {
-                    return false;
-                }
-                
-                if let Some(ref statuses) = backend_statuses {
-                    if let Some(status) = statuses.get(&server.name) {
-                        return status == &ServerStatus::Up;
-                    }
-                }
-                
-                true
-            })
-            .collect();
+    fn track_ddos(&mut self, ip: IpAddr) {
+        self.ddos_ip = Some(ip);
+    }
 
-        if available_servers.is_empty() {
-            return Err(anyhow!("No healthy servers available"));
-        }
+    fn routed(&mut self, backend: &str, rule: &str) {
+        metrics::routing_rule_match(&self.frontend, rule, backend);
+        debug!("Connection from {} on frontend {} routed to backend {} by rule {}", self.client_addr, self.frontend, backend, rule);
+        self.state = ConnectionState::Routed { backend: backend.to_string() };
+    }
 
-        let selected_server = backend_state.load_balancer.select_server()?;
-        if let Some(server_state) = selected_server {
-            Ok(server_state.config.clone())
-        } else {
-            Err(anyhow!("No server selected by load balancer"))
-        }
+    fn proxied(&mut self, server: &ServerConfig) {
+        let ConnectionState::Routed { ref backend } = self.state else {
+            return;
+        };
+        let backend = backend.clone();
+        let server_id = server.id.unwrap_or(0);
+        metrics::request_started(&backend, &server.name, server_id);
+        debug!("Connection from {} on frontend {} proxied to {}/{}", self.client_addr, self.frontend, backend, server.name);
+        self.state = ConnectionState::Proxied { backend, server: server.name.clone(), server_id, started: Instant::now() };
     }
 
-    async fn proxy_connection(client_stream: TcpStream, server: &ServerConfig) -> Result<()> {
-        let server_addr = format!("{}:{}", server.address, server.port);
-        let server_stream = TcpStream::connect(&server_addr).await?;
+    fn redispatched(&mut self, next: &ServerConfig) {
+        let ConnectionState::Proxied { ref backend, ref mut server, ref mut server_id, .. } = self.state else {
+            return;
+        };
+        let next_id = next.id.unwrap_or(0);
+        metrics::request_moved(backend, server, *server_id, &next.name, next_id);
+        debug!("Connection from {} on frontend {} moved from {}/{} to {}/{}", self.client_addr, self.frontend, backend, server, backend, next.name);
+        *server = next.name.clone();
+        *server_id = next_id;
+    }
 
-        let (mut client_read, mut client_write) = client_stream.into_split();
-        let (mut server_read, mut server_write) = server_stream.into_split();
+    fn proxied_for(&self) -> Option<Duration> {
+        match self.state {
+            ConnectionState::Proxied { started, .. } => Some(started.elapsed()),
+            _ => None,
+        }
+    }
 
-        let client_to_server = tokio::io::copy(&mut client_read, &mut server_write);
-        let server_to_client = tokio::io::copy(&mut server_read, &mut client_write);
+    fn transferred(&self, counters: &SessionCounters) {
+        self.listener_counters.bytes_in.fetch_add(counters.bytes_in(), Ordering::Relaxed);
+        self.listener_counters.bytes_out.fetch_add(counters.bytes_out(), Ordering::Relaxed);
+    }
 
-        tokio::select! {
-            result = client_to_server => {
-                if let Err(e) = result {
-                    return Err(anyhow!("Client to server error: {}", e));
-                }
-            }
-            result = server_to_client => {
-                if let Err(e) = result {
-                    return Err(anyhow!("Server to client error: {}", e));
-                }
-            }
+    fn completed(&mut self) {
+        if let ConnectionState::Proxied { ref backend, ref server, server_id, started } = self.state {
+            metrics::request_completed(backend, server, server_id, "success", started.elapsed().as_millis() as u64);
         }
+        self.state = ConnectionState::Closed;
+    }
 
-        Ok(())
+    fn reject(&mut self, reason: &'static str) {
+        match self.state {
+            ConnectionState::Rejected(_) | ConnectionState::Closed => return,
+            ConnectionState::Proxied { ref backend, ref server, server_id, .. } => {
+                metrics::request_failed(backend, server, server_id, reason);
+            }
+            ConnectionState::Accepted | ConnectionState::Routed { .. } => {}
+        }
+        metrics::connection_error(&self.frontend, &self.listener, reason);
+        debug!("Connection from {} on frontend {} rejected after {}: {}", self.client_addr, self.frontend, self.state.as_str(), reason);
+        self.state = ConnectionState::Rejected(reason);
     }
+}
 
-    fn evaluate_acl_condition(condition: &str, client_addr: SocketAddr) -> Result<bool> {
-        let acl_config = crate::config::AclConfig {
-            name: "temp".to_string(),
-            criterion: condition.to_string(),
-        };
-        let acl = Acl::from_config(&acl_config)?;
-        acl.evaluate(client_addr)
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        if let ConnectionState::Proxied { ref backend, ref server, server_id, .. } = self.state {
+            metrics::request_failed(backend, server, server_id, "aborted");
+        }
+        if let Some(mut count) = self.active_connections.get_mut(&self.frontend) {
+            *count = count.saturating_sub(1);
+        }
+        if let (Some(ip), Some(ddos_protection)) = (self.ddos_ip, &self.features_manager.ddos_protection) {
+            ddos_protection.connection_closed(ip);
+        }
+        self.listener_counters.current.fetch_sub(1, Ordering::Relaxed);
+        self.features_manager.process.session_closed();
+        metrics::connection_closed(&self.frontend, &self.listener);
+        debug!("Connection from {} on frontend {} closed in state {}", self.client_addr, self.frontend, self.state.as_str());
     }
+}
 
-    fn setup_shutdown_signal() -> tokio::signal::unix::Signal {
-        use tokio::signal::unix::{signal, SignalKind};
-        signal(SignalKind::terminate()).expect("Failed to create signal handler")
+#[derive(Clone)]
+struct FrontendListener {
+    name: String,
+    socket: Arc<TcpListener>,
+    counters: Arc<ListenerCounters>,
+    tls: Option<TlsTerminator>,
+    pipeline: Pipeline,
+    tfo: bool,
+    defer_accept: bool,
+}
+
+struct FrontendState {
+    index: usize,
+    config: FrontendConfig,
+    routing: Arc<CompiledRouting>,
+    listeners: Vec<FrontendListener>,
+    maxconn: Arc<RuntimeLimit>,
+    bwlim: BandwidthLimits,
+    log_format: Option<Arc<LogFormat>>,
+    tarpit_connections: Arc<AtomicU32>,
+    rates: Arc<FrontendRates>,
+    payload_inspection: Option<PayloadInspection>,
+    request_timeout: Option<Duration>,
+    inspect_buffers: InspectBuffers,
+    connect_auth: Option<Arc<Userlist>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum InspectOverflow {
+    Route,
+    Reject,
+}
+
+#[derive(Clone)]
+struct InspectBuffers {
+    pool: Arc<BufferPool>,
+    overflow: InspectOverflow,
+}
+
+impl InspectBuffers {
+    fn new(global: &GlobalConfig, current: Option<&InspectBuffers>) -> Self {
+        let bufsize = global.tune_inspect_bufsize.unwrap_or(session::DEFAULT_INSPECT_BUFSIZE);
+        let pool = current
+            .filter(|current| current.pool.capacity() == bufsize)
+            .map(|current| Arc::clone(&current.pool))
+            .unwrap_or_else(|| Arc::new(BufferPool::new(bufsize, INSPECT_POOL_IDLE)));
+        let overflow = match global.tune_inspect_overflow.as_deref() {
+            Some("reject") => InspectOverflow::Reject,
+            _ => InspectOverflow::Route,
+        };
+
+        Self { pool, overflow }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PayloadInspection {
+    delay: Duration,
+    needed: usize,
+    until_headers: bool,
+    until_client_hello: bool,
+}
+
+fn report_admin_state(events: &EventBus, backend: &str, server: &ServerState, previous: Option<AdminState>) {
+    let state = server.admin_state;
+    if (previous == Some(AdminState::Maint)) != (state == AdminState::Maint) {
+        events.publish(TurbogateEvent::ServerMaintenance {
+            backend: backend.to_string(),
+            server: server.config.name.clone(),
+            maintenance: state == AdminState::Maint,
+        });
+    }
+    metrics::server_admin_state(backend, &server.config.name, server.id, state.as_str());
+    if state == AdminState::Drain && server.active_connections == 0 {
+        log_server_drained(backend, &server.config.name);
+    }
+}
+
+fn classify_accept_error(error: &std::io::Error) -> (&'static str, Option<Duration>) {
+    match error.raw_os_error() {
+        Some(libc::EMFILE) => ("EMFILE", Some(ACCEPT_RESOURCE_BACKOFF)),
+        Some(libc::ENFILE) => ("ENFILE", Some(ACCEPT_RESOURCE_BACKOFF)),
+        Some(libc::ENOBUFS) => ("ENOBUFS", Some(ACCEPT_RESOURCE_BACKOFF)),
+        Some(libc::ENOMEM) => ("ENOMEM", Some(ACCEPT_RESOURCE_BACKOFF)),
+        Some(libc::ECONNABORTED) => ("ECONNABORTED", Some(Duration::ZERO)),
+        Some(libc::EINTR) => ("EINTR", Some(Duration::ZERO)),
+        Some(libc::EPROTO) => ("EPROTO", Some(Duration::ZERO)),
+        Some(libc::EPERM) => ("EPERM", Some(Duration::ZERO)),
+        _ => match error.kind() {
+            std::io::ErrorKind::ConnectionAborted => ("ECONNABORTED", Some(Duration::ZERO)),
+            std::io::ErrorKind::Interrupted => ("EINTR", Some(Duration::ZERO)),
+            _ => ("other", None),
+        },
+    }
+}
+
+// Accepts the next connection. Recoverable errors are counted and retried
+// after their backoff; an unrecoverable one is published and returned.
+async fn accept_retrying<T, F, Fut>(mut accept: F, frontend_name: &str, listener_name: &str,
+                                    log_throttle: &LogThrottle, events: &EventBus) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = std::io::Result<T>>,
+{
+    loop {
+        let e = match accept().await {
+            Ok(accepted) => return Ok(accepted),
+            Err(e) => e,
+        };
+        let (errno, backoff) = classify_accept_error(&e);
+        metrics::accept_error(frontend_name, listener_name, errno);
+        let Some(backoff) = backoff else {
+            events.publish(TurbogateEvent::ListenerFailed {
+                frontend: frontend_name.to_string(),
+                address: listener_name.to_string(),
+                error: e.to_string(),
+            });
+            return Err(anyhow!("Listener {} stopped accepting: {}", listener_name, e));
+        };
+        if log_throttle.admit(errno, frontend_name) {
+            warn!("Accept failed on frontend {} listener {}: {}; retrying in {:?}", frontend_name, listener_name, e, backoff);
+        }
+        tokio::time::sleep(backoff).await;
+    }
+}
+
+type ResponseInspector<'a> = &'a (dyn Fn(&[u8]) + Sync);
+
+struct BackendState {
+    index: usize,
+    config: BackendConfig,
+    load_balancer: BackendLoadBalancer,
+    stick_table: Option<StickTable>,
+    sessions: Arc<BackendSessions>,
+    health_response: Option<&'static [u8]>,
+    http_return: Option<Arc<StaticResponse>>,
+    use_original_dst: bool,
+    connect_direct: bool,
+    connect_race_delay: Duration,
+    copy_yield_bytes: u64,
+    bwlim: BandwidthLimits,
+    peers: Option<Arc<PeerSection>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FullconnAction {
+    Reject,
+    Errorfile,
+}
+
+impl FullconnAction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            FullconnAction::Reject => "reject",
+            FullconnAction::Errorfile => "errorfile",
+        }
+    }
+}
+
+struct BackendSessions {
+    name: String,
+    current: AtomicU32,
+    fullconn: Option<u32>,
+    action: FullconnAction,
+    queue_timeout: Option<Duration>,
+    released: Notify,
+    connect_rate: ConnectRate,
+    connect_queue: Duration,
+}
+
+impl BackendSessions {
+    fn new(config: &BackendConfig, global: &GlobalConfig) -> Self {
+        let http = config.mode.as_deref() == Some("http");
+        let action = match config.on_fullconn.as_deref() {
+            Some("reject") => FullconnAction::Reject,
+            Some("errorfile") => FullconnAction::Errorfile,
+            _ if http => FullconnAction::Errorfile,
+            _ => FullconnAction::Reject,
+        };
+        let timeout_queue = Duration::from_millis(config.options.as_ref()
+            .and_then(|options| options.general_options.timeout_queue)
+            .unwrap_or(DEFAULT_QUEUE_TIMEOUT_MS));
+        let queue_timeout = (config.on_fullconn.as_deref() == Some("queue")).then_some(timeout_queue);
+
+        Self {
+            name: config.name.clone(),
+            current: AtomicU32::new(0),
+            fullconn: config.fullconn,
+            action,
+            queue_timeout,
+            released: Notify::new(),
+            connect_rate: ConnectRate::new(config.connect_rate.or(global.tune_backend_connect_rate)),
+            connect_queue: timeout_queue,
+        }
+    }
+
+    async fn connect_slot(&self, deadline: tokio::time::Instant) -> Result<Duration> {
+        let max_wait = deadline.saturating_duration_since(tokio::time::Instant::now());
+        match self.connect_rate.reserve(max_wait) {
+            Some(wait) if wait.is_zero() => Ok(wait),
+            Some(wait) => {
+                metrics::backend_connect_throttled(&self.name, "delayed");
+                tokio::time::sleep(wait).await;
+                Ok(wait)
+            }
+            None => {
+                metrics::backend_connect_throttled(&self.name, "timeout");
+                Err(SessionError::ConnectThrottled(self.connect_queue).into())
+            }
+        }
+    }
+
+    fn try_enter(self: &Arc<Self>) -> Option<BackendSession> {
+        let fullconn = self.fullconn.unwrap_or(u32::MAX);
+        let previous = self.current
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| (current < fullconn).then_some(current + 1))
+            .ok()?;
+        metrics::backend_sessions(&self.name, previous + 1);
+        Some(BackendSession { sessions: Arc::clone(self) })
+    }
+
+    async fn enter(self: &Arc<Self>) -> Option<BackendSession> {
+        let Some(queue_timeout) = self.queue_timeout else {
+            return self.try_enter();
+        };
+
+        let deadline = tokio::time::Instant::now() + queue_timeout;
+        loop {
+            let released = self.released.notified();
+            if let Some(session) = self.try_enter() {
+                return Some(session);
+            }
+            if tokio::time::timeout_at(deadline, released).await.is_err() {
+                return self.try_enter();
+            }
+        }
+    }
+}
+
+struct BackendSession {
+    sessions: Arc<BackendSessions>,
+}
+
+impl Drop for BackendSession {
+    fn drop(&mut self) {
+        let previous = self.sessions.current.fetch_sub(1, Ordering::AcqRel);
+        metrics::backend_sessions(&self.sessions.name, previous.saturating_sub(1));
+        self.sessions.released.notify_one();
+    }
+}
+
+impl BackendState {
+    fn stick_sessions<I>(&mut self, keys: I, server_name: &str)
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+    {
+        let Some(ref mut stick_table) = self.stick_table else {
+            return;
+        };
+        let Some(server) = self.load_balancer.servers().iter().find(|s| s.config.name == server_name) else {
+            return;
+        };
+
+        for key in keys {
+            let updated = stick_table.store(key.clone(), server.index);
+            if let Some(ref peers) = self.peers {
+                peers.publish(PeerUpdate::StickStore {
+                    backend: self.config.name.clone(),
+                    key,
+                    server: server_name.to_string(),
+                    expires_in_ms: stick_table.expire().as_millis() as u64,
+                    updated,
+                });
+            }
+        }
+    }
+
+    fn publish_admin_state(&self, server: &ServerState) {
+        if let Some(ref peers) = self.peers {
+            peers.publish(PeerUpdate::ServerState {
+                backend: self.config.name.clone(),
+                server: server.config.name.clone(),
+                state: server.admin_state.as_str().to_string(),
+                updated: server.admin_updated,
+            });
+        }
+    }
+
+    fn report_effective_weights(&self) {
+        for server in self.load_balancer.servers() {
+            metrics::server_effective_weight(&self.config.name, &server.config.name, server.id, server.effective_weight());
+        }
+    }
+}
+
+impl ProxyServer {
+    pub fn new(features_manager: Arc<FeaturesManager>) -> Self {
+        let (reloaded, _) = broadcast::channel(RELOAD_CHANNEL_CAPACITY);
+        let (config_api, config_changes) = ConfigApi::channel();
+        Self {
+            frontends: Arc::new(DashMap::new()),
+            backends: Arc::new(DashMap::new()),
+            health_manager: HealthManager::new(&[], DEFAULT_MAX_CHECK_CONN, Arc::clone(&features_manager.events), &features_manager.resolvers),
+            active_connections: Arc::new(DashMap::new()),
+            tarpit_connections: Arc::new(AtomicU32::new(0)),
+            server_statuses: Arc::new(StatusTable::new(&[])),
+            accept_tasks: Vec::new(),
+            tasks: Arc::new(TaskRegistry::default()),
+            inspect_buffers: InspectBuffers::new(&features_manager.config.global, None),
+            config: Arc::clone(&features_manager.config),
+            config_hash: features_manager.config.content_hash.clone(),
+            reloaded,
+            config_api,
+            config_changes: Some(config_changes),
+            peers: Peers::default(),
+            features_manager,
+        }
+    }
+
+    pub fn tasks(&self) -> Arc<TaskRegistry> {
+        Arc::clone(&self.tasks)
+    }
+
+    pub fn subscribe_reloads(&self) -> broadcast::Receiver<Arc<Config>> {
+        self.reloaded.subscribe()
+    }
+
+    pub fn config_api(&self) -> ConfigApi {
+        self.config_api.clone()
+    }
+
+    pub async fn run(&mut self) -> Result<()> {
+        let mut config_changes = self.config_changes.take()
+            .ok_or_else(|| anyhow!("Proxy server is already running"))?;
+        if self.features_manager.config.global.warmup_checks {
+            self.warm_up().await;
+        }
+        let staged = self.stage(Arc::clone(&self.features_manager.config))?;
+        self.commit(staged);
+        self.start_admin_server();
+
+        let mut shutdown_signal = Self::setup_shutdown_signal();
+        let mut reload_signal = Self::setup_reload_signal();
+        let mut reloads = self.features_manager.hot_reload.as_ref().map(|hot_reload| hot_reload.subscribe());
+
+        {
+            let features_manager = Arc::clone(&self.features_manager);
+            self.tasks.spawn("ddos-reset", TaskGroup::Background, async move {
+                loop {
+                    let interval = {
+                        if let Some(ddos) = &features_manager.ddos_protection {
+                            let val = ddos.reset_interval_seconds();
+                            if val < 1 { 60 } else { val }
+                        } else {
+                            60
+                        }
+                    };
+                    info!("DDoS: ожидаю {} секунд до следующего сброса счетчиков", interval);
+                    tokio::time::sleep(std::time::Duration::from_secs(interval)).await;
+                    if let Some(ddos) = &features_manager.ddos_protection {
+                        ddos.reset_counters();
+                        info!("DDoS: счетчики сброшены (reset_counters)");
+                    }
+                }
+            });
+        }
+
+        {
+            let log_throttle = Arc::clone(&self.features_manager.log_throttle);
+            self.tasks.spawn("log-throttle", TaskGroup::Background, async move {
+                loop {
+                    tokio::time::sleep(log_throttle.interval()).await;
+                    log_throttle.flush();
+                }
+            });
+        }
+
+        {
+            let frontends = Arc::clone(&self.frontends);
+            let fair_accept = Arc::clone(&self.features_manager.fair_accept);
+            let process = Arc::clone(&self.features_manager.process);
+            self.tasks.spawn("conn-rate", TaskGroup::Background, async move {
+                loop {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    metrics::fair_accept_active_ips(fair_accept.active_ips());
+                    let mut conn_rate = 0;
+                    for frontend_state in frontends.iter() {
+                        let rates = &frontend_state.rates;
+                        conn_rate += rates.connections.rate();
+                        metrics::frontend_conn_rate(frontend_state.key(), rates.connections.rate(),
+                            rates.connections.rate_max(), rates.refused.rate());
+                    }
+                    process.record_conn_rate(conn_rate);
+                }
+            });
+        }
+
+        {
+            let backends = Arc::clone(&self.backends);
+            let server_statuses = Arc::clone(&self.server_statuses);
+            let events = Arc::clone(&self.features_manager.events);
+            self.tasks.spawn("server-state", TaskGroup::Background, async move {
+                loop {
+                    for mut backend_state in backends.iter_mut() {
+                        let backend_index = backend_state.index;
+                        backend_state.load_balancer.update_health(&server_statuses, backend_index);
+                        let backend_name = backend_state.config.name.clone();
+                        for change in backend_state.load_balancer.refresh_states() {
+                            Self::report_server_state(&events, &backend_name, change);
+                        }
+                    }
+                    tokio::time::sleep(SERVER_STATE_INTERVAL).await;
+                }
+            });
+        }
+
+        {
+            let backends = Arc::clone(&self.backends);
+            self.tasks.spawn("adaptive-weights", TaskGroup::Background, async move {
+                loop {
+                    tokio::time::sleep(ADAPTIVE_WEIGHT_INTERVAL).await;
+                    for mut backend_state in backends.iter_mut() {
+                        if !backend_state.load_balancer.adaptive().enabled {
+                            continue;
+                        }
+                        backend_state.load_balancer.adapt_weights();
+                        backend_state.report_effective_weights();
+                    }
+                }
+            });
+        }
+
+        {
+            let geoip = Arc::clone(&self.features_manager.geoip);
+            self.tasks.spawn("geoip-refresh", TaskGroup::Background, async move {
+                loop {
+                    tokio::time::sleep(GEOIP_REFRESH_INTERVAL).await;
+                    geoip.refresh();
+                }
+            });
+        }
+
+        self.tasks.spawn("fd-sampler", TaskGroup::Background, async move {
+            loop {
+                if let Ok(used) = utils::open_fds() {
+                    metrics::fd_used(used);
+                }
+                tokio::time::sleep(FD_SAMPLE_INTERVAL).await;
+            }
+        });
+
+        self.announce_ready();
+
+        loop {
+            tokio::select! {
+                _ = shutdown_signal.recv() => break,
+                config = Self::next_reload(&mut reloads) => self.reload(config, AuditSource::FileWatcher),
+                _ = reload_signal.recv() => self.reload_from_file(),
+                Some((change, done)) = config_changes.recv() => {
+                    let _ = done.send(self.apply_change(change));
+                }
+            }
+        }
+
+        notify::stopping();
+        self.features_manager.drain.begin();
+        let pre_stop_delay = self.features_manager.drain.pre_stop_delay();
+        if !pre_stop_delay.is_zero() {
+            info!("SIGTERM received, waiting {:?} before closing listeners", pre_stop_delay);
+            tokio::select! {
+                _ = tokio::time::sleep(pre_stop_delay) => {}
+                _ = shutdown_signal.recv() => info!("Second SIGTERM received, skipping the rest of pre-stop-delay"),
+            }
+        }
+        
+        let active_conns = self.active_connections.iter().map(|entry| *entry.value()).sum();
+        log_graceful_shutdown(active_conns);
+        
+        self.tasks.shutdown().await;
+        for frontend_state in self.frontends.iter() {
+            for listener in &frontend_state.listeners {
+                if let Ok(addr) = listener.socket.local_addr() {
+                    self.features_manager.events.publish(TurbogateEvent::FrontendUnbound {
+                        frontend: frontend_state.key().clone(),
+                        address: addr.to_string(),
+                    });
+                }
+            }
+        }
+
+        info!("Proxy server stopped");
+        Ok(())
+    }
+
+    async fn next_reload(reloads: &mut Option<broadcast::Receiver<Config>>) -> Config {
+        loop {
+            let Some(receiver) = reloads.as_mut() else {
+                return std::future::pending().await;
+            };
+            match receiver.recv().await {
+                Ok(config) => return config,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => warn!("Skipped {} stale configuration reloads", skipped),
+                Err(broadcast::error::RecvError::Closed) => *reloads = None,
+            }
+        }
+    }
+
+    fn announce_ready(&self) {
+        let mut frontends: Vec<(usize, ReadyFrontend)> = self.frontends.iter()
+            .map(|frontend_state| {
+                let binds = frontend_state.listeners.iter()
+                    .map(|listener| ReadyBind {
+                        address: listener.socket.local_addr().map(|addr| addr.to_string()).unwrap_or_else(|_| listener.name.clone()),
+                        tls: listener.tls.is_some(),
+                    })
+                    .collect();
+                let config = &frontend_state.config;
+                let backends: HashSet<&String> = config.default_backend.iter()
+                    .chain(config.use_backend.iter().map(|rule| &rule.backend))
+                    .collect();
+                (frontend_state.index, ReadyFrontend {
+                    name: frontend_state.key().clone(),
+                    binds,
+                    backends: backends.len(),
+                })
+            })
+            .collect();
+        frontends.sort_by_key(|(index, _)| *index);
+        let frontends: Vec<ReadyFrontend> = frontends.into_iter().map(|(_, frontend)| frontend).collect();
+        let backends = self.backends.len();
+
+        log_ready(env!("CARGO_PKG_VERSION"), &self.features_manager.config_path, &frontends, backends);
+        let listeners: usize = frontends.iter().map(|frontend| frontend.binds.len()).sum();
+        self.features_manager.events.publish(TurbogateEvent::Ready { frontends, backends });
+        self.features_manager.process.set_ready();
+        notify::ready(&format!("Accepting connections on {} listener(s)", listeners));
+    }
+
+    fn reload_from_file(&mut self) {
+        info!("SIGHUP received, reloading {}", self.features_manager.config_path);
+        let loaded = HotReload::load_config(&self.features_manager.config_path, &self.features_manager.audit,
+                                            AuditSource::Sighup, self.features_manager.allow_degraded);
+        match loaded {
+            Ok(config) => self.reload(config, AuditSource::Sighup),
+            Err(e) => {
+                error!("Configuration reload rejected, keeping the running configuration: {}", e);
+                metrics::config_reload_failed();
+                self.features_manager.events.publish(TurbogateEvent::ConfigReloaded { ok: false, error: Some(e.to_string()) });
+            }
+        }
+    }
+
+    fn reload(&mut self, config: Config, source: AuditSource) {
+        notify::reloading();
+        let config = Arc::new(config);
+        let staged = self.stage(Arc::clone(&config));
+        self.features_manager.audit.record(AuditEntry::new(source, "reload")
+            .config_hash(config.content_hash.clone())
+            .outcome(&staged));
+        match staged {
+            Ok(staged) => {
+                self.commit(staged);
+                self.features_manager.process.reloaded();
+                metrics::config_loaded(&config.content_hash, Some(&self.config_hash));
+                self.config_hash = config.content_hash.clone();
+                self.features_manager.events.publish(TurbogateEvent::ConfigReloaded { ok: true, error: None });
+                let _ = self.reloaded.send(config);
+                info!("Configuration reloaded successfully");
+                notify::ready("Configuration reloaded");
+            }
+            Err(e) => {
+                error!("Configuration reload rejected, keeping the running configuration: {}", e);
+                metrics::config_reload_failed();
+                self.features_manager.events.publish(TurbogateEvent::ConfigReloaded { ok: false, error: Some(e.to_string()) });
+                notify::ready("Configuration reload rejected, running the previous configuration");
+            }
+        }
+    }
+
+    fn apply_change(&mut self, change: ConfigChange) -> Result<(), ConfigApiError> {
+        let description = change.to_string();
+        let mut config = (*self.config).clone();
+        change.apply(&mut config)?;
+        config.validate(self.features_manager.allow_degraded).map_err(ConfigApiError::Invalid)?;
+        let config = Arc::new(config);
+        let staged = self.stage(Arc::clone(&config)).map_err(ConfigApiError::Invalid)?;
+        self.commit(staged);
+        info!("Configuration changed: {}", description);
+        self.features_manager.events.publish(TurbogateEvent::ConfigChanged { change: description });
+        let _ = self.reloaded.send(config);
+        Ok(())
+    }
+
+    async fn warm_up(&self) {
+        let config = &self.features_manager.config;
+        let timeout = config.global.warmup_timeout.unwrap_or(DEFAULT_WARMUP_TIMEOUT);
+        self.server_statuses.replace(&config.backends);
+        let health_manager = HealthManager::new(
+            &config.backends,
+            config.global.tune_maxcheckconn.unwrap_or(DEFAULT_MAX_CHECK_CONN),
+            Arc::clone(&self.features_manager.events),
+            &self.features_manager.resolvers,
+        );
+
+        info!("Warm-up: running one health check pass (up to {:?}) before binding listeners", timeout);
+        health_manager.warm_up(Arc::clone(&self.server_statuses), timeout).await;
+    }
+
+    fn stage(&self, config: Arc<Config>) -> Result<StagedReload> {
+        let mut live_listeners: HashMap<SocketAddr, Vec<FrontendListener>> = HashMap::new();
+        for listener in self.frontends.iter().flat_map(|frontend_state| frontend_state.listeners.clone()) {
+            if let Ok(addr) = listener.socket.local_addr() {
+                live_listeners.entry(addr).or_default().push(listener);
+            }
+        }
+
+        let inspect_buffers = InspectBuffers::new(&config.global, Some(&self.inspect_buffers));
+        let mut frontends = Vec::new();
+        let mut bound = Vec::new();
+        for (index, frontend_config) in config.frontends.iter().enumerate() {
+            let mut listeners = Vec::new();
+            for bind in &frontend_config.bind {
+                for option in &bind.options {
+                    warn!("Frontend '{}' bind {}: option '{}' is not supported and will be ignored",
+                          frontend_config.name, bind, option);
+                }
+
+                let tls = bind.ssl.then(|| TlsTerminator::new(bind))
+                    .transpose()
+                    .map_err(|e| anyhow!("Frontend '{}' bind {}: {}", frontend_config.name, bind, e))?;
+
+                for (addr, shard) in bind.addresses().into_iter().flat_map(|addr| (0..bind.shard_count()).map(move |shard| (addr, shard))) {
+                    let live = live_listeners.get_mut(&addr).and_then(|live| (!live.is_empty()).then(|| live.remove(0)));
+                    let listener = match live {
+                        Some(listener) => {
+                            Self::set_listener_options(&SockRef::from(&*listener.socket), bind, Some(&listener))
+                                .map_err(|e| anyhow!("Frontend '{}' failed to update {}: {}", frontend_config.name, addr, e))?;
+                            FrontendListener {
+                                name: bind.shard_name(addr, shard),
+                                tls: tls.clone(),
+                                pipeline: Pipeline::for_bind(bind),
+                                tfo: bind.tfo,
+                                defer_accept: bind.defer_accept,
+                                ..listener
+                            }
+                        }
+                        None => {
+                            let socket = Self::bind_listener(addr, bind)
+                                .map_err(|e| anyhow!("Frontend '{}' failed to bind {}: {}", frontend_config.name, addr, e))?;
+                            bound.push((frontend_config.name.clone(), addr));
+                            FrontendListener {
+                                name: bind.shard_name(addr, shard),
+                                socket: Arc::new(socket),
+                                counters: Arc::new(ListenerCounters::default()),
+                                tls: tls.clone(),
+                                pipeline: Pipeline::for_bind(bind),
+                                tfo: bind.tfo,
+                                defer_accept: bind.defer_accept,
+                            }
+                        }
+                    };
+                    listeners.push(listener);
+                }
+            }
+            frontends.push(self.build_frontend(index, frontend_config, &config, listeners, &inspect_buffers)?);
+        }
+
+        let backends = config.backends.iter()
+            .enumerate()
+            .map(|(index, backend_config)| Self::build_backend(index, backend_config, &config.global))
+            .collect::<Result<Vec<_>>>()?;
+
+        let health_manager = HealthManager::new(
+            &config.backends,
+            config.global.tune_maxcheckconn.unwrap_or(DEFAULT_MAX_CHECK_CONN),
+            Arc::clone(&self.features_manager.events),
+            &self.features_manager.resolvers,
+        );
+
+        Ok(StagedReload { config, frontends, backends, health_manager, inspect_buffers, bound })
+    }
+
+    fn commit(&mut self, staged: StagedReload) {
+        self.config = Arc::clone(&staged.config);
+        for task in self.accept_tasks.drain(..) {
+            task.abort();
+        }
+        self.health_manager.stop();
+        self.server_statuses.replace(&staged.config.backends);
+        self.features_manager.log_throttle.configure(&staged.config.global);
+        self.features_manager.fair_accept.configure(&staged.config.global);
+        self.features_manager.drain.configure(&staged.config.global);
+        self.features_manager.events.configure(&staged.config.global);
+        if let Err(e) = self.features_manager.audit.configure(&staged.config.global) {
+            error!("{}, keeping the previous audit log", e);
+        }
+        if let Err(e) = self.features_manager.geoip.configure(&staged.config.global) {
+            error!("{}, keeping the previous GeoIP database", e);
+        }
+        let keep_runtime_maxconn = staged.config.global.keep_runtime_maxconn;
+        self.features_manager.maxconn.reconcile(staged.config.global.maxconn.unwrap_or(DEFAULT_MAXCONN), keep_runtime_maxconn);
+        for frontend_state in &staged.frontends {
+            frontend_state.maxconn.reconcile(frontend_state.config.maxconn.unwrap_or(0), keep_runtime_maxconn);
+            frontend_state.bwlim.configure(frontend_state.config.bwlim_in.as_ref(), frontend_state.config.bwlim_out.as_ref());
+        }
+
+        self.peers.reconcile(&staged.config.peers, &staged.config.global.local_peer_name(), &self.tasks, self.peer_store());
+
+        metrics::limit_server_series(staged.config.metrics.max_series_per_backend, &staged.config.backends);
+        let backend_names: HashSet<&str> = staged.config.backends.iter().map(|b| b.name.as_str()).collect();
+        self.backends.retain(|name, _| backend_names.contains(name.as_str()));
+        for mut backend_state in staged.backends {
+            backend_state.peers = backend_state.config.stick_table.as_ref()
+                .and_then(|stick_table| stick_table.peers.as_deref())
+                .and_then(|name| self.peers.section(name));
+            if let Some(previous) = self.backends.get(&backend_state.config.name) {
+                backend_state.load_balancer.inherit(&previous.load_balancer);
+                backend_state.bwlim = previous.bwlim.clone();
+                backend_state.bwlim.configure(backend_state.config.bwlim_in.as_ref(), backend_state.config.bwlim_out.as_ref());
+            }
+            for server in backend_state.load_balancer.servers() {
+                metrics::server_admin_state(&backend_state.config.name, &server.config.name, server.id, server.admin_state.as_str());
+            }
+            self.backends.insert(backend_state.config.name.clone(), backend_state);
+        }
+
+        let staged_addrs: HashSet<SocketAddr> = staged.frontends.iter()
+            .flat_map(|frontend_state| frontend_state.listeners.iter().filter_map(|listener| listener.socket.local_addr().ok()))
+            .collect();
+        for frontend_state in self.frontends.iter() {
+            for addr in frontend_state.listeners.iter().filter_map(|listener| listener.socket.local_addr().ok()) {
+                if !staged_addrs.contains(&addr) {
+                    info!("Frontend '{}' stopped listening on {}", frontend_state.key(), addr);
+                    self.features_manager.events.publish(TurbogateEvent::FrontendUnbound {
+                        frontend: frontend_state.key().clone(),
+                        address: addr.to_string(),
+                    });
+                }
+            }
+        }
+        let previous_pipelines: HashMap<(String, String), Pipeline> = self.frontends.iter()
+            .flat_map(|frontend_state| frontend_state.listeners.iter()
+                .map(|listener| ((frontend_state.key().clone(), listener.name.clone()), listener.pipeline))
+                .collect::<Vec<_>>())
+            .collect();
+        self.frontends.clear();
+        for frontend_state in staged.frontends {
+            for listener in &frontend_state.listeners {
+                let key = (frontend_state.config.name.clone(), listener.name.clone());
+                if previous_pipelines.get(&key) != Some(&listener.pipeline) {
+                    info!("Frontend '{}' listener {} pipeline: {}", key.0, key.1, listener.pipeline);
+                }
+            }
+            self.frontends.insert(frontend_state.config.name.clone(), frontend_state);
+        }
+        for (frontend, addr) in staged.bound {
+            info!("Frontend '{}' listening on {}", frontend, addr);
+            self.features_manager.events.publish(TurbogateEvent::FrontendBound {
+                frontend,
+                address: addr.to_string(),
+            });
+        }
+
+        self.inspect_buffers = staged.inspect_buffers;
+        self.health_manager = staged.health_manager;
+        self.health_manager.start(Arc::clone(&self.server_statuses), &self.tasks);
+        self.spawn_accept_tasks();
+    }
+
+    fn build_frontend(&self, index: usize, frontend_config: &FrontendConfig, config: &Config, mut listeners: Vec<FrontendListener>, inspect_buffers: &InspectBuffers) -> Result<FrontendState> {
+        let log_format = frontend_config.log_format.as_deref()
+            .map(LogFormat::parse)
+            .transpose()?
+            .map(Arc::new);
+
+        let mut routing = CompiledRouting::compile(frontend_config, &config.userlists)
+            .map_err(|e| anyhow!("{} in frontend '{}'", e, frontend_config.name))?;
+        if let Some(frontend_state) = self.frontends.get(&frontend_config.name) {
+            routing.acls.adopt_counters(&frontend_state.routing.acls);
+        }
+
+        let payload_inspection = match frontend_config.tcp_request_inspect_delay.as_deref() {
+            Some(delay) => Some(PayloadInspection {
+                delay: utils::parse_duration(delay)?,
+                needed: routing.acls.payload_needed(),
+                until_headers: routing.acls.inspects_http(),
+                until_client_hello: routing.acls.uses_req_ssl_sni(),
+            }),
+            None => None,
+        };
+
+        let http_request = matches!(frontend_config.mode.as_deref(), Some("http" | "http-connect"));
+        let rate_limit_key = self.features_manager.rate_limiter.as_ref().map(|rate_limiter| rate_limiter.key());
+        let request_timeout = frontend_config.options.as_ref()
+            .and_then(|o| o.http_options.http_request_timeout)
+            .filter(|timeout| *timeout > 0)
+            .or_else(|| http_request.then_some(DEFAULT_INSPECT_TIMEOUT_MS))
+            .or_else(|| rate_limit_key.filter(|key| key.needs_server_name()).map(|_| DEFAULT_INSPECT_TIMEOUT_MS))
+            .or_else(|| frontend_config.use_backend.iter()
+                .any(|rule| NameTemplate::is_template(&rule.backend))
+                .then_some(DEFAULT_INSPECT_TIMEOUT_MS))
+            .or_else(|| frontend_config.default_backend.iter()
+                .chain(frontend_config.use_backend.iter().map(|rule| &rule.backend))
+                .any(|name| config.backends.iter().any(|backend| &backend.name == name && backend.stick_table.is_some()))
+                .then_some(DEFAULT_INSPECT_TIMEOUT_MS))
+            .map(Duration::from_millis);
+
+        let connect_auth = frontend_config.connect_auth.as_ref()
+            .map(|name| config.userlists.iter()
+                .find(|userlist| &userlist.name == name)
+                .map(|userlist| Arc::new(Userlist::new(userlist)))
+                .ok_or_else(|| anyhow!("Userlist '{}' not found for frontend '{}'", name, frontend_config.name)))
+            .transpose()?;
+
+        let inspect = payload_inspection.is_some() || request_timeout.is_some();
+        for listener in &mut listeners {
+            listener.pipeline = listener.pipeline.with_inspect(inspect);
+        }
+
+        let rates = self.frontends.get(&frontend_config.name)
+            .map(|frontend_state| Arc::clone(&frontend_state.rates))
+            .unwrap_or_else(|| Arc::new(FrontendRates {
+                connections: ConnRateTracker::new(),
+                refused: ConnRateTracker::new(),
+            }));
+        let maxconn = self.frontends.get(&frontend_config.name)
+            .map(|frontend_state| Arc::clone(&frontend_state.maxconn))
+            .unwrap_or_else(|| Arc::new(RuntimeLimit::new(frontend_config.maxconn.unwrap_or(0))));
+        let bwlim = self.frontends.get(&frontend_config.name)
+            .map(|frontend_state| frontend_state.bwlim.clone())
+            .unwrap_or_else(|| BandwidthLimits::new(frontend_config.bwlim_in.as_ref(), frontend_config.bwlim_out.as_ref()));
+
+        Ok(FrontendState {
+            index,
+            config: frontend_config.clone(),
+            routing: Arc::new(routing),
+            listeners,
+            maxconn,
+            bwlim,
+            log_format,
+            tarpit_connections: Arc::clone(&self.tarpit_connections),
+            rates,
+            payload_inspection,
+            request_timeout,
+            inspect_buffers: inspect_buffers.clone(),
+            connect_auth,
+        })
+    }
+
+    fn bind_listener(addr: SocketAddr, bind: &BindConfig) -> Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        socket.set_reuse_address(true)?;
+        if bind.reuseport {
+            utils::set_reuse_port(&socket).map_err(|e| anyhow!("cannot set SO_REUSEPORT: {}", e))?;
+        }
+        if let (true, Some(v6only)) = (addr.is_ipv6(), bind.v6only) {
+            socket.set_only_v6(v6only)?;
+        }
+        if let Some(ref interface) = bind.interface {
+            utils::bind_to_device(&socket, interface)
+                .map_err(|e| anyhow!("cannot bind to interface '{}': {}", interface, e))?;
+        }
+        socket.set_nonblocking(true)?;
+        socket.bind(&addr.into())?;
+        Self::set_listener_options(&socket, bind, None)?;
+        socket.listen(bind.backlog.map(|backlog| i32::try_from(backlog).unwrap_or(i32::MAX)).unwrap_or(DEFAULT_LISTEN_BACKLOG))?;
+
+        Ok(TcpListener::from_std(socket.into())?)
+    }
+
+    fn set_listener_options(socket: &Socket, bind: &BindConfig, current: Option<&FrontendListener>) -> Result<()> {
+        if bind.tfo != current.is_some_and(|listener| listener.tfo) {
+            let queue = if bind.tfo { bind.backlog.unwrap_or(DEFAULT_LISTEN_BACKLOG as u32) } else { 0 };
+            utils::set_fast_open(socket, queue).map_err(|e| anyhow!("cannot set TCP fast open: {}", e))?;
+        }
+        if bind.defer_accept != current.is_some_and(|listener| listener.defer_accept) {
+            let seconds = if bind.defer_accept { DEFER_ACCEPT_SECS } else { 0 };
+            utils::set_defer_accept(socket, seconds).map_err(|e| anyhow!("cannot set defer-accept: {}", e))?;
+        }
+        Ok(())
+    }
+
+    fn build_backend(index: usize, backend_config: &BackendConfig, global: &GlobalConfig) -> Result<BackendState> {
+        let algorithm = backend_config.balance.as_deref().unwrap_or("roundrobin");
+        let mut load_balancer = BackendLoadBalancer::new(&backend_config.name, backend_config.server.clone(), algorithm)?;
+        load_balancer.set_decision_trace(backend_config.options.as_ref()
+            .map(|options| options.general_options.balance_debug)
+            .unwrap_or(false));
+        load_balancer.set_require_check(backend_config.require_check);
+        load_balancer.set_persist_weight_zero(backend_config.persist_weight_zero);
+        load_balancer.set_adaptive(AdaptiveSettings {
+            enabled: backend_config.balance_adaptive,
+            metric: backend_config.adaptive_metric.as_deref().map(AdaptiveMetric::parse).transpose()?.unwrap_or(AdaptiveMetric::Latency),
+            half_life: backend_config.adaptive_half_life.unwrap_or(DEFAULT_ADAPTIVE_HALF_LIFE),
+        });
+        
+        let stick_table = backend_config.stick_table.as_ref()
+            .filter(|_| backend_config.stick_on.as_deref() == Some("ssl_session"))
+            .map(StickTable::new);
+
+        let health_response = (backend_config.mode.as_deref() == Some("health")).then(|| {
+            let httpchk = backend_config.options.as_ref()
+                .is_some_and(|options| options.http_options.httpchk.is_some());
+            if httpchk { HEALTH_HTTP_RESPONSE } else { HEALTH_RESPONSE }
+        });
+
+        Ok(BackendState {
+            index,
+            config: backend_config.clone(),
+            load_balancer,
+            stick_table,
+            sessions: Arc::new(BackendSessions::new(backend_config, global)),
+            health_response,
+            http_return: backend_config.http_request_return.as_ref().map(|rule| Arc::new(StaticResponse::new(rule))),
+            use_original_dst: backend_config.options.as_ref()
+                .is_some_and(|options| options.tcp_options.use_original_dst),
+            connect_direct: backend_config.options.as_ref()
+                .is_some_and(|options| options.tcp_options.connect_direct),
+            connect_race_delay: global.tune_connect_race_delay.unwrap_or(session::DEFAULT_CONNECT_RACE_DELAY),
+            copy_yield_bytes: global.tune_copy_yield_bytes.unwrap_or(session::DEFAULT_COPY_YIELD_BYTES),
+            bwlim: BandwidthLimits::new(backend_config.bwlim_in.as_ref(), backend_config.bwlim_out.as_ref()),
+            peers: None,
+        })
+    }
+
+    fn proxy_stats(&self) -> Arc<ProxyStats> {
+        Arc::new(ProxyStats {
+            frontends: Arc::clone(&self.frontends),
+            backends: Arc::clone(&self.backends),
+            server_statuses: Arc::clone(&self.server_statuses),
+            maxconn: Arc::clone(&self.features_manager.maxconn),
+            fds: Arc::clone(&self.features_manager.fds),
+            process: Arc::clone(&self.features_manager.process),
+            drain: Arc::clone(&self.features_manager.drain),
+        })
+    }
+
+    pub fn stats(&self) -> Arc<dyn StatsSource> {
+        self.proxy_stats()
+    }
+
+    pub fn admin(&self) -> Arc<dyn AdminHandler> {
+        Arc::new(ProxyAdmin {
+            frontends: Arc::clone(&self.frontends),
+            backends: Arc::clone(&self.backends),
+            server_statuses: Arc::clone(&self.server_statuses),
+            stats: self.proxy_stats(),
+            maxconn: Arc::clone(&self.features_manager.maxconn),
+            events: Arc::clone(&self.features_manager.events),
+            errors: Arc::clone(&self.features_manager.errors),
+            drain: Arc::clone(&self.features_manager.drain),
+            audit: Arc::clone(&self.features_manager.audit),
+            metrics_server: Arc::clone(&self.features_manager.metrics_server),
+            config_api: self.config_api(),
+        })
+    }
+
+    fn peer_store(&self) -> Arc<dyn PeerStore> {
+        Arc::new(ProxyPeers {
+            backends: Arc::clone(&self.backends),
+            events: Arc::clone(&self.features_manager.events),
+        })
+    }
+
+    fn start_admin_server(&self) {
+        let Some(path) = self.features_manager.config.global.stats_socket.clone() else {
+            return;
+        };
+        let handler = self.admin();
+
+        self.tasks.spawn("admin", TaskGroup::Accept, async move {
+            if let Err(e) = AdminServer::new(path, handler).run().await {
+                error!("Admin server failed: {}", e);
+            }
+        });
+    }
+
+    fn spawn_accept_tasks(&mut self) {
+        for frontend_state in self.frontends.iter() {
+            for listener in &frontend_state.listeners {
+                let listener = listener.clone();
+                let frontend_name = frontend_state.key().clone();
+                let frontends = Arc::clone(&self.frontends);
+                let backends = Arc::clone(&self.backends);
+                let active_connections = Arc::clone(&self.active_connections);
+                let server_statuses = Arc::clone(&self.server_statuses);
+                let features_manager = Arc::clone(&self.features_manager);
+                
+                let task_name = format!("accept:{}/{}", frontend_name, listener.name);
+                self.accept_tasks.push(self.tasks.spawn(task_name, TaskGroup::Accept, async move {
+                    if let Err(e) = Self::accept_connections(
+                        &listener,
+                        &frontend_name,
+                        frontends,
+                        backends,
+                        active_connections,
+                        server_statuses,
+                        features_manager,
+                    ).await {
+                        error!("Error accepting connections on frontend {} listener {}: {}", frontend_name, listener.name, e);
+                    }
+                }));
+            }
+        }
+    }
+
+    async fn accept_connections(
+        listener: &FrontendListener,
+        frontend_name: &str,
+        frontends: Arc<DashMap<String, FrontendState>>,
+        backends: Arc<DashMap<String, BackendState>>,
+        active_connections: Arc<DashMap<String, u64>>,
+        server_statuses: Arc<StatusTable>,
+        features_manager: Arc<FeaturesManager>,
+    ) -> Result<()> {
+        let listener_addr = listener.socket.local_addr()?;
+        let listener_name = &listener.name;
+        let (rates, frontend_maxconn, rate_limit_sessions, transparent) = frontends.get(frontend_name)
+            .map(|f| (Arc::clone(&f.rates), Arc::clone(&f.maxconn), f.config.rate_limit_sessions,
+                      f.config.bind.iter().any(|bind| bind.transparent && bind.addresses().contains(&listener_addr))))
+            .ok_or_else(|| anyhow!("Frontend '{}' not found", frontend_name))?;
+
+        loop {
+            if let Some(limit) = rate_limit_sessions {
+                while rates.connections.current() >= limit as u64 {
+                    tokio::time::sleep(rates.connections.until_next_second()).await;
+                }
+            }
+
+            let (client_stream, client_addr) = accept_retrying(|| listener.socket.accept(), frontend_name, listener_name,
+                                                               &features_manager.log_throttle, &features_manager.events).await?;
+            rates.connections.record();
+            features_manager.process.connection_accepted();
+            listener.counters.total.fetch_add(1, Ordering::Relaxed);
+            metrics::connection_accepted(frontend_name, listener_name);
+            if listener.tfo && utils::fast_open_used(&SockRef::from(&client_stream)).unwrap_or(false) {
+                metrics::fast_open_accepted(frontend_name, listener_name);
+            }
+            let mut guard = ConnectionGuard::accept(frontend_name, listener, client_addr, Arc::clone(&active_connections),
+                                                    Arc::clone(&features_manager));
+            if features_manager.drain.refuses_connections() {
+                debug!("Connection from {} refused on frontend {}: draining", client_addr, frontend_name);
+                rates.refused.record();
+                features_manager.process.connection_denied();
+                guard.reject("draining");
+                continue;
+            }
+            let connection = ConnectionContext::new(&client_stream, client_addr, client_stream.local_addr().ok(), transparent, listener_name)
+                .with_tls(listener.tls.clone())
+                .with_pipeline(listener.pipeline)
+                .with_country(features_manager.geoip.lookup(client_addr.ip()));
+
+            match Self::tcp_request_connection_action(&frontends, frontend_name, &connection, features_manager.reverse_dns.as_ref()) {
+                ConnectionAction::Accept => {}
+                ConnectionAction::Reject => {
+                    debug!("Connection from {} rejected by tcp-request rule on frontend {}", client_addr, frontend_name);
+                    rates.refused.record();
+                    features_manager.process.connection_denied();
+                    guard.reject("tcp_request_reject");
+                    continue;
+                }
+                ConnectionAction::Tarpit(duration, tarpit_connections) => {
+                    let tarpit_maxconn = features_manager.config.global.tarpit_maxconn.unwrap_or(1000);
+                    rates.refused.record();
+                    features_manager.process.connection_denied();
+                    let tarpitted = Self::tarpit_connection(client_stream, &connection, frontend_name, duration, tarpit_connections, tarpit_maxconn);
+                    guard.reject(if tarpitted { "tarpit" } else { "tarpit_limit" });
+                    continue;
+                }
+            }
+
+            // The guard already counts this connection; the limits apply to the ones before it.
+            let current_connections: u64 = active_connections.iter().map(|entry| *entry.value()).sum::<u64>().saturating_sub(1);
+            let frontend_connections = active_connections.get(frontend_name).map(|count| count.saturating_sub(1)).unwrap_or(0);
+
+            if !features_manager.maxconn.admits(current_connections) {
+                if features_manager.log_throttle.admit("maxconn_limit", frontend_name) {
+                    warn!("Max connections limit reached: {} >= {}", current_connections, features_manager.maxconn.get());
+                }
+                rates.refused.record();
+                features_manager.process.connection_denied();
+                guard.reject("maxconn_limit");
+                continue;
+            }
+
+            if !frontend_maxconn.admits(frontend_connections) {
+                if features_manager.log_throttle.admit("frontend_maxconn_limit", frontend_name) {
+                    warn!("Frontend {} max connections limit reached: {} >= {}", frontend_name, frontend_connections, frontend_maxconn.get());
+                }
+                rates.refused.record();
+                features_manager.process.connection_denied();
+                guard.reject("frontend_maxconn_limit");
+                continue;
+            }
+
+            match features_manager.fair_accept.admit(client_addr.ip(), current_connections, features_manager.maxconn.get()) {
+                Ok(fair_share) => guard.hold_fair_share(fair_share),
+                Err(share) => {
+                    if features_manager.log_throttle.admit("fair_accept", frontend_name) {
+                        warn!("Connection from {} rejected by fair accept: it already holds its share of {} connections", client_addr, share);
+                    }
+                    rates.refused.record();
+                    features_manager.process.connection_denied();
+                    metrics::fair_accept_rejected(frontend_name);
+                    guard.reject("fair_accept");
+                    continue;
+                }
+            }
+
+            let frontends = Arc::clone(&frontends);
+            let backends = Arc::clone(&backends);
+            let server_statuses = Arc::clone(&server_statuses);
+            let features_manager = Arc::clone(&features_manager);
+
+            task::spawn(async move {
+                match Self::handle_connection(
+                    client_stream,
+                    connection,
+                    &mut guard,
+                    frontends,
+                    backends,
+                    server_statuses,
+                    Arc::clone(&features_manager),
+                ).await {
+                    Ok(()) => {
+                        debug!("Connection from {} handled successfully", client_addr);
+                    }
+                    Err(e) => {
+                        let session_error = SessionError::classify(&e);
+                        let error_type = session_error.map(|se| se.error_type()).unwrap_or("handle_error");
+                        let termination_state = session_error.map(|se| se.termination_state()).unwrap_or("--");
+                        if features_manager.log_throttle.admit(error_type, guard.frontend()) {
+                            error!("Error handling connection from {}: {:#}", client_addr, e);
+                        }
+                        guard.reject(error_type);
+                        features_manager.errors.record(client_addr, guard.frontend(), &e, error_type, termination_state);
+                    }
+                }
+            });
+        }
+    }
+
+    fn tcp_request_connection_action(
+        frontends: &DashMap<String, FrontendState>,
+        frontend_name: &str,
+        connection: &ConnectionContext,
+        reverse_dns: Option<&Arc<ReverseDns>>,
+    ) -> ConnectionAction {
+        let Some(frontend_state) = frontends.get(frontend_name) else {
+            return ConnectionAction::Accept;
+        };
+        let config = &frontend_state.config;
+        let routing = &frontend_state.routing;
+        let src_dn = reverse_dns
+            .filter(|_| !routing.tcp_request_connection.is_empty() && routing.acls.uses_src_dn())
+            .and_then(|reverse_dns| reverse_dns.cached(connection.client_addr.ip()));
+
+        for (rule, condition) in config.tcp_request_connection.iter().zip(&routing.tcp_request_connection) {
+            let matched = match condition {
+                Some(condition) => match routing.acls.evaluate(condition, &connection.acl_context(&[]).with_src_dn(src_dn.as_deref())) {
+                    Ok(matched) => matched,
+                    Err(e) => {
+                        warn!("Failed to evaluate tcp-request rule on frontend {}: {}", frontend_name, e);
+                        metrics::acl_eval_error(frontend_name, &e.acl);
+                        false
+                    }
+                },
+                None => true,
+            };
+
+            if !matched {
+                continue;
+            }
+
+            return match rule.action.as_str() {
+                "reject" => ConnectionAction::Reject,
+                "tarpit" => ConnectionAction::Tarpit(Self::tarpit_duration(config), Arc::clone(&frontend_state.tarpit_connections)),
+                _ => ConnectionAction::Accept,
+            };
+        }
+
+        ConnectionAction::Accept
+    }
+
+    fn tarpit_duration(config: &FrontendConfig) -> Duration {
+        let general = config.options.as_ref().map(|o| &o.general_options);
+        let timeout_ms = general.and_then(|o| o.timeout_tarpit.or(o.timeout_connect)).unwrap_or(5000);
+        Duration::from_millis(timeout_ms)
+    }
+
+    fn tarpit_connection<S: Send + 'static>(
+        client_stream: S,
+        connection: &ConnectionContext,
+        frontend_name: &str,
+        duration: Duration,
+        tarpit_connections: Arc<AtomicU32>,
+        tarpit_maxconn: u32,
+    ) -> bool {
+        if tarpit_connections.fetch_add(1, Ordering::Relaxed) >= tarpit_maxconn {
+            tarpit_connections.fetch_sub(1, Ordering::Relaxed);
+            warn!("Tarpit limit reached ({}), closing connection from {}", tarpit_maxconn, connection.client_addr);
+            return false;
+        }
+
+        let client_addr = connection.client_addr;
+        metrics::tarpitted(frontend_name);
+        let frontend_name = frontend_name.to_string();
+
+        task::spawn(async move {
+            tokio::time::sleep(duration).await;
+            drop(client_stream);
+            tarpit_connections.fetch_sub(1, Ordering::Relaxed);
+            log_tarpit(&frontend_name, client_addr, duration);
+        });
+        true
+    }
+
+    async fn handle_connection(
+        client_stream: TcpStream,
+        connection: ConnectionContext,
+        guard: &mut ConnectionGuard,
+        frontends: Arc<DashMap<String, FrontendState>>,
+        backends: Arc<DashMap<String, BackendState>>,
+        server_statuses: Arc<StatusTable>,
+        features_manager: Arc<FeaturesManager>,
+    ) -> Result<()> {
+        let pipeline = connection.pipeline;
+        let mut flow = ConnectionFlow::new(client_stream, connection, guard, frontends, backends, server_statuses, features_manager)?;
+        for stage in pipeline.stages() {
+            if flow.run(stage).await? == Step::Done {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    async fn close_unrouted(
+        mut client_stream: ClientStream,
+        connection: &ConnectionContext,
+        frontends: &DashMap<String, FrontendState>,
+        frontend_config: &FrontendConfig,
+        features_manager: &FeaturesManager,
+    ) {
+        let frontend_name = frontend_config.name.as_str();
+        let action = frontend_config.on_no_backend.as_deref().unwrap_or("reject");
+        debug!("No backend selected for {} on frontend {}, on-no-backend {}", connection.client_addr, frontend_name, action);
+        metrics::no_backend(frontend_name, action);
+
+        match action {
+            "silent-drop" => {
+                let _ = client_stream.shutdown().await;
+            }
+            "tarpit" => {
+                let Some(tarpit_connections) = frontends.get(frontend_name).map(|f| Arc::clone(&f.tarpit_connections)) else {
+                    return;
+                };
+                let tarpit_maxconn = features_manager.config.global.tarpit_maxconn.unwrap_or(1000);
+                if !Self::tarpit_connection(client_stream, connection, frontend_name, Self::tarpit_duration(frontend_config),
+                                            tarpit_connections, tarpit_maxconn) {
+                    metrics::connection_error(frontend_name, &connection.listener, "tarpit_limit");
+                }
+            }
+            _ => {
+                if let Err(e) = client_stream.tcp_stream().set_linger(Some(Duration::ZERO)) {
+                    debug!("Cannot reset connection from {} on frontend {}: {}", connection.client_addr, frontend_name, e);
+                }
+            }
+        }
+    }
+
+    fn check_rate_limit(features_manager: &FeaturesManager, guard: &mut ConnectionGuard, key: RateKey) -> Result<()> {
+        if let Some(rate_limiter) = &features_manager.rate_limiter {
+            if !rate_limiter.check_rate_limit(key.clone()) {
+                if features_manager.log_throttle.admit("rate_limit_exceeded", guard.frontend()) {
+                    warn!("Rate limit exceeded for {} on frontend {}", key, guard.frontend());
+                }
+                guard.reject("rate_limit_exceeded");
+                return Err(anyhow!("Rate limit exceeded"));
+            }
+        }
+        Ok(())
+    }
+
+    fn auth_challenge<'a>(
+        frontend_name: &str,
+        routing: &'a CompiledRouting,
+        acl_context: &AclContext,
+        log_throttle: &LogThrottle,
+    ) -> Option<&'a str> {
+        routing.http_request_auth.iter()
+            .find(|rule| match rule.condition {
+                Some(ref condition) => match routing.acls.evaluate(condition, acl_context) {
+                    Ok(matched) => matched,
+                    Err(e) => {
+                        if log_throttle.admit("acl_eval_error", frontend_name) {
+                            warn!("http-request auth on frontend {} applied: {}", frontend_name, e);
+                        }
+                        metrics::acl_eval_error(frontend_name, &e.acl);
+                        true
+                    }
+                },
+                None => true,
+            })
+            .map(|rule| rule.realm.as_str())
+    }
+
+    fn http_return<'a>(
+        frontend_name: &str,
+        routing: &'a CompiledRouting,
+        acl_context: &AclContext,
+        log_throttle: &LogThrottle,
+    ) -> Option<&'a Arc<StaticResponse>> {
+        routing.http_request_return.iter()
+            .find(|rule| match rule.condition {
+                Some(ref condition) => match routing.acls.evaluate(condition, acl_context) {
+                    Ok(matched) => matched,
+                    Err(e) => {
+                        if log_throttle.admit("acl_eval_error", frontend_name) {
+                            warn!("http-request return on frontend {} skipped: {}", frontend_name, e);
+                        }
+                        metrics::acl_eval_error(frontend_name, &e.acl);
+                        false
+                    }
+                },
+                None => true,
+            })
+            .map(|rule| &rule.response)
+    }
+
+    async fn serve_return(
+        client_stream: &mut ClientStream,
+        request_prefix: &[u8],
+        response: &StaticResponse,
+        backend: Option<&str>,
+        frontend_config: &FrontendConfig,
+        features_manager: &FeaturesManager,
+        policy: Option<&HttpRequestPolicy>,
+    ) -> Result<Option<Vec<u8>>> {
+        let frontend_name = frontend_config.name.as_str();
+        let proxy_name = backend.unwrap_or(frontend_name);
+        let idle = SessionTimeouts::new(frontend_config.options.as_ref(), None).client;
+        let drain = &features_manager.drain;
+        let policy = policy.map(|policy| policy as &dyn RequestPolicy);
+        let served = response.serve(client_stream, request_prefix, idle, || drain.is_draining(), policy, backend.is_some()).await?;
+        debug!("Frontend {} answered {} of {} requests with http-request return of {}", frontend_name, served.responses, served.requests, proxy_name);
+        metrics::http_return(frontend_name, proxy_name, response.status(), served.responses);
+        Ok(served.forward)
+    }
+
+    fn select_backend(
+        frontend_name: &str,
+        routing: &CompiledRouting,
+        backends: &DashMap<String, BackendState>,
+        acl_context: &AclContext,
+        request_prefix: &[u8],
+        log_throttle: &LogThrottle,
+    ) -> Option<(String, RoutingRule)> {
+        for (index, use_backend) in routing.use_backend.iter().enumerate() {
+            if let Some(ref condition) = use_backend.condition {
+                match routing.acls.evaluate(condition, acl_context) {
+                    Ok(true) => {}
+                    Ok(false) => continue,
+                    Err(e) => {
+                        if log_throttle.admit("acl_eval_error", frontend_name) {
+                            warn!("use_backend {} on frontend {} skipped: {}", use_backend.backend, frontend_name, e);
+                        }
+                        metrics::acl_eval_error(frontend_name, &e.acl);
+                        continue;
+                    }
+                }
+            }
+
+            let rule = RoutingRule::UseBackend(index);
+            let template = match use_backend.target {
+                RouteTarget::Backend(ref backend_name) => return Some((backend_name.clone(), rule)),
+                RouteTarget::Template(ref template) => template,
+            };
+
+            match template.render(request_prefix, acl_context.sni) {
+                Some(backend_name) if backends.contains_key(&backend_name) => return Some((backend_name, rule)),
+                Some(backend_name) => debug!("use_backend {} resolved to unknown backend '{}' on frontend {}",
+                                             use_backend.backend, backend_name, frontend_name),
+                None => debug!("use_backend {} could not be resolved on frontend {}", use_backend.backend, frontend_name),
+            }
+        }
+
+        None
+    }
+
+    fn select_server(
+        backend_state: &mut BackendState,
+        server_statuses: &StatusTable,
+        session_keys: Vec<Vec<u8>>,
+    ) -> Result<ServerConfig> {
+        let backend_index = backend_state.index;
+        backend_state.load_balancer.update_health(server_statuses, backend_index);
+
+        let selected = match Self::sticky_server(backend_state, &session_keys) {
+            Some(server) => server,
+            None => backend_state.load_balancer.select_server()?
+                .map(|server_state| server_state.config.clone())
+                .ok_or_else(|| anyhow!("No healthy servers available"))?,
+        };
+        backend_state.stick_sessions(session_keys, &selected.name);
+
+        let server_state = backend_state.load_balancer.connection_started(&selected.name)?;
+        metrics::server_active_connections(&backend_state.config.name, &selected.name, server_state.id, server_state.active_connections);
+
+        Ok(selected)
+    }
+
+    fn redispatch_server(
+        backends: &DashMap<String, BackendState>,
+        server_statuses: &StatusTable,
+        backend_name: &str,
+        failed: &str,
+        session_keys: &[Vec<u8>],
+    ) -> Result<ServerConfig> {
+        let mut backend_state = backends.get_mut(backend_name)
+            .ok_or_else(|| anyhow!("Backend '{}' not found", backend_name))?;
+        let backend_index = backend_state.index;
+        backend_state.load_balancer.update_health(server_statuses, backend_index);
+
+        let selected = backend_state.load_balancer.select_server_excluding(Some(failed))?
+            .map(|server_state| server_state.config.clone())
+            .ok_or_else(|| anyhow!("No other healthy server available"))?;
+        backend_state.stick_sessions(session_keys.iter().cloned(), &selected.name);
+
+        let server_state = backend_state.load_balancer.connection_started(&selected.name)?;
+        metrics::server_active_connections(backend_name, &selected.name, server_state.id, server_state.active_connections);
+
+        Ok(selected)
+    }
+
+    fn sticky_server(backend_state: &mut BackendState, session_keys: &[Vec<u8>]) -> Option<ServerConfig> {
+        let stick_table = backend_state.stick_table.as_mut()?;
+        if session_keys.is_empty() {
+            return None;
+        }
+
+        for key in session_keys {
+            let Some(index) = stick_table.lookup(key) else {
+                continue;
+            };
+            match backend_state.load_balancer.select_sticky(index) {
+                Some(server) => {
+                    debug!("Backend {}: resumed TLS session routed to server {}", backend_state.config.name, server.config.name);
+                    metrics::stick_table_lookup(&backend_state.config.name, "hit", stick_table.used());
+                    return Some(server.config.clone());
+                }
+                None => {
+                    stick_table.forget(key);
+                    if let Some(ref peers) = backend_state.peers {
+                        peers.publish(PeerUpdate::StickForget {
+                            backend: backend_state.config.name.clone(),
+                            key: key.clone(),
+                            updated: utils::unix_millis(),
+                        });
+                    }
+                }
+            }
+        }
+
+        metrics::stick_table_lookup(&backend_state.config.name, "miss", stick_table.used());
+        None
+    }
+
+    fn release_server(backends: &DashMap<String, BackendState>, backend_name: &str, server_name: &str) {
+        let Some(mut backend_state) = backends.get_mut(backend_name) else {
+            return;
+        };
+
+        match backend_state.load_balancer.connection_finished(server_name) {
+            Ok((server_state, drained)) => {
+                metrics::server_active_connections(backend_name, server_name, server_state.id, server_state.active_connections);
+                if drained {
+                    log_server_drained(backend_name, server_name);
+                }
+            }
+            Err(e) => warn!("Failed to release server '{}' in backend '{}': {}", server_name, backend_name, e),
+        }
+    }
+
+    fn record_outcome(backends: &DashMap<String, BackendState>, backend_name: &str, server_name: &str, duration: Duration, ok: bool) {
+        let Some(mut backend_state) = backends.get_mut(backend_name) else {
+            return;
+        };
+
+        if let Err(e) = backend_state.load_balancer.record_outcome(server_name, duration, ok) {
+            warn!("Failed to record outcome for server '{}' in backend '{}': {}", server_name, backend_name, e);
+        }
+    }
+
+    fn record_bytes(backends: &DashMap<String, BackendState>, backend_name: &str, server_name: &str, counters: &SessionCounters) {
+        let Some(mut backend_state) = backends.get_mut(backend_name) else {
+            return;
+        };
+
+        if let Err(e) = backend_state.load_balancer.record_bytes(server_name, counters.bytes_in(), counters.bytes_out()) {
+            warn!("Failed to record traffic for server '{}' in backend '{}': {}", server_name, backend_name, e);
+        }
+    }
+
+    fn report_server_state(events: &EventBus, backend_name: &str, change: ServerStateChange) {
+        let previous = change.previous.map(|state| state.as_str());
+        log_server_status(backend_name, &change.server, change.state.as_str(), previous);
+        metrics::server_state(backend_name, &change.server, change.id, change.state);
+        events.publish(TurbogateEvent::ServerStateChanged {
+            backend: backend_name.to_string(),
+            server: change.server,
+            state: change.state.as_str().to_string(),
+            previous: previous.map(str::to_string),
+        });
+    }
+
+    async fn server_addresses(features_manager: &FeaturesManager, server: &ServerConfig) -> Result<Vec<SocketAddr>> {
+        let addresses: Vec<SocketAddr> = match (&server.resolvers, server.port) {
+            (Some(resolvers_name), port) => {
+                let resolver = features_manager.resolvers.get(resolvers_name)
+                    .ok_or_else(|| anyhow!("Resolvers '{}' not found", resolvers_name))?;
+                match port {
+                    Some(port) => resolver.resolve(&server.address).await?
+                        .into_iter()
+                        .map(|address| SocketAddr::new(address, port))
+                        .collect(),
+                    None => resolver.resolve_srv(&server.address).await?,
+                }
+            }
+            (None, Some(port)) => {
+                let host = server.address.strip_prefix('[').and_then(|host| host.strip_suffix(']')).unwrap_or(&server.address);
+                tokio::net::lookup_host((host, port)).await?.collect()
+            }
+            (None, None) => return Err(anyhow!("Server '{}' uses SRV name '{}' without resolvers", server.name, server.address)),
+        };
+
+        if addresses.is_empty() {
+            return Err(anyhow!("No address resolved for server '{}'", server.name));
+        }
+        Ok(addresses)
+    }
+
+    async fn connect_server(
+        features_manager: &FeaturesManager,
+        backend_name: &str,
+        server: &ServerConfig,
+        timeouts: &SessionTimeouts,
+        race_delay: Duration,
+    ) -> Result<TcpStream> {
+        let race = async {
+            let addresses = Self::server_addresses(features_manager, server).await?;
+            let (stream, addr) = session::connect_race(&addresses, race_delay, server.tfo == Some(true)).await?;
+            if addresses.len() > 1 {
+                debug!("Backend {}: server {} connected via {} out of {} addresses", backend_name, server.name, addr, addresses.len());
+                metrics::connect_race_won(backend_name, if addr.is_ipv6() { "ipv6" } else { "ipv4" });
+            }
+            Ok(stream)
+        };
+
+        match timeouts.connect {
+            Some(connect_timeout) => tokio::time::timeout(connect_timeout, race).await
+                .map_err(|_| SessionError::ConnectTimeout(server.endpoint(), connect_timeout))?,
+            None => race.await,
+        }
+    }
+
+    async fn proxy_connection(
+        client_stream: ClientStream,
+        server_stream: TcpStream,
+        request_prefix: &[u8],
+        response_prefix: &[u8],
+        inspect_response: Option<ResponseInspector<'_>>,
+        timeouts: &SessionTimeouts,
+        counters: &SessionCounters,
+    ) -> Result<()> {
+
+        let (mut client_read, mut client_write) = tokio::io::split(client_stream);
+        let (mut server_read, mut server_write) = server_stream.into_split();
+
+        if !request_prefix.is_empty() {
+            let mut rewritten = Vec::with_capacity(request_prefix.len());
+            counters.forward_request(request_prefix, &mut rewritten);
+            server_write.write_all(&rewritten).await?;
+            counters.record(Side::Client, request_prefix.len() as u64);
+        }
+
+        if !response_prefix.is_empty() {
+            if let Some(inspect_response) = inspect_response {
+                inspect_response(response_prefix);
+            }
+            let mut rewritten = Vec::with_capacity(response_prefix.len());
+            let closed = counters.forward_response(response_prefix, &mut rewritten);
+            client_write.write_all(&rewritten).await?;
+            counters.record(Side::Server, response_prefix.len() as u64);
+            if closed {
+                client_write.shutdown().await?;
+                return Ok(());
+            }
+        } else if let Some(inspect_response) = inspect_response {
+            let mut response = vec![0u8; 16 * 1024];
+            let n = match timeouts.server {
+                Some(idle) => tokio::time::timeout(idle, server_read.read(&mut response)).await
+                    .map_err(|_| SessionError::ServerIdleTimeout(idle))??,
+                None => server_read.read(&mut response).await?,
+            };
+            inspect_response(&response[..n]);
+            client_write.write_all(&response[..n]).await?;
+            counters.record(Side::Server, n as u64);
+        }
+
+        let transfer = async {
+            let client_to_server = session::copy_with_idle_timeout(&mut client_read, &mut server_write, Side::Client, timeouts, counters);
+            let server_to_client = session::copy_with_idle_timeout(&mut server_read, &mut client_write, Side::Server, timeouts, counters);
+            tokio::pin!(server_to_client);
+            tokio::select! {
+                reason = counters.drain_idle_closed() => {
+                    debug!("Closing idle session while draining ({})", reason);
+                    metrics::drain_closed(reason);
+                }
+                _ = counters.keep_alive_expired(timeouts.keep_alive) => {
+                    debug!("Closing keep-alive connection idle between requests for {:?}", timeouts.keep_alive.unwrap_or_default());
+                }
+                result = client_to_server => {
+                    if let Err(e) = result {
+                        return Err(Self::direction_error("Client to server error", e));
+                    }
+
+                    let remaining = match timeouts.client_fin {
+                        Some(client_fin) => tokio::time::timeout(client_fin, &mut server_to_client).await
+                            .map_err(|_| SessionError::ClientFinTimeout(client_fin))?,
+                        None => (&mut server_to_client).await,
+                    };
+                    if let Err(e) = remaining {
+                        return Err(Self::direction_error("Server to client error", e));
+                    }
+                }
+                result = &mut server_to_client => {
+                    if let Err(e) = result {
+                        return Err(Self::direction_error("Server to client error", e));
+                    }
+                }
+            }
+
+            Ok(())
+        };
+
+        match timeouts.tunnel {
+            Some(tunnel_timeout) => tokio::time::timeout(tunnel_timeout, transfer).await
+                .map_err(|_| SessionError::SessionMaxDuration(tunnel_timeout))?,
+            None => transfer.await,
+        }
+    }
+
+    fn direction_error(direction: &str, error: anyhow::Error) -> anyhow::Error {
+        if SessionError::classify(&error).is_some() {
+            error
+        } else {
+            anyhow!("{}: {}", direction, error)
+        }
+    }
+
+    fn setup_shutdown_signal() -> tokio::signal::unix::Signal {
+        use tokio::signal::unix::{signal, SignalKind};
+        signal(SignalKind::terminate()).expect("Failed to create signal handler")
+    }
+
+    fn setup_reload_signal() -> tokio::signal::unix::Signal {
+        use tokio::signal::unix::{signal, SignalKind};
+        signal(SignalKind::hangup()).expect("Failed to create signal handler")
+    }
+}
+
+// What a pipeline stage leaves for the next one: `Done` once the connection
+// has been answered, refused or proxied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    Next,
+    Done,
+}
+
+// Where the route stage sent a connection, for the forward stage.
+struct Routed {
+    backend: String,
+    rule: String,
+    request_time: Duration,
+}
+
+// A connection on its way through its listener's pipeline. Each stage reads
+// the state the earlier ones filled in and adds its own.
+struct ConnectionFlow<'a> {
+    client_stream: Option<ClientStream>,
+    connection: ConnectionContext,
+    guard: &'a mut ConnectionGuard,
+    frontends: Arc<DashMap<String, FrontendState>>,
+    backends: Arc<DashMap<String, BackendState>>,
+    server_statuses: Arc<StatusTable>,
+    features_manager: Arc<FeaturesManager>,
+    frontend_name: String,
+    frontend_config: FrontendConfig,
+    routing: Arc<CompiledRouting>,
+    log_format: Option<Arc<LogFormat>>,
+    payload_inspection: Option<PayloadInspection>,
+    request_timeout: Option<Duration>,
+    inspect_buffers: InspectBuffers,
+    frontend_bwlim: BandwidthLimits,
+    connect_auth: Option<Arc<Userlist>>,
+    handshake_timeout: Duration,
+    rate_limit_key: Option<RateLimitKey>,
+    http_mode: bool,
+    connect_mode: bool,
+    accept_invalid: bool,
+    admitted: bool,
+    request_prefix: PooledBuffer,
+    overflowed: bool,
+    connect_request: Option<inspect::ConnectRequest>,
+    policy: Option<HttpRequestPolicy>,
+    routed: Option<Routed>,
+}
+
+impl<'a> ConnectionFlow<'a> {
+    fn new(
+        client_stream: TcpStream,
+        connection: ConnectionContext,
+        guard: &'a mut ConnectionGuard,
+        frontends: Arc<DashMap<String, FrontendState>>,
+        backends: Arc<DashMap<String, BackendState>>,
+        server_statuses: Arc<StatusTable>,
+        features_manager: Arc<FeaturesManager>,
+    ) -> Result<Self> {
+        let frontend_name = guard.frontend().to_string();
+        let (frontend_config, routing, log_format, payload_inspection, request_timeout, inspect_buffers, frontend_bwlim, connect_auth) = if let Some(frontend_state) = frontends.get(&frontend_name) {
+            (frontend_state.config.clone(), Arc::clone(&frontend_state.routing), frontend_state.log_format.clone(),
+             frontend_state.payload_inspection, frontend_state.request_timeout, frontend_state.inspect_buffers.clone(), frontend_state.bwlim.clone(),
+             frontend_state.connect_auth.clone())
+        } else {
+            return Err(anyhow!("Frontend '{}' not found", frontend_name));
+        };
+        let handshake_timeout = frontend_config.options.as_ref()
+            .and_then(|o| o.general_options.timeout_client)
+            .filter(|ms| *ms > 0)
+            .map(Duration::from_millis)
+            .unwrap_or(tls::DEFAULT_HANDSHAKE_TIMEOUT);
+        let rate_limit_key = features_manager.rate_limiter.as_ref().map(|rate_limiter| rate_limiter.key());
+        let http_mode = frontend_config.mode.as_deref() == Some("http");
+        let connect_mode = frontend_config.mode.as_deref() == Some("http-connect");
+        let accept_invalid = frontend_config.options.as_ref().is_some_and(|o| o.http_options.accept_invalid_http_request);
+        let request_prefix = inspect_buffers.pool.get();
+
+        Ok(Self {
+            client_stream: Some(ClientStream::Plain(client_stream)),
+            connection,
+            guard,
+            frontends,
+            backends,
+            server_statuses,
+            features_manager,
+            frontend_name,
+            frontend_config,
+            routing,
+            log_format,
+            payload_inspection,
+            request_timeout,
+            inspect_buffers,
+            frontend_bwlim,
+            connect_auth,
+            handshake_timeout,
+            rate_limit_key,
+            http_mode,
+            connect_mode,
+            accept_invalid,
+            admitted: false,
+            request_prefix,
+            overflowed: false,
+            connect_request: None,
+            policy: None,
+            routed: None,
+        })
+    }
+
+    async fn run(&mut self, stage: Stage) -> Result<Step> {
+        // Connection limits apply to the client a PROXY header names, before any
+        // TLS handshake is spent on it.
+        if stage != Stage::ProxyProtocol && !self.admitted {
+            self.admitted = true;
+            self.admit()?;
+        }
+        match stage {
+            Stage::ProxyProtocol => self.proxy_protocol().await,
+            Stage::TlsAccept => self.tls_accept().await,
+            Stage::Inspect => self.inspect().await,
+            Stage::Route => self.route().await,
+            Stage::Forward => self.forward().await,
+        }
+    }
+
+    fn client_stream(client_stream: &mut Option<ClientStream>) -> &mut ClientStream {
+        client_stream.as_mut().expect("the client stream stays in place until the forward stage")
+    }
+
+    async fn proxy_protocol(&mut self) -> Result<Step> {
+        let Some(ClientStream::Plain(client_stream)) = self.client_stream.as_mut() else {
+            return Err(anyhow!("PROXY header expected before any TLS handshake"));
+        };
+        if let Some(header) = proxy_protocol::read_header(client_stream, self.handshake_timeout).await? {
+            debug!("PROXY header from {} on frontend {}: client {} to {}", self.connection.client_addr, self.frontend_name,
+                   header.source, header.destination);
+            self.connection.client_addr = header.source;
+            self.connection.local_addr = Some(header.destination);
+            self.connection.country = self.features_manager.geoip.lookup(header.source.ip());
+        }
+        Ok(Step::Next)
+    }
+
+    fn admit(&mut self) -> Result<()> {
+        let Self { connection, guard, features_manager, frontend_name, rate_limit_key, .. } = self;
+        let frontend_name = frontend_name.as_str();
+        if let Some(country) = features_manager.geoip.metric_label(connection.country) {
+            metrics::connection_country(frontend_name, &country);
+        }
+
+        let client_addr = connection.client_addr;
+        if *rate_limit_key == Some(RateLimitKey::Src) {
+            ProxyServer::check_rate_limit(features_manager, guard, RateKey::Ip(client_addr.ip()))?;
+        }
+
+        if let Some(ddos_protection) = &features_manager.ddos_protection {
+            if !ddos_protection.check_connection_limit(client_addr.ip()) {
+                if features_manager.log_throttle.admit("ddos_connection_limit", frontend_name) {
+                    warn!("DDoS protection: connection limit exceeded for client {} on frontend {}", client_addr.ip(), frontend_name);
+                }
+                guard.reject("ddos_connection_limit");
+                return Err(anyhow!("DDoS protection: connection limit exceeded"));
+            }
+            guard.track_ddos(client_addr.ip());
+        }
+        Ok(())
+    }
+
+    async fn tls_accept(&mut self) -> Result<Step> {
+        let (Some(tls), Some(ClientStream::Plain(client_stream))) = (self.connection.tls.clone(), self.client_stream.take()) else {
+            return Err(anyhow!("TLS listener without a plain client stream"));
+        };
+        let (stream, info) = tls.accept(client_stream, &self.frontend_name, self.handshake_timeout).await?;
+        debug!("TLS handshake with {} on frontend {}: {} {} alpn={}", self.connection.client_addr, self.frontend_name,
+               info.version, info.cipher, info.alpn.as_deref().unwrap_or("-"));
+        self.connection.tls_info = Some(info);
+        self.client_stream = Some(stream);
+        Ok(Step::Next)
+    }
+
+    async fn inspect(&mut self) -> Result<Step> {
+        let Self {
+            client_stream, connection, guard, features_manager, frontend_name, payload_inspection, request_timeout,
+            inspect_buffers, connect_auth, http_mode, connect_mode, accept_invalid, request_prefix, overflowed, connect_request, ..
+        } = self;
+        let client_stream = Self::client_stream(client_stream);
+        let frontend_name = frontend_name.as_str();
+        let client_addr = connection.client_addr;
+        let inspect_bufsize = inspect_buffers.pool.capacity();
+
+        if let Some(inspection) = payload_inspection {
+            *overflowed = session::read_client_payload(client_stream, request_prefix, inspect_bufsize, inspection.needed, inspection.until_headers, inspection.until_client_hello, inspection.delay).await?;
+        }
+
+        if let Some(request_timeout) = *request_timeout {
+            match session::read_client_prefix(client_stream, request_prefix, inspect_bufsize, *http_mode || *connect_mode, request_timeout).await {
+                Ok(full) => *overflowed |= full,
+                Err(e) => {
+                    if matches!(SessionError::classify(&e), Some(SessionError::RequestTimeout(_))) {
+                        if features_manager.log_throttle.admit("request_timeout", frontend_name) {
+                            warn!("Client {} sent no request within {:?} on frontend {}", client_addr, request_timeout, frontend_name);
+                        }
+                        metrics::slowloris_expired(frontend_name);
+                        if let Some(ddos_protection) = &features_manager.ddos_protection {
+                            ddos_protection.record_suspicious(client_addr.ip());
+                        }
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        if *overflowed {
+            debug!("Inspect buffer of {} bytes filled by client {} on frontend {}", inspect_bufsize, client_addr, frontend_name);
+        }
+
+        if *http_mode && !validate_http_head(frontend_name, client_addr, *accept_invalid, &features_manager.log_throttle, request_prefix) {
+            let _ = client_stream.write_all(BAD_REQUEST_RESPONSE).await;
+            let _ = client_stream.shutdown().await;
+            guard.reject("invalid_request");
+            return Err(anyhow!("Invalid HTTP request on frontend '{}'", frontend_name));
+        }
+
+        if *connect_mode {
+            let request = match inspect::parse_connect_request(request_prefix) {
+                Ok(request) => request,
+                Err(violation) => {
+                    if features_manager.log_throttle.admit("invalid_http_request", frontend_name) {
+                        warn!("Client {} sent an invalid CONNECT request on frontend {} ({}), rejecting",
+                              client_addr, frontend_name, violation.as_str());
+                    }
+                    metrics::http_invalid_request(frontend_name, violation.as_str(), "rejected");
+                    let response = if violation == inspect::HttpViolation::NotConnect { METHOD_NOT_ALLOWED_RESPONSE } else { BAD_REQUEST_RESPONSE };
+                    let _ = client_stream.write_all(response).await;
+                    let _ = client_stream.shutdown().await;
+                    guard.reject("invalid_request");
+                    return Err(anyhow!("Invalid CONNECT request on frontend '{}': {}", frontend_name, violation.as_str()));
+                }
+            };
+            if let Some(auth) = connect_auth {
+                let header = inspect::extract_headers(&request_prefix[..request.head_len], "proxy-authorization");
+                if auth.authenticate(header.first().copied()).is_none() {
+                    debug!("Client {} failed proxy authentication on frontend {}", client_addr, frontend_name);
+                    metrics::connect_auth_failure(frontend_name);
+                    let challenge = auth::challenge("407 Proxy Authentication Required", "Proxy-Authenticate", auth.name(),
+                                                    "You need valid credentials to use this proxy.");
+                    let _ = client_stream.write_all(&challenge).await;
+                    let _ = client_stream.shutdown().await;
+                    guard.reject("unauthorized");
+                    return Ok(Step::Done);
+                }
+            }
+            *connect_request = Some(request);
+        }
+        Ok(Step::Next)
+    }
+
+
+    async fn route(&mut self) -> Result<Step> {
+        let Self {
+            client_stream, connection, guard, frontends, backends, features_manager, frontend_name, frontend_config, routing,
+            inspect_buffers, rate_limit_key, http_mode, connect_mode, accept_invalid, request_prefix, overflowed, connect_request,
+            policy, routed, ..
+        } = self;
+        let frontend_name = frontend_name.as_str();
+        let client_addr = connection.client_addr;
+        let connect_target = connect_request.as_ref().map(|request| (request.host.as_str(), request.port));
+
+        let server_name = || connection.tls_info.as_ref()
+            .and_then(|info| info.sni.clone())
+            .or_else(|| inspect::extract_server_name(request_prefix));
+        let rate_key = match rate_limit_key {
+            Some(RateLimitKey::Sni) => Some(server_name()
+                .map(|name| RateKey::name(&name))
+                .unwrap_or(RateKey::Ip(client_addr.ip()))),
+            Some(RateLimitKey::SrcAndSni) => Some(server_name()
+                .map(|name| RateKey::ip_and_name(client_addr.ip(), &name))
+                .unwrap_or(RateKey::Ip(client_addr.ip()))),
+            _ => None,
+        };
+        if let Some(rate_key) = rate_key {
+            ProxyServer::check_rate_limit(features_manager, guard, rate_key)?;
+        }
+
+        if let Some(reverse_dns) = features_manager.reverse_dns.as_ref().filter(|_| routing.acls.uses_src_dn()) {
+            connection.src_dn = reverse_dns.lookup(client_addr.ip()).await;
+        }
+
+        *policy = http_mode.then(|| HttpRequestPolicy {
+            frontend: frontend_name.to_string(),
+            connection: connection.clone(),
+            routing: Arc::clone(routing),
+            accept_invalid: *accept_invalid,
+            log_throttle: Arc::clone(&features_manager.log_throttle),
+        });
+        let stream = Self::client_stream(client_stream);
+        let acl_context = connection.acl_context(request_prefix).with_connect(connect_target);
+        if let Some(realm) = ProxyServer::auth_challenge(frontend_name, routing, &acl_context, &features_manager.log_throttle) {
+            debug!("Client {} must authenticate on frontend {}", client_addr, frontend_name);
+            metrics::http_auth_challenge(frontend_name);
+            let challenge = auth::challenge("401 Unauthorized", "WWW-Authenticate", realm,
+                                            "You need a valid user and password to access this content.");
+            let _ = stream.write_all(&challenge).await;
+            let _ = stream.shutdown().await;
+            guard.reject("unauthorized");
+            return Ok(Step::Done);
+        }
+        if let Some(response) = ProxyServer::http_return(frontend_name, routing, &acl_context, &features_manager.log_throttle).cloned() {
+            match ProxyServer::serve_return(stream, request_prefix, &response, None, frontend_config, features_manager, policy.as_ref()).await? {
+                Some(forward) => {
+                    request_prefix.clear();
+                    request_prefix.extend_from_slice(&forward);
+                }
+                None => return Ok(Step::Done),
+            }
+        }
+        let acl_context = connection.acl_context(request_prefix).with_connect(connect_target);
+        let (backend, rule) = match ProxyServer::select_backend(frontend_name, routing, backends, &acl_context, request_prefix, &features_manager.log_throttle) {
+            Some(selected) => {
+                if *overflowed {
+                    metrics::inspect_buffer_overflow(frontend_name, "matched");
+                }
+                selected
+            }
+            None if *overflowed && inspect_buffers.overflow == InspectOverflow::Reject => {
+                if features_manager.log_throttle.admit("inspect_overflow", frontend_name) {
+                    warn!("Client {} filled the {} byte inspect buffer on frontend {} without matching a rule, rejecting",
+                          client_addr, inspect_buffers.pool.capacity(), frontend_name);
+                }
+                metrics::inspect_buffer_overflow(frontend_name, "reject");
+                metrics::routing_no_match(frontend_name);
+                guard.reject("inspect_overflow");
+                return Err(anyhow!("Inspect buffer overflow on frontend '{}'", frontend_name));
+            }
+            None => {
+                if *overflowed {
+                    metrics::inspect_buffer_overflow(frontend_name, "route");
+                }
+                match frontend_config.default_backend.clone() {
+                    Some(backend_name) => (backend_name, RoutingRule::DefaultBackend),
+                    None if *connect_mode && frontend_config.on_no_backend.is_none() => {
+                        if let Some((host, port)) = connect_target {
+                            debug!("CONNECT to {}:{} from {} forbidden on frontend {}", host, port, client_addr, frontend_name);
+                        }
+                        metrics::routing_no_match(frontend_name);
+                        metrics::no_backend(frontend_name, "forbidden");
+                        let _ = stream.write_all(FORBIDDEN_RESPONSE).await;
+                        let _ = stream.shutdown().await;
+                        guard.reject("forbidden");
+                        return Ok(Step::Done);
+                    }
+                    None => {
+                        metrics::routing_no_match(frontend_name);
+                        guard.reject("no_backend");
+                        let stream = client_stream.take().expect("the client stream stays in place until the forward stage");
+                        ProxyServer::close_unrouted(stream, connection, frontends, frontend_config, features_manager).await;
+                        return Ok(Step::Done);
+                    }
+                }
+            }
+        };
+        let rule = rule.label();
+        guard.routed(&backend, &rule);
+        let request_time = guard.accepted().elapsed();
+
+        if *rate_limit_key == Some(RateLimitKey::Backend) {
+            ProxyServer::check_rate_limit(features_manager, guard, RateKey::name(&backend))?;
+        }
+        *routed = Some(Routed { backend, rule, request_time });
+        Ok(Step::Next)
+    }
+
+    async fn forward(&mut self) -> Result<Step> {
+        let (Some(mut client_stream), Some(Routed { backend: backend_name, rule, request_time })) = (self.client_stream.take(), self.routed.take()) else {
+            return Err(anyhow!("Forward stage reached without a routed client"));
+        };
+        let Self {
+            connection, guard, backends, server_statuses, features_manager, frontend_name, frontend_config, log_format,
+            inspect_buffers, frontend_bwlim, connect_auth, http_mode, connect_mode, request_prefix, connect_request, policy, ..
+        } = self;
+        let frontend_name = frontend_name.as_str();
+        let client_addr = connection.client_addr;
+        let connect_target = connect_request.as_ref().map(|request| (request.host.as_str(), request.port));
+
+        let (backend_sessions, health_response, http_return, use_original_dst, connect_direct) = backends.get(&backend_name)
+            .map(|backend_state| (Arc::clone(&backend_state.sessions), backend_state.health_response, backend_state.http_return.clone(),
+                                  backend_state.use_original_dst, backend_state.connect_direct))
+            .ok_or_else(|| anyhow!("Backend '{}' not found", backend_name))?;
+        let direct = use_original_dst || connect_direct;
+
+        match &*connect_request {
+            Some(request) if connect_direct => {
+                request_prefix.drain(..request.head_len);
+            }
+            Some(request) if connect_auth.is_some() => {
+                let stripped = inspect::strip_header(request_prefix, request.head_len, "proxy-authorization");
+                request_prefix.clear();
+                request_prefix.extend_from_slice(&stripped);
+            }
+            _ => {}
+        }
+
+        if let Some(response) = health_response {
+            metrics::health_probe(&backend_name);
+            let dontlognull = frontend_config.options.as_ref()
+                .map(|options| options.http_options.dontlognull)
+                .unwrap_or(true);
+            if !dontlognull {
+                debug!("Health probe from {} answered by backend '{}'", client_addr, backend_name);
+            }
+            let result = match client_stream.write_all(response).await {
+                Ok(()) => client_stream.shutdown().await,
+                Err(e) => Err(e),
+            };
+            return result.map(|()| Step::Done).map_err(Into::into);
+        }
+
+        if let Some(response) = http_return {
+            ProxyServer::serve_return(&mut client_stream, request_prefix, &response, Some(&backend_name), frontend_config, features_manager, policy.as_ref()).await?;
+            return Ok(Step::Done);
+        }
+
+        if let Some(ddos_protection) = &features_manager.ddos_protection {
+            if !ddos_protection.check_rate_limit(client_addr.ip()) {
+                if features_manager.log_throttle.admit("ddos_rate_limit", frontend_name) {
+                    warn!("DDoS protection: rate limit exceeded for client {} on frontend {}", client_addr.ip(), frontend_name);
+                }
+                guard.reject("ddos_rate_limit");
+                return Err(anyhow!("DDoS protection: rate limit exceeded"));
+            }
+        }
+
+        let counters = SessionCounters::new();
+        let queued = Instant::now();
+        let backend_session = backend_sessions.enter().await;
+        counters.record_wait(queued.elapsed());
+        let _backend_session = match backend_session {
+            Some(session) => session,
+            None => {
+                let action = backend_sessions.action;
+                if features_manager.log_throttle.admit("backend_full", frontend_name) {
+                    warn!("Backend '{}' reached fullconn {}, {} client {}", backend_name,
+                          backend_sessions.fullconn.unwrap_or(0), action.as_str(), client_addr);
+                }
+                metrics::backend_fullconn_reject(&backend_name, action.as_str());
+                if action == FullconnAction::Errorfile {
+                    let _ = client_stream.write_all(FULLCONN_RESPONSE).await;
+                    let _ = client_stream.shutdown().await;
+                }
+                guard.reject("backend_full");
+                return Err(anyhow!("Backend '{}' is full", backend_name).context(ErrorTarget::backend(&backend_name)));
+            }
+        };
+
+        let (mut server, timeouts, connect_race_delay, copy_yield_bytes, retry_policy, session_keys, sticky, backend_bwlim, name_headers) = {
+            let mut backend_state = backends.get_mut(&backend_name)
+                .ok_or_else(|| anyhow!("Backend '{}' not found", backend_name))?;
+            let timeouts = SessionTimeouts::new(frontend_config.options.as_ref(), backend_state.config.options.as_ref());
+            let sticky = backend_state.stick_table.is_some();
+            let session_keys = if sticky {
+                inspect::extract_tls_session_keys(request_prefix)
+            } else {
+                Vec::new()
+            };
+            let server = if use_original_dst {
+                connection.original_dst_server()
+            } else if connect_direct {
+                connect_target.map(|(host, port)| direct_server(CONNECT_DST_SERVER, host, port))
+                    .ok_or_else(|| anyhow!("Backend '{}' uses 'option connect-direct' without a CONNECT request", backend_name))
+            } else {
+                ProxyServer::select_server(&mut backend_state, server_statuses, session_keys.clone())
+            }.map_err(|e| e.context(ErrorTarget::backend(&backend_name)))?;
+            let retry_policy = RetryPolicy::new(&backend_state.config);
+            let name_headers = (backend_state.config.send_name_header.clone(), backend_state.config.send_backend_header.clone());
+            (server, timeouts, backend_state.connect_race_delay, backend_state.copy_yield_bytes, retry_policy, session_keys, sticky, backend_state.bwlim.clone(), name_headers)
+        };
+
+        let mut logger = RequestLogger::new(
+            client_addr,
+            frontend_name.to_string(),
+            backend_name.clone(),
+            server.name.clone(),
+        )
+        .with_addresses(
+            client_stream.local_addr().ok(),
+            server.endpoint().parse().ok(),
+        )
+        .with_original_dst(connection.original_dst)
+        .with_listener(connection.listener.clone())
+        .with_tls(connection.tls_info.clone())
+        .with_country(features_manager.geoip.enabled().then_some(connection.country))
+        .with_rule(rule)
+        .with_log_format(log_format.take())
+        .with_accept_time(guard.accepted(), request_time);
+
+        logger.log_request_start();
+        guard.proxied(&server);
+
+        let counters = counters.with_throttles(
+            Throttle::new([Arc::clone(&frontend_bwlim.inbound), Arc::clone(&backend_bwlim.inbound)]),
+            Throttle::new([Arc::clone(&frontend_bwlim.outbound), Arc::clone(&backend_bwlim.outbound)]),
+        ).with_copy_yield_bytes(copy_yield_bytes)
+        .with_request_time(guard.accepted(), request_time)
+        .with_drain(match policy.take() {
+            Some(policy) => {
+                let checked = inspect::head_complete(request_prefix);
+                features_manager.drain.watch(*http_mode).with_policy(Arc::new(policy), checked as u64)
+            }
+            None => features_manager.drain.watch(*http_mode),
+        });
+        let mut retries = 0;
+        let mut redispatched = false;
+        let mut replay = (retry_policy.conn_failure && !connect_direct).then(|| ReplayBuffer::new(request_prefix, inspect_buffers.pool.capacity()));
+        let connect_deadline = tokio::time::Instant::now() + backend_sessions.connect_queue;
+        let request_headers = |server_name: &str| -> Vec<(String, String)> {
+            let (ref name_header, ref backend_header) = name_headers;
+            name_header.iter().map(|header| (header.clone(), server_name.to_string()))
+                .chain(backend_header.iter().map(|header| (header.clone(), backend_name.clone())))
+                .collect()
+        };
+        let connected = loop {
+            counters.reset_exchange(request_headers(&server.name));
+            match backend_sessions.connect_slot(connect_deadline).await {
+                Ok(waited) => counters.record_wait(waited),
+                Err(e) => break Err(e),
+            }
+            let error = match ProxyServer::connect_server(features_manager, &backend_name, &server, &timeouts, connect_race_delay).await {
+                Err(e) => e,
+                Ok(mut server_stream) => match replay {
+                    None => break Ok((server_stream, Vec::new())),
+                    Some(ref mut replay) => match session::replay_handshake(&mut client_stream, &mut server_stream, replay, &timeouts, &counters).await {
+                        Ok(response) => break Ok((server_stream, response)),
+                        Err(e) if matches!(SessionError::classify(&e), Some(SessionError::ServerAborted)) => e,
+                        Err(e) => break Err(e),
+                    },
+                },
+            };
+            if retries >= retry_policy.retries {
+                break Err(error);
+            }
+
+            retries += 1;
+            debug!("Backend {}: connect to server {} failed ({:#}), retry {}/{}",
+                   backend_name, server.name, error, retries, retry_policy.retries);
+            metrics::connect_retry(&backend_name, &server.name, server.id.unwrap_or(0));
+            if direct || !retry_policy.redispatch_on(retries) {
+                continue;
+            }
+
+            match ProxyServer::redispatch_server(backends, server_statuses, &backend_name, &server.name, &session_keys) {
+                Ok(next) => {
+                    debug!("Backend {}: redispatching from server {} to {}", backend_name, server.name, next.name);
+                    metrics::redispatch(&backend_name);
+                    ProxyServer::record_outcome(backends, &backend_name, &server.name, guard.proxied_for().unwrap_or_default(), false);
+                    ProxyServer::release_server(backends, &backend_name, &server.name);
+                    server = next;
+                    redispatched = true;
+                    guard.redispatched(&server);
+                    logger.set_server(server.name.clone(), server.endpoint().parse().ok());
+                }
+                Err(e) => debug!("Backend {}: redispatch from server {} failed: {}", backend_name, server.name, e),
+            }
+        };
+        logger.set_retries(retries, redispatched);
+
+        let learn_session = |response: &[u8]| {
+            if let Some(key) = inspect::extract_server_hello_session_key(response) {
+                if let Some(mut backend_state) = backends.get_mut(&backend_name) {
+                    backend_state.stick_sessions([key], &server.name);
+                }
+            }
+        };
+        let inspect_response: Option<ResponseInspector> = if sticky { Some(&learn_session) } else { None };
+
+        let result = match connected {
+            Ok((server_stream, mut response_prefix)) => {
+                counters.record_connect();
+                if connect_direct {
+                    response_prefix.splice(0..0, CONNECT_ESTABLISHED_RESPONSE.iter().copied());
+                }
+                let request_prefix: &[u8] = match replay {
+                    Some(ref replay) => {
+                        counters.record(Side::Client, replay.size());
+                        &[]
+                    }
+                    None => request_prefix,
+                };
+                let fast_open_probe = if server.tfo == Some(true) { SockRef::from(&server_stream).try_clone().ok() } else { None };
+                let result = ProxyServer::proxy_connection(client_stream, server_stream, request_prefix, &response_prefix, inspect_response, &timeouts, &counters).await;
+                if fast_open_probe.is_some_and(|probe| utils::fast_open_used(&probe).unwrap_or(false)) {
+                    metrics::fast_open_connected(&backend_name, &server.name);
+                }
+                result
+            }
+            Err(e) => {
+                if *connect_mode {
+                    let _ = client_stream.write_all(FULLCONN_RESPONSE).await;
+                    let _ = client_stream.shutdown().await;
+                }
+                Err(e)
+            }
+        };
+        guard.transferred(&counters);
+        if !direct {
+            ProxyServer::record_outcome(backends, &backend_name, &server.name, guard.proxied_for().unwrap_or_default(), result.is_ok());
+            ProxyServer::record_bytes(backends, &backend_name, &server.name, &counters);
+            ProxyServer::release_server(backends, &backend_name, &server.name);
+        }
+        metrics::session_phases(request_time, counters.wait_time_ms(), counters.connect_time_ms(),
+                                counters.response_time_ms(), guard.accepted().elapsed());
+        if counters.connect_time_ms().is_some() {
+            metrics::session_buffer_high_water(frontend_name, counters.pending_high_water(Side::Client), counters.pending_high_water(Side::Server));
+        }
+
+        match result {
+            Ok(()) => {
+                logger.log_request_end("success", "--", &counters);
+                guard.completed();
+                Ok(Step::Done)
+            }
+            Err(e) => {
+                let session_error = SessionError::classify(&e);
+                let connected = counters.connect_time_ms().is_some();
+                let termination_state = session_error.map(|se| se.termination_state())
+                    .unwrap_or(if connected { "--" } else { "SC" });
+                let error_type = session_error.map(|se| se.error_type())
+                    .unwrap_or_else(|| match ConnectFailure::classify(&e) {
+                        ConnectFailure::Other => "connection_failed",
+                        failure => failure.as_str(),
+                    });
+                logger.set_error(error_type);
+                logger.log_request_end("failure", termination_state, &counters);
+                guard.reject(error_type);
+                Err(e.context(ErrorTarget::server(&backend_name, &server.name)))
+            }
+        }
+    }
+}
+
+struct ProxyStats {
+    frontends: Arc<DashMap<String, FrontendState>>,
+    backends: Arc<DashMap<String, BackendState>>,
+    server_statuses: Arc<StatusTable>,
+    maxconn: Arc<RuntimeLimit>,
+    fds: Arc<FdLimits>,
+    process: Arc<ProcessStats>,
+    drain: Arc<DrainState>,
+}
+
+impl ProxyStats {
+    fn frontend_rows(frontend_state: &FrontendState) -> Vec<StatRow> {
+        let config = &frontend_state.config;
+        let iid = frontend_state.index + 1;
+        let mode = config.mode.clone().unwrap_or_else(|| "tcp".to_string());
+        let listeners: Vec<StatRow> = frontend_state.listeners.iter()
+            .enumerate()
+            .map(|(index, listener)| {
+                let mut row = StatRow::new(StatKind::Listener, &config.name, &listener.name, iid, "OPEN");
+                row.sid = Some(index as u32 + 1);
+                row.scur = Some(listener.counters.current.load(Ordering::Relaxed));
+                row.stot = Some(listener.counters.total.load(Ordering::Relaxed));
+                row.bin = Some(listener.counters.bytes_in.load(Ordering::Relaxed));
+                row.bout = Some(listener.counters.bytes_out.load(Ordering::Relaxed));
+                row.addr = listener.socket.local_addr().ok().map(|addr| addr.to_string());
+                row.mode = Some(mode.clone());
+                row
+            })
+            .collect();
+
+        let status = if listeners.is_empty() { "STOP" } else { "OPEN" };
+        let mut row = StatRow::new(StatKind::Frontend, &config.name, "FRONTEND", iid, status);
+        row.slim = Some(frontend_state.maxconn.get()).filter(|maxconn| *maxconn > 0);
+        row.bwlim_in = Some(frontend_state.bwlim.inbound.rate()).filter(|rate| *rate > 0);
+        row.bwlim_out = Some(frontend_state.bwlim.outbound.rate()).filter(|rate| *rate > 0);
+        row.scur = Some(listeners.iter().filter_map(|listener| listener.scur).sum());
+        row.stot = Some(listeners.iter().filter_map(|listener| listener.stot).sum());
+        row.bin = Some(listeners.iter().filter_map(|listener| listener.bin).sum());
+        row.bout = Some(listeners.iter().filter_map(|listener| listener.bout).sum());
+        row.rate = Some(frontend_state.rates.connections.rate());
+        row.rate_lim = config.rate_limit_sessions;
+        row.rate_max = Some(frontend_state.rates.connections.rate_max());
+        row.mode = Some(mode);
+        std::iter::once(row).chain(listeners).collect()
+    }
+
+    fn server_row(backend_state: &BackendState, server: &ServerState, health: &ServerStatus, last_failure: Option<ConnectFailure>, iid: usize) -> StatRow {
+        let state = backend_state.load_balancer.effective_state(server, health);
+        let backup = server.config.backup.unwrap_or(false);
+        let checked = server.config.check == Some(true);
+        let mut row = StatRow::new(StatKind::Server, &backend_state.config.name, &server.config.name, iid, state.stat_status());
+        row.state = Some(state.as_str().to_string());
+        row.sid = Some(server.id);
+        row.scur = Some(server.active_connections);
+        row.slim = server.config.maxconn;
+        row.bin = Some(server.bytes_in);
+        row.bout = Some(server.bytes_out);
+        row.weight = Some(server.weight);
+        row.act = Some(u32::from(!backup));
+        row.bck = Some(u32::from(backup));
+        let httpchk = backend_state.config.options.as_ref()
+            .is_some_and(|options| options.http_options.httpchk.is_some());
+        row.check_status = checked.then(|| match last_failure {
+            None if httpchk => "L7OK",
+            None => "L4OK",
+            Some(ConnectFailure::TimedOut) => "L4TOUT",
+            Some(ConnectFailure::Other) if httpchk => "L7STS",
+            Some(_) => "L4CON",
+        }.to_string());
+        row.check_desc = last_failure.filter(|_| checked).map(|failure| failure.as_str().to_string());
+        row.check_rise = checked.then(|| server.config.rise.unwrap_or(2));
+        row.check_fall = checked.then(|| server.config.fall.unwrap_or(3));
+        row.addr = Some(server.config.endpoint());
+        row.mode = Some(backend_state.config.mode.clone().unwrap_or_else(|| "tcp".to_string()));
+        row
+    }
+}
+
+impl StatsSource for ProxyStats {
+    fn rows(&self) -> Vec<StatRow> {
+        let mut frontends: Vec<(usize, Vec<StatRow>)> = self.frontends.iter()
+            .map(|frontend_state| (frontend_state.index, Self::frontend_rows(&frontend_state)))
+            .collect();
+        frontends.sort_by_key(|(index, _)| *index);
+        let backend_offset = frontends.len() + 1;
+
+        let statuses = self.server_statuses.snapshot();
+        let mut backends: Vec<(usize, Vec<StatRow>)> = self.backends.iter()
+            .map(|backend_state| {
+                let config = &backend_state.config;
+                let iid = backend_offset + backend_state.index;
+                let mut rows: Vec<StatRow> = backend_state.load_balancer.servers().iter()
+                    .map(|server| {
+                        let health = statuses.status(backend_state.index, server.index)
+                            .unwrap_or_else(|| server.status.clone());
+                        let last_failure = statuses.last_failure(backend_state.index, server.index);
+                        let mut row = Self::server_row(&backend_state, server, &health, last_failure, iid);
+                        if row.check_status.is_some() {
+                            row.check_duration = statuses.check_duration(backend_state.index, server.index)
+                                .map(|duration| duration.as_millis() as u64);
+                        }
+                        row
+                    })
+                    .collect();
+
+                let usable: Vec<&StatRow> = rows.iter()
+                    .filter(|row| EffectiveServerState::ALL.iter()
+                        .any(|state| state.is_usable() && row.state.as_deref() == Some(state.as_str())))
+                    .collect();
+                let status = if usable.is_empty() { "DOWN" } else { "UP" };
+                let mut row = StatRow::new(StatKind::Backend, &config.name, "BACKEND", iid, status);
+                row.scur = Some(backend_state.sessions.current.load(Ordering::Relaxed));
+                row.slim = config.fullconn;
+                row.bin = Some(rows.iter().filter_map(|row| row.bin).sum());
+                row.bout = Some(rows.iter().filter_map(|row| row.bout).sum());
+                row.weight = Some(usable.iter().filter_map(|row| row.weight).sum());
+                row.act = Some(usable.iter().filter_map(|row| row.act).sum());
+                row.bck = Some(usable.iter().filter_map(|row| row.bck).sum());
+                row.mode = Some(config.mode.clone().unwrap_or_else(|| "tcp".to_string()));
+                row.algo = Some(config.balance.clone().unwrap_or_else(|| "roundrobin".to_string()));
+                row.bwlim_in = Some(backend_state.bwlim.inbound.rate()).filter(|rate| *rate > 0);
+                row.bwlim_out = Some(backend_state.bwlim.outbound.rate()).filter(|rate| *rate > 0);
+                rows.push(row);
+                (backend_state.index, rows)
+            })
+            .collect();
+        backends.sort_by_key(|(index, _)| *index);
+
+        frontends.into_iter()
+            .chain(backends)
+            .flat_map(|(_, rows)| rows)
+            .collect()
+    }
+
+    fn acls(&self) -> Vec<AclStat> {
+        let mut frontends: Vec<(usize, Vec<AclStat>)> = self.frontends.iter()
+            .map(|frontend_state| (frontend_state.index, frontend_state.routing.acls.stats(frontend_state.key())))
+            .collect();
+        frontends.sort_by_key(|(index, _)| *index);
+        frontends.into_iter().flat_map(|(_, acls)| acls).collect()
+    }
+
+    fn info(&self) -> Vec<InfoField> {
+        let process = &self.process;
+        let uptime = process.uptime();
+        let mut fields = vec![
+            InfoField::text("Name", "turbogate"),
+            InfoField::text("Version", env!("CARGO_PKG_VERSION")),
+            InfoField::number("Pid", std::process::id() as u64),
+            InfoField::text("Uptime", stats::format_uptime(uptime)),
+            InfoField::number("Uptime_sec", uptime.as_secs()),
+        ];
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let runtime_metrics = runtime.metrics();
+            fields.push(InfoField::number("Nbthread", runtime_metrics.num_workers() as u64));
+            fields.push(InfoField::number("Tasks", runtime_metrics.num_alive_tasks() as u64));
+            fields.push(InfoField::number("Run_queue", runtime_metrics.global_queue_depth() as u64));
+        }
+        fields.extend([
+            InfoField::number("Ulimit-n", self.fds.limit()),
+            InfoField::number("Maxsock", self.fds.wanted()),
+            InfoField::number("Maxconn", self.maxconn.get() as u64),
+            InfoField::number("Hard_maxconn", self.maxconn.configured() as u64),
+            InfoField::number("CurrConns", process.current()),
+            InfoField::number("PeakConns", process.peak()),
+            InfoField::number("CumConns", process.total()),
+            InfoField::number("CumDenied", process.denied()),
+            InfoField::number("ConnRate", self.frontends.iter().map(|frontend_state| frontend_state.rates.connections.rate()).sum()),
+            InfoField::number("MaxConnRate", process.conn_rate_max()),
+            InfoField::number("Reloads", process.reloads()),
+            InfoField::number("Stopping", self.drain.is_draining() as u64),
+        ]);
+        if let Some(pool) = self.frontends.iter().next().map(|frontend_state| Arc::clone(&frontend_state.inspect_buffers.pool)) {
+            fields.push(InfoField::number("Inspect_bufsize", pool.capacity() as u64));
+            fields.push(InfoField::number("Inspect_buffers_used", pool.in_use() as u64));
+            fields.push(InfoField::number("Inspect_buffers_idle", pool.idle() as u64));
+        }
+        if let Ok(rss) = utils::resident_memory() {
+            fields.push(InfoField::number("Rss_bytes", rss));
+        }
+        fields
+    }
+
+    fn draining(&self) -> bool {
+        self.drain.is_draining()
+    }
+
+    fn ready(&self) -> bool {
+        self.process.is_ready()
+    }
+}
+
+struct ProxyPeers {
+    backends: Arc<DashMap<String, BackendState>>,
+    events: Arc<EventBus>,
+}
+
+impl PeerStore for ProxyPeers {
+    fn apply(&self, section: &str, update: PeerUpdate) -> Result<bool> {
+        let backend = update.backend().to_string();
+        let mut backend_state = self.backends.get_mut(&backend)
+            .ok_or_else(|| anyhow!("Backend '{}' not found", backend))?;
+        if backend_state.peers.as_ref().map(|peers| peers.name()) != Some(section) {
+            return Err(anyhow!("Backend '{}' does not share its state with peers '{}'", backend, section));
+        }
+
+        match update {
+            PeerUpdate::StickStore { key, server, expires_in_ms, updated, .. } => {
+                let index = backend_state.load_balancer.servers().iter()
+                    .find(|s| s.config.name == server)
+                    .map(|s| s.index)
+                    .ok_or_else(|| anyhow!("Server '{}' not found in backend '{}'", server, backend))?;
+                let stick_table = backend_state.stick_table.as_mut()
+                    .ok_or_else(|| anyhow!("Backend '{}' has no stick-table", backend))?;
+                Ok(stick_table.apply(key, index, Duration::from_millis(expires_in_ms), updated))
+            }
+            PeerUpdate::StickForget { key, updated, .. } => {
+                let stick_table = backend_state.stick_table.as_mut()
+                    .ok_or_else(|| anyhow!("Backend '{}' has no stick-table", backend))?;
+                Ok(stick_table.forget_before(&key, updated))
+            }
+            PeerUpdate::ServerState { server, state, updated, .. } => {
+                let state = AdminState::parse(&state)?;
+                let previous_state = backend_state.load_balancer.servers().iter()
+                    .find(|s| s.config.name == server)
+                    .map(|s| s.admin_state);
+                let Some(server_state) = backend_state.load_balancer.apply_admin_state(&server, state, updated)? else {
+                    return Ok(false);
+                };
+                info!("Server {}/{} set to state {} by peers {}", backend, server, state.as_str(), section);
+                report_admin_state(&self.events, &backend, server_state, previous_state);
+                Ok(true)
+            }
+        }
+    }
+
+    fn snapshot(&self, section: &str) -> Vec<PeerUpdate> {
+        let mut updates = Vec::new();
+        for backend_state in self.backends.iter() {
+            if backend_state.peers.as_ref().map(|peers| peers.name()) != Some(section) {
+                continue;
+            }
+            let servers = backend_state.load_balancer.servers();
+            if let Some(ref stick_table) = backend_state.stick_table {
+                for entry in stick_table.snapshot() {
+                    let Some(server) = servers.get(entry.server) else {
+                        continue;
+                    };
+                    updates.push(PeerUpdate::StickStore {
+                        backend: backend_state.config.name.clone(),
+                        key: entry.key.to_vec(),
+                        server: server.config.name.clone(),
+                        expires_in_ms: entry.expires_in.as_millis() as u64,
+                        updated: entry.updated,
+                    });
+                }
+            }
+            for server in servers.iter().filter(|server| server.admin_updated > 0) {
+                updates.push(PeerUpdate::ServerState {
+                    backend: backend_state.config.name.clone(),
+                    server: server.config.name.clone(),
+                    state: server.admin_state.as_str().to_string(),
+                    updated: server.admin_updated,
+                });
+            }
+        }
+        updates
+    }
+}
+
+struct ProxyAdmin {
+    frontends: Arc<DashMap<String, FrontendState>>,
+    backends: Arc<DashMap<String, BackendState>>,
+    server_statuses: Arc<StatusTable>,
+    stats: Arc<ProxyStats>,
+    maxconn: Arc<RuntimeLimit>,
+    events: Arc<EventBus>,
+    errors: Arc<ErrorLog>,
+    drain: Arc<DrainState>,
+    audit: Arc<AuditLog>,
+    metrics_server: MetricsHandle,
+    config_api: ConfigApi,
+}
+
+#[async_trait]
+impl AdminHandler for ProxyAdmin {
+    async fn handle(&self, command: AdminCommand, source: AuditSource) -> Result<String> {
+        let entry = command.audit_entry(source);
+        let result = self.apply(command).await;
+        if let Some(entry) = entry {
+            self.audit.record(entry.outcome(&result));
+        }
+        result
+    }
+}
+
+impl ProxyAdmin {
+    async fn apply(&self, command: AdminCommand) -> Result<String> {
+        match command {
+            AdminCommand::SetServerState { backend, server, state } => {
+                let mut backend_state = self.backends.get_mut(&backend)
+                    .ok_or_else(|| anyhow!("Backend '{}' not found", backend))?;
+                let previous_state = backend_state.load_balancer.servers().iter()
+                    .find(|s| s.config.name == server)
+                    .map(|s| s.admin_state);
+                let server_state = backend_state.load_balancer.set_admin_state(&server, state)?.clone();
+
+                info!("Server {}/{} set to state {}", backend, server, state.as_str());
+                report_admin_state(&self.events, &backend, &server_state, previous_state);
+                backend_state.publish_admin_state(&server_state);
+
+                Ok(String::new())
+            }
+            AdminCommand::SetWeight { backend, server, weight } => {
+                let mut backend_state = self.backends.get_mut(&backend)
+                    .ok_or_else(|| anyhow!("Backend '{}' not found", backend))?;
+                let server_state = backend_state.load_balancer.set_weight(&server, weight)?;
+
+                info!("Server {}/{} weight set to {}", backend, server, weight);
+                if weight == 0 && server_state.active_connections == 0 {
+                    log_server_drained(&backend, &server);
+                }
+                backend_state.report_effective_weights();
+
+                Ok(String::new())
+            }
+            AdminCommand::ShowServersState => {
+                let mut output = String::from("# backend sid server health admin_state active_connections check_inter last_failure_reason");
+                let statuses = self.server_statuses.snapshot();
+
+                for backend_state in self.backends.iter() {
+                    for server in backend_state.load_balancer.servers() {
+                        let health = statuses.status(backend_state.index, server.index)
+                            .unwrap_or_else(|| server.status.clone());
+                        let check_inter = statuses.check_interval(backend_state.index, server.index)
+                            .map(|interval| format!("{}ms", interval.as_millis()))
+                            .unwrap_or_else(|| "-".to_string());
+                        let last_failure = statuses.last_failure(backend_state.index, server.index)
+                            .map_or("-", |failure| failure.as_str());
+                        output.push_str(&format!("\n{} {} {} {} {} {} {} {}",
+                            backend_state.key(), server.id, server.config.name, health.as_str(),
+                            server.admin_state.as_str(), server.active_connections, check_inter, last_failure));
+                    }
+                }
+
+                Ok(output)
+            }
+            AdminCommand::ShowBalance { backend } => {
+                let backend_state = self.backends.get(&backend)
+                    .ok_or_else(|| anyhow!("Backend '{}' not found", backend))?;
+                let adaptive = backend_state.load_balancer.adaptive();
+                let mut output = format!("# adaptive {} metric {} half-life {}ms",
+                    if adaptive.enabled { "on" } else { "off" }, adaptive.metric.as_str(), adaptive.half_life.as_millis());
+                output.push_str("\n# server weight effective_weight tier health admin_state active_connections eligible reason latency_ms error_rate");
+
+                for decision in backend_state.load_balancer.explain() {
+                    output.push_str(&format!("\n{} {} {} {} {} {} {} {} {} {} {}",
+                        decision.name, decision.weight, decision.effective_weight, decision.tier, decision.status.as_str(), decision.admin_state.as_str(),
+                        decision.active_connections, decision.eligible, decision.reason,
+                        decision.latency_ms.map(|latency| format!("{:.1}", latency)).unwrap_or_else(|| "-".to_string()),
+                        decision.error_rate.map(|rate| format!("{:.3}", rate)).unwrap_or_else(|| "-".to_string())));
+                }
+
+                Ok(output)
+            }
+            AdminCommand::ShowAcl => {
+                let mut output = String::from("# frontend acl hits misses last_hit criterion");
+                for acl in self.stats.acls() {
+                    output.push_str(&format!("\n{} {} {} {} {} {}",
+                        acl.frontend, acl.acl, acl.hits, acl.misses,
+                        acl.last_hit.map(|last_hit| last_hit.to_rfc3339_opts(chrono::SecondsFormat::Millis, true))
+                            .unwrap_or_else(|| "-".to_string()),
+                        acl.criterion));
+                }
+                Ok(output)
+            }
+            AdminCommand::ShowStat => Ok(stats::to_csv(&self.stats.rows()).trim_end().to_string()),
+            AdminCommand::ShowInfo { json: false } => Ok(stats::info_to_text(&self.stats.info())),
+            AdminCommand::ShowInfo { json: true } => Ok(stats::info_to_json(&self.stats.info())),
+            AdminCommand::SetMaxconn { target: MaxconnTarget::Global, value } => {
+                if value == 0 {
+                    return Err(anyhow!("Global maxconn must be positive"));
+                }
+                self.maxconn.set(value);
+                info!("Global maxconn set to {} at runtime", value);
+                Ok(String::new())
+            }
+            AdminCommand::SetMaxconn { target: MaxconnTarget::Frontend(frontend), value } => {
+                let frontend_state = self.frontends.get(&frontend)
+                    .ok_or_else(|| anyhow!("Frontend '{}' not found", frontend))?;
+                frontend_state.maxconn.set(value);
+                info!("Frontend {} maxconn set to {} at runtime", frontend, value);
+                Ok(String::new())
+            }
+            AdminCommand::ShowErrors { scope } => {
+                let mut output = String::from("# timestamp client frontend backend server error termination_state message");
+                for entry in self.errors.recent(&scope) {
+                    output.push('\n');
+                    output.push_str(&entry.to_line());
+                }
+                Ok(output)
+            }
+            AdminCommand::ClearErrors => {
+                self.errors.clear();
+                Ok(String::new())
+            }
+            AdminCommand::UpdateSslCert { path } => {
+                let canonical = std::fs::canonicalize(&path)
+                    .map_err(|e| anyhow!("Cannot read certificate '{}': {}", path, e))?;
+                let mut updated: Vec<TlsTerminator> = Vec::new();
+                for frontend_state in self.frontends.iter() {
+                    for tls in frontend_state.listeners.iter().filter_map(|listener| listener.tls.as_ref()) {
+                        if updated.iter().any(|other| other.shares_certs(tls)) {
+                            continue;
+                        }
+                        if tls.update_cert(&canonical)? {
+                            info!("Certificate {} updated on frontend {}", path, frontend_state.key());
+                            updated.push(tls.clone());
+                        }
+                    }
+                }
+                if updated.is_empty() {
+                    return Err(anyhow!("No ssl bind uses certificate '{}'", path));
+                }
+                Ok(String::new())
+            }
+            AdminCommand::SetDraining { on: true } => {
+                if !self.drain.begin() {
+                    return Err(anyhow!("Already draining"));
+                }
+                Ok(String::new())
+            }
+            AdminCommand::SetDraining { on: false } => {
+                if !self.drain.end() {
+                    return Err(anyhow!("Not draining"));
+                }
+                Ok(String::new())
+            }
+            AdminCommand::ShowAudit { last } => {
+                let mut output = String::from("# timestamp source action target value result error");
+                for entry in self.audit.last(last) {
+                    output.push('\n');
+                    output.push_str(&entry.to_line());
+                }
+                Ok(output)
+            }
+            AdminCommand::ShowMetrics => {
+                let metrics_server = self.metrics_server.lock().await;
+                let metrics_server = metrics_server.as_ref().ok_or_else(|| anyhow!("Metrics are disabled"))?;
+                let mut output = String::from("# address status");
+                for listener in metrics_server.status() {
+                    output.push_str(&format!("\n{} {}", listener.address, if listener.running { "running" } else { "stopped" }));
+                }
+                Ok(output)
+            }
+            AdminCommand::SetMetricsBind { addresses } => {
+                let mut metrics_server = self.metrics_server.lock().await;
+                let metrics_server = metrics_server.as_mut().ok_or_else(|| anyhow!("Metrics are disabled"))?;
+                let mut config = metrics_server.config().clone();
+                config.binds = addresses.iter().map(|address| StatsBindConfig::new(address)).collect();
+                metrics_server.rebind(&config).await?;
+                Ok(String::new())
+            }
+            AdminCommand::SetMetrics { on } => {
+                let mut metrics_server = self.metrics_server.lock().await;
+                let metrics_server = metrics_server.as_mut().ok_or_else(|| anyhow!("Metrics are disabled"))?;
+                if on {
+                    let config = metrics_server.config().clone();
+                    metrics_server.rebind(&config).await?;
+                } else {
+                    metrics_server.disable();
+                }
+                Ok(String::new())
+            }
+            AdminCommand::SetBalanceAdaptive { backend, on } => {
+                let mut backend_state = self.backends.get_mut(&backend)
+                    .ok_or_else(|| anyhow!("Backend '{}' not found", backend))?;
+                backend_state.load_balancer.set_adaptive_enabled(on);
+                backend_state.report_effective_weights();
+                info!("Backend {} adaptive balancing turned {}", backend, if on { "on" } else { "off" });
+                Ok(String::new())
+            }
+            AdminCommand::AddBackend { backend, server, args } => {
+                let mut backend = BackendConfig::new(&backend, SourceSpan::runtime());
+                backend.server.push(Self::parse_server(&server, &args)?);
+                self.config_api.add_backend(backend).await?;
+                Ok(String::new())
+            }
+            AdminCommand::DelBackend { backend } => {
+                self.config_api.remove_backend(&backend).await?;
+                Ok(String::new())
+            }
+            AdminCommand::AddServer { backend, server, args } => {
+                self.config_api.add_server(&backend, Self::parse_server(&server, &args)?).await?;
+                Ok(String::new())
+            }
+            AdminCommand::DelServer { backend, server } => {
+                self.config_api.remove_server(&backend, &server).await?;
+                Ok(String::new())
+            }
+            AdminCommand::SetDefaultBackend { frontend, backend } => {
+                self.config_api.set_frontend_default_backend(&frontend, &backend).await?;
+                Ok(String::new())
+            }
+        }
+    }
+
+    fn parse_server(name: &str, args: &[String]) -> Result<ServerConfig> {
+        let parts: Vec<&str> = std::iter::once(name).chain(args.iter().map(String::as_str)).collect();
+        ServerConfig::parse(&parts, &SourceSpan::runtime())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acl::Acl;
+    use crate::config::AclConfig;
+    use std::sync::Barrier;
+
+    fn retry_policy(options: &str) -> RetryPolicy {
+        let config = Config::from_haproxy_config(&format!("backend be\n{options}    server s1 127.0.0.1:9001\n"), None).unwrap();
+        RetryPolicy::new(&config.backends[0])
+    }
+
+    fn redispatches(policy: RetryPolicy) -> Vec<u32> {
+        (1..=policy.retries).filter(|attempt| policy.redispatch_on(*attempt)).collect()
+    }
+
+    #[test]
+    fn positive_redispatch_intervals_repeat() {
+        assert_eq!(redispatches(retry_policy("    retries 5\n    option redispatch 1\n")), [1, 2, 3, 4, 5]);
+        assert_eq!(redispatches(retry_policy("    retries 5\n    option redispatch 2\n")), [2, 4]);
+    }
+
+    #[test]
+    fn negative_redispatch_intervals_count_back_from_the_last_retry() {
+        let policy = retry_policy("    retries 3\n    option redispatch\n");
+        assert_eq!(policy.redispatch, Some(-1));
+        assert_eq!(redispatches(policy), [3]);
+        assert_eq!(redispatches(retry_policy("    retries 3\n    option redispatch -2\n")), [2]);
+        assert_eq!(redispatches(retry_policy("    retries 3\n    option redispatch -9\n")), [1]);
+    }
+
+    #[test]
+    fn zero_or_missing_redispatch_interval_never_redispatches() {
+        let policy = retry_policy("    retries 3\n    option redispatch 0\n");
+        assert_eq!(policy.redispatch, None);
+        assert_eq!(redispatches(policy), Vec::<u32>::new());
+        assert_eq!(redispatches(retry_policy("    retries 3\n")), Vec::<u32>::new());
+    }
+
+    #[cfg(target_os = "linux")]
+    fn tcp_option(socket: &Socket, name: libc::c_int) -> libc::c_int {
+        use std::os::fd::AsRawFd;
+        let mut value: libc::c_int = 0;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(socket.as_raw_fd(), libc::IPPROTO_TCP, name,
+                             &mut value as *mut libc::c_int as *mut libc::c_void, &mut len)
+        };
+        assert_eq!(result, 0, "{}", std::io::Error::last_os_error());
+        value
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn tfo_and_defer_accept_are_set_on_listener_and_server_sockets() {
+        let config = Config::from_haproxy_config("frontend web\n    bind 127.0.0.1:8080 tfo backlog 64 defer-accept\n    bind 127.0.0.1:8081\n", None).unwrap();
+        let binds = &config.frontends[0].bind;
+
+        let socket = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+        ProxyServer::set_listener_options(&socket, &binds[0], None).unwrap();
+        assert_eq!(tcp_option(&socket, libc::TCP_FASTOPEN), 64);
+        assert!(tcp_option(&socket, libc::TCP_DEFER_ACCEPT) > 0);
+
+        let plain = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+        ProxyServer::set_listener_options(&plain, &binds[1], None).unwrap();
+        assert_eq!(tcp_option(&plain, libc::TCP_FASTOPEN), 0);
+        assert_eq!(tcp_option(&plain, libc::TCP_DEFER_ACCEPT), 0);
+
+        let outbound = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+        utils::set_fast_open_connect(&outbound).unwrap();
+        assert_eq!(tcp_option(&outbound, libc::TCP_FASTOPEN_CONNECT), 1);
+    }
+
+    fn routing(frontend: &str) -> CompiledRouting {
+        let config = Config::from_haproxy_config(&format!("frontend web\n    bind 127.0.0.1:8080\n{frontend}"), None).unwrap();
+        CompiledRouting::compile(&config.frontends[0], &[]).unwrap()
+    }
+
+    fn route(routing: &CompiledRouting) -> Option<(String, RoutingRule)> {
+        let context = AclContext::with_payload("192.0.2.1:40000".parse().unwrap(), b"");
+        let throttle = LogThrottle::new(&GlobalConfig::default());
+        ProxyServer::select_backend("web", routing, &DashMap::new(), &context, b"", &throttle)
+    }
+
+    #[test]
+    fn failing_acls_skip_their_rule() {
+        let mut rules = routing("    acl broken src 0.0.0.0/0\n    acl local src 192.0.2.0/24\n    \
+                                 use_backend b1 if broken\n    use_backend b3 unless broken\n    use_backend b2 if local\n");
+        assert_eq!(route(&rules), Some(("b1".to_string(), RoutingRule::UseBackend(0))));
+
+        rules.acls.make_failing("broken");
+        assert_eq!(route(&rules), Some(("b2".to_string(), RoutingRule::UseBackend(2))));
+
+        let mut only = routing("    acl broken src 0.0.0.0/0\n    use_backend b1 if broken\n");
+        only.acls.make_failing("broken");
+        assert_eq!(route(&only), None);
+    }
+
+    fn connection() -> ConnectionContext {
+        ConnectionContext {
+            client_addr: "192.0.2.1:40000".parse().unwrap(),
+            local_addr: None,
+            original_dst: None,
+            listener: "web".to_string(),
+            tls: None,
+            tls_info: None,
+            src_dn: None,
+            country: Country::UNKNOWN,
+            pipeline: Pipeline::default(),
+        }
+    }
+
+    // A stream whose SO_ORIGINAL_DST lookup returns the given address, or fails.
+    struct RedirectedTo(Option<SocketAddr>);
+
+    impl OriginalDst for RedirectedTo {
+        fn original_dst(&self) -> std::io::Result<SocketAddr> {
+            self.0.ok_or_else(|| std::io::ErrorKind::NotFound.into())
+        }
+    }
+
+    #[test]
+    fn transparent_binds_route_and_connect_on_the_original_destination() {
+        let client_addr = "192.0.2.1:40000".parse().unwrap();
+        let local_addr: SocketAddr = "127.0.0.1:15001".parse().unwrap();
+        let original_dst: SocketAddr = "203.0.113.10:8443".parse().unwrap();
+        let acl = |criterion: &str| Acl::from_config(&AclConfig {
+            name: "a".to_string(),
+            criterion: criterion.split_whitespace().map(str::to_string).collect(),
+            span: SourceSpan::default(),
+        }, &[]).unwrap();
+
+        let redirected = ConnectionContext::new(&RedirectedTo(Some(original_dst)), client_addr, Some(local_addr), true, "web");
+        assert_eq!(redirected.destination(), Some(original_dst));
+        assert!(acl("dst_port 8443").evaluate(&redirected.acl_context(b"")).unwrap());
+        assert!(acl("dst 203.0.113.0/24").evaluate(&redirected.acl_context(b"")).unwrap());
+        assert_eq!(redirected.original_dst_server().unwrap().endpoint(), "203.0.113.10:8443");
+
+        for (stream, transparent) in [(RedirectedTo(Some(original_dst)), false), (RedirectedTo(None), true)] {
+            let direct = ConnectionContext::new(&stream, client_addr, Some(local_addr), transparent, "web");
+            assert_eq!(direct.destination(), Some(local_addr));
+            assert!(!acl("dst_port 8443").evaluate(&direct.acl_context(b"")).unwrap());
+            assert!(direct.original_dst_server().is_err());
+        }
+
+        // Connections that were not redirected report the listener itself.
+        let unredirected = ConnectionContext::new(&RedirectedTo(Some(local_addr)), client_addr, Some(local_addr), true, "web");
+        assert!(unredirected.original_dst_server().is_err());
+    }
+
+    struct LogBuffer(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn accepts_continue_after_recoverable_errors() {
+        let throttle = LogThrottle::new(&GlobalConfig::default());
+        let events = EventBus::new();
+        let mut failed = events.subscribe();
+        let os_error = std::io::Error::from_raw_os_error;
+
+        let mut results = vec![Err(os_error(libc::ECONNABORTED)), Err(os_error(libc::EMFILE)), Ok(7)].into_iter();
+        let accepted = accept_retrying(|| std::future::ready(results.next().unwrap()), "web", "l1", &throttle, &events).await;
+        assert_eq!(accepted.unwrap(), 7);
+        assert!(failed.try_recv().is_err(), "recoverable errors are not published");
+
+        let mut results = vec![Err::<u32, _>(os_error(libc::EBADF)), Ok(7)].into_iter();
+        let stopped = accept_retrying(|| std::future::ready(results.next().unwrap()), "web", "l1", &throttle, &events).await;
+        assert_eq!(stopped.unwrap_err().to_string(), format!("Listener l1 stopped accepting: {}", os_error(libc::EBADF)));
+        assert!(matches!(failed.try_recv(), Ok(TurbogateEvent::ListenerFailed { frontend, .. }) if frontend == "web"));
+        assert_eq!(results.len(), 1, "the loop stops at the first unrecoverable error");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tarpit_holds_connections_up_to_its_cap() {
+        let logs = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let writer = { let logs = Arc::clone(&logs); move || LogBuffer(Arc::clone(&logs)) };
+        let _logging = tracing::subscriber::set_default(tracing_subscriber::fmt().with_writer(writer).with_ansi(false).finish());
+
+        let tarpitted = Arc::new(AtomicU32::new(0));
+        let mut peers = Vec::new();
+        let mut held = Vec::new();
+        for _ in 0..3 {
+            let (stream, peer) = tokio::io::duplex(64);
+            held.push(ProxyServer::tarpit_connection(stream, &connection(), "web", Duration::from_secs(5), Arc::clone(&tarpitted), 2));
+            peers.push(peer);
+        }
+        assert_eq!(held, [true, true, false]);
+        assert_eq!(tarpitted.load(Ordering::Relaxed), 2);
+
+        let started = tokio::time::Instant::now();
+        let mut byte = [0u8; 1];
+        assert_eq!(peers[2].read(&mut byte).await.unwrap(), 0, "the connection over the cap is closed at once");
+        assert_eq!(started.elapsed(), Duration::ZERO);
+        assert_eq!(peers[0].read(&mut byte).await.unwrap(), 0);
+        assert_eq!(started.elapsed(), Duration::from_secs(5));
+        assert_eq!(peers[1].read(&mut byte).await.unwrap(), 0);
+        tokio::task::yield_now().await;
+        assert_eq!(tarpitted.load(Ordering::Relaxed), 0);
+
+        let logs = String::from_utf8(logs.lock().unwrap().clone()).unwrap();
+        assert_eq!(logs.matches("Tarpitted connection closed").count(), 2, "{logs}");
+        assert_eq!(logs.matches("termination_state=\"PT\"").count(), 2, "{logs}");
+    }
+
+    #[test]
+    fn conn_rate_counts_per_second() {
+        let tracker = ConnRateTracker::new();
+        assert_eq!(tracker.count_at(0), 0);
+        for _ in 0..3 {
+            tracker.record_at(5);
+        }
+        tracker.record_at(6);
+        assert_eq!(tracker.count_at(5), 3);
+        assert_eq!(tracker.count_at(6), 1);
+        assert_eq!(tracker.count_at(5 + CONN_RATE_SLOTS as u64), 0, "a slot only counts its own second");
+
+        tracker.record_at(5 + CONN_RATE_SLOTS as u64);
+        assert_eq!(tracker.count_at(5 + CONN_RATE_SLOTS as u64), 1, "a reused slot starts over");
+        assert_eq!(tracker.count_at(5), 0);
+    }
+
+    #[test]
+    fn conn_rate_loses_no_accepts_when_a_slot_rolls_over() {
+        const THREADS: usize = 8;
+        const PER_SECOND: u64 = 200;
+        let tracker = Arc::new(ConnRateTracker::new());
+        let barrier = Arc::new(Barrier::new(THREADS));
+        let threads: Vec<_> = (0..THREADS)
+            .map(|thread| {
+                let tracker = Arc::clone(&tracker);
+                let barrier = Arc::clone(&barrier);
+                std::thread::spawn(move || {
+                    // Every second lands on a slot last used CONN_RATE_SLOTS seconds
+                    // earlier, so all threads race to reset it.
+                    for second in (0..4).map(|round| round * CONN_RATE_SLOTS as u64 + 7) {
+                        barrier.wait();
+                        for _ in 0..PER_SECOND {
+                            tracker.record_at(second);
+                        }
+                        barrier.wait();
+                        if thread == 0 {
+                            assert_eq!(tracker.count_at(second), THREADS as u64 * PER_SECOND, "second {second}");
+                        }
+                        barrier.wait();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn conn_rate_saturates() {
+        let tracker = ConnRateTracker::new();
+        tracker.slots[3].store(ConnRateTracker::stamp(3) | (COUNT_MASK - 1), Ordering::Relaxed);
+        tracker.record_at(3);
+        tracker.record_at(3);
+        assert_eq!(tracker.count_at(3), COUNT_MASK);
+        assert_eq!(tracker.slots[3].load(Ordering::Relaxed) & !COUNT_MASK, ConnRateTracker::stamp(3), "the count never spills into the stamp");
     }
 }
@@ -0,0 +1,213 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use anyhow::Result;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::session::SessionError;
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V1_MAX_LENGTH: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+const V2_HEADER_LENGTH: usize = 16;
+const V2_COMMAND_LOCAL: u8 = 0x0;
+const V2_COMMAND_PROXY: u8 = 0x1;
+const V2_FAMILY_INET: u8 = 0x1;
+const V2_FAMILY_INET6: u8 = 0x2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProxyHeader {
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+}
+
+pub async fn read_header<S>(stream: &mut S, timeout: Duration) -> Result<Option<ProxyHeader>>
+where
+    S: AsyncRead + Unpin,
+{
+    match tokio::time::timeout(timeout, read(stream)).await {
+        Ok(result) => result,
+        Err(_) => Err(SessionError::ProxyProtocolTimeout(timeout).into()),
+    }
+}
+
+fn invalid(reason: impl Into<String>) -> anyhow::Error {
+    SessionError::ProxyProtocol(reason.into()).into()
+}
+
+async fn read<S>(stream: &mut S) -> Result<Option<ProxyHeader>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut header = vec![0; V1_PREFIX.len()];
+    stream.read_exact(&mut header).await.map_err(|e| invalid(e.to_string()))?;
+
+    if header == V1_PREFIX {
+        while !header.ends_with(b"\r\n") {
+            if header.len() >= V1_MAX_LENGTH {
+                return Err(invalid("version 1 header too long"));
+            }
+            header.push(stream.read_u8().await.map_err(|e| invalid(e.to_string()))?);
+        }
+        return parse_v1(&header[V1_PREFIX.len()..header.len() - 2]);
+    }
+
+    if V2_SIGNATURE.starts_with(&header) {
+        header.resize(V2_HEADER_LENGTH, 0);
+        stream.read_exact(&mut header[V1_PREFIX.len()..]).await.map_err(|e| invalid(e.to_string()))?;
+        if &header[..V2_SIGNATURE.len()] != V2_SIGNATURE {
+            return Err(invalid("bad version 2 signature"));
+        }
+        let length = u16::from_be_bytes([header[14], header[15]]) as usize;
+        let mut addresses = vec![0; length];
+        stream.read_exact(&mut addresses).await.map_err(|e| invalid(e.to_string()))?;
+        return parse_v2(header[12], header[13], &addresses);
+    }
+
+    Err(invalid("missing PROXY signature"))
+}
+
+fn parse_v1(line: &[u8]) -> Result<Option<ProxyHeader>> {
+    let line = std::str::from_utf8(line).map_err(|_| invalid("version 1 header is not ASCII"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["UNKNOWN", ..] => Ok(None),
+        [protocol @ ("TCP4" | "TCP6"), source, destination, source_port, destination_port] => {
+            let address = |address: &str| address.parse::<IpAddr>()
+                .ok()
+                .filter(|address| address.is_ipv4() == (*protocol == "TCP4"))
+                .ok_or_else(|| invalid(format!("bad {} address '{}'", protocol, address)));
+            let port = |port: &str| port.parse::<u16>().map_err(|_| invalid(format!("bad port '{}'", port)));
+            Ok(Some(ProxyHeader {
+                source: SocketAddr::new(address(source)?, port(source_port)?),
+                destination: SocketAddr::new(address(destination)?, port(destination_port)?),
+            }))
+        }
+        _ => Err(invalid(format!("malformed version 1 header '{}'", line))),
+    }
+}
+
+fn parse_v2(version_command: u8, family_protocol: u8, addresses: &[u8]) -> Result<Option<ProxyHeader>> {
+    if version_command >> 4 != 2 {
+        return Err(invalid(format!("unsupported version {}", version_command >> 4)));
+    }
+    match version_command & 0x0f {
+        V2_COMMAND_LOCAL => return Ok(None),
+        V2_COMMAND_PROXY => {}
+        command => return Err(invalid(format!("unsupported command {}", command))),
+    }
+
+    let port = |offset: usize| u16::from_be_bytes([addresses[offset], addresses[offset + 1]]);
+    match family_protocol >> 4 {
+        V2_FAMILY_INET if addresses.len() >= 12 => {
+            let source: [u8; 4] = addresses[0..4].try_into()?;
+            let destination: [u8; 4] = addresses[4..8].try_into()?;
+            Ok(Some(ProxyHeader {
+                source: SocketAddr::new(Ipv4Addr::from(source).into(), port(8)),
+                destination: SocketAddr::new(Ipv4Addr::from(destination).into(), port(10)),
+            }))
+        }
+        V2_FAMILY_INET6 if addresses.len() >= 36 => {
+            let source: [u8; 16] = addresses[0..16].try_into()?;
+            let destination: [u8; 16] = addresses[16..32].try_into()?;
+            Ok(Some(ProxyHeader {
+                source: SocketAddr::new(Ipv6Addr::from(source).into(), port(32)),
+                destination: SocketAddr::new(Ipv6Addr::from(destination).into(), port(34)),
+            }))
+        }
+        V2_FAMILY_INET | V2_FAMILY_INET6 => Err(invalid("version 2 address block too short")),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    async fn header(mut input: &[u8]) -> Result<Option<ProxyHeader>> {
+        read_header(&mut input, TIMEOUT).await
+    }
+
+    async fn error(input: &[u8]) -> String {
+        header(input).await.unwrap_err().to_string()
+    }
+
+    fn v2(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x20 | command, family << 4 | 0x1]);
+        header.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+        header.extend_from_slice(addresses);
+        header
+    }
+
+    #[tokio::test]
+    async fn version_1_tcp4_and_tcp6() {
+        let parsed = header(b"PROXY TCP4 192.0.2.1 198.51.100.2 40000 443\r\nGET /").await.unwrap().unwrap();
+        assert_eq!(parsed.source, "192.0.2.1:40000".parse().unwrap());
+        assert_eq!(parsed.destination, "198.51.100.2:443".parse().unwrap());
+
+        let parsed = header(b"PROXY TCP6 2001:db8::1 2001:db8::2 1 2\r\n").await.unwrap().unwrap();
+        assert_eq!(parsed.source, "[2001:db8::1]:1".parse().unwrap());
+        assert_eq!(parsed.destination, "[2001:db8::2]:2".parse().unwrap());
+
+        assert_eq!(header(b"PROXY UNKNOWN\r\n").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn version_1_stops_at_the_line_end() {
+        let mut input: &[u8] = b"PROXY TCP4 192.0.2.1 198.51.100.2 1 2\r\nHELLO";
+        read_header(&mut input, TIMEOUT).await.unwrap();
+        assert_eq!(input, b"HELLO");
+    }
+
+    #[tokio::test]
+    async fn malformed_version_1_headers_are_rejected() {
+        assert!(error(b"PROXY TCP4 2001:db8::1 198.51.100.2 1 2\r\n").await.contains("bad TCP4 address '2001:db8::1'"));
+        assert!(error(b"PROXY TCP4 192.0.2.1 198.51.100.2 1 70000\r\n").await.contains("bad port '70000'"));
+        assert!(error(b"PROXY TCP4 192.0.2.1\r\n").await.contains("malformed version 1 header 'TCP4 192.0.2.1'"));
+        assert!(error(&[b"PROXY ".as_slice(), &[b'x'; 120], b"\r\n"].concat()).await.contains("version 1 header too long"));
+        assert!(error(b"GET / HTTP/1.1\r\n").await.contains("missing PROXY signature"));
+    }
+
+    #[tokio::test]
+    async fn version_2_inet_and_inet6() {
+        let parsed = header(&v2(V2_COMMAND_PROXY, V2_FAMILY_INET, &[192, 0, 2, 1, 198, 51, 100, 2, 0x9c, 0x40, 0x01, 0xbb])).await.unwrap().unwrap();
+        assert_eq!(parsed.source, "192.0.2.1:40000".parse().unwrap());
+        assert_eq!(parsed.destination, "198.51.100.2:443".parse().unwrap());
+
+        let mut addresses = Vec::new();
+        addresses.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        addresses.extend_from_slice(&"2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+        addresses.extend_from_slice(&[0, 1, 0, 2]);
+        let parsed = header(&v2(V2_COMMAND_PROXY, V2_FAMILY_INET6, &addresses)).await.unwrap().unwrap();
+        assert_eq!(parsed.source, "[2001:db8::1]:1".parse().unwrap());
+        assert_eq!(parsed.destination, "[2001:db8::2]:2".parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn version_2_local_and_unknown_families_keep_the_peer() {
+        assert_eq!(header(&v2(V2_COMMAND_LOCAL, 0, &[])).await.unwrap(), None);
+        assert_eq!(header(&v2(V2_COMMAND_PROXY, 0x3, &[0; 216])).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn malformed_version_2_headers_are_rejected() {
+        assert!(error(&v2(V2_COMMAND_PROXY, V2_FAMILY_INET, &[0; 8])).await.contains("version 2 address block too short"));
+        assert!(error(&v2(0x2, V2_FAMILY_INET, &[0; 12])).await.contains("unsupported command 2"));
+        let mut bad_version = v2(V2_COMMAND_PROXY, V2_FAMILY_INET, &[0; 12]);
+        bad_version[12] = 0x11;
+        assert!(error(&bad_version).await.contains("unsupported version 1"));
+        let mut bad_signature = v2(V2_COMMAND_PROXY, V2_FAMILY_INET, &[0; 12]);
+        bad_signature[8] = b'X';
+        assert!(error(&bad_signature).await.contains("bad version 2 signature"));
+        assert!(error(&V2_SIGNATURE[..10]).await.contains("early eof"));
+    }
+
+    #[tokio::test]
+    async fn a_silent_client_times_out() {
+        let (mut client, _server) = tokio::io::duplex(64);
+        let error = read_header(&mut client, Duration::from_millis(20)).await.unwrap_err();
+        assert!(matches!(SessionError::classify(&error), Some(SessionError::ProxyProtocolTimeout(_))), "{error}");
+    }
+}
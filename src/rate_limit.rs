@@ -1,16 +1,121 @@
-use governor::{Quota, RateLimiter as GovRateLimiter, state::keyed::DefaultKeyedStateStore, clock::DefaultClock};
+use governor::{Quota, RateLimiter as GovRateLimiter, state::{InMemoryState, NotKeyed}, clock::DefaultClock};
+use anyhow::{Result, anyhow};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::net::IpAddr;
 use std::num::NonZeroU32;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use dashmap::DashMap;
 
-use tracing::{debug, warn};
+use crate::metrics;
+
+use tracing::{debug, info, warn};
+
+const MAX_KEY_LENGTH: usize = 64;
+const MAX_TRACKED_KEYS: usize = 100_000;
+const OBSERVE_LOG_SAMPLE: u64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtectionMode {
+    Enforce,
+    Observe,
+}
+
+impl ProtectionMode {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "enforce" => Ok(ProtectionMode::Enforce),
+            "observe" => Ok(ProtectionMode::Observe),
+            _ => Err(anyhow!("Unknown protection mode '{}', expected enforce or observe", value)),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProtectionMode::Enforce => "enforce",
+            ProtectionMode::Observe => "observe",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitKey {
+    Src,
+    Sni,
+    Backend,
+    SrcAndSni,
+}
+
+impl RateLimitKey {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "src" => Ok(RateLimitKey::Src),
+            "sni" => Ok(RateLimitKey::Sni),
+            "backend" => Ok(RateLimitKey::Backend),
+            "src-and-sni" => Ok(RateLimitKey::SrcAndSni),
+            _ => Err(anyhow!("Unknown rate-limit key '{}', expected src, sni, backend or src-and-sni", value)),
+        }
+    }
+
+    pub fn needs_server_name(&self) -> bool {
+        matches!(self, RateLimitKey::Sni | RateLimitKey::SrcAndSni)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RateLimitKey::Src => "src",
+            RateLimitKey::Sni => "sni",
+            RateLimitKey::Backend => "backend",
+            RateLimitKey::SrcAndSni => "src-and-sni",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RateKey {
+    Ip(IpAddr),
+    Name(String),
+    IpAndName(IpAddr, String),
+}
+
+impl RateKey {
+    pub fn name(name: &str) -> Self {
+        RateKey::Name(bounded_name(name))
+    }
+
+    pub fn ip_and_name(ip: IpAddr, name: &str) -> Self {
+        RateKey::IpAndName(ip, bounded_name(name))
+    }
+}
+
+impl std::fmt::Display for RateKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RateKey::Ip(ip) => write!(f, "{}", ip),
+            RateKey::Name(name) => write!(f, "{}", name),
+            RateKey::IpAndName(ip, name) => write!(f, "{}/{}", ip, name),
+        }
+    }
+}
+
+fn bounded_name(name: &str) -> String {
+    if name.len() <= MAX_KEY_LENGTH {
+        return name.to_ascii_lowercase();
+    }
+
+    let mut hasher = DefaultHasher::new();
+    name.to_ascii_lowercase().hash(&mut hasher);
+    format!("#{:016x}", hasher.finish())
+}
 
 #[derive(Debug, Clone)]
 pub struct RateLimitConfig {
     pub requests_per_second: u32,
     pub burst_size: u32,
     pub window_size: std::time::Duration,
+    pub key: RateLimitKey,
+    pub mode: ProtectionMode,
 }
 
 impl Default for RateLimitConfig {
@@ -19,46 +124,88 @@ impl Default for RateLimitConfig {
             requests_per_second: 100,
             burst_size: 10,
             window_size: std::time::Duration::from_secs(1),
+            key: RateLimitKey::Src,
+            mode: ProtectionMode::Enforce,
         }
     }
 }
 
 pub struct RateLimiter {
-    limiters: Arc<DashMap<IpAddr, Arc<GovRateLimiter<IpAddr, DefaultKeyedStateStore<IpAddr>, DefaultClock>>>>,
+    limiters: Arc<DashMap<RateKey, Arc<GovRateLimiter<NotKeyed, InMemoryState, DefaultClock>>>>,
     config: RateLimitConfig,
+    observe: AtomicBool,
+    would_reject: AtomicU64,
 }
 
 impl RateLimiter {
     pub fn new(config: RateLimitConfig) -> Self {
         Self {
             limiters: Arc::new(DashMap::new()),
+            observe: AtomicBool::new(config.mode == ProtectionMode::Observe),
+            would_reject: AtomicU64::new(0),
             config,
         }
     }
 
-    pub fn check_rate_limit(&self, ip: IpAddr) -> bool {
+    pub fn key(&self) -> RateLimitKey {
+        self.config.key
+    }
+
+    pub fn mode(&self) -> ProtectionMode {
+        if self.observe.load(Ordering::Relaxed) {
+            ProtectionMode::Observe
+        } else {
+            ProtectionMode::Enforce
+        }
+    }
+
+    pub fn set_mode(&self, mode: ProtectionMode) {
+        if self.mode() != mode {
+            info!("Rate limiting switched to {} mode", mode.as_str());
+        }
+        self.observe.store(mode == ProtectionMode::Observe, Ordering::Relaxed);
+    }
+
+    pub fn check_rate_limit(&self, key: RateKey) -> bool {
+        if self.limiters.len() >= MAX_TRACKED_KEYS && !self.limiters.contains_key(&key) {
+            let evicted = self.limiters.iter().next().map(|entry| entry.key().clone());
+            if let Some(evicted) = evicted {
+                debug!("Rate limiter tracking {} keys, evicting {}", MAX_TRACKED_KEYS, evicted);
+                self.limiters.remove(&evicted);
+            }
+        }
+
         let limiter = self.limiters
-            .entry(ip)
+            .entry(key.clone())
             .or_insert_with(|| {
                 let quota = Quota::per_second(NonZeroU32::new(self.config.requests_per_second).unwrap())
                     .allow_burst(NonZeroU32::new(self.config.burst_size).unwrap());
-                Arc::new(GovRateLimiter::keyed(quota))
+                Arc::new(GovRateLimiter::direct(quota))
             })
             .clone();
 
-        match limiter.check_key(&ip) {
+        match limiter.check() {
             Ok(_) => {
-                debug!("Rate limit check passed for IP: {}", ip);
+                debug!("Rate limit check passed for key: {}", key);
+                true
+            }
+            Err(_) if self.mode() == ProtectionMode::Observe => {
+                metrics::rate_limit_would_reject(self.config.key.as_str());
+                let seen = self.would_reject.fetch_add(1, Ordering::Relaxed);
+                if seen.is_multiple_of(OBSERVE_LOG_SAMPLE) {
+                    warn!("Rate limit would reject key: {} (observe mode, {} would-reject so far)", key, seen + 1);
+                }
                 true
             }
             Err(_) => {
-                warn!("Rate limit exceeded for IP: {}", ip);
+                warn!("Rate limit exceeded for key: {}", key);
                 false
             }
         }
     }
 
     pub fn update_config(&mut self, config: RateLimitConfig) {
+        self.set_mode(config.mode);
         self.config = config;
         self.limiters.clear();
     }
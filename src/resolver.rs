@@ -0,0 +1,823 @@
+use crate::config::{GlobalConfig, NameserverConfig, ResolversConfig};
+use crate::metrics;
+use anyhow::{Result, anyhow};
+use dashmap::DashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::Semaphore;
+use tokio::task::{self, JoinHandle};
+use tracing::{debug, info, warn};
+
+const DNS_TYPE_A: u16 = 1;
+const DNS_TYPE_PTR: u16 = 12;
+const DNS_TYPE_AAAA: u16 = 28;
+const DNS_TYPE_SRV: u16 = 33;
+const DNS_CLASS_IN: u16 = 1;
+const DNS_FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const DNS_FLAG_TRUNCATED: u16 = 0x0200;
+const DNS_RCODE_NXDOMAIN: u16 = 3;
+const DNS_MAX_UDP_SIZE: usize = 4096;
+const DNS_MAX_POINTERS: usize = 16;
+pub const DEFAULT_SRC_DN_TIMEOUT: Duration = Duration::from_millis(50);
+const DEFAULT_SRC_DN_MAXLOOKUPS: usize = 64;
+const DEFAULT_SRC_DN_CACHE_SIZE: usize = 10_000;
+const DEFAULT_SRC_DN_CACHE_TTL: Duration = Duration::from_secs(300);
+const SRC_DN_ERROR_TTL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq)]
+enum Resolution {
+    Valid(Vec<IpAddr>),
+    NxDomain,
+    Timeout,
+    Other(String),
+}
+
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    last_resolution: Instant,
+    last_status: Resolution,
+    last_valid: Option<(Instant, Vec<IpAddr>)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct SrvTarget {
+    priority: u16,
+    weight: u16,
+    port: u16,
+    target: String,
+}
+
+#[derive(Debug, Clone)]
+struct SrvCacheEntry {
+    last_resolution: Instant,
+    last_error: Option<String>,
+    last_valid: Option<(Instant, SrvTarget)>,
+}
+
+pub struct Resolver {
+    config: ResolversConfig,
+    cache: DashMap<String, CacheEntry>,
+    srv_cache: DashMap<String, SrvCacheEntry>,
+}
+
+impl Resolver {
+    pub fn new(config: ResolversConfig) -> Self {
+        Self {
+            config,
+            cache: DashMap::new(),
+            srv_cache: DashMap::new(),
+        }
+    }
+
+    pub async fn resolve(&self, hostname: &str) -> Result<Vec<IpAddr>> {
+        self.resolve_with(hostname, false).await
+    }
+
+    pub async fn refresh(&self, hostname: &str) -> Result<Vec<IpAddr>> {
+        self.resolve_with(hostname, true).await
+    }
+
+    async fn resolve_with(&self, hostname: &str, force: bool) -> Result<Vec<IpAddr>> {
+        if let Ok(ip) = hostname.parse::<IpAddr>() {
+            return Ok(vec![ip]);
+        }
+
+        let hostname = hostname.to_ascii_lowercase();
+        let cached = self.cache.get(&hostname).map(|entry| entry.clone());
+        let timeout_resolve = Duration::from_millis(self.config.timeout_resolve_ms);
+
+        let entry = match cached {
+            Some(entry) if !force && entry.last_resolution.elapsed() < timeout_resolve => entry,
+            previous => {
+                let status = self.query_nameservers(&hostname).await;
+                let last_valid = match status {
+                    Resolution::Valid(ref addresses) => Some((Instant::now(), addresses.clone())),
+                    _ => previous.and_then(|entry| entry.last_valid),
+                };
+                let entry = CacheEntry {
+                    last_resolution: Instant::now(),
+                    last_status: status,
+                    last_valid,
+                };
+                self.cache.insert(hostname.clone(), entry.clone());
+                entry
+            }
+        };
+
+        let hold = match entry.last_status {
+            Resolution::Valid(addresses) => return Ok(addresses),
+            Resolution::NxDomain => self.config.hold_nx_ms,
+            Resolution::Timeout => self.config.hold_timeout_ms,
+            Resolution::Other(_) => self.config.hold_valid_ms,
+        };
+
+        match entry.last_valid {
+            Some((resolved_at, addresses)) if resolved_at.elapsed() < Duration::from_millis(hold) => {
+                debug!("Resolvers '{}': using held answer for {} after {:?}", self.config.name, hostname, entry.last_status);
+                Ok(addresses)
+            }
+            _ => Err(match entry.last_status {
+                Resolution::NxDomain => anyhow!("Resolvers '{}': {} does not exist", self.config.name, hostname),
+                Resolution::Timeout => anyhow!("Resolvers '{}': no nameserver answered for {}", self.config.name, hostname),
+                Resolution::Other(error) => anyhow!("Resolvers '{}': failed to resolve {}: {}", self.config.name, hostname, error),
+                Resolution::Valid(_) => unreachable!(),
+            }),
+        }
+    }
+
+    pub async fn resolve_srv(&self, name: &str) -> Result<Vec<SocketAddr>> {
+        self.resolve_srv_with(name, false).await
+    }
+
+    pub async fn refresh_srv(&self, name: &str) -> Result<Vec<SocketAddr>> {
+        self.resolve_srv_with(name, true).await
+    }
+
+    async fn resolve_srv_with(&self, name: &str, force: bool) -> Result<Vec<SocketAddr>> {
+        let name = name.to_ascii_lowercase();
+        let cached = self.srv_cache.get(&name).map(|entry| entry.clone());
+        let timeout_resolve = Duration::from_millis(self.config.timeout_resolve_ms);
+
+        let entry = match cached {
+            Some(entry) if !force && entry.last_resolution.elapsed() < timeout_resolve => entry,
+            previous => {
+                let status = self.query_srv(&name).await;
+                let previous_target = previous.as_ref()
+                    .and_then(|entry| entry.last_valid.as_ref())
+                    .map(|(_, target)| target.clone());
+                let (last_error, last_valid) = match status {
+                    Ok(target) => {
+                        if previous_target.as_ref() != Some(&target) {
+                            info!("Resolvers '{}': {} now points to {}:{} (priority {}, weight {})",
+                                  self.config.name, name, target.target, target.port, target.priority, target.weight);
+                        }
+                        (None, Some((Instant::now(), target)))
+                    }
+                    Err(e) => (Some(e.to_string()), previous.and_then(|entry| entry.last_valid)),
+                };
+                let entry = SrvCacheEntry {
+                    last_resolution: Instant::now(),
+                    last_error,
+                    last_valid,
+                };
+                self.srv_cache.insert(name.clone(), entry.clone());
+                entry
+            }
+        };
+
+        let target = match (entry.last_error, entry.last_valid) {
+            (None, Some((_, target))) => target,
+            (Some(_), Some((resolved_at, target))) if resolved_at.elapsed() < Duration::from_millis(self.config.hold_valid_ms) => {
+                debug!("Resolvers '{}': using held SRV answer for {}", self.config.name, name);
+                target
+            }
+            (error, _) => return Err(anyhow!("Resolvers '{}': failed to resolve SRV {}: {}",
+                                             self.config.name, name, error.unwrap_or_else(|| "no answer".to_string()))),
+        };
+
+        Ok(self.resolve_with(&target.target, force).await?
+            .into_iter()
+            .map(|address| SocketAddr::new(address, target.port))
+            .collect())
+    }
+
+    pub fn cached(&self, hostname: &str) -> Option<Vec<IpAddr>> {
+        if let Ok(ip) = hostname.parse::<IpAddr>() {
+            return Some(vec![ip]);
+        }
+        self.cache.get(&hostname.to_ascii_lowercase())
+            .and_then(|entry| entry.last_valid.as_ref().map(|(_, addresses)| addresses.clone()))
+    }
+
+    pub fn cached_srv(&self, name: &str) -> Option<Vec<SocketAddr>> {
+        let target = self.srv_cache.get(&name.to_ascii_lowercase())
+            .and_then(|entry| entry.last_valid.as_ref().map(|(_, target)| target.clone()))?;
+        Some(self.cached(&target.target)?
+            .into_iter()
+            .map(|address| SocketAddr::new(address, target.port))
+            .collect())
+    }
+
+    pub async fn reverse(&self, ip: IpAddr) -> Result<Option<String>> {
+        let name = reverse_name(ip);
+        let retry_timeout = Duration::from_millis(self.config.timeout_retry_ms);
+        let attempts = self.config.resolve_retries.max(1) as usize;
+        let mut last_error = anyhow!("Resolvers '{}': no nameserver answered for {}", self.config.name, name);
+
+        for attempt in 0..attempts {
+            let nameserver = &self.config.nameservers[attempt % self.config.nameservers.len()];
+
+            match tokio::time::timeout(retry_timeout, query_ptr(nameserver.address, &name)).await {
+                Ok(Ok(None)) => return Ok(None),
+                Ok(Ok(Some(hostname))) => {
+                    let confirmed = self.resolve(&hostname).await
+                        .is_ok_and(|addresses| addresses.iter().any(|address| address.to_canonical() == ip));
+                    if !confirmed {
+                        debug!("Resolvers '{}': {} points to {}, which does not resolve back to it", self.config.name, ip, hostname);
+                        return Ok(None);
+                    }
+                    return Ok(Some(hostname));
+                }
+                Ok(Err(e)) => {
+                    warn!("Resolvers '{}': nameserver '{}' failed for {}: {}", self.config.name, nameserver.name, name, e);
+                    last_error = e;
+                }
+                Err(_) => {
+                    warn!("Resolvers '{}': nameserver '{}' timed out for {}", self.config.name, nameserver.name, name);
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+
+    async fn query_nameservers(&self, hostname: &str) -> Resolution {
+        let retry_timeout = Duration::from_millis(self.config.timeout_retry_ms);
+        let attempts = self.config.resolve_retries.max(1) as usize;
+        let mut status = Resolution::Timeout;
+
+        for attempt in 0..attempts {
+            let nameserver = &self.config.nameservers[attempt % self.config.nameservers.len()];
+
+            match tokio::time::timeout(retry_timeout, Self::query_addresses(nameserver, hostname)).await {
+                Ok(Ok(resolution)) => return resolution,
+                Ok(Err(e)) => {
+                    warn!("Resolvers '{}': nameserver '{}' failed for {}: {}", self.config.name, nameserver.name, hostname, e);
+                    status = Resolution::Other(e.to_string());
+                }
+                Err(_) => {
+                    warn!("Resolvers '{}': nameserver '{}' timed out for {}", self.config.name, nameserver.name, hostname);
+                }
+            }
+        }
+
+        status
+    }
+
+    async fn query_srv(&self, name: &str) -> Result<SrvTarget> {
+        let retry_timeout = Duration::from_millis(self.config.timeout_retry_ms);
+        let attempts = self.config.resolve_retries.max(1) as usize;
+        let mut last_error = anyhow!("no nameserver answered");
+
+        for attempt in 0..attempts {
+            let nameserver = &self.config.nameservers[attempt % self.config.nameservers.len()];
+
+            match tokio::time::timeout(retry_timeout, query_srv(nameserver.address, name)).await {
+                Ok(result) => return result,
+                Err(_) => {
+                    warn!("Resolvers '{}': nameserver '{}' timed out for SRV {}", self.config.name, nameserver.name, name);
+                    last_error = anyhow!("nameserver '{}' timed out", nameserver.name);
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+
+    async fn query_addresses(nameserver: &NameserverConfig, hostname: &str) -> Result<Resolution> {
+        let mut addresses = Vec::new();
+
+        for record_type in [DNS_TYPE_A, DNS_TYPE_AAAA] {
+            match query(nameserver.address, hostname, record_type).await {
+                Ok(Resolution::Valid(found)) => addresses.extend(found),
+                Ok(Resolution::NxDomain) => return Ok(Resolution::NxDomain),
+                Ok(other) if addresses.is_empty() => return Ok(other),
+                Err(e) if addresses.is_empty() => return Err(e),
+                _ => break,
+            }
+        }
+
+        if addresses.is_empty() {
+            return Ok(Resolution::Other("no A or AAAA records".to_string()));
+        }
+
+        Ok(Resolution::Valid(addresses))
+    }
+}
+
+async fn query(nameserver: SocketAddr, hostname: &str, record_type: u16) -> Result<Resolution> {
+    let (id, response) = exchange(nameserver, hostname, record_type).await?;
+    decode_response(id, &response, record_type)
+}
+
+async fn query_ptr(nameserver: SocketAddr, name: &str) -> Result<Option<String>> {
+    let (id, response) = exchange(nameserver, name, DNS_TYPE_PTR).await?;
+    decode_ptr(id, &response)
+}
+
+async fn query_srv(nameserver: SocketAddr, name: &str) -> Result<SrvTarget> {
+    let (id, response) = exchange(nameserver, name, DNS_TYPE_SRV).await?;
+    decode_srv(id, &response)
+}
+
+async fn exchange(nameserver: SocketAddr, hostname: &str, record_type: u16) -> Result<(u16, Vec<u8>)> {
+    let id = rand::random::<u16>();
+    let request = encode_query(id, hostname, record_type)?;
+
+    let bind_addr: SocketAddr = if nameserver.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(bind_addr).await?;
+    socket.connect(nameserver).await?;
+    socket.send(&request).await?;
+
+    let mut buffer = vec![0u8; DNS_MAX_UDP_SIZE];
+    let response = loop {
+        let n = socket.recv(&mut buffer).await?;
+        if n >= 2 && u16::from_be_bytes([buffer[0], buffer[1]]) == id {
+            break buffer[..n].to_vec();
+        }
+    };
+
+    if response.len() >= 4 && u16::from_be_bytes([response[2], response[3]]) & DNS_FLAG_TRUNCATED != 0 {
+        debug!("Truncated DNS answer from {}, retrying over TCP", nameserver);
+        return Ok((id, query_tcp(nameserver, &request).await?));
+    }
+
+    Ok((id, response))
+}
+
+async fn query_tcp(nameserver: SocketAddr, request: &[u8]) -> Result<Vec<u8>> {
+    let mut stream = TcpStream::connect(nameserver).await?;
+    stream.write_all(&(request.len() as u16).to_be_bytes()).await?;
+    stream.write_all(request).await?;
+
+    let mut length = [0u8; 2];
+    stream.read_exact(&mut length).await?;
+    let mut response = vec![0u8; u16::from_be_bytes(length) as usize];
+    stream.read_exact(&mut response).await?;
+    Ok(response)
+}
+
+fn encode_query(id: u16, hostname: &str, record_type: u16) -> Result<Vec<u8>> {
+    let mut packet = Vec::with_capacity(hostname.len() + 18);
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&DNS_FLAG_RECURSION_DESIRED.to_be_bytes());
+    packet.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+
+    for label in hostname.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(anyhow!("Invalid hostname '{}'", hostname));
+        }
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+    packet.extend_from_slice(&record_type.to_be_bytes());
+    packet.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+
+    Ok(packet)
+}
+
+fn skip_name(packet: &[u8], mut position: usize) -> Result<usize> {
+    loop {
+        let len = *packet.get(position).ok_or_else(|| anyhow!("Truncated DNS name"))? as usize;
+        if len == 0 {
+            return Ok(position + 1);
+        }
+        if len & 0xC0 == 0xC0 {
+            return Ok(position + 2);
+        }
+        position += len + 1;
+    }
+}
+
+fn read_u16(packet: &[u8], position: usize) -> Result<u16> {
+    packet.get(position..position + 2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+        .ok_or_else(|| anyhow!("Truncated DNS packet"))
+}
+
+fn read_name(packet: &[u8], mut position: usize) -> Result<String> {
+    let mut labels = Vec::new();
+    let mut pointers = 0;
+    loop {
+        let len = *packet.get(position).ok_or_else(|| anyhow!("Truncated DNS name"))? as usize;
+        if len == 0 {
+            return Ok(labels.join(".").to_ascii_lowercase());
+        }
+        if len & 0xC0 == 0xC0 {
+            pointers += 1;
+            if pointers > DNS_MAX_POINTERS {
+                return Err(anyhow!("DNS name has too many compression pointers"));
+            }
+            position = (read_u16(packet, position)? & 0x3FFF) as usize;
+            continue;
+        }
+        let label = packet.get(position + 1..position + 1 + len)
+            .ok_or_else(|| anyhow!("Truncated DNS name"))?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        position += len + 1;
+    }
+}
+
+fn reverse_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, d] = ip.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+        }
+        IpAddr::V6(ip) => {
+            let mut name = String::with_capacity(72);
+            for byte in ip.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", byte & 0x0F, byte >> 4));
+            }
+            name.push_str("ip6.arpa");
+            name
+        }
+    }
+}
+
+type DnsRecords = Vec<(u16, Range<usize>)>;
+
+fn read_answers(id: u16, packet: &[u8]) -> Result<(u16, DnsRecords)> {
+    if packet.len() < 12 || read_u16(packet, 0)? != id {
+        return Err(anyhow!("Malformed DNS response"));
+    }
+
+    let rcode = read_u16(packet, 2)? & 0x000F;
+    if rcode != 0 {
+        return Ok((rcode, Vec::new()));
+    }
+
+    let questions = read_u16(packet, 4)?;
+    let answers = read_u16(packet, 6)?;
+    let mut position = 12;
+
+    for _ in 0..questions {
+        position = skip_name(packet, position)? + 4;
+    }
+
+    let mut records = Vec::new();
+    for _ in 0..answers {
+        position = skip_name(packet, position)?;
+        let answer_type = read_u16(packet, position)?;
+        let data_len = read_u16(packet, position + 8)? as usize;
+        let data_start = position + 10;
+        if packet.len() < data_start + data_len {
+            return Err(anyhow!("Truncated DNS record"));
+        }
+        position = data_start + data_len;
+        records.push((answer_type, data_start..position));
+    }
+
+    Ok((0, records))
+}
+
+fn decode_response(id: u16, packet: &[u8], record_type: u16) -> Result<Resolution> {
+    let (rcode, records) = read_answers(id, packet)?;
+    match rcode {
+        0 => {}
+        DNS_RCODE_NXDOMAIN => return Ok(Resolution::NxDomain),
+        _ => return Ok(Resolution::Other(format!("rcode {}", rcode))),
+    }
+
+    let mut addresses = Vec::new();
+    for (answer_type, range) in records {
+        if answer_type != record_type {
+            continue;
+        }
+        let data = &packet[range];
+        match (answer_type, data.len()) {
+            (DNS_TYPE_A, 4) => addresses.push(IpAddr::from([data[0], data[1], data[2], data[3]])),
+            (DNS_TYPE_AAAA, 16) => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(data);
+                addresses.push(IpAddr::from(octets));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(Resolution::Valid(addresses))
+}
+
+fn decode_ptr(id: u16, packet: &[u8]) -> Result<Option<String>> {
+    let (rcode, records) = read_answers(id, packet)?;
+    match rcode {
+        0 => {}
+        DNS_RCODE_NXDOMAIN => return Ok(None),
+        _ => return Err(anyhow!("rcode {}", rcode)),
+    }
+
+    records.into_iter()
+        .find(|(answer_type, _)| *answer_type == DNS_TYPE_PTR)
+        .map(|(_, range)| read_name(packet, range.start))
+        .transpose()
+}
+
+fn decode_srv(id: u16, packet: &[u8]) -> Result<SrvTarget> {
+    let (rcode, records) = read_answers(id, packet)?;
+    match rcode {
+        0 => {}
+        DNS_RCODE_NXDOMAIN => return Err(anyhow!("does not exist")),
+        _ => return Err(anyhow!("rcode {}", rcode)),
+    }
+
+    let mut targets = Vec::new();
+    for (answer_type, range) in records {
+        if answer_type != DNS_TYPE_SRV || range.len() < 7 {
+            continue;
+        }
+        let target = SrvTarget {
+            priority: read_u16(packet, range.start)?,
+            weight: read_u16(packet, range.start + 2)?,
+            port: read_u16(packet, range.start + 4)?,
+            target: read_name(packet, range.start + 6)?,
+        };
+        if !target.target.is_empty() {
+            targets.push(target);
+        }
+    }
+
+    targets.into_iter()
+        .min_by_key(|target| (target.priority, std::cmp::Reverse(target.weight)))
+        .ok_or_else(|| anyhow!("no SRV records"))
+}
+
+#[derive(Default)]
+struct ReverseCache {
+    entries: HashMap<IpAddr, (u64, Instant, Option<String>)>,
+    recency: BTreeMap<u64, IpAddr>,
+    tick: u64,
+}
+
+impl ReverseCache {
+    fn get(&mut self, ip: IpAddr) -> Option<Option<String>> {
+        let (used, expires, name) = self.entries.get_mut(&ip)?;
+        self.recency.remove(used);
+        if Instant::now() >= *expires {
+            self.entries.remove(&ip);
+            return None;
+        }
+        self.tick += 1;
+        *used = self.tick;
+        self.recency.insert(self.tick, ip);
+        Some(name.clone())
+    }
+
+    fn insert(&mut self, ip: IpAddr, name: Option<String>, ttl: Duration, capacity: usize) {
+        if let Some((used, _, _)) = self.entries.remove(&ip) {
+            self.recency.remove(&used);
+        }
+        while self.entries.len() >= capacity {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+        self.tick += 1;
+        self.recency.insert(self.tick, ip);
+        self.entries.insert(ip, (self.tick, Instant::now() + ttl, name));
+    }
+}
+
+pub struct ReverseDns {
+    resolver: Arc<Resolver>,
+    timeout: Duration,
+    ttl: Duration,
+    capacity: usize,
+    lookups: Arc<Semaphore>,
+    cache: Mutex<ReverseCache>,
+}
+
+impl ReverseDns {
+    pub fn new(resolver: Arc<Resolver>, global: &GlobalConfig) -> Self {
+        Self {
+            resolver,
+            timeout: global.tune_src_dn_timeout.unwrap_or(DEFAULT_SRC_DN_TIMEOUT),
+            ttl: global.tune_src_dn_cache_ttl.unwrap_or(DEFAULT_SRC_DN_CACHE_TTL),
+            capacity: global.tune_src_dn_cache_size.unwrap_or(DEFAULT_SRC_DN_CACHE_SIZE),
+            lookups: Arc::new(Semaphore::new(global.tune_src_dn_maxlookups.unwrap_or(DEFAULT_SRC_DN_MAXLOOKUPS))),
+            cache: Mutex::new(ReverseCache::default()),
+        }
+    }
+
+    pub fn cached(self: &Arc<Self>, ip: IpAddr) -> Option<String> {
+        let ip = ip.to_canonical();
+        if let Some(name) = self.cache_get(ip) {
+            return name;
+        }
+        self.spawn_lookup(ip);
+        None
+    }
+
+    pub async fn lookup(self: &Arc<Self>, ip: IpAddr) -> Option<String> {
+        let ip = ip.to_canonical();
+        if let Some(name) = self.cache_get(ip) {
+            return name;
+        }
+        let lookup = self.spawn_lookup(ip)?;
+
+        match tokio::time::timeout(self.timeout, lookup).await {
+            Ok(name) => name.ok().flatten(),
+            Err(_) => {
+                debug!("Reverse lookup of {} did not finish within {:?}", ip, self.timeout);
+                metrics::src_dn_lookup("timeout");
+                None
+            }
+        }
+    }
+
+    fn cache_get(&self, ip: IpAddr) -> Option<Option<String>> {
+        let cached = self.cache.lock().unwrap_or_else(|e| e.into_inner()).get(ip);
+        metrics::src_dn_lookup(if cached.is_some() { "hit" } else { "miss" });
+        cached
+    }
+
+    fn spawn_lookup(self: &Arc<Self>, ip: IpAddr) -> Option<JoinHandle<Option<String>>> {
+        let Ok(permit) = Arc::clone(&self.lookups).try_acquire_owned() else {
+            metrics::src_dn_lookup("busy");
+            return None;
+        };
+        let reverse_dns = Arc::clone(self);
+
+        Some(task::spawn(async move {
+            let _permit = permit;
+            let (name, ttl) = match reverse_dns.resolver.reverse(ip).await {
+                Ok(name) => (name, reverse_dns.ttl),
+                Err(e) => {
+                    debug!("Reverse lookup of {} failed: {}", ip, e);
+                    metrics::src_dn_lookup("error");
+                    (None, reverse_dns.ttl.min(SRC_DN_ERROR_TTL))
+                }
+            };
+            reverse_dns.cache.lock().unwrap_or_else(|e| e.into_inner()).insert(ip, name.clone(), ttl, reverse_dns.capacity);
+            name
+        }))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn name(hostname: &str) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for label in hostname.split('.') {
+            bytes.push(label.len() as u8);
+            bytes.extend_from_slice(label.as_bytes());
+        }
+        bytes.push(0);
+        bytes
+    }
+
+    // Answers point back at the question name, like most servers compress them.
+    pub(crate) fn response(id: u16, rcode: u16, question: &str, record_type: u16, answers: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut packet = Vec::new();
+        packet.extend_from_slice(&id.to_be_bytes());
+        packet.extend_from_slice(&(0x8180 | rcode).to_be_bytes());
+        packet.extend_from_slice(&[0, 1]);
+        packet.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0, 0]);
+        packet.extend(name(question));
+        packet.extend_from_slice(&record_type.to_be_bytes());
+        packet.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+        for (answer_type, data) in answers {
+            packet.extend_from_slice(&[0xc0, 12]);
+            packet.extend_from_slice(&answer_type.to_be_bytes());
+            packet.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+            packet.extend_from_slice(&300u32.to_be_bytes());
+            packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            packet.extend_from_slice(data);
+        }
+        packet
+    }
+
+    // A UDP nameserver answering A queries for `hostname` with whatever `address`
+    // currently holds, and AAAA queries with no records.
+    pub(crate) async fn nameserver(hostname: &'static str, address: Arc<Mutex<Ipv4Addr>>) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let local = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buffer = [0u8; DNS_MAX_UDP_SIZE];
+            while let Ok((n, peer)) = socket.recv_from(&mut buffer).await {
+                let id = u16::from_be_bytes([buffer[0], buffer[1]]);
+                let record_type = u16::from_be_bytes([buffer[n - 4], buffer[n - 3]]);
+                let answers = match record_type {
+                    DNS_TYPE_A => vec![(DNS_TYPE_A, address.lock().unwrap().octets().to_vec())],
+                    _ => Vec::new(),
+                };
+                let _ = socket.send_to(&response(id, 0, hostname, record_type, &answers), peer).await;
+            }
+        });
+        local
+    }
+
+    #[test]
+    fn queries_encode_each_label() {
+        let packet = encode_query(0x1234, "app.example.com.", DNS_TYPE_AAAA).unwrap();
+        assert_eq!(&packet[..12], &[0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&packet[12..29], name("app.example.com").as_slice());
+        assert_eq!(&packet[29..], &[0, 28, 0, 1]);
+
+        for invalid in ["", "app..example.com", &format!("{}.com", "a".repeat(64))] {
+            assert_eq!(encode_query(1, invalid, DNS_TYPE_A).unwrap_err().to_string(), format!("Invalid hostname '{}'", invalid));
+        }
+    }
+
+    #[test]
+    fn responses_keep_only_the_requested_address_family() {
+        let answers = [
+            (DNS_TYPE_A, vec![192, 0, 2, 1]),
+            (DNS_TYPE_AAAA, "2001:db8::1".parse::<std::net::Ipv6Addr>().unwrap().octets().to_vec()),
+            (DNS_TYPE_A, vec![192, 0, 2, 2]),
+            (DNS_TYPE_A, vec![1, 2, 3]),
+        ];
+        let packet = response(7, 0, "app.example.com", DNS_TYPE_A, &answers);
+        assert_eq!(decode_response(7, &packet, DNS_TYPE_A).unwrap(),
+            Resolution::Valid(vec!["192.0.2.1".parse().unwrap(), "192.0.2.2".parse().unwrap()]));
+        assert_eq!(decode_response(7, &packet, DNS_TYPE_AAAA).unwrap(),
+            Resolution::Valid(vec!["2001:db8::1".parse().unwrap()]));
+    }
+
+    #[test]
+    fn error_responses_map_to_resolutions() {
+        let nxdomain = response(7, DNS_RCODE_NXDOMAIN, "gone.example.com", DNS_TYPE_A, &[]);
+        assert_eq!(decode_response(7, &nxdomain, DNS_TYPE_A).unwrap(), Resolution::NxDomain);
+        let servfail = response(7, 2, "app.example.com", DNS_TYPE_A, &[]);
+        assert_eq!(decode_response(7, &servfail, DNS_TYPE_A).unwrap(), Resolution::Other("rcode 2".to_string()));
+
+        let packet = response(7, 0, "app.example.com", DNS_TYPE_A, &[(DNS_TYPE_A, vec![192, 0, 2, 1])]);
+        assert_eq!(decode_response(8, &packet, DNS_TYPE_A).unwrap_err().to_string(), "Malformed DNS response");
+        assert_eq!(decode_response(7, &packet[..packet.len() - 2], DNS_TYPE_A).unwrap_err().to_string(), "Truncated DNS record");
+        assert_eq!(decode_response(7, &packet[..8], DNS_TYPE_A).unwrap_err().to_string(), "Malformed DNS response");
+    }
+
+    #[test]
+    fn reverse_names_use_the_arpa_zones() {
+        assert_eq!(reverse_name("192.0.2.10".parse().unwrap()), "10.2.0.192.in-addr.arpa");
+        assert_eq!(reverse_name("2001:db8::1".parse().unwrap()),
+            "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa");
+    }
+
+    #[test]
+    fn ptr_answers_follow_compression_pointers() {
+        let question = "10.2.0.192.in-addr.arpa";
+        let mut data = name("Client");
+        data.pop();
+        data.extend_from_slice(&[0xc0, 12 + 3]);
+        let packet = response(9, 0, question, DNS_TYPE_PTR, &[(DNS_TYPE_PTR, data)]);
+        assert_eq!(decode_ptr(9, &packet).unwrap().as_deref(), Some("client.2.0.192.in-addr.arpa"));
+
+        let nxdomain = response(9, DNS_RCODE_NXDOMAIN, question, DNS_TYPE_PTR, &[]);
+        assert_eq!(decode_ptr(9, &nxdomain).unwrap(), None);
+        assert_eq!(decode_ptr(9, &response(9, 0, question, DNS_TYPE_PTR, &[])).unwrap(), None);
+        assert_eq!(decode_ptr(9, &response(9, 5, question, DNS_TYPE_PTR, &[])).unwrap_err().to_string(), "rcode 5");
+
+        let mut looping = response(9, 0, question, DNS_TYPE_PTR, &[(DNS_TYPE_PTR, vec![0xc0, 0])]);
+        let pointer = looping.len() - 2;
+        looping[pointer + 1] = pointer as u8;
+        assert_eq!(decode_ptr(9, &looping).unwrap_err().to_string(), "DNS name has too many compression pointers");
+    }
+
+    #[test]
+    fn reverse_cache_evicts_the_least_recently_used_entry() {
+        let ip = |last: u8| IpAddr::from([192, 0, 2, last]);
+        let mut cache = ReverseCache::default();
+        cache.insert(ip(1), Some("one".to_string()), Duration::from_secs(60), 2);
+        cache.insert(ip(2), None, Duration::from_secs(60), 2);
+        assert_eq!(cache.get(ip(1)), Some(Some("one".to_string())));
+
+        cache.insert(ip(3), Some("three".to_string()), Duration::from_secs(60), 2);
+        assert_eq!(cache.get(ip(2)), None);
+        assert_eq!(cache.get(ip(1)), Some(Some("one".to_string())));
+        assert_eq!(cache.get(ip(3)), Some(Some("three".to_string())));
+
+        cache.insert(ip(3), Some("renamed".to_string()), Duration::ZERO, 2);
+        assert_eq!(cache.get(ip(3)), None);
+        assert_eq!(cache.entries.len(), 1);
+        assert_eq!(cache.recency.len(), 1);
+    }
+
+    #[test]
+    fn srv_answers_pick_the_lowest_priority_then_the_highest_weight() {
+        let srv = |priority: u16, weight: u16, port: u16, target: &str| {
+            let mut data = [priority, weight, port].iter().flat_map(|value| value.to_be_bytes()).collect::<Vec<u8>>();
+            data.extend(name(target));
+            (DNS_TYPE_SRV, data)
+        };
+        let question = "_app._tcp.example.com";
+        let packet = response(3, 0, question, DNS_TYPE_SRV, &[
+            srv(20, 100, 9000, "backup.example.com"),
+            srv(10, 5, 9001, "small.example.com"),
+            srv(10, 50, 9002, "Big.Example.com"),
+            (DNS_TYPE_SRV, vec![0, 1, 0, 1]),
+        ]);
+        assert_eq!(decode_srv(3, &packet).unwrap(),
+            SrvTarget { priority: 10, weight: 50, port: 9002, target: "big.example.com".to_string() });
+
+        let root_only = response(3, 0, question, DNS_TYPE_SRV, &[(DNS_TYPE_SRV, vec![0, 1, 0, 1, 0, 80, 0])]);
+        assert_eq!(decode_srv(3, &root_only).unwrap_err().to_string(), "no SRV records");
+        let nxdomain = response(3, DNS_RCODE_NXDOMAIN, question, DNS_TYPE_SRV, &[]);
+        assert_eq!(decode_srv(3, &nxdomain).unwrap_err().to_string(), "does not exist");
+        assert_eq!(decode_srv(3, &response(3, 2, question, DNS_TYPE_SRV, &[])).unwrap_err().to_string(), "rcode 2");
+    }
+}
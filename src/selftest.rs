@@ -0,0 +1,148 @@
+use crate::config::Config;
+use crate::events::EventBus;
+use crate::health::HealthChecker;
+use crate::limits::DEFAULT_MAX_CHECK_CONN;
+use crate::resolver::Resolver;
+use futures::future::join_all;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerProbe {
+    pub backend: String,
+    pub server: String,
+    pub address: String,
+    pub check: &'static str,
+    pub reachable: bool,
+    pub latency_ms: Option<f64>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BackendProbe {
+    pub backend: String,
+    pub reachable: usize,
+    pub total: usize,
+}
+
+impl BackendProbe {
+    pub fn healthy(&self) -> bool {
+        self.reachable > 0
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BackendReport {
+    pub passed: bool,
+    pub backends: Vec<BackendProbe>,
+    pub servers: Vec<ServerProbe>,
+}
+
+impl BackendReport {
+    pub fn failed_backends(&self) -> impl Iterator<Item = &BackendProbe> {
+        self.backends.iter().filter(|backend| !backend.healthy())
+    }
+
+    pub fn to_text(&self) -> String {
+        let header = ["BACKEND", "SERVER", "ADDRESS", "CHECK", "STATUS", "LATENCY", "ERROR"];
+        let rows: Vec<[String; 7]> = self.servers.iter()
+            .map(|probe| [
+                probe.backend.clone(),
+                probe.server.clone(),
+                probe.address.clone(),
+                probe.check.to_string(),
+                if probe.reachable { "reachable" } else { "unreachable" }.to_string(),
+                probe.latency_ms.map(|ms| format!("{:.1}ms", ms)).unwrap_or_else(|| "-".to_string()),
+                probe.error.clone().unwrap_or_else(|| "-".to_string()),
+            ])
+            .collect();
+
+        let mut widths = header.map(str::len);
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.len());
+            }
+        }
+
+        let format_row = |cells: &[String]| cells.iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string();
+
+        let mut output = format_row(&header.map(str::to_string));
+        output.push('\n');
+        for row in &rows {
+            output.push_str(&format_row(row));
+            output.push('\n');
+        }
+        output.push('\n');
+        for backend in &self.backends {
+            output.push_str(&format!("backend {}: {}/{} servers reachable{}\n", backend.backend, backend.reachable, backend.total,
+                                     if backend.healthy() { "" } else { " - would start with no healthy servers" }));
+        }
+        output
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_else(|_| "{}".to_string())
+    }
+}
+
+pub async fn check_backends(config: &Config, timeout: Duration) -> BackendReport {
+    let events = Arc::new(EventBus::new());
+    let budget = Arc::new(Semaphore::new(config.global.tune_maxcheckconn.unwrap_or(DEFAULT_MAX_CHECK_CONN).max(1)));
+    let deadline = tokio::time::Instant::now() + timeout;
+    let resolvers: HashMap<String, Arc<Resolver>> = config.resolvers.iter()
+        .map(|resolvers| (resolvers.name.clone(), Arc::new(Resolver::new(resolvers.clone()))))
+        .collect();
+
+    let backends = config.backends.iter()
+        .filter(|backend| backend.mode.as_deref() != Some("health"))
+        .filter(|backend| !backend.options.as_ref().is_some_and(|options| options.tcp_options.use_original_dst || options.tcp_options.connect_direct));
+
+    let checks = backends.map(|backend| {
+        let checker = HealthChecker::new(backend.clone(), Arc::clone(&events), Arc::clone(&budget), &resolvers);
+        async move {
+            let probes = backend.server.iter()
+                .filter(|server| !server.disabled.unwrap_or(false))
+                .map(|server| {
+                    let checker = &checker;
+                    async move {
+                        let result = match tokio::time::timeout_at(deadline, checker.probe(server)).await {
+                            Ok(result) => result,
+                            Err(_) => Err(anyhow::anyhow!("No answer within the {:?} deadline", timeout)),
+                        };
+                        ServerProbe {
+                            backend: backend.name.clone(),
+                            server: server.name.clone(),
+                            address: server.endpoint(),
+                            check: checker.check_kind(),
+                            reachable: result.is_ok(),
+                            latency_ms: result.as_ref().ok().map(|latency| latency.as_secs_f64() * 1000.0),
+                            error: result.err().map(|e| e.to_string()),
+                        }
+                    }
+                });
+            let servers = join_all(probes).await;
+            let summary = BackendProbe {
+                backend: backend.name.clone(),
+                reachable: servers.iter().filter(|probe| probe.reachable).count(),
+                total: servers.len(),
+            };
+            (summary, servers)
+        }
+    });
+
+    let (backends, servers): (Vec<BackendProbe>, Vec<Vec<ServerProbe>>) = join_all(checks).await.into_iter().unzip();
+    BackendReport {
+        passed: backends.iter().all(BackendProbe::healthy),
+        backends,
+        servers: servers.into_iter().flatten().collect(),
+    }
+}
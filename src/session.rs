@@ -0,0 +1,950 @@
+use crate::bwlim::Throttle;
+use crate::drain::DrainWatch;
+use crate::options::Options;
+use crate::inspect;
+use crate::metrics;
+use crate::utils;
+use futures::stream::{FuturesUnordered, StreamExt};
+use socket2::SockRef;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpSocket, TcpStream};
+
+pub const DEFAULT_INSPECT_BUFSIZE: usize = 16 * 1024;
+pub const DEFAULT_COPY_YIELD_BYTES: u64 = 1 << 20;
+const COPY_BUFSIZE: usize = 16 * 1024;
+pub const DEFAULT_CONNECT_RACE_DELAY: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Error)]
+pub enum SessionError {
+    #[error("Connect to {0} timed out after {1:?}")]
+    ConnectTimeout(String, Duration),
+    #[error("Client idle for {0:?}")]
+    ClientIdleTimeout(Duration),
+    #[error("Server idle for {0:?}")]
+    ServerIdleTimeout(Duration),
+    #[error("Session exceeded maximum duration of {0:?}")]
+    SessionMaxDuration(Duration),
+    #[error("Client sent no complete request within {0:?}")]
+    RequestTimeout(Duration),
+    #[error("Half-closed client session not finished within {0:?}")]
+    ClientFinTimeout(Duration),
+    #[error("Client stopped reading for {0:?} with data pending")]
+    ClientWriteStall(Duration),
+    #[error("Server stopped reading for {0:?} with data pending")]
+    ServerWriteStall(Duration),
+    #[error("Server aborted the connection before responding")]
+    ServerAborted,
+    #[error("TLS handshake failed: {0}")]
+    TlsHandshake(&'static str),
+    #[error("Invalid PROXY protocol header: {0}")]
+    ProxyProtocol(String),
+    #[error("No PROXY protocol header within {0:?}")]
+    ProxyProtocolTimeout(Duration),
+    #[error("No connect slot under the backend connect rate within {0:?}")]
+    ConnectThrottled(Duration),
+}
+
+impl SessionError {
+    pub fn error_type(&self) -> &'static str {
+        match self {
+            SessionError::ConnectTimeout(..) => "connect_timeout",
+            SessionError::ClientIdleTimeout(_) => "client_idle_timeout",
+            SessionError::ServerIdleTimeout(_) => "server_idle_timeout",
+            SessionError::SessionMaxDuration(_) => "session_max_duration",
+            SessionError::RequestTimeout(_) => "request_timeout",
+            SessionError::ClientFinTimeout(_) => "client_fin_timeout",
+            SessionError::ClientWriteStall(_) => "client_write_stall",
+            SessionError::ServerWriteStall(_) => "server_write_stall",
+            SessionError::ServerAborted => "server_aborted",
+            SessionError::TlsHandshake(_) => "tls_handshake",
+            SessionError::ProxyProtocol(_) | SessionError::ProxyProtocolTimeout(_) => "proxy_protocol",
+            SessionError::ConnectThrottled(_) => "connect_throttled",
+        }
+    }
+
+    pub fn termination_state(&self) -> &'static str {
+        match self {
+            SessionError::ConnectTimeout(..) => "sC",
+            SessionError::ClientIdleTimeout(_) => "cD",
+            SessionError::ServerIdleTimeout(_) => "sD",
+            SessionError::SessionMaxDuration(_) => "tD",
+            SessionError::RequestTimeout(_) => "cR",
+            SessionError::ClientFinTimeout(_) => "cD",
+            SessionError::ClientWriteStall(_) => "cW",
+            SessionError::ServerWriteStall(_) => "sW",
+            SessionError::ServerAborted => "SH",
+            SessionError::TlsHandshake("timeout") => "cR",
+            SessionError::TlsHandshake(_) => "CR",
+            SessionError::ProxyProtocol(_) => "CR",
+            SessionError::ProxyProtocolTimeout(_) => "cR",
+            SessionError::ConnectThrottled(_) => "sQ",
+        }
+    }
+
+    pub fn classify(error: &anyhow::Error) -> Option<&SessionError> {
+        error.downcast_ref::<SessionError>()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectFailure {
+    Refused,
+    TimedOut,
+    Reset,
+    Unreachable,
+    AddrNotAvail,
+    Other,
+}
+
+impl ConnectFailure {
+    pub const ALL: [ConnectFailure; 6] = [
+        ConnectFailure::Refused,
+        ConnectFailure::TimedOut,
+        ConnectFailure::Reset,
+        ConnectFailure::Unreachable,
+        ConnectFailure::AddrNotAvail,
+        ConnectFailure::Other,
+    ];
+
+    pub fn from_io(error: &io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::ConnectionRefused => ConnectFailure::Refused,
+            io::ErrorKind::TimedOut => ConnectFailure::TimedOut,
+            io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted | io::ErrorKind::BrokenPipe => ConnectFailure::Reset,
+            io::ErrorKind::HostUnreachable | io::ErrorKind::NetworkUnreachable | io::ErrorKind::NetworkDown => ConnectFailure::Unreachable,
+            io::ErrorKind::AddrNotAvailable => ConnectFailure::AddrNotAvail,
+            _ => ConnectFailure::Other,
+        }
+    }
+
+    pub fn classify(error: &anyhow::Error) -> Self {
+        error.chain()
+            .find_map(|cause| {
+                if let Some(error) = cause.downcast_ref::<io::Error>() {
+                    return Some(Self::from_io(error));
+                }
+                if cause.is::<tokio::time::error::Elapsed>() || matches!(cause.downcast_ref::<SessionError>(), Some(SessionError::ConnectTimeout(..))) {
+                    return Some(ConnectFailure::TimedOut);
+                }
+                None
+            })
+            .unwrap_or(ConnectFailure::Other)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnectFailure::Refused => "refused",
+            ConnectFailure::TimedOut => "timeout",
+            ConnectFailure::Reset => "reset",
+            ConnectFailure::Unreachable => "unreachable",
+            ConnectFailure::AddrNotAvail => "addr_not_avail",
+            ConnectFailure::Other => "other",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Client,
+    Server,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SessionTimeouts {
+    pub connect: Option<Duration>,
+    pub client: Option<Duration>,
+    pub server: Option<Duration>,
+    pub tunnel: Option<Duration>,
+    pub client_fin: Option<Duration>,
+    pub client_stall: Option<Duration>,
+    pub server_stall: Option<Duration>,
+    pub keep_alive: Option<Duration>,
+}
+
+impl SessionTimeouts {
+    pub fn new(frontend: Option<&Options>, backend: Option<&Options>) -> Self {
+        let millis = |value: Option<u64>| value.filter(|ms| *ms > 0).map(Duration::from_millis);
+        let keep_alive = frontend.and_then(|o| o.http_options.http_keep_alive_timeout)
+            .or_else(|| backend.and_then(|o| o.http_options.http_keep_alive_timeout));
+        let frontend = frontend.map(|o| &o.general_options);
+        let backend = backend.map(|o| &o.general_options);
+
+        Self {
+            connect: millis(backend.and_then(|o| o.timeout_connect)),
+            client: millis(frontend.and_then(|o| o.timeout_client)),
+            server: millis(backend.and_then(|o| o.timeout_server)),
+            tunnel: millis(backend.and_then(|o| o.timeout_tunnel)
+                .or_else(|| frontend.and_then(|o| o.timeout_tunnel))),
+            client_fin: millis(frontend.and_then(|o| o.timeout_client_fin)),
+            client_stall: millis(frontend.and_then(|o| o.timeout_client_stall)),
+            server_stall: millis(backend.and_then(|o| o.timeout_server_stall)),
+            keep_alive: millis(keep_alive),
+        }
+    }
+
+    fn idle(&self, side: Side) -> Option<Duration> {
+        match side {
+            Side::Client => self.client,
+            Side::Server => self.server,
+        }
+    }
+
+    fn stall(&self, from: Side) -> Option<Duration> {
+        match from {
+            Side::Client => self.server_stall,
+            Side::Server => self.client_stall,
+        }
+    }
+}
+
+pub struct SessionCounters {
+    started: Instant,
+    accepted: Instant,
+    request_time: Duration,
+    last_activity_ms: AtomicU64,
+    connect_ms: AtomicU64,
+    wait_ms: AtomicU64,
+    response_ms: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    pending_in: AtomicU64,
+    pending_out: AtomicU64,
+    inbound: Throttle,
+    outbound: Throttle,
+    copy_yield_bytes: u64,
+    drain: Option<DrainWatch>,
+}
+
+impl Default for SessionCounters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SessionCounters {
+    pub fn new() -> Self {
+        let started = Instant::now();
+        Self {
+            started,
+            accepted: started,
+            request_time: Duration::ZERO,
+            last_activity_ms: AtomicU64::new(0),
+            connect_ms: AtomicU64::new(u64::MAX),
+            wait_ms: AtomicU64::new(0),
+            response_ms: AtomicU64::new(u64::MAX),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            pending_in: AtomicU64::new(0),
+            pending_out: AtomicU64::new(0),
+            inbound: Throttle::unlimited(),
+            outbound: Throttle::unlimited(),
+            copy_yield_bytes: DEFAULT_COPY_YIELD_BYTES,
+            drain: None,
+        }
+    }
+
+    pub fn with_throttles(mut self, inbound: Throttle, outbound: Throttle) -> Self {
+        self.inbound = inbound;
+        self.outbound = outbound;
+        self
+    }
+
+    pub fn with_copy_yield_bytes(mut self, copy_yield_bytes: u64) -> Self {
+        self.copy_yield_bytes = copy_yield_bytes;
+        self
+    }
+
+    pub fn with_request_time(mut self, accepted: Instant, request_time: Duration) -> Self {
+        self.accepted = accepted;
+        self.request_time = request_time;
+        self
+    }
+
+    pub fn with_drain(mut self, drain: DrainWatch) -> Self {
+        self.drain = Some(drain);
+        self
+    }
+
+    pub fn reset_exchange(&self, request_headers: Vec<(String, String)>) {
+        if let Some(ref drain) = self.drain {
+            drain.reset_exchange(request_headers);
+        }
+    }
+
+    pub fn forward_request(&self, data: &[u8], output: &mut Vec<u8>) {
+        match self.drain {
+            Some(ref drain) => drain.forward_request(data, output),
+            None => output.extend_from_slice(data),
+        }
+    }
+
+    pub fn forward_response(&self, data: &[u8], output: &mut Vec<u8>) -> bool {
+        match self.drain {
+            Some(ref drain) => {
+                let closed = drain.forward_response(data, output);
+                self.record_requests(drain);
+                closed
+            }
+            None => {
+                output.extend_from_slice(data);
+                false
+            }
+        }
+    }
+
+    fn record_requests(&self, drain: &DrainWatch) {
+        for timing in drain.take_timings() {
+            let response = timing.responded.saturating_duration_since(timing.sent);
+            if timing.reused {
+                metrics::http_request_phases(timing.sent.saturating_duration_since(timing.started), Duration::ZERO, Duration::ZERO,
+                                             response, timing.finished.saturating_duration_since(timing.started));
+            } else {
+                let connect = Duration::from_millis(self.connect_time_ms().unwrap_or_default());
+                metrics::http_request_phases(self.request_time, Duration::from_millis(self.wait_time_ms()), connect,
+                                             response, timing.finished.saturating_duration_since(self.accepted));
+            }
+        }
+    }
+
+    pub async fn drain_idle_closed(&self) -> &'static str {
+        match self.drain {
+            Some(ref drain) => drain.clone().idle_closed(self).await,
+            None => std::future::pending().await,
+        }
+    }
+
+    pub async fn keep_alive_expired(&self, timeout: Option<Duration>) {
+        match (&self.drain, timeout) {
+            (Some(drain), Some(timeout)) => drain.keep_alive_expired(timeout).await,
+            _ => std::future::pending().await,
+        }
+    }
+
+    pub fn connect_time_ms(&self) -> Option<u64> {
+        match self.connect_ms.load(Ordering::Relaxed) {
+            u64::MAX => None,
+            ms => Some(ms.saturating_sub(self.wait_time_ms())),
+        }
+    }
+
+    pub fn wait_time_ms(&self) -> u64 {
+        self.wait_ms.load(Ordering::Relaxed)
+    }
+
+    pub fn response_time_ms(&self) -> Option<u64> {
+        match (self.connect_ms.load(Ordering::Relaxed), self.response_ms.load(Ordering::Relaxed)) {
+            (u64::MAX, _) | (_, u64::MAX) => None,
+            (connect, response) => Some(response.saturating_sub(connect)),
+        }
+    }
+
+    pub fn record_wait(&self, waited: Duration) {
+        self.wait_ms.fetch_add(waited.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    pub fn bytes_in(&self) -> u64 {
+        self.bytes_in.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_out(&self) -> u64 {
+        self.bytes_out.load(Ordering::Relaxed)
+    }
+
+    pub fn pending_high_water(&self, from: Side) -> u64 {
+        match from {
+            Side::Client => self.pending_in.load(Ordering::Relaxed),
+            Side::Server => self.pending_out.load(Ordering::Relaxed),
+        }
+    }
+
+    fn record_pending(&self, from: Side, bytes: u64) {
+        match from {
+            Side::Client => self.pending_in.fetch_max(bytes, Ordering::Relaxed),
+            Side::Server => self.pending_out.fetch_max(bytes, Ordering::Relaxed),
+        };
+    }
+
+    pub fn record_connect(&self) {
+        let elapsed = self.started.elapsed();
+        self.connect_ms.store(elapsed.as_millis() as u64, Ordering::Relaxed);
+        self.last_activity_ms.store(elapsed.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    pub fn record(&self, side: Side, bytes: u64) {
+        let counter = match side {
+            Side::Client => &self.bytes_in,
+            Side::Server => &self.bytes_out,
+        };
+        counter.fetch_add(bytes, Ordering::Relaxed);
+        let elapsed_ms = self.started.elapsed().as_millis() as u64;
+        self.last_activity_ms.store(elapsed_ms, Ordering::Relaxed);
+        if side == Side::Server && bytes > 0 {
+            let _ = self.response_ms.compare_exchange(u64::MAX, elapsed_ms, Ordering::Relaxed, Ordering::Relaxed);
+        }
+    }
+
+    async fn pace(&self, side: Side, bytes: usize) {
+        match side {
+            Side::Client => self.inbound.pace(bytes).await,
+            Side::Server => self.outbound.pace(bytes).await,
+        }
+    }
+
+    pub fn idle_for(&self) -> Duration {
+        let last = Duration::from_millis(self.last_activity_ms.load(Ordering::Relaxed));
+        self.started.elapsed().saturating_sub(last)
+    }
+}
+
+pub async fn copy_with_idle_timeout<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    from: Side,
+    timeouts: &SessionTimeouts,
+    counters: &SessionCounters,
+) -> anyhow::Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buffer = vec![0u8; COPY_BUFSIZE];
+    let mut rewritten = Vec::new();
+    let mut total = 0u64;
+    let mut since_yield = 0u64;
+    let drain = counters.drain.as_ref().filter(|drain| drain.tracks_http());
+
+    loop {
+        let read = async {
+            match timeouts.idle(from) {
+                Some(idle) => tokio::time::timeout(idle, reader.read(&mut buffer)).await.map_err(|_| idle),
+                None => Ok(reader.read(&mut buffer).await),
+            }
+        };
+        let local = async {
+            match (drain, from) {
+                (Some(drain), Side::Server) => drain.local_ready().await,
+                _ => std::future::pending().await,
+            }
+        };
+        let n = tokio::select! {
+            read = read => match read {
+                Ok(result) => result?,
+                Err(idle) => {
+                    if counters.idle_for() < idle {
+                        continue;
+                    }
+                    return Err(match from {
+                        Side::Client => SessionError::ClientIdleTimeout(idle),
+                        Side::Server => SessionError::ServerIdleTimeout(idle),
+                    }.into());
+                }
+            },
+            _ = local => {
+                rewritten.clear();
+                let closed = drain.is_some_and(|drain| drain.flush_local(&mut rewritten));
+                write_with_stall_timeout(writer, &rewritten, from, timeouts, counters).await?;
+                counters.record(from, rewritten.len() as u64);
+                if closed {
+                    writer.shutdown().await?;
+                    return Ok(total);
+                }
+                continue;
+            }
+        };
+
+        if n == 0 {
+            writer.shutdown().await?;
+            return Ok(total);
+        }
+
+        let mut closed = false;
+        let data = match (drain, from) {
+            (Some(_), Side::Server) => {
+                rewritten.clear();
+                closed = counters.forward_response(&buffer[..n], &mut rewritten);
+                &rewritten[..]
+            }
+            (Some(_), Side::Client) => {
+                rewritten.clear();
+                counters.forward_request(&buffer[..n], &mut rewritten);
+                &rewritten[..]
+            }
+            (None, _) => &buffer[..n],
+        };
+        write_with_stall_timeout(writer, data, from, timeouts, counters).await?;
+        total += n as u64;
+        counters.record(from, n as u64);
+        counters.pace(from, n).await;
+        if closed {
+            writer.shutdown().await?;
+            return Ok(total);
+        }
+
+        since_yield += n as u64;
+        if counters.copy_yield_bytes > 0 && since_yield >= counters.copy_yield_bytes {
+            since_yield = 0;
+            tokio::task::yield_now().await;
+        }
+    }
+}
+
+async fn write_with_stall_timeout<W>(
+    writer: &mut W,
+    mut data: &[u8],
+    from: Side,
+    timeouts: &SessionTimeouts,
+    counters: &SessionCounters,
+) -> anyhow::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    while !data.is_empty() {
+        let written = match timeouts.stall(from) {
+            Some(stall) => match tokio::time::timeout(stall, writer.write(data)).await {
+                Ok(result) => result?,
+                Err(_) => return Err(match from {
+                    Side::Client => SessionError::ServerWriteStall(stall),
+                    Side::Server => SessionError::ClientWriteStall(stall),
+                }.into()),
+            },
+            None => writer.write(data).await?,
+        };
+        if written == 0 {
+            return Err(io::Error::from(io::ErrorKind::WriteZero).into());
+        }
+        data = &data[written..];
+        counters.record_pending(from, data.len() as u64);
+    }
+    Ok(())
+}
+
+pub async fn read_client_payload<R>(reader: &mut R, buffer: &mut Vec<u8>, limit: usize, needed: usize, until_headers: bool, until_client_hello: bool, delay: Duration) -> anyhow::Result<bool>
+where
+    R: AsyncRead + Unpin,
+{
+    let wanted = if until_headers || until_client_hello { limit } else { needed.clamp(1, limit) };
+    let deadline = tokio::time::Instant::now() + delay;
+    let mut chunk = [0u8; 4096];
+
+    while buffer.len() < wanted {
+        if until_headers && buffer.len() >= needed && buffer.windows(4).any(|w| w == b"\r\n\r\n") {
+            return Ok(false);
+        }
+        if until_client_hello && buffer.len() >= needed && inspect::tls_record_complete(buffer) {
+            return Ok(false);
+        }
+        let room = (wanted - buffer.len()).min(chunk.len());
+        match tokio::time::timeout_at(deadline, reader.read(&mut chunk[..room])).await {
+            Ok(Ok(0)) | Err(_) => break,
+            Ok(Ok(n)) => buffer.extend_from_slice(&chunk[..n]),
+            Ok(Err(e)) => return Err(e.into()),
+        }
+    }
+
+    Ok((needed > limit || until_headers || until_client_hello) && buffer.len() >= limit)
+}
+
+pub async fn read_client_prefix<R>(reader: &mut R, buffer: &mut Vec<u8>, limit: usize, until_headers: bool, timeout: Duration) -> anyhow::Result<bool>
+where
+    R: AsyncRead + Unpin,
+{
+    let read = async {
+        let mut chunk = [0u8; 4096];
+
+        loop {
+            if !buffer.is_empty() && (!until_headers || buffer.windows(4).any(|w| w == b"\r\n\r\n")) {
+                return Ok(false);
+            }
+
+            if buffer.len() >= limit {
+                return Ok(true);
+            }
+
+            let room = (limit - buffer.len()).min(chunk.len());
+            let n = reader.read(&mut chunk[..room]).await?;
+            if n == 0 {
+                return Ok(false);
+            }
+
+            buffer.extend_from_slice(&chunk[..n]);
+        }
+    };
+
+    match tokio::time::timeout(timeout, read).await {
+        Ok(result) => result,
+        Err(_) => Err(SessionError::RequestTimeout(timeout).into()),
+    }
+}
+
+pub struct ReplayBuffer {
+    data: Vec<u8>,
+    limit: usize,
+    client_closed: bool,
+}
+
+impl ReplayBuffer {
+    pub fn new(prefix: &[u8], limit: usize) -> Self {
+        Self { data: prefix.to_vec(), limit, client_closed: false }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.data.len() as u64
+    }
+}
+
+pub async fn replay_handshake<C, S>(
+    client: &mut C,
+    server: &mut S,
+    replay: &mut ReplayBuffer,
+    timeouts: &SessionTimeouts,
+    counters: &SessionCounters,
+) -> anyhow::Result<Vec<u8>>
+where
+    C: AsyncRead + Unpin,
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let aborted = |_: io::Error| SessionError::ServerAborted;
+
+    let mut rewritten = Vec::new();
+    counters.forward_request(&replay.data, &mut rewritten);
+    server.write_all(&rewritten).await.map_err(aborted)?;
+    if replay.client_closed {
+        server.shutdown().await.map_err(aborted)?;
+    }
+
+    let mut response = vec![0u8; 16 * 1024];
+    let mut chunk = [0u8; 4096];
+
+    loop {
+        if replay.data.len() >= replay.limit {
+            return Ok(Vec::new());
+        }
+
+        let room = (replay.limit - replay.data.len()).min(chunk.len());
+        let idle = async {
+            match timeouts.server {
+                Some(idle) => tokio::time::sleep(idle).await,
+                None => std::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            result = server.read(&mut response) => match result {
+                Ok(0) | Err(_) => return Err(SessionError::ServerAborted.into()),
+                Ok(n) => {
+                    response.truncate(n);
+                    return Ok(response);
+                }
+            },
+            result = client.read(&mut chunk[..room]), if !replay.client_closed => match result? {
+                0 => {
+                    replay.client_closed = true;
+                    server.shutdown().await.map_err(aborted)?;
+                }
+                n => {
+                    replay.data.extend_from_slice(&chunk[..n]);
+                    rewritten.clear();
+                    counters.forward_request(&chunk[..n], &mut rewritten);
+                    server.write_all(&rewritten).await.map_err(aborted)?;
+                }
+            },
+            _ = idle => return Err(SessionError::ServerIdleTimeout(timeouts.server.unwrap_or_default()).into()),
+        }
+    }
+}
+
+fn interleave_families(addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+    let Some(first) = addrs.first() else {
+        return Vec::new();
+    };
+    let (preferred, other): (Vec<SocketAddr>, Vec<SocketAddr>) = addrs.iter()
+        .partition(|addr| addr.is_ipv6() == first.is_ipv6());
+
+    let mut ordered = Vec::with_capacity(addrs.len());
+    let mut preferred = preferred.into_iter();
+    let mut other = other.into_iter();
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => return ordered,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+async fn connect_addr(addr: SocketAddr, fast_open: bool) -> io::Result<(TcpStream, SocketAddr)> {
+    if !fast_open {
+        return TcpStream::connect(addr).await.map(|stream| (stream, addr));
+    }
+
+    let socket = if addr.is_ipv6() { TcpSocket::new_v6()? } else { TcpSocket::new_v4()? };
+    utils::set_fast_open_connect(&SockRef::from(&socket))?;
+    socket.connect(addr).await.map(|stream| (stream, addr))
+}
+
+pub async fn connect_race(addrs: &[SocketAddr], delay: Duration, fast_open: bool) -> io::Result<(TcpStream, SocketAddr)> {
+    let mut pending = interleave_families(addrs).into_iter().peekable();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_error = None;
+
+    loop {
+        if attempts.is_empty() {
+            match pending.next() {
+                Some(addr) => attempts.push(connect_addr(addr, fast_open)),
+                None => return Err(last_error.unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address to connect to"))),
+            }
+        }
+
+        tokio::select! {
+            Some(result) = attempts.next() => match result {
+                Ok(connected) => return Ok(connected),
+                Err(e) => {
+                    last_error = Some(e);
+                    if let Some(addr) = pending.next() {
+                        attempts.push(connect_addr(addr, fast_open));
+                    }
+                }
+            },
+            _ = tokio::time::sleep(delay), if pending.peek().is_some() => {
+                if let Some(addr) = pending.next() {
+                    attempts.push(connect_addr(addr, fast_open));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::duplex;
+
+    const IDLE: Duration = Duration::from_millis(100);
+
+    fn labels(error: &anyhow::Error) -> (&'static str, &'static str) {
+        let session_error = SessionError::classify(error).unwrap_or_else(|| panic!("not a session error: {error:#}"));
+        (session_error.error_type(), session_error.termination_state())
+    }
+
+    #[test]
+    fn every_error_has_its_type_and_termination_state() {
+        let cases = [
+            (SessionError::ConnectTimeout("127.0.0.1:80".to_string(), IDLE), "connect_timeout", "sC"),
+            (SessionError::ClientIdleTimeout(IDLE), "client_idle_timeout", "cD"),
+            (SessionError::ServerIdleTimeout(IDLE), "server_idle_timeout", "sD"),
+            (SessionError::SessionMaxDuration(IDLE), "session_max_duration", "tD"),
+            (SessionError::RequestTimeout(IDLE), "request_timeout", "cR"),
+            (SessionError::ClientFinTimeout(IDLE), "client_fin_timeout", "cD"),
+            (SessionError::ClientWriteStall(IDLE), "client_write_stall", "cW"),
+            (SessionError::ServerWriteStall(IDLE), "server_write_stall", "sW"),
+            (SessionError::ServerAborted, "server_aborted", "SH"),
+            (SessionError::TlsHandshake("timeout"), "tls_handshake", "cR"),
+            (SessionError::TlsHandshake("bad certificate"), "tls_handshake", "CR"),
+            (SessionError::ProxyProtocol("bad signature".to_string()), "proxy_protocol", "CR"),
+            (SessionError::ProxyProtocolTimeout(IDLE), "proxy_protocol", "cR"),
+            (SessionError::ConnectThrottled(IDLE), "connect_throttled", "sQ"),
+        ];
+        for (error, error_type, termination_state) in cases {
+            let error = anyhow::Error::new(error).context("Backend 'be'");
+            assert_eq!(labels(&error), (error_type, termination_state), "{error:#}");
+        }
+        assert!(SessionError::classify(&anyhow::anyhow!("Connection reset")).is_none());
+    }
+
+    #[test]
+    fn connect_timeouts_are_classified_as_timed_out() {
+        let error = anyhow::Error::new(SessionError::ConnectTimeout("127.0.0.1:80".to_string(), IDLE));
+        assert_eq!(ConnectFailure::classify(&error), ConnectFailure::TimedOut);
+        let refused = anyhow::Error::new(io::Error::from(io::ErrorKind::ConnectionRefused));
+        assert_eq!(ConnectFailure::classify(&refused), ConnectFailure::Refused);
+    }
+
+    #[tokio::test]
+    async fn idle_timeouts_name_the_silent_side() {
+        for (from, expected) in [(Side::Client, ("client_idle_timeout", "cD")), (Side::Server, ("server_idle_timeout", "sD"))] {
+            let (mut silent, _peer) = duplex(64);
+            let (mut writer, _reader) = duplex(64);
+            let timeouts = SessionTimeouts { client: Some(IDLE), server: Some(IDLE), ..SessionTimeouts::default() };
+            let started = Instant::now();
+            let error = copy_with_idle_timeout(&mut silent, &mut writer, from, &timeouts, &SessionCounters::new()).await.unwrap_err();
+            assert_eq!(labels(&error), expected);
+            assert!(started.elapsed() >= IDLE);
+        }
+    }
+
+    #[tokio::test]
+    async fn traffic_keeps_an_idle_timeout_from_firing() {
+        let (mut client, mut peer) = duplex(64);
+        let (mut writer, mut reader) = duplex(64);
+        let timeouts = SessionTimeouts { client: Some(IDLE), ..SessionTimeouts::default() };
+        let counters = SessionCounters::new();
+        let trickle = async {
+            for _ in 0..5 {
+                tokio::time::sleep(IDLE / 2).await;
+                peer.write_all(b"x").await.unwrap();
+            }
+            drop(peer);
+        };
+        let (copied, _) = tokio::join!(copy_with_idle_timeout(&mut client, &mut writer, Side::Client, &timeouts, &counters), trickle);
+        assert_eq!(copied.unwrap(), 5);
+        let mut forwarded = Vec::new();
+        reader.read_to_end(&mut forwarded).await.unwrap();
+        assert_eq!(forwarded, b"xxxxx");
+    }
+
+    #[tokio::test]
+    async fn clients_without_a_complete_request_expire_with_cr() {
+        for sent in [&b""[..], b"GET / HTTP/1.1\r\nHost: a\r\n"] {
+            let (mut client, mut peer) = duplex(1024);
+            peer.write_all(sent).await.unwrap();
+            let mut prefix = Vec::new();
+            let error = read_client_prefix(&mut client, &mut prefix, 1024, true, IDLE).await.unwrap_err();
+            assert_eq!(labels(&error), ("request_timeout", "cR"));
+            assert_eq!(prefix, sent);
+        }
+
+        let (mut client, mut peer) = duplex(1024);
+        peer.write_all(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n").await.unwrap();
+        let mut prefix = Vec::new();
+        assert!(!read_client_prefix(&mut client, &mut prefix, 1024, true, IDLE).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn write_stalls_name_the_side_that_stopped_reading() {
+        for (from, expected) in [(Side::Server, ("client_write_stall", "cW")), (Side::Client, ("server_write_stall", "sW"))] {
+            let (mut reader, mut sender) = duplex(64 * 1024);
+            // The peer on the other end never reads, so at most 16 bytes get through.
+            let (mut stalled, _not_reading) = duplex(16);
+            let timeouts = SessionTimeouts { client_stall: Some(IDLE), server_stall: Some(IDLE), ..SessionTimeouts::default() };
+            let counters = SessionCounters::new();
+            sender.write_all(&[0u8; 1024]).await.unwrap();
+
+            let error = copy_with_idle_timeout(&mut reader, &mut stalled, from, &timeouts, &counters).await.unwrap_err();
+            assert_eq!(labels(&error), expected);
+            assert_eq!(counters.pending_high_water(from), 1024 - 16);
+        }
+    }
+
+    #[tokio::test]
+    async fn idle_sessions_without_pending_data_do_not_stall() {
+        let (mut reader, sender) = duplex(64);
+        let (mut writer, _not_reading) = duplex(16);
+        let timeouts = SessionTimeouts { client_stall: Some(IDLE), server_stall: Some(IDLE), ..SessionTimeouts::default() };
+        let counters = SessionCounters::new();
+        let close = async {
+            tokio::time::sleep(IDLE * 3).await;
+            drop(sender);
+        };
+        let (copied, _) = tokio::join!(copy_with_idle_timeout(&mut reader, &mut writer, Side::Server, &timeouts, &counters), close);
+        assert_eq!(copied.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn a_dead_address_costs_one_race_delay() {
+        // Once the backlog of a listener that never accepts is full, new SYNs go unanswered.
+        let unanswered = TcpSocket::new_v4().unwrap();
+        unanswered.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let unanswered = unanswered.listen(0).unwrap();
+        let dead = unanswered.local_addr().unwrap();
+        let mut fillers = Vec::new();
+        for _ in 0..4 {
+            if let Ok(Ok(stream)) = tokio::time::timeout(IDLE, TcpStream::connect(dead)).await {
+                fillers.push(stream);
+            }
+        }
+        let live_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let live = live_listener.local_addr().unwrap();
+
+        let started = Instant::now();
+        let (_stream, connected) = connect_race(&[dead, live], IDLE, false).await.unwrap();
+        assert_eq!(connected, live);
+        assert!(started.elapsed() >= IDLE && started.elapsed() < IDLE * 5, "connected after {:?}", started.elapsed());
+
+        let refused = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let started = Instant::now();
+        let (_stream, connected) = connect_race(&[refused, live], Duration::from_secs(5), false).await.unwrap();
+        assert_eq!(connected, live);
+        assert!(started.elapsed() < IDLE, "a refused address waited {:?}", started.elapsed());
+    }
+
+    // A reader and a writer that are always ready and never return to the scheduler on their own.
+    struct Endless(u64);
+
+    impl AsyncRead for Endless {
+        fn poll_read(mut self: std::pin::Pin<&mut Self>, _cx: &mut std::task::Context<'_>, buf: &mut tokio::io::ReadBuf<'_>) -> std::task::Poll<io::Result<()>> {
+            let n = (buf.remaining() as u64).min(self.0) as usize;
+            buf.put_slice(&vec![0u8; n]);
+            self.0 -= n as u64;
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    struct Discard;
+
+    impl AsyncWrite for Discard {
+        fn poll_write(self: std::pin::Pin<&mut Self>, _cx: &mut std::task::Context<'_>, buf: &[u8]) -> std::task::Poll<io::Result<usize>> {
+            std::task::Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: std::pin::Pin<&mut Self>, _cx: &mut std::task::Context<'_>) -> std::task::Poll<io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: std::pin::Pin<&mut Self>, _cx: &mut std::task::Context<'_>) -> std::task::Poll<io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    // Round trips of 64-byte echo requests, on the same single-threaded runtime, while 256m are copied in bulk.
+    async fn echo_latencies_during_bulk_copy(copy_yield_bytes: u64) -> Vec<Duration> {
+        let (mut client, mut echoed) = duplex(1024);
+        let echo = tokio::spawn(async move {
+            let mut request = [0u8; 64];
+            while echoed.read_exact(&mut request).await.is_ok() {
+                echoed.write_all(&request).await.unwrap();
+            }
+        });
+        let counters = SessionCounters::new().with_copy_yield_bytes(copy_yield_bytes);
+        let copying = std::cell::Cell::new(true);
+        let bulk = async {
+            let copied = copy_with_idle_timeout(&mut Endless(256 << 20), &mut Discard, Side::Client, &SessionTimeouts::default(), &counters).await;
+            copying.set(false);
+            copied.unwrap()
+        };
+        let requests = async {
+            let mut latencies = Vec::new();
+            let mut response = [0u8; 64];
+            while copying.get() {
+                let started = Instant::now();
+                client.write_all(&[1u8; 64]).await.unwrap();
+                client.read_exact(&mut response).await.unwrap();
+                latencies.push(started.elapsed());
+            }
+            latencies
+        };
+        // The first request goes out before the copy starts.
+        let (mut latencies, copied) = tokio::join!(requests, bulk);
+        assert_eq!(copied, 256 << 20);
+        echo.abort();
+        latencies.sort();
+        latencies
+    }
+
+    fn p99(latencies: &[Duration]) -> Duration {
+        latencies[(latencies.len() * 99).div_ceil(100) - 1]
+    }
+
+    #[tokio::test]
+    async fn bulk_copies_yield_to_small_requests() {
+        let yielding = echo_latencies_during_bulk_copy(DEFAULT_COPY_YIELD_BYTES).await;
+        let monopolizing = echo_latencies_during_bulk_copy(0).await;
+
+        assert!(yielding.len() >= 128, "only {} requests completed during the copy", yielding.len());
+        assert_eq!(monopolizing.len(), 1, "{monopolizing:?}");
+        assert!(p99(&yielding) * 4 < p99(&monopolizing), "p99 {:?} with yielding, {:?} without", p99(&yielding), p99(&monopolizing));
+    }
+}
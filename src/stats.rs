@@ -0,0 +1,494 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use serde::{Serialize, Serializer};
+use crate::acl::AclStat;
+
+pub const STAT_COLUMNS: &[&str] = &[
+    "pxname", "svname", "qcur", "qmax", "scur", "smax", "slim", "stot", "bin", "bout",
+    "dreq", "dresp", "ereq", "econ", "eresp", "wretr", "wredis", "status", "weight", "act",
+    "bck", "chkfail", "chkdown", "lastchg", "downtime", "qlimit", "pid", "iid", "sid", "throttle",
+    "lbtot", "tracked", "type", "rate", "rate_lim", "rate_max", "check_status", "check_code", "check_duration", "hrsp_1xx",
+    "hrsp_2xx", "hrsp_3xx", "hrsp_4xx", "hrsp_5xx", "hrsp_other", "hanafail", "req_rate", "req_rate_max", "req_tot", "cli_abrt",
+    "srv_abrt", "comp_in", "comp_out", "comp_byp", "comp_rsp", "lastsess", "last_chk", "last_agt", "qtime", "ctime",
+    "rtime", "ttime", "agent_status", "agent_code", "agent_duration", "check_desc", "agent_desc", "check_rise", "check_fall", "check_health",
+    "agent_rise", "agent_fall", "agent_health", "addr", "cookie", "mode", "algo", "conn_rate", "conn_rate_max", "conn_tot",
+    "intercepted", "dcon", "dses", "bwlim_in", "bwlim_out", "state",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatKind {
+    Frontend,
+    Backend,
+    Server,
+    Listener,
+}
+
+impl StatKind {
+    fn code(self) -> u8 {
+        match self {
+            StatKind::Frontend => 0,
+            StatKind::Backend => 1,
+            StatKind::Server => 2,
+            StatKind::Listener => 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StatRow {
+    pub kind: StatKind,
+    pub pxname: String,
+    pub svname: String,
+    pub iid: usize,
+    pub sid: Option<u32>,
+    pub status: String,
+    pub scur: Option<u32>,
+    pub slim: Option<u32>,
+    pub stot: Option<u64>,
+    pub bin: Option<u64>,
+    pub bout: Option<u64>,
+    pub weight: Option<u32>,
+    pub act: Option<u32>,
+    pub bck: Option<u32>,
+    pub rate: Option<u64>,
+    pub rate_lim: Option<u32>,
+    pub rate_max: Option<u64>,
+    pub check_status: Option<String>,
+    pub check_desc: Option<String>,
+    pub check_duration: Option<u64>,
+    pub check_rise: Option<u32>,
+    pub check_fall: Option<u32>,
+    pub addr: Option<String>,
+    pub mode: Option<String>,
+    pub algo: Option<String>,
+    pub bwlim_in: Option<u64>,
+    pub bwlim_out: Option<u64>,
+    pub state: Option<String>,
+}
+
+impl StatRow {
+    pub fn new(kind: StatKind, pxname: &str, svname: &str, iid: usize, status: &str) -> Self {
+        Self {
+            kind,
+            pxname: pxname.to_string(),
+            svname: svname.to_string(),
+            iid,
+            sid: None,
+            status: status.to_string(),
+            scur: None,
+            slim: None,
+            stot: None,
+            bin: None,
+            bout: None,
+            weight: None,
+            act: None,
+            bck: None,
+            rate: None,
+            rate_lim: None,
+            rate_max: None,
+            check_status: None,
+            check_desc: None,
+            check_duration: None,
+            check_rise: None,
+            check_fall: None,
+            addr: None,
+            mode: None,
+            algo: None,
+            bwlim_in: None,
+            bwlim_out: None,
+            state: None,
+        }
+    }
+
+    fn value(&self, column: &str) -> Option<String> {
+        let number = |value: Option<u32>| value.map(|value| value.to_string());
+        match column {
+            "pxname" => Some(self.pxname.clone()),
+            "svname" => Some(self.svname.clone()),
+            "scur" => number(self.scur),
+            "slim" => number(self.slim),
+            "stot" => self.stot.map(|stot| stot.to_string()),
+            "bin" => self.bin.map(|bytes| bytes.to_string()),
+            "bout" => self.bout.map(|bytes| bytes.to_string()),
+            "status" => Some(self.status.clone()),
+            "weight" => number(self.weight),
+            "act" => number(self.act),
+            "bck" => number(self.bck),
+            "pid" => Some(std::process::id().to_string()),
+            "iid" => Some(self.iid.to_string()),
+            "sid" => Some(self.sid.unwrap_or(0).to_string()),
+            "type" => Some(self.kind.code().to_string()),
+            "rate" | "conn_rate" => self.rate.map(|rate| rate.to_string()),
+            "rate_lim" => number(self.rate_lim),
+            "rate_max" | "conn_rate_max" => self.rate_max.map(|rate| rate.to_string()),
+            "check_status" => self.check_status.clone(),
+            "check_desc" => self.check_desc.clone(),
+            "check_duration" => self.check_duration.map(|ms| ms.to_string()),
+            "check_rise" => number(self.check_rise),
+            "check_fall" => number(self.check_fall),
+            "addr" => self.addr.clone(),
+            "mode" => self.mode.clone(),
+            "algo" => self.algo.clone(),
+            "bwlim_in" => self.bwlim_in.map(|rate| rate.to_string()),
+            "bwlim_out" => self.bwlim_out.map(|rate| rate.to_string()),
+            "state" => self.state.clone(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum InfoValue {
+    Text(String),
+    Number(u64),
+}
+
+impl std::fmt::Display for InfoValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InfoValue::Text(text) => f.write_str(text),
+            InfoValue::Number(number) => write!(f, "{}", number),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InfoField {
+    pub name: &'static str,
+    pub value: InfoValue,
+}
+
+impl InfoField {
+    pub fn text(name: &'static str, value: impl Into<String>) -> Self {
+        Self { name, value: InfoValue::Text(value.into()) }
+    }
+
+    pub fn number(name: &'static str, value: u64) -> Self {
+        Self { name, value: InfoValue::Number(value) }
+    }
+}
+
+pub struct ProcessStats {
+    started: Instant,
+    current: AtomicU64,
+    peak: AtomicU64,
+    total: AtomicU64,
+    denied: AtomicU64,
+    reloads: AtomicU64,
+    conn_rate_max: AtomicU64,
+    ready: AtomicBool,
+}
+
+impl Default for ProcessStats {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            current: AtomicU64::new(0),
+            peak: AtomicU64::new(0),
+            total: AtomicU64::new(0),
+            denied: AtomicU64::new(0),
+            reloads: AtomicU64::new(0),
+            conn_rate_max: AtomicU64::new(0),
+            ready: AtomicBool::new(false),
+        }
+    }
+}
+
+impl ProcessStats {
+    pub fn connection_accepted(&self) {
+        self.total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn connection_denied(&self) {
+        self.denied.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn session_opened(&self) {
+        let current = self.current.fetch_add(1, Ordering::Relaxed) + 1;
+        self.peak.fetch_max(current, Ordering::Relaxed);
+    }
+
+    pub fn session_closed(&self) {
+        self.current.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn reloaded(&self) {
+        self.reloads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_ready(&self) {
+        self.ready.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+
+    pub fn record_conn_rate(&self, rate: u64) {
+        self.conn_rate_max.fetch_max(rate, Ordering::Relaxed);
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn current(&self) -> u64 {
+        self.current.load(Ordering::Relaxed)
+    }
+
+    pub fn peak(&self) -> u64 {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    pub fn denied(&self) -> u64 {
+        self.denied.load(Ordering::Relaxed)
+    }
+
+    pub fn reloads(&self) -> u64 {
+        self.reloads.load(Ordering::Relaxed)
+    }
+
+    pub fn conn_rate_max(&self) -> u64 {
+        self.conn_rate_max.load(Ordering::Relaxed)
+    }
+}
+
+pub trait StatsSource: Send + Sync {
+    fn rows(&self) -> Vec<StatRow>;
+    fn acls(&self) -> Vec<AclStat>;
+    fn info(&self) -> Vec<InfoField>;
+    fn draining(&self) -> bool;
+    fn ready(&self) -> bool;
+}
+
+pub fn format_uptime(uptime: Duration) -> String {
+    let seconds = uptime.as_secs();
+    format!("{}d {}h{:02}m{:02}s", seconds / 86400, seconds / 3600 % 24, seconds / 60 % 60, seconds % 60)
+}
+
+pub fn info_to_text(fields: &[InfoField]) -> String {
+    fields.iter()
+        .map(|field| format!("{}: {}", field.name, field.value))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn info_to_json(fields: &[InfoField]) -> String {
+    let mut output = Vec::new();
+    let mut serializer = serde_json::Serializer::new(&mut output);
+    match serializer.collect_map(fields.iter().map(|field| (field.name, &field.value))) {
+        Ok(()) => String::from_utf8(output).unwrap_or_else(|_| "{}".to_string()),
+        Err(_) => "{}".to_string(),
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn to_csv(rows: &[StatRow]) -> String {
+    let mut output = format!("# {},\n", STAT_COLUMNS.join(","));
+    for row in rows {
+        for column in STAT_COLUMNS {
+            output.push_str(&row.value(column).map(|value| csv_field(&value)).unwrap_or_default());
+            output.push(',');
+        }
+        output.push('\n');
+    }
+    output
+}
+
+pub fn to_json(rows: &[StatRow]) -> String {
+    let rows: Vec<serde_json::Map<String, serde_json::Value>> = rows.iter()
+        .map(|row| STAT_COLUMNS.iter()
+            .filter_map(|column| row.value(column).map(|value| (column.to_string(), serde_json::Value::String(value))))
+            .collect())
+        .collect();
+    serde_json::to_string(&rows).unwrap_or_else(|_| "[]".to_string())
+}
+
+fn html_escape(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HtmlOptions {
+    pub refresh_secs: Option<u64>,
+    pub admin: bool,
+}
+
+const HTML_STYLE: &str = "body{font-family:sans-serif;font-size:13px}\
+table{border-collapse:collapse;margin-bottom:16px}th,td{border:1px solid #999;padding:2px 6px;text-align:right}\
+th{background:#ddd}td.name{text-align:left}tr.total{font-weight:bold;background:#eee}\
+.up{background:#c0ffc0}.down{background:#ffa0a0}.drain{background:#a0c0ff}.maint{background:#c0c0c0}\
+.nocheck{background:#e0e0ff}.open{background:#c0ffc0}.stop{background:#ffa0a0}form{display:inline}";
+
+const FRONTEND_COLUMNS: &[(&str, &str)] = &[
+    ("svname", "Name"), ("status", "Status"), ("scur", "Cur"), ("slim", "Limit"), ("stot", "Total"),
+    ("rate", "Rate"), ("rate_max", "Rate max"), ("rate_lim", "Rate limit"), ("bin", "Bytes in"), ("bout", "Bytes out"),
+    ("addr", "Address"),
+];
+
+const BACKEND_COLUMNS: &[(&str, &str)] = &[
+    ("svname", "Name"), ("status", "Status"), ("weight", "Weight"), ("act", "Act"), ("bck", "Bck"),
+    ("scur", "Cur"), ("slim", "Limit"), ("stot", "Total"), ("bin", "Bytes in"), ("bout", "Bytes out"),
+    ("check_status", "Last check"), ("check_desc", "Check error"), ("check_duration", "Check ms"), ("addr", "Address"),
+];
+
+fn status_class(status: &str) -> &'static str {
+    match status {
+        "UP" => "up",
+        "DOWN" => "down",
+        "DRAIN" => "drain",
+        "MAINT" => "maint",
+        "OPEN" => "open",
+        "STOP" => "stop",
+        _ => "nocheck",
+    }
+}
+
+fn html_table(output: &mut String, title: &str, columns: &[(&str, &str)], rows: &[&StatRow], options: &HtmlOptions) {
+    let admin = options.admin && rows.first().is_some_and(|row| row.kind == StatKind::Backend || row.kind == StatKind::Server);
+    output.push_str(&format!("<h2>{}</h2>\n<table>\n<tr>", html_escape(title)));
+    for (_, header) in columns {
+        output.push_str(&format!("<th>{}</th>", header));
+    }
+    if admin {
+        output.push_str("<th>Action</th>");
+    }
+    output.push_str("</tr>\n");
+
+    for row in rows {
+        let total = matches!(row.kind, StatKind::Frontend | StatKind::Backend);
+        output.push_str(if total { "<tr class=\"total\">" } else { "<tr>" });
+        for (column, _) in columns {
+            let value = html_escape(&row.value(column).unwrap_or_default());
+            match *column {
+                "svname" => output.push_str(&format!("<td class=\"name\">{}</td>", value)),
+                "status" => output.push_str(&format!("<td class=\"{}\">{}</td>", status_class(&row.status), value)),
+                _ => output.push_str(&format!("<td>{}</td>", value)),
+            }
+        }
+        if admin {
+            output.push_str("<td>");
+            if row.kind == StatKind::Server {
+                for (state, label) in [("ready", "enable"), ("drain", "drain"), ("maint", "disable")] {
+                    output.push_str(&format!(
+                        "<form method=\"post\" action=\"/server?backend={}&amp;server={}&amp;state={}\"><button>{}</button></form>",
+                        html_escape(&row.pxname), html_escape(&row.svname), state, label
+                    ));
+                }
+            }
+            output.push_str("</td>");
+        }
+        output.push_str("</tr>\n");
+    }
+    output.push_str("</table>\n");
+}
+
+pub fn to_html(rows: &[StatRow], options: &HtmlOptions) -> String {
+    let mut output = String::from("<!DOCTYPE html>\n<html><head><title>Turbogate statistics</title>\n");
+    if let Some(refresh) = options.refresh_secs {
+        output.push_str(&format!("<meta http-equiv=\"refresh\" content=\"{}\">\n", refresh));
+    }
+    output.push_str(&format!("<style>{}</style>\n</head><body>\n<h1>Turbogate statistics</h1>\n", HTML_STYLE));
+
+    let mut start = 0;
+    while start < rows.len() {
+        let first = &rows[start];
+        let end = rows[start..].iter()
+            .position(|row| row.pxname != first.pxname || row.iid != first.iid)
+            .map_or(rows.len(), |offset| start + offset);
+        let group: Vec<&StatRow> = rows[start..end].iter().collect();
+        match first.kind {
+            StatKind::Frontend | StatKind::Listener => html_table(&mut output, &format!("Frontend {}", first.pxname), FRONTEND_COLUMNS, &group, options),
+            StatKind::Backend | StatKind::Server => html_table(&mut output, &format!("Backend {}", first.pxname), BACKEND_COLUMNS, &group, options),
+        }
+        start = end;
+    }
+    output.push_str("</body></html>\n");
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows() -> Vec<StatRow> {
+        let frontend = StatRow { scur: Some(3), stot: Some(10), ..StatRow::new(StatKind::Frontend, "web", "FRONTEND", 1, "OPEN") };
+        let backend = StatRow { algo: Some("roundrobin".to_string()), ..StatRow::new(StatKind::Backend, "app", "BACKEND", 2, "UP") };
+        let server = StatRow {
+            sid: Some(1),
+            weight: Some(100),
+            check_desc: Some("Connection refused, \"port 8080\"".to_string()),
+            ..StatRow::new(StatKind::Server, "app", "<s1>", 2, "DOWN")
+        };
+        vec![frontend, backend, server]
+    }
+
+    fn column(name: &str) -> usize {
+        STAT_COLUMNS.iter().position(|column| *column == name).unwrap()
+    }
+
+    #[test]
+    fn csv_has_the_haproxy_header_and_one_field_per_column() {
+        let csv = to_csv(&rows());
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("# pxname,svname,qcur,qmax,scur,smax,slim,stot,"));
+        assert!(lines[0].ends_with(",bwlim_in,bwlim_out,state,"));
+        let frontend: Vec<&str> = lines[1].split(',').collect();
+        assert_eq!(frontend.len(), STAT_COLUMNS.len() + 1);
+        assert_eq!([frontend[column("pxname")], frontend[column("svname")], frontend[column("scur")], frontend[column("stot")], frontend[column("status")]],
+                   ["web", "FRONTEND", "3", "10", "OPEN"]);
+        assert_eq!(frontend[column("type")], "0");
+        assert_eq!(frontend[column("qcur")], "", "untracked columns are empty");
+    }
+
+    #[test]
+    fn csv_quotes_fields_with_separators() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert!(to_csv(&rows()).contains(",\"Connection refused, \"\"port 8080\"\"\","));
+    }
+
+    #[test]
+    fn json_skips_untracked_columns() {
+        let json: serde_json::Value = serde_json::from_str(&to_json(&rows())).unwrap();
+        assert_eq!(json[2]["svname"], "<s1>");
+        assert_eq!(json[2]["weight"], "100");
+        assert_eq!(json[2]["type"], "2");
+        assert!(json[2].get("qcur").is_none());
+    }
+
+    #[test]
+    fn html_groups_rows_by_proxy_and_escapes_names() {
+        let html = to_html(&rows(), &HtmlOptions { refresh_secs: Some(5), admin: true });
+        assert!(html.contains("<meta http-equiv=\"refresh\" content=\"5\">"));
+        assert!(html.contains("<h2>Frontend web</h2>"));
+        assert!(html.contains("<h2>Backend app</h2>"));
+        assert!(html.contains("<td class=\"name\">&lt;s1&gt;</td><td class=\"down\">DOWN</td>"));
+        assert!(html.contains("action=\"/server?backend=app&amp;server=&lt;s1&gt;&amp;state=maint\""));
+        assert_eq!(html.matches("<th>Action</th>").count(), 1, "only backends get admin actions");
+        assert!(!to_html(&rows(), &HtmlOptions { refresh_secs: None, admin: false }).contains("<form"));
+    }
+
+    #[test]
+    fn uptime_and_info_formats() {
+        assert_eq!(format_uptime(Duration::from_secs(2 * 86400 + 3 * 3600 + 4 * 60 + 5)), "2d 3h04m05s");
+        let fields = [InfoField::text("Name", "turbogate"), InfoField::number("CurrConns", 7)];
+        assert_eq!(info_to_text(&fields), "Name: turbogate\nCurrConns: 7");
+        assert_eq!(info_to_json(&fields), "{\"Name\":\"turbogate\",\"CurrConns\":7}");
+    }
+}
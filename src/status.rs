@@ -0,0 +1,318 @@
+use crate::config::BackendConfig;
+use crate::health::ServerStatus;
+use crate::session::ConnectFailure;
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// Versions are unique across tables, so a thread's cached snapshot can never be
+// mistaken for the current one of another table.
+static NEXT_VERSION: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static CACHED: RefCell<Option<(u64, Arc<StatusSnapshot>)>> = const { RefCell::new(None) };
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerReport {
+    pub status: ServerStatus,
+    pub interval: Option<Duration>,
+    pub failure: Option<ConnectFailure>,
+    pub duration: Option<Duration>,
+}
+
+#[derive(Clone)]
+struct ServerSlot {
+    name: String,
+    id: Option<u32>,
+    report: ServerReport,
+}
+
+#[derive(Clone)]
+struct BackendSlots {
+    name: String,
+    servers: Vec<ServerSlot>,
+}
+
+// An immutable view of every server's health, indexed by (backend, server).
+// Backends are shared between snapshots, so a publish only copies the one it
+// changes.
+#[derive(Clone, Default)]
+pub struct StatusSnapshot {
+    backends: Vec<Arc<BackendSlots>>,
+}
+
+impl StatusSnapshot {
+    fn new(backends: &[BackendConfig], previous: &StatusSnapshot) -> Self {
+        let backends = backends.iter()
+            .map(|backend| {
+                let previous = previous.backends.iter().find(|slots| slots.name == backend.name);
+                Arc::new(BackendSlots {
+                    name: backend.name.clone(),
+                    servers: backend.server.iter()
+                        .map(|server| {
+                            let status = previous
+                                .and_then(|slots| server.predecessor(&slots.servers, |slot| (&slot.name, slot.id)))
+                                .map(|slot| slot.report.status.clone())
+                                .filter(|status| *status != ServerStatus::Unchecked)
+                                .unwrap_or(ServerStatus::Up);
+                            ServerSlot {
+                                name: server.name.clone(),
+                                id: server.id,
+                                report: ServerReport {
+                                    status: if server.check == Some(true) { status } else { ServerStatus::Unchecked },
+                                    interval: None,
+                                    failure: None,
+                                    duration: None,
+                                },
+                            }
+                        })
+                        .collect(),
+                })
+            })
+            .collect();
+        Self { backends }
+    }
+
+    fn report(&self, backend: usize, server: usize) -> Option<&ServerReport> {
+        Some(&self.backends.get(backend)?.servers.get(server)?.report)
+    }
+
+    pub fn backend_index(&self, name: &str) -> Option<usize> {
+        self.backends.iter().position(|backend| backend.name == name)
+    }
+
+    pub fn status(&self, backend: usize, server: usize) -> Option<ServerStatus> {
+        self.report(backend, server).map(|report| report.status.clone())
+    }
+
+    pub fn check_interval(&self, backend: usize, server: usize) -> Option<Duration> {
+        self.report(backend, server)?.interval
+    }
+
+    pub fn last_failure(&self, backend: usize, server: usize) -> Option<ConnectFailure> {
+        self.report(backend, server)?.failure
+    }
+
+    pub fn check_duration(&self, backend: usize, server: usize) -> Option<Duration> {
+        self.report(backend, server)?.duration
+    }
+}
+
+// Server health shared between the health checkers, the proxy and the admin
+// socket. Writers publish a whole new snapshot; readers keep the last one they
+// saw per thread and only take the lock after a publish, so the connection path
+// reads two array slots without locking.
+pub struct StatusTable {
+    version: AtomicU64,
+    current: Mutex<(u64, Arc<StatusSnapshot>)>,
+}
+
+impl StatusTable {
+    pub fn new(backends: &[BackendConfig]) -> Self {
+        let version = NEXT_VERSION.fetch_add(1, Ordering::Relaxed);
+        let snapshot = Arc::new(StatusSnapshot::new(backends, &StatusSnapshot::default()));
+        Self { version: AtomicU64::new(version), current: Mutex::new((version, snapshot)) }
+    }
+
+    pub fn snapshot(&self) -> Arc<StatusSnapshot> {
+        self.read(Arc::clone)
+    }
+
+    // Runs `read` on the current snapshot without touching its reference count,
+    // which every reader thread would otherwise contend on. `read` must not use
+    // the table again.
+    pub fn read<R>(&self, read: impl FnOnce(&Arc<StatusSnapshot>) -> R) -> R {
+        let version = self.version.load(Ordering::Acquire);
+        CACHED.with(|cached| {
+            let mut cached = cached.borrow_mut();
+            if !matches!(*cached, Some((seen, _)) if seen == version) {
+                let (version, ref snapshot) = *self.current.lock().unwrap_or_else(|e| e.into_inner());
+                *cached = Some((version, Arc::clone(snapshot)));
+            }
+            let (_, ref snapshot) = cached.as_ref().expect("cached above");
+            read(snapshot)
+        })
+    }
+
+    fn update(&self, change: impl FnOnce(&mut StatusSnapshot)) {
+        let mut current = self.current.lock().unwrap_or_else(|e| e.into_inner());
+        let mut snapshot = StatusSnapshot::clone(&current.1);
+        change(&mut snapshot);
+        let version = NEXT_VERSION.fetch_add(1, Ordering::Relaxed);
+        *current = (version, Arc::new(snapshot));
+        self.version.store(version, Ordering::Release);
+    }
+
+    pub fn replace(&self, backends: &[BackendConfig]) {
+        self.update(|snapshot| *snapshot = StatusSnapshot::new(backends, snapshot));
+    }
+
+    pub fn backend_index(&self, name: &str) -> Option<usize> {
+        self.read(|snapshot| snapshot.backend_index(name))
+    }
+
+    pub fn status(&self, backend: usize, server: usize) -> Option<ServerStatus> {
+        self.read(|snapshot| snapshot.status(backend, server))
+    }
+
+    // Publishes the results of one health check cycle of a backend at once.
+    pub fn publish<I>(&self, backend: usize, reports: I)
+    where
+        I: IntoIterator<Item = (usize, ServerReport)>,
+    {
+        let reports: Vec<(usize, ServerReport)> = reports.into_iter().collect();
+        self.update(|snapshot| {
+            let Some(slots) = snapshot.backends.get_mut(backend).map(Arc::make_mut) else {
+                return;
+            };
+            for (server, report) in reports {
+                if let Some(slot) = slots.servers.get_mut(server) {
+                    slot.report = ServerReport {
+                        interval: report.interval.filter(|interval| !interval.is_zero()),
+                        ..report
+                    };
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn backends(config: &str) -> Vec<BackendConfig> {
+        Config::from_haproxy_config(config, None).unwrap().backends
+    }
+
+    const TWO_BACKENDS: &str = "backend a\n    server a1 127.0.0.1:1 check\n    server a2 127.0.0.1:2 check\n    server a3 127.0.0.1:3\n\nbackend b\n    server b1 127.0.0.1:4 check\n";
+
+    fn report(status: ServerStatus) -> ServerReport {
+        ServerReport {
+            status,
+            interval: Some(Duration::from_secs(2)),
+            failure: Some(ConnectFailure::ALL[0]),
+            duration: Some(Duration::from_millis(3)),
+        }
+    }
+
+    #[test]
+    fn servers_start_up_unless_unchecked() {
+        let table = StatusTable::new(&backends(TWO_BACKENDS));
+        assert_eq!(table.backend_index("b"), Some(1));
+        assert_eq!(table.status(0, 0), Some(ServerStatus::Up));
+        assert_eq!(table.status(0, 2), Some(ServerStatus::Unchecked));
+        assert_eq!(table.status(0, 3), None);
+        assert_eq!(table.status(2, 0), None);
+        assert_eq!(table.snapshot().check_interval(0, 0), None);
+    }
+
+    #[test]
+    fn publish_updates_only_the_reported_servers() {
+        let table = StatusTable::new(&backends(TWO_BACKENDS));
+        let before = table.snapshot();
+        table.publish(0, [(1, report(ServerStatus::Down)), (7, report(ServerStatus::Down))]);
+        table.publish(5, [(0, report(ServerStatus::Down))]);
+
+        let after = table.snapshot();
+        assert_eq!(after.status(0, 0), Some(ServerStatus::Up));
+        assert_eq!(after.status(0, 1), Some(ServerStatus::Down));
+        assert_eq!(after.status(1, 0), Some(ServerStatus::Up));
+        assert_eq!(after.check_interval(0, 1), Some(Duration::from_secs(2)));
+        assert_eq!(after.last_failure(0, 1), Some(ConnectFailure::ALL[0]));
+        assert_eq!(after.check_duration(0, 1), Some(Duration::from_millis(3)));
+        assert_eq!(before.status(0, 1), Some(ServerStatus::Up), "published snapshots are immutable");
+        assert!(Arc::ptr_eq(&before.backends[1], &after.backends[1]), "untouched backends are shared");
+    }
+
+    #[test]
+    fn zero_intervals_read_as_unset() {
+        let table = StatusTable::new(&backends(TWO_BACKENDS));
+        table.publish(0, [(0, ServerReport { interval: Some(Duration::ZERO), ..report(ServerStatus::Up) })]);
+        assert_eq!(table.snapshot().check_interval(0, 0), None);
+    }
+
+    #[test]
+    fn replace_keeps_statuses_by_name() {
+        let table = StatusTable::new(&backends(TWO_BACKENDS));
+        table.publish(0, [(0, report(ServerStatus::Down)), (1, report(ServerStatus::Down))]);
+        table.replace(&backends("backend b\n    server b1 127.0.0.1:4 check\n\nbackend a\n    server a2 127.0.0.1:2 check\n    server a1 127.0.0.1:1\n    server a4 127.0.0.1:5 check\n"));
+
+        assert_eq!(table.backend_index("a"), Some(1));
+        assert_eq!(table.status(1, 0), Some(ServerStatus::Down), "a2 keeps its status at its new index");
+        assert_eq!(table.status(1, 1), Some(ServerStatus::Unchecked), "a1 no longer checked");
+        assert_eq!(table.status(1, 2), Some(ServerStatus::Up), "new servers start up");
+        assert_eq!(table.status(0, 0), Some(ServerStatus::Up));
+        assert_eq!(table.snapshot().last_failure(1, 0), None, "reports are reset on reload");
+    }
+
+    #[test]
+    fn renamed_servers_keep_their_status_by_explicit_id() {
+        let table = StatusTable::new(&backends("backend a\n    server old 127.0.0.1:1 check id 7\n    server a2 127.0.0.1:2 check\n"));
+        table.publish(0, [(0, report(ServerStatus::Down))]);
+        table.replace(&backends("backend a\n    server a2 127.0.0.1:2 check\n    server new 127.0.0.1:1 check id 7\n"));
+
+        assert_eq!(table.status(0, 1), Some(ServerStatus::Down), "the renamed server keeps its status");
+        assert_eq!(table.status(0, 0), Some(ServerStatus::Up), "a2 now has the assigned id 1 but is matched by name");
+    }
+
+    #[test]
+    fn tables_do_not_share_cached_snapshots() {
+        let first = StatusTable::new(&backends(TWO_BACKENDS));
+        let second = StatusTable::new(&backends(TWO_BACKENDS));
+        first.publish(0, [(0, report(ServerStatus::Down))]);
+        assert_eq!(first.status(0, 0), Some(ServerStatus::Down));
+        assert_eq!(second.status(0, 0), Some(ServerStatus::Up));
+        assert_eq!(first.status(0, 0), Some(ServerStatus::Down));
+    }
+
+    #[test]
+    fn publishes_reach_readers_on_other_threads() {
+        let table = Arc::new(StatusTable::new(&backends(TWO_BACKENDS)));
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let table = Arc::clone(&table);
+                std::thread::spawn(move || {
+                    let mut seen = Vec::new();
+                    while seen.len() < 200 {
+                        let snapshot = table.snapshot();
+                        // A report is published whole: its fields always belong together.
+                        let report = snapshot.report(0, 0).unwrap();
+                        let expected = match report.status {
+                            ServerStatus::Down => Some(Duration::from_millis(3)),
+                            _ => None,
+                        };
+                        assert_eq!(report.duration, expected);
+                        if seen.last() != Some(&report.status) {
+                            seen.push(report.status.clone());
+                        }
+                        std::thread::yield_now();
+                    }
+                })
+            })
+            .collect();
+
+        let writer = {
+            let table = Arc::clone(&table);
+            std::thread::spawn(move || {
+                while !readers_done(&table) {
+                    table.publish(0, [(0, report(ServerStatus::Down))]);
+                    table.publish(0, [(0, ServerReport { status: ServerStatus::Up, interval: None, failure: None, duration: None })]);
+                }
+            })
+        };
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        table.publish(1, [(0, report(ServerStatus::Down))]);
+        writer.join().unwrap();
+    }
+
+    // The writer runs until the readers have finished and a final publish marks backend b down.
+    fn readers_done(table: &StatusTable) -> bool {
+        table.status(1, 0) == Some(ServerStatus::Down)
+    }
+}
@@ -0,0 +1,175 @@
+use crate::config::StickTableConfig;
+use crate::utils;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+struct StickEntry {
+    server: usize,
+    expires: Instant,
+    updated: u64,
+}
+
+pub struct StickSnapshot<'a> {
+    pub key: &'a [u8],
+    pub server: usize,
+    pub expires_in: Duration,
+    pub updated: u64,
+}
+
+pub struct StickTable {
+    size: usize,
+    expire: Duration,
+    entries: HashMap<Vec<u8>, StickEntry>,
+}
+
+impl StickTable {
+    pub fn new(config: &StickTableConfig) -> Self {
+        Self {
+            size: config.size,
+            expire: utils::parse_duration(&config.expire).unwrap_or(Duration::from_secs(30 * 60)),
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn lookup(&mut self, key: &[u8]) -> Option<usize> {
+        let entry = self.entries.get(key)?;
+        if entry.expires <= Instant::now() {
+            self.entries.remove(key);
+            return None;
+        }
+        Some(entry.server)
+    }
+
+    pub fn forget(&mut self, key: &[u8]) {
+        self.entries.remove(key);
+    }
+
+    pub fn forget_before(&mut self, key: &[u8], timestamp: u64) -> bool {
+        if self.entries.get(key).is_some_and(|entry| entry.updated <= timestamp) {
+            self.entries.remove(key);
+            return true;
+        }
+        false
+    }
+
+    pub fn store(&mut self, key: Vec<u8>, server: usize) -> u64 {
+        let updated = utils::unix_millis();
+        self.insert(key, server, self.expire, updated);
+        updated
+    }
+
+    pub fn apply(&mut self, key: Vec<u8>, server: usize, expires_in: Duration, updated: u64) -> bool {
+        if self.entries.get(&key).is_some_and(|entry| entry.updated > updated) {
+            return false;
+        }
+        self.insert(key, server, expires_in.min(self.expire), updated);
+        true
+    }
+
+    fn insert(&mut self, key: Vec<u8>, server: usize, expires_in: Duration, updated: u64) {
+        if self.entries.len() >= self.size && !self.entries.contains_key(&key) {
+            self.evict();
+        }
+
+        self.entries.insert(key, StickEntry {
+            server,
+            expires: Instant::now() + expires_in,
+            updated,
+        });
+    }
+
+    pub fn expire(&self) -> Duration {
+        self.expire
+    }
+
+    pub fn snapshot(&self) -> impl Iterator<Item = StickSnapshot<'_>> {
+        let now = Instant::now();
+        self.entries.iter()
+            .filter(move |(_, entry)| entry.expires > now)
+            .map(move |(key, entry)| StickSnapshot {
+                key,
+                server: entry.server,
+                expires_in: entry.expires - now,
+                updated: entry.updated,
+            })
+    }
+
+    pub fn used(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn evict(&mut self) {
+        let now = Instant::now();
+        self.entries.retain(|_, entry| entry.expires > now);
+
+        if self.entries.len() >= self.size {
+            let oldest = self.entries.iter()
+                .min_by_key(|(_, entry)| entry.expires)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(size: usize, expire: &str) -> StickTable {
+        StickTable::new(&StickTableConfig { size, expire: expire.to_string(), peers: None })
+    }
+
+    #[test]
+    fn stored_keys_map_to_their_server() {
+        let mut table = table(10, "1m");
+        table.store(b"a".to_vec(), 1);
+        table.store(b"b".to_vec(), 2);
+        table.store(b"a".to_vec(), 3);
+        assert_eq!((table.lookup(b"a"), table.lookup(b"b"), table.lookup(b"c")), (Some(3), Some(2), None));
+        assert_eq!(table.used(), 2);
+        table.forget(b"a");
+        assert_eq!(table.lookup(b"a"), None);
+    }
+
+    #[test]
+    fn expired_entries_are_dropped() {
+        let mut table = table(10, "20ms");
+        table.store(b"a".to_vec(), 1);
+        assert_eq!(table.snapshot().count(), 1);
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(table.snapshot().count(), 0);
+        assert_eq!(table.lookup(b"a"), None);
+        assert_eq!(table.used(), 0);
+    }
+
+    #[test]
+    fn a_full_table_evicts_the_entry_expiring_first() {
+        let mut table = table(2, "1m");
+        assert!(table.apply(b"short".to_vec(), 1, Duration::from_secs(5), 1));
+        assert!(table.apply(b"long".to_vec(), 2, Duration::from_secs(50), 1));
+        table.store(b"new".to_vec(), 3);
+        assert_eq!(table.used(), 2);
+        assert_eq!((table.lookup(b"short"), table.lookup(b"long"), table.lookup(b"new")), (None, Some(2), Some(3)));
+    }
+
+    #[test]
+    fn older_updates_do_not_overwrite_newer_ones() {
+        let mut table = table(10, "1m");
+        assert!(table.apply(b"a".to_vec(), 1, Duration::from_secs(10), 200));
+        assert!(!table.apply(b"a".to_vec(), 2, Duration::from_secs(10), 100));
+        assert_eq!(table.lookup(b"a"), Some(1));
+        assert!(!table.forget_before(b"a", 199));
+        assert!(table.forget_before(b"a", 200));
+        assert_eq!(table.lookup(b"a"), None);
+    }
+
+    #[test]
+    fn applied_entries_never_outlive_the_local_expire() {
+        let mut table = table(10, "1s");
+        table.apply(b"a".to_vec(), 1, Duration::from_secs(3600), 1);
+        assert!(table.snapshot().all(|entry| entry.expires_in <= Duration::from_secs(1)));
+        assert_eq!(table.expire(), Duration::from_secs(1));
+    }
+}
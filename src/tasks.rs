@@ -0,0 +1,147 @@
+use crate::metrics;
+use std::future::Future;
+use std::sync::Mutex;
+use tokio::task::{self, AbortHandle, JoinError, JoinHandle};
+use tracing::{debug, error, info};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskGroup {
+    Accept,
+    Health,
+    Background,
+    Metrics,
+}
+
+impl TaskGroup {
+    const SHUTDOWN_ORDER: [TaskGroup; 4] = [TaskGroup::Accept, TaskGroup::Health, TaskGroup::Background, TaskGroup::Metrics];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TaskGroup::Accept => "accept",
+            TaskGroup::Health => "health",
+            TaskGroup::Background => "background",
+            TaskGroup::Metrics => "metrics",
+        }
+    }
+}
+
+struct RegisteredTask {
+    group: TaskGroup,
+    abort: AbortHandle,
+    monitor: JoinHandle<()>,
+}
+
+#[derive(Default)]
+pub struct TaskRegistry {
+    tasks: Mutex<Vec<RegisteredTask>>,
+}
+
+impl TaskRegistry {
+    pub fn spawn<F>(&self, name: impl Into<String>, group: TaskGroup, future: F) -> AbortHandle
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.register(name, group, task::spawn(future))
+    }
+
+    pub fn register(&self, name: impl Into<String>, group: TaskGroup, handle: JoinHandle<()>) -> AbortHandle {
+        let name = name.into();
+        let abort = handle.abort_handle();
+        let monitor = task::spawn(async move {
+            match handle.await {
+                Ok(()) => {
+                    error!("Task '{}' exited unexpectedly", name);
+                    metrics::task_failure(&name);
+                }
+                Err(e) if e.is_panic() => {
+                    error!("Task '{}' panicked: {}", name, panic_message(e));
+                    metrics::task_failure(&name);
+                }
+                Err(_) => debug!("Task '{}' stopped", name),
+            }
+        });
+
+        let mut tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        tasks.retain(|task| !task.monitor.is_finished());
+        tasks.push(RegisteredTask { group, abort: abort.clone(), monitor });
+        abort
+    }
+
+    pub async fn shutdown(&self) {
+        for group in TaskGroup::SHUTDOWN_ORDER {
+            let stopping: Vec<RegisteredTask> = {
+                let mut tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+                let (stopping, remaining) = tasks.drain(..)
+                    .filter(|task| !task.monitor.is_finished())
+                    .partition(|task| task.group == group);
+                *tasks = remaining;
+                stopping
+            };
+            if stopping.is_empty() {
+                continue;
+            }
+
+            for task in &stopping {
+                task.abort.abort();
+            }
+            let count = stopping.len();
+            for task in stopping {
+                let _ = task.monitor.await;
+            }
+            info!("Stopped {} {} task(s)", count, group.as_str());
+        }
+    }
+}
+
+fn panic_message(error: JoinError) -> String {
+    let panic = error.into_panic();
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    struct Stopped(Arc<Mutex<Vec<&'static str>>>, &'static str);
+
+    impl Drop for Stopped {
+        fn drop(&mut self) {
+            self.0.lock().unwrap().push(self.1);
+        }
+    }
+
+    #[tokio::test]
+    async fn shutdown_stops_groups_in_order() {
+        let registry = TaskRegistry::default();
+        let stopped = Arc::new(Mutex::new(Vec::new()));
+        for (name, group) in [("stats", TaskGroup::Metrics), ("checks", TaskGroup::Health), ("listener", TaskGroup::Accept), ("peers", TaskGroup::Background)] {
+            let guard = Stopped(Arc::clone(&stopped), name);
+            registry.spawn(name, group, async move {
+                let _guard = guard;
+                std::future::pending::<()>().await;
+            });
+        }
+        registry.shutdown().await;
+        assert_eq!(*stopped.lock().unwrap(), ["listener", "checks", "peers", "stats"]);
+    }
+
+    #[tokio::test]
+    async fn finished_tasks_are_forgotten() {
+        let registry = TaskRegistry::default();
+        registry.spawn("short", TaskGroup::Background, async {});
+        registry.spawn("panicking", TaskGroup::Background, async { panic!("boom") });
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        let abort = registry.spawn("long", TaskGroup::Accept, std::future::pending());
+        assert_eq!(registry.tasks.lock().unwrap().len(), 1);
+        registry.shutdown().await;
+        assert!(abort.is_finished());
+        assert!(registry.tasks.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,398 @@
+use crate::config::BindConfig;
+use crate::metrics;
+use crate::session::SessionError;
+use anyhow::{Result, anyhow};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::{self, CertifiedKey};
+use rustls::{AlertDescription, Certificate, PeerIncompatible, PrivateKey, ProtocolVersion, ServerConfig};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::server::TlsStream;
+use tracing::{debug, info};
+
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct TlsInfo {
+    pub version: &'static str,
+    pub cipher: String,
+    pub alpn: Option<String>,
+    pub sni: Option<String>,
+}
+
+impl TlsInfo {
+    fn from_stream(stream: &TlsStream<TcpStream>) -> Self {
+        let (_, connection) = stream.get_ref();
+        Self {
+            version: match connection.protocol_version() {
+                Some(ProtocolVersion::TLSv1_3) => "TLSv1.3",
+                Some(ProtocolVersion::TLSv1_2) => "TLSv1.2",
+                _ => "unknown",
+            },
+            cipher: connection.negotiated_cipher_suite()
+                .map(|suite| format!("{:?}", suite.suite()))
+                .unwrap_or_default(),
+            alpn: connection.alpn_protocol().map(|protocol| String::from_utf8_lossy(protocol).into_owned()),
+            sni: connection.server_name().map(|name| name.to_ascii_lowercase()),
+        }
+    }
+}
+
+struct LoadedCert {
+    names: Vec<String>,
+    key: Arc<CertifiedKey>,
+}
+
+#[derive(Default)]
+struct CertIndex {
+    exact: HashMap<String, Arc<CertifiedKey>>,
+    wildcard: HashMap<String, Arc<CertifiedKey>>,
+    default: Option<Arc<CertifiedKey>>,
+}
+
+impl CertIndex {
+    fn build(certs: &BTreeMap<PathBuf, LoadedCert>, default: Option<&Path>) -> Self {
+        let mut index = CertIndex {
+            default: default.and_then(|path| certs.get(path))
+                .or_else(|| certs.values().next())
+                .map(|cert| Arc::clone(&cert.key)),
+            ..Default::default()
+        };
+        for cert in certs.values() {
+            for name in &cert.names {
+                let (table, name) = match name.strip_prefix("*.") {
+                    Some(suffix) => (&mut index.wildcard, suffix),
+                    None => (&mut index.exact, name.as_str()),
+                };
+                table.entry(name.to_string()).or_insert_with(|| Arc::clone(&cert.key));
+            }
+        }
+        index
+    }
+
+    fn lookup(&self, server_name: Option<&str>) -> Option<Arc<CertifiedKey>> {
+        let matched = server_name.and_then(|name| {
+            let name = name.trim_end_matches('.').to_ascii_lowercase();
+            self.exact.get(&name).or_else(|| {
+                let (_, parent) = name.split_once('.')?;
+                self.wildcard.get(parent)
+            }).cloned()
+        });
+        matched.or_else(|| self.default.clone())
+    }
+}
+
+struct CertStore {
+    source: PathBuf,
+    directory: bool,
+    default: Option<PathBuf>,
+    certs: RwLock<(BTreeMap<PathBuf, LoadedCert>, CertIndex)>,
+}
+
+impl CertStore {
+    fn load(crt: &str, default_crt: Option<&str>) -> Result<Self> {
+        let source = fs::canonicalize(crt).map_err(|e| anyhow!("Cannot read certificate '{}': {}", crt, e))?;
+        let directory = source.is_dir();
+        let mut paths = if directory {
+            let mut paths = Vec::new();
+            for entry in fs::read_dir(&source).map_err(|e| anyhow!("Cannot read certificate directory '{}': {}", crt, e))? {
+                let path = entry?.path();
+                if path.is_file() && path.extension().is_some_and(|extension| extension == "pem") {
+                    paths.push(path);
+                }
+            }
+            if paths.is_empty() {
+                return Err(anyhow!("Certificate directory '{}' contains no .pem file", crt));
+            }
+            paths
+        } else {
+            vec![source.clone()]
+        };
+
+        let default = match default_crt {
+            Some(default_crt) => {
+                let base = if directory { source.as_path() } else { source.parent().unwrap_or(Path::new("/")) };
+                let path = fs::canonicalize(base.join(default_crt))
+                    .map_err(|e| anyhow!("Cannot read default certificate '{}': {}", default_crt, e))?;
+                if !paths.contains(&path) {
+                    paths.push(path.clone());
+                }
+                Some(path)
+            }
+            None => None,
+        };
+
+        let mut certs = BTreeMap::new();
+        for path in paths {
+            let cert = load_cert(&path)?;
+            certs.insert(path, cert);
+        }
+        if directory {
+            info!("Loaded {} certificates from {}", certs.len(), source.display());
+        }
+
+        let index = CertIndex::build(&certs, default.as_deref());
+        Ok(Self { source, directory, default, certs: RwLock::new((certs, index)) })
+    }
+
+    fn covers(&self, path: &Path) -> bool {
+        path == self.source
+            || self.default.as_deref() == Some(path)
+            || (self.directory && path.parent() == Some(self.source.as_path()) && path.extension().is_some_and(|extension| extension == "pem"))
+    }
+
+    fn update(&self, path: &Path) -> Result<bool> {
+        if !self.covers(path) {
+            return Ok(false);
+        }
+        let cert = load_cert(path)?;
+        let mut guard = self.certs.write().unwrap_or_else(|e| e.into_inner());
+        let (certs, index) = &mut *guard;
+        certs.insert(path.to_path_buf(), cert);
+        *index = CertIndex::build(certs, self.default.as_deref());
+        Ok(true)
+    }
+}
+
+impl ResolvesServerCert for CertStore {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let guard = self.certs.read().unwrap_or_else(|e| e.into_inner());
+        guard.1.lookup(client_hello.server_name())
+    }
+}
+
+#[derive(Clone)]
+pub struct TlsTerminator {
+    acceptor: TlsAcceptor,
+    certs: Arc<CertStore>,
+}
+
+impl fmt::Debug for TlsTerminator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TlsTerminator")
+    }
+}
+
+impl TlsTerminator {
+    pub fn new(bind: &BindConfig) -> Result<Self> {
+        let crt = bind.crt.as_deref().ok_or_else(|| anyhow!("Bind {} has 'ssl' without 'crt'", bind))?;
+        let certs = Arc::new(CertStore::load(crt, bind.default_crt.as_deref())?);
+        let mut config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::clone(&certs) as Arc<dyn ResolvesServerCert>);
+        config.alpn_protocols = bind.alpn.iter().map(|protocol| protocol.as_bytes().to_vec()).collect();
+
+        Ok(Self { acceptor: TlsAcceptor::from(Arc::new(config)), certs })
+    }
+
+    pub fn shares_certs(&self, other: &TlsTerminator) -> bool {
+        Arc::ptr_eq(&self.certs, &other.certs)
+    }
+
+    pub fn update_cert(&self, path: &Path) -> Result<bool> {
+        self.certs.update(path)
+    }
+
+    pub async fn accept(&self, stream: TcpStream, frontend: &str, timeout: Duration) -> Result<(ClientStream, TlsInfo)> {
+        let started = Instant::now();
+        let result = match tokio::time::timeout(timeout, self.acceptor.accept(stream)).await {
+            Ok(Ok(stream)) => Ok(stream),
+            Ok(Err(e)) => {
+                debug!("TLS handshake on frontend {} failed: {}", frontend, e);
+                Err(handshake_failure(&e))
+            }
+            Err(_) => Err("timeout"),
+        };
+        let elapsed = started.elapsed();
+
+        match result {
+            Ok(stream) => {
+                let info = TlsInfo::from_stream(&stream);
+                metrics::tls_handshake(frontend, info.version, info.alpn.as_deref().unwrap_or("-"), "success", elapsed);
+                Ok((ClientStream::Tls(Box::new(stream)), info))
+            }
+            Err(reason) => {
+                metrics::tls_handshake(frontend, "-", "-", reason, elapsed);
+                Err(SessionError::TlsHandshake(reason).into())
+            }
+        }
+    }
+}
+
+fn handshake_failure(error: &io::Error) -> &'static str {
+    let Some(error) = error.get_ref().and_then(|inner| inner.downcast_ref::<rustls::Error>()) else {
+        return match error.kind() {
+            io::ErrorKind::UnexpectedEof | io::ErrorKind::ConnectionReset => "client_aborted",
+            _ => "io_error",
+        };
+    };
+
+    match error {
+        rustls::Error::NoCertificatesPresented => "no_cert",
+        rustls::Error::General(message) if message.contains("no server certificate") => "no_cert",
+        rustls::Error::AlertReceived(AlertDescription::UnknownCA) => "unknown_ca",
+        rustls::Error::AlertReceived(AlertDescription::ProtocolVersion) => "protocol_version",
+        rustls::Error::PeerIncompatible(
+            PeerIncompatible::SupportedVersionsExtensionRequired
+            | PeerIncompatible::SignatureAlgorithmsExtensionRequired
+            | PeerIncompatible::Tls12NotOffered
+            | PeerIncompatible::Tls12NotOfferedOrEnabled
+        ) => "protocol_version",
+        rustls::Error::PeerIncompatible(PeerIncompatible::NoCipherSuitesInCommon) => "no_shared_cipher",
+        rustls::Error::NoApplicationProtocol => "no_alpn",
+        rustls::Error::DecryptError => "decrypt_error",
+        rustls::Error::AlertReceived(_) => "alert",
+        _ => "handshake_error",
+    }
+}
+
+fn load_cert(path: &Path) -> Result<LoadedCert> {
+    let (certs, key) = load_pem(path)?;
+    let names = certificate_names(&certs[0])
+        .map_err(|e| anyhow!("Invalid certificate '{}': {}", path.display(), e))?;
+    let key = sign::any_supported_type(&key)
+        .map_err(|e| anyhow!("Invalid private key in '{}': {}", path.display(), e))?;
+    debug!("Certificate {} serves {}", path.display(), names.join(", "));
+    Ok(LoadedCert { names, key: Arc::new(CertifiedKey::new(certs, key)) })
+}
+
+fn certificate_names(cert: &Certificate) -> Result<Vec<String>> {
+    let parsed = webpki::EndEntityCert::try_from(cert.0.as_slice()).map_err(|e| anyhow!("{:?}", e))?;
+    let mut names: Vec<String> = parsed.dns_names()
+        .map(|names| names.map(|name| <&str>::from(name).to_ascii_lowercase()).collect())
+        .unwrap_or_default();
+    if names.is_empty() {
+        names.extend(common_name(&cert.0));
+    }
+    Ok(names)
+}
+
+const COMMON_NAME_OID: &[u8] = &[0x55, 0x04, 0x03];
+
+fn der_element(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (length, rest) = if first & 0x80 == 0 {
+        (usize::from(first), rest)
+    } else {
+        let count = usize::from(first & 0x7f);
+        if count == 0 || count > 4 || rest.len() < count {
+            return None;
+        }
+        let (bytes, rest) = rest.split_at(count);
+        (bytes.iter().fold(0, |length, byte| length << 8 | usize::from(*byte)), rest)
+    };
+    if rest.len() < length {
+        return None;
+    }
+    let (content, rest) = rest.split_at(length);
+    Some((tag, content, rest))
+}
+
+fn der_elements(mut input: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    std::iter::from_fn(move || {
+        let (tag, content, rest) = der_element(input)?;
+        input = rest;
+        Some((tag, content))
+    })
+}
+
+fn common_name(der: &[u8]) -> Option<String> {
+    let (_, certificate, _) = der_element(der)?;
+    let (_, tbs, _) = der_element(certificate)?;
+    let (_, subject) = der_elements(tbs).skip_while(|(tag, _)| *tag == 0xa0).nth(4)?;
+    der_elements(subject)
+        .flat_map(|(_, set)| der_elements(set))
+        .find_map(|(_, attribute)| {
+            let mut fields = der_elements(attribute);
+            let (_, oid) = fields.next()?;
+            let (_, value) = fields.next()?;
+            (oid == COMMON_NAME_OID).then(|| String::from_utf8_lossy(value).to_ascii_lowercase())
+        })
+}
+
+fn load_pem(path: &Path) -> Result<(Vec<Certificate>, PrivateKey)> {
+    let file = File::open(path).map_err(|e| anyhow!("Cannot read certificate '{}': {}", path.display(), e))?;
+    let items = rustls_pemfile::read_all(&mut BufReader::new(file))
+        .map_err(|e| anyhow!("Cannot parse certificate '{}': {}", path.display(), e))?;
+
+    let mut certs = Vec::new();
+    let mut key = None;
+    for item in items {
+        match item {
+            rustls_pemfile::Item::X509Certificate(cert) => certs.push(Certificate(cert)),
+            rustls_pemfile::Item::RSAKey(der)
+            | rustls_pemfile::Item::PKCS8Key(der)
+            | rustls_pemfile::Item::ECKey(der) if key.is_none() => key = Some(PrivateKey(der)),
+            _ => {}
+        }
+    }
+
+    if certs.is_empty() {
+        return Err(anyhow!("Certificate '{}' contains no certificate", path.display()));
+    }
+    let key = key.ok_or_else(|| anyhow!("Certificate '{}' contains no private key", path.display()))?;
+    Ok((certs, key))
+}
+
+pub enum ClientStream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl ClientStream {
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.tcp_stream().local_addr()
+    }
+
+    pub fn tcp_stream(&self) -> &TcpStream {
+        match self {
+            ClientStream::Plain(stream) => stream,
+            ClientStream::Tls(stream) => stream.get_ref().0,
+        }
+    }
+}
+
+impl AsyncRead for ClientStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            ClientStream::Tls(stream) => Pin::new(stream.as_mut()).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ClientStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ClientStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            ClientStream::Tls(stream) => Pin::new(stream.as_mut()).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            ClientStream::Tls(stream) => Pin::new(stream.as_mut()).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            ClientStream::Tls(stream) => Pin::new(stream.as_mut()).poll_shutdown(cx),
+        }
+    }
+}
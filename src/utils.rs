@@ -1,5 +1,6 @@
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use anyhow::{Result, anyhow};
 use ipnetwork::IpNetwork;
 
@@ -16,5 +17,374 @@ pub fn parse_ip_or_cidr(input: &str) -> Result<IpNetwork> {
 }
 
 pub fn ip_in_network(ip: IpAddr, network: &IpNetwork) -> bool {
-    network.contains(ip)
+    network.contains(ip) || network.contains(ip.to_canonical())
+}
+
+pub fn parse_duration(input: &str) -> Result<Duration> {
+    let value = input.trim();
+    if value.is_empty() {
+        return Err(anyhow!("Empty duration"));
+    }
+
+    if value.bytes().all(|b| b.is_ascii_digit()) {
+        let secs: u64 = value.parse()
+            .map_err(|_| anyhow!("Duration '{}' is too large", value))?;
+        return Ok(Duration::from_secs(secs));
+    }
+
+    let mut total = Duration::ZERO;
+    let mut rest = value;
+
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        if digits == 0 {
+            return Err(anyhow!("Invalid duration '{}': expected a number at '{}'", value, rest));
+        }
+        let amount: u64 = rest[..digits].parse()
+            .map_err(|_| anyhow!("Duration '{}' is too large", value))?;
+        rest = &rest[digits..];
+
+        let unit_len = rest.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(rest.len());
+        let part = match &rest[..unit_len] {
+            "us" => Some(Duration::from_micros(amount)),
+            "ms" => Some(Duration::from_millis(amount)),
+            "s" => Some(Duration::from_secs(amount)),
+            "m" => amount.checked_mul(60).map(Duration::from_secs),
+            "h" => amount.checked_mul(3600).map(Duration::from_secs),
+            "d" => amount.checked_mul(86400).map(Duration::from_secs),
+            "" => return Err(anyhow!("Invalid duration '{}': missing unit after {}", value, amount)),
+            unit => return Err(anyhow!("Invalid duration '{}': unknown unit '{}', expected us, ms, s, m, h or d", value, unit)),
+        };
+        total = part
+            .and_then(|part| total.checked_add(part))
+            .ok_or_else(|| anyhow!("Duration '{}' is too large", value))?;
+        rest = rest[unit_len..].trim_start();
+    }
+
+    Ok(total)
+}
+
+pub fn parse_size(input: &str) -> Result<u64> {
+    let value = input.trim();
+    let (digits, multiplier) = match value.char_indices().last() {
+        Some((i, 'k')) | Some((i, 'K')) => (&value[..i], 1 << 10),
+        Some((i, 'm')) | Some((i, 'M')) => (&value[..i], 1 << 20),
+        Some((i, 'g')) | Some((i, 'G')) => (&value[..i], 1 << 30),
+        _ => (value, 1),
+    };
+    digits.parse::<u64>().ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| anyhow!("Invalid size '{}', expected a number with an optional k, m or g suffix", value))
+}
+
+pub fn raise_nofile_limit(wanted: u64) -> Result<u64> {
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return Err(anyhow!("getrlimit(RLIMIT_NOFILE) failed: {}", std::io::Error::last_os_error()));
+    }
+
+    if limit.rlim_cur >= wanted {
+        return Ok(limit.rlim_cur);
+    }
+
+    let raised = libc::rlimit { rlim_cur: wanted, rlim_max: limit.rlim_max.max(wanted) };
+    if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &raised) } == 0 {
+        return Ok(wanted);
+    }
+
+    let capped = libc::rlimit { rlim_cur: limit.rlim_max.min(wanted), rlim_max: limit.rlim_max };
+    if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &capped) } != 0 {
+        return Err(anyhow!("setrlimit(RLIMIT_NOFILE) failed: {}", std::io::Error::last_os_error()));
+    }
+
+    Ok(capped.rlim_cur)
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+pub fn unix_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+pub fn hostname() -> String {
+    let mut buffer = [0u8; 256];
+    let result = unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) };
+    if result != 0 {
+        return "localhost".to_string();
+    }
+    let length = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    String::from_utf8_lossy(&buffer[..length]).into_owned()
+}
+
+pub fn open_fds() -> std::io::Result<u64> {
+    Ok(std::fs::read_dir("/proc/self/fd")?.count() as u64)
+}
+
+pub fn resident_memory() -> std::io::Result<u64> {
+    let pages: u64 = std::fs::read_to_string("/proc/self/statm")?
+        .split_whitespace()
+        .nth(1)
+        .and_then(|pages| pages.parse().ok())
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "Malformed /proc/self/statm"))?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Ok(pages * page_size.max(0) as u64)
+}
+
+pub trait OriginalDst {
+    fn original_dst(&self) -> std::io::Result<SocketAddr>;
+}
+
+#[cfg(target_os = "linux")]
+impl OriginalDst for tokio::net::TcpStream {
+    fn original_dst(&self) -> std::io::Result<SocketAddr> {
+        use std::os::fd::AsRawFd;
+
+        let (level, name) = match self.local_addr()? {
+            SocketAddr::V4(_) => (libc::SOL_IP, libc::SO_ORIGINAL_DST),
+            SocketAddr::V6(_) => (libc::SOL_IPV6, libc::IP6T_SO_ORIGINAL_DST),
+        };
+        let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+        let mut len = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(self.as_raw_fd(), level, name, &mut storage as *mut _ as *mut libc::c_void, &mut len)
+        };
+        if result != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        match storage.ss_family as libc::c_int {
+            libc::AF_INET => {
+                let addr = unsafe { &*(&storage as *const _ as *const libc::sockaddr_in) };
+                Ok(SocketAddr::from((u32::from_be(addr.sin_addr.s_addr).to_be_bytes(), u16::from_be(addr.sin_port))))
+            }
+            libc::AF_INET6 => {
+                let addr = unsafe { &*(&storage as *const _ as *const libc::sockaddr_in6) };
+                Ok(SocketAddr::from((addr.sin6_addr.s6_addr, u16::from_be(addr.sin6_port))))
+            }
+            family => Err(std::io::Error::other(format!("Unexpected address family {} from SO_ORIGINAL_DST", family))),
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl OriginalDst for tokio::net::TcpStream {
+    fn original_dst(&self) -> std::io::Result<SocketAddr> {
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "SO_ORIGINAL_DST is only available on Linux"))
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub fn bind_to_device(socket: &socket2::Socket, interface: &str) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let result = unsafe {
+        libc::setsockopt(socket.as_raw_fd(), libc::SOL_SOCKET, libc::SO_BINDTODEVICE,
+                         interface.as_ptr() as *const libc::c_void, interface.len() as libc::socklen_t)
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn bind_to_device(_socket: &socket2::Socket, _interface: &str) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "SO_BINDTODEVICE is only available on Linux"))
+}
+
+#[cfg(target_os = "linux")]
+pub fn set_reuse_port(socket: &socket2::Socket) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let value: libc::c_int = 1;
+    let result = unsafe {
+        libc::setsockopt(socket.as_raw_fd(), libc::SOL_SOCKET, libc::SO_REUSEPORT,
+                         &value as *const _ as *const libc::c_void, std::mem::size_of::<libc::c_int>() as libc::socklen_t)
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_reuse_port(_socket: &socket2::Socket) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "SO_REUSEPORT load balancing is only available on Linux"))
+}
+
+#[cfg(target_os = "linux")]
+const TCPI_OPT_SYN_DATA: u8 = 32;
+
+#[cfg(target_os = "linux")]
+fn set_tcp_option(socket: &socket2::Socket, name: libc::c_int, value: libc::c_int) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let result = unsafe {
+        libc::setsockopt(socket.as_raw_fd(), libc::IPPROTO_TCP, name,
+                         &value as *const _ as *const libc::c_void, std::mem::size_of::<libc::c_int>() as libc::socklen_t)
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+pub fn set_fast_open(socket: &socket2::Socket, queue: u32) -> std::io::Result<()> {
+    set_tcp_option(socket, libc::TCP_FASTOPEN, libc::c_int::try_from(queue).unwrap_or(libc::c_int::MAX))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_fast_open(_socket: &socket2::Socket, _queue: u32) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "TCP_FASTOPEN is only available on Linux"))
+}
+
+#[cfg(target_os = "linux")]
+pub fn set_fast_open_connect(socket: &socket2::Socket) -> std::io::Result<()> {
+    set_tcp_option(socket, libc::TCP_FASTOPEN_CONNECT, 1)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_fast_open_connect(_socket: &socket2::Socket) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "TCP_FASTOPEN_CONNECT is only available on Linux"))
+}
+
+#[cfg(target_os = "linux")]
+pub fn set_defer_accept(socket: &socket2::Socket, seconds: u32) -> std::io::Result<()> {
+    set_tcp_option(socket, libc::TCP_DEFER_ACCEPT, libc::c_int::try_from(seconds).unwrap_or(libc::c_int::MAX))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_defer_accept(_socket: &socket2::Socket, _seconds: u32) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "TCP_DEFER_ACCEPT is only available on Linux"))
+}
+
+#[cfg(target_os = "linux")]
+pub fn fast_open_used(socket: &socket2::Socket) -> std::io::Result<bool> {
+    use std::os::fd::AsRawFd;
+
+    let mut info: libc::tcp_info = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(socket.as_raw_fd(), libc::IPPROTO_TCP, libc::TCP_INFO, &mut info as *mut _ as *mut libc::c_void, &mut len)
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(info.tcpi_options & TCPI_OPT_SYN_DATA != 0)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn fast_open_used(_socket: &socket2::Socket) -> std::io::Result<bool> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "TCP_INFO is only available on Linux"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn error(input: &str) -> String {
+        parse_duration(input).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn every_unit_parses() {
+        assert_eq!(parse_duration("250us").unwrap(), Duration::from_micros(250));
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+        assert_eq!(parse_duration("5s").unwrap(), Duration::from_secs(5));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(7200));
+        assert_eq!(parse_duration("1d").unwrap(), Duration::from_secs(86400));
+        assert_eq!(parse_duration("0ms").unwrap(), Duration::ZERO);
+    }
+
+    #[test]
+    fn bare_numbers_are_seconds() {
+        assert_eq!(parse_duration("30").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("0").unwrap(), Duration::ZERO);
+    }
+
+    #[test]
+    fn combined_values_add_up() {
+        assert_eq!(parse_duration("1m30s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("1d2h3m4s5ms6us").unwrap(),
+                   Duration::from_secs(86400 + 7200 + 180 + 4) + Duration::from_millis(5) + Duration::from_micros(6));
+        assert_eq!(parse_duration("1s1s").unwrap(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn whitespace_is_allowed_around_and_between_parts() {
+        assert_eq!(parse_duration("  5s\t").unwrap(), Duration::from_secs(5));
+        assert_eq!(parse_duration("1m 30s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration(" 42 ").unwrap(), Duration::from_secs(42));
+        assert_eq!(error("1 m"), "Invalid duration '1 m': missing unit after 1");
+    }
+
+    #[test]
+    fn invalid_values_say_what_is_wrong() {
+        assert_eq!(error(""), "Empty duration");
+        assert_eq!(error("   "), "Empty duration");
+        assert_eq!(error("10x"), "Invalid duration '10x': unknown unit 'x', expected us, ms, s, m, h or d");
+        assert_eq!(error("10sec"), "Invalid duration '10sec': unknown unit 'sec', expected us, ms, s, m, h or d");
+        assert_eq!(error("1m30"), "Invalid duration '1m30': missing unit after 30");
+        assert_eq!(error("1.5s"), "Invalid duration '1.5s': missing unit after 1");
+        assert_eq!(error("s"), "Invalid duration 's': expected a number at 's'");
+        assert_eq!(error("-1s"), "Invalid duration '-1s': expected a number at '-1s'");
+    }
+
+    #[test]
+    fn overflow_is_an_error() {
+        assert_eq!(error("18446744073709551616"), "Duration '18446744073709551616' is too large");
+        assert_eq!(error("18446744073709551616ms"), "Duration '18446744073709551616ms' is too large");
+        assert_eq!(error("18446744073709551615d"), "Duration '18446744073709551615d' is too large");
+        assert_eq!(error("18446744073709551615s1s"), "Duration '18446744073709551615s1s' is too large");
+        assert_eq!(parse_duration("18446744073709551615s").unwrap(), Duration::from_secs(u64::MAX));
+    }
+
+    #[test]
+    fn random_combinations_sum_their_parts() {
+        const UNITS: [(&str, Duration); 6] = [
+            ("us", Duration::from_micros(1)),
+            ("ms", Duration::from_millis(1)),
+            ("s", Duration::from_secs(1)),
+            ("m", Duration::from_secs(60)),
+            ("h", Duration::from_secs(3600)),
+            ("d", Duration::from_secs(86400)),
+        ];
+        let mut rng = StdRng::seed_from_u64(2384);
+        for _ in 0..2000 {
+            let mut input = String::new();
+            let mut expected = Duration::ZERO;
+            for _ in 0..rng.gen_range(1..=6) {
+                let (unit, scale) = UNITS[rng.gen_range(0..UNITS.len())];
+                let amount: u32 = rng.gen_range(0..100_000);
+                input.push_str(&amount.to_string());
+                input.push_str(unit);
+                input.push_str([" ", "", "\t", ""][rng.gen_range(0..4)]);
+                expected += scale * amount;
+            }
+            assert_eq!(parse_duration(&input).unwrap(), expected, "{input:?}");
+        }
+    }
+
+    #[test]
+    fn random_durations_round_trip() {
+        let mut rng = StdRng::seed_from_u64(0x2384);
+        for _ in 0..2000 {
+            let micros: u64 = rng.gen_range(0..10 * 86400 * 1_000_000);
+            let (mut rest, mut input) = (micros, String::new());
+            for (unit, scale) in [("d", 86_400_000_000), ("h", 3_600_000_000), ("m", 60_000_000), ("s", 1_000_000), ("ms", 1000), ("us", 1)] {
+                if rest >= scale {
+                    input.push_str(&format!("{}{}", rest / scale, unit));
+                    rest %= scale;
+                }
+            }
+            let input = if input.is_empty() { "0".to_string() } else { input };
+            assert_eq!(parse_duration(&input).unwrap(), Duration::from_micros(micros), "{input:?}");
+        }
+    }
 }
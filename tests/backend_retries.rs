@@ -0,0 +1,68 @@
+mod common;
+
+use common::{Turbogate, addr, backend, exchange, free_port, statuses};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+const WAIT: Duration = Duration::from_millis(500);
+
+#[tokio::test]
+async fn redispatch_never_retries_the_failed_server() {
+    let port = free_port();
+    let refused = free_port();
+    let live = free_port();
+    let (recorded, _backend) = backend(live).await;
+    let config = format!("frontend web\n    bind 127.0.0.1:{port}\n    default_backend be\n\n\
+                          backend be\n    balance random\n    retries 1\n    option redispatch 1\n    \
+                          server s1 127.0.0.1:{refused}\n    server s2 127.0.0.1:{live}\n");
+    let _turbogate = Turbogate::start(&config, port).await;
+
+    for index in 0..20 {
+        let request = format!("GET /{index} HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n");
+        let (response, _) = exchange(addr(port), request.as_bytes(), WAIT).await;
+        assert_eq!(statuses(&response), [200], "connection {index}: {}", String::from_utf8_lossy(&response));
+    }
+    assert_eq!(recorded.paths().len(), 20);
+}
+
+// A server that accepts and drops every connection before answering, counting them.
+async fn dropping_server() -> (u16, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind(addr(0)).await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&accepted);
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            counter.fetch_add(1, Ordering::Relaxed);
+            drop(stream);
+        }
+    });
+    (port, accepted)
+}
+
+#[tokio::test]
+async fn conn_failure_retries_replay_the_request_to_another_server() {
+    for (retry_on, replayed) in [("conn-failure", true), ("none", false)] {
+        let port = free_port();
+        let live = free_port();
+        let (dropping, dropped) = dropping_server().await;
+        let (recorded, _backend) = backend(live).await;
+        let config = format!("frontend web\n    bind 127.0.0.1:{port}\n    default_backend be\n\n\
+                              backend be\n    balance random\n    retries 2\n    option redispatch 1\n    retry-on {retry_on}\n    \
+                              server s1 127.0.0.1:{dropping}\n    server s2 127.0.0.1:{live}\n");
+        let _turbogate = Turbogate::start(&config, port).await;
+
+        let mut outcomes = Vec::new();
+        for index in 0..20 {
+            let request = format!("GET /{index} HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n");
+            outcomes.push(statuses(&exchange(addr(port), request.as_bytes(), WAIT).await.0));
+        }
+        assert!(dropped.load(Ordering::Relaxed) > 0, "retry-on {retry_on}: the dropping server was never chosen");
+        assert_eq!(outcomes.iter().all(|outcome| *outcome == [200]), replayed, "retry-on {retry_on}: {outcomes:?}");
+        if replayed {
+            assert_eq!(recorded.paths().len(), 20);
+        }
+    }
+}
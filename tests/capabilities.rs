@@ -0,0 +1,36 @@
+mod common;
+
+use common::check_config;
+use std::process::Command;
+
+fn check_file(path: &str) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_turbogate"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .arg("--config").arg(path)
+        .arg("--check")
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn shipped_configs_pass_the_check() {
+    for path in ["test.cfg", "examples/example.cfg", "examples/haproxy-migrate.cfg"] {
+        let output = check_file(path);
+        assert!(output.status.success(), "{path}: {}", String::from_utf8_lossy(&output.stderr));
+    }
+}
+
+#[test]
+fn http_mode_and_keep_alive_timeout_need_no_capability() {
+    let output = check_config("frontend web\n    bind 127.0.0.1:0\n    mode http\n    timeout http-keep-alive 5s\n    default_backend be\n\nbackend be\n    timeout http-keep-alive 5s\n    server s1 127.0.0.1:1\n");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn unimplemented_directives_fail_the_check() {
+    let output = check_config("frontend web\n    bind 127.0.0.1:0\n    mode http\n    compression-gzip enabled\n    default_backend be\n\nbackend be\n    server s1 127.0.0.1:1\n");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(":4: frontend web uses 'compression-gzip' which requires the http capability"), "{stderr}");
+    assert!(!stderr.contains("mode http"), "{stderr}");
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use common::{backend, free_port};
+use tokio::process::Command;
+
+// Runs `--check-backends --json` against `config` and returns the exit success and the report.
+async fn check_backends(config: &str) -> (bool, serde_json::Value) {
+    let dir = common::scratch_dir();
+    let path = dir.join("turbogate.cfg");
+    std::fs::write(&path, config).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_turbogate"))
+        .arg("--config").arg(&path)
+        .arg("--check-backends")
+        .arg("--check-timeout").arg("2s")
+        .arg("--json")
+        .output()
+        .await
+        .unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+    let report = serde_json::from_slice(&output.stdout)
+        .unwrap_or_else(|e| panic!("{e}: {}", String::from_utf8_lossy(&output.stdout)));
+    (output.status.success(), report)
+}
+
+#[tokio::test]
+async fn check_backends_reports_each_server_and_fails_on_an_unreachable_backend() {
+    let live = free_port();
+    let dead = free_port();
+    let (_recorded, _backend) = backend(live).await;
+    let frontend = "frontend web\n    bind 127.0.0.1:0\n    default_backend app\n\n";
+    let app = format!("backend app\n    server up 127.0.0.1:{live} check\n    server down 127.0.0.1:{dead} check\n");
+
+    let (passed, report) = check_backends(&format!("{frontend}{app}")).await;
+    assert!(passed, "{report}");
+    assert_eq!(report["passed"], true);
+    let servers: Vec<(&str, bool)> = report["servers"].as_array().unwrap().iter()
+        .map(|server| (server["server"].as_str().unwrap(), server["reachable"].as_bool().unwrap()))
+        .collect();
+    assert_eq!(servers, [("up", true), ("down", false)]);
+    assert!(report["servers"][0]["latency_ms"].is_number(), "{report}");
+    assert!(report["servers"][1]["error"].is_string(), "{report}");
+    assert_eq!(report["backends"][0]["reachable"], 1);
+    assert_eq!(report["backends"][0]["total"], 2);
+
+    let (passed, report) = check_backends(&format!("{frontend}{app}\nbackend gone\n    server down 127.0.0.1:{dead} check\n")).await;
+    assert!(!passed, "{report}");
+    assert_eq!(report["passed"], false);
+    let backends: Vec<(&str, u64)> = report["backends"].as_array().unwrap().iter()
+        .map(|backend| (backend["backend"].as_str().unwrap(), backend["reachable"].as_u64().unwrap()))
+        .collect();
+    assert_eq!(backends, [("app", 1), ("gone", 0)]);
+}
@@ -0,0 +1,258 @@
+#![allow(dead_code)]
+
+use std::net::{SocketAddr, TcpListener as StdListener};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+static SCRATCH: AtomicUsize = AtomicUsize::new(0);
+
+pub fn free_port() -> u16 {
+    StdListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+pub fn scratch_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("turbogate-test-{}-{}", std::process::id(), SCRATCH.fetch_add(1, Ordering::Relaxed)));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+pub struct Turbogate {
+    child: Child,
+    pub dir: PathBuf,
+    pub stats_port: u16,
+}
+
+// Gives every instance its own stats endpoint, so tests can run in parallel.
+fn with_stats_bind(config: &str, stats_port: u16) -> String {
+    let bind = format!("    stats bind 127.0.0.1:{stats_port}\n");
+    match config.strip_prefix("global\n") {
+        Some(rest) => format!("global\n{bind}{rest}"),
+        None => format!("global\n{bind}\n{config}"),
+    }
+}
+
+impl Turbogate {
+    pub async fn start(config: &str, port: u16) -> Self {
+        let dir = scratch_dir();
+        let path = dir.join("turbogate.cfg");
+        let stats_port = free_port();
+        std::fs::write(&path, with_stats_bind(config, stats_port)).unwrap();
+        let child = Command::new(env!("CARGO_BIN_EXE_turbogate"))
+            .arg("--config").arg(&path)
+            .arg("--allow-degraded")
+            .arg("--log-level").arg("error")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let mut turbogate = Self { child, dir, stats_port };
+        turbogate.wait_for(port).await;
+        turbogate
+    }
+
+    async fn wait_for(&mut self, port: u16) {
+        for _ in 0..200 {
+            if TcpStream::connect(("127.0.0.1", port)).await.is_ok() {
+                return;
+            }
+            if let Some(status) = self.child.try_wait().unwrap() {
+                panic!("turbogate exited with {} before listening on {}", status, port);
+            }
+            tokio::time::sleep(Duration::from_millis(25)).await;
+        }
+        panic!("turbogate did not listen on {}", port);
+    }
+}
+
+impl Turbogate {
+    // Replaces the configuration file, keeping this instance's stats endpoint.
+    pub fn write_config(&self, config: &str) {
+        std::fs::write(self.dir.join("turbogate.cfg"), with_stats_bind(config, self.stats_port)).unwrap();
+    }
+
+    pub fn signal(&self, signal: libc::c_int) {
+        assert_eq!(unsafe { libc::kill(self.child.id() as libc::pid_t, signal) }, 0);
+    }
+
+    pub async fn wait_for_port(&mut self, port: u16) {
+        self.wait_for(port).await;
+    }
+
+    pub async fn get(&self, path: &str) -> String {
+        let request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        let (response, _) = exchange(addr(self.stats_port), request.as_bytes(), Duration::from_secs(5)).await;
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    // Sum of the samples of a metric whose labels contain all of `labels`.
+    pub async fn metric(&self, name: &str, labels: &[&str]) -> f64 {
+        self.get("/metrics").await.lines()
+            .filter(|line| line.starts_with(name) && line[name.len()..].starts_with(['{', ' ']))
+            .filter(|line| labels.iter().all(|label| line.contains(label)))
+            .filter_map(|line| line.rsplit(' ').next()?.parse::<f64>().ok())
+            .sum()
+    }
+}
+
+impl Drop for Turbogate {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+pub fn check_config(config: &str) -> std::process::Output {
+    let dir = scratch_dir();
+    let path = dir.join("turbogate.cfg");
+    std::fs::write(&path, config).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_turbogate"))
+        .arg("--config").arg(&path)
+        .arg("--check")
+        .output()
+        .unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+    output
+}
+
+fn head_end(data: &[u8]) -> Option<usize> {
+    data.windows(4).position(|w| w == b"\r\n\r\n").map(|position| position + 4)
+}
+
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .find(|(header, _)| header.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim())
+}
+
+// Splits one complete request (head and body) off the front of `data`.
+pub fn take_request(data: &mut Vec<u8>) -> Option<(String, Vec<u8>)> {
+    let end = head_end(data)?;
+    let head = String::from_utf8_lossy(&data[..end]).into_owned();
+    let chunked = header(&head, "transfer-encoding").is_some_and(|value| value.to_ascii_lowercase().ends_with("chunked"));
+    let mut body = Vec::new();
+    let consumed = if chunked {
+        let mut position = end;
+        loop {
+            let line_end = data[position..].windows(2).position(|w| w == b"\r\n")? + position;
+            let size = usize::from_str_radix(String::from_utf8_lossy(&data[position..line_end]).split(';').next()?.trim(), 16).ok()?;
+            position = line_end + 2;
+            if size == 0 {
+                let trailer_end = data[position..].windows(2).position(|w| w == b"\r\n")? + position;
+                break trailer_end + 2;
+            }
+            if data.len() < position + size + 2 {
+                return None;
+            }
+            body.extend_from_slice(&data[position..position + size]);
+            position += size + 2;
+        }
+    } else {
+        let length: usize = header(&head, "content-length").and_then(|value| value.parse().ok()).unwrap_or(0);
+        if data.len() < end + length {
+            return None;
+        }
+        body.extend_from_slice(&data[end..end + length]);
+        end + length
+    };
+    data.drain(..consumed);
+    Some((head, body))
+}
+
+#[derive(Clone, Default)]
+pub struct Recorded {
+    pub requests: Arc<Mutex<Vec<String>>>,
+    pub connections: Arc<AtomicUsize>,
+}
+
+impl Recorded {
+    pub fn heads(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    pub fn paths(&self) -> Vec<String> {
+        self.heads().iter()
+            .map(|head| head.split(' ').nth(1).unwrap_or_default().to_string())
+            .collect()
+    }
+}
+
+// An HTTP/1.1 keep-alive backend answering every request with its path as the body.
+pub async fn backend(port: u16) -> (Recorded, JoinHandle<()>) {
+    backend_with_delay(port, Duration::ZERO).await
+}
+
+pub async fn backend_with_delay(port: u16, delay: Duration) -> (Recorded, JoinHandle<()>) {
+    let listener = TcpListener::bind(("127.0.0.1", port)).await.unwrap();
+    let recorded = Recorded::default();
+    let shared = recorded.clone();
+    let handle = tokio::spawn(async move {
+        loop {
+            let Ok((mut stream, _)) = listener.accept().await else {
+                return;
+            };
+            shared.connections.fetch_add(1, Ordering::Relaxed);
+            let recorded = shared.clone();
+            tokio::spawn(async move {
+                let mut data = Vec::new();
+                let mut buffer = [0u8; 4096];
+                loop {
+                    while let Some((head, _)) = take_request(&mut data) {
+                        recorded.requests.lock().unwrap().push(head.clone());
+                        tokio::time::sleep(delay).await;
+                        let path = head.split(' ').nth(1).unwrap_or_default().to_string();
+                        let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", path.len(), path);
+                        if stream.write_all(response.as_bytes()).await.is_err() {
+                            return;
+                        }
+                        if header(&head, "connection").is_some_and(|value| value.eq_ignore_ascii_case("close")) {
+                            let _ = stream.shutdown().await;
+                            return;
+                        }
+                    }
+                    match stream.read(&mut buffer).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => data.extend_from_slice(&buffer[..n]),
+                    }
+                }
+            });
+        }
+    });
+    (recorded, handle)
+}
+
+// Sends `request` and reads until the peer closes or `timeout` passes without data.
+pub async fn exchange(addr: SocketAddr, request: &[u8], timeout: Duration) -> (Vec<u8>, bool) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(request).await.unwrap();
+    read_all(&mut stream, timeout).await
+}
+
+pub async fn read_all(stream: &mut TcpStream, timeout: Duration) -> (Vec<u8>, bool) {
+    let mut response = Vec::new();
+    let mut buffer = [0u8; 4096];
+    loop {
+        match tokio::time::timeout(timeout, stream.read(&mut buffer)).await {
+            Ok(Ok(0)) | Ok(Err(_)) => return (response, true),
+            Ok(Ok(n)) => response.extend_from_slice(&buffer[..n]),
+            Err(_) => return (response, false),
+        }
+    }
+}
+
+pub fn statuses(response: &[u8]) -> Vec<u16> {
+    String::from_utf8_lossy(response).match_indices("HTTP/1.")
+        .filter_map(|(index, _)| String::from_utf8_lossy(response)[index..].split(' ').nth(1).and_then(|status| status.parse().ok()))
+        .collect()
+}
+
+pub fn addr(port: u16) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], port))
+}
@@ -0,0 +1,179 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::panic;
+use turbogate::config::Config;
+
+fn parse(content: &str) -> Result<Config, String> {
+    Config::from_haproxy_config(content, Some("test.cfg")).map_err(|e| e.to_string())
+}
+
+// Parses `content`, failing the test with the input if the parser panics.
+fn parse_without_panic(content: &str) {
+    if panic::catch_unwind(|| parse(content)).is_err() {
+        panic!("parser panicked on:\n{content}");
+    }
+}
+
+#[test]
+fn truncated_directives_are_errors_with_a_line_number() {
+    let cases = [
+        ("frontend f\n    bind *:\n", "test.cfg:2: Frontend 'f' has invalid bind '*:'"),
+        ("frontend f\n    bind\n", "test.cfg:2: Frontend 'f' has invalid bind '': Missing bind address"),
+        ("frontend f\n    bind *:port\n", "test.cfg:2: Frontend 'f' has invalid bind '*:port'"),
+        ("frontend f\n    http-request auth realm\n", "test.cfg:2: Frontend 'f' has invalid http-request auth 'auth realm'"),
+        ("frontend f\n    http-request return status\n", "test.cfg:2: Frontend 'f' has invalid http-request return"),
+        ("frontend f\n    http-request return status 200 string\n", "test.cfg:2: Frontend 'f' has invalid http-request return"),
+        ("backend b\n    http-request return content-type\n", "test.cfg:2: Backend 'b' has invalid http-request return"),
+        ("frontend f\n    maxconn\n", "test.cfg:2: 'maxconn' requires an argument"),
+        ("frontend f\n    maxconn 10 20\n", "test.cfg:2: 'maxconn' expects a single argument, got '10' '20'"),
+        ("global\n    stats refresh\n", "test.cfg:2: stats refresh requires a delay"),
+        ("global\n    audit-log\n", "test.cfg:2: audit-log expects <path> [fsync], got ''"),
+        ("backend b\n    stick on\n", "test.cfg:2: Invalid stick directive 'on'"),
+        ("frontend f\n    bind 127.0.0.1:80 \"unterminated\n", "test.cfg:2: Invalid line: unterminated double quote"),
+    ];
+    for (content, expected) in cases {
+        let error = parse(content).expect_err(content);
+        assert!(error.starts_with(expected), "{content:?}: {error}");
+    }
+}
+
+#[test]
+fn errors_deep_in_a_file_name_their_line() {
+    let mut content = String::from("global\n    maxconn 100\n\ndefaults\n    timeout connect 5s\n");
+    for backend in 0..7 {
+        content.push_str(&format!("\nbackend b{backend}\n    server s1 127.0.0.1:{}\n    # spare\n\n", 9000 + backend));
+    }
+    assert_eq!(content.lines().count(), 40);
+    content.push_str("backend b7\n    stick on\n");
+
+    let error = parse(&content).expect_err("line 42 is invalid");
+    assert!(error.starts_with("test.cfg:42: Invalid stick directive 'on'"), "{error}");
+    let error = Config::from_haproxy_config(&content, None).expect_err("line 42 is invalid").to_string();
+    assert!(error.starts_with("line 42: "), "{error}");
+}
+
+#[test]
+fn log_format_is_inherited_and_validated() {
+    let content = "defaults\n    log-format \"%ci %b/%s %ts\"\n\n\
+                   frontend a\n    bind 127.0.0.1:8080\n    default_backend b\n\n\
+                   frontend c\n    bind 127.0.0.1:8081\n    log-format %ci\n    default_backend b\n\n\
+                   backend b\n    server s1 127.0.0.1:9001\n";
+    let config = parse(content).unwrap();
+    let formats: Vec<_> = config.frontends.iter().map(|frontend| frontend.log_format.as_deref()).collect();
+    assert_eq!(formats, [Some("%ci %b/%s %ts"), Some("%ci")]);
+    config.validate(false).unwrap();
+
+    let error = parse(&content.replace("log-format %ci", "log-format %ci:%nope")).unwrap().validate(false).unwrap_err().to_string();
+    assert!(error.contains("test.cfg:8") && error.ends_with("Frontend 'c' has invalid log-format: Unknown log-format token '%nope'"), "{error}");
+}
+
+#[test]
+fn short_http_request_rules_are_not_indexed_past_the_end() {
+    for rule in ["http-request", "http-request set-header", "http-request set-header X-Id", "http-response add-header X"] {
+        let config = format!("frontend f\n    bind 127.0.0.1:8080\n    {rule}\n");
+        assert!(parse(&config).is_ok(), "{rule}");
+    }
+}
+
+const WORDS: &[&str] = &[
+    "global", "defaults", "frontend f", "backend b", "resolvers dns", "peers p", "userlist u", "listen l",
+    "bind", "server", "mode", "http", "tcp", "option", "httpchk", "http-check", "expect", "string", "status", "rstring",
+    "tcp-check", "connect", "port", "timeout", "connect", "client", "server", "http-request", "http-response",
+    "auth", "realm", "return", "content-type", "file", "set-header", "use_backend", "default_backend", "acl",
+    "path_beg", "src", "hdr(host)", "http_auth(u)", "if", "unless", "!", "||", "or", "stats", "socket", "bind",
+    "path", "refresh", "metrics", "label", "prefix", "ddos-protection", "rate-limit", "sessions", "maxconn",
+    "stick-table", "type", "size", "expire", "stick", "on", "balance", "random(3)", "roundrobin", "check",
+    "inter", "rise", "fall", "weight", "backup", "user", "password", "insecure-password", "group", "groups",
+    "nameserver", "peer", "log-format", "%ci", "%{+Q}o", "bwlim-in", "tcp-request", "inspect-delay",
+    "*:", "*:80", ":", "127.0.0.1:", "[::1]:", "unix:", "ipv4@", "abns@", "0", "-1", "65536",
+    "99999999999999999999", "1s", "1xs", "0ms", "10m", "$6$", "$2a$04$", "\"", "'", "\\", "#", "\"a b\"",
+    "'c d'", "{", "}", "é", "\u{0}", "\t", "",
+];
+
+fn random_line(rng: &mut StdRng) -> String {
+    let indent = if rng.gen_bool(0.7) { "    " } else { "" };
+    let words: Vec<&str> = (0..rng.gen_range(0..8)).map(|_| WORDS[rng.gen_range(0..WORDS.len())]).collect();
+    let separator = if rng.gen_bool(0.1) { "" } else { " " };
+    format!("{indent}{}", words.join(separator))
+}
+
+#[test]
+fn random_configs_never_panic() {
+    let mut rng = StdRng::seed_from_u64(0x7475_7262_6f67_6174);
+    for _ in 0..3000 {
+        let lines: Vec<String> = (0..rng.gen_range(1..40)).map(|_| random_line(&mut rng)).collect();
+        parse_without_panic(&lines.join("\n"));
+    }
+}
+
+fn mutate(content: &str, rng: &mut StdRng) -> String {
+    let mut chars: Vec<char> = content.chars().collect();
+    for _ in 0..rng.gen_range(1..8) {
+        if chars.is_empty() {
+            break;
+        }
+        let at = rng.gen_range(0..chars.len());
+        match rng.gen_range(0..4) {
+            0 => {
+                chars.remove(at);
+            }
+            1 => chars.insert(at, ['"', '\'', '\\', ':', ' ', '\n', '#', '*', '0', '{'][rng.gen_range(0..10)]),
+            2 => {
+                let end = chars[at..].iter().position(|c| *c == '\n').map_or(chars.len(), |offset| at + offset);
+                chars.drain(at..end);
+            }
+            _ => {
+                let word = WORDS[rng.gen_range(0..WORDS.len())];
+                chars.splice(at..at, word.chars());
+            }
+        }
+    }
+    chars.into_iter().collect()
+}
+
+#[test]
+fn mutated_example_configs_never_panic() {
+    let mut rng = StdRng::seed_from_u64(2451);
+    for path in ["examples/example.cfg", "examples/haproxy-migrate.cfg", "test.cfg"] {
+        let content = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/").to_string() + path).unwrap();
+        parse_without_panic(&content);
+        for _ in 0..500 {
+            parse_without_panic(&mutate(&content, &mut rng));
+        }
+    }
+}
+
+// A generated configuration of about `lines` lines, mostly servers.
+fn large_config(lines: usize) -> String {
+    let mut config = String::from("global\n    maxconn 100000\n\ndefaults\n    mode tcp\n    timeout connect 5s\n\n");
+    let mut backend = 0;
+    while config.lines().count() < lines {
+        config.push_str(&format!("frontend f{backend}\n    bind 127.0.0.1:{}\n    acl admin src 10.{}.0.0/16\n    use_backend b{backend} if admin\n    default_backend b{backend}\n\n", 10000 + backend, backend % 256));
+        config.push_str(&format!("backend b{backend}\n    balance roundrobin\n    option httpchk GET /health\n"));
+        for server in 0..100 {
+            config.push_str(&format!("    server s{server} 10.{}.{}.{}:8080 check inter 2s rise 2 fall 3 weight 10\n", backend % 256, server / 256, server % 256));
+        }
+        config.push('\n');
+        backend += 1;
+    }
+    config
+}
+
+#[test]
+fn large_configs_parse() {
+    let config = parse(&large_config(50_000)).unwrap();
+    assert_eq!(config.frontends.len(), config.backends.len());
+    assert!(config.backends.iter().all(|backend| backend.server.len() == 100));
+    assert!(config.backends.len() * 100 > 45_000);
+}
+
+#[test]
+fn bad_acl_networks_fail_validation() {
+    let content = "frontend f\n    bind 127.0.0.1:8080\n    acl admin src 10.0.0.0/33\n    use_backend b if admin\n    default_backend b\n\n\
+                   backend b\n    server s1 127.0.0.1:9001\n";
+    let error = parse(content).unwrap().validate(false).unwrap_err().to_string();
+    assert!(error.contains("test.cfg:3") && error.contains("acl admin"), "{error}");
+
+    let fixed = content.replace("/33", "/8");
+    parse(&fixed).unwrap().validate(false).unwrap();
+}
@@ -0,0 +1,368 @@
+mod common;
+
+use common::{Turbogate, addr, backend, backend_with_delay, exchange, free_port, read_all, statuses};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const WAIT: Duration = Duration::from_millis(500);
+const REQUEST: &[u8] = b"GET /ok HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n";
+
+fn config(global: &str, frontend: &str, backend: &str, backend_port: u16) -> String {
+    format!("global\n{global}\nfrontend web\n    bind 127.0.0.1:{{port}}\n{frontend}\n\
+             backend be\n{backend}    server s1 127.0.0.1:{backend_port}\n")
+}
+
+async fn start(template: &str) -> (Turbogate, u16) {
+    let port = free_port();
+    let turbogate = Turbogate::start(&template.replace("{port}", &port.to_string()), port).await;
+    // Let the readiness probe of `start` close before the limits are exercised.
+    settle(&turbogate).await;
+    (turbogate, port)
+}
+
+async fn held(port: u16) -> TcpStream {
+    let mut stream = TcpStream::connect(addr(port)).await.unwrap();
+    stream.write_all(b"GET /held HTTP/1.1\r\nHost: example.com\r\n\r\n").await.unwrap();
+    let mut response = [0u8; 12];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(&response, b"HTTP/1.1 200");
+    stream
+}
+
+// The process, listener and metrics views of open connections.
+async fn open_connections(turbogate: &Turbogate) -> [f64; 3] {
+    let info = turbogate.get("/info").await;
+    let info: serde_json::Value = serde_json::from_str(info.split_once("\r\n\r\n").unwrap().1).unwrap();
+    let stats = turbogate.get("/stats;csv").await;
+    let scur = stats.lines().find(|line| line.starts_with("web,FRONTEND,"))
+        .and_then(|line| line.split(',').nth(4)?.parse().ok())
+        .unwrap_or(f64::NAN);
+    [info["CurrConns"].as_f64().unwrap_or(f64::NAN), scur, turbogate.metric("turbogate_active_connections", &["frontend=\"web\""]).await]
+}
+
+async fn settle(turbogate: &Turbogate) -> [f64; 3] {
+    let mut counts = [f64::NAN; 3];
+    for _ in 0..40 {
+        counts = open_connections(turbogate).await;
+        if counts == [0.0; 3] {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(25)).await;
+    }
+    counts
+}
+
+async fn assert_all_closed(turbogate: &Turbogate, case: &str) {
+    let counts = settle(turbogate).await;
+    assert_eq!(counts, [0.0; 3], "{case}: CurrConns, scur and turbogate_active_connections");
+    let errors = turbogate.metric("turbogate_connection_errors_total", &["frontend=\"web\""]).await;
+    assert!(errors >= 1.0, "{case}: no connection error was counted");
+}
+
+async fn refused(port: u16) -> Vec<u8> {
+    exchange(addr(port), REQUEST, WAIT).await.0
+}
+
+#[tokio::test]
+async fn rate_limited_connections_are_released() {
+    let backend_port = free_port();
+    let (_recorded, _backend) = backend(backend_port).await;
+    let (turbogate, port) = start(&config("    rate-limit requests-per-second 1\n    rate-limit burst-size 1\n", "    default_backend be\n", "", backend_port)).await;
+    let mut outcomes = Vec::new();
+    for _ in 0..5 {
+        outcomes.push(statuses(&refused(port).await));
+    }
+    assert!(outcomes.iter().any(Vec::is_empty), "{outcomes:?}");
+    assert_all_closed(&turbogate, "rate limit").await;
+}
+
+#[tokio::test]
+async fn sni_keyed_rate_limits_are_per_hostname() {
+    let backend_port = free_port();
+    let (_recorded, _backend) = backend(backend_port).await;
+    let global = "    rate-limit requests-per-second 1\n    rate-limit burst-size 1\n    rate-limit key sni\n";
+    let (_turbogate, port) = start(&config(global, "    default_backend be\n", "", backend_port)).await;
+    let request = |host: &str| format!("GET /ok HTTP/1.1\r\nHost: {host}\r\nConnection: close\r\n\r\n");
+
+    let mut outcomes = Vec::new();
+    for host in ["a.example.com", "a.example.com", "b.example.com"] {
+        outcomes.push(statuses(&exchange(addr(port), request(host).as_bytes(), WAIT).await.0));
+    }
+    assert_eq!(outcomes, [vec![200], vec![], vec![200]]);
+}
+
+#[tokio::test]
+async fn observed_limits_serve_over_limit_clients_and_count_them() {
+    let backend_port = free_port();
+    let (_recorded, _backend) = backend(backend_port).await;
+    let global = ["rate-limit requests-per-second 1", "rate-limit burst-size 1", "rate-limit mode observe",
+                  "ddos-protection max-connections-per-ip 1", "ddos-protection mode observe"]
+        .map(|line| format!("    {line}\n"))
+        .concat();
+    let (turbogate, port) = start(&config(&global, "    default_backend be\n", "", backend_port)).await;
+
+    let first = held(port).await;
+    let mut outcomes = Vec::new();
+    for _ in 0..5 {
+        outcomes.push(statuses(&refused(port).await));
+    }
+    drop(first);
+    assert_eq!(outcomes, vec![vec![200]; 5]);
+    assert!(turbogate.metric("turbogate_rate_limit_would_reject_total", &[]).await >= 3.0);
+    assert!(turbogate.metric("turbogate_ddos_would_reject_total", &[]).await >= 5.0);
+}
+
+#[tokio::test]
+async fn ddos_limited_connections_are_released() {
+    let backend_port = free_port();
+    let (_recorded, _backend) = backend(backend_port).await;
+    let (turbogate, port) = start(&config("    ddos-protection max-connections-per-ip 1\n", "    default_backend be\n", "", backend_port)).await;
+    let first = held(port).await;
+    assert_eq!(statuses(&refused(port).await), Vec::<u16>::new());
+    drop(first);
+    assert_all_closed(&turbogate, "ddos").await;
+}
+
+#[tokio::test]
+async fn connections_without_a_backend_are_released() {
+    let backend_port = free_port();
+    let (turbogate, port) = start(&config("", "    acl never dst_port 1\n    use_backend be if never\n", "", backend_port)).await;
+    assert_eq!(statuses(&refused(port).await), Vec::<u16>::new());
+    assert_all_closed(&turbogate, "no backend").await;
+}
+
+#[tokio::test]
+async fn connections_to_a_full_backend_are_released() {
+    let backend_port = free_port();
+    let (_recorded, _backend) = backend(backend_port).await;
+    let (turbogate, port) = start(&config("", "    default_backend be\n", "    fullconn 1\n", backend_port)).await;
+    let first = held(port).await;
+    assert_eq!(statuses(&refused(port).await), Vec::<u16>::new());
+    drop(first);
+    assert_all_closed(&turbogate, "backend full").await;
+}
+
+#[tokio::test]
+async fn sessions_over_fullconn_follow_the_configured_policy() {
+    let backend_port = free_port();
+    let (_recorded, _backend) = backend(backend_port).await;
+    for (frontend, policy, expected) in [("", "reject", vec![]), ("    mode http\n", "errorfile", vec![503])] {
+        let backend = format!("    fullconn 2\n    on-fullconn {policy}\n");
+        let (turbogate, port) = start(&config("", &format!("{frontend}    default_backend be\n"), &backend, backend_port)).await;
+        let held = [held(port).await, held(port).await];
+        let (response, closed) = exchange(addr(port), REQUEST, WAIT).await;
+        assert_eq!(statuses(&response), expected, "{policy}");
+        assert!(closed, "{policy}");
+        assert_eq!(turbogate.metric("turbogate_backend_fullconn_rejects_total", &["backend=\"be\""]).await, 1.0, "{policy}");
+        drop(held);
+    }
+
+    let backend = "    fullconn 2\n    on-fullconn queue\n    timeout queue 2s\n";
+    let (_turbogate, port) = start(&config("", "    default_backend be\n", backend, backend_port)).await;
+    let [first, second] = [held(port).await, held(port).await];
+    let started = Instant::now();
+    let queued = tokio::spawn(exchange(addr(port), REQUEST, Duration::from_secs(3)));
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    drop(first);
+    let (response, _) = queued.await.unwrap();
+    assert_eq!(statuses(&response), [200], "queue");
+    assert!(started.elapsed() >= Duration::from_millis(200), "served after {:?}", started.elapsed());
+    drop(second);
+}
+
+#[tokio::test]
+async fn invalid_requests_are_released() {
+    let backend_port = free_port();
+    let (_recorded, _backend) = backend(backend_port).await;
+    let (turbogate, port) = start(&config("", "    mode http\n    default_backend be\n", "", backend_port)).await;
+    let (response, closed) = exchange(addr(port), b"NOT A REQUEST\r\n\r\n", WAIT).await;
+    assert_eq!(statuses(&response), [400]);
+    assert!(closed);
+    assert_all_closed(&turbogate, "invalid request").await;
+}
+
+#[tokio::test]
+async fn aborted_clients_are_released() {
+    let backend_port = free_port();
+    let (recorded, _backend) = backend_with_delay(backend_port, Duration::from_secs(2)).await;
+    let (turbogate, port) = start(&config("", "    default_backend be\n", "", backend_port)).await;
+    let mut stream = TcpStream::connect(addr(port)).await.unwrap();
+    stream.write_all(b"GET /slow HTTP/1.1\r\nHost: example.com\r\n\r\n").await.unwrap();
+    while recorded.paths().is_empty() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    stream.set_linger(Some(Duration::ZERO)).unwrap();
+    drop(stream);
+    assert_all_closed(&turbogate, "client abort").await;
+}
+
+#[tokio::test]
+async fn connections_over_maxconn_are_released() {
+    let backend_port = free_port();
+    let (_recorded, _backend) = backend(backend_port).await;
+    let (turbogate, port) = start(&config("", "    maxconn 1\n    default_backend be\n", "", backend_port)).await;
+    let first = held(port).await;
+    assert_eq!(statuses(&refused(port).await), Vec::<u16>::new());
+    drop(first);
+    assert_all_closed(&turbogate, "maxconn").await;
+    assert_eq!(statuses(&refused(port).await), [200], "the refused connection still holds a frontend slot");
+}
+
+#[tokio::test]
+async fn tarpitted_connections_are_released() {
+    let backend_port = free_port();
+    let (_recorded, _backend) = backend(backend_port).await;
+    let (turbogate, port) = start(&config("", "    timeout tarpit 100ms\n    tcp-request connection tarpit\n    default_backend be\n", "", backend_port)).await;
+    assert_eq!(statuses(&refused(port).await), Vec::<u16>::new());
+    assert_all_closed(&turbogate, "tarpit").await;
+}
+
+#[tokio::test]
+async fn tarpit_holds_blacklisted_clients_without_reaching_the_backend() {
+    let backend_port = free_port();
+    let (recorded, _backend) = backend(backend_port).await;
+    let frontend = "    timeout tarpit 300ms\n    acl blacklisted src 127.0.0.1\n    \
+                    tcp-request connection tarpit if blacklisted\n    default_backend be\n";
+    let (turbogate, port) = start(&config("    tarpit-maxconn 1\n", frontend, "", backend_port)).await;
+    // Let the tarpitted readiness probe of `start` go.
+    tokio::time::sleep(Duration::from_millis(400)).await;
+
+    let started = Instant::now();
+    let mut tarpitted = TcpStream::connect(addr(port)).await.unwrap();
+    tarpitted.write_all(REQUEST).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let over_cap = Instant::now();
+    assert_eq!(refused(port).await, b"");
+    assert!(over_cap.elapsed() < Duration::from_millis(200), "the connection over tarpit-maxconn was held");
+
+    let (response, closed) = read_all(&mut tarpitted, WAIT).await;
+    assert!(closed && response.is_empty());
+    assert!(started.elapsed() >= Duration::from_millis(300), "released after {:?}", started.elapsed());
+    assert_eq!(recorded.connections.load(Ordering::Relaxed), 0);
+    assert_eq!(turbogate.metric("turbogate_tarpitted_total", &["frontend=\"web\""]).await, 2.0);
+}
+
+// The first of three consecutive ports that are all free.
+fn free_port_range() -> u16 {
+    loop {
+        let first = free_port();
+        if first < u16::MAX - 2 && (1..3).all(|offset| std::net::TcpListener::bind(addr(first + offset)).is_ok()) {
+            return first;
+        }
+    }
+}
+
+#[tokio::test]
+async fn bind_ranges_listen_and_count_on_every_port() {
+    let backend_port = free_port();
+    let (_recorded, _backend) = backend(backend_port).await;
+    let first = free_port_range();
+    let template = config("", "    default_backend be\n", "", backend_port)
+        .replace("{port}", &format!("{first}-{}", first + 2));
+    let turbogate = Turbogate::start(&template, first).await;
+
+    for (port, requests) in [(first, 1), (first + 1, 2), (first + 2, 3)] {
+        let listener = format!("listener=\"127.0.0.1:{port}\"");
+        // The readiness probe of `start` is counted on the first listener.
+        let before = turbogate.metric("turbogate_connections_total", &["frontend=\"web\"", &listener]).await;
+        for _ in 0..requests {
+            assert_eq!(statuses(&exchange(addr(port), REQUEST, WAIT).await.0), [200], "port {port}");
+        }
+        let accepted = turbogate.metric("turbogate_connections_total", &["frontend=\"web\"", &listener]).await;
+        assert_eq!(accepted - before, requests as f64, "{listener}");
+    }
+}
+
+#[tokio::test]
+async fn each_bind_counts_connections_under_its_own_listener() {
+    let backend_port = free_port();
+    let (_recorded, _backend) = backend(backend_port).await;
+    let (port, named_port) = (free_port(), free_port());
+    let template = config("", &format!("    bind 127.0.0.1:{named_port} name alt\n    default_backend be\n"), "", backend_port)
+        .replace("{port}", &port.to_string());
+    let turbogate = Turbogate::start(&template, port).await;
+    let counted = async |listener: &str| {
+        turbogate.metric("turbogate_connections_total", &["frontend=\"web\"", &format!("listener=\"{listener}\"")]).await
+    };
+    let unnamed = format!("127.0.0.1:{port}");
+    // The readiness probe of `start` is counted on the unnamed listener.
+    let before = counted(&unnamed).await;
+
+    for (listener_port, requests) in [(port, 2), (named_port, 3)] {
+        for _ in 0..requests {
+            assert_eq!(statuses(&exchange(addr(listener_port), REQUEST, WAIT).await.0), [200], "port {listener_port}");
+        }
+    }
+    assert_eq!(counted(&unnamed).await - before, 2.0);
+    assert_eq!(counted("alt").await, 3.0);
+
+    let stats = turbogate.get("/stats;csv").await;
+    let rows: Vec<&str> = stats.lines().filter_map(|line| line.strip_prefix("web,")?.split(',').next()).collect();
+    assert_eq!(rows, ["FRONTEND", unnamed.as_str(), "alt"]);
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn sharded_binds_accept_on_every_shard_of_one_address() {
+    let backend_port = free_port();
+    let (_recorded, _backend) = backend(backend_port).await;
+    let template = config("", "    default_backend be\n", "", backend_port)
+        .replace("bind 127.0.0.1:{port}\n", "bind 127.0.0.1:{port} shards 3\n");
+    let (turbogate, port) = start(&template).await;
+    let counted = async || turbogate.metric("turbogate_connections_total", &["frontend=\"web\""]).await;
+    let before = counted().await;
+
+    for _ in 0..12 {
+        assert_eq!(statuses(&exchange(addr(port), REQUEST, WAIT).await.0), [200]);
+    }
+    assert_eq!(counted().await - before, 12.0);
+
+    let stats = turbogate.get("/stats;csv").await;
+    let rows: Vec<&str> = stats.lines().filter_map(|line| line.strip_prefix("web,")?.split(',').next()).collect();
+    let shards: Vec<String> = (1..=3).map(|shard| format!("127.0.0.1:{port}#{shard}")).collect();
+    assert_eq!(rows, ["FRONTEND", &shards[0], &shards[1], &shards[2]]);
+}
+
+// The field names and `CumConns` of `show info` on the admin socket.
+async fn show_info(socket: &std::path::Path) -> (Vec<String>, u64) {
+    let mut stream = tokio::net::UnixStream::connect(socket).await.unwrap();
+    stream.write_all(b"show info\n").await.unwrap();
+    stream.shutdown().await.unwrap();
+    let mut output = String::new();
+    tokio::time::timeout(WAIT, stream.read_to_string(&mut output)).await.unwrap().unwrap();
+    let fields: Vec<(String, String)> = output.lines()
+        .filter_map(|line| line.split_once(": "))
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    let cum_conns = fields.iter().find(|(name, _)| name == "CumConns").and_then(|(_, value)| value.parse().ok()).unwrap();
+    (fields.into_iter().map(|(name, _)| name).collect(), cum_conns)
+}
+
+#[tokio::test]
+async fn show_info_reports_process_fields_and_counts_connections() {
+    let backend_port = free_port();
+    let (_recorded, _backend) = backend(backend_port).await;
+    let dir = common::scratch_dir();
+    let socket = dir.join("admin.sock");
+    let (turbogate, port) = start(&config(&format!("    stats socket {}\n", socket.display()), "    default_backend be\n", "", backend_port)).await;
+    let (names, before) = show_info(&socket).await;
+
+    for name in ["Name", "Version", "Pid", "Uptime", "Uptime_sec", "Nbthread", "Tasks", "Maxconn", "Hard_maxconn",
+                 "CurrConns", "PeakConns", "CumConns", "CumDenied", "ConnRate", "MaxConnRate", "Reloads", "Rss_bytes"] {
+        assert!(names.iter().any(|field| field == name), "show info lacks {name}: {names:?}");
+    }
+    let info = turbogate.get("/info").await;
+    let info: serde_json::Value = serde_json::from_str(info.split_once("\r\n\r\n").unwrap().1).unwrap();
+    let json_names: Vec<&String> = info.as_object().unwrap().keys().collect();
+    assert_eq!(json_names.len(), names.len(), "{json_names:?}");
+    assert!(names.iter().all(|name| info.get(name).is_some()), "{json_names:?}");
+
+    for _ in 0..3 {
+        assert_eq!(statuses(&refused(port).await), [200]);
+    }
+    let (_, after) = show_info(&socket).await;
+    assert_eq!(after - before, 3);
+    let _ = std::fs::remove_dir_all(dir);
+}
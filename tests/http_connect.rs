@@ -0,0 +1,88 @@
+mod common;
+
+use common::{Turbogate, addr, exchange, free_port, statuses};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const WAIT: Duration = Duration::from_secs(2);
+// ci:s3cret and ci:wrong
+const GOOD: &str = "Proxy-Authorization: Basic Y2k6czNjcmV0\r\n";
+const BAD: &str = "Proxy-Authorization: Basic Y2k6d3Jvbmc=\r\n";
+
+async fn echo_server() -> u16 {
+    let listener = TcpListener::bind(addr(0)).await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+    port
+}
+
+// An egress gateway that only lets authenticated clients reach `allowed_port` on 127.0.0.1.
+async fn gateway(allowed_port: u16) -> (Turbogate, u16) {
+    let port = free_port();
+    let config = format!("userlist egress-users\n    user ci insecure-password s3cret\n\n\
+                          frontend egress\n    bind 127.0.0.1:{port}\n    mode http-connect\n    connect-auth egress-users\n    \
+                          acl local connect_host 127.0.0.1\n    acl allowed connect_port {allowed_port}\n    \
+                          use_backend direct if local allowed\n\n\
+                          backend direct\n    option connect-direct\n");
+    (Turbogate::start(&config, port).await, port)
+}
+
+fn connect(target: &str, credentials: &str) -> Vec<u8> {
+    format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n{credentials}\r\n").into_bytes()
+}
+
+#[tokio::test]
+async fn missing_or_wrong_credentials_get_a_407_challenge() {
+    let echo_port = echo_server().await;
+    let target = format!("127.0.0.1:{echo_port}");
+    let (turbogate, port) = gateway(echo_port).await;
+
+    for credentials in ["", BAD] {
+        let (response, closed) = exchange(addr(port), &connect(&target, credentials), WAIT).await;
+        let response = String::from_utf8_lossy(&response);
+        assert_eq!(statuses(response.as_bytes()), [407], "{response}");
+        assert!(response.contains("Proxy-Authenticate: Basic realm=\"egress-users\""), "{response}");
+        assert!(closed);
+    }
+    assert_eq!(turbogate.metric("turbogate_connect_auth_failures_total", &["frontend=\"egress\""]).await, 2.0);
+}
+
+#[tokio::test]
+async fn allowed_targets_get_a_200_and_a_tunnel() {
+    let echo_port = echo_server().await;
+    let target = format!("127.0.0.1:{echo_port}");
+    let (_turbogate, port) = gateway(echo_port).await;
+
+    let mut stream = TcpStream::connect(addr(port)).await.unwrap();
+    stream.write_all(&connect(&target, GOOD)).await.unwrap();
+    let mut established = [0u8; 39];
+    tokio::time::timeout(WAIT, stream.read_exact(&mut established)).await.unwrap().unwrap();
+    assert_eq!(&established, b"HTTP/1.1 200 Connection Established\r\n\r\n");
+
+    stream.write_all(b"ping").await.unwrap();
+    let mut echoed = [0u8; 4];
+    tokio::time::timeout(WAIT, stream.read_exact(&mut echoed)).await.unwrap().unwrap();
+    assert_eq!(&echoed, b"ping");
+}
+
+#[tokio::test]
+async fn targets_no_rule_allows_are_forbidden() {
+    let echo_port = echo_server().await;
+    let other_port = echo_server().await;
+    let (turbogate, port) = gateway(echo_port).await;
+
+    for target in [format!("127.0.0.1:{other_port}"), format!("localhost:{echo_port}")] {
+        let (response, closed) = exchange(addr(port), &connect(&target, GOOD), WAIT).await;
+        assert_eq!(statuses(&response), [403], "{target}: {}", String::from_utf8_lossy(&response));
+        assert!(closed);
+    }
+    assert_eq!(turbogate.metric("turbogate_no_backend_total", &["frontend=\"egress\"", "action=\"forbidden\""]).await, 2.0);
+}
@@ -0,0 +1,310 @@
+mod common;
+
+use common::{Turbogate, addr, backend, backend_with_delay, exchange, free_port, read_all, statuses};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use std::time::Duration;
+
+const WAIT: Duration = Duration::from_millis(500);
+
+fn http_config(port: u16, backend_port: u16, extra: &str) -> String {
+    format!("frontend web\n    bind 127.0.0.1:{port}\n    mode http\n{extra}    default_backend be\n\nbackend be\n    server s1 127.0.0.1:{backend_port}\n")
+}
+
+const FIRST: &str = "GET /first HTTP/1.1\r\nHost: example.com\r\n\r\n";
+
+#[tokio::test]
+async fn smuggling_attempts_after_a_valid_request_are_rejected() {
+    let cases: &[(&str, &str)] = &[
+        ("CL.TE", "POST /cl-te HTTP/1.1\r\nHost: example.com\r\nContent-Length: 6\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nGET /hidden HTTP/1.1\r\nHost: example.com\r\n\r\n"),
+        ("TE.CL", "POST /te-cl HTTP/1.1\r\nHost: example.com\r\nContent-Length: 4\r\nTransfer-Encoding: chunked\r\n\r\n2d\r\nGET /hidden HTTP/1.1\r\nHost: example.com\r\n\r\n\r\n0\r\n\r\n"),
+        ("TE.TE duplicate", "POST /te-te HTTP/1.1\r\nHost: example.com\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: x\r\n\r\n0\r\n\r\nGET /hidden HTTP/1.1\r\nHost: example.com\r\n\r\n"),
+        ("TE.TE unknown coding", "POST /te-te HTTP/1.1\r\nHost: example.com\r\nTransfer-Encoding: xchunked\r\n\r\n0\r\n\r\nGET /hidden HTTP/1.1\r\nHost: example.com\r\n\r\n"),
+        ("TE.TE space before colon", "POST /te-te HTTP/1.1\r\nHost: example.com\r\nTransfer-Encoding : chunked\r\n\r\n0\r\n\r\nGET /hidden HTTP/1.1\r\nHost: example.com\r\n\r\n"),
+        ("TE.TE obs-fold", "POST /te-te HTTP/1.1\r\nHost: example.com\r\nTransfer-Encoding:\r\n chunked\r\n\r\n0\r\n\r\nGET /hidden HTTP/1.1\r\nHost: example.com\r\n\r\n"),
+        ("TE.TE bare LF", "POST /te-te HTTP/1.1\r\nHost: example.com\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nGET /hidden HTTP/1.1\r\nHost: example.com\r\n\r\n"),
+        ("CL.CL", "POST /cl-cl HTTP/1.1\r\nHost: example.com\r\nContent-Length: 0\r\nContent-Length: 44\r\n\r\nGET /hidden HTTP/1.1\r\nHost: example.com\r\n\r\n"),
+    ];
+
+    let port = free_port();
+    let backend_port = free_port();
+    let (recorded, _backend) = backend(backend_port).await;
+    let _turbogate = Turbogate::start(&http_config(port, backend_port, ""), port).await;
+
+    for (name, smuggled) in cases {
+        let request = format!("{FIRST}{smuggled}");
+        let before = recorded.heads().len();
+        let (response, closed) = exchange(addr(port), request.as_bytes(), WAIT).await;
+        assert_eq!(statuses(&response), [200, 400], "{name}: {}", String::from_utf8_lossy(&response));
+        assert!(closed, "{name}: connection left open");
+        let forwarded: Vec<String> = recorded.paths()[before..].to_vec();
+        assert_eq!(forwarded, ["/first"], "{name}: backend saw {forwarded:?}");
+    }
+}
+
+#[tokio::test]
+async fn accept_invalid_http_request_forwards_later_requests() {
+    let port = free_port();
+    let backend_port = free_port();
+    let (recorded, _backend) = backend(backend_port).await;
+    let config = http_config(port, backend_port, "    option accept-invalid-http-request\n");
+    let _turbogate = Turbogate::start(&config, port).await;
+
+    let request = format!("{FIRST}GET /folded HTTP/1.1\r\nHost: example.com\r\nX-Folded: a\r\n b\r\n\r\n");
+    let (response, _) = exchange(addr(port), request.as_bytes(), WAIT).await;
+    assert_eq!(statuses(&response), [200, 200]);
+    assert_eq!(recorded.paths(), ["/first", "/folded"]);
+}
+
+#[tokio::test]
+async fn later_requests_are_normalized_before_forwarding() {
+    let port = free_port();
+    let backend_port = free_port();
+    let (recorded, _backend) = backend(backend_port).await;
+    let _turbogate = Turbogate::start(&http_config(port, backend_port, ""), port).await;
+
+    let request = format!("{FIRST}GET /second HTTP/1.1\r\nHOST:   example.com  \r\n\r\n");
+    let (response, _) = exchange(addr(port), request.as_bytes(), WAIT).await;
+    assert_eq!(statuses(&response), [200, 200]);
+    assert_eq!(recorded.heads()[1], "GET /second HTTP/1.1\r\nhost: example.com\r\n\r\n");
+}
+
+const AUTH: &str = "    acl admin path_beg /admin\n    acl auth_ok http_auth(admins)\n    http-request auth realm admin if admin !auth_ok\n";
+
+fn auth_config(port: u16, backend_port: u16) -> String {
+    format!("userlist admins\n    user alice insecure-password secret\n\n{}", http_config(port, backend_port, AUTH))
+}
+
+#[tokio::test]
+async fn auth_is_checked_on_every_request() {
+    let port = free_port();
+    let backend_port = free_port();
+    let (recorded, _backend) = backend(backend_port).await;
+    let _turbogate = Turbogate::start(&auth_config(port, backend_port), port).await;
+
+    let request = format!("{FIRST}GET /admin/secret HTTP/1.1\r\nHost: example.com\r\n\r\nGET /after HTTP/1.1\r\nHost: example.com\r\n\r\n");
+    let (response, closed) = exchange(addr(port), request.as_bytes(), WAIT).await;
+    let text = String::from_utf8_lossy(&response);
+    assert_eq!(statuses(&response), [200, 401], "{text}");
+    assert!(text.contains("WWW-Authenticate: Basic realm=\"admin\""), "{text}");
+    assert!(closed);
+    assert_eq!(recorded.paths(), ["/first"]);
+}
+
+#[tokio::test]
+async fn authenticated_later_requests_are_forwarded() {
+    let port = free_port();
+    let backend_port = free_port();
+    let (recorded, _backend) = backend(backend_port).await;
+    let _turbogate = Turbogate::start(&auth_config(port, backend_port), port).await;
+
+    // alice:secret
+    let request = format!("{FIRST}GET /admin/secret HTTP/1.1\r\nHost: example.com\r\nAuthorization: Basic YWxpY2U6c2VjcmV0\r\n\r\n");
+    let (response, _) = exchange(addr(port), request.as_bytes(), WAIT).await;
+    assert_eq!(statuses(&response), [200, 200]);
+    assert_eq!(recorded.paths(), ["/first", "/admin/secret"]);
+}
+
+const RETURN: &str = "    acl teapot path /teapot\n    http-request return status 418 content-type text/plain string \"short and stout\" if teapot\n";
+
+#[tokio::test]
+async fn return_rule_is_evaluated_per_request() {
+    let port = free_port();
+    let backend_port = free_port();
+    let (recorded, _backend) = backend(backend_port).await;
+    let _turbogate = Turbogate::start(&http_config(port, backend_port, RETURN), port).await;
+
+    let request = format!("{FIRST}GET /teapot HTTP/1.1\r\nHost: example.com\r\n\r\nGET /third HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n");
+    let (response, closed) = exchange(addr(port), request.as_bytes(), WAIT).await;
+    let text = String::from_utf8_lossy(&response);
+    assert_eq!(statuses(&response), [200, 418, 200], "{text}");
+    assert!(text.contains("\r\n\r\nshort and stout"), "{text}");
+    assert!(text.ends_with("/third"), "{text}");
+    assert!(closed);
+    assert_eq!(recorded.paths(), ["/first", "/third"]);
+}
+
+#[tokio::test]
+async fn requests_after_a_returned_one_go_to_the_backend() {
+    let port = free_port();
+    let backend_port = free_port();
+    let (recorded, _backend) = backend(backend_port).await;
+    let _turbogate = Turbogate::start(&http_config(port, backend_port, RETURN), port).await;
+
+    let request = "GET /teapot HTTP/1.1\r\nHost: example.com\r\n\r\nPOST /second HTTP/1.1\r\nHost: example.com\r\nContent-Length: 3\r\n\r\nabcGET /teapot HTTP/1.1\r\nHost: example.com\r\n\r\n";
+    let (response, _) = exchange(addr(port), request.as_bytes(), WAIT).await;
+    assert_eq!(statuses(&response), [418, 200, 418], "{}", String::from_utf8_lossy(&response));
+    assert_eq!(recorded.paths(), ["/second"]);
+}
+
+#[tokio::test]
+async fn returned_request_bodies_are_not_forwarded() {
+    let port = free_port();
+    let backend_port = free_port();
+    let (recorded, _backend) = backend(backend_port).await;
+    let _turbogate = Turbogate::start(&http_config(port, backend_port, RETURN), port).await;
+
+    let request = format!("{FIRST}POST /teapot HTTP/1.1\r\nHost: example.com\r\nContent-Length: 44\r\n\r\nGET /hidden HTTP/1.1\r\nHost: example.com\r\n\r\nGET /last HTTP/1.1\r\nHost: example.com\r\n\r\n");
+    let (response, _) = exchange(addr(port), request.as_bytes(), WAIT).await;
+    assert_eq!(statuses(&response), [200, 418, 200], "{}", String::from_utf8_lossy(&response));
+    assert_eq!(recorded.paths(), ["/first", "/last"]);
+}
+
+async fn read_until(stream: &mut TcpStream, end: &[u8]) -> Vec<u8> {
+    let mut response = Vec::new();
+    let mut buffer = [0u8; 4096];
+    while !response.ends_with(end) {
+        let n = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buffer)).await.unwrap().unwrap();
+        assert!(n > 0, "closed after {}", String::from_utf8_lossy(&response));
+        response.extend_from_slice(&buffer[..n]);
+    }
+    response
+}
+
+#[tokio::test]
+async fn request_phase_timers_are_taken_per_request() {
+    let port = free_port();
+    let backend_port = free_port();
+    let (_recorded, _backend) = backend_with_delay(backend_port, Duration::from_millis(200)).await;
+    let turbogate = Turbogate::start(&http_config(port, backend_port, ""), port).await;
+
+    let mut client = TcpStream::connect(addr(port)).await.unwrap();
+    client.write_all(FIRST.as_bytes()).await.unwrap();
+    read_until(&mut client, b"/first").await;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    client.write_all(b"GET /second HTTP/1.1\r\n").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    client.write_all(b"Host: example.com\r\n\r\n").await.unwrap();
+    read_until(&mut client, b"/second").await;
+
+    let phase = |name: &'static str| format!("phase=\"{name}\"");
+    for name in ["Tq", "Tw", "Tc", "Tr", "Tt"] {
+        let count = turbogate.metric("turbogate_http_request_phase_seconds_count", &[&phase(name)]).await;
+        assert_eq!(count, 2.0, "{name}");
+    }
+    let sum = |name| {
+        let turbogate = &turbogate;
+        async move { turbogate.metric("turbogate_http_request_phase_seconds_sum", &[&phase(name)]).await }
+    };
+    let request = sum("Tq").await;
+    assert!((0.15..0.3).contains(&request), "Tq {request}");
+    let response = sum("Tr").await;
+    assert!((0.4..0.6).contains(&response), "Tr {response}");
+    let total = sum("Tt").await;
+    assert!((0.55..0.8).contains(&total), "Tt {total}");
+    assert!(sum("Tw").await < 0.05);
+    assert!(sum("Tc").await < 0.05);
+}
+
+#[tokio::test]
+async fn idle_keep_alive_connections_are_closed_after_the_timeout() {
+    let port = free_port();
+    let backend_port = free_port();
+    let (recorded, _backend) = backend(backend_port).await;
+    let config = http_config(port, backend_port, "    timeout http-keep-alive 300ms\n");
+    let _turbogate = Turbogate::start(&config, port).await;
+
+    let mut client = TcpStream::connect(addr(port)).await.unwrap();
+    client.write_all(FIRST.as_bytes()).await.unwrap();
+    let (response, closed) = read_all(&mut client, Duration::from_millis(100)).await;
+    assert_eq!(statuses(&response), [200]);
+    assert!(!closed, "closed before the keep-alive timeout");
+
+    client.write_all(b"GET /second HTTP/1.1\r\nHost: example.com\r\n\r\n").await.unwrap();
+    let (response, closed) = read_all(&mut client, Duration::from_secs(2)).await;
+    assert_eq!(statuses(&response), [200]);
+    assert!(closed, "idle keep-alive connection left open");
+    assert_eq!(recorded.paths(), ["/first", "/second"]);
+}
+
+
+#[tokio::test]
+async fn proxy_header_source_is_routed_through_every_stage() {
+    let port = free_port();
+    let backend_port = free_port();
+    let (recorded, _backend) = backend(backend_port).await;
+    let config = format!("frontend web\n    bind 127.0.0.1:{port} accept-proxy\n    mode http\n    acl proxied src 192.0.2.0/24\n    use_backend be if proxied\n\nbackend be\n    server s1 127.0.0.1:{backend_port}\n");
+    let _turbogate = Turbogate::start(&config, port).await;
+
+    let routed = format!("PROXY TCP4 192.0.2.7 127.0.0.1 40000 {port}\r\n{FIRST}");
+    let (response, _) = exchange(addr(port), routed.as_bytes(), WAIT).await;
+    assert_eq!(statuses(&response), [200]);
+    assert_eq!(recorded.paths(), ["/first"]);
+
+    let unrouted = format!("PROXY TCP4 198.51.100.7 127.0.0.1 40000 {port}\r\n{FIRST}");
+    let (response, closed) = exchange(addr(port), unrouted.as_bytes(), WAIT).await;
+    assert!(response.is_empty() && closed, "{}", String::from_utf8_lossy(&response));
+    assert_eq!(recorded.paths(), ["/first"]);
+}
+
+#[tokio::test]
+async fn draining_closes_idle_keep_alive_clients_and_busy_ones_after_their_response() {
+    let port = free_port();
+    let backend_port = free_port();
+    let (_recorded, _backend) = backend_with_delay(backend_port, Duration::from_millis(600)).await;
+    let dir = common::scratch_dir();
+    let socket = dir.join("admin.sock");
+    let config = format!("global\n    stats socket {}\n\n{}", socket.display(), http_config(port, backend_port, ""));
+    let turbogate = Turbogate::start(&config, port).await;
+
+    let mut idle = TcpStream::connect(addr(port)).await.unwrap();
+    idle.write_all(FIRST.as_bytes()).await.unwrap();
+    read_until(&mut idle, b"/first").await;
+    let mut busy = TcpStream::connect(addr(port)).await.unwrap();
+    busy.write_all(b"GET /busy HTTP/1.1\r\nHost: example.com\r\n\r\n").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut admin = tokio::net::UnixStream::connect(&socket).await.unwrap();
+    admin.write_all(b"set draining on\n").await.unwrap();
+    let draining = std::time::Instant::now();
+
+    let (response, closed) = read_all(&mut idle, Duration::from_secs(2)).await;
+    assert!(response.is_empty() && closed, "{}", String::from_utf8_lossy(&response));
+    assert!(draining.elapsed() < Duration::from_millis(400), "idle client closed after {:?}", draining.elapsed());
+
+    let (response, closed) = read_all(&mut busy, Duration::from_secs(2)).await;
+    let response = String::from_utf8_lossy(&response);
+    assert_eq!(statuses(response.as_bytes()), [200]);
+    assert!(response.to_ascii_lowercase().contains("\r\nconnection: close\r\n") && response.ends_with("/busy"), "{response}");
+    assert!(closed, "busy client left open after its response");
+
+    for reason in ["keepalive_idle", "after_response"] {
+        assert_eq!(turbogate.metric("turbogate_drain_closed_total", &[&format!("reason=\"{reason}\"")]).await, 1.0, "{reason}");
+    }
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn servers_receive_their_own_name_on_every_request_of_a_connection() {
+    let port = free_port();
+    let (s1_port, s2_port) = (free_port(), free_port());
+    let (s1, _s1) = backend(s1_port).await;
+    let (s2, _s2) = backend(s2_port).await;
+    let config = format!("frontend web\n    bind 127.0.0.1:{port}\n    mode http\n    default_backend be\n\n\
+                          backend be\n    balance roundrobin\n    http-send-name-header X-Backend-Server\n    \
+                          http-send-backend-header X-Backend\n    \
+                          server s1 127.0.0.1:{s1_port}\n    server s2 127.0.0.1:{s2_port}\n");
+    let _turbogate = Turbogate::start(&config, port).await;
+
+    for _ in 0..2 {
+        let mut client = TcpStream::connect(addr(port)).await.unwrap();
+        client.write_all(FIRST.as_bytes()).await.unwrap();
+        read_until(&mut client, b"/first").await;
+        client.write_all(b"GET /second HTTP/1.1\r\nHost: example.com\r\nX-Backend-Server: spoofed\r\n\r\n").await.unwrap();
+        read_until(&mut client, b"/second").await;
+    }
+
+    for (name, recorded) in [("s1", &s1), ("s2", &s2)] {
+        let heads = recorded.heads();
+        assert_eq!(recorded.paths(), ["/first", "/second"], "{name}");
+        for head in &heads {
+            let values = |header: &str| head.lines()
+                .filter_map(|line| line.split_once(':'))
+                .filter(|(field, _)| field.eq_ignore_ascii_case(header))
+                .map(|(_, value)| value.trim().to_string())
+                .collect::<Vec<_>>();
+            assert_eq!(values("x-backend-server"), [name], "{head}");
+            assert_eq!(values("x-backend"), ["be"], "{head}");
+        }
+    }
+}
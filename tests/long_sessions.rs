@@ -0,0 +1,49 @@
+mod common;
+
+use common::{Turbogate, addr, free_port};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+async fn echo_backend(port: u16) -> JoinHandle<()> {
+    let listener = TcpListener::bind(addr(port)).await.unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut buf = [0u8; 1024];
+                while let Ok(n) = stream.read(&mut buf).await {
+                    if n == 0 || stream.write_all(&buf[..n]).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    })
+}
+
+// Sessions used to be cut after a fixed 30s regardless of traffic.
+#[tokio::test]
+async fn keepalive_session_outlives_the_old_handle_timeout() {
+    let port = free_port();
+    let backend_port = free_port();
+    let _backend = echo_backend(backend_port).await;
+    let config = format!("defaults\n    mode tcp\n    timeout connect 1s\n    timeout client 10s\n    timeout server 10s\n\n\
+                          frontend web\n    bind 127.0.0.1:{port}\n    default_backend be\n\n\
+                          backend be\n    server s1 127.0.0.1:{backend_port}\n");
+    let _turbogate = Turbogate::start(&config, port).await;
+
+    let mut stream = TcpStream::connect(addr(port)).await.unwrap();
+    let mut byte = [0u8; 1];
+    for round in 0..8u8 {
+        stream.write_all(&[round]).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(1), stream.read_exact(&mut byte)).await
+            .unwrap_or_else(|_| panic!("no echo after {}s", round * 5))
+            .unwrap_or_else(|e| panic!("session killed after {}s: {e}", round * 5));
+        assert_eq!(byte[0], round);
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+    stream.write_all(b"x").await.unwrap();
+    stream.read_exact(&mut byte).await.expect("session killed after 40s");
+    assert_eq!(&byte, b"x");
+}
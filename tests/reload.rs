@@ -0,0 +1,137 @@
+mod common;
+
+use common::{Turbogate, addr, backend, exchange, free_port, statuses};
+use std::time::Duration;
+
+const WAIT: Duration = Duration::from_millis(500);
+
+fn frontend(name: &str, port: u16) -> String {
+    format!("frontend {name}\n    bind 127.0.0.1:{port}\n    mode http\n    default_backend be\n\n")
+}
+
+fn backend_section(port: u16) -> String {
+    format!("backend be\n    server s1 127.0.0.1:{port}\n")
+}
+
+async fn get(port: u16) -> Vec<u16> {
+    let (response, _) = exchange(addr(port), b"GET / HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n", WAIT).await;
+    statuses(&response)
+}
+
+#[tokio::test]
+async fn sighup_reloads_the_configuration_file() {
+    let port = free_port();
+    let added = free_port();
+    let backend_port = free_port();
+    let (_recorded, _backend) = backend(backend_port).await;
+    let mut turbogate = Turbogate::start(&(frontend("web", port) + &backend_section(backend_port)), port).await;
+
+    turbogate.write_config(&(frontend("web", port) + &frontend("added", added) + &backend_section(backend_port)));
+    turbogate.signal(libc::SIGHUP);
+    turbogate.wait_for_port(added).await;
+
+    assert_eq!(get(added).await, [200]);
+    assert_eq!(get(port).await, [200]);
+    assert_eq!(turbogate.metric("turbogate_config_reloads_total", &["result=\"applied\""]).await, 1.0);
+}
+
+#[tokio::test]
+async fn sighup_with_an_invalid_file_keeps_the_running_configuration() {
+    let port = free_port();
+    let backend_port = free_port();
+    let (_recorded, _backend) = backend(backend_port).await;
+    let turbogate = Turbogate::start(&(frontend("web", port) + &backend_section(backend_port)), port).await;
+
+    turbogate.write_config(&(frontend("web", port) + "frontend broken\n    bind *:\n\n" + &backend_section(backend_port)));
+    turbogate.signal(libc::SIGHUP);
+    for _ in 0..40 {
+        if turbogate.metric("turbogate_config_reloads_total", &["result=\"rejected\""]).await == 1.0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(25)).await;
+    }
+
+    assert_eq!(turbogate.metric("turbogate_config_reloads_total", &["result=\"rejected\""]).await, 1.0);
+    assert_eq!(get(port).await, [200]);
+}
+
+// The hash label of the live `turbogate_config_hash` series.
+async fn live_config_hash(turbogate: &Turbogate) -> String {
+    turbogate.get("/metrics").await.lines()
+        .filter(|line| line.starts_with("turbogate_config_hash{") && line.ends_with(" 1"))
+        .find_map(|line| Some(line.split_once("hash=\"")?.1.split_once('"')?.0.to_string()))
+        .expect("no live turbogate_config_hash series")
+}
+
+#[tokio::test]
+async fn reloads_update_the_config_hash_and_timestamp() {
+    let port = free_port();
+    let backend_port = free_port();
+    let (_recorded, _backend) = backend(backend_port).await;
+    let turbogate = Turbogate::start(&(frontend("web", port) + &backend_section(backend_port)), port).await;
+    let hash = live_config_hash(&turbogate).await;
+    let loaded_at = turbogate.metric("turbogate_config_last_reload_timestamp_seconds", &[]).await;
+
+    turbogate.write_config(&(frontend("web", port) + &backend_section(backend_port) + "    timeout server 5s\n"));
+    turbogate.signal(libc::SIGHUP);
+    for _ in 0..40 {
+        if turbogate.metric("turbogate_config_reloads_total", &["result=\"applied\""]).await == 1.0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(25)).await;
+    }
+
+    let reloaded_hash = live_config_hash(&turbogate).await;
+    assert_eq!(reloaded_hash.len(), 64, "{reloaded_hash}");
+    assert_ne!(reloaded_hash, hash);
+    assert_eq!(turbogate.metric("turbogate_config_hash", &[&format!("hash=\"{hash}\"")]).await, 0.0);
+    assert!(turbogate.metric("turbogate_config_last_reload_timestamp_seconds", &[]).await > loaded_at);
+    assert_eq!(turbogate.metric("turbogate_config_last_reload_success", &[]).await, 1.0);
+}
+
+// Hits and misses of each ACL on the /acls endpoint, keyed by ACL name.
+async fn acl_counts(turbogate: &Turbogate) -> Vec<(String, u64, u64)> {
+    let response = turbogate.get("/acls").await;
+    let acls: serde_json::Value = serde_json::from_str(response.split_once("\r\n\r\n").unwrap().1).unwrap();
+    acls.as_array().unwrap().iter()
+        .map(|acl| (acl["acl"].as_str().unwrap().to_string(), acl["hits"].as_u64().unwrap(), acl["misses"].as_u64().unwrap()))
+        .collect()
+}
+
+#[tokio::test]
+async fn acl_counters_count_matches_and_survive_a_reload() {
+    let port = free_port();
+    let backend_port = free_port();
+    let (_recorded, _backend) = backend(backend_port).await;
+    let config = format!(
+        "frontend web\n    bind 127.0.0.1:{port}\n    mode http\n    \
+         acl is_api path_beg /api\n    acl is_admin path_beg /admin\n    \
+         use_backend be if is_api\n    use_backend be if is_admin\n    default_backend be\n\n{}",
+        backend_section(backend_port));
+    let turbogate = Turbogate::start(&config, port).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let before = acl_counts(&turbogate).await;
+
+    for path in ["/api/a", "/api/b", "/api/c", "/other", "/other"] {
+        let request = format!("GET {path} HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n");
+        let (response, _) = exchange(addr(port), request.as_bytes(), WAIT).await;
+        assert_eq!(statuses(&response), [200]);
+    }
+    let counted = acl_counts(&turbogate).await;
+    let deltas: Vec<(String, u64, u64)> = counted.iter().zip(&before)
+        .map(|((name, hits, misses), (_, hits_before, misses_before))| (name.clone(), hits - hits_before, misses - misses_before))
+        .collect();
+    assert_eq!(deltas, [("is_api".to_string(), 3, 2), ("is_admin".to_string(), 0, 2)]);
+
+    turbogate.write_config(&(config + "    timeout server 5s\n"));
+    turbogate.signal(libc::SIGHUP);
+    for _ in 0..40 {
+        if turbogate.metric("turbogate_config_reloads_total", &["result=\"applied\""]).await == 1.0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(25)).await;
+    }
+
+    assert_eq!(turbogate.metric("turbogate_config_reloads_total", &["result=\"applied\""]).await, 1.0);
+    assert_eq!(acl_counts(&turbogate).await, counted);
+}
@@ -0,0 +1,152 @@
+mod common;
+
+use common::{Turbogate, addr, backend, exchange, free_port, statuses};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+// A TLS 1.2 ClientHello record carrying only a server_name extension.
+fn client_hello(server_name: &str) -> Vec<u8> {
+    let name = server_name.as_bytes();
+    let mut server_names = vec![0x00];
+    server_names.extend((name.len() as u16).to_be_bytes());
+    server_names.extend(name);
+    let mut extension = vec![0x00, 0x00];
+    extension.extend((server_names.len() as u16 + 2).to_be_bytes());
+    extension.extend((server_names.len() as u16).to_be_bytes());
+    extension.extend(server_names);
+
+    let mut body = vec![0x03, 0x03];
+    body.extend([0u8; 32]);
+    body.extend([0x00, 0x00, 0x02, 0x00, 0x2f, 0x01, 0x00]);
+    body.extend((extension.len() as u16).to_be_bytes());
+    body.extend(extension);
+
+    let mut handshake = vec![0x01, 0x00];
+    handshake.extend((body.len() as u16).to_be_bytes());
+    handshake.extend(body);
+    let mut record = vec![0x16, 0x03, 0x01];
+    record.extend((handshake.len() as u16).to_be_bytes());
+    record.extend(handshake);
+    record
+}
+
+#[tokio::test]
+async fn sni_templates_route_to_the_named_backend_or_fall_back() {
+    let (port, tenant_port, default_port) = (free_port(), free_port(), free_port());
+    let (tenant, _tenant_backend) = backend(tenant_port).await;
+    let (fallback, _default_backend) = backend(default_port).await;
+    let config = format!("frontend tls\n    bind 127.0.0.1:{port}\n    use_backend %[sni,lower,word(1,.)]\n    default_backend fallback\n\n\
+                          backend tenant1\n    server s1 127.0.0.1:{tenant_port}\n\n\
+                          backend fallback\n    server s1 127.0.0.1:{default_port}\n");
+    let _turbogate = Turbogate::start(&config, port).await;
+    // The readiness probe of `start` may have been forwarded already.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let before = [tenant.connections.load(Ordering::Relaxed), fallback.connections.load(Ordering::Relaxed)];
+
+    for (server_name, expected) in [("Tenant1.example.com", [1, 0]), ("tenant2.example.com", [1, 1])] {
+        let mut stream = TcpStream::connect(addr(port)).await.unwrap();
+        stream.write_all(&client_hello(server_name)).await.unwrap();
+        let mut routed = [0; 2];
+        for _ in 0..40 {
+            routed = [tenant.connections.load(Ordering::Relaxed) - before[0], fallback.connections.load(Ordering::Relaxed) - before[1]];
+            if routed == expected {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(25)).await;
+        }
+        assert_eq!(routed, expected, "{server_name}");
+    }
+}
+
+#[tokio::test]
+async fn health_mode_backends_answer_probes_without_an_upstream() {
+    let upstream_port = free_port();
+    let (upstream, _upstream) = backend(upstream_port).await;
+    for (option, expected) in [("", &b"OK\n"[..]), ("    option httpchk\n", b"HTTP/1.0 200 OK\r\n")] {
+        let port = free_port();
+        let config = format!("frontend probes\n    bind 127.0.0.1:{port}\n    default_backend monitor\n\n\
+                              backend monitor\n    mode health\n{option}\n\
+                              backend app\n    server s1 127.0.0.1:{upstream_port}\n");
+        let turbogate = Turbogate::start(&config, port).await;
+
+        let (response, closed) = exchange(addr(port), b"GET /health HTTP/1.1\r\nHost: lb\r\n\r\n", Duration::from_millis(500)).await;
+        assert!(response.starts_with(expected), "{}", String::from_utf8_lossy(&response));
+        assert!(response.ends_with(b"OK\n"));
+        assert!(closed);
+        // The readiness probe of `start` was answered too.
+        assert_eq!(turbogate.metric("turbogate_health_probes_total", &["backend=\"monitor\""]).await, 2.0);
+    }
+    assert_eq!(upstream.connections.load(Ordering::Relaxed), 0);
+}
+
+#[tokio::test]
+async fn routing_decisions_are_counted_per_rule() {
+    let (port, upstream_port) = (free_port(), free_port());
+    let (_upstream, _upstream_backend) = backend(upstream_port).await;
+    let server = format!("    mode http\n    server s1 127.0.0.1:{upstream_port}\n");
+    let config = format!("frontend web\n    bind 127.0.0.1:{port}\n    mode http\n    \
+                          acl api path_beg /api\n    acl admin path_beg /admin\n    \
+                          use_backend api if api\n    use_backend admin if admin\n    default_backend site\n\n\
+                          backend api\n{server}\nbackend admin\n{server}\nbackend site\n{server}");
+    let turbogate = Turbogate::start(&config, port).await;
+    let rules = [("1", "api"), ("2", "admin"), ("default_backend", "site")];
+    let counted = async || {
+        let mut counts = Vec::new();
+        for (rule, backend) in rules {
+            let labels = ["frontend=\"web\"", &format!("rule=\"{rule}\""), &format!("backend=\"{backend}\"")];
+            counts.push(turbogate.metric("turbogate_routing_rule_matches_total", &labels).await);
+        }
+        counts
+    };
+    // The readiness probe of `start` may have been routed already.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let before = counted().await;
+
+    for (path, times) in [("/api/users", 1), ("/admin", 2), ("/", 3)] {
+        for _ in 0..times {
+            let request = format!("GET {path} HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n");
+            assert_eq!(statuses(&exchange(addr(port), request.as_bytes(), Duration::from_millis(500)).await.0), [200], "{path}");
+        }
+    }
+    let after = counted().await;
+    assert_eq!([after[0] - before[0], after[1] - before[1], after[2] - before[2]], [1.0, 2.0, 3.0]);
+}
+
+// How the frontend on `port` ends a connection: `Ok(received)` on EOF, or the read error.
+async fn closed_with(port: u16) -> std::io::Result<Vec<u8>> {
+    let mut stream = TcpStream::connect(addr(port)).await.unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n").await.unwrap();
+    let mut received = Vec::new();
+    tokio::time::timeout(Duration::from_secs(2), stream.read_to_end(&mut received)).await
+        .expect("the unrouted connection was held open")
+        .map(|_| received)
+}
+
+#[tokio::test]
+async fn unrouted_connections_are_reset_or_closed_per_on_no_backend() {
+    let backend_port = free_port();
+    let (recorded, _backend) = backend(backend_port).await;
+    for policy in ["reject", "silent-drop"] {
+        let port = free_port();
+        let config = format!(
+            "frontend web\n    bind 127.0.0.1:{port}\n    on-no-backend {policy}\n    \
+             acl elsewhere src 10.255.255.1\n    use_backend be if elsewhere\n\n\
+             backend be\n    server s1 127.0.0.1:{backend_port}\n");
+        let turbogate = Turbogate::start(&config, port).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let labels = [r#"frontend="web""#, &format!("action=\"{policy}\"")];
+        let before = turbogate.metric("turbogate_no_backend_total", &labels).await;
+
+        let started = std::time::Instant::now();
+        let outcome = closed_with(port).await;
+        match policy {
+            "reject" => assert_eq!(outcome.map_err(|e| e.kind()), Err(std::io::ErrorKind::ConnectionReset)),
+            _ => assert_eq!(outcome.unwrap(), b""),
+        }
+        assert!(started.elapsed() < Duration::from_millis(500), "{policy}: {:?}", started.elapsed());
+        assert_eq!(turbogate.metric("turbogate_no_backend_total", &labels).await - before, 1.0, "{policy}");
+    }
+    assert_eq!(recorded.connections.load(Ordering::SeqCst), 0);
+}
@@ -0,0 +1,221 @@
+mod common;
+
+use common::{Turbogate, addr, backend, exchange, free_port, statuses};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const WAIT: Duration = Duration::from_millis(500);
+
+fn two_server_config(port: u16, up: u16, down: u16) -> String {
+    format!("frontend web\n    bind 127.0.0.1:{port}\n    mode http\n    default_backend be\n\n\
+             backend be\n    balance roundrobin\n    \
+             server s1 127.0.0.1:{up} check inter 100ms fall 1 rise 1\n    \
+             server s2 127.0.0.1:{down} check inter 100ms fall 1 rise 1\n")
+}
+
+async fn send(port: u16, count: usize) {
+    for index in 0..count {
+        let request = format!("GET /{index} HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n");
+        let (response, _) = exchange(addr(port), request.as_bytes(), WAIT).await;
+        assert_eq!(statuses(&response), [200], "{}", String::from_utf8_lossy(&response));
+    }
+}
+
+#[tokio::test]
+async fn health_check_results_reach_server_selection() {
+    let port = free_port();
+    let up_port = free_port();
+    let down_port = free_port();
+    let (up, _up) = backend(up_port).await;
+    let _turbogate = Turbogate::start(&two_server_config(port, up_port, down_port), port).await;
+    tokio::time::sleep(WAIT).await;
+
+    send(port, 6).await;
+    assert_eq!(up.heads().len(), 6, "the down server was selected");
+
+    let (recovered, _recovered) = backend(down_port).await;
+    tokio::time::sleep(WAIT).await;
+    send(port, 6).await;
+    assert!(!recovered.heads().is_empty(), "the recovered server was never selected");
+    assert_eq!(up.heads().len() + recovered.heads().len(), 12);
+}
+
+// A strict RFC 4180 reader: every quote must be balanced and every row must end with CRLF or LF.
+fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if field.is_empty() => loop {
+                match chars.next().expect("unterminated quoted field") {
+                    '"' if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    '"' => {
+                        assert!(matches!(chars.peek(), Some(',' | '\n')), "text after a closing quote");
+                        break;
+                    }
+                    c => field.push(c),
+                }
+            },
+            '"' => panic!("quote inside an unquoted field"),
+            ',' => row.push(std::mem::take(&mut field)),
+            '\n' => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            c => field.push(c),
+        }
+    }
+    assert!(row.is_empty() && field.is_empty(), "last row not terminated");
+    rows
+}
+
+#[tokio::test]
+async fn stats_csv_reports_a_down_server() {
+    let port = free_port();
+    let up_port = free_port();
+    let down_port = free_port();
+    let (_up, _up_backend) = backend(up_port).await;
+    let turbogate = Turbogate::start(&two_server_config(port, up_port, down_port), port).await;
+    tokio::time::sleep(WAIT).await;
+
+    let mut open = TcpStream::connect(addr(port)).await.unwrap();
+    open.write_all(b"GET /held HTTP/1.1\r\nHost: example.com\r\n\r\n").await.unwrap();
+    let mut response = [0u8; 12];
+    open.read_exact(&mut response).await.unwrap();
+    assert_eq!(&response, b"HTTP/1.1 200");
+
+    let response = turbogate.get("/stats;csv").await;
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200"), "{head}");
+    let rows = parse_csv(body);
+    let (header, rows) = rows.split_first().unwrap();
+    assert_eq!(header[0], "# pxname");
+    assert_eq!(header.last().map(String::as_str), Some(""), "the header ends with a comma");
+    assert!(rows.iter().all(|row| row.len() == header.len()), "ragged rows in {body}");
+
+    let column = |name: &str| header.iter().position(|column| column.trim_start_matches("# ") == name).unwrap();
+    let summary: Vec<[&str; 4]> = rows.iter()
+        .map(|row| [row[column("pxname")].as_str(), row[column("svname")].as_str(), row[column("status")].as_str(), row[column("scur")].as_str()])
+        .collect();
+    assert!(summary.contains(&["be", "s1", "UP", "1"]), "{summary:?}");
+    assert!(summary.contains(&["be", "s2", "DOWN", "0"]), "{summary:?}");
+    assert!(summary.contains(&["be", "BACKEND", "UP", "1"]), "{summary:?}");
+    assert!(summary.contains(&["web", "FRONTEND", "OPEN", "1"]), "{summary:?}");
+}
+
+#[tokio::test]
+async fn traffic_and_active_tier_follow_tier_health() {
+    let port = free_port();
+    let local_port = free_port();
+    let remote_port = free_port();
+    let sorry_port = free_port();
+    let (remote, _remote) = backend(remote_port).await;
+    let (sorry, _sorry) = backend(sorry_port).await;
+    let config = format!("frontend web\n    bind 127.0.0.1:{port}\n    mode http\n    default_backend be\n\n\
+                          backend be\n    \
+                          server local 127.0.0.1:{local_port} check inter 100ms fall 1 rise 1\n    \
+                          server remote 127.0.0.1:{remote_port} check inter 100ms fall 1 rise 1 tier 2\n    \
+                          server sorry 127.0.0.1:{sorry_port} check inter 100ms fall 1 rise 1 tier 3\n");
+    let turbogate = Turbogate::start(&config, port).await;
+    tokio::time::sleep(WAIT).await;
+
+    send(port, 4).await;
+    assert_eq!(remote.heads().len(), 4);
+    assert!(sorry.heads().is_empty());
+    assert_eq!(turbogate.metric("turbogate_backend_active_tier", &["backend=\"be\""]).await, 2.0);
+
+    let (local, _local) = backend(local_port).await;
+    tokio::time::sleep(WAIT).await;
+    send(port, 4).await;
+    assert_eq!(local.heads().len(), 4);
+    assert_eq!(remote.heads().len(), 4);
+    assert_eq!(turbogate.metric("turbogate_backend_active_tier", &["backend=\"be\""]).await, 1.0);
+    assert!(turbogate.metric("turbogate_backend_tier_changes_total", &["backend=\"be\""]).await >= 2.0);
+}
+
+#[tokio::test]
+async fn warm_up_keeps_first_connections_off_dead_servers() {
+    for (warmup, dead_selected) in [("on", false), ("off", true)] {
+        let port = free_port();
+        let up_port = free_port();
+        let (_up, _backend) = backend(up_port).await;
+        let config = format!("global\n    warmup-checks {warmup}\n\n\
+                              frontend web\n    bind 127.0.0.1:{port}\n    mode http\n    default_backend be\n\n\
+                              backend be\n    balance roundrobin\n    \
+                              server s1 127.0.0.1:{up_port} check inter 10s\n    \
+                              server s2 127.0.0.1:{} check inter 10s\n", free_port());
+        let _turbogate = Turbogate::start(&config, port).await;
+
+        let mut outcomes = Vec::new();
+        for _ in 0..4 {
+            let (response, _) = exchange(addr(port), b"GET / HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n", WAIT).await;
+            outcomes.push(statuses(&response));
+        }
+        assert_eq!(outcomes.iter().any(|outcome| *outcome != [200]), dead_selected, "warmup-checks {warmup}: {outcomes:?}");
+    }
+}
+
+// Each server's state in the CSV, the JSON rows and the `turbogate_server_state` series set to 1.
+async fn server_states(turbogate: &Turbogate, servers: &[&str]) -> Vec<[String; 3]> {
+    let csv = turbogate.get("/stats;csv").await;
+    let header: Vec<&str> = csv.lines().find(|line| line.starts_with("# ")).unwrap()[2..].split(',').collect();
+    let state_column = header.iter().position(|column| *column == "state").unwrap();
+    let json = turbogate.get("/stats.json").await;
+    let rows: serde_json::Value = serde_json::from_str(json.split_once("\r\n\r\n").unwrap().1).unwrap();
+    let metrics = turbogate.get("/metrics").await;
+
+    let mut states = Vec::new();
+    for server in servers {
+        let in_csv = csv.lines().find(|line| line.starts_with(&format!("be,{server},")))
+            .and_then(|line| line.split(',').nth(state_column)).unwrap_or_default().to_string();
+        let in_json = rows.as_array().unwrap().iter()
+            .find(|row| row["pxname"] == "be" && row["svname"] == *server)
+            .and_then(|row| row["state"].as_str()).unwrap_or_default().to_string();
+        let in_metrics = metrics.lines()
+            .filter(|line| line.starts_with("turbogate_server_state{") && line.ends_with(" 1"))
+            .filter(|line| line.contains("backend=\"be\"") && line.contains(&format!("server=\"{server}\"")))
+            .find_map(|line| Some(line.split_once("state=\"")?.1.split_once('"')?.0.to_string()))
+            .unwrap_or_default();
+        states.push([in_csv, in_json, in_metrics]);
+    }
+    states
+}
+
+#[tokio::test]
+async fn server_states_agree_across_csv_json_and_metrics() {
+    let port = free_port();
+    let live = free_port();
+    let dead = free_port();
+    let (_recorded, _backend) = backend(live).await;
+    let config = format!(
+        "global\n    stats path /stats.json json\n\n\
+         frontend web\n    bind 127.0.0.1:{port}\n    mode http\n    default_backend be\n\n\
+         backend be\n    \
+         server up 127.0.0.1:{live} check inter 100ms fall 1 rise 1\n    \
+         server unchecked 127.0.0.1:{live}\n    \
+         server down 127.0.0.1:{dead} check inter 100ms fall 1 rise 1\n    \
+         server off 127.0.0.1:{live} disabled\n    \
+         server drained 127.0.0.1:{live} weight 0\n    \
+         server spare 127.0.0.1:{live} backup\n");
+    let turbogate = Turbogate::start(&config, port).await;
+
+    let servers = ["up", "unchecked", "down", "off", "drained", "spare"];
+    let expected: Vec<[String; 3]> = ["up", "no-check", "down", "disabled", "drain", "standby"].iter()
+        .map(|state| [state.to_string(), state.to_string(), state.to_string()])
+        .collect();
+    let mut states = Vec::new();
+    for _ in 0..40 {
+        states = server_states(&turbogate, &servers).await;
+        if states == expected {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(states, expected);
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use common::{Turbogate, addr, backend, free_port, read_all};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpSocket, TcpStream};
+
+const WAIT: Duration = Duration::from_secs(3);
+
+fn config(timeouts: &str, backend_port: u16, port: u16) -> String {
+    format!("defaults\n    mode tcp\n{timeouts}\n\
+             frontend web\n    bind 127.0.0.1:{port}\n    default_backend be\n\n\
+             backend be\n    server s1 127.0.0.1:{backend_port}\n")
+}
+
+// The error type and termination state of the newest entry in `GET /errors`.
+async fn last_error(turbogate: &Turbogate) -> (String, String) {
+    for _ in 0..40 {
+        let response = turbogate.get("/errors?frontend=web").await;
+        let errors: serde_json::Value = serde_json::from_str(response.split_once("\r\n\r\n").unwrap().1).unwrap();
+        if let Some(newest) = errors.as_array().and_then(|errors| errors.first()) {
+            return (newest["error"].as_str().unwrap().to_string(), newest["termination_state"].as_str().unwrap().to_string());
+        }
+        tokio::time::sleep(Duration::from_millis(25)).await;
+    }
+    panic!("no error was recorded");
+}
+
+// Connects, sends `data` and returns how long the proxy kept the connection open.
+async fn held_for(port: u16, data: &[u8]) -> Duration {
+    let started = Instant::now();
+    let mut stream = TcpStream::connect(addr(port)).await.unwrap();
+    stream.write_all(data).await.unwrap();
+    let (_, closed) = read_all(&mut stream, WAIT).await;
+    assert!(closed, "the proxy did not close the connection within {WAIT:?}");
+    started.elapsed()
+}
+
+#[tokio::test]
+async fn idle_timeouts_name_the_silent_side() {
+    let cases = [
+        ("    timeout client 200ms\n    timeout server 10s\n", &b""[..], ("client_idle_timeout", "cD")),
+        // The backend waits for the end of the request line that never comes.
+        ("    timeout client 10s\n    timeout server 200ms\n", &b"GET / HTTP/1.1"[..], ("server_idle_timeout", "sD")),
+    ];
+    for (timeouts, data, (error, termination_state)) in cases {
+        let (port, backend_port) = (free_port(), free_port());
+        let (_recorded, _backend) = backend(backend_port).await;
+        let turbogate = Turbogate::start(&config(timeouts, backend_port, port), port).await;
+
+        assert!(held_for(port, data).await >= Duration::from_millis(200));
+        assert_eq!(last_error(&turbogate).await, (error.to_string(), termination_state.to_string()));
+    }
+}
+
+#[tokio::test]
+async fn unanswered_connects_time_out_with_sc() {
+    // A listener that is never accepted from: once its backlog is full, new SYNs are dropped.
+    let socket = TcpSocket::new_v4().unwrap();
+    socket.bind(addr(0)).unwrap();
+    let listener = socket.listen(0).unwrap();
+    let backend_port = listener.local_addr().unwrap().port();
+    let mut fillers = Vec::new();
+    for _ in 0..4 {
+        if let Ok(Ok(stream)) = tokio::time::timeout(Duration::from_millis(100), TcpStream::connect(addr(backend_port))).await {
+            fillers.push(stream);
+        }
+    }
+
+    let port = free_port();
+    let turbogate = Turbogate::start(&config("    timeout connect 200ms\n", backend_port, port), port).await;
+    let held = held_for(port, b"").await;
+    assert!(held >= Duration::from_millis(200) && held < WAIT, "held for {held:?}");
+    assert_eq!(last_error(&turbogate).await, ("connect_timeout".to_string(), "sC".to_string()));
+}
+
+#[tokio::test]
+async fn tunnel_timeout_caps_busy_sessions_with_td() {
+    let (port, backend_port) = (free_port(), free_port());
+    let (_recorded, _backend) = backend(backend_port).await;
+    let timeouts = "    timeout client 10s\n    timeout server 10s\n    timeout tunnel 300ms\n";
+    let turbogate = Turbogate::start(&config(timeouts, backend_port, port), port).await;
+
+    let started = Instant::now();
+    let mut stream = TcpStream::connect(addr(port)).await.unwrap();
+    while stream.write_all(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n").await.is_ok() && started.elapsed() < WAIT {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert!(started.elapsed() >= Duration::from_millis(300) && started.elapsed() < WAIT, "held for {:?}", started.elapsed());
+    assert_eq!(last_error(&turbogate).await, ("session_max_duration".to_string(), "tD".to_string()));
+}
+
+#[tokio::test]
+async fn silent_http_clients_expire_at_the_request_timeout() {
+    let (port, backend_port) = (free_port(), free_port());
+    let (recorded, _backend) = backend(backend_port).await;
+    let config = format!("defaults\n    timeout client 30s\n    timeout http-request 200ms\n\n\
+                          frontend web\n    mode http\n    bind 127.0.0.1:{port}\n    default_backend be\n\n\
+                          backend be\n    mode http\n    server s1 127.0.0.1:{backend_port}\n");
+    let turbogate = Turbogate::start(&config, port).await;
+    // The readiness probe of `start` may have been forwarded already.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let connections = recorded.connections.load(Ordering::Relaxed);
+
+    for data in [&b""[..], b"GET / HTTP/1.1\r\nHost: a\r\n"] {
+        let held = held_for(port, data).await;
+        assert!(held >= Duration::from_millis(200) && held < WAIT, "held for {held:?}");
+        assert_eq!(last_error(&turbogate).await, ("request_timeout".to_string(), "cR".to_string()));
+    }
+    assert_eq!(turbogate.metric("turbogate_slowloris_expired_total", &["frontend=\"web\""]).await, 2.0);
+    assert_eq!(recorded.connections.load(Ordering::Relaxed), connections, "a slow client reached the backend");
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use common::{addr, free_port};
+use std::os::unix::net::UnixDatagram;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::process::Command;
+
+// The next sd_notify message sent to `socket`.
+async fn notified(socket: &UnixDatagram) -> String {
+    for _ in 0..200 {
+        let mut message = [0u8; 512];
+        if let Ok(len) = socket.recv(&mut message) {
+            return String::from_utf8_lossy(&message[..len]).into_owned();
+        }
+        tokio::time::sleep(Duration::from_millis(25)).await;
+    }
+    panic!("no sd_notify message");
+}
+
+async fn ready_status(stats_port: u16) -> String {
+    let mut stream = TcpStream::connect(addr(stats_port)).await.unwrap();
+    stream.write_all(b"GET /ready HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    tokio::time::timeout(Duration::from_secs(5), stream.read_to_string(&mut response)).await.unwrap().unwrap();
+    response.lines().next().unwrap_or_default().to_string()
+}
+
+#[tokio::test]
+async fn readiness_is_logged_and_notified_once_every_listener_accepts() {
+    let dir = common::scratch_dir();
+    let socket = UnixDatagram::bind(dir.join("notify.sock")).unwrap();
+    socket.set_nonblocking(true).unwrap();
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    std::fs::write(dir.join("cert.pem"), cert.serialize_pem().unwrap() + &cert.serialize_private_key_pem()).unwrap();
+
+    let (web, secure, stats_port) = (free_port(), free_port(), free_port());
+    let config = format!(
+        "global\n    stats bind 127.0.0.1:{stats_port}\n\n\
+         frontend web\n    bind 127.0.0.1:{web}\n    acl other src 10.255.255.1\n    use_backend b if other\n    default_backend a\n\n\
+         frontend secure\n    bind 127.0.0.1:{secure} ssl crt {}\n    default_backend a\n\n\
+         backend a\n    server s1 127.0.0.1:1\n\n\
+         backend b\n    server s1 127.0.0.1:1\n",
+        dir.join("cert.pem").display());
+    std::fs::write(dir.join("turbogate.cfg"), &config).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_turbogate"))
+        .arg("--config").arg(dir.join("turbogate.cfg"))
+        .arg("--json-logs")
+        .env("NOTIFY_SOCKET", dir.join("notify.sock"))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .unwrap();
+
+    let ready = notified(&socket).await;
+    assert_eq!(ready, "READY=1\nSTATUS=Accepting connections on 2 listener(s)");
+    assert_eq!(ready_status(stats_port).await, "HTTP/1.1 200 OK");
+    assert!(TcpStream::connect(addr(web)).await.is_ok() && TcpStream::connect(addr(secure)).await.is_ok());
+
+    let mut logs = BufReader::new(child.stdout.take().unwrap()).lines();
+    let logged = tokio::time::timeout(Duration::from_secs(5), async {
+        while let Some(line) = logs.next_line().await.unwrap() {
+            if line.contains("\"Turbogate ready\"") {
+                return line;
+            }
+        }
+        panic!("no ready log line");
+    }).await.unwrap();
+    let logged: serde_json::Value = serde_json::from_str(&logged).unwrap();
+    assert_eq!(logged["fields"]["backends"], 2, "{logged}");
+    let frontends = logged["fields"]["frontends"].as_str().unwrap();
+    assert_eq!(frontends, format!(r#"["web [127.0.0.1:{web}] -> 2 backend(s)", "secure [127.0.0.1:{secure} ssl] -> 1 backend(s)"]"#));
+
+    let pid = child.id().unwrap() as libc::pid_t;
+    assert_eq!(unsafe { libc::kill(pid, libc::SIGHUP) }, 0);
+    let reloading = notified(&socket).await;
+    assert!(reloading.starts_with("RELOADING=1\nMONOTONIC_USEC="), "{reloading}");
+    assert!(notified(&socket).await.starts_with("READY=1\n"));
+
+    assert_eq!(unsafe { libc::kill(pid, libc::SIGTERM) }, 0);
+    assert_eq!(notified(&socket).await, "STOPPING=1\nSTATUS=Shutting down");
+    let _ = child.kill().await;
+    let _ = std::fs::remove_dir_all(dir);
+}
@@ -0,0 +1,215 @@
+mod common;
+
+use common::{Turbogate, addr, backend, exchange, free_port, statuses};
+use std::net::TcpListener;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UnixStream};
+use tokio::process::Command;
+
+#[tokio::test]
+async fn metrics_are_scraped_over_a_unix_socket() {
+    let port = free_port();
+    let dir = common::scratch_dir();
+    let socket = dir.join("stats.sock");
+    let config = format!("global\n    stats bind unix:{} mode 640\n\n\
+                          frontend web\n    bind 127.0.0.1:{port}\n    default_backend be\n\n\
+                          backend be\n    server s1 127.0.0.1:1\n", socket.display());
+    let _turbogate = Turbogate::start(&config, port).await;
+
+    assert_eq!(std::fs::metadata(&socket).unwrap().permissions().mode() & 0o777, 0o640);
+
+    let mut stream = UnixStream::connect(&socket).await.unwrap();
+    stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    tokio::time::timeout(Duration::from_secs(5), stream.read_to_string(&mut response)).await.unwrap().unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert!(response.contains("turbogate_"), "{response}");
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+// Starts turbogate on `config` as is, without the harness's own stats bind.
+async fn run_with_stats_binds(config: &str, port: u16) -> tokio::process::Child {
+    let dir = common::scratch_dir();
+    let path = dir.join("turbogate.cfg");
+    std::fs::write(&path, config).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_turbogate"))
+        .arg("--config").arg(&path)
+        .arg("--log-level").arg("error")
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .unwrap();
+    for _ in 0..200 {
+        if TcpStream::connect(addr(port)).await.is_ok() || child.try_wait().unwrap().is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(25)).await;
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+    child
+}
+
+async fn scrape(port: u16) -> Option<String> {
+    let mut stream = TcpStream::connect(addr(port)).await.ok()?;
+    stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.ok()?;
+    let mut response = String::new();
+    tokio::time::timeout(Duration::from_secs(5), stream.read_to_string(&mut response)).await.ok()?.ok()?;
+    Some(response)
+}
+
+#[tokio::test]
+async fn a_taken_stats_bind_aborts_startup_unless_metrics_are_optional() {
+    let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+    let taken_port = taken.local_addr().unwrap().port();
+    let free = free_port();
+    let frontend = "frontend web\n    bind 127.0.0.1:{port}\n    default_backend be\n\nbackend be\n    server s1 127.0.0.1:1\n";
+
+    let port = free_port();
+    let config = format!("global\n    stats bind 127.0.0.1:{taken_port}\n    metrics required on\n\n{}", frontend.replace("{port}", &port.to_string()));
+    let child = run_with_stats_binds(&config, port).await;
+    let output = tokio::time::timeout(Duration::from_secs(5), child.wait_with_output()).await.unwrap().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(&format!("127.0.0.1:{taken_port}")), "{stderr}");
+
+    let port = free_port();
+    let config = format!("global\n    stats bind 127.0.0.1:{taken_port}\n    stats bind 127.0.0.1:{free}\n    metrics required off\n\n{}",
+                         frontend.replace("{port}", &port.to_string()));
+    let mut child = run_with_stats_binds(&config, port).await;
+    assert!(child.try_wait().unwrap().is_none(), "turbogate exited with metrics required off");
+    assert!(scrape(free).await.is_some_and(|response| response.starts_with("HTTP/1.1 200")));
+}
+
+async fn admin(socket: &Path, command: &str) -> String {
+    let mut stream = UnixStream::connect(socket).await.unwrap();
+    stream.write_all(format!("{command}\n").as_bytes()).await.unwrap();
+    stream.shutdown().await.unwrap();
+    let mut output = String::new();
+    tokio::time::timeout(Duration::from_secs(5), stream.read_to_string(&mut output)).await.unwrap().unwrap();
+    output
+}
+
+#[tokio::test]
+async fn the_metrics_endpoint_moves_at_runtime() {
+    let port = free_port();
+    let moved = free_port();
+    let dir = common::scratch_dir();
+    let socket = dir.join("admin.sock");
+    let config = format!("global\n    stats socket {}\n\n\
+                          frontend web\n    bind 127.0.0.1:{port}\n    default_backend be\n\n\
+                          backend be\n    server s1 127.0.0.1:1\n", socket.display());
+    let turbogate = Turbogate::start(&config, port).await;
+    assert!(scrape(turbogate.stats_port).await.is_some());
+
+    admin(&socket, &format!("set metrics bind 127.0.0.1:{moved}")).await;
+
+    assert!(scrape(moved).await.is_some_and(|response| response.starts_with("HTTP/1.1 200")));
+    assert!(TcpStream::connect(addr(turbogate.stats_port)).await.is_err(), "the old metrics listener is still open");
+    let listeners = admin(&socket, "show metrics").await;
+    assert!(listeners.contains(&format!("127.0.0.1:{moved} running")), "{listeners}");
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn every_series_carries_the_metrics_prefix_and_labels() {
+    let port = free_port();
+    let backend_port = free_port();
+    let (_recorded, _backend) = backend(backend_port).await;
+    let config = format!("global\n    metrics prefix acme\n    metrics label dc eu1\n    metrics label group edge\n\n\
+                          frontend web\n    bind 127.0.0.1:{port}\n    mode http\n    default_backend be\n\n\
+                          backend be\n    server s1 127.0.0.1:{backend_port}\n");
+    let turbogate = Turbogate::start(&config, port).await;
+    let (response, _) = exchange(addr(port), b"GET / HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n", Duration::from_millis(500)).await;
+    assert_eq!(statuses(&response), [200]);
+
+    let metrics = turbogate.get("/metrics").await;
+    let series: Vec<&str> = metrics.lines().filter(|line| !line.starts_with('#') && line.contains('{')).collect();
+    assert!(!series.is_empty(), "{metrics}");
+    for line in &series {
+        assert!(line.starts_with("acme_turbogate_"), "{line}");
+        assert!(line.contains("dc=\"eu1\"") && line.contains("group=\"edge\""), "{line}");
+    }
+    for name in ["acme_turbogate_connections_total{", "acme_turbogate_session_phase_seconds{"] {
+        assert!(series.iter().any(|line| line.starts_with(name)), "no {name} series in {metrics}");
+    }
+}
+
+#[tokio::test]
+async fn server_names_that_are_not_label_values_scrape_sanitized() {
+    let port = free_port();
+    let backend_port = free_port();
+    let (_recorded, _backend) = backend(backend_port).await;
+    let config = format!("frontend web\n    bind 127.0.0.1:{port}\n    mode http\n    default_backend be\n\n\
+                          backend be\n    server \"web 1 \\\"x\\\"\" 127.0.0.1:{backend_port}\n");
+    let turbogate = Turbogate::start(&config, port).await;
+    // The readiness probe of `start` reaches the server too.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let requests = async || turbogate.metric("turbogate_requests_total", &["server=\"web_1__x_\"", "status="]).await;
+    let before = requests().await;
+    let (response, _) = exchange(addr(port), b"GET / HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n", Duration::from_millis(500)).await;
+    assert_eq!(statuses(&response), [200]);
+
+    let sample = regex::Regex::new(r#"^[a-zA-Z_:][a-zA-Z0-9_:]*(\{[a-zA-Z_][a-zA-Z0-9_]*="[^"\\]*"(,[a-zA-Z_][a-zA-Z0-9_]*="[^"\\]*")*\})? \S+$"#).unwrap();
+    let metrics = turbogate.get("/metrics").await;
+    let body = metrics.split_once("\r\n\r\n").unwrap().1;
+    for line in body.lines().filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        assert!(sample.is_match(line), "invalid sample: {line}");
+    }
+    assert_eq!(requests().await - before, 1.0);
+}
+
+#[tokio::test]
+async fn servers_past_the_series_limit_are_counted_as_other() {
+    let port = free_port();
+    let backend_port = free_port();
+    let (_recorded, _backend) = backend(backend_port).await;
+    let servers: String = (0..5000).map(|index| format!("    server s{index} 127.0.0.1:{backend_port}\n")).collect();
+    let config = format!("global\n    metrics max-series-per-backend 100\n\n\
+                          frontend web\n    bind 127.0.0.1:{port}\n    mode http\n    default_backend be\n\n\
+                          backend be\n    balance roundrobin\n{servers}");
+    let turbogate = Turbogate::start(&config, port).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let total = async || turbogate.metric("turbogate_requests_total", &["backend=\"be\"", "status="]).await;
+    let before = total().await;
+    for _ in 0..150 {
+        let (response, _) = exchange(addr(port), b"GET / HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n", Duration::from_millis(500)).await;
+        assert_eq!(statuses(&response), [200]);
+    }
+
+    let metrics = turbogate.get("/metrics").await;
+    let mut servers: Vec<&str> = metrics.lines()
+        .filter(|line| line.starts_with("turbogate_requests_total{") && line.contains("status="))
+        .filter_map(|line| line.split_once("server=\"")?.1.split_once('"').map(|(server, _)| server))
+        .collect();
+    servers.sort();
+    servers.dedup();
+    assert_eq!(servers.len(), 101, "{servers:?}");
+    assert!(servers.contains(&"_other") && servers.contains(&"s99") && !servers.contains(&"s100"), "{servers:?}");
+    assert_eq!(total().await - before, 150.0);
+    // Roundrobin walks the servers in order, so at least the 50 requests past s99 land in _other.
+    assert!(turbogate.metric("turbogate_requests_total", &["server=\"_other\"", "status="]).await >= 50.0);
+}
+
+#[tokio::test]
+async fn stats_paths_can_be_renamed_and_disabled() {
+    let port = free_port();
+    let config = format!("global\n    stats path /prom prometheus\n    stats path /stats disabled\n    stats path /healthz liveness\n\n\
+                          frontend web\n    bind 127.0.0.1:{port}\n    default_backend be\n\n\
+                          backend be\n    server s1 127.0.0.1:1\n");
+    let turbogate = Turbogate::start(&config, port).await;
+    let status = async |path: &str| turbogate.get(path).await.split(' ').nth(1).unwrap_or_default().to_string();
+
+    assert!(turbogate.get("/prom").await.contains("turbogate_"));
+    for (path, expected) in [("/prom", "200"), ("/metrics", "404"), ("/stats", "404"), ("/healthz", "200"), ("/stats;csv", "200"), ("/unknown", "404")] {
+        assert_eq!(status(path).await, expected, "{path}");
+    }
+
+    let rejected = common::check_config(&config.replace("liveness", "bogus"));
+    assert!(!rejected.status.success());
+    assert!(String::from_utf8_lossy(&rejected.stderr).contains("Unknown stats path handler 'bogus'"),
+            "{}", String::from_utf8_lossy(&rejected.stderr));
+}
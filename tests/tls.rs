@@ -0,0 +1,65 @@
+mod common;
+
+use common::{Turbogate, addr, backend, free_port};
+use rustls::{Certificate, ClientConfig, RootCertStore, ServerName};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+// Writes a self-signed certificate for `name` with its key to `path`; returns the certificate DER.
+fn self_signed(path: &Path, name: &str) -> Vec<u8> {
+    let cert = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+    let pem = cert.serialize_pem().unwrap();
+    std::fs::write(path, pem.clone() + &cert.serialize_private_key_pem()).unwrap();
+    rustls_pemfile::certs(&mut pem.as_bytes()).unwrap().remove(0)
+}
+
+// The certificate the frontend on `port` presents to a client sending `server_name` as SNI.
+async fn served_cert(port: u16, server_name: &str, trusted: &[&[u8]]) -> Vec<u8> {
+    let mut roots = RootCertStore::empty();
+    for der in trusted {
+        roots.add(&Certificate(der.to_vec())).unwrap();
+    }
+    let config = ClientConfig::builder().with_safe_defaults().with_root_certificates(roots).with_no_client_auth();
+    let stream = TcpStream::connect(addr(port)).await.unwrap();
+    let tls = tokio::time::timeout(
+        Duration::from_secs(5),
+        TlsConnector::from(Arc::new(config)).connect(ServerName::try_from(server_name).unwrap(), stream),
+    ).await.unwrap().unwrap();
+    tls.get_ref().1.peer_certificates().unwrap()[0].0.clone()
+}
+
+#[tokio::test]
+async fn crt_directories_serve_the_certificate_for_each_server_name() {
+    let port = free_port();
+    let backend_port = free_port();
+    let (_recorded, _backend) = backend(backend_port).await;
+    let certs = common::scratch_dir();
+    let a = self_signed(&certs.join("a.pem"), "a.example.com");
+    let b = self_signed(&certs.join("b.pem"), "b.example.com");
+    let config = format!(
+        "frontend web\n    bind 127.0.0.1:{port} ssl crt {}\n    default_backend be\n\n\
+         backend be\n    server s1 127.0.0.1:{backend_port}\n",
+        certs.display());
+    let turbogate = Turbogate::start(&config, port).await;
+
+    assert_eq!(served_cert(port, "a.example.com", &[&a, &b]).await, a);
+    assert_eq!(served_cert(port, "b.example.com", &[&a, &b]).await, b);
+
+    let replaced = self_signed(&certs.join("a.pem"), "a.example.com");
+    turbogate.write_config(&(config + "    timeout server 5s\n"));
+    turbogate.signal(libc::SIGHUP);
+    for _ in 0..40 {
+        if turbogate.metric("turbogate_config_reloads_total", &["result=\"applied\""]).await == 1.0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(25)).await;
+    }
+
+    assert_eq!(turbogate.metric("turbogate_config_reloads_total", &["result=\"applied\""]).await, 1.0);
+    assert_eq!(served_cert(port, "a.example.com", &[&a, &replaced, &b]).await, replaced);
+    assert_eq!(served_cert(port, "b.example.com", &[&a, &replaced, &b]).await, b);
+    let _ = std::fs::remove_dir_all(&certs);
+}